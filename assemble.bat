@@ -2,10 +2,10 @@ del bin\*.a /q
 
 set crate=bl602-hal
 
-riscv64-unknown-elf-gcc -ggdb3 -fdebug-prefix-map=%cd%=/%crate% -c -mabi=ilp32 -march=rv32i trap.S -o bin/%crate%.o
-riscv64-unknown-elf-ar crs bin/trap_riscv32i-unknown-none-elf.a bin/%crate%.o
+clang -E -x assembler-with-cpp trap.S | llvm-mc -g -fdebug-prefix-map=%cd%=/%crate% -triple=riscv32 -mattr=-relax -filetype=obj -o bin/%crate%.o
+llvm-ar crs bin/trap_riscv32i-unknown-none-elf.a bin/%crate%.o
 
-riscv64-unknown-elf-gcc -ggdb3 -fdebug-prefix-map=%cd%=/%crate% -c -mabi=ilp32f -march=rv32if trap.S -o bin/%crate%.o
-riscv64-unknown-elf-ar crs bin/trap_riscv32if-unknown-none-elf.a bin/%crate%.o
+clang -E -x assembler-with-cpp trap.S | llvm-mc -g -fdebug-prefix-map=%cd%=/%crate% -triple=riscv32 -mattr=+f,-relax -target-abi=ilp32f -filetype=obj -o bin/%crate%.o
+llvm-ar crs bin/trap_riscv32if-unknown-none-elf.a bin/%crate%.o
 
 del bin\%crate%.o
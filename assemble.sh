@@ -7,10 +7,12 @@ crate=bl602-hal
 # remove existing blobs because otherwise this will append object files to the old blobs
 rm -f bin/*.a
 
-riscv64-unknown-elf-gcc -ggdb3 -fdebug-prefix-map=$(pwd)=/$crate -c -mabi=ilp32 -march=rv32i trap.S -o bin/$crate.o
-riscv64-unknown-elf-ar crs bin/trap_riscv32i-unknown-none-elf.a bin/$crate.o
+# trap.S only needs the C preprocessor for its register size macros; its line markers keep the
+# debug info pointing at trap.S
+cpp trap.S | llvm-mc -g -fdebug-prefix-map=$(pwd)=/$crate -triple=riscv32 -mattr=-relax -filetype=obj -o bin/$crate.o
+llvm-ar crs bin/trap_riscv32i-unknown-none-elf.a bin/$crate.o
 
-riscv64-unknown-elf-gcc -ggdb3 -fdebug-prefix-map=$(pwd)=/$crate -c -mabi=ilp32f -march=rv32if trap.S -o bin/$crate.o
-riscv64-unknown-elf-ar crs bin/trap_riscv32if-unknown-none-elf.a bin/$crate.o
+cpp trap.S | llvm-mc -g -fdebug-prefix-map=$(pwd)=/$crate -triple=riscv32 -mattr=+f,-relax -target-abi=ilp32f -filetype=obj -o bin/$crate.o
+llvm-ar crs bin/trap_riscv32if-unknown-none-elf.a bin/$crate.o
 
 rm bin/$crate.o
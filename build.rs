@@ -31,9 +31,14 @@ fn main() {
             .unwrap()
             .write_all(include_bytes!("hal_defaults.x"))
             .unwrap();
+        fs::File::create(out_dir.join("hbn_ram.x"))
+            .unwrap()
+            .write_all(include_bytes!("hbn_ram.x"))
+            .unwrap();
         println!("cargo:rustc-link-search={}", out_dir.display());
     }
 
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=hal_defaults.x");
+    println!("cargo:rerun-if-changed=hbn_ram.x");
 }
@@ -0,0 +1,236 @@
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use hal::{
+    aes::{Aes, Error, Gcm},
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    pac,
+    prelude::*,
+    sec_eng::SecEngExt,
+    serial::*,
+};
+use panic_halt as _;
+
+// FIPS-197 appendix C
+const FIPS_PLAINTEXT: [u8; 16] = [
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+];
+const FIPS_AES128: [u8; 16] = [
+    0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4, 0xc5, 0x5a,
+];
+const FIPS_AES192: [u8; 16] = [
+    0xdd, 0xa9, 0x7c, 0xa4, 0x86, 0x4c, 0xdf, 0xe0, 0x6e, 0xaf, 0x70, 0xa0, 0xec, 0x0d, 0x71, 0x91,
+];
+const FIPS_AES256: [u8; 16] = [
+    0x8e, 0xa2, 0xb7, 0xca, 0x51, 0x67, 0x45, 0xbf, 0xea, 0xfc, 0x49, 0x90, 0x4b, 0x49, 0x60, 0x89,
+];
+
+// NIST SP 800-38A F.2.1 and F.5.1, first two blocks
+const SP800_KEY: [u8; 16] = [
+    0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f, 0x3c,
+];
+const SP800_PLAINTEXT: [u8; 32] = [
+    0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17, 0x2a,
+    0xae, 0x2d, 0x8a, 0x57, 0x1e, 0x03, 0xac, 0x9c, 0x9e, 0xb7, 0x6f, 0xac, 0x45, 0xaf, 0x8e, 0x51,
+];
+const CBC_IV: [u8; 16] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+];
+const CBC_CIPHERTEXT: [u8; 16] = [
+    0x76, 0x49, 0xab, 0xac, 0x81, 0x19, 0xb2, 0x46, 0xce, 0xe9, 0x8e, 0x9b, 0x12, 0xe9, 0x19, 0x7d,
+];
+const CTR_IV: [u8; 16] = [
+    0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa, 0xfb, 0xfc, 0xfd, 0xfe, 0xff,
+];
+const CTR_CIPHERTEXT: [u8; 32] = [
+    0x87, 0x4d, 0x61, 0x91, 0xb6, 0x20, 0xe3, 0x26, 0x1b, 0xef, 0x68, 0x64, 0x99, 0x0d, 0xb6, 0xce,
+    0x98, 0x06, 0xf6, 0x6b, 0x79, 0x70, 0xfd, 0xff, 0x86, 0x17, 0x18, 0x7b, 0xb9, 0xff, 0xfd, 0xff,
+];
+
+// GCM test cases 1, 2 and 4 from the GCM specification, and test case 4 without a payload
+const GCM_TC1_TAG: [u8; 16] = [
+    0x58, 0xe2, 0xfc, 0xce, 0xfa, 0x7e, 0x30, 0x61, 0x36, 0x7f, 0x1d, 0x57, 0xa4, 0xe7, 0x45, 0x5a,
+];
+const GCM_TC2_CIPHERTEXT: [u8; 16] = [
+    0x03, 0x88, 0xda, 0xce, 0x60, 0xb6, 0xa3, 0x92, 0xf3, 0x28, 0xc2, 0xb9, 0x71, 0xb2, 0xfe, 0x78,
+];
+const GCM_TC2_TAG: [u8; 16] = [
+    0xab, 0x6e, 0x47, 0xd4, 0x2c, 0xec, 0x13, 0xbd, 0xf5, 0x3a, 0x67, 0xb2, 0x12, 0x57, 0xbd, 0xdf,
+];
+const GCM_TC4_KEY: [u8; 16] = [
+    0xfe, 0xff, 0xe9, 0x92, 0x86, 0x65, 0x73, 0x1c, 0x6d, 0x6a, 0x8f, 0x94, 0x67, 0x30, 0x83, 0x08,
+];
+const GCM_TC4_NONCE: [u8; 12] = [
+    0xca, 0xfe, 0xba, 0xbe, 0xfa, 0xce, 0xdb, 0xad, 0xde, 0xca, 0xf8, 0x88,
+];
+const GCM_TC4_AAD: [u8; 20] = [
+    0xfe, 0xed, 0xfa, 0xce, 0xde, 0xad, 0xbe, 0xef, 0xfe, 0xed, 0xfa, 0xce, 0xde, 0xad, 0xbe, 0xef,
+    0xab, 0xad, 0xda, 0xd2,
+];
+const GCM_TC4_PLAINTEXT: [u8; 60] = [
+    0xd9, 0x31, 0x32, 0x25, 0xf8, 0x84, 0x06, 0xe5, 0xa5, 0x59, 0x09, 0xc5, 0xaf, 0xf5, 0x26, 0x9a,
+    0x86, 0xa7, 0xa9, 0x53, 0x15, 0x34, 0xf7, 0xda, 0x2e, 0x4c, 0x30, 0x3d, 0x8a, 0x31, 0x8a, 0x72,
+    0x1c, 0x3c, 0x0c, 0x95, 0x95, 0x68, 0x09, 0x53, 0x2f, 0xcf, 0x0e, 0x24, 0x49, 0xa6, 0xb5, 0x25,
+    0xb1, 0x6a, 0xed, 0xf5, 0xaa, 0x0d, 0xe6, 0x57, 0xba, 0x63, 0x7b, 0x39,
+];
+const GCM_TC4_CIPHERTEXT: [u8; 60] = [
+    0x42, 0x83, 0x1e, 0xc2, 0x21, 0x77, 0x74, 0x24, 0x4b, 0x72, 0x21, 0xb7, 0x84, 0xd0, 0xd4, 0x9c,
+    0xe3, 0xaa, 0x21, 0x2f, 0x2c, 0x02, 0xa4, 0xe0, 0x35, 0xc1, 0x7e, 0x23, 0x29, 0xac, 0xa1, 0x2e,
+    0x21, 0xd5, 0x14, 0xb2, 0x54, 0x66, 0x93, 0x1c, 0x7d, 0x8f, 0x6a, 0x5a, 0xac, 0x84, 0xaa, 0x05,
+    0x1b, 0xa3, 0x0b, 0x39, 0x6a, 0x0a, 0xac, 0x97, 0x3d, 0x58, 0xe0, 0x91,
+];
+const GCM_TC4_TAG: [u8; 16] = [
+    0x5b, 0xc9, 0x4f, 0xbc, 0x32, 0x21, 0xa5, 0xdb, 0x94, 0xfa, 0xe9, 0x5a, 0xe7, 0x12, 0x1a, 0x47,
+];
+const GCM_AAD_ONLY_TAG: [u8; 16] = [
+    0x34, 0x64, 0x34, 0xfd, 0x51, 0xd5, 0xcd, 0x0c, 0x58, 0x87, 0xec, 0x63, 0xe3, 0x9b, 0x90, 0x7a,
+];
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(115_200.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let sec_eng = dp.SEC_ENG.split();
+    let mut aes = Aes::new(sec_eng.aes, &mut parts.clk_cfg);
+
+    let mut report = |name: &str, ok: bool| {
+        let result = if ok { "ok" } else { "FAILED" };
+        writeln!(serial, "{}: {}\r", name, result).ok();
+    };
+
+    // The FIPS-197 keys are 00 01 02 ...
+    let mut fips_key = [0u8; 32];
+    for (i, b) in fips_key.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    for (key_len, expected) in [(16, FIPS_AES128), (24, FIPS_AES192), (32, FIPS_AES256)].iter() {
+        let mut block = FIPS_PLAINTEXT;
+        aes.encrypt_ecb(&fips_key[..*key_len], &mut block).unwrap();
+        let encrypted = block == *expected;
+        aes.decrypt_ecb(&fips_key[..*key_len], &mut block).unwrap();
+        report("ECB", encrypted && block == FIPS_PLAINTEXT);
+    }
+
+    let mut block = [0u8; 16];
+    block.copy_from_slice(&SP800_PLAINTEXT[..16]);
+    aes.encrypt_cbc(&SP800_KEY, &CBC_IV, &mut block).unwrap();
+    let encrypted = block == CBC_CIPHERTEXT;
+    aes.decrypt_cbc(&SP800_KEY, &CBC_IV, &mut block).unwrap();
+    report("CBC", encrypted && block[..] == SP800_PLAINTEXT[..16]);
+
+    let mut data = SP800_PLAINTEXT;
+    aes.ctr(&SP800_KEY, &CTR_IV, &mut data).unwrap();
+    report("CTR", data == CTR_CIPHERTEXT);
+
+    // A length which is not a multiple of the block size
+    let mut data = [0u8; 21];
+    data.copy_from_slice(&SP800_PLAINTEXT[..21]);
+    aes.ctr(&SP800_KEY, &CTR_IV, &mut data).unwrap();
+    report("CTR partial block", data[..] == CTR_CIPHERTEXT[..21]);
+
+    let mut gcm = Gcm::new(aes);
+    let zero_key = [0u8; 16];
+    let zero_nonce = [0u8; 12];
+
+    // Zero length plaintext and no additional data
+    let tag = gcm
+        .encrypt_in_place(&zero_key, &zero_nonce, &[], &mut [])
+        .unwrap();
+    report("GCM empty", tag == GCM_TC1_TAG);
+
+    let mut data = [0u8; 16];
+    let tag = gcm
+        .encrypt_in_place(&zero_key, &zero_nonce, &[], &mut data)
+        .unwrap();
+    report(
+        "GCM one block",
+        data == GCM_TC2_CIPHERTEXT && tag == GCM_TC2_TAG,
+    );
+
+    let mut data = GCM_TC4_PLAINTEXT;
+    let tag = gcm
+        .encrypt_in_place(&GCM_TC4_KEY, &GCM_TC4_NONCE, &GCM_TC4_AAD, &mut data)
+        .unwrap();
+    let encrypted = data[..] == GCM_TC4_CIPHERTEXT[..] && tag == GCM_TC4_TAG;
+    gcm.decrypt_in_place(&GCM_TC4_KEY, &GCM_TC4_NONCE, &GCM_TC4_AAD, &mut data, &tag)
+        .unwrap();
+    report(
+        "GCM with AAD",
+        encrypted && data[..] == GCM_TC4_PLAINTEXT[..],
+    );
+
+    let tag = gcm
+        .encrypt_in_place(&GCM_TC4_KEY, &GCM_TC4_NONCE, &GCM_TC4_AAD, &mut [])
+        .unwrap();
+    report("GCM AAD only", tag == GCM_AAD_ONLY_TAG);
+
+    // The same message in pieces which don't line up with the block size
+    let mut data = GCM_TC4_PLAINTEXT;
+    gcm.start(&GCM_TC4_KEY, &GCM_TC4_NONCE).unwrap();
+    gcm.aad(&GCM_TC4_AAD[..7]).unwrap();
+    gcm.aad(&GCM_TC4_AAD[7..]).unwrap();
+    let (first, rest) = data.split_at_mut(5);
+    gcm.encrypt(first).unwrap();
+    let (second, third) = rest.split_at_mut(30);
+    gcm.encrypt(second).unwrap();
+    gcm.encrypt(third).unwrap();
+    let tag = gcm.finish().unwrap();
+    report(
+        "GCM streaming",
+        data[..] == GCM_TC4_CIPHERTEXT[..] && tag == GCM_TC4_TAG,
+    );
+
+    // A modified ciphertext must be rejected and left untouched
+    let mut data = GCM_TC4_CIPHERTEXT;
+    data[0] ^= 1;
+    let result = gcm.decrypt_in_place(
+        &GCM_TC4_KEY,
+        &GCM_TC4_NONCE,
+        &GCM_TC4_AAD,
+        &mut data,
+        &GCM_TC4_TAG,
+    );
+    report(
+        "GCM tampered",
+        result == Err(Error::TagMismatch) && data[1..] == GCM_TC4_CIPHERTEXT[1..],
+    );
+
+    // No key material may be left in the engine, the key registers read back what was written
+    let mut aes = gcm.free();
+    aes.clear_key();
+    let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+    let key_registers = [
+        sec_eng.se_aes_0_key_0.read().bits(),
+        sec_eng.se_aes_0_key_1.read().bits(),
+        sec_eng.se_aes_0_key_2.read().bits(),
+        sec_eng.se_aes_0_key_3.read().bits(),
+        sec_eng.se_aes_0_key_4.read().bits(),
+        sec_eng.se_aes_0_key_5.read().bits(),
+        sec_eng.se_aes_0_key_6.read().bits(),
+        sec_eng.se_aes_0_key_7.read().bits(),
+    ];
+    report("Key cleared", key_registers.iter().all(|&word| word == 0));
+
+    loop {}
+}
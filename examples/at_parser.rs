@@ -0,0 +1,200 @@
+/*
+
+   Runs the AT command parser against a modem simulated by the TimerCh0 interrupt.
+
+   Connect GPIO16 (UART0 TX) to GPIO1, which is UART0 RX here instead of GPIO7, so everything
+   sent comes back: the commands as their echo, and the replies which the timer interrupt writes
+   into the TX FIFO a few milliseconds after each command. The parser has to skip the echo and
+   return the reply as `Ok`, `Data` or `Error`, report a `Timeout` when there's no reply, and find
+   a `+RECEIVE:` line among other lines with `wait_for_urc`. The results are printed over UART0
+   once the tests are done, followed by "ok" or "FAILED".
+
+   Needs the `at-parser` feature:
+   cargo run --example at_parser --features at-parser
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_time::{duration::*, rate::*};
+use hal::{
+    at::{AtError, AtParser, AtResponse},
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    interrupts::{self, Interrupt},
+    pac,
+    prelude::*,
+    serial::*,
+    sync::SpinLock,
+    timer::*,
+};
+use panic_halt as _;
+use riscv::register::mcycle;
+
+const SYSCLK_HZ: u32 = 160_000_000;
+
+static TIMER: SpinLock<Option<ConfiguredTimerChannel0>> = SpinLock::new(None);
+
+/// Bytes the simulated modem still has to send, and the milliseconds until it starts
+static REPLY: SpinLock<Option<(u32, &'static [u8])>> = SpinLock::new(None);
+
+fn reply_after(ms: u32, reply: &'static [u8]) {
+    *REPLY.lock_irq_disabled() = Some((ms, reply));
+}
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin1 = parts.pin1.into_uart_sig1();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux1 = parts.uart_mux1.into_uart0_rx();
+
+    let serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin1, mux1)),
+        clocks,
+    );
+
+    let timers = dp.TIMER.split();
+    let timer = timers
+        .channel0
+        .set_clock_source(ClockSource::Fclk(&clocks), 1_000_000_u32.Hz());
+    timer.set_match0(1000_u32.microseconds());
+    timer.set_preload_value(0.microseconds());
+    timer.set_preload(Preload::PreloadMatchComparator0);
+    timer.enable_match0_interrupt();
+    timer.enable();
+    *TIMER.lock_irq_disabled() = Some(timer);
+    interrupts::enable(Interrupt::TimerCh0);
+
+    let mut modem = AtParser::new(serial, clocks);
+
+    reply_after(2, b"\r\nOK\r\n");
+    let ok_result = modem.send_command("AT", 100);
+
+    reply_after(2, b"\r\n+GMR: 1.2.3\r\nSDK 3.0\r\n\r\nOK\r\n");
+    let data_result = modem.send_command("AT+GMR", 100);
+
+    reply_after(2, b"\r\n+CME ERROR: 10\r\n");
+    let error_result = modem.send_command("AT+CPIN?", 100);
+
+    let start = mcycle::read() as u32;
+    let timeout_result = modem.send_command("AT+SILENT", 20);
+    let timeout_ms = (mcycle::read() as u32).wrapping_sub(start) / (SYSCLK_HZ / 1000);
+
+    reply_after(2, b"+CSQ: 20,0\r\nNO CARRIER\r\n+RECEIVE: 0,5\r\n");
+    let urc_result = modem.wait_for_urc("+RECEIVE:", 100);
+
+    interrupts::disable(Interrupt::TimerCh0);
+    let mut serial = modem.free();
+    writeln!(serial, "\r").ok();
+
+    let mut failed = false;
+
+    let ok = ok_result == Ok(AtResponse::Ok);
+    writeln!(
+        serial,
+        "AT: {:?}, {}\r",
+        ok_result,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    let ok = match &data_result {
+        Ok(AtResponse::Data(data)) => data == "+GMR: 1.2.3\nSDK 3.0",
+        _ => false,
+    };
+    writeln!(
+        serial,
+        "AT+GMR: {:?}, {}\r",
+        data_result,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    let ok = error_result == Ok(AtResponse::Error(10));
+    writeln!(
+        serial,
+        "AT+CPIN?: {:?}, {}\r",
+        error_result,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    let ok = timeout_result == Err(AtError::Timeout) && (20..=22).contains(&timeout_ms);
+    writeln!(
+        serial,
+        "AT+SILENT: {:?} after {} ms, {}\r",
+        timeout_result,
+        timeout_ms,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    let ok = match &urc_result {
+        Ok(urc) => urc == "+RECEIVE: 0,5",
+        _ => false,
+    };
+    writeln!(
+        serial,
+        "URC: {:?}, {}\r",
+        urc_result,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
+
+hal::interrupt!(TimerCh0, on_timer);
+
+fn on_timer() {
+    if let Some(timer) = TIMER.lock_irq_disabled().as_mut() {
+        timer.clear_match0_interrupt();
+    }
+    interrupts::clear_interrupt(Interrupt::TimerCh0);
+
+    let mut reply = REPLY.lock();
+    let (ms, mut bytes) = match *reply {
+        Some(pending) => pending,
+        None => return,
+    };
+    if ms > 0 {
+        *reply = Some((ms - 1, bytes));
+        return;
+    }
+
+    // Writes as much as fits into the TX FIFO, the rest follows on the next tick
+    let uart = unsafe { &*pac::UART::ptr() };
+    while let Some((&byte, rest)) = bytes.split_first() {
+        if uart.uart_fifo_config_1.read().tx_fifo_cnt().bits() == 0 {
+            break;
+        }
+        uart.uart_fifo_wdata
+            .write(|w| unsafe { w.bits(byte as u32) });
+        bytes = rest;
+    }
+    *reply = if bytes.is_empty() {
+        None
+    } else {
+        Some((0, bytes))
+    };
+}
@@ -2,7 +2,7 @@
 #![no_main]
 
 use bl602_hal as hal;
-use embedded_hal::delay::blocking::DelayMs;
+use embedded_hal::delay::blocking::DelayUs;
 use embedded_hal::digital::blocking::OutputPin;
 use hal::{
     clock::{Strict, SysclkFreq, UART_PLL_FREQ},
@@ -0,0 +1,106 @@
+/*
+
+   Checks the HAL's implementation of the `critical_section` crate.
+
+   How nested critical sections save and restore the interrupt state is checked by the tests of
+   `sync` on the PC, against a flag standing in for `mstatus.MIE`. This checks the real thing in
+   an interrupt handler: interrupts are disabled inside a critical section entered there, still
+   disabled after it, and enabled again after the handler returns. The handler is TimerCh0,
+   triggered from software. The results are printed over UART0, followed by "ok" or "FAILED".
+
+   Build with `--features critical-section-impl`.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    interrupts::{self, Interrupt, TrapFrame},
+    pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+use riscv::register::mstatus;
+
+static HANDLER_RAN: AtomicBool = AtomicBool::new(false);
+/// Checks failed in the handler, one bit each
+static HANDLER_FAILED: AtomicU8 = AtomicU8::new(0);
+
+fn enabled() -> bool {
+    mstatus::read().mie()
+}
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut failed = false;
+    let mut check = |name: &str, ok: bool| {
+        writeln!(serial, "{}: {}\r", name, if ok { "ok" } else { "FAILED" }).ok();
+        failed |= !ok;
+    };
+
+    unsafe { riscv::interrupt::enable() };
+
+    // In an interrupt handler, which runs with interrupts disabled
+    interrupts::enable(Interrupt::TimerCh0);
+    interrupts::pend(Interrupt::TimerCh0);
+    while !HANDLER_RAN.load(Ordering::SeqCst) {}
+    let handler_failed = HANDLER_FAILED.load(Ordering::SeqCst);
+    check(
+        "disabled in the handler's critical section",
+        handler_failed & 1 == 0,
+    );
+    check(
+        "still disabled in the handler afterwards",
+        handler_failed & 2 == 0,
+    );
+    check("enabled again after the handler", enabled());
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
+
+#[allow(non_snake_case)]
+#[no_mangle]
+fn TimerCh0(_trap_frame: &mut TrapFrame) {
+    interrupts::unpend(Interrupt::TimerCh0);
+    interrupts::disable(Interrupt::TimerCh0);
+
+    let inside = critical_section::with(|_| enabled());
+    let mut failed = 0;
+    if inside {
+        failed |= 1;
+    }
+    if enabled() {
+        failed |= 2;
+    }
+
+    HANDLER_FAILED.store(failed, Ordering::SeqCst);
+    HANDLER_RAN.store(true, Ordering::SeqCst);
+}
@@ -0,0 +1,145 @@
+/*
+
+   Checks the debug writer on UART0, including writes which interrupt each other.
+
+   UART0 is registered as the debug writer. Then TimerCh0 prints a line every 50 µs while `main`
+   prints long lines: the timer's lines which start in the middle of one of `main`'s have to be
+   skipped, so no line is torn apart. Finally the timer interrupt panics while `main` is in the
+   middle of a line. The panic handler of the HAL has to print the panic message right after the
+   line cut short, which ends in "ok"; "FAILED" or no panic message means it failed.
+
+   Needs the `panic-handler` feature:
+   cargo run --example debug_writer --features panic-handler
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use embedded_time::{duration::*, rate::*};
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    debug, debug_println,
+    interrupts::{self, Interrupt},
+    pac,
+    prelude::*,
+    serial::*,
+    sync::SpinLock,
+    timer::*,
+};
+
+static TIMER: SpinLock<Option<ConfiguredTimerChannel0>> = SpinLock::new(None);
+
+static TICKS: AtomicU32 = AtomicU32::new(0);
+static PRINTED_TICKS: AtomicU32 = AtomicU32::new(0);
+static PRINT_TICKS: AtomicBool = AtomicBool::new(false);
+static PANIC_WHILE_BUSY: AtomicBool = AtomicBool::new(false);
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+    serial.into_debug_writer();
+
+    let mut failed = false;
+
+    let ok = debug_println!("written by the debug writer") && debug::skipped() == 0;
+    debug_println!("plain write: {}", if ok { "ok" } else { "FAILED" });
+    failed |= !ok;
+
+    let timers = dp.TIMER.split();
+    let timer = timers
+        .channel0
+        .set_clock_source(ClockSource::Fclk(&clocks), 1_000_000_u32.Hz());
+    timer.set_match0(50_u32.microseconds());
+    timer.set_preload_value(0.microseconds());
+    timer.set_preload(Preload::PreloadMatchComparator0);
+    timer.enable_match0_interrupt();
+    timer.enable();
+    *TIMER.lock_irq_disabled() = Some(timer);
+    interrupts::enable(Interrupt::TimerCh0);
+
+    // Each line takes about 400 µs at 2 MBaud, so ticks come while it is written
+    PRINT_TICKS.store(true, Ordering::SeqCst);
+    let mut main_lines_skipped = 0;
+    for line in 0..20 {
+        if !debug_println!(
+            "line {:2} from main, long enough for timer interrupts to come in between",
+            line
+        ) {
+            main_lines_skipped += 1;
+        }
+    }
+    PRINT_TICKS.store(false, Ordering::SeqCst);
+
+    let ticks = TICKS.load(Ordering::SeqCst);
+    let printed = PRINTED_TICKS.load(Ordering::SeqCst);
+    let skipped = debug::skipped();
+    // The handler's writes end before `main` continues, so `main` never finds the writer busy
+    let ok = skipped > 0 && printed + skipped == ticks && main_lines_skipped == 0;
+    debug_println!(
+        "{} ticks, {} printed, {} skipped, {} lines of main skipped: {}",
+        ticks,
+        printed,
+        skipped,
+        main_lines_skipped,
+        if ok { "ok" } else { "FAILED" }
+    );
+    failed |= !ok;
+
+    if failed {
+        debug_println!("FAILED");
+        loop {}
+    }
+
+    debug_println!("the next line is cut short by a panic in TimerCh0");
+    PANIC_WHILE_BUSY.store(true, Ordering::SeqCst);
+    for _ in 0..100 {
+        debug_println!("main keeps writing this line until the panic interrupts it");
+    }
+
+    // Only reached if the panic didn't happen
+    PANIC_WHILE_BUSY.store(false, Ordering::SeqCst);
+    debug_println!("no panic, FAILED");
+
+    loop {}
+}
+
+hal::interrupt!(TimerCh0, on_timer);
+
+fn on_timer() {
+    if let Some(timer) = TIMER.lock_irq_disabled().as_mut() {
+        timer.clear_match0_interrupt();
+    }
+    interrupts::clear_interrupt(Interrupt::TimerCh0);
+
+    if PANIC_WHILE_BUSY.load(Ordering::SeqCst) && debug::is_busy() {
+        panic!("in TimerCh0 while main was writing, ok");
+    }
+
+    if PRINT_TICKS.load(Ordering::SeqCst) {
+        let tick = TICKS.fetch_add(1, Ordering::SeqCst);
+        if debug_println!("tick {}", tick) {
+            PRINTED_TICKS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+/*
+
+   Checks the software interrupt and the Deferred queue with a producer which preempts the
+   consumer.
+
+   TimerCh0 fires every 20 µs at priority 10 and pushes a running number into the queue. The
+   software interrupt handler runs at priority 1, lets the timer preempt it and pops the numbers,
+   which have to come in order without gaps. Every 64th number it busy waits for 1 ms, so the
+   queue runs full and the timer has to try again with the same number. After half a second the
+   timer is stopped, and every pushed number has to have been popped.
+
+   Before that, a software interrupt pended and cleared with interrupts disabled must not run the
+   handler. The results are printed over UART0, followed by "ok" or "FAILED".
+
+   The order of the queue, turning items away when it is full and pending the software interrupt
+   are checked by the tests of `sync` on the PC, this is about the handlers preempting each other.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use embedded_hal::delay::blocking::DelayUs;
+use embedded_time::{duration::*, rate::*};
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    interrupts::{self, Interrupt, Priority},
+    pac,
+    prelude::*,
+    serial::*,
+    sync::{Deferred, SpinLock},
+    timer::*,
+};
+use panic_halt as _;
+use riscv::register::mcycle;
+
+const SYSCLK_HZ: u32 = 160_000_000;
+
+static TIMER: SpinLock<Option<ConfiguredTimerChannel0>> = SpinLock::new(None);
+static QUEUE: Deferred<u32> = Deferred::new();
+
+/// Next number the timer pushes
+static PUSHED: AtomicU32 = AtomicU32::new(0);
+/// Next number the software handler expects
+static POPPED: AtomicU32 = AtomicU32::new(0);
+/// Numbers which didn't come in order
+static OUT_OF_ORDER: AtomicU32 = AtomicU32::new(0);
+/// Pushes which preempted the software handler
+static PUSHED_DURING_POP: AtomicU32 = AtomicU32::new(0);
+static SOFTWARE_CALLS: AtomicU32 = AtomicU32::new(0);
+static POPPING: AtomicBool = AtomicBool::new(false);
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut delay = McycleDelay::new(clocks.sysclk().0);
+    let mut failed = false;
+
+    interrupts::set_level_bits(4);
+    interrupts::set_priority(Interrupt::TimerCh0, Priority::new(10).unwrap());
+    interrupts::set_software_priority(Priority::new(1).unwrap());
+    interrupts::set_software_handler(on_software);
+
+    riscv::interrupt::free(|_| {
+        interrupts::pend_software();
+        interrupts::clear_software();
+    });
+    delay.delay_ms(1).ok();
+    let ok = SOFTWARE_CALLS.load(Ordering::SeqCst) == 0;
+    writeln!(
+        serial,
+        "cleared interrupt not taken: {}\r",
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    let timers = dp.TIMER.split();
+    let timer = timers
+        .channel0
+        .set_clock_source(ClockSource::Fclk(&clocks), 1_000_000_u32.Hz());
+    timer.set_match0(20_u32.microseconds());
+    timer.set_preload_value(0.microseconds());
+    timer.set_preload(Preload::PreloadMatchComparator0);
+    timer.enable_match0_interrupt();
+    timer.enable();
+    *TIMER.lock_irq_disabled() = Some(timer);
+    interrupts::enable(Interrupt::TimerCh0);
+
+    delay.delay_ms(500).ok();
+    interrupts::disable(Interrupt::TimerCh0);
+    delay.delay_ms(10).ok();
+
+    let pushed = PUSHED.load(Ordering::SeqCst);
+    let popped = POPPED.load(Ordering::SeqCst);
+    let out_of_order = OUT_OF_ORDER.load(Ordering::SeqCst);
+    let ok = pushed > 0 && popped == pushed && out_of_order == 0 && QUEUE.is_empty();
+    writeln!(
+        serial,
+        "{} pushed, {} popped, {} out of order: {}\r",
+        pushed,
+        popped,
+        out_of_order,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    let during_pop = PUSHED_DURING_POP.load(Ordering::SeqCst);
+    let full = QUEUE.dropped();
+    let ok = during_pop > 0 && full > 0;
+    writeln!(
+        serial,
+        "{} pushes while popping, queue full {} times: {}\r",
+        during_pop,
+        full,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
+
+hal::interrupt!(TimerCh0, on_timer);
+
+fn on_timer() {
+    if let Some(timer) = TIMER.lock_irq_disabled().as_mut() {
+        timer.clear_match0_interrupt();
+    }
+    interrupts::clear_interrupt(Interrupt::TimerCh0);
+
+    // Only the timer pushes, so the number can be read and written back separately
+    let number = PUSHED.load(Ordering::SeqCst);
+    if QUEUE.push(number).is_ok() {
+        PUSHED.store(number + 1, Ordering::SeqCst);
+        if POPPING.load(Ordering::SeqCst) {
+            PUSHED_DURING_POP.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+fn on_software() {
+    SOFTWARE_CALLS.fetch_add(1, Ordering::SeqCst);
+    let _preemptible = interrupts::allow_preemption_above(Priority::new(1).unwrap());
+
+    POPPING.store(true, Ordering::SeqCst);
+    while let Some(number) = QUEUE.pop() {
+        let expected = POPPED.load(Ordering::SeqCst);
+        if number != expected {
+            OUT_OF_ORDER.fetch_add(1, Ordering::SeqCst);
+        }
+        POPPED.store(number + 1, Ordering::SeqCst);
+
+        if number % 64 == 63 {
+            let start = mcycle::read() as u32;
+            while (mcycle::read() as u32).wrapping_sub(start) < SYSCLK_HZ / 1000 {}
+        }
+    }
+    POPPING.store(false, Ordering::SeqCst);
+}
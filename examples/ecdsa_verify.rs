@@ -0,0 +1,101 @@
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    p256::{self, AffinePoint},
+    pac,
+    pka::{OperandSize, Pka, WordOrder},
+    prelude::*,
+    sec_eng::SecEngExt,
+    serial::*,
+    sha::{Mode, Sha},
+};
+use panic_halt as _;
+
+// RFC 6979 appendix A.2.5: P-256 key, SHA-256 signature of the message "sample"
+const PUBLIC_X: [u8; 32] = [
+    0x60, 0xfe, 0xd4, 0xba, 0x25, 0x5a, 0x9d, 0x31, 0xc9, 0x61, 0xeb, 0x74, 0xc6, 0x35, 0x6d, 0x68,
+    0xc0, 0x49, 0xb8, 0x92, 0x3b, 0x61, 0xfa, 0x6c, 0xe6, 0x69, 0x62, 0x2e, 0x60, 0xf2, 0x9f, 0xb6,
+];
+const PUBLIC_Y: [u8; 32] = [
+    0x79, 0x03, 0xfe, 0x10, 0x08, 0xb8, 0xbc, 0x99, 0xa4, 0x1a, 0xe9, 0xe9, 0x56, 0x28, 0xbc, 0x64,
+    0xf2, 0xf1, 0xb2, 0x0c, 0x2d, 0x7e, 0x9f, 0x51, 0x77, 0xa3, 0xc2, 0x94, 0xd4, 0x46, 0x22, 0x99,
+];
+const SIGNATURE_R: [u8; 32] = [
+    0xef, 0xd4, 0x8b, 0x2a, 0xac, 0xb6, 0xa8, 0xfd, 0x11, 0x40, 0xdd, 0x9c, 0xd4, 0x5e, 0x81, 0xd6,
+    0x9d, 0x2c, 0x87, 0x7b, 0x56, 0xaa, 0xf9, 0x91, 0xc3, 0x4d, 0x0e, 0xa8, 0x4e, 0xaf, 0x37, 0x16,
+];
+const SIGNATURE_S: [u8; 32] = [
+    0xf7, 0xcb, 0x1c, 0x94, 0x2d, 0x65, 0x7c, 0x41, 0xd4, 0x36, 0xc7, 0xa1, 0xb6, 0xe2, 0x9f, 0x65,
+    0xf3, 0xe9, 0x00, 0xdb, 0xb9, 0xaf, 0xf4, 0x06, 0x4d, 0xc4, 0xab, 0x2f, 0x84, 0x3a, 0xcd, 0xa8,
+];
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(115_200.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let sec_eng = dp.SEC_ENG.split();
+    let mut sha = Sha::new(sec_eng.sha, &mut parts.clk_cfg);
+    let mut pka = Pka::new(
+        sec_eng.pka,
+        &mut parts.clk_cfg,
+        WordOrder::LeastSignificantFirst,
+    );
+
+    let mut report = |name: &str, ok: bool| {
+        let result = if ok { "ok" } else { "FAILED" };
+        writeln!(serial, "{}: {}\r", name, result).ok();
+    };
+
+    // 4^13 mod 497 = 445
+    let mut out = [0u32; 1];
+    pka.mod_exp(OperandSize::Bits256, &[4], &[13], &[497], &mut out)
+        .unwrap();
+    report("modexp", out[0] == 445);
+
+    let public_key = AffinePoint::from_coordinates(&PUBLIC_X, &PUBLIC_Y);
+    let digest = sha.digest(Mode::Sha256, b"sample").unwrap();
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(digest.as_bytes());
+
+    let valid = p256::verify(&mut pka, &public_key, &hash, &SIGNATURE_R, &SIGNATURE_S);
+    report("ECDSA valid signature", valid.is_ok());
+
+    let mut wrong_hash = hash;
+    wrong_hash[0] ^= 1;
+    let invalid = p256::verify(
+        &mut pka,
+        &public_key,
+        &wrong_hash,
+        &SIGNATURE_R,
+        &SIGNATURE_S,
+    );
+    report(
+        "ECDSA wrong message",
+        invalid == Err(p256::Error::InvalidSignature),
+    );
+
+    loop {}
+}
@@ -0,0 +1,149 @@
+/*
+
+   Decodes eFuse data blocks and reads the MAC address, chip ID and trims of the chip.
+
+   Three data blocks written down in the eFuse layout are decoded with `EfuseData`: one with
+   only a factory MAC address, ADC and TSEN trims and an RC32K trim with a wrong parity bit, one
+   whose MAC address has been replaced in slot 1 and whose ADC trim isn't enabled, and one with
+   a broken slot 1 and an empty slot 0. Each field has to decode to the value it was built from,
+   or be rejected. Then the array of the chip is loaded and read: the MAC address has to be
+   valid and match the decoded copy of the whole block. The results are printed over UART0,
+   followed by "ok" or "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    efuse::{self, AdcTrim, EfuseData, WORDS},
+    pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+/// Returns a data block with `words` set at their byte offsets
+const fn block(words: &[(usize, u32)]) -> EfuseData {
+    let mut block = [0; WORDS];
+    let mut i = 0;
+    while i < words.len() {
+        block[words[i].0 / 4] = words[i].1;
+        i += 1;
+    }
+    EfuseData::from_words(block)
+}
+
+/// Factory MAC b4:e8:42:1c:9a:33, ADC gain -5, TSEN 0x7a3, RC32K 0x1b5 with a wrong parity bit
+const FACTORY: EfuseData = block(&[
+    (0x14, 0x1c42_e8b4),
+    (0x18, 0x001b_339a),
+    (0x6c, 0x0de8_fffb),
+    (0x70, 0x0000_0db5),
+]);
+
+/// FACTORY's MAC replaced by 02:00:5e:10:20:31, ADC gain 37 not enabled, TSEN 0x690, RC32K 0x2c4
+const REPLACED: EfuseData = block(&[
+    (0x14, 0x1c42_e8b4),
+    (0x18, 0x001b_339a),
+    (0x74, 0x105e_0002),
+    (0x78, 0x0025_3120),
+    (0x6c, 0x09a4_1025),
+    (0x70, 0x0000_0ac4),
+]);
+
+/// Slot 1 with a zero count one off, slot 0 empty
+const BROKEN: EfuseData = block(&[(0x74, 0x3322_1102), (0x78, 0x0020_5544)]);
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut failed = false;
+
+    let mut check = |serial: &mut dyn Write, name: &str, ok: bool| {
+        writeln!(serial, "{}: {}\r", name, if ok { "ok" } else { "FAILED" }).ok();
+        failed |= !ok;
+    };
+
+    let gain = AdcTrim {
+        gain_coefficient: -5,
+    };
+    check(
+        &mut serial,
+        "factory",
+        FACTORY.mac() == Ok([0xb4, 0xe8, 0x42, 0x1c, 0x9a, 0x33])
+            && FACTORY.chip_id() == [0xb4, 0xe8, 0x42, 0x1c, 0x9a, 0x33, 0x1b, 0x00]
+            && FACTORY.adc_trim() == Some(gain)
+            && gain.correct(2000) == 1995
+            && FACTORY.tsen_trim() == Some(0x7a3)
+            && FACTORY.rc32k_trim() == None,
+    );
+    check(
+        &mut serial,
+        "replaced",
+        REPLACED.mac() == Ok([0x02, 0x00, 0x5e, 0x10, 0x20, 0x31])
+            && REPLACED.chip_id() == FACTORY.chip_id()
+            && REPLACED.adc_trim() == None
+            && REPLACED.tsen_trim() == Some(0x690)
+            && REPLACED.rc32k_trim() == Some(0x2c4),
+    );
+    check(
+        &mut serial,
+        "broken",
+        BROKEN.mac() == Err(efuse::Error::NoMac)
+            && BROKEN.word(0x78) == Ok(0x0020_5544)
+            && BROKEN.word(0x80) == Err(efuse::Error::InvalidOffset)
+            && BROKEN.word(0x2) == Err(efuse::Error::InvalidOffset),
+    );
+
+    let data = EfuseData::read();
+    let mac = efuse::read_mac();
+    writeln!(
+        serial,
+        "MAC {:02x?}, chip ID {:02x?}\r",
+        mac,
+        efuse::chip_id()
+    )
+    .ok();
+    writeln!(
+        serial,
+        "ADC {:?}, TSEN {:?}, RC32K {:?}\r",
+        efuse::adc_trim(),
+        efuse::tsen_trim(),
+        efuse::rc32k_trim()
+    )
+    .ok();
+    check(
+        &mut serial,
+        "chip",
+        mac.is_ok()
+            && data.map(|data| data.mac()) == Ok(mac)
+            && data.and_then(|data| data.word(0x14)) == efuse::read_word(0x14),
+    );
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
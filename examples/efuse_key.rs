@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use hal::{
+    aes::{Aes, Error},
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    efuse::{self, KeySlot},
+    pac,
+    prelude::*,
+    sec_eng::SecEngExt,
+    serial::*,
+};
+use panic_halt as _;
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(115_200.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let sec_eng = dp.SEC_ENG.split();
+    let mut aes = Aes::new(sec_eng.aes, &mut parts.clk_cfg);
+
+    let slots = [
+        KeySlot::Slot0,
+        KeySlot::Slot1,
+        KeySlot::Slot2,
+        KeySlot::Slot3,
+    ];
+
+    for &slot in slots.iter() {
+        let locked = efuse::key_slot_locked(slot);
+        let programmed = efuse::key_slot_programmed(slot);
+        writeln!(
+            serial,
+            "{:?}: read-protected {}, programmed {:?}\r",
+            slot, locked, programmed
+        )
+        .ok();
+
+        // Unprogrammed slots, as found on most development boards, have to be refused
+        let result = match (aes.with_efuse_key(slot), programmed) {
+            (Err(Error::KeySlotEmpty), Ok(false)) => "refused as expected",
+            (Ok(_), Ok(true)) | (Ok(_), Err(efuse::Error::ReadLocked)) => "usable",
+            _ => "FAILED",
+        };
+        writeln!(serial, "{:?}: {}\r", slot, result).ok();
+
+        // Encrypting the same block twice has to give the same result with a usable key
+        if let Ok(mut keyed) = aes.with_efuse_key(slot) {
+            let mut first = [0u8; 16];
+            let mut second = [0u8; 16];
+            keyed.encrypt_ecb(&mut first).unwrap();
+            keyed.encrypt_ecb(&mut second).unwrap();
+            let mut plain = first;
+            keyed.decrypt_ecb(&mut plain).unwrap();
+
+            let result = if first == second && plain == [0; 16] {
+                "ok"
+            } else {
+                "FAILED"
+            };
+            writeln!(serial, "{:?} round trip: {}\r", slot, result).ok();
+        }
+    }
+
+    loop {}
+}
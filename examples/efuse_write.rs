@@ -0,0 +1,106 @@
+/*
+
+   Provisions a MAC address and a serial number into the eFuses, as a production line would.
+
+   Nothing is burnt unless `BURN` is set to `true`, every bit burnt stays set for the life of the
+   chip. Without it the example only checks the refusals of the writer which don't program
+   anything: an all-zero MAC address, too much user data, an unaligned offset and the lock and
+   security words through `burn`. With it the MAC address of `MAC` is burnt into MAC slot 1 and
+   `SERIAL` into the user data, then both are read back. A second `burn_mac` has to be refused
+   with `AlreadyProgrammed`. The results are printed over UART0, followed by "ok" or "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    efuse::{self, Error, Irreversible, Writer, LOCK_WORD, SECURITY_WORD, USER_DATA_LEN},
+    pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+/// Set to burn `MAC` and `SERIAL`, which can only be done once per chip
+const BURN: bool = false;
+
+/// Locally administered address to provision
+const MAC: [u8; 6] = [0x02, 0x00, 0x5e, 0x10, 0x20, 0x31];
+
+/// Serial number to provision into the user data
+const SERIAL: &[u8] = b"BL602-000042";
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut failed = false;
+
+    let mut check = |serial: &mut dyn Write, name: &str, ok: bool| {
+        writeln!(serial, "{}: {}\r", name, if ok { "ok" } else { "FAILED" }).ok();
+        failed |= !ok;
+    };
+
+    // Safety: this example is meant to provision the chip it runs on
+    let mut writer = Writer::new(unsafe { Irreversible::new() }, &clocks);
+
+    check(
+        &mut serial,
+        "refusals",
+        writer.burn_mac([0; 6]) == Err(Error::InvalidData)
+            && writer.burn_user_data(&[0xa5; USER_DATA_LEN + 1]) == Err(Error::InvalidData)
+            && unsafe { writer.burn(0x5e, 1) } == Err(Error::InvalidOffset)
+            && unsafe { writer.burn(LOCK_WORD, 1) } == Err(Error::LockWord)
+            && unsafe { writer.burn(SECURITY_WORD, 1) } == Err(Error::LockWord),
+    );
+
+    writeln!(serial, "MAC before {:02x?}\r", efuse::read_mac()).ok();
+
+    if BURN {
+        let mac = writer.burn_mac(MAC);
+        let user_data = writer.burn_user_data(SERIAL);
+        writeln!(serial, "burn MAC {:?}, user data {:?}\r", mac, user_data).ok();
+
+        let mut read = [0; 12];
+        for (i, chunk) in read.chunks_mut(4).enumerate() {
+            let word = efuse::read_word(0x5c + 4 * i).unwrap_or(0);
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+
+        check(
+            &mut serial,
+            "burnt",
+            mac.is_ok()
+                && user_data.is_ok()
+                && efuse::read_mac() == Ok(MAC)
+                && &read[..] == SERIAL
+                && writer.burn_mac(MAC) == Err(Error::AlreadyProgrammed),
+        );
+    }
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
@@ -0,0 +1,128 @@
+/*
+
+   Checks the decoding of exceptions and the fault record which survives a reset.
+
+   After a reset without a recorded fault, the program makes a misaligned load. The fault
+   handler prints what it got and resets the chip with `power::software_reset`. After starting
+   again, the recorded fault has to be the same load, and `clear_last_fault` has to remove it.
+   The results are printed over UART0, followed by "ok" or "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_hal::serial::nb::Write as _;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    gpio::{pin::Pin16, pin::Pin7, Uart, Uart0Rx, Uart0Tx, UartMux0, UartMux7},
+    pac, power,
+    prelude::*,
+    serial::*,
+    sync::SpinLock,
+    trap::{self, Cause, FaultInfo},
+};
+use panic_halt as _;
+
+type Uart0Pins = (
+    (Pin16<Uart>, UartMux0<Uart0Tx>),
+    (Pin7<Uart>, UartMux7<Uart0Rx>),
+);
+
+static SERIAL: SpinLock<Option<Serial<pac::UART, Uart0Pins>>> = SpinLock::new(None);
+
+fn on_fault(info: &FaultInfo) -> ! {
+    // A plain lock could wait forever if the fault happened while the serial was in use
+    if let Some(mut serial) = SERIAL.try_lock() {
+        if let Some(serial) = serial.as_mut() {
+            writeln!(serial, "fault: {}\r", info).ok();
+            nb::block!(serial.flush()).ok();
+        }
+    }
+
+    power::software_reset()
+}
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let fault = trap::last_fault();
+
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let fault = match fault {
+        Some(fault) => fault,
+        None => {
+            let mut failed = false;
+            for code in 0..16 {
+                if Cause::from_code(code).code() != code {
+                    writeln!(serial, "exception code {} doesn't round trip\r", code).ok();
+                    failed = true;
+                }
+            }
+            if failed {
+                writeln!(serial, "FAILED\r").ok();
+                loop {}
+            }
+
+            writeln!(serial, "no fault recorded, making a misaligned load\r").ok();
+            *SERIAL.lock_irq_disabled() = Some(serial);
+            trap::set_fault_handler(on_fault);
+
+            let buffer = [0u32; 2];
+            let address = buffer.as_ptr() as usize + 1;
+            let value = unsafe { (address as *const u32).read_volatile() };
+
+            // Only reached if the load didn't fault
+            if let Some(serial) = SERIAL.lock_irq_disabled().as_mut() {
+                writeln!(serial, "load returned {:#x}, FAILED\r", value).ok();
+            }
+            loop {}
+        }
+    };
+
+    writeln!(
+        serial,
+        "recorded fault: {:?} at {:#010x}\r",
+        fault.cause, fault.mepc
+    )
+    .ok();
+    let recorded = fault.cause == Cause::LoadMisaligned && fault.mepc != 0;
+
+    trap::clear_last_fault();
+    let cleared = trap::last_fault().is_none();
+    writeln!(
+        serial,
+        "cleared: {}\r",
+        if cleared { "ok" } else { "FAILED" }
+    )
+    .ok();
+
+    writeln!(
+        serial,
+        "{}\r",
+        if recorded && cleared { "ok" } else { "FAILED" }
+    )
+    .ok();
+
+    loop {}
+}
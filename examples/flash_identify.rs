@@ -0,0 +1,179 @@
+/*
+
+   Identifies the flash chip and checks the quad enable procedures of the known chips.
+
+   The status register writes which set the quad enable bit are computed for the GD25Q16, the
+   W25Q16 and the XT25F16 from the table of known chips, for status registers with protection
+   bits, busy and write enable latch set, and have to match the write sequences of the
+   datasheets: `01` with both registers for the first two, `31` with status register 2 alone for
+   the XT25F16, and nothing at all once the bit is set. Then the parameters of the flash on the
+   board are read with `flash::identify` and have to describe a usable chip, and its quad enable
+   bit is read without writing it. The results are printed over UART0, followed by "ok" or
+   "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_storage::nor_flash::ReadNorFlash;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    flash::{self, Flash, FlashParams, QuadEnable, SECTOR_SIZE},
+    pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+/// A known chip, the status write expected for a clear quad enable bit and the status registers
+/// it's computed from
+struct Sequence {
+    name: &'static str,
+    jedec_id: [u8; 3],
+    sr1: u8,
+    sr2: u8,
+    opcode: u8,
+    bytes: &'static [u8],
+}
+
+const SEQUENCES: &[Sequence] = &[
+    Sequence {
+        name: "GD25Q16",
+        jedec_id: [0xc8, 0x40, 0x15],
+        sr1: 0x1f,
+        sr2: 0x40,
+        opcode: 0x01,
+        bytes: &[0x1c, 0x42],
+    },
+    Sequence {
+        name: "W25Q16",
+        jedec_id: [0xef, 0x40, 0x15],
+        sr1: 0x1f,
+        sr2: 0x40,
+        opcode: 0x01,
+        bytes: &[0x1c, 0x42],
+    },
+    Sequence {
+        name: "XT25F16",
+        jedec_id: [0x0b, 0x40, 0x15],
+        sr1: 0x1f,
+        sr2: 0x40,
+        opcode: 0x31,
+        bytes: &[0x42],
+    },
+];
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let mut dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut failed = false;
+
+    let mut check = |serial: &mut dyn Write, name: &str, ok: bool| {
+        writeln!(serial, "{}: {}\r", name, if ok { "ok" } else { "FAILED" }).ok();
+        failed |= !ok;
+    };
+
+    for sequence in SEQUENCES {
+        let params = FlashParams::from_jedec_id(sequence.jedec_id);
+        let quad_enable = params.and_then(|params| params.quad_enable);
+        let (sr1, sr2) = (sequence.sr1, sequence.sr2);
+        let write = quad_enable.and_then(|qe| qe.status_write(sr1, sr2));
+        writeln!(serial, "{:?}: {:?}\r", quad_enable, write).ok();
+
+        let written = match write {
+            Some(write) => write.opcode == sequence.opcode && write.bytes() == sequence.bytes,
+            None => false,
+        };
+        // What the write leaves in the status registers, status register 1 is unchanged if
+        // only status register 2 was written
+        let (sr1, sr2) = match sequence.bytes {
+            [sr1, sr2] => (*sr1, *sr2),
+            [sr2] => (sr1, *sr2),
+            _ => (sr1, sr2),
+        };
+        let enabled = match quad_enable {
+            Some(qe) => qe.is_enabled(sr1, sr2) && qe.status_write(sr1, sr2).is_none(),
+            None => false,
+        };
+        check(
+            &mut serial,
+            sequence.name,
+            params.map(|params| params.name) == Some(Some(sequence.name)) && written && enabled,
+        );
+    }
+
+    check(
+        &mut serial,
+        "QER",
+        QuadEnable::from_qer(0b000) == Some(QuadEnable::NotNeeded)
+            && QuadEnable::from_qer(0b010) == Some(QuadEnable::Sr1Bit6)
+            && QuadEnable::from_qer(0b011) == Some(QuadEnable::Sr2Bit7)
+            && QuadEnable::from_qer(0b101) == Some(QuadEnable::Sr2Bit1WithSr1)
+            && QuadEnable::from_qer(0b110) == Some(QuadEnable::Sr2Bit1)
+            && QuadEnable::from_qer(0b111) == None,
+    );
+
+    let params = flash::identify(&mut dp.SF_CTRL, &clocks);
+    writeln!(serial, "{:?}\r", params).ok();
+    let params = match params {
+        Ok(params) => params,
+        Err(_) => {
+            writeln!(serial, "FAILED\r").ok();
+            loop {}
+        }
+    };
+    check(
+        &mut serial,
+        "identify",
+        params.sector_erase().is_some()
+            && params.capacity.is_power_of_two()
+            && params.capacity >= 64 * 1024
+            && params.page_size >= 256
+            && params.program_timeout_ms > 0,
+    );
+
+    let mut flash = match Flash::new(dp.SF_CTRL, &clocks) {
+        Ok(flash) => flash,
+        Err(error) => {
+            writeln!(serial, "Flash::new: {:?}\r\nFAILED\r", error).ok();
+            loop {}
+        }
+    };
+    check(
+        &mut serial,
+        "flash",
+        *flash.params() == params && flash.capacity() as u32 % SECTOR_SIZE == 0,
+    );
+
+    match flash.quad_enabled() {
+        Ok(enabled) => writeln!(serial, "quad enable bit set: {}\r", enabled).ok(),
+        // Fine for chips with an unknown procedure
+        Err(error) => writeln!(serial, "quad enable bit: {:?}\r", error).ok(),
+    };
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
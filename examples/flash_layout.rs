@@ -0,0 +1,65 @@
+/*
+
+   Prints the partition table of the chip and the `FW` partition boot2 uses, reading the flash
+   but never writing it. The result is printed over UART0.
+
+   Parsing and building tables and boot headers is checked by the tests of `flash::layout` on
+   the PC.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    flash::{layout, Flash},
+    pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    match Flash::new(dp.SF_CTRL, &clocks) {
+        Ok(mut flash) => {
+            match layout::active_table(&mut flash) {
+                Ok(Some((index, table))) => {
+                    writeln!(serial, "table {}: {:?}\r", index, table).ok();
+                }
+                result => {
+                    writeln!(serial, "table: {:?}\r", result).ok();
+                }
+            }
+            writeln!(serial, "FW: {:?}\r", layout::find(&mut flash, "FW")).ok();
+        }
+        Err(error) => {
+            writeln!(serial, "Flash::new: {:?}\r", error).ok();
+        }
+    }
+
+    loop {}
+}
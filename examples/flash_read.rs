@@ -0,0 +1,181 @@
+/*
+
+   Measures the ways of reading the flash and checks that they return the same data.
+
+   64 KiB from the offset the firmware was booted from are read four times, through a 4 KiB
+   buffer: byte by byte from the XIP window, with `read`, which copies words from the XIP window,
+   with `read_dma` on DMA channel 0, and with `read_with_commands`. The time of each path is
+   printed in core cycles and KiB/s; every run but the first starts with the data cached, so the
+   byte loop and the word copy are measured after a warm-up read.
+
+   Then reads which cross the start of the XIP window, end at the end of the flash, or start and
+   end unaligned into unaligned buffers are compared against `read_with_commands`, for `read`,
+   `read_dma` and `read_iter`. The results are printed over UART0, followed by "ok" or
+   "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_storage::nor_flash::ReadNorFlash;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    flash::{Flash, XIP_BASE},
+    pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+use riscv::register::mcycle;
+
+const BENCH_LEN: u32 = 64 * 1024;
+const CHUNK: usize = 4096;
+const SYSCLK: u64 = 160_000_000;
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let mut dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut flash = match Flash::new(dp.SF_CTRL, &clocks) {
+        Ok(flash) => flash,
+        Err(error) => {
+            writeln!(serial, "Flash::new: {:?}\r\nFAILED\r", error).ok();
+            loop {}
+        }
+    };
+    let dma = &mut dp.DMA;
+    let capacity = flash.capacity() as u32;
+    let xip_offset = flash.xip_offset();
+    writeln!(
+        serial,
+        "capacity {:#x}, XIP at {:x?}\r",
+        capacity, xip_offset
+    )
+    .ok();
+
+    let mut failed = false;
+
+    let mut check = |serial: &mut dyn Write, name: &str, ok: bool| {
+        writeln!(serial, "{}: {}\r", name, if ok { "ok" } else { "FAILED" }).ok();
+        failed |= !ok;
+    };
+
+    let mut buffer = [0u8; CHUNK];
+    let start = xip_offset.unwrap_or(0);
+    let len = BENCH_LEN.min(capacity - start);
+    let report = |serial: &mut dyn Write, name: &str, cycles: u64| {
+        let kib_per_s = len as u64 * SYSCLK / cycles.max(1) / 1024;
+        writeln!(serial, "{}: {} cycles, {} KiB/s\r", name, cycles, kib_per_s).ok();
+    };
+
+    if let Some(xip_offset) = xip_offset {
+        let run = |buffer: &mut [u8; CHUNK]| {
+            for offset in (0..len).step_by(CHUNK) {
+                let n = CHUNK.min((len - offset) as usize);
+                let xip = XIP_BASE + (start - xip_offset + offset) as usize;
+                for (i, byte) in buffer[..n].iter_mut().enumerate() {
+                    *byte = unsafe { ((xip + i) as *const u8).read_volatile() };
+                }
+            }
+        };
+        run(&mut buffer);
+        let begin = mcycle::read64();
+        run(&mut buffer);
+        report(&mut serial, "byte loop", mcycle::read64() - begin);
+    }
+
+    let mut run = |flash: &mut Flash, path: usize, buffer: &mut [u8; CHUNK]| {
+        for offset in (start..start + len).step_by(CHUNK) {
+            let n = CHUNK.min((start + len - offset) as usize);
+            let result = match path {
+                0 => flash.read(offset, &mut buffer[..n]),
+                1 => flash.read_dma(offset, &mut buffer[..n], dma, 0),
+                _ => flash.read_with_commands(offset, &mut buffer[..n]),
+            };
+            result?;
+        }
+        Ok::<(), hal::flash::Error>(())
+    };
+    run(&mut flash, 0, &mut buffer).ok();
+    for (path, name) in ["word copy", "DMA", "commands"].iter().enumerate() {
+        let begin = mcycle::read64();
+        let result = run(&mut flash, path, &mut buffer);
+        let cycles = mcycle::read64() - begin;
+        match result {
+            Ok(()) => report(&mut serial, name, cycles),
+            Err(error) => {
+                writeln!(serial, "{}: {:?}\r", name, error).ok();
+                check(&mut serial, name, false);
+            }
+        }
+    }
+
+    // Ranges to compare, as offset and length, and the offset into the buffers
+    let mut ranges = [(0u32, 0usize, 0usize); 4];
+    ranges[0] = (capacity - 300, 300, 0);
+    ranges[1] = (start + 1, 1001, 3);
+    ranges[2] = (start + 4, 2048, 1);
+    if let Some(xip_offset) = xip_offset.filter(|&offset| offset >= 1000) {
+        ranges[3] = (xip_offset - 1000, 2001, 2);
+    }
+
+    let mut expected = [0u8; CHUNK];
+    for &(offset, len, skew) in ranges.iter().filter(|range| range.1 > 0) {
+        let reference = flash
+            .read_with_commands(offset, &mut expected[..len])
+            .is_ok();
+
+        let same = |buffer: &[u8]| reference && buffer[skew..skew + len] == expected[..len];
+
+        for byte in buffer.iter_mut() {
+            *byte = 0;
+        }
+        let read = flash.read(offset, &mut buffer[skew..skew + len]).is_ok() && same(&buffer);
+
+        for byte in buffer.iter_mut() {
+            *byte = 0;
+        }
+        let read_dma = flash
+            .read_dma(offset, &mut buffer[skew..skew + len], dma, 0)
+            .is_ok()
+            && same(&buffer);
+
+        let iter = match flash.read_iter(offset, len as u32) {
+            Ok(mut iter) => {
+                let equal = (&mut iter).eq(expected[..len].iter().copied());
+                reference && equal && iter.error().is_none()
+            }
+            Err(_) => false,
+        };
+
+        writeln!(serial, "{:#x}+{}:\r", offset, len).ok();
+        check(&mut serial, "  read", read);
+        check(&mut serial, "  read_dma", read_dma);
+        check(&mut serial, "  read_iter", iter);
+    }
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
@@ -0,0 +1,217 @@
+/*
+
+   Keeps settings in the last 4 KiB sector of the flash with a read/modify/write helper.
+
+   `update_sector` only erases the sector when a change needs a bit to go from 0 to 1. Any other
+   change is programmed over the old content, and an unchanged sector isn't touched at all, so
+   the sector wears out no faster than the settings actually need. The example erases the
+   sector, stores a record, stores it again, clears a flag and sets it back; each step has to
+   take the expected kind of update and read back what was stored, through the XIP window as
+   well as with read commands. A record written with its CRC has to verify, one written with a
+   wrong CRC has to be reported and get its sector erased. Erasing the running firmware, writing
+   past the end of the flash and erasing half a sector have to be rejected. The results are
+   printed over UART0, followed by "ok" or "FAILED".
+
+   The sector is erased and rewritten a few times on each run.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    crc::{Algorithm, Crc},
+    flash::{self, Flash, SECTOR_SIZE},
+    pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+const SECTOR: usize = SECTOR_SIZE as usize;
+
+/// Stored from flash, the driver has to copy it to RAM before programming
+static RECORD: &[u8] = b"wifi=home;channel=6;flags=\xff";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Update {
+    Unchanged,
+    Programmed,
+    Erased,
+}
+
+/// Applies `modify` to the sector at `offset`, erasing it only if a bit has to be set
+fn update_sector(
+    flash: &mut Flash,
+    offset: u32,
+    modify: impl FnOnce(&mut [u8; SECTOR]),
+) -> Result<Update, flash::Error> {
+    let mut old = [0u8; SECTOR];
+    flash.read(offset, &mut old)?;
+    let mut new = old;
+    modify(&mut new);
+
+    if new[..] == old[..] {
+        return Ok(Update::Unchanged);
+    }
+
+    let needs_erase = old
+        .iter()
+        .zip(new.iter())
+        .any(|(&old, &new)| old & new != new);
+    if needs_erase {
+        flash.erase(offset, offset + SECTOR_SIZE)?;
+        old = [0xff; SECTOR];
+    }
+
+    // Only the pages which changed are programmed
+    let page = flash::PAGE_SIZE as usize;
+    for start in (0..SECTOR).step_by(page) {
+        let range = start..start + page;
+        if new[range.clone()] != old[range.clone()] {
+            flash.write(offset + start as u32, &new[range])?;
+        }
+    }
+
+    Ok(if needs_erase {
+        Update::Erased
+    } else {
+        Update::Programmed
+    })
+}
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut flash = match Flash::new(dp.SF_CTRL, &clocks) {
+        Ok(flash) => flash,
+        Err(error) => {
+            writeln!(serial, "Flash::new: {:?}\r\nFAILED\r", error).ok();
+            loop {}
+        }
+    };
+    writeln!(serial, "{:?}\r", flash).ok();
+
+    let settings = flash.capacity() as u32 - SECTOR_SIZE;
+    let mut failed = false;
+
+    let mut check = |serial: &mut dyn Write, name: &str, ok: bool| {
+        writeln!(serial, "{}: {}\r", name, if ok { "ok" } else { "FAILED" }).ok();
+        failed |= !ok;
+    };
+
+    let erased = flash.erase(settings, settings + SECTOR_SIZE);
+    let mut sector = [0u8; SECTOR];
+    let read = flash.read(settings, &mut sector);
+    check(
+        &mut serial,
+        "erase",
+        erased.is_ok() && read.is_ok() && sector.iter().all(|&b| b == 0xff),
+    );
+
+    let store_record = |sector: &mut [u8; SECTOR]| sector[..RECORD.len()].copy_from_slice(RECORD);
+    let update = update_sector(&mut flash, settings, store_record);
+    flash.read(settings, &mut sector).ok();
+    check(
+        &mut serial,
+        "store",
+        update == Ok(Update::Programmed) && sector[..RECORD.len()] == *RECORD,
+    );
+
+    let update = update_sector(&mut flash, settings, store_record);
+    check(&mut serial, "store again", update == Ok(Update::Unchanged));
+
+    let flags = RECORD.len() - 1;
+    let update = update_sector(&mut flash, settings, |sector| sector[flags] = 0x7f);
+    flash.read(settings, &mut sector).ok();
+    check(
+        &mut serial,
+        "clear flag",
+        update == Ok(Update::Programmed) && sector[flags] == 0x7f,
+    );
+
+    let update = update_sector(&mut flash, settings, |sector| sector[flags] = 0xff);
+    flash.read(settings, &mut sector).ok();
+    check(
+        &mut serial,
+        "set flag",
+        update == Ok(Update::Erased)
+            && sector[..RECORD.len()] == *RECORD
+            && sector[RECORD.len()..].iter().all(|&b| b == 0xff),
+    );
+
+    let mut through_xip = [0u8; 64];
+    let mut by_command = [0u8; 64];
+    let read = flash
+        .read(settings, &mut through_xip)
+        .and(flash.read_with_commands(settings, &mut by_command));
+    check(
+        &mut serial,
+        "xip and command reads",
+        read.is_ok() && through_xip == by_command && through_xip[..RECORD.len()] == *RECORD,
+    );
+
+    let erased = flash.erase(settings, settings + SECTOR_SIZE);
+    let crc = Crc::checksum(Algorithm::Crc32, RECORD);
+    let verified = flash.write_with_crc_verify(settings, RECORD, crc);
+    let corrupted = flash.write_with_crc_verify(settings + 64, RECORD, crc ^ 1);
+    flash.read(settings, &mut sector).ok();
+    check(
+        &mut serial,
+        "crc verify",
+        erased.is_ok()
+            && verified == Ok(crc)
+            && corrupted
+                == Err(flash::Error::CrcMismatch {
+                    written_crc: crc ^ 1,
+                    read_crc: crc,
+                })
+            && sector.iter().all(|&b| b == 0xff),
+    );
+
+    let firmware = flash.protected();
+    check(
+        &mut serial,
+        "protected",
+        firmware.start == 0
+            && firmware.end > 0
+            && flash.erase(0, SECTOR_SIZE) == Err(flash::Error::Protected),
+    );
+    check(
+        &mut serial,
+        "out of bounds",
+        flash.write(flash.capacity() as u32 - 1, b"ab") == Err(flash::Error::OutOfBounds),
+    );
+    check(
+        &mut serial,
+        "not aligned",
+        flash.erase(settings, settings + SECTOR_SIZE / 2) == Err(flash::Error::NotAligned),
+    );
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
@@ -0,0 +1,130 @@
+/*
+
+   Counts edges on GPIO3 with a handler registered with the GPIO interrupt manager.
+
+   Connect GPIO5 to GPIO3. GPIO5 is toggled as an output, and the handler registered for rising
+   edges of GPIO3 counts them: ten pulses have to be counted ten times, and none once the
+   handler has been removed again. A second handler for falling edges of GPIO4, which stays
+   unconnected and low, must never run. The counts are printed over UART0, followed by "ok" or
+   "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, Ordering};
+use embedded_hal::delay::blocking::DelayUs;
+use embedded_hal::digital::blocking::OutputPin;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    gpio::{Event, GpioInterruptManager},
+    pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+static RISING: AtomicU32 = AtomicU32::new(0);
+static UNCONNECTED: AtomicU32 = AtomicU32::new(0);
+
+fn on_rising() {
+    RISING.fetch_add(1, Ordering::Relaxed);
+}
+
+fn on_unconnected() {
+    UNCONNECTED.fetch_add(1, Ordering::Relaxed);
+}
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut delay = McycleDelay::new(clocks.sysclk().0);
+    let mut output = parts.pin5.into_pull_down_output();
+    output.set_low().ok();
+
+    let mut input = parts.pin3.into_pull_down_input();
+    input.enable_smitter();
+    let unconnected = parts.pin4.into_pull_down_input();
+
+    let mut manager = GpioInterruptManager::take().unwrap();
+    manager.on_pin_interrupt(input.erase(), Event::PositivePulse, on_rising);
+    manager.on_pin_interrupt(unconnected.erase(), Event::NegativePulse, on_unconnected);
+
+    let mut pulse = || {
+        output.set_high().ok();
+        delay.delay_us(50).ok();
+        output.set_low().ok();
+        delay.delay_us(50).ok();
+    };
+
+    for _ in 0..10 {
+        pulse();
+    }
+    let counted = RISING.load(Ordering::Relaxed);
+
+    let removed = manager.remove_handler(3);
+    for _ in 0..10 {
+        pulse();
+    }
+    let after_remove = RISING.load(Ordering::Relaxed);
+
+    let mut failed = false;
+
+    let ok = counted == 10;
+    writeln!(
+        serial,
+        "rising edges: {}, {}\r",
+        counted,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    let ok = removed && after_remove == counted && !manager.remove_handler(3);
+    writeln!(
+        serial,
+        "after remove_handler: {}, {}\r",
+        after_remove,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    let unconnected = UNCONNECTED.load(Ordering::Relaxed);
+    let ok = unconnected == 0;
+    writeln!(
+        serial,
+        "unconnected pin: {}, {}\r",
+        unconnected,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
@@ -0,0 +1,222 @@
+/*
+
+   Runs the HART master against a field device simulated by the TimerCh0 interrupt.
+
+   Connect GPIO16 (UART0 TX) to GPIO1, which is UART0 RX here instead of GPIO7, so everything
+   sent comes back: the requests, which the master has to skip, and the responses which the
+   timer interrupt writes into the TX FIFO a few milliseconds after each request. The
+   simulation runs at 2 Mbaud rather than the 1200 baud of a modem, the protocol doesn't
+   depend on it. RTS goes to GPIO11, where a scope shows it low during each request.
+
+   The master has to read the identity of command 0 and take the long address from it, find
+   the response to command 1 behind a burst frame, report a communication error after a retry
+   which times out, report a timeout when there's no response at all, and reject a polling
+   address above 63. The results are printed over UART0 once the tests are done, followed by
+   "ok" or "FAILED".
+
+   Needs the `hart` feature:
+   cargo run --example hart --features hart
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_time::{duration::*, rate::*};
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    hart::{self, Hart, HartAddr, HartError},
+    interrupts::{self, Interrupt},
+    pac,
+    prelude::*,
+    serial::*,
+    sync::SpinLock,
+    timer::*,
+};
+use panic_halt as _;
+use riscv::register::mcycle;
+
+const SYSCLK_HZ: u32 = 160_000_000;
+
+/// Response to command 0 at polling address 0: manufacturer 0x26, device type 0x0e, device ID
+/// 0x123456
+const IDENTITY: &[u8] = b"\xff\xff\xff\xff\xff\x06\x00\x00\x0e\x00\x00\xfe\x26\x0e\x05\x07\x01\x01\x01\x00\x12\x34\x56\xad";
+
+/// A burst frame of command 1, then the response to command 1 at the long address: device
+/// status 0x10, units 32 (degrees Celsius) and 21.5
+const PRIMARY_VARIABLE: &[u8] = b"\xff\xff\xff\xff\xff\x81\x66\x0e\x12\x34\x56\x01\x07\x00\x00\x20\x41\xac\x00\x00\x52\xff\xff\xff\xff\xff\x86\x26\x0e\x12\x34\x56\x01\x07\x00\x10\x20\x41\xac\x00\x00\x05";
+
+/// Response to command 0 at polling address 0 with a communication error: parity error in the
+/// check byte of the request
+const COMMUNICATION_ERROR: &[u8] = b"\xff\xff\xff\xff\xff\x06\x00\x00\x02\x88\x00\x8c";
+
+static TIMER: SpinLock<Option<ConfiguredTimerChannel0>> = SpinLock::new(None);
+
+/// Bytes the simulated device still has to send, and the milliseconds until it starts
+static REPLY: SpinLock<Option<(u32, &'static [u8])>> = SpinLock::new(None);
+
+fn reply_after(ms: u32, reply: &'static [u8]) {
+    *REPLY.lock_irq_disabled() = Some((ms, reply));
+}
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin1 = parts.pin1.into_uart_sig1();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux1 = parts.uart_mux1.into_uart0_rx();
+
+    let serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()).parity_odd(),
+        ((pin16, mux0), (pin1, mux1)),
+        clocks,
+    );
+
+    let timers = dp.TIMER.split();
+    let timer = timers
+        .channel0
+        .set_clock_source(ClockSource::Fclk(&clocks), 1_000_000_u32.Hz());
+    timer.set_match0(1000_u32.microseconds());
+    timer.set_preload_value(0.microseconds());
+    timer.set_preload(Preload::PreloadMatchComparator0);
+    timer.enable_match0_interrupt();
+    timer.enable();
+    *TIMER.lock_irq_disabled() = Some(timer);
+    interrupts::enable(Interrupt::TimerCh0);
+
+    let mut master = Hart::new(
+        serial,
+        parts.pin11.into_pull_up_output(),
+        hart::Config::default().retries(1).timeout_ms(20),
+        clocks,
+    );
+
+    reply_after(2, IDENTITY);
+    let identity_result = master.send_command(HartAddr::Short(0), 0, &[]);
+    let device = identity_result
+        .ok()
+        .and_then(|identity| HartAddr::from_identity(identity.data()));
+
+    reply_after(2, PRIMARY_VARIABLE);
+    let pv_result = device.map(|device| master.send_command(device, 1, &[]));
+
+    reply_after(2, COMMUNICATION_ERROR);
+    let error_result = master.send_command(HartAddr::Short(0), 0, &[]);
+
+    let start = mcycle::read() as u32;
+    let timeout_result = master.send_command(HartAddr::Short(5), 0, &[]);
+    let timeout_ms = (mcycle::read() as u32).wrapping_sub(start) / (SYSCLK_HZ / 1000);
+
+    let invalid_result = master.send_command(HartAddr::Short(64), 0, &[]);
+
+    interrupts::disable(Interrupt::TimerCh0);
+    let (mut serial, _rts) = master.free();
+    writeln!(serial, "\r").ok();
+
+    let mut failed = false;
+
+    let mut check = |serial: &mut dyn Write, name: &str, ok: bool| {
+        writeln!(serial, "{}: {}\r", name, if ok { "ok" } else { "FAILED" }).ok();
+        failed |= !ok;
+    };
+
+    writeln!(serial, "command 0: {:x?}\r", identity_result).ok();
+    check(
+        &mut serial,
+        "identity",
+        matches!(identity_result, Ok(identity) if identity.response_code == 0)
+            && device
+                == Some(HartAddr::Long {
+                    device_type: 0x260e,
+                    device_id: 0x12_3456,
+                }),
+    );
+
+    writeln!(serial, "command 1: {:x?}\r", pv_result).ok();
+    let ok = match pv_result {
+        Some(Ok(pv)) => {
+            let data = pv.data();
+            pv.device_status == 0x10
+                && data.len() == 5
+                && data[0] == 32
+                && f32::from_be_bytes([data[1], data[2], data[3], data[4]]) == 21.5
+        }
+        _ => false,
+    };
+    check(&mut serial, "primary variable", ok);
+
+    writeln!(serial, "communication error: {:x?}\r", error_result).ok();
+    check(
+        &mut serial,
+        "communication error",
+        error_result == Err(HartError::Communication(0x88)),
+    );
+
+    writeln!(
+        serial,
+        "timeout: {:?} after {} ms\r",
+        timeout_result, timeout_ms
+    )
+    .ok();
+    check(
+        &mut serial,
+        "timeout",
+        timeout_result == Err(HartError::Timeout) && (40..=44).contains(&timeout_ms),
+    );
+
+    check(
+        &mut serial,
+        "invalid request",
+        invalid_result == Err(HartError::InvalidRequest),
+    );
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
+
+hal::interrupt!(TimerCh0, on_timer);
+
+fn on_timer() {
+    if let Some(timer) = TIMER.lock_irq_disabled().as_mut() {
+        timer.clear_match0_interrupt();
+    }
+    interrupts::clear_interrupt(Interrupt::TimerCh0);
+
+    let mut reply = REPLY.lock();
+    let (ms, mut bytes) = match *reply {
+        Some(pending) => pending,
+        None => return,
+    };
+    if ms > 0 {
+        *reply = Some((ms - 1, bytes));
+        return;
+    }
+
+    // Writes as much as fits into the TX FIFO, the rest follows on the next tick
+    let uart = unsafe { &*pac::UART::ptr() };
+    while let Some((&byte, rest)) = bytes.split_first() {
+        if uart.uart_fifo_config_1.read().tx_fifo_cnt().bits() == 0 {
+            break;
+        }
+        uart.uart_fifo_wdata
+            .write(|w| unsafe { w.bits(byte as u32) });
+        bytes = rest;
+    }
+    *reply = if bytes.is_empty() {
+        None
+    } else {
+        Some((0, bytes))
+    };
+}
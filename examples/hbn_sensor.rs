@@ -0,0 +1,65 @@
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use core::time::Duration;
+use embedded_hal::serial::nb::Write as _;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    hbn::{self, Hbn, HbnLevel, WakePin, WakeSources, WakeTrigger},
+    pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+// Sensor node which sleeps in hibernate and wakes every ten minutes, or when the button between
+// GPIO7 and ground is pressed
+#[riscv_rt::entry]
+fn main() -> ! {
+    // Has to come first, the flags are cleared by reading them
+    let cause = hbn::wakeup_cause();
+
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    // GPIO7 is the wake button, so receive on GPIO3 instead
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin3 = parts.pin3.into_uart_sig3();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux3 = parts.uart_mux3.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(115_200.Bd()),
+        ((pin16, mux0), (pin3, mux3)),
+        clocks,
+    );
+
+    writeln!(serial, "woke up: {:?}\r", cause).ok();
+
+    // Take the measurement and send it off here
+
+    nb::block!(serial.flush()).ok();
+
+    let mut hbn = Hbn::new(dp.HBN);
+    let error = hbn.enter(
+        HbnLevel::Level1,
+        WakeSources {
+            rtc_after: Some(Duration::from_secs(10 * 60)),
+            gpio: &[WakePin::Gpio7],
+            gpio_trigger: WakeTrigger::FallingEdge,
+            acomp: false,
+        },
+    );
+
+    writeln!(serial, "could not hibernate: {:?}\r", error).ok();
+    loop {}
+}
@@ -0,0 +1,77 @@
+/*
+
+   Compares the time of one byte writes with `I2c::write_direct` and with `write`.
+
+   Connect any I2C device, e.g. an SSD1306 display at address 0x3c, to pin 4 (SCL) and pin 5
+   (SDA). The example writes the byte 0x00 a thousand times with each method and prints the
+   average number of core clock cycles per write over UART0.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_hal::i2c::blocking::Write as _;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    i2c::I2c,
+    pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+const ADDRESS: u8 = 0x3c;
+const WRITES: u64 = 1_000;
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .i2c_clk(1_000_000u32.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let scl = parts.pin4.into_i2c_scl();
+    let sda = parts.pin5.into_i2c_sda();
+    let mut i2c = I2c::new(dp.I2C, (scl, sda), 100_000u32.Hz(), clocks);
+
+    let start = McycleDelay::get_cycle_count();
+    for _ in 0..WRITES {
+        i2c.write(ADDRESS, &[0x00]).unwrap();
+    }
+    let write = McycleDelay::cycles_since(start) / WRITES;
+
+    let start = McycleDelay::get_cycle_count();
+    for _ in 0..WRITES {
+        i2c.write_direct(ADDRESS, 0x00).unwrap();
+    }
+    let direct = McycleDelay::cycles_since(start) / WRITES;
+
+    writeln!(
+        serial,
+        "write: {} cycles, write_direct: {} cycles\r",
+        write, direct
+    )
+    .ok();
+
+    loop {}
+}
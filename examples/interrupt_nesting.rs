@@ -0,0 +1,184 @@
+/*
+
+   Checks that a high priority interrupt preempts a slow handler which allows it.
+
+   TimerCh0 fires every 100 µs at priority 10 and toggles pin 4. The Uart0 handler is triggered
+   from software at priority 2; it sets pin 5 high, busy waits for 2 ms and sets pin 5 low again.
+   Connect a logic analyzer to pins 4 and 5: while pin 5 is high in the first run, pin 4 keeps
+   toggling, and in the second run, which doesn't call `allow_preemption_above`, it pauses.
+
+   The timer interrupts taken during each run are counted: there have to be about 20 in the first
+   run and none in the second. A third run checks that the Uart0 handler can still be taken after
+   the guard restored its state. The results are printed over UART0, followed by "ok" or "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use embedded_hal::digital::blocking::{OutputPin, ToggleableOutputPin};
+use embedded_time::{duration::*, rate::*};
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    gpio::{pin::Pin4, pin::Pin5, Output, PullDown},
+    interrupts::{self, Interrupt, Priority},
+    pac,
+    prelude::*,
+    serial::*,
+    sync::SpinLock,
+    timer::*,
+};
+use panic_halt as _;
+use riscv::register::mcycle;
+
+const SYSCLK_HZ: u32 = 160_000_000;
+const SLOW_HANDLER_MS: u32 = 2;
+
+static TIMER: SpinLock<Option<ConfiguredTimerChannel0>> = SpinLock::new(None);
+static TIMER_PIN: SpinLock<Option<Pin4<Output<PullDown>>>> = SpinLock::new(None);
+static SLOW_PIN: SpinLock<Option<Pin5<Output<PullDown>>>> = SpinLock::new(None);
+
+static PREEMPTIBLE: AtomicBool = AtomicBool::new(true);
+static TICKS: AtomicU32 = AtomicU32::new(0);
+/// Timer interrupts taken while the Uart0 handler ran, `u32::MAX` until it is done
+static TICKS_DURING: AtomicU32 = AtomicU32::new(u32::MAX);
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    *TIMER_PIN.lock_irq_disabled() = Some(parts.pin4.into_pull_down_output());
+    *SLOW_PIN.lock_irq_disabled() = Some(parts.pin5.into_pull_down_output());
+
+    let timers = dp.TIMER.split();
+    let timer = timers
+        .channel0
+        .set_clock_source(ClockSource::Fclk(&clocks), 1_000_000_u32.Hz());
+    timer.set_match0(100_u32.microseconds());
+    timer.set_preload_value(0.microseconds());
+    timer.set_preload(Preload::PreloadMatchComparator0);
+    timer.enable_match0_interrupt();
+    timer.enable();
+    *TIMER.lock_irq_disabled() = Some(timer);
+
+    interrupts::set_level_bits(4);
+    interrupts::set_priority(Interrupt::TimerCh0, Priority::new(10).unwrap());
+    interrupts::set_priority(Interrupt::Uart0, Priority::new(2).unwrap());
+    interrupts::enable(Interrupt::TimerCh0);
+    interrupts::enable(Interrupt::Uart0);
+
+    let mut failed = false;
+    let run = |preemptible: bool| {
+        PREEMPTIBLE.store(preemptible, Ordering::SeqCst);
+        TICKS_DURING.store(u32::MAX, Ordering::SeqCst);
+        interrupts::pend(Interrupt::Uart0);
+        loop {
+            let ticks = TICKS_DURING.load(Ordering::SeqCst);
+            if ticks != u32::MAX {
+                break ticks;
+            }
+        }
+    };
+
+    let ticks = run(true);
+    let expected = SLOW_HANDLER_MS * 10;
+    let ok = ticks + 1 >= expected && ticks <= expected + 1;
+    writeln!(
+        serial,
+        "preemptible: {} timer interrupts during the slow handler, {}\r",
+        ticks,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    let ticks = run(false);
+    let ok = ticks == 0;
+    writeln!(
+        serial,
+        "not preemptible: {} timer interrupts during the slow handler, {}\r",
+        ticks,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    let ticks = run(true);
+    let ok = ticks > 0 && TICKS.load(Ordering::SeqCst) > 0;
+    writeln!(
+        serial,
+        "taken again after the guard: {}\r",
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
+
+hal::interrupt!(TimerCh0, on_timer);
+
+fn on_timer() {
+    if let Some(timer) = TIMER.lock_irq_disabled().as_mut() {
+        timer.clear_match0_interrupt();
+    }
+    interrupts::clear_interrupt(Interrupt::TimerCh0);
+
+    if let Some(pin) = TIMER_PIN.lock_irq_disabled().as_mut() {
+        pin.toggle().ok();
+    }
+    TICKS.fetch_add(1, Ordering::SeqCst);
+}
+
+hal::interrupt!(Uart0, on_slow_uart);
+
+fn on_slow_uart() {
+    interrupts::unpend(Interrupt::Uart0);
+
+    if let Some(pin) = SLOW_PIN.lock_irq_disabled().as_mut() {
+        pin.set_high().ok();
+    }
+    let ticks_before = TICKS.load(Ordering::SeqCst);
+
+    {
+        let _preemptible = if PREEMPTIBLE.load(Ordering::SeqCst) {
+            Some(interrupts::allow_preemption_above(
+                interrupts::get_priority(Interrupt::Uart0),
+            ))
+        } else {
+            None
+        };
+
+        let start = mcycle::read() as u32;
+        while (mcycle::read() as u32).wrapping_sub(start) < SLOW_HANDLER_MS * (SYSCLK_HZ / 1000) {}
+    }
+
+    let ticks = TICKS.load(Ordering::SeqCst).wrapping_sub(ticks_before);
+    if let Some(pin) = SLOW_PIN.lock_irq_disabled().as_mut() {
+        pin.set_low().ok();
+    }
+    TICKS_DURING.store(ticks, Ordering::SeqCst);
+}
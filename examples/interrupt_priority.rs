@@ -0,0 +1,146 @@
+/*
+
+   Checks the priority rules of the interrupt controller.
+
+   TimerCh0 and TimerCh1 are pended from software with interrupts disabled, so both are pending
+   when interrupts are enabled again, and the order their handlers run in is compared with
+   `runs_before` for several priorities; the rules themselves are checked by the host tests.
+   The timers themselves stay stopped. The results are printed over UART0, followed by "ok" or
+   "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU8, Ordering};
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    interrupts::{self, Interrupt, Priority, TrapFrame},
+    pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+/// Handlers in the order they ran, 0 for TimerCh0 and 1 for TimerCh1, 2 bits each
+static ORDER: AtomicU8 = AtomicU8::new(0);
+static RUNS: AtomicU8 = AtomicU8::new(0);
+
+/// `(priority a, priority b, a runs before b)` with a = TimerCh0 and b = TimerCh1
+const ARBITRATION: [(u8, u8, bool); 4] = [
+    (1, 0, true),
+    (0, 1, false),
+    // Same priority, the higher interrupt number wins
+    (5, 5, false),
+    (15, 14, true),
+];
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let priority = |value| Priority::new(value).unwrap();
+    let mut failed = false;
+
+    writeln!(serial, "level bits: {}\r", interrupts::level_bits()).ok();
+
+    interrupts::enable(Interrupt::TimerCh0);
+    interrupts::enable(Interrupt::TimerCh1);
+
+    for &(a, b, a_first) in ARBITRATION.iter() {
+        let a_before_b = interrupts::runs_before(
+            (Interrupt::TimerCh0, priority(a)),
+            (Interrupt::TimerCh1, priority(b)),
+        );
+
+        interrupts::set_priority(Interrupt::TimerCh0, priority(a));
+        interrupts::set_priority(Interrupt::TimerCh1, priority(b));
+        let read_back = interrupts::get_priority(Interrupt::TimerCh0) == priority(a)
+            && interrupts::get_priority(Interrupt::TimerCh1) == priority(b);
+
+        ORDER.store(0, Ordering::SeqCst);
+        RUNS.store(0, Ordering::SeqCst);
+        interrupts::free(|_| {
+            interrupts::pend(Interrupt::TimerCh0);
+            interrupts::pend(Interrupt::TimerCh1);
+        });
+        while RUNS.load(Ordering::SeqCst) < 2 {}
+
+        let ch0_first = ORDER.load(Ordering::SeqCst) & 0b11 == 0;
+        let ok = read_back && a_before_b == a_first && ch0_first == a_first;
+        writeln!(
+            serial,
+            "TimerCh0 {}, TimerCh1 {}: {} ran first, {}\r",
+            a,
+            b,
+            if ch0_first { "TimerCh0" } else { "TimerCh1" },
+            if ok { "ok" } else { "FAILED" }
+        )
+        .ok();
+        failed |= !ok;
+    }
+
+    // Masking at the controller keeps a pending interrupt from being taken
+    RUNS.store(0, Ordering::SeqCst);
+    let taken_while_masked = interrupts::masked(&[Interrupt::TimerCh0], || {
+        interrupts::pend(Interrupt::TimerCh0);
+        (0..1000).any(|_| RUNS.load(Ordering::SeqCst) != 0)
+    });
+    while RUNS.load(Ordering::SeqCst) == 0 {}
+    writeln!(
+        serial,
+        "masked: {}\r",
+        if taken_while_masked {
+            "taken while masked, FAILED"
+        } else {
+            "ok"
+        }
+    )
+    .ok();
+    failed |= taken_while_masked || !interrupts::is_enabled(Interrupt::TimerCh0);
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
+
+fn record(channel: u8) {
+    let run = RUNS.load(Ordering::SeqCst);
+    ORDER.fetch_or(channel << (2 * run), Ordering::SeqCst);
+    RUNS.store(run + 1, Ordering::SeqCst);
+}
+
+#[allow(non_snake_case)]
+#[no_mangle]
+fn TimerCh0(_trap_frame: &mut TrapFrame) {
+    interrupts::unpend(Interrupt::TimerCh0);
+    record(0);
+}
+
+#[allow(non_snake_case)]
+#[no_mangle]
+fn TimerCh1(_trap_frame: &mut TrapFrame) {
+    interrupts::unpend(Interrupt::TimerCh1);
+    record(1);
+}
@@ -0,0 +1,123 @@
+/*
+
+   Receives an NEC frame sent in a loop, then prints the frames of a remote.
+
+   Connect pin 22 to pin 11: the transmitter sends an NEC frame without carrier, the LED driver
+   pulls pin 11 low during the marks and the receiver reads the frame back. Then a receiver
+   module, e.g. a TSOP38238, with its output on pin 11 in place of the wire shows the frames of a
+   remote. The results are printed over UART0, the checks followed by "ok" or "FAILED".
+
+   The decoder is checked by the tests of `ir` on the PC, against frames of the encoder distorted
+   the way a receiver module distorts them.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    ir::{self, IrExt, NecCommand, Receiver, Transmitter},
+    pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut failed = false;
+
+    let mut check = |serial: &mut dyn Write, name: &str, ok: bool| {
+        writeln!(serial, "{}: {}\r", name, if ok { "ok" } else { "FAILED" }).ok();
+        failed |= !ok;
+    };
+
+    let press = NecCommand::Press {
+        address: 0xfb04,
+        command: 0x08,
+    };
+    let ir = dp.IR.split();
+    let tx = Transmitter::new(
+        ir.tx,
+        parts.pin22.into_analog(),
+        ir::Config::default().unmodulated(),
+        &clocks,
+    );
+    let rx = Receiver::new(
+        ir.rx,
+        parts.pin11.into_pull_up_input(),
+        ir::RxConfig::default(),
+        &clocks,
+    );
+    let (mut tx, mut rx) = match (tx, rx) {
+        (Ok(tx), Ok(rx)) => (tx, rx),
+        (tx, rx) => {
+            writeln!(serial, "{:?} {:?}\r\nFAILED\r", tx.err(), rx.err()).ok();
+            loop {}
+        }
+    };
+
+    // Start a frame, then poll the receiver while it's sent, as the 67 pulses don't fit into
+    // the FIFO
+    let mut pulses = [0; 100];
+    let mut received = Err(nb::Error::WouldBlock);
+    if tx.start(&ir::nec(0x04, 0x08)).is_ok() {
+        while tx.poll().is_err() {
+            if let Err(nb::Error::WouldBlock) = received {
+                received = rx.poll(&mut pulses);
+            }
+        }
+    }
+    let received = match received {
+        Err(nb::Error::WouldBlock) => rx.read_frame(&mut pulses, 100),
+        Err(nb::Error::Other(error)) => Err(error),
+        Ok(len) => Ok(len),
+    };
+    writeln!(serial, "loop: {:?}\r", received).ok();
+    check(
+        &mut serial,
+        "loop",
+        received.map(|len| ir::decode_nec(&pulses[..len])) == Ok(Ok(press)),
+    );
+    check(
+        &mut serial,
+        "timeout",
+        rx.read_frame(&mut pulses, 50) == Err(ir::Error::Timeout),
+    );
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {
+        match rx.read_frame(&mut pulses, 1000) {
+            Ok(len) => match ir::decode_nec(&pulses[..len]) {
+                Ok(command) => writeln!(serial, "{:x?}\r", command).ok(),
+                Err(error) => writeln!(serial, "{:?}: {:?}\r", error, &pulses[..len]).ok(),
+            },
+            Err(ir::Error::Timeout) => None,
+            Err(error) => writeln!(serial, "{:?}\r", error).ok(),
+        };
+    }
+}
@@ -0,0 +1,111 @@
+/*
+
+   Sends a long pulse train, then remote control frames with an IR LED on pin 22.
+
+   The transmitter is checked to send a train of 150 pulses, which takes three chunks, in the
+   time it should take. The result is printed over UART0, followed by "ok" or "FAILED". The
+   encoders and the pulse widths the transmitter rejects are checked by the tests of `ir` on the
+   PC.
+
+   Then, once a second, NEC address 0x04 command 0x08 (power on many TVs) is sent with two
+   repeat frames, followed by RC5 address 0 command 12 (standby) with the toggle bit changing
+   every time. A TV, an LED strip controller or a receiver module on a scope shows the frames.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_hal::delay::blocking::DelayUs;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    ir::{self, IrExt, Transmitter},
+    pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+    let mut delay = McycleDelay::new(clocks.sysclk().0);
+
+    let mut failed = false;
+
+    let mut check = |serial: &mut dyn Write, name: &str, ok: bool| {
+        writeln!(serial, "{}: {}\r", name, if ok { "ok" } else { "FAILED" }).ok();
+        failed |= !ok;
+    };
+
+    let mut tx = match Transmitter::new(
+        dp.IR.split().tx,
+        parts.pin22.into_analog(),
+        ir::Config::default(),
+        &clocks,
+    ) {
+        Ok(tx) => tx,
+        Err(error) => {
+            writeln!(serial, "Transmitter::new: {:?}\r\nFAILED\r", error).ok();
+            loop {}
+        }
+    };
+
+    // 150 pulses of 500 and 1000 µs, 100 ms, in chunks of 64, 64 and 22 pulses
+    let mut long = [500; 150];
+    for pulse in long.iter_mut().step_by(3) {
+        *pulse = 1000;
+    }
+    let begin = McycleDelay::get_cycle_count();
+    let sent = tx.start(&long).is_ok() && tx.start(&long) == Err(ir::Error::Busy);
+    let mut polls = 0;
+    while tx.poll().is_err() {
+        polls += 1;
+    }
+    let us = McycleDelay::cycles_since(begin) / (clocks.sysclk().0 as u64 / 1_000_000);
+    writeln!(serial, "150 pulses: {} us\r", us).ok();
+    check(
+        &mut serial,
+        "long train",
+        sent && polls > 0 && !tx.is_busy() && (100_000..103_000).contains(&us),
+    );
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    let mut toggle = false;
+    loop {
+        let start = McycleDelay::get_cycle_count();
+        tx.send_pulses(&ir::nec(0x04, 0x08)).ok();
+        let period = ir::NEC_REPEAT_PERIOD_US as u64 * (clocks.sysclk().0 as u64 / 1_000_000);
+        for n in 1..=2 {
+            while McycleDelay::cycles_since(start) < period * n {}
+            tx.send_pulses(&ir::nec_repeat()).ok();
+        }
+
+        delay.delay_ms(500).ok();
+        tx.send_pulses(&ir::rc5(0, 12, toggle)).ok();
+        toggle = !toggle;
+        delay.delay_ms(500).ok();
+    }
+}
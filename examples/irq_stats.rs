@@ -0,0 +1,156 @@
+/*
+
+   Prints the interrupt statistics of the `irq-stats` feature.
+
+   TimerCh0 fires every millisecond and busy waits for about 10 µs. Uart0 is pended from
+   software 100 times and returns right away, so its latency is measured. After one second the
+   report is printed over UART0: TimerCh0 has to have run about 1000 times for at least 1600
+   cycles each, Uart0 exactly 100 times with a latency for every run. After `reset_stats` no
+   interrupt may be listed anymore. The results are followed by "ok" or "FAILED".
+
+   Needs the `irq-stats` feature:
+   cargo run --example irq_stats --features irq-stats
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_hal::delay::blocking::DelayUs;
+use embedded_time::{duration::*, rate::*};
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    interrupts::{self, Interrupt},
+    pac,
+    prelude::*,
+    serial::*,
+    sync::SpinLock,
+    timer::*,
+};
+use panic_halt as _;
+use riscv::register::mcycle;
+
+const SYSCLK_HZ: u32 = 160_000_000;
+
+static TIMER: SpinLock<Option<ConfiguredTimerChannel0>> = SpinLock::new(None);
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut delay = McycleDelay::new(clocks.sysclk().0);
+
+    let timers = dp.TIMER.split();
+    let timer = timers
+        .channel0
+        .set_clock_source(ClockSource::Fclk(&clocks), 1_000_000_u32.Hz());
+    timer.set_match0(1000_u32.microseconds());
+    timer.set_preload_value(0.microseconds());
+    timer.set_preload(Preload::PreloadMatchComparator0);
+    timer.enable_match0_interrupt();
+    timer.enable();
+    *TIMER.lock_irq_disabled() = Some(timer);
+
+    interrupts::reset_stats();
+    interrupts::enable(Interrupt::Uart0);
+    interrupts::enable(Interrupt::TimerCh0);
+
+    for _ in 0..100 {
+        interrupts::pend(Interrupt::Uart0);
+        delay.delay_ms(10).ok();
+    }
+    interrupts::disable(Interrupt::TimerCh0);
+
+    let mut timer_ok = false;
+    let mut uart_ok = false;
+    for (interrupt, stats) in interrupts::stats() {
+        writeln!(
+            serial,
+            "{:?}: {} runs, {}..{} cycles, mean {}, {} latencies, max {}, mean {:?}\r",
+            interrupt,
+            stats.count,
+            stats.min_cycles,
+            stats.max_cycles,
+            stats.mean_cycles(),
+            stats.latency_count,
+            stats.max_latency_cycles,
+            stats.mean_latency_cycles()
+        )
+        .ok();
+
+        match interrupt {
+            Interrupt::TimerCh0 => {
+                timer_ok = (990..=1010).contains(&stats.count)
+                    && stats.min_cycles >= SYSCLK_HZ / 100_000
+                    && stats.min_cycles <= stats.mean_cycles()
+                    && stats.mean_cycles() <= stats.max_cycles;
+            }
+            Interrupt::Uart0 => {
+                uart_ok = stats.count == 100
+                    && stats.latency_count == 100
+                    && stats.mean_latency_cycles().is_some();
+            }
+            _ => {}
+        }
+    }
+    writeln!(
+        serial,
+        "TimerCh0: {}, Uart0: {}\r",
+        if timer_ok { "ok" } else { "FAILED" },
+        if uart_ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+
+    interrupts::reset_stats();
+    let reset_ok = interrupts::stats().next().is_none();
+    writeln!(
+        serial,
+        "reset: {}\r",
+        if reset_ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+
+    let ok = timer_ok && uart_ok && reset_ok;
+    writeln!(serial, "{}\r", if ok { "ok" } else { "FAILED" }).ok();
+
+    loop {}
+}
+
+hal::interrupt!(TimerCh0, on_timer);
+
+fn on_timer() {
+    if let Some(timer) = TIMER.lock_irq_disabled().as_mut() {
+        timer.clear_match0_interrupt();
+    }
+    interrupts::clear_interrupt(Interrupt::TimerCh0);
+
+    let start = mcycle::read() as u32;
+    while (mcycle::read() as u32).wrapping_sub(start) < SYSCLK_HZ / 100_000 {}
+}
+
+hal::interrupt!(Uart0, on_uart);
+
+fn on_uart() {
+    interrupts::unpend(Interrupt::Uart0);
+}
@@ -0,0 +1,141 @@
+/*
+
+   Checks the periodic tick of the machine timer.
+
+   A 1 kHz tick is started and counted for one second, which has to give 1000 ticks. Then
+   interrupts are disabled for 3.5 ms, longer than a period: the missed ticks are dropped, and
+   the ticks after it still have to come at multiples of the period from the start. After `stop`
+   no tick may come anymore. The results are printed over UART0, followed by "ok" or "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, Ordering};
+use embedded_hal::delay::blocking::DelayUs;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    mtimer::{self, MTimer},
+    pac,
+    power::Instant,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+const TICK_HZ: u32 = 1000;
+const PERIOD: u64 = (mtimer::MTIME_FREQ / TICK_HZ) as u64;
+
+static TICKS: AtomicU32 = AtomicU32::new(0);
+/// `mtime` when the last tick was handled
+static LAST_TICK_AT: AtomicU32 = AtomicU32::new(0);
+
+fn on_tick() {
+    LAST_TICK_AT.store(Instant::now().ticks() as u32, Ordering::SeqCst);
+    TICKS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Returns by how many `mtime` ticks the last tick came after its scheduled time
+fn last_tick_late_by(start: u32) -> u64 {
+    (LAST_TICK_AT.load(Ordering::SeqCst).wrapping_sub(start) as u64) % PERIOD
+}
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut delay = McycleDelay::new(clocks.sysclk().0);
+    let mut mtimer = MTimer::take(&clocks).unwrap();
+    let mut failed = false;
+
+    let ok = mtimer.start_periodic(0.Hz(), on_tick) == Err(mtimer::Error::InvalidFrequency)
+        && MTimer::take(&clocks).is_none();
+    writeln!(
+        serial,
+        "invalid frequency and second take: {}\r",
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    mtimer.set_compare(0x1_2345_6789);
+    let ok = mtimer.compare() == 0x1_2345_6789;
+    writeln!(
+        serial,
+        "compare read back: {}\r",
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    mtimer.start_periodic(TICK_HZ.Hz(), on_tick).unwrap();
+    // The first tick is due at the compare value, all others at multiples of the period after it
+    let start = mtimer.compare() as u32;
+    delay.delay_ms(1000).ok();
+    let ticks = TICKS.load(Ordering::SeqCst);
+    let late_by = last_tick_late_by(start);
+    // A few microseconds for taking the interrupt
+    let ok = (999..=1001).contains(&ticks) && late_by < 5;
+    writeln!(
+        serial,
+        "{} ticks in one second, {} µs late, {}\r",
+        ticks,
+        late_by,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    let before = TICKS.load(Ordering::SeqCst);
+    riscv::interrupt::free(|_| {
+        let start = Instant::now().ticks();
+        while Instant::now().ticks() - start < 3 * PERIOD + PERIOD / 2 {}
+    });
+    delay.delay_ms(100).ok();
+    let ticks = TICKS.load(Ordering::SeqCst) - before;
+    let late_by = last_tick_late_by(start);
+    // The 3 or 4 ticks due while interrupts were disabled are taken as one right afterwards
+    let ok = (99..=101).contains(&ticks) && late_by < 5;
+    writeln!(
+        serial,
+        "{} ticks in 103.5 ms with interrupts disabled for 3.5 ms, {} µs late, {}\r",
+        ticks,
+        late_by,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    mtimer.stop();
+    let stopped_at = TICKS.load(Ordering::SeqCst);
+    delay.delay_ms(10).ok();
+    let ok = TICKS.load(Ordering::SeqCst) == stopped_at && !mtimer.is_periodic();
+    writeln!(serial, "stopped: {}\r", if ok { "ok" } else { "FAILED" }).ok();
+    failed |= !ok;
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
@@ -0,0 +1,141 @@
+/*
+
+   Updates the firmware with an image sent over UART0, then boots it.
+
+   At startup the example prints the slot it runs from and whether the partition table has been
+   confirmed. A firmware started for the first time after an update marks its boot successful,
+   which makes the update permanent. Then it waits for an update at 2 Mbaud: the length of the
+   image as 4 bytes little endian, its SHA-256, then the image itself, the raw firmware as boot2
+   expects it in the slot. It's written into the other slot while the example keeps running,
+   read back and checked against the hash, and only then the partition table is switched and the
+   chip reset. A failed update is printed and the old firmware keeps running.
+
+   Erasing a sector masks interrupts for up to a few hundred milliseconds, far longer than the
+   receive FIFO lasts, so the image is sent in chunks of 64 bytes and the example asks for each
+   one with a `.`. A host can send an update with e.g.
+
+   ```python
+   data = open("fw.bin", "rb").read()
+   port.write(struct.pack("<I", len(data)) + hashlib.sha256(data).digest())
+   for i in range(0, len(data), 64):
+       while port.read(1) != b".":
+           pass
+       port.write(data[i:i + 64])
+   ```
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_hal::serial::nb::{Read as _, Write as _};
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    flash::{
+        ota::{self, OtaUpdater},
+        Flash,
+    },
+    pac, power,
+    prelude::*,
+    sec_eng::SecEngExt,
+    serial::*,
+    sha::Sha,
+};
+use panic_halt as _;
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let sec_eng = dp.SEC_ENG.split();
+    let mut sha = Sha::new(sec_eng.sha, &mut parts.clk_cfg);
+    let mut flash = match Flash::new(dp.SF_CTRL, &clocks) {
+        Ok(flash) => flash,
+        Err(error) => {
+            writeln!(serial, "Flash::new: {:?}\r\nFAILED\r", error).ok();
+            loop {}
+        }
+    };
+
+    let slot = ota::current_slot(&mut flash);
+    let confirmed = ota::is_confirmed(&mut flash);
+    writeln!(
+        serial,
+        "running from {:?}, confirmed {:?}\r",
+        slot, confirmed
+    )
+    .ok();
+    if confirmed == Ok(false) {
+        writeln!(
+            serial,
+            "mark boot successful: {:?}\r",
+            ota::mark_boot_successful(&mut flash)
+        )
+        .ok();
+    }
+
+    let slot = match slot {
+        Ok(slot) => slot.other(),
+        Err(_) => loop {},
+    };
+
+    loop {
+        writeln!(serial, "waiting for an image for {:?}\r", slot).ok();
+
+        let mut header = [0u8; 36];
+        for byte in header.iter_mut() {
+            *byte = nb::block!(serial.read()).unwrap_or(0);
+        }
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let mut sha256 = [0u8; 32];
+        sha256.copy_from_slice(&header[4..]);
+
+        let result = OtaUpdater::begin(&mut flash, &mut sha, slot).and_then(|mut update| {
+            let mut chunk = [0u8; 64];
+            let mut left = len as usize;
+            while left > 0 {
+                let n = left.min(chunk.len());
+                nb::block!(serial.write(b'.')).ok();
+                for byte in chunk[..n].iter_mut() {
+                    // A byte lost to an overrun is caught by the hash
+                    *byte = nb::block!(serial.read()).unwrap_or(0);
+                }
+                update.write(&chunk[..n])?;
+                left -= n;
+            }
+            update.finalize(&sha256)?;
+            update.activate()
+        });
+
+        match result {
+            Ok(()) => {
+                writeln!(serial, "{} bytes written, restarting\r", len).ok();
+                nb::block!(serial.flush()).ok();
+                power::software_reset();
+            }
+            Err(error) => {
+                writeln!(serial, "update: {:?}\r", error).ok();
+            }
+        }
+    }
+}
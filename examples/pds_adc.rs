@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use core::time::Duration;
+use embedded_hal::serial::nb::Write as _;
+use hal::{
+    adc::{Adc, Channel},
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    pac,
+    pds::{self, PdsConfig, PdsLevel},
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+// Samples ADC channel 1 (GPIO4) every 100 ms and sleeps in between. Run it with an ammeter in
+// the supply line to compare the levels: the average current is dominated by the time spent
+// asleep, the print out shows how long each wake up takes.
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(115_200.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let _ch1 = parts.pin4.into_analog();
+    let mut adc = Adc::new(dp.GPIP, &mut parts.clk_cfg);
+
+    for &level in [PdsLevel::Level0, PdsLevel::Level1, PdsLevel::Level2]
+        .iter()
+        .cycle()
+    {
+        for _ in 0..50 {
+            let sample = adc.read(Channel::Ch1).unwrap();
+            writeln!(serial, "{:?}: {}\r", level, sample).ok();
+            // The UART stops while sleeping, so get the output out first
+            nb::block!(serial.flush()).ok();
+
+            let start = McycleDelay::get_cycle_count();
+            let cause = pds::enter(
+                level,
+                Some(Duration::from_millis(100)),
+                PdsConfig::new(clocks),
+            )
+            .unwrap();
+            // mcycle doesn't count while the core clock is gated, so this is the time spent
+            // going to sleep and waking up again
+            let cycles = McycleDelay::cycles_since(start);
+            writeln!(serial, "woke up by {:?} after {} cycles\r", cause, cycles).ok();
+        }
+    }
+
+    loop {}
+}
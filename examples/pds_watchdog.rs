@@ -0,0 +1,131 @@
+/*
+
+   Checks each watchdog policy of power-down sleep with a watchdog which would reset the chip
+   after one second.
+
+   The example sleeps for three seconds with `PauseDuringSleep` and with `ExtendToCoverSleep`,
+   and checks after each wake up that the watchdog runs again with its original timeout.
+   `KeepRunning` has to accept a sleep of 200 ms; which sleeps each policy refuses is checked by
+   the tests of `power`, on the PC. The policy under test is stored in `HBN_RSV1` first, so if
+   the watchdog resets the chip anyway, the next boot prints which policy failed. At the end
+   "ok" or "FAILED" is printed over UART0.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use core::time::Duration;
+use embedded_hal::serial::nb::Write as _;
+use embedded_hal::watchdog::blocking::{Enable, Watchdog};
+use embedded_time::{duration::*, rate::*};
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    hbn::retention::{self, Register},
+    pac,
+    pds::{self, PdsConfig, PdsLevel},
+    power::WdtPolicy,
+    prelude::*,
+    serial::*,
+    timer::*,
+    watchdog::*,
+};
+use panic_halt as _;
+
+/// Watchdog ticks per second
+const WDT_HZ: u32 = 125;
+const LONG_SLEEP: Duration = Duration::from_secs(3);
+
+const POLICY_NAMES: [&str; 3] = ["PauseDuringSleep", "ExtendToCoverSleep", "KeepRunning"];
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let timers = dp.TIMER.split();
+    let watchdog = timers
+        .watchdog
+        .set_clock_source(WdtClockSource::Rc32Khz, WDT_HZ.Hz());
+
+    if watchdog.has_watchdog_reset_occurred() {
+        let policy = retention::register(Register::Rsv1) as usize;
+        let name = POLICY_NAMES.get(policy).unwrap_or(&"unknown policy");
+        writeln!(serial, "watchdog reset while sleeping with {}\r", name).ok();
+        writeln!(serial, "FAILED\r").ok();
+        watchdog.clear_wts();
+        loop {}
+    }
+
+    watchdog.set_mode(WatchdogMode::Reset);
+    let mut watchdog = watchdog.start(1_u32.seconds()).unwrap();
+    let timeout = watchdog.get_match_ticks();
+    let mut failed = false;
+
+    let policies = [
+        WdtPolicy::PauseDuringSleep,
+        WdtPolicy::ExtendToCoverSleep {
+            margin: Duration::from_millis(100),
+        },
+    ];
+    for (i, &policy) in policies.iter().enumerate() {
+        retention::set_register(Register::Rsv1, i as u32);
+        watchdog.feed().ok();
+        nb::block!(serial.flush()).ok();
+
+        let result = pds::enter(
+            PdsLevel::Level1,
+            Some(LONG_SLEEP),
+            PdsConfig::new(clocks).watchdog(&watchdog, policy),
+        );
+
+        // Fed right after waking up, so only the time since then has been counted
+        let restored = watchdog.get_match_ticks() == timeout
+            && (watchdog.get_current_ticks() as u32) < WDT_HZ / 10;
+        writeln!(
+            serial,
+            "{}: {:?}, watchdog {}\r",
+            POLICY_NAMES[i],
+            result,
+            if restored { "restored" } else { "not restored" }
+        )
+        .ok();
+        failed |= result.is_err() || !restored;
+    }
+
+    retention::set_register(Register::Rsv1, 2);
+    watchdog.feed().ok();
+    nb::block!(serial.flush()).ok();
+    let accepted = pds::enter(
+        PdsLevel::Level1,
+        Some(Duration::from_millis(200)),
+        PdsConfig::new(clocks).watchdog(&watchdog, WdtPolicy::KeepRunning),
+    );
+    writeln!(serial, "{}: {:?} for 200 ms\r", POLICY_NAMES[2], accepted).ok();
+    failed |= accepted.is_err();
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {
+        watchdog.feed().ok();
+    }
+}
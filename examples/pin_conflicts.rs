@@ -0,0 +1,111 @@
+/*
+
+   Finds peripheral signals routed to two pins, in a debug build:
+   cargo run --example pin_conflicts
+
+   UART0 is on GPIO16 and GPIO7 as usual. GPIO0 and GPIO4 are both configured as SPI MISO, then
+   GPIO8 is put on UART signal 0 as well, which carries UART0 TX on GPIO16. Both have to be
+   reported with the pins, the function and the signal, and the check has to come back to its
+   initial result once the pins are GPIOs again. The initial result is printed first, a conflict
+   left by the boot ROM shows there. The results are printed over UART0, followed by "ok" or
+   "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    #[cfg(debug_assertions)]
+    {
+        use hal::gpio::{ConflictError, Parts};
+
+        let mut failed = false;
+
+        let mut check = |serial: &mut dyn Write, name: &str, ok: bool| {
+            writeln!(serial, "{}: {}\r", name, if ok { "ok" } else { "FAILED" }).ok();
+            failed |= !ok;
+        };
+
+        let initial = Parts::check_pin_usage_conflicts();
+        writeln!(serial, "initial: {:?}\r", initial).ok();
+
+        let miso = parts.pin0.into_spi_miso();
+        let second_miso = parts.pin4.into_spi_miso();
+        let result = Parts::check_pin_usage_conflicts();
+        writeln!(serial, "two MISO: {:?}\r", result).ok();
+        check(
+            &mut serial,
+            "SPI",
+            result
+                == Err(ConflictError {
+                    pin: 4,
+                    other_pin: 0,
+                    function: 4,
+                    signal: 0,
+                }),
+        );
+        let _pin0 = miso.into_floating_input();
+        let _pin4 = second_miso.into_floating_input();
+
+        let second_tx = parts.pin8.into_uart_sig0();
+        let result = Parts::check_pin_usage_conflicts();
+        writeln!(serial, "two UART0 TX: {:?}\r", result).ok();
+        check(
+            &mut serial,
+            "UART",
+            result
+                == Err(ConflictError {
+                    pin: 16,
+                    other_pin: 8,
+                    function: 7,
+                    signal: 2,
+                }),
+        );
+        let _pin8 = second_tx.into_floating_input();
+
+        check(
+            &mut serial,
+            "resolved",
+            Parts::check_pin_usage_conflicts() == initial,
+        );
+
+        writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+    }
+
+    #[cfg(not(debug_assertions))]
+    writeln!(serial, "the check is only in debug builds\r\nFAILED\r").ok();
+
+    loop {}
+}
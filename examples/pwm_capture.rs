@@ -0,0 +1,124 @@
+/*
+
+   Measures the PWM output of channel 2 with a PwmCapture on another pin.
+
+   Connect pin 17, the PWM output, to pin 3. The channel runs at 1 kHz with a duty cycle of 25%,
+   the captured duty cycle and frequency are printed over UART0 every 100 ms, followed by "ok"
+   if both are within 2% of the output, "FAILED" otherwise.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_hal::delay::blocking::DelayUs;
+use embedded_hal::pwm::blocking::Pwm;
+use embedded_time::duration::Milliseconds;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    gpio::{pin::Pin3, Floating, Input},
+    interrupts::*,
+    pac,
+    prelude::*,
+    pwm::{self, PwmCapture},
+    serial::*,
+    sync::SpinLock,
+};
+use panic_halt as _;
+
+const FREQUENCY_HZ: u32 = 1_000;
+const DUTY_CYCLE: f32 = 0.25;
+
+static CAPTURE: SpinLock<Option<PwmCapture<Pin3<Input<Floating>>>>> = SpinLock::new(None);
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut channels = pwm::Channels::from((dp.PWM, clocks));
+    channels
+        .channel2
+        .set_period(Milliseconds::new(1000 / FREQUENCY_HZ as u64))
+        .unwrap();
+    let duty = (channels.channel2.get_max_duty().unwrap() as f32 * DUTY_CYCLE) as u16;
+    channels.channel2.set_duty(&(), duty).unwrap();
+    channels.channel2.enable(&()).unwrap();
+    let _pwm_pin = parts.pin17.into_pull_down_pwm();
+
+    let mut input = parts.pin3.into_floating_input();
+    input.enable_smitter();
+    *CAPTURE.lock_irq_disabled() = Some(PwmCapture::new(input, &clocks));
+    enable_interrupt(Interrupt::Gpio);
+
+    let mut delay = McycleDelay::new(clocks.sysclk().0);
+
+    loop {
+        delay.delay_ms(100).ok();
+
+        let (duty, frequency) = match CAPTURE.lock_irq_disabled().as_ref() {
+            Some(capture) => (capture.get_duty_cycle(), capture.get_frequency_hz()),
+            None => (None, None),
+        };
+
+        match (duty, frequency) {
+            (Some(duty), Some(frequency)) => {
+                // f32::abs needs std
+                let duty_error = if duty > DUTY_CYCLE {
+                    duty - DUTY_CYCLE
+                } else {
+                    DUTY_CYCLE - duty
+                };
+                let duty_ok = duty_error <= DUTY_CYCLE * 0.02;
+                let frequency_ok =
+                    (frequency as i32 - FREQUENCY_HZ as i32).abs() as u32 <= FREQUENCY_HZ / 50;
+
+                writeln!(
+                    serial,
+                    "duty {}%, {} Hz: {}\r",
+                    (duty * 100.0) as u32,
+                    frequency,
+                    if duty_ok && frequency_ok {
+                        "ok"
+                    } else {
+                        "FAILED"
+                    }
+                )
+                .ok();
+            }
+            _ => {
+                writeln!(serial, "no complete period captured: FAILED\r").ok();
+            }
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[no_mangle]
+fn Gpio(_trap_frame: &mut TrapFrame) {
+    clear_interrupt(Interrupt::Gpio);
+
+    if let Some(capture) = CAPTURE.lock().as_mut() {
+        capture.on_interrupt();
+    }
+}
@@ -0,0 +1,107 @@
+/*
+
+   Checks that Channels::global_sync_start starts PWM channels in step.
+
+   Channels 0, 1 and 2 output 1 kHz at a duty cycle of 50% on pins 0, 1 and 2. They are first
+   enabled one by one, 100 µs apart, then restarted with `global_sync_start`. Each time, the
+   levels of the three pins are sampled together many times: out of step they often differ,
+   in step they have to be the same in nearly every sample. The counts are printed over UART0,
+   followed by "ok" or "FAILED". On a logic analyzer the edges of the pins line up afterwards.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_hal::delay::blocking::DelayUs;
+use embedded_hal::pwm::blocking::Pwm;
+use embedded_time::duration::Milliseconds;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    pac,
+    prelude::*,
+    pwm,
+    serial::*,
+};
+use panic_halt as _;
+
+const SAMPLES: u32 = 100_000;
+
+/// Returns in how many of `SAMPLES` samples pins 0, 1 and 2 didn't all have the same level
+fn mismatches() -> u32 {
+    let glb = unsafe { &*pac::GLB::ptr() };
+    let mut mismatches = 0;
+    for _ in 0..SAMPLES {
+        let levels = glb.gpio_cfgctl30.read().bits() & 0b111;
+        if levels != 0 && levels != 0b111 {
+            mismatches += 1;
+        }
+    }
+    mismatches
+}
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut delay = McycleDelay::new(clocks.sysclk().0);
+    let mut channels = pwm::Channels::from((dp.PWM, clocks));
+
+    let period = Milliseconds::<u64>::new(1);
+    channels.channel0.set_period(period).unwrap();
+    channels.channel1.set_period(period).unwrap();
+    channels.channel2.set_period(period).unwrap();
+    let duty = channels.channel0.get_max_duty().unwrap() / 2;
+    channels.channel0.set_duty(&(), duty).unwrap();
+    channels.channel1.set_duty(&(), duty).unwrap();
+    channels.channel2.set_duty(&(), duty).unwrap();
+
+    let _pin0 = parts.pin0.into_pull_down_pwm();
+    let _pin1 = parts.pin1.into_pull_down_pwm();
+    let _pin2 = parts.pin2.into_pull_down_pwm();
+
+    channels.channel0.enable(&()).unwrap();
+    delay.delay_us(100).ok();
+    channels.channel1.enable(&()).unwrap();
+    delay.delay_us(100).ok();
+    channels.channel2.enable(&()).unwrap();
+    let one_by_one = mismatches();
+
+    channels.global_sync_start();
+    let synced = mismatches();
+
+    // 100 µs apart is 20% of the samples, a few bus cycles apart is next to none
+    let ok = one_by_one > SAMPLES / 10 && synced < SAMPLES / 1000;
+    writeln!(
+        serial,
+        "levels differ in {} of {} samples one by one, {} after global_sync_start: {}\r",
+        one_by_one,
+        SAMPLES,
+        synced,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+
+    loop {}
+}
@@ -0,0 +1,145 @@
+/*
+
+   Decodes a rotary encoder simulated on two GPIO outputs.
+
+   Connect GPIO11 to GPIO3 and GPIO12 to GPIO4. GPIO11 and GPIO12 play the A and B signals of an
+   encoder with 4 quarter steps per detent, GPIO3 and GPIO4 are the encoder's pins. The
+   interrupt driven count, the delta and the velocity of steps 1 ms apart have to match what was
+   played. The results are printed over UART0, followed by "ok" or "FAILED".
+
+   How the transition table copes with contact bounce is checked by the tests of `gpio::qdec` on
+   the PC.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_hal::delay::blocking::DelayUs;
+use embedded_hal::digital::blocking::OutputPin;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    gpio::{qdec::Encoder, PullUp},
+    interrupts::{self, Interrupt},
+    pac,
+    prelude::*,
+    serial::*,
+    sync::SpinLock,
+};
+use panic_halt as _;
+
+/// One detent forward, A leading B, with A bouncing at both of its edges
+const FORWARD_BOUNCING_A: [u8; 11] = [
+    0b11, 0b01, 0b11, 0b01, 0b11, 0b01, 0b00, 0b10, 0b00, 0b10, 0b11,
+];
+
+/// A forward detent as driven on GPIO11 and GPIO12, from the resting state with both high
+const DETENT: [u8; 4] = [0b01, 0b00, 0b10, 0b11];
+
+static ENCODER: SpinLock<Option<Encoder<PullUp>>> = SpinLock::new(None);
+
+fn encoder<R>(f: impl FnOnce(&Encoder<PullUp>) -> R) -> R {
+    f(ENCODER.lock_irq_disabled().as_ref().unwrap())
+}
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+    let mut delay = McycleDelay::new(clocks.sysclk().0);
+
+    let mut failed = false;
+
+    let mut check = |serial: &mut dyn Write, name: &str, ok: bool| {
+        writeln!(serial, "{}: {}\r", name, if ok { "ok" } else { "FAILED" }).ok();
+        failed |= !ok;
+    };
+
+    let mut a = parts.pin11.into_pull_up_output();
+    let mut b = parts.pin12.into_pull_up_output();
+    a.set_high().ok();
+    b.set_high().ok();
+    delay.delay_us(10).ok();
+
+    let pin_a = parts.pin3.into_pull_up_input().erase();
+    let pin_b = parts.pin4.into_pull_up_input().erase();
+    *ENCODER.lock_irq_disabled() = Some(Encoder::new(pin_a, pin_b).steps_per_detent(4));
+    interrupts::enable(Interrupt::Gpio);
+
+    let mut play = |states: &[u8], interval_us: u32| {
+        for &state in states {
+            if state & 0b10 != 0 {
+                a.set_high().ok();
+            } else {
+                a.set_low().ok();
+            }
+            if state & 0b01 != 0 {
+                b.set_high().ok();
+            } else {
+                b.set_low().ok();
+            }
+            delay.delay_us(interval_us).ok();
+        }
+    };
+
+    // Three detents forward, one back
+    for _ in 0..3 {
+        play(&DETENT, 50);
+    }
+    play(&[0b10, 0b00, 0b01, 0b11], 50);
+    let count = encoder(Encoder::count);
+    let delta = encoder(Encoder::take_delta);
+    writeln!(serial, "count {}, delta {}\r", count, delta).ok();
+    check(&mut serial, "count", count == 2 && delta == 2);
+
+    play(&FORWARD_BOUNCING_A[1..], 50);
+    let delta = encoder(Encoder::take_delta);
+    check(
+        &mut serial,
+        "bounce",
+        delta == 1 && encoder(Encoder::take_delta) == 0,
+    );
+
+    // A quarter step every ms is 250 detents per second
+    play(&DETENT, 1000);
+    let velocity = encoder(|encoder| encoder.velocity(&clocks));
+    writeln!(serial, "velocity {} detents/s\r", velocity as i32).ok();
+    check(&mut serial, "velocity", (240.0..260.0).contains(&velocity));
+
+    delay.delay_ms(1100).ok();
+    let velocity = encoder(|encoder| encoder.velocity(&clocks));
+    check(&mut serial, "stopped", velocity == 0.0);
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
+
+hal::interrupt!(Gpio, on_gpio);
+
+fn on_gpio() {
+    if let Some(encoder) = ENCODER.lock().as_ref() {
+        encoder.on_interrupt();
+    }
+    interrupts::clear_interrupt(Interrupt::Gpio);
+}
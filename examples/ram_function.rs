@@ -0,0 +1,149 @@
+/*
+
+   Times the same function running from flash and from ITCM as a `ram_function!`.
+
+   Both versions compute an FNV-1a hash of a 4 KiB buffer. Each one is timed with `mcycle` on
+   its first call, when the flash version still has to be fetched into the cache, and as the
+   fastest of ten calls afterwards. The two hashes have to match, the RAM version has to be
+   located in the TCM and the flash version in XIP flash, and the first call from RAM has to be
+   faster than the first call from flash. The timings and `memory::tcm_free` are printed over
+   UART0, followed by "ok" or "FAILED".
+
+   Build with `--release`, in a debug build the iterators aren't inlined and are called in flash.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    memory, pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+use riscv::register::mcycle;
+
+const XIP_FLASH_START: usize = 0x2300_0000;
+
+macro_rules! fnv1a {
+    ($data:expr) => {{
+        let mut hash: u32 = 0x811c_9dc5;
+        for &byte in $data.iter() {
+            hash ^= byte as u32;
+            hash = hash.wrapping_mul(0x0100_0193);
+        }
+        hash
+    }};
+}
+
+#[inline(never)]
+fn hash_from_flash(data: &[u8]) -> u32 {
+    fnv1a!(data)
+}
+
+hal::ram_function! {
+    fn hash_from_ram(data: &[u8]) -> u32 {
+        fnv1a!(data)
+    }
+}
+
+/// Returns the result of `f` and the cycles of its first call and of its fastest call out of ten
+fn time(f: fn(&[u8]) -> u32, data: &[u8]) -> (u32, u32, u32) {
+    let start = mcycle::read() as u32;
+    let result = f(data);
+    let first = (mcycle::read() as u32).wrapping_sub(start);
+
+    let mut fastest = u32::MAX;
+    for _ in 0..10 {
+        let start = mcycle::read() as u32;
+        f(data);
+        fastest = fastest.min((mcycle::read() as u32).wrapping_sub(start));
+    }
+
+    (result, first, fastest)
+}
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut buffer = [0u8; 4096];
+    for (i, byte) in buffer.iter_mut().enumerate() {
+        *byte = (i * 31 + i / 7) as u8;
+    }
+
+    // `hash_from_flash` hasn't run yet, so it's not in the cache
+    let (flash_hash, flash_first, flash_fastest) = time(hash_from_flash, &buffer);
+    let (ram_hash, ram_first, ram_fastest) = time(hash_from_ram, &buffer);
+
+    let flash_address = hash_from_flash as usize;
+    let ram_address = hash_from_ram as usize;
+
+    writeln!(
+        serial,
+        "flash at {:#010x}: first {} cycles, fastest {} cycles\r",
+        flash_address, flash_first, flash_fastest
+    )
+    .ok();
+    writeln!(
+        serial,
+        "ITCM at {:#010x}: first {} cycles, fastest {} cycles\r",
+        ram_address, ram_first, ram_fastest
+    )
+    .ok();
+    writeln!(serial, "{} bytes of TCM free\r", memory::tcm_free()).ok();
+
+    let mut failed = false;
+
+    let ok = flash_hash == ram_hash;
+    writeln!(
+        serial,
+        "hashes {:#010x} {:#010x}: {}\r",
+        flash_hash,
+        ram_hash,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    let ok = memory::is_in_tcm(ram_address)
+        && !memory::is_in_tcm(flash_address)
+        && flash_address >= XIP_FLASH_START;
+    writeln!(serial, "placement: {}\r", if ok { "ok" } else { "FAILED" }).ok();
+    failed |= !ok;
+
+    let ok = ram_first < flash_first;
+    writeln!(serial, "first call: {}\r", if ok { "ok" } else { "FAILED" }).ok();
+    failed |= !ok;
+
+    let free = memory::tcm_free();
+    let ok = free > 0 && free < memory::ITCM_SIZE;
+    writeln!(serial, "tcm_free: {}\r", if ok { "ok" } else { "FAILED" }).ok();
+    failed |= !ok;
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
@@ -0,0 +1,73 @@
+/*
+
+   Checks a software reset round trip, the decoding of the flags is covered by the host tests.
+
+   The program resets itself with `power::software_reset`, and after starting again checks
+   that the reset reason is `Software` and that `clear_reset_reason` turns it back into
+   `PowerOn`. The results are printed over UART0, followed by "ok" or "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_hal::serial::nb::Write as _;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    pac,
+    power::{self, ResetReason},
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let reason = power::reset_reason();
+
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    writeln!(serial, "reset reason: {:?}\r", reason).ok();
+
+    if reason != ResetReason::Software {
+        writeln!(serial, "resetting\r").ok();
+        nb::block!(serial.flush()).ok();
+        power::software_reset();
+    }
+
+    power::clear_reset_reason();
+    let cleared = power::reset_reason();
+    writeln!(serial, "after clearing: {:?}\r", cleared).ok();
+    writeln!(
+        serial,
+        "{}\r",
+        if cleared == ResetReason::PowerOn {
+            "ok"
+        } else {
+            "FAILED"
+        }
+    )
+    .ok();
+
+    loop {}
+}
@@ -0,0 +1,111 @@
+/*
+
+   Counts watchdog resets in memory which is retained through them.
+
+   On a power-on no valid counter exists, so the example starts counting at zero. Each boot
+   increments the counter stored with `hbn::retention::write` and a copy of it in a `retained!`
+   static, starts the watchdog in reset mode and never feeds it. After the third watchdog reset
+   both copies are checked to have counted every reset, and "ok" or "FAILED" is printed.
+
+   Press the reset button to run the example again; a power cycle also clears the counter.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_hal::watchdog::blocking::Enable;
+use embedded_time::{duration::*, rate::*};
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    hbn::retention::{self, Retained},
+    pac,
+    prelude::*,
+    serial::*,
+    timer::*,
+    watchdog::*,
+};
+use panic_halt as _;
+
+const RESETS: u32 = 3;
+
+hal::retained! {
+    static RESET_COUNT: Retained<u32>;
+}
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut gpio_pins = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut gpio_pins.clk_cfg);
+
+    let pin16 = gpio_pins.pin16.into_uart_sig0();
+    let pin7 = gpio_pins.pin7.into_uart_sig7();
+    let mux0 = gpio_pins.uart_mux0.into_uart0_tx();
+    let mux7 = gpio_pins.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let timers = dp.TIMER.split();
+    let watchdog = timers
+        .watchdog
+        .set_clock_source(WdtClockSource::Rc32Khz, 125.Hz());
+
+    // Anything but a watchdog reset starts a new run
+    let counter = if watchdog.has_watchdog_reset_occurred() {
+        retention::read::<u32>()
+    } else {
+        Ok(0)
+    };
+    let copy = RESET_COUNT.read();
+
+    match counter {
+        Ok(counter) => writeln!(serial, "watchdog resets: {}\r", counter).ok(),
+        Err(_) => writeln!(serial, "no valid counter\r").ok(),
+    };
+
+    if counter != Ok(0) && copy != counter {
+        writeln!(serial, "retained copy differs: {:?}\r", copy).ok();
+    }
+
+    match counter {
+        Ok(counter) if counter >= RESETS => {
+            watchdog.clear_wts();
+            retention::clear();
+            RESET_COUNT.clear();
+
+            if counter == RESETS && copy == Ok(RESETS) {
+                writeln!(serial, "ok\r").ok();
+            } else {
+                writeln!(serial, "FAILED\r").ok();
+            }
+
+            loop {}
+        }
+        Ok(counter) => {
+            retention::write(&(counter + 1)).unwrap();
+            RESET_COUNT.write(&(counter + 1));
+
+            watchdog.set_mode(WatchdogMode::Reset);
+            let _watchdog = watchdog.start(1_u32.seconds()).unwrap();
+
+            // Never fed, so the board resets
+            loop {}
+        }
+        Err(_) => {
+            writeln!(serial, "FAILED\r").ok();
+            loop {}
+        }
+    }
+}
@@ -0,0 +1,71 @@
+/*
+
+   Draws random numbers in a range from the TRNG.
+
+   Installs the TRNG as the global generator, then prints ten rolls of a die, a number drawn
+   from the driver directly and a few jittered delays over UART0.
+
+   How the rejection sampling avoids bias is checked by the tests of `rng` on the PC.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    pac,
+    prelude::*,
+    rng::{self, Trng},
+    sec_eng::SecEngExt,
+    serial::*,
+};
+use panic_halt as _;
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+    let sec_eng = dp.SEC_ENG.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(115_200.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut trng = Trng::new(sec_eng.trng, &mut parts.clk_cfg).unwrap();
+    let value = rng::gen_range(&mut trng, 0..1000);
+    writeln!(serial, "gen_range: {}\r", value).ok();
+    rng::init(trng);
+
+    write!(serial, "dice:").ok();
+    for _ in 0..10 {
+        write!(serial, " {}", rng::random_range(1..7).unwrap()).ok();
+    }
+    writeln!(serial, "\r").ok();
+
+    let mut delay = McycleDelay::new(clocks.sysclk().0);
+    for _ in 0..5 {
+        let start = McycleDelay::get_cycle_count();
+        let us = rng::jitter_delay(&mut delay, 50..200).unwrap();
+        let cycles = McycleDelay::cycles_since(start);
+        writeln!(serial, "jitter_delay: {} us, took {} cycles\r", us, cycles).ok();
+    }
+
+    loop {}
+}
@@ -0,0 +1,115 @@
+/*
+
+   Checks the wrappers of the ROM function table against the HAL's own implementations.
+
+   The CRC-32 of a few buffers has to match `crc::Crc`, the delays have to take the given time
+   measured with `mcycle`, and `mem_eq` has to agree with comparing the slices in Rust. A wrong
+   table index usually hangs or crashes instead, so a missing final line also means "FAILED".
+   The results are printed over UART0, followed by "ok" or "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    crc::{Algorithm, Crc},
+    pac,
+    prelude::*,
+    rom::RomApi,
+    serial::*,
+};
+use panic_halt as _;
+use riscv::register::mcycle;
+
+const SYSCLK_HZ: u32 = 160_000_000;
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let rom = RomApi::new();
+    let mut failed = false;
+
+    writeln!(serial, "ROM table revision {:#x}\r", rom.revision()).ok();
+
+    let mut buffer = [0u8; 1000];
+    for (i, byte) in buffer.iter_mut().enumerate() {
+        *byte = (i * 7 + i / 13) as u8;
+    }
+    let inputs: [&[u8]; 4] = [b"", b"123456789", &buffer[..1], &buffer];
+    for input in inputs.iter() {
+        let rom_crc = rom.crc32(input);
+        let hal_crc = Crc::checksum(Algorithm::Crc32, input);
+        let ok = rom_crc == hal_crc;
+        writeln!(
+            serial,
+            "crc32 of {} bytes: {:#010x}, {}\r",
+            input.len(),
+            rom_crc,
+            if ok { "ok" } else { "FAILED" }
+        )
+        .ok();
+        failed |= !ok;
+    }
+
+    let start = mcycle::read() as u32;
+    rom.delay_us(1000);
+    let us = (mcycle::read() as u32).wrapping_sub(start) / (SYSCLK_HZ / 1_000_000);
+    let ok = (990..=1050).contains(&us);
+    writeln!(
+        serial,
+        "delay_us(1000) took {} µs, {}\r",
+        us,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    let start = mcycle::read() as u32;
+    rom.delay_ms(10);
+    let us = (mcycle::read() as u32).wrapping_sub(start) / (SYSCLK_HZ / 1_000_000);
+    let ok = (9_900..=10_500).contains(&us);
+    writeln!(
+        serial,
+        "delay_ms(10) took {} µs, {}\r",
+        us,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    let mut other = buffer;
+    other[999] ^= 1;
+    let ok = rom.mem_eq(&buffer, &buffer)
+        && !rom.mem_eq(&buffer, &other)
+        && rom.mem_eq(&buffer[..999], &other[..999])
+        && !rom.mem_eq(&buffer[..10], &buffer[..11]);
+    writeln!(serial, "mem_eq: {}\r", if ok { "ok" } else { "FAILED" }).ok();
+    failed |= !ok;
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
@@ -0,0 +1,132 @@
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use hal::{
+    aes::Aes,
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    pac,
+    prelude::*,
+    sec_eng::SecEngExt,
+    serial::*,
+    sha::{Mode, Sha},
+};
+use panic_halt as _;
+
+const CORE_FREQUENCY: u64 = 160_000_000;
+
+/// Start of the memory mapped flash
+const XIP_BASE: usize = 0x2300_0000;
+
+/// Amount of flash to hash, this includes the running firmware itself
+const FLASH_LENGTH: usize = 256 * 1024;
+
+#[repr(align(4))]
+struct Buffer([u8; 16 * 1024]);
+
+static mut SOURCE: Buffer = Buffer([0; 16 * 1024]);
+static mut DESTINATION: Buffer = Buffer([0; 16 * 1024]);
+
+/// Throughput in kB/s for `bytes` processed in `cycles`
+fn kbps(bytes: usize, cycles: u64) -> u64 {
+    bytes as u64 * CORE_FREQUENCY / cycles.max(1) / 1000
+}
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(115_200.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let sec_eng = dp.SEC_ENG.split();
+    let mut sha = Sha::new(sec_eng.sha, &mut parts.clk_cfg);
+    let mut aes = Aes::new(sec_eng.aes, &mut parts.clk_cfg);
+
+    // Hash the flash straight from its memory mapping, both ways have to agree
+    let flash = unsafe { core::slice::from_raw_parts(XIP_BASE as *const u8, FLASH_LENGTH) };
+
+    let start = McycleDelay::get_cycle_count();
+    let registers = sha.digest(Mode::Sha256, flash).unwrap();
+    let register_cycles = McycleDelay::cycles_since(start);
+
+    let start = McycleDelay::get_cycle_count();
+    let linked = sha.digest_linked(Mode::Sha256, flash).unwrap();
+    let linked_cycles = McycleDelay::cycles_since(start);
+
+    let result = if registers == linked { "ok" } else { "FAILED" };
+    writeln!(
+        serial,
+        "SHA-256 of {} KiB flash: {}, registers {} kB/s, linked {} kB/s\r",
+        FLASH_LENGTH / 1024,
+        result,
+        kbps(FLASH_LENGTH, register_cycles),
+        kbps(FLASH_LENGTH, linked_cycles)
+    )
+    .ok();
+
+    // Odd length, so the last partial block goes through the registers
+    let key = [0x2bu8; 16];
+    let iv = [0xf0u8; 16];
+    let (source, destination) = unsafe { (&mut SOURCE.0, &mut DESTINATION.0) };
+    let length = source.len() - 5;
+    for (i, byte) in source.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+
+    let start = McycleDelay::get_cycle_count();
+    destination[..length].copy_from_slice(&source[..length]);
+    aes.ctr(&key, &iv, &mut destination[..length]).unwrap();
+    let register_cycles = McycleDelay::cycles_since(start);
+    let mut expected = [0u8; 32];
+    expected.copy_from_slice(&destination[length - 32..length]);
+
+    let start = McycleDelay::get_cycle_count();
+    aes.ctr_linked(&key, &iv, &source[..length], &mut destination[..length])
+        .unwrap();
+    let linked_cycles = McycleDelay::cycles_since(start);
+
+    let result = if destination[length - 32..length] == expected {
+        "ok"
+    } else {
+        "FAILED"
+    };
+    writeln!(
+        serial,
+        "AES-128-CTR of {} bytes: {}, registers {} kB/s, linked {} kB/s\r",
+        length,
+        result,
+        kbps(length, register_cycles),
+        kbps(length, linked_cycles)
+    )
+    .ok();
+
+    // Decrypting the linked result in place has to give the source back
+    aes.ctr(&key, &iv, &mut destination[..length]).unwrap();
+    let result = if destination[..length] == source[..length] {
+        "ok"
+    } else {
+        "FAILED"
+    };
+    writeln!(serial, "AES-128-CTR round trip: {}\r", result).ok();
+
+    loop {}
+}
@@ -3,7 +3,7 @@
 
 use bl602_hal as hal;
 use core::fmt::Write;
-use embedded_hal::delay::blocking::DelayMs;
+use embedded_hal::delay::blocking::DelayUs;
 use hal::{
     clock::{Strict, SysclkFreq, UART_PLL_FREQ},
     pac,
@@ -0,0 +1,130 @@
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    pac,
+    prelude::*,
+    sec_eng::SecEngExt,
+    serial::*,
+    sha::{Mode, Sha},
+};
+use panic_halt as _;
+
+// Test vectors from FIPS 180-2 appendix B
+const ABC: &[u8] = b"abc";
+const TWO_BLOCKS: &[u8] = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+
+const SHA256_EMPTY: [u8; 32] = [
+    0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f, 0xb9, 0x24,
+    0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b, 0x78, 0x52, 0xb8, 0x55,
+];
+const SHA256_ABC: [u8; 32] = [
+    0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae, 0x22, 0x23,
+    0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61, 0xf2, 0x00, 0x15, 0xad,
+];
+const SHA256_TWO_BLOCKS: [u8; 32] = [
+    0x24, 0x8d, 0x6a, 0x61, 0xd2, 0x06, 0x38, 0xb8, 0xe5, 0xc0, 0x26, 0x93, 0x0c, 0x3e, 0x60, 0x39,
+    0xa3, 0x3c, 0xe4, 0x59, 0x64, 0xff, 0x21, 0x67, 0xf6, 0xec, 0xed, 0xd4, 0x19, 0xdb, 0x06, 0xc1,
+];
+const SHA256_MILLION_A: [u8; 32] = [
+    0xcd, 0xc7, 0x6e, 0x5c, 0x99, 0x14, 0xfb, 0x92, 0x81, 0xa1, 0xc7, 0xe2, 0x84, 0xd7, 0x3e, 0x67,
+    0xf1, 0x80, 0x9a, 0x48, 0xa4, 0x97, 0x20, 0x0e, 0x04, 0x6d, 0x39, 0xcc, 0xc7, 0x11, 0x2c, 0xd0,
+];
+// 64 and 65 times "a", a whole block and one byte more, the padding taking a block of its own
+const SHA256_A64: [u8; 32] = [
+    0xff, 0xe0, 0x54, 0xfe, 0x7a, 0xe0, 0xcb, 0x6d, 0xc6, 0x5c, 0x3a, 0xf9, 0xb6, 0x1d, 0x52, 0x09,
+    0xf4, 0x39, 0x85, 0x1d, 0xb4, 0x3d, 0x0b, 0xa5, 0x99, 0x73, 0x37, 0xdf, 0x15, 0x46, 0x68, 0xeb,
+];
+const SHA256_A65: [u8; 32] = [
+    0x63, 0x53, 0x61, 0xc4, 0x8b, 0xb9, 0xea, 0xb1, 0x41, 0x98, 0xe7, 0x6e, 0xa8, 0xab, 0x7f, 0x1a,
+    0x41, 0x68, 0x5d, 0x6a, 0xd6, 0x2a, 0xa9, 0x14, 0x6d, 0x30, 0x1d, 0x4f, 0x17, 0xeb, 0x0a, 0xe0,
+];
+const SHA224_ABC: [u8; 28] = [
+    0x23, 0x09, 0x7d, 0x22, 0x34, 0x05, 0xd8, 0x22, 0x86, 0x42, 0xa4, 0x77, 0xbd, 0xa2, 0x55, 0xb3,
+    0x2a, 0xad, 0xbc, 0xe4, 0xbd, 0xa0, 0xb3, 0xf7, 0xe3, 0x6c, 0x9d, 0xa7,
+];
+const SHA1_ABC: [u8; 20] = [
+    0xa9, 0x99, 0x3e, 0x36, 0x47, 0x06, 0x81, 0x6a, 0xba, 0x3e, 0x25, 0x71, 0x78, 0x50, 0xc2, 0x6c,
+    0x9c, 0xd0, 0xd8, 0x9d,
+];
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(115_200.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let sec_eng = dp.SEC_ENG.split();
+    let mut sha = Sha::new(sec_eng.sha, &mut parts.clk_cfg);
+
+    let mut check = |name: &str, mode: Mode, data: &[u8], expected: &[u8]| {
+        let digest = sha.digest(mode, data).unwrap();
+        let result = if digest.as_bytes() == expected {
+            "ok"
+        } else {
+            "FAILED"
+        };
+        writeln!(serial, "{}: {}\r", name, result).ok();
+    };
+
+    check("SHA-256 empty", Mode::Sha256, b"", &SHA256_EMPTY);
+    check("SHA-256 abc", Mode::Sha256, ABC, &SHA256_ABC);
+    // 56 bytes, the length no longer fits into the first block
+    check(
+        "SHA-256 two blocks",
+        Mode::Sha256,
+        TWO_BLOCKS,
+        &SHA256_TWO_BLOCKS,
+    );
+    let a65 = [b'a'; 65];
+    check("SHA-256 64 bytes", Mode::Sha256, &a65[..64], &SHA256_A64);
+    check("SHA-256 65 bytes", Mode::Sha256, &a65, &SHA256_A65);
+    check("SHA-224 abc", Mode::Sha224, ABC, &SHA224_ABC);
+    check("SHA-1 abc", Mode::Sha1, ABC, &SHA1_ABC);
+
+    // Streamed in chunks which are not a multiple of the block size, to exercise the buffering
+    let chunk = [b'a'; 999];
+    sha.start(Mode::Sha256);
+    for _ in 0..1001 {
+        sha.update(&chunk).unwrap();
+    }
+    sha.update(&chunk[..1]).unwrap();
+    let mut hash = [0u8; 32];
+    sha.finalize(&mut hash).unwrap();
+    let result = if hash == SHA256_MILLION_A {
+        "ok"
+    } else {
+        "FAILED"
+    };
+    writeln!(serial, "SHA-256 million a: {}\r", result).ok();
+
+    // A partial block topped up across the block boundary
+    sha.start(Mode::Sha256);
+    sha.update(&chunk[..63]).unwrap();
+    sha.update(&chunk[..2]).unwrap();
+    sha.finalize(&mut hash).unwrap();
+    let result = if hash == SHA256_A65 { "ok" } else { "FAILED" };
+    writeln!(serial, "SHA-256 65 bytes streamed: {}\r", result).ok();
+
+    loop {}
+}
@@ -0,0 +1,369 @@
+/*
+
+   Shares a simulated I2C bus and a simulated SPI bus between two devices each.
+
+   The I2C bus has register files at 0x20 and 0x48, used through two `I2cDevice`s by a small
+   generic driver function, the way a driver crate would. Each device has to see only its own
+   registers, an address without a device has to fail, and a bus which is in use, in a
+   `RefCell` or a `SpinLock`, has to fail with `Busy` and work again once released. With the
+   `critical-section-impl` feature the same runs on a `sync::Mutex`.
+
+   The SPI bus logs every frame and every change of the two chip selects, of a flash at CS 0
+   and a DAC at CS 1. Each operation has to come out framed by its own chip select, a failing
+   operation has to release it as well, and an operation on a busy bus must not touch it. The
+   results are printed over UART0, followed by "ok" or "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::cell::RefCell;
+use core::convert::Infallible;
+use core::fmt::Write as _;
+use embedded_hal::digital::blocking::OutputPin;
+use embedded_hal::i2c::blocking::{Read, Write, WriteRead};
+use embedded_hal::i2c::{self, SevenBitAddress};
+use embedded_hal::spi::{self, blocking as spi_blocking};
+use hal::{
+    bus::{Error, I2cDevice, SpiDevice},
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    pac,
+    prelude::*,
+    serial::*,
+    sync::SpinLock,
+};
+use panic_halt as _;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum MockError {
+    /// No device at the address
+    Nack,
+    /// The SPI bus fails on a frame of 0xee
+    Fault,
+}
+
+impl i2c::Error for MockError {
+    fn kind(&self) -> i2c::ErrorKind {
+        i2c::ErrorKind::Other
+    }
+}
+
+impl spi::Error for MockError {
+    fn kind(&self) -> spi::ErrorKind {
+        spi::ErrorKind::Other
+    }
+}
+
+/// Devices at 0x20 and 0x48 with 16 registers each, the first byte written sets the register
+/// which the following bytes are written to or read from
+struct MockI2c {
+    registers: [[u8; 16]; 2],
+    pointer: [usize; 2],
+}
+
+impl MockI2c {
+    const fn new() -> Self {
+        MockI2c {
+            registers: [[0; 16]; 2],
+            pointer: [0; 2],
+        }
+    }
+
+    fn device(address: SevenBitAddress) -> Result<usize, MockError> {
+        match address {
+            0x20 => Ok(0),
+            0x48 => Ok(1),
+            _ => Err(MockError::Nack),
+        }
+    }
+}
+
+impl Write for MockI2c {
+    type Error = MockError;
+
+    fn write(&mut self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), MockError> {
+        let device = Self::device(address)?;
+        if let Some((&register, data)) = bytes.split_first() {
+            self.pointer[device] = register as usize % 16;
+            for &byte in data {
+                self.registers[device][self.pointer[device]] = byte;
+                self.pointer[device] = (self.pointer[device] + 1) % 16;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for MockI2c {
+    type Error = MockError;
+
+    fn read(&mut self, address: SevenBitAddress, buffer: &mut [u8]) -> Result<(), MockError> {
+        let device = Self::device(address)?;
+        for byte in buffer.iter_mut() {
+            *byte = self.registers[device][self.pointer[device]];
+            self.pointer[device] = (self.pointer[device] + 1) % 16;
+        }
+        Ok(())
+    }
+}
+
+impl WriteRead for MockI2c {
+    type Error = MockError;
+
+    fn write_read(
+        &mut self,
+        address: SevenBitAddress,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), MockError> {
+        self.write(address, bytes)?;
+        self.read(address, buffer)
+    }
+}
+
+/// What a driver crate would do: write a register, then read it back
+fn write_and_verify<I, E>(
+    i2c: &mut I,
+    address: SevenBitAddress,
+    register: u8,
+    value: u8,
+) -> Result<u8, E>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    i2c.write(address, &[register, value])?;
+    let mut read = [0];
+    i2c.write_read(address, &[register], &mut read)?;
+    Ok(read[0])
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Event {
+    Select(u8),
+    Deselect(u8),
+    Frame(u8),
+}
+
+struct Log {
+    events: [Event; 32],
+    len: usize,
+}
+
+impl Log {
+    fn push(&mut self, event: Event) {
+        if self.len < self.events.len() {
+            self.events[self.len] = event;
+            self.len += 1;
+        }
+    }
+
+    fn take(&mut self) -> ([Event; 32], usize) {
+        let taken = (self.events, self.len);
+        self.len = 0;
+        taken
+    }
+}
+
+/// Logs every frame and returns it plus 1
+struct MockSpi<'a> {
+    log: &'a RefCell<Log>,
+}
+
+impl MockSpi<'_> {
+    fn frame(&mut self, word: u8) -> Result<u8, MockError> {
+        if word == 0xee {
+            return Err(MockError::Fault);
+        }
+        self.log.borrow_mut().push(Event::Frame(word));
+        Ok(word.wrapping_add(1))
+    }
+}
+
+impl spi_blocking::Write<u8> for MockSpi<'_> {
+    type Error = MockError;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), MockError> {
+        for &word in words {
+            self.frame(word)?;
+        }
+        Ok(())
+    }
+}
+
+impl spi_blocking::TransferInplace<u8> for MockSpi<'_> {
+    type Error = MockError;
+
+    fn transfer_inplace(&mut self, words: &mut [u8]) -> Result<(), MockError> {
+        for word in words.iter_mut() {
+            *word = self.frame(*word)?;
+        }
+        Ok(())
+    }
+}
+
+struct MockCs<'a> {
+    id: u8,
+    log: &'a RefCell<Log>,
+}
+
+impl OutputPin for MockCs<'_> {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.log.borrow_mut().push(Event::Select(self.id));
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.log.borrow_mut().push(Event::Deselect(self.id));
+        Ok(())
+    }
+}
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    use spi_blocking::{TransferInplace as _, Write as _};
+
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut failed = false;
+
+    let mut check = |serial: &mut dyn core::fmt::Write, name: &str, ok: bool| {
+        writeln!(serial, "{}: {}\r", name, if ok { "ok" } else { "FAILED" }).ok();
+        failed |= !ok;
+    };
+
+    let i2c = RefCell::new(MockI2c::new());
+    let mut expander = I2cDevice::new(&i2c);
+    let mut sensor = I2cDevice::new(&i2c);
+    let expander_value = write_and_verify(&mut expander, 0x20, 3, 0x5a);
+    let sensor_value = write_and_verify(&mut sensor, 0x48, 3, 0xa5);
+    check(
+        &mut serial,
+        "I2C devices",
+        expander_value == Ok(0x5a)
+            && sensor_value == Ok(0xa5)
+            && i2c.borrow().registers[0][3] == 0x5a,
+    );
+    check(
+        &mut serial,
+        "I2C error",
+        expander.write(0x30, &[0]) == Err(Error::Bus(MockError::Nack)),
+    );
+    let held = i2c.borrow_mut();
+    let busy = sensor.write(0x48, &[0]);
+    drop(held);
+    check(
+        &mut serial,
+        "RefCell busy",
+        busy == Err(Error::Busy) && sensor.write(0x48, &[0]).is_ok(),
+    );
+
+    let locked = SpinLock::new(MockI2c::new());
+    let mut device = I2cDevice::new(&locked);
+    let held = locked.try_lock();
+    let busy = device.write(0x20, &[0]);
+    drop(held);
+    check(
+        &mut serial,
+        "SpinLock",
+        busy == Err(Error::Busy) && write_and_verify(&mut device, 0x20, 7, 0x11) == Ok(0x11),
+    );
+
+    #[cfg(feature = "critical-section-impl")]
+    {
+        let mutex = hal::sync::Mutex::new(MockI2c::new());
+        let mut a = I2cDevice::new(&mutex);
+        let mut b = I2cDevice::new(&mutex);
+        check(
+            &mut serial,
+            "Mutex",
+            write_and_verify(&mut a, 0x20, 1, 0x22) == Ok(0x22)
+                && write_and_verify(&mut b, 0x48, 1, 0x33) == Ok(0x33),
+        );
+    }
+
+    let log = RefCell::new(Log {
+        events: [Event::Frame(0); 32],
+        len: 0,
+    });
+    let spi = RefCell::new(MockSpi { log: &log });
+    let mut flash = SpiDevice::new(&spi, MockCs { id: 0, log: &log });
+    let mut dac = SpiDevice::new(&spi, MockCs { id: 1, log: &log });
+    log.borrow_mut().take();
+
+    let written = flash.write(&[0x9f, 0x00]).and(dac.write(&[0x30, 0x12]));
+    let (events, len) = log.borrow_mut().take();
+    check(
+        &mut serial,
+        "SPI framing",
+        written.is_ok()
+            && events[..len]
+                == [
+                    Event::Select(0),
+                    Event::Frame(0x9f),
+                    Event::Frame(0x00),
+                    Event::Deselect(0),
+                    Event::Select(1),
+                    Event::Frame(0x30),
+                    Event::Frame(0x12),
+                    Event::Deselect(1),
+                ],
+    );
+
+    let mut words = [1, 2, 3];
+    let transferred = dac.transfer_inplace(&mut words);
+    let (events, len) = log.borrow_mut().take();
+    check(
+        &mut serial,
+        "SPI transfer",
+        transferred.is_ok()
+            && words == [2, 3, 4]
+            && len == 5
+            && events[0] == Event::Select(1)
+            && events[4] == Event::Deselect(1),
+    );
+
+    let failed_write = flash.write(&[0x01, 0xee, 0x02]);
+    let (events, len) = log.borrow_mut().take();
+    check(
+        &mut serial,
+        "SPI error releases CS",
+        failed_write == Err(Error::Bus(MockError::Fault))
+            && events[..len] == [Event::Select(0), Event::Frame(0x01), Event::Deselect(0)],
+    );
+
+    let held = spi.borrow_mut();
+    let busy = dac.write(&[0x30]);
+    drop(held);
+    let (_, len) = log.borrow_mut().take();
+    check(
+        &mut serial,
+        "SPI busy",
+        busy == Err(Error::Busy) && len == 0,
+    );
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
@@ -0,0 +1,95 @@
+/*
+
+   Shares UART0 between `main` and the TimerCh0 interrupt handler with a SharedPeripheral.
+
+   The timer fires every 500 ms and the handler prints a tick, while `main` prints its own line
+   every second. Each line is written inside a critical section, so they never interleave.
+
+   Build with `--features critical-section-impl`.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, Ordering};
+use embedded_hal::delay::blocking::DelayUs;
+use embedded_time::{duration::*, rate::*};
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    gpio::{pin::Pin16, pin::Pin7, Uart, Uart0Rx, Uart0Tx, UartMux0, UartMux7},
+    interrupts::*,
+    pac,
+    prelude::*,
+    serial::*,
+    sync::SharedPeripheral,
+    timer::*,
+};
+use panic_halt as _;
+
+type Uart0Pins = (
+    (Pin16<Uart>, UartMux0<Uart0Tx>),
+    (Pin7<Uart>, UartMux7<Uart0Rx>),
+);
+
+static UART: SharedPeripheral<Serial<pac::UART, Uart0Pins>> = SharedPeripheral::new();
+static TIMER: SharedPeripheral<ConfiguredTimerChannel0> = SharedPeripheral::new();
+static TICKS: AtomicU32 = AtomicU32::new(0);
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+    UART.init(serial);
+
+    let timers = dp.TIMER.split();
+    let timer_ch0 = timers
+        .channel0
+        .set_clock_source(ClockSource::Clock1Khz, 1000u32.Hz());
+    timer_ch0.enable_match0_interrupt();
+    timer_ch0.set_match0(500_u32.milliseconds());
+    timer_ch0.set_preload_value(0.microseconds());
+    timer_ch0.set_preload(hal::timer::Preload::PreloadMatchComparator0);
+    timer_ch0.enable();
+    TIMER.init(timer_ch0);
+
+    // Only once both globals are set, so the handler finds them
+    enable_interrupt(Interrupt::TimerCh0);
+
+    let mut delay = McycleDelay::new(clocks.sysclk().0);
+    loop {
+        let ticks = TICKS.load(Ordering::Relaxed);
+        UART.with(|serial| writeln!(serial, "main: {} ticks so far\r", ticks).ok());
+        delay.delay_ms(1000).ok();
+    }
+}
+
+hal::interrupt!(TimerCh0, on_timer);
+
+fn on_timer() {
+    TIMER.with(|timer| timer.clear_match0_interrupt());
+    clear_interrupt(Interrupt::TimerCh0);
+
+    let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    UART.with(|serial| writeln!(serial, "TimerCh0: tick {}\r", ticks).ok());
+}
@@ -0,0 +1,135 @@
+/*
+
+   Stress test for power::sleep_until, with TimerCh0 interrupting every 7 µs while the main loop
+   sleeps towards 100 µs deadlines.
+
+   Every sleep has to end with the deadline passed, and not much later than that: a wake up lost
+   to the race between the deadline check and WFI would show up as a sleep which only ends at
+   the next interrupt after the deadline, or never, if the interrupts are stopped. Both are
+   checked, then "ok" or "FAILED" is printed over UART0.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+use embedded_time::{duration::*, rate::*};
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    interrupts::*,
+    pac,
+    power::{self, Instant, Wake},
+    prelude::*,
+    serial::*,
+    timer::*,
+};
+use panic_halt as _;
+
+const ROUNDS: u32 = 10_000;
+/// How late a deadline may be noticed, covering an interrupt handler running in between
+const MAX_LATENESS_US: u64 = 20;
+
+static TICKS: AtomicU32 = AtomicU32::new(0);
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut gpio_pins = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut gpio_pins.clk_cfg);
+
+    let pin16 = gpio_pins.pin16.into_uart_sig0();
+    let pin7 = gpio_pins.pin7.into_uart_sig7();
+    let mux0 = gpio_pins.uart_mux0.into_uart0_tx();
+    let mux7 = gpio_pins.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    power::start_mtime(&clocks);
+
+    // A period which isn't a divisor of the deadline spacing, so the interrupts hit every phase
+    // of the sleep sequence
+    let timers = dp.TIMER.split();
+    let timer_ch0 = timers
+        .channel0
+        .set_clock_source(ClockSource::Fclk(&clocks), 160_000_000_u32.Hz());
+    timer_ch0.enable_match0_interrupt();
+    timer_ch0.set_match0(7_u32.microseconds());
+    timer_ch0.set_preload_value(0.microseconds());
+    timer_ch0.set_preload(hal::timer::Preload::PreloadMatchComparator0);
+    timer_ch0.enable();
+    enable_interrupt(Interrupt::TimerCh0);
+
+    let mut interrupted = 0u32;
+    let mut worst = 0u64;
+    let mut failed = false;
+
+    for _ in 0..ROUNDS {
+        let deadline = Instant::now() + Duration::from_micros(100);
+
+        while power::sleep_until(deadline) == Wake::Interrupt {
+            interrupted += 1;
+        }
+
+        let now = Instant::now();
+        if now < deadline {
+            failed = true;
+        }
+        let late = now.ticks() - deadline.ticks();
+        worst = worst.max(late);
+        if late > MAX_LATENESS_US {
+            failed = true;
+        }
+    }
+
+    disable_interrupt(Interrupt::TimerCh0);
+
+    writeln!(
+        serial,
+        "{} timer interrupts, {} early wakes, latest deadline +{} us\r",
+        TICKS.load(Ordering::Relaxed),
+        interrupted,
+        worst
+    )
+    .ok();
+
+    // Without timer interrupts, only the deadline can end the sleep
+    let deadline = Instant::now() + Duration::from_millis(5);
+    if power::sleep_until(deadline) != Wake::Deadline || Instant::now() < deadline {
+        failed = true;
+    }
+
+    if failed || interrupted == 0 {
+        writeln!(serial, "FAILED\r").ok();
+    } else {
+        writeln!(serial, "ok\r").ok();
+    }
+
+    loop {
+        power::idle();
+    }
+}
+
+#[allow(non_snake_case)]
+#[no_mangle]
+fn TimerCh0(_: &mut TrapFrame) {
+    clear_interrupt(Interrupt::TimerCh0);
+
+    // The timer was only handed to the interrupt through its registers, clear match0 directly
+    let timer = unsafe { &*pac::TIMER::ptr() };
+    timer.ticr2.write(|w| w.tclr_0().set_bit());
+
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
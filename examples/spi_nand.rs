@@ -0,0 +1,141 @@
+/*
+
+   Checks the SPI NAND driver against a GD5F1GQ4 on the SPI bus.
+
+   Connect the chip with MISO on pin 4, MOSI on pin 5, SCLK on pin 3 and CS on pin 2. The ID,
+   the bad block table, erasing, programming and reading back a page of the first good block,
+   and the rejection of out of bounds pages are checked. The bad block table is loaded again
+   by a second driver, which has to find the same bad blocks. The results are printed over
+   UART0, followed by "ok" or "FAILED".
+
+   The first good block is erased, don't run this on a chip with data to keep.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    pac,
+    prelude::*,
+    serial::*,
+    spi::{
+        nand::{Geometry, NandError, SpiNand},
+        Spi,
+    },
+};
+use panic_halt as _;
+
+fn out_of_bounds<E>(result: Result<(), NandError<E>>) -> bool {
+    matches!(result, Err(NandError::OutOfBounds))
+}
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .spi_clk(40_000_000u32.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let miso = parts.pin4.into_spi_miso();
+    let mosi = parts.pin5.into_spi_mosi();
+    let sclk = parts.pin3.into_spi_sclk();
+    let spi = Spi::new(
+        dp.SPI,
+        (miso, mosi, sclk),
+        embedded_hal::spi::MODE_0,
+        10_000_000u32.Hz(),
+        clocks,
+    );
+    let cs = parts.pin2.into_pull_up_output();
+
+    let mut failed = false;
+
+    let mut check = |serial: &mut dyn Write, name: &str, ok: bool| {
+        writeln!(serial, "{}: {}\r", name, if ok { "ok" } else { "FAILED" }).ok();
+        failed |= !ok;
+    };
+
+    let mut nand = match SpiNand::new(spi, cs, Geometry::GD5F1GQ4, &clocks) {
+        Ok(nand) => nand,
+        Err(error) => {
+            writeln!(serial, "SpiNand::new: {:?}\r\nFAILED\r", error).ok();
+            loop {}
+        }
+    };
+
+    let id = nand.read_id();
+    writeln!(serial, "id: {:x?}\r", id).ok();
+    check(&mut serial, "id", matches!(id, Ok([0xc8, _])));
+
+    let bad = (0..nand.blocks())
+        .filter(|&block| nand.is_bad(block))
+        .count();
+    writeln!(serial, "bad blocks: {}\r", bad).ok();
+    check(&mut serial, "bad blocks", bad < 20);
+
+    let block = (0..nand.blocks())
+        .find(|&block| !nand.is_bad(block))
+        .unwrap();
+    let mut data = [0; 2048];
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte = (i * 7) as u8;
+    }
+    let mut buffer = [0; 2048];
+    let erased = nand.erase_block(block).is_ok()
+        && nand.read_page(block, 1, &mut buffer).is_ok()
+        && buffer.iter().all(|&byte| byte == 0xff);
+    check(&mut serial, "erase", erased);
+    let programmed = nand.program_page(block, 1, &data).is_ok()
+        && nand.read_page(block, 1, &mut buffer).is_ok()
+        && buffer == data;
+    check(&mut serial, "program", programmed);
+
+    let last = nand.blocks();
+    check(
+        &mut serial,
+        "rejected",
+        out_of_bounds(nand.read_page(last, 0, &mut buffer))
+            && out_of_bounds(nand.erase_block(last))
+            && out_of_bounds(nand.read_page(block, 64, &mut buffer))
+            && out_of_bounds(nand.program_page(block, 2, &[0; 2048 + 129]))
+            && out_of_bounds(nand.mark_bad(last)),
+    );
+
+    // A second driver loads the table the first wrote, with the same bad blocks
+    let (spi, cs) = nand.free();
+    let reloaded = SpiNand::new(spi, cs, Geometry::GD5F1GQ4, &clocks);
+    check(
+        &mut serial,
+        "table",
+        reloaded.map_or(false, |nand| {
+            (0..nand.blocks())
+                .filter(|&block| nand.is_bad(block))
+                .count()
+                == bad
+        }),
+    );
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
@@ -0,0 +1,148 @@
+/*
+
+   Plays a 500 Hz tone on an SPI DAC, one second on and one second off.
+
+   Connect a 16 bit DAC which latches on the rising edge of chip select, e.g. an AD5541A, with
+   its clock on pin 3, its data input on pin 5 and its chip select on pin 2; pin 4 is claimed as
+   MISO but not used. An amplifier or headphones through a capacitor on the DAC's output play
+   the tone.
+
+   First the sample rate reached, the time one second of samples takes to play, measured with
+   the cycle counter, and the underrun detection, with a write held back for two buffers, are
+   checked. The results are printed over UART0, followed by "ok" or "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_hal::delay::blocking::DelayUs;
+use hal::{
+    audio::{self, PcmBuffer, SpiPcm},
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    pac,
+    prelude::*,
+    serial::*,
+    spi::Spi,
+};
+use panic_halt as _;
+
+const SAMPLE_RATE: u32 = 16_000;
+
+/// A period of 500 Hz at 16 kHz
+const SINE: [i16; 32] = [
+    0, 5853, 11481, 16667, 21213, 24944, 27716, 29424, 30000, 29424, 27716, 24944, 21213, 16667,
+    11481, 5853, 0, -5853, -11481, -16667, -21213, -24944, -27716, -29424, -30000, -29424, -27716,
+    -24944, -21213, -16667, -11481, -5853,
+];
+
+static mut BUFFER: PcmBuffer = PcmBuffer::new();
+/// Two buffers of 256 frames
+static mut FRAMES: [u32; 512] = [0; 512];
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .spi_clk(40_000_000u32.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+    let mut delay = McycleDelay::new(clocks.sysclk().0);
+
+    let miso = parts.pin4.into_spi_miso();
+    let mosi = parts.pin5.into_spi_mosi();
+    let ss = parts.pin2.into_spi_ss();
+    let sclk = parts.pin3.into_spi_sclk();
+    let spi = Spi::new(
+        dp.SPI,
+        (miso, mosi, ss, sclk),
+        embedded_hal::spi::MODE_0,
+        1_000_000u32.Hz(),
+        clocks,
+    );
+
+    let mut pcm = match SpiPcm::new(
+        spi,
+        dp.DMA,
+        0,
+        unsafe { &mut BUFFER },
+        unsafe { &mut FRAMES },
+        SAMPLE_RATE.Hz(),
+        &clocks,
+    ) {
+        Ok(pcm) => pcm,
+        Err(error) => {
+            writeln!(serial, "SpiPcm::new: {:?}\r\nFAILED\r", error).ok();
+            loop {}
+        }
+    };
+
+    let mut failed = false;
+
+    let mut check = |serial: &mut dyn Write, name: &str, ok: bool| {
+        writeln!(serial, "{}: {}\r", name, if ok { "ok" } else { "FAILED" }).ok();
+        failed |= !ok;
+    };
+
+    // 40 MHz / 16 kHz is 2500 cycles, 73 half periods of 34 and an interval of 18
+    check(
+        &mut serial,
+        "sample rate",
+        pcm.sample_rate().0 == SAMPLE_RATE,
+    );
+
+    // A second of tone, 500 periods, ends half way into a buffer: flush pads it and appends a
+    // buffer of silence, 24 ms more
+    let start = McycleDelay::get_cycle_count();
+    let mut played = Ok(());
+    for _ in 0..500 {
+        played = played.and_then(|()| pcm.write_samples(&SINE));
+    }
+    played = played.and_then(|()| pcm.flush());
+    let us = McycleDelay::cycles_since(start) / (clocks.sysclk().0 as u64 / 1_000_000);
+    writeln!(serial, "1 s of samples: {:?} in {} us\r", played, us).ok();
+    check(
+        &mut serial,
+        "timing",
+        played.is_ok() && (1_024_000..1_026_000).contains(&us),
+    );
+
+    // Two buffers are 32 ms, waiting longer lets the channel run out
+    let late = pcm.write_samples(&[0; 512]).and_then(|()| {
+        delay.delay_ms(50).ok();
+        pcm.write_samples(&SINE)
+    });
+    check(
+        &mut serial,
+        "underrun",
+        late == Err(audio::Error::Underrun) && pcm.flush().is_ok(),
+    );
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {
+        for _ in 0..500 {
+            pcm.write_samples(&SINE).ok();
+        }
+        pcm.flush().ok();
+        delay.delay_ms(1000).ok();
+    }
+}
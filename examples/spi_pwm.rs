@@ -0,0 +1,56 @@
+/*
+
+   Fades an LED on pin 5 in and out with PWM sent over SPI.
+
+   The SPI master shifts a 1024 bit pattern out of MOSI at 20 MHz, so the PWM runs at 19.5 kHz
+   with 10 bit resolution. Each step of the fade sends the pattern 200 times, about 10 ms, with
+   DMA channel 0 moving the data. A scope on pin 5 shows the pulse width following the duty
+   cycle without gaps between the patterns.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    pac,
+    prelude::*,
+    spi::{Spi, SpiPwm},
+};
+use panic_halt as _;
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .spi_clk(40_000_000u32.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let miso = parts.pin4.into_spi_miso();
+    let mosi = parts.pin5.into_spi_mosi();
+    let sclk = parts.pin3.into_spi_sclk();
+
+    let spi = Spi::new(
+        dp.SPI,
+        (miso, mosi, sclk),
+        embedded_hal::spi::MODE_0,
+        20_000_000u32.Hz(),
+        clocks,
+    );
+    // 32 words of 32 bits, 1024 steps
+    let mut pattern = [0; 32];
+    let mut pwm = SpiPwm::new(spi, dp.DMA, 0, &mut pattern);
+
+    loop {
+        for duty in (0..=1024).step_by(8).chain((0..1024).step_by(8).rev()) {
+            pwm.set_duty(duty);
+            pwm.run(200).unwrap();
+        }
+    }
+}
@@ -0,0 +1,139 @@
+/*
+
+   Checks one-shot callbacks on timer channel 0.
+
+   A one-shot of 100 ms is started and the time until the callback runs is measured with
+   `mcycle`. A second one-shot while the first is pending has to fail with `TimerBusy`, and a
+   cancelled one-shot must not run at all. The results are printed over UART0, followed by "ok"
+   or "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, Ordering};
+use embedded_hal::delay::blocking::DelayUs;
+use embedded_time::{duration::*, rate::*};
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    interrupts::*,
+    pac,
+    prelude::*,
+    serial::*,
+    timer::*,
+};
+use panic_halt as _;
+use riscv::register::mcycle;
+
+const SYSCLK_HZ: u32 = 160_000_000;
+
+static FIRED: AtomicU32 = AtomicU32::new(0);
+static FIRED_AT: AtomicU32 = AtomicU32::new(0);
+
+fn on_timeout() {
+    FIRED_AT.store(mcycle::read() as u32, Ordering::SeqCst);
+    FIRED.fetch_add(1, Ordering::SeqCst);
+}
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let timers = dp.TIMER.split();
+    let timer = timers
+        .channel0
+        .set_clock_source(ClockSource::Fclk(&clocks), 1_000_000_u32.Hz());
+    enable_interrupt(Interrupt::TimerCh0);
+
+    let mut delay = McycleDelay::new(clocks.sysclk().0);
+    let mut failed = false;
+
+    // Fires once after about 100 ms
+    let start = mcycle::read() as u32;
+    let token = timer
+        .one_shot_after(100_u32.milliseconds(), on_timeout)
+        .unwrap();
+    let busy = timer.one_shot_after(10_u32.milliseconds(), on_timeout);
+    delay.delay_ms(200).ok();
+
+    let fired = FIRED.load(Ordering::SeqCst);
+    let elapsed_ms = FIRED_AT.load(Ordering::SeqCst).wrapping_sub(start) / (SYSCLK_HZ / 1000);
+    let ok = fired == 1 && (99..=101).contains(&elapsed_ms) && !token.is_pending();
+    writeln!(
+        serial,
+        "one-shot: fired {} times after {} ms, {}\r",
+        fired,
+        elapsed_ms,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    let ok = busy.err() == Some(OneShotError::TimerBusy);
+    writeln!(
+        serial,
+        "second one-shot while pending: {}\r",
+        if ok { "busy, ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    // Cancelled before it fires, the channel is free again afterwards
+    let token = timer
+        .one_shot_after(50_u32.milliseconds(), on_timeout)
+        .unwrap();
+    let cancelled = token.cancel();
+    delay.delay_ms(100).ok();
+    let ok = cancelled && FIRED.load(Ordering::SeqCst) == 1;
+    writeln!(
+        serial,
+        "cancelled one-shot: {}\r",
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    let again = timer.one_shot_after(10_u32.milliseconds(), on_timeout);
+    delay.delay_ms(20).ok();
+    let ok = again.is_ok() && FIRED.load(Ordering::SeqCst) == 2;
+    writeln!(
+        serial,
+        "one-shot after cancel: {}\r",
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
+
+#[allow(non_snake_case)]
+#[no_mangle]
+fn TimerCh0(_trap_frame: &mut TrapFrame) {
+    clear_interrupt(Interrupt::TimerCh0);
+    OneShotToken::<ConfiguredTimerChannel0>::on_interrupt();
+}
@@ -0,0 +1,96 @@
+/*
+
+   Reads a touch pad on GPIO3, a piece of copper or just a wire, with the internal pull-up.
+
+   GPIO3 has to give readings and be calibrated, and GPIO4, a pull-down input which never
+   charges, has to time out. The results are printed over UART0, followed by "ok" or "FAILED",
+   and then the state of the pad on every change.
+
+   The detector, its hysteresis, the tracking of drift and the calibration are checked by the
+   tests of `touch` on the PC.
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use embedded_hal::delay::blocking::DelayUs;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    pac,
+    prelude::*,
+    serial::*,
+    touch::{self, TouchPad},
+};
+use panic_halt as _;
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+    let mut delay = McycleDelay::new(clocks.sysclk().0);
+
+    let mut failed = false;
+
+    let mut check = |serial: &mut dyn Write, name: &str, ok: bool| {
+        writeln!(serial, "{}: {}\r", name, if ok { "ok" } else { "FAILED" }).ok();
+        failed |= !ok;
+    };
+
+    let pin = parts.pin3.into_pull_up_input().erase();
+    let mut pad = TouchPad::new(pin, touch::Config::default(), &clocks);
+    let reading = pad.measure();
+    let calibration = pad.calibrate(32);
+    writeln!(
+        serial,
+        "reading: {:?} cycles, calibration: {:?}\r",
+        reading, calibration
+    )
+    .ok();
+    check(
+        &mut serial,
+        "pad",
+        matches!(reading, Ok(cycles) if cycles > 0) && calibration.is_ok(),
+    );
+
+    let pin = parts.pin4.into_pull_down_input().erase();
+    let mut grounded = TouchPad::new(pin, touch::Config::default(), &clocks);
+    check(
+        &mut serial,
+        "timeout",
+        grounded.measure() == Err(touch::Error::Timeout),
+    );
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    let mut touched = false;
+    loop {
+        if let Ok(now) = pad.update() {
+            if now != touched {
+                touched = now;
+                writeln!(serial, "{}\r", if touched { "touched" } else { "released" }).ok();
+            }
+        }
+        delay.delay_ms(20).ok();
+    }
+}
@@ -0,0 +1,123 @@
+/*
+
+   Lets the RX FIFO of UART0 overflow and checks how the overrun is reported.
+
+   Paste a line of more than 32 characters into the terminal when asked, twice. The first time
+   nothing is read until the overrun interrupt has fired: `read` then has to return the 32 bytes
+   which were in the FIFO, followed by `Error::Overrun`, and nothing else is pending after
+   `recover_from_error`. The second time the overrun is polled with `check_overrun`, which has to
+   report it once. The results are printed over UART0, followed by "ok" or "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, Ordering};
+use embedded_hal::delay::blocking::DelayUs;
+use embedded_hal::serial::nb::Read;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    interrupts::{self, Interrupt, TrapFrame},
+    pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+static OVERRUNS: AtomicU32 = AtomicU32::new(0);
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut delay = McycleDelay::new(clocks.sysclk().0);
+    let mut failed = false;
+
+    writeln!(serial, "paste more than 32 characters\r").ok();
+    serial.enable_overrun_interrupt();
+    while OVERRUNS.load(Ordering::Relaxed) == 0 {}
+    // Let the rest of the line arrive
+    delay.delay_ms(100).ok();
+
+    let mut received = 0;
+    let error = loop {
+        match serial.read() {
+            Ok(_) => received += 1,
+            Err(nb::Error::Other(error)) => break Some(error),
+            Err(nb::Error::WouldBlock) => break None,
+        }
+    };
+    let recovered = serial.recover_from_error().is_ok();
+    let pending = serial.read();
+
+    let ok = received == 32
+        && matches!(error, Some(Error::Overrun { bytes_lost }) if bytes_lost >= 1)
+        && recovered
+        && pending == Err(nb::Error::WouldBlock)
+        && !serial.check_overrun()
+        && serial.error_count() == 1
+        && OVERRUNS.load(Ordering::Relaxed) == 1;
+    writeln!(
+        serial,
+        "read {} bytes, then {:?}: {}\r",
+        received,
+        error,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    writeln!(serial, "paste more than 32 characters again\r").ok();
+    let mut polls = 0u32;
+    while !serial.check_overrun() {
+        polls += 1;
+        delay.delay_ms(1).ok();
+    }
+    delay.delay_ms(100).ok();
+    // The rest of the line may have arrived after the FIFO was cleared, drop it
+    while serial.read().is_ok() {}
+
+    let ok = !serial.check_overrun() && serial.error_count() == 2;
+    writeln!(
+        serial,
+        "check_overrun after {} ms: {}\r",
+        polls,
+        if ok { "ok" } else { "FAILED" }
+    )
+    .ok();
+    failed |= !ok;
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
+
+#[allow(non_snake_case)]
+#[no_mangle]
+fn Uart0(_trap_frame: &mut TrapFrame) {
+    OVERRUNS.fetch_add(1, Ordering::Relaxed);
+    // The flag stays set until main clears it, stop the interrupt from firing again
+    interrupts::disable(Interrupt::Uart0);
+}
@@ -0,0 +1,101 @@
+/*
+
+   Prints how long each wake up takes, so changes to the clock set up code which slow down the
+   resume path show up right away.
+
+   The example sleeps ten times for 100 ms in each power-down sleep level and prints the stats
+   after every wake up, then hibernates for a second. After the wake up from hibernate, which
+   restarts the program, the time from the RTC wake until `main` is printed before starting
+   over.
+
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write;
+use core::time::Duration;
+use embedded_hal::serial::nb::Write as _;
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    hbn::{self, Hbn, HbnLevel, WakeSources, WakeTrigger},
+    pac,
+    pds::{self, PdsConfig, PdsLevel},
+    power,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    // Records the stats of a wake up from hibernate
+    let cause = hbn::wakeup_cause();
+
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    writeln!(serial, "started after {:?}\r", cause).ok();
+    if let Some(stats) = power::last_wake_stats() {
+        writeln!(serial, "{:?}\r", stats).ok();
+    }
+
+    for &level in [PdsLevel::Level0, PdsLevel::Level1, PdsLevel::Level2].iter() {
+        for _ in 0..10 {
+            // The UART stops while sleeping, so get the output out first
+            nb::block!(serial.flush()).ok();
+
+            pds::enter(
+                level,
+                Some(Duration::from_millis(100)),
+                PdsConfig::new(clocks),
+            )
+            .unwrap();
+
+            match power::last_wake_stats() {
+                Some(stats) => writeln!(
+                    serial,
+                    "{:?}: resume {:?}, PLL lock {:?}\r",
+                    level, stats.time_to_resume, stats.time_to_pll_lock
+                ),
+                None => writeln!(serial, "{:?}: no stats recorded\r", level),
+            }
+            .ok();
+        }
+    }
+
+    nb::block!(serial.flush()).ok();
+
+    let mut hbn = Hbn::new(dp.HBN);
+    let error = hbn.enter(
+        HbnLevel::Level0,
+        WakeSources {
+            rtc_after: Some(Duration::from_secs(1)),
+            gpio: &[],
+            gpio_trigger: WakeTrigger::FallingEdge,
+            acomp: false,
+        },
+    );
+    writeln!(serial, "hibernate failed: {:?}\r", error).ok();
+
+    loop {}
+}
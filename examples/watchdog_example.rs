@@ -24,7 +24,7 @@ use bl602_hal as hal;
 use core::cell::RefCell;
 use core::fmt::Write;
 use core::ops::DerefMut;
-use embedded_hal::delay::blocking::DelayMs;
+use embedded_hal::delay::blocking::DelayUs;
 use embedded_hal::digital::blocking::{OutputPin, ToggleableOutputPin};
 use embedded_hal::watchdog::blocking::{Enable, Watchdog};
 use embedded_time::{duration::*, rate::*};
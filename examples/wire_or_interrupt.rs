@@ -0,0 +1,218 @@
+/*
+
+   Identifies the sources of a wire-ORed interrupt line among simulated I2C devices.
+
+   Connect GPIO5 to GPIO3. GPIO5 plays the shared line, driven low while any of the simulated
+   devices has an interrupt flag set, and GPIO3 is the interrupt pin, triggering on the falling
+   edge. The devices are an I2C bus simulated in software: 0x18 clears its flags when they're
+   read, 0x29 when they're written back, and 0x40 isn't registered.
+
+   The sources have to be found and acknowledged with the line released and the pending bit
+   cleared, a flag which 0x18 raises while 0x29 is being read has to be found by a second
+   pass, as there's no new edge for it, and a line held by 0x40 has to be reported as stuck.
+   The results are printed over UART0, followed by "ok" or "FAILED".
+*/
+
+#![no_std]
+#![no_main]
+
+use bl602_hal as hal;
+use core::fmt::Write as _;
+use embedded_hal::delay::blocking::DelayUs;
+use embedded_hal::digital::blocking::OutputPin;
+use embedded_hal::i2c::blocking::{Read, Write};
+use embedded_hal::i2c::{ErrorKind, SevenBitAddress};
+use hal::{
+    clock::{Strict, SysclkFreq, UART_PLL_FREQ},
+    delay::McycleDelay,
+    gpio::{
+        wire_or::{Acknowledge, Error, Source, WireOrInterrupt},
+        Event, InterruptPin,
+    },
+    pac,
+    prelude::*,
+    serial::*,
+};
+use panic_halt as _;
+
+const ACCEL: u8 = 0x18;
+const LIGHT: u8 = 0x29;
+const UNREGISTERED: u8 = 0x40;
+
+/// I2C devices with an interrupt status register each, which drive the shared line
+struct SimulatedBus<LINE> {
+    /// Status of 0x18, 0x29 and 0x40
+    status: [u8; 3],
+    /// 0x18 raises this flag while 0x29 is read
+    raise_while_reading: u8,
+    line: LINE,
+    delay: McycleDelay,
+}
+
+impl<LINE: OutputPin> SimulatedBus<LINE> {
+    fn device(address: SevenBitAddress) -> usize {
+        match address {
+            ACCEL => 0,
+            LIGHT => 1,
+            _ => 2,
+        }
+    }
+
+    fn set(&mut self, address: SevenBitAddress, flags: u8) {
+        self.status[Self::device(address)] |= flags;
+        self.update_line();
+    }
+
+    fn update_line(&mut self) {
+        if self.status.iter().any(|&status| status != 0) {
+            self.line.set_low().ok();
+        } else {
+            self.line.set_high().ok();
+        }
+        self.delay.delay_us(10).ok();
+    }
+}
+
+impl<LINE: OutputPin> Write for SimulatedBus<LINE> {
+    type Error = ErrorKind;
+
+    fn write(&mut self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), ErrorKind> {
+        // bytes[0] is the status register, the only one there is
+        if let Some(&flags) = bytes.get(1) {
+            self.status[Self::device(address)] &= !flags;
+            self.update_line();
+        }
+        Ok(())
+    }
+}
+
+impl<LINE: OutputPin> Read for SimulatedBus<LINE> {
+    type Error = ErrorKind;
+
+    fn read(&mut self, address: SevenBitAddress, buffer: &mut [u8]) -> Result<(), ErrorKind> {
+        let device = Self::device(address);
+        buffer[0] = self.status[device];
+        if address == ACCEL {
+            self.status[device] = 0;
+        }
+        if address == LIGHT && self.raise_while_reading != 0 {
+            self.status[0] |= self.raise_while_reading;
+            self.raise_while_reading = 0;
+        }
+        self.update_line();
+        Ok(())
+    }
+}
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+
+    let clocks = Strict::new()
+        .use_pll(40_000_000u32.Hz())
+        .sys_clk(SysclkFreq::Pll160Mhz)
+        .uart_clk(UART_PLL_FREQ.Hz())
+        .freeze(&mut parts.clk_cfg);
+
+    let pin16 = parts.pin16.into_uart_sig0();
+    let pin7 = parts.pin7.into_uart_sig7();
+    let mux0 = parts.uart_mux0.into_uart0_tx();
+    let mux7 = parts.uart_mux7.into_uart0_rx();
+
+    let mut serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(2_000_000.Bd()),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+
+    let mut line = parts.pin5.into_pull_up_output();
+    line.set_high().ok();
+    let mut bus = SimulatedBus {
+        status: [0; 3],
+        raise_while_reading: 0,
+        line,
+        delay: McycleDelay::new(clocks.sysclk().0),
+    };
+
+    let mut pin = parts.pin3.into_pull_up_input();
+    pin.enable_smitter();
+    pin.trigger_on_event(Event::NegativePulse);
+    pin.enable_interrupt();
+    pin.clear_interrupt_pending_bit();
+
+    let mut interrupt = WireOrInterrupt::new(pin);
+    interrupt
+        .register(Source::new(ACCEL, 0x31, 0x40, Acknowledge::OnRead))
+        .unwrap();
+    interrupt
+        .register(Source::new(LIGHT, 0x13, 0x01, Acknowledge::WriteBack))
+        .unwrap();
+
+    let mut failed = false;
+
+    let mut check = |serial: &mut dyn core::fmt::Write, name: &str, ok: bool| {
+        writeln!(serial, "{}: {}\r", name, if ok { "ok" } else { "FAILED" }).ok();
+        failed |= !ok;
+    };
+
+    bus.set(ACCEL, 0x40);
+    bus.set(LIGHT, 0x01);
+    let fired = interrupt.is_pending();
+    let sources = interrupt.identify_sources(&mut bus);
+    writeln!(serial, "both: {:x?}\r", sources).ok();
+    check(
+        &mut serial,
+        "both",
+        fired
+            && sources.map_or(false, |sources| *sources == [ACCEL, LIGHT])
+            && bus.status == [0; 3]
+            && !interrupt.is_pending(),
+    );
+
+    bus.raise_while_reading = 0x40;
+    bus.set(LIGHT, 0x01);
+    let sources = interrupt.identify_sources(&mut bus);
+    writeln!(serial, "raised meanwhile: {:x?}\r", sources).ok();
+    check(
+        &mut serial,
+        "raised meanwhile",
+        sources.map_or(false, |sources| *sources == [LIGHT, ACCEL])
+            && bus.status == [0; 3]
+            && !interrupt.is_pending(),
+    );
+
+    bus.set(UNREGISTERED, 0x01);
+    let sources = interrupt.identify_sources(&mut bus);
+    check(&mut serial, "stuck", sources == Err(Error::LineStuck));
+    interrupt
+        .register(Source::new(
+            UNREGISTERED,
+            0x00,
+            0xff,
+            Acknowledge::WriteBack,
+        ))
+        .unwrap();
+    let sources = interrupt.identify_sources(&mut bus);
+    check(
+        &mut serial,
+        "registered later",
+        sources.map_or(false, |sources| *sources == [UNREGISTERED]) && bus.status == [0; 3],
+    );
+
+    let full = (0x50..0x58)
+        .map(|address| interrupt.register(Source::new(address, 0, 1, Acknowledge::OnRead)))
+        .filter(|result| *result == Err(Error::Full))
+        .count();
+    let removed = interrupt.unregister(0x50).is_some() && interrupt.unregister(0x50).is_none();
+    check(
+        &mut serial,
+        "full",
+        full == 3 && removed && interrupt.sources().count() == 7,
+    );
+
+    writeln!(serial, "{}\r", if failed { "FAILED" } else { "ok" }).ok();
+
+    loop {}
+}
@@ -1,4 +1,44 @@
-PROVIDE(Gpio = DefaultHandler);
+PROVIDE(BmxErr = DefaultHandler);
+PROVIDE(BmxTimeout = DefaultHandler);
+PROVIDE(L1cBmxErr = DefaultHandler);
+PROVIDE(L1cBmxTimeout = DefaultHandler);
+PROVIDE(SecBmxErr = DefaultHandler);
+PROVIDE(RfTop0 = DefaultHandler);
+PROVIDE(RfTop1 = DefaultHandler);
+PROVIDE(Sdio = DefaultHandler);
+PROVIDE(DmaBmxErr = DefaultHandler);
+PROVIDE(SecGmac = DefaultHandler);
+PROVIDE(SecCdet = DefaultHandler);
+PROVIDE(SecPka = DefaultHandler);
+PROVIDE(SecTrng = DefaultHandler);
+PROVIDE(SecAes = DefaultHandler);
+PROVIDE(SecSha = DefaultHandler);
+PROVIDE(Dma = DefaultHandler);
+PROVIDE(IrTx = DefaultHandler);
+PROVIDE(IrRx = DefaultHandler);
+PROVIDE(SfCtrl = DefaultHandler);
+PROVIDE(GpadcDma = DefaultHandler);
+PROVIDE(Efuse = DefaultHandler);
+PROVIDE(Spi = DefaultHandler);
+PROVIDE(Uart0 = DefaultHandler);
+PROVIDE(Uart1 = DefaultHandler);
+PROVIDE(I2c = DefaultHandler);
+PROVIDE(Pwm = DefaultHandler);
 PROVIDE(TimerCh0 = DefaultHandler);
 PROVIDE(TimerCh1 = DefaultHandler);
 PROVIDE(Watchdog = DefaultHandler);
+PROVIDE(Gpio = DefaultHandler);
+PROVIDE(PdsWakeup = DefaultHandler);
+PROVIDE(HbnOut0 = DefaultHandler);
+PROVIDE(HbnOut1 = DefaultHandler);
+PROVIDE(Bor = DefaultHandler);
+PROVIDE(Wifi = DefaultHandler);
+PROVIDE(BzPhy = DefaultHandler);
+PROVIDE(Ble = DefaultHandler);
+PROVIDE(MacTxRxTimer = DefaultHandler);
+PROVIDE(MacTxRxMisc = DefaultHandler);
+PROVIDE(MacRxTrigger = DefaultHandler);
+PROVIDE(MacTxTrigger = DefaultHandler);
+PROVIDE(MacGeneral = DefaultHandler);
+PROVIDE(MacPortTrigger = DefaultHandler);
+PROVIDE(WifiIpc = DefaultHandler);
@@ -0,0 +1,12 @@
+/* Statics declared with `retained!`, kept through hibernate and resets. They refer to
+   `_shbn_ram` and `_ehbn_ram`, so they don't link without this script */
+SECTIONS
+{
+    .hbn_ram (NOLOAD) : ALIGN(4)
+    {
+        _shbn_ram = .;
+        *(.hbn_ram .hbn_ram.*);
+        _ehbn_ram = .;
+    } > HBN_RAM
+}
+INSERT AFTER .bss;
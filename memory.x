@@ -6,6 +6,7 @@ MEMORY
     DTCM      (wxa) : ORIGIN = 0x22014000, LENGTH = 48K
     XIP_FLASH (rwx) : ORIGIN = 0x23000000, LENGTH = 16M
     WIFI_RAM  (wxa) : ORIGIN = 0x42030000, LENGTH = 112K
+    HBN_RAM   (wxa) : ORIGIN = 0x40010000, LENGTH = 4K - 256
 }
 
 REGION_ALIAS("REGION_TEXT", XIP_FLASH);
@@ -0,0 +1,434 @@
+/*!
+  # Analog to Digital Converter
+  The general purpose ADC (GPADC) can convert twelve external channels, which are bound to fixed
+  GPIO pins, as well as a number of internal sources. Its analog part lives in the AON block,
+  while the conversion results are read from the FIFO in the GPIP block.
+
+  The pins of the external channels should be configured with `into_analog` before converting.
+  The results are corrected for the gain error measured in the factory, see
+  [`Adc::gain_trim`].
+
+  | Channel | Pin    | Channel | Pin    |
+  |---------|--------|---------|--------|
+  | `Ch0`   | GPIO12 | `Ch6`   | GPIO7  |
+  | `Ch1`   | GPIO4  | `Ch7`   | GPIO9  |
+  | `Ch2`   | GPIO14 | `Ch8`   | GPIO18 |
+  | `Ch3`   | GPIO13 | `Ch9`   | GPIO10 |
+  | `Ch4`   | GPIO5  | `Ch10`  | GPIO11 |
+  | `Ch5`   | GPIO6  | `Ch11`  | GPIO15 |
+
+  ## Example
+  ```rust
+    use bl602_hal::adc::{Adc, Channel, DiffChannel};
+
+    let _ch1 = parts.pin4.into_analog();
+    let _ch4 = parts.pin5.into_analog();
+    let mut adc = Adc::new(dp.GPIP, &mut parts.clk_cfg);
+
+    let single_ended: u16 = adc.read(Channel::Ch1).unwrap();
+    let bridge: i16 = adc.differential_read(DiffChannel::Ch1Ch4).unwrap();
+  ```
+
+  # Temperature sensor
+  The on-chip sensor is read with [`Adc::read_temperature_code`], which grows linearly with the
+  die temperature. The HAL doesn't read a conversion to degrees from the factory trim, the
+  sensor is calibrated against an external thermometer instead: record the code at two or more
+  known temperatures, as far apart as possible, and fit a line through them with
+  [`Adc::calibrate_temperature`]. The calibration only depends on the chip, store it e.g. in
+  flash and reuse it.
+  ```rust
+    use bl602_hal::adc::Adc;
+
+    // (reference temperature in °C, code read at that temperature)
+    let cal = Adc::calibrate_temperature(&[(-10.0, 1923), (25.0, 2201), (70.0, 2556)]).unwrap();
+
+    let celsius: f32 = adc.read_temperature_calibrated(&cal).unwrap();
+  ```
+*/
+
+use crate::efuse::{self, AdcTrim};
+use crate::gpio::ClkCfg;
+use crate::pac;
+use crate::power::{Domain, DomainGuard};
+
+/// Number of polling iterations to wait for a conversion before giving up
+const CONVERSION_TIMEOUT: u32 = 100_000;
+
+/// Number of conversion pairs averaged for a temperature code
+const TEMPERATURE_SAMPLES: u32 = 8;
+
+/// ADC error
+///
+/// Unlike serial, SPI and I2C, embedded-hal doesn't define error kinds for ADCs, so there is no
+/// `Error` trait to implement here.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum AdcError {
+    /// The conversion did not finish in time
+    Timeout,
+    /// The result in the FIFO belongs to another channel than the one requested
+    ChannelMismatch,
+    /// The calibration points don't span at least two different codes, so no line fits them
+    InvalidCalibration,
+}
+
+/// ADC input channel
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum Channel {
+    /// GPIO12
+    Ch0 = 0,
+    /// GPIO4
+    Ch1 = 1,
+    /// GPIO14
+    Ch2 = 2,
+    /// GPIO13
+    Ch3 = 3,
+    /// GPIO5
+    Ch4 = 4,
+    /// GPIO6
+    Ch5 = 5,
+    /// GPIO7
+    Ch6 = 6,
+    /// GPIO9
+    Ch7 = 7,
+    /// GPIO18
+    Ch8 = 8,
+    /// GPIO10
+    Ch9 = 9,
+    /// GPIO11
+    Ch10 = 10,
+    /// GPIO15
+    Ch11 = 11,
+    /// DAC output A
+    DacOutA = 12,
+    /// DAC output B
+    DacOutB = 13,
+    /// Temperature sensor, positive side
+    TsenP = 14,
+    /// Temperature sensor, negative side
+    TsenN = 15,
+    /// Internal voltage reference
+    Vref = 16,
+    /// Half of the battery voltage
+    VbatHalf = 18,
+    /// Ground
+    Gnd = 23,
+}
+
+/// Pairs of channels which can be measured differentially
+///
+/// The first channel of each pair is the positive input. Only the external channels can be used
+/// as the negative input, and the pairs are limited to channels on neighbouring pads so that both
+/// signal traces of a bridge sensor can be routed next to each other. Swapping the inputs of a
+/// pair only negates the result, so each pair is listed once.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DiffChannel {
+    /// GPIO4 (positive) against GPIO5 (negative)
+    Ch1Ch4,
+    /// GPIO5 (positive) against GPIO6 (negative)
+    Ch4Ch5,
+    /// GPIO12 (positive) against GPIO13 (negative)
+    Ch0Ch3,
+    /// GPIO13 (positive) against GPIO14 (negative)
+    Ch3Ch2,
+    /// GPIO14 (positive) against GPIO15 (negative)
+    Ch2Ch11,
+    /// GPIO9 (positive) against GPIO10 (negative)
+    Ch7Ch9,
+    /// GPIO10 (positive) against GPIO11 (negative)
+    Ch9Ch10,
+}
+
+impl DiffChannel {
+    /// Returns the (positive, negative) channels of the pair
+    pub fn channels(&self) -> (Channel, Channel) {
+        match self {
+            DiffChannel::Ch1Ch4 => (Channel::Ch1, Channel::Ch4),
+            DiffChannel::Ch4Ch5 => (Channel::Ch4, Channel::Ch5),
+            DiffChannel::Ch0Ch3 => (Channel::Ch0, Channel::Ch3),
+            DiffChannel::Ch3Ch2 => (Channel::Ch3, Channel::Ch2),
+            DiffChannel::Ch2Ch11 => (Channel::Ch2, Channel::Ch11),
+            DiffChannel::Ch7Ch9 => (Channel::Ch7, Channel::Ch9),
+            DiffChannel::Ch9Ch10 => (Channel::Ch9, Channel::Ch10),
+        }
+    }
+}
+
+/// Linear conversion of temperature sensor codes to °C, see the module documentation
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TemperatureCalibration {
+    /// °C per code
+    pub slope: f32,
+    /// °C at code 0
+    pub offset: f32,
+}
+
+impl TemperatureCalibration {
+    /// Returns the temperature in °C for a sensor code
+    pub fn celsius(&self, code: u16) -> f32 {
+        self.slope * code as f32 + self.offset
+    }
+}
+
+/// General purpose ADC
+pub struct Adc {
+    gpip: pac::GPIP,
+    trim: Option<AdcTrim>,
+    _power: DomainGuard,
+}
+
+impl Adc {
+    /// Powers up the ADC and configures it for 12 bit one-shot conversions
+    pub fn new(gpip: pac::GPIP, _clk_cfg: &mut ClkCfg) -> Self {
+        let glb = unsafe { &*pac::GLB::ptr() };
+        let aon = unsafe { &*pac::AON::ptr() };
+
+        // ADC clock from XCLK (RC32M or crystal), so it's available whether the PLL runs or not
+        glb.gpadc_32m_src_ctrl.modify(|_, w| unsafe {
+            w.gpadc_32m_clk_sel()
+                .clear_bit()
+                .gpadc_32m_clk_div()
+                .bits(0)
+                .gpadc_32m_div_en()
+                .set_bit()
+        });
+
+        // Enable the ADC, then reset it
+        let power = Domain::Adc.power_up();
+        aon.gpadc_reg_cmd
+            .modify(|_, w| w.gpadc_soft_rst().set_bit());
+        aon.gpadc_reg_cmd
+            .modify(|_, w| w.gpadc_soft_rst().clear_bit());
+
+        aon.gpadc_reg_config1.modify(|_, w| unsafe {
+            w.gpadc_clk_div_ratio()
+                .bits(7) // XCLK / 32
+                .gpadc_res_sel()
+                .bits(0) // 12 bit
+                .gpadc_cont_conv_en()
+                .clear_bit() // one-shot
+                .gpadc_scan_en()
+                .clear_bit()
+        });
+
+        aon.gpadc_reg_config2.modify(|_, w| unsafe {
+            w.gpadc_diff_mode()
+                .clear_bit()
+                .gpadc_vref_sel()
+                .clear_bit() // 3.2V
+                .gpadc_pga_en()
+                .clear_bit()
+                .gpadc_dly_sel()
+                .bits(0)
+        });
+
+        gpip.gpadc_config
+            .modify(|_, w| w.gpadc_fifo_clr().set_bit().gpadc_dma_en().clear_bit());
+
+        Adc {
+            gpip,
+            trim: efuse::adc_trim(),
+            _power: power,
+        }
+    }
+
+    /// Performs a single-ended conversion of `channel` against ground.
+    ///
+    /// Returns the 12 bit conversion result.
+    pub fn read(&mut self, channel: Channel) -> Result<u16, AdcError> {
+        let aon = unsafe { &*pac::AON::ptr() };
+
+        aon.gpadc_reg_config2
+            .modify(|_, w| w.gpadc_diff_mode().clear_bit());
+        aon.gpadc_reg_cmd.modify(|_, w| unsafe {
+            w.gpadc_pos_sel()
+                .bits(channel as u8)
+                .gpadc_neg_sel()
+                .bits(Channel::Gnd as u8)
+                .gpadc_neg_gnd()
+                .set_bit()
+        });
+
+        let raw = self.convert(channel, Channel::Gnd)?;
+
+        Ok(self.correct(i32::from(raw >> 4)).max(0).min(0xfff) as u16)
+    }
+
+    /// Performs a differential conversion between the two channels of `pair`.
+    ///
+    /// Returns the signed 12 bit conversion result in two's complement: 0 means both inputs are
+    /// equal, a positive value means the positive input is higher.
+    pub fn differential_read(&mut self, pair: DiffChannel) -> Result<i16, AdcError> {
+        let aon = unsafe { &*pac::AON::ptr() };
+        let (positive, negative) = pair.channels();
+
+        aon.gpadc_reg_config2
+            .modify(|_, w| w.gpadc_diff_mode().set_bit());
+        aon.gpadc_reg_cmd.modify(|_, w| unsafe {
+            w.gpadc_pos_sel()
+                .bits(positive as u8)
+                .gpadc_neg_sel()
+                .bits(negative as u8)
+                .gpadc_neg_gnd()
+                .clear_bit()
+        });
+
+        let raw = self.convert(positive, negative)?;
+
+        // The result is left aligned in 16 bits, the arithmetic shift keeps the sign
+        let value = self.correct(i32::from((raw as i16) >> 4));
+        Ok(value.max(-0x800).min(0x7ff) as i16)
+    }
+
+    /// Returns the gain correction applied to conversion results, by default the factory trim
+    /// from [`efuse::adc_trim`]
+    pub fn gain_trim(&self) -> Option<AdcTrim> {
+        self.trim
+    }
+
+    /// Replaces the gain correction, `None` returns the results as converted
+    pub fn set_gain_trim(&mut self, trim: Option<AdcTrim>) {
+        self.trim = trim;
+    }
+
+    /// Measures the on-chip temperature sensor.
+    ///
+    /// Returns the difference between the sensor voltage at high and at low bias current, in
+    /// conversion steps and averaged over a few conversions. It's proportional to the absolute
+    /// temperature, but the exact relation varies between chips, see
+    /// [`calibrate_temperature`](Adc::calibrate_temperature).
+    pub fn read_temperature_code(&mut self) -> Result<u16, AdcError> {
+        let aon = unsafe { &*pac::AON::ptr() };
+
+        // gpadc_ts_en
+        aon.gpadc_reg_config2
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << 6)) });
+
+        let mut sum = Ok(0);
+        for _ in 0..TEMPERATURE_SAMPLES {
+            sum = sum.and_then(|sum| {
+                set_tsvbe_low(false);
+                let high = self.read(Channel::TsenP)?;
+                set_tsvbe_low(true);
+                let low = self.read(Channel::TsenP)?;
+
+                Ok(sum + high.saturating_sub(low) as u32)
+            });
+        }
+
+        set_tsvbe_low(false);
+        aon.gpadc_reg_config2
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 6)) });
+
+        Ok((sum? / TEMPERATURE_SAMPLES) as u16)
+    }
+
+    /// Fits a line through `(reference temperature in °C, code)` pairs by least squares.
+    ///
+    /// The codes are taken from [`read_temperature_code`](Adc::read_temperature_code) while the
+    /// chip is at the reference temperature. At least two different codes are needed, more points
+    /// average out the noise of the sensor and of the reference thermometer.
+    pub fn calibrate_temperature(
+        reference_temps: &[(f32, u16)],
+    ) -> Result<TemperatureCalibration, AdcError> {
+        if reference_temps.len() < 2 {
+            return Err(AdcError::InvalidCalibration);
+        }
+
+        // Work relative to the means, the sums of squares of raw codes lose too much precision
+        // in f32
+        let n = reference_temps.len() as f32;
+        let (sum_code, sum_temp) = reference_temps
+            .iter()
+            .fold((0.0, 0.0), |(codes, temps), &(temp, code)| {
+                (codes + code as f32, temps + temp)
+            });
+        let mean_code = sum_code / n;
+        let mean_temp = sum_temp / n;
+
+        let (sxx, sxy) = reference_temps
+            .iter()
+            .fold((0.0, 0.0), |(sxx, sxy), &(temp, code)| {
+                let dx = code as f32 - mean_code;
+                (sxx + dx * dx, sxy + dx * (temp - mean_temp))
+            });
+        if sxx == 0.0 {
+            return Err(AdcError::InvalidCalibration);
+        }
+
+        let slope = sxy / sxx;
+        Ok(TemperatureCalibration {
+            slope,
+            offset: mean_temp - slope * mean_code,
+        })
+    }
+
+    /// Measures the on-chip temperature sensor and returns the die temperature in °C
+    pub fn read_temperature_calibrated(
+        &mut self,
+        cal: &TemperatureCalibration,
+    ) -> Result<f32, AdcError> {
+        Ok(cal.celsius(self.read_temperature_code()?))
+    }
+
+    /// Releases the GPIP peripheral and powers down the ADC
+    pub fn free(self) -> pac::GPIP {
+        // Dropping the guard powers the ADC down
+        self.gpip
+    }
+
+    /// Starts a conversion with the selected inputs and returns the raw 16 bit result
+    fn correct(&self, value: i32) -> i32 {
+        match self.trim {
+            Some(trim) => trim.correct(value),
+            None => value,
+        }
+    }
+
+    fn convert(&mut self, positive: Channel, negative: Channel) -> Result<u16, AdcError> {
+        let aon = unsafe { &*pac::AON::ptr() };
+
+        self.gpip
+            .gpadc_config
+            .modify(|_, w| w.gpadc_fifo_clr().set_bit());
+
+        // The conversion starts on the rising edge of `conv_start`
+        aon.gpadc_reg_cmd
+            .modify(|_, w| w.gpadc_conv_start().clear_bit());
+        aon.gpadc_reg_cmd
+            .modify(|_, w| w.gpadc_conv_start().set_bit());
+
+        let mut timeout_countdown = CONVERSION_TIMEOUT;
+        while self.gpip.gpadc_config.read().gpadc_fifo_data_count().bits() == 0 {
+            if timeout_countdown == 0 {
+                aon.gpadc_reg_cmd
+                    .modify(|_, w| w.gpadc_conv_start().clear_bit());
+                return Err(AdcError::Timeout);
+            }
+            timeout_countdown -= 1;
+        }
+
+        let word = self.gpip.gpadc_dma_rdata.read().bits();
+        aon.gpadc_reg_cmd
+            .modify(|_, w| w.gpadc_conv_start().clear_bit());
+
+        // [25:21] positive channel, [20:16] negative channel, [15:0] result
+        let word_positive = ((word >> 21) & 0x1f) as u8;
+        let word_negative = ((word >> 16) & 0x1f) as u8;
+        if word_positive != positive as u8 || word_negative != negative as u8 {
+            return Err(AdcError::ChannelMismatch);
+        }
+
+        Ok((word & 0xffff) as u16)
+    }
+}
+
+/// Selects the low bias current of the temperature sensor
+fn set_tsvbe_low(low: bool) {
+    let aon = unsafe { &*pac::AON::ptr() };
+
+    // gpadc_tsvbe_low
+    aon.gpadc_reg_config2
+        .modify(|r, w| unsafe { w.bits((r.bits() & !(1 << 31)) | ((low as u32) << 31)) });
+}
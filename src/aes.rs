@@ -0,0 +1,1193 @@
+/*!
+  # Hardware AES engine
+  The SEC_ENG block contains an AES engine supporting 128, 192 and 256 bit keys in ECB, CBC and
+  CTR mode. It reads the input from memory and writes the result back, so all operations here
+  work in place on the given buffer.
+
+  ## Example
+  ```rust
+    use bl602_hal::aes::{Aes, Gcm};
+    use bl602_hal::sec_eng::SecEngExt;
+
+    let sec_eng = dp.SEC_ENG.split();
+    let mut aes = Aes::new(sec_eng.aes, &mut parts.clk_cfg);
+
+    let key = [0u8; 16];
+    let iv = [0u8; 16];
+    let mut data = *b"sixteen byte msg";
+    aes.encrypt_cbc(&key, &iv, &mut data).unwrap();
+    aes.decrypt_cbc(&key, &iv, &mut data).unwrap();
+
+    // CTR mode takes data of any length and is its own inverse
+    let mut message = *b"hello world";
+    aes.ctr(&key, &iv, &mut message).unwrap();
+
+    // Authenticated encryption
+    let mut gcm = Gcm::new(aes);
+    let nonce = [0u8; 12];
+    let tag = gcm.encrypt_in_place(&key, &nonce, b"header", &mut message).unwrap();
+    gcm.decrypt_in_place(&key, &nonce, b"header", &mut message, &tag).unwrap();
+  ```
+
+  # Key and IV loading
+  The engine expects the key and the IV loaded back to front: the first four bytes of the key go
+  into the highest used key register, and every register holds its four bytes in big endian
+  order. The same applies to the IV registers. The driver takes both as they are written in the
+  standards, so there is no need to reorder them by hand.
+
+  In CTR mode the engine increments the last 32 bits of the IV as a big endian counter, which
+  matches NIST SP 800-38A as long as fewer than 2<sup>32</sup> blocks are processed with one IV.
+
+  # Link mode
+  The `*_linked` operations read from a source buffer and write to a separate destination, with
+  the engine configured through a link configuration in memory. The source can be the memory
+  mapped flash, e.g. to decrypt an image while copying it to RAM; see the [`sha`](crate::sha)
+  module for how the engine sees the flash. Buffers below 256 bytes or not aligned to 4 bytes are
+  copied to the destination and processed in place instead.
+
+  # eFuse keys
+  [`Aes::with_efuse_key`] runs the engine with an AES-128 key burnt into one of the eFuse key
+  slots. The engine fetches the key itself, so the slot can be read-protected and the key never
+  becomes visible to software, not even to the application using it.
+
+  The `cipher` feature implements the [`cipher`](https://crates.io/crates/cipher) 0.3 block
+  cipher traits for `Aes128`, `Aes192` and `Aes256`, so the engine can be plugged into
+  RustCrypto mode and protocol implementations. The `aead` feature does the same with the
+  [`aead`](https://crates.io/crates/aead) 0.4 traits for `Aes128Gcm` and `Aes256Gcm`.
+*/
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use crate::clock::{glb_ahb_slave1_clock_enable, AhbSlave1};
+use crate::efuse::{self, KeySlot};
+use crate::gpio::ClkCfg;
+use crate::pac;
+use crate::sec_eng::Aes0;
+use crate::secure::{ct_eq, Zeroize, Zeroizing};
+
+/// Size of an AES block, in bytes
+pub const BLOCK_SIZE: usize = 16;
+
+/// Number of polling iterations to wait for the engine before giving up
+const AES_TIMEOUT: u32 = 1_000_000;
+
+/// The message length register counts blocks in 16 bits
+const MAX_BLOCKS_PER_TRIGGER: usize = 0xffff;
+
+/// Below this, the data is processed through the registers
+const LINK_MODE_THRESHOLD: usize = 16 * BLOCK_SIZE;
+
+/// AES error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The engine did not finish in time
+    Timeout,
+    /// The key is not 16, 24 or 32 bytes long
+    InvalidKeyLength,
+    /// ECB and CBC data must be a multiple of the block size
+    InvalidDataLength,
+    /// The authentication tag doesn't match the message
+    TagMismatch,
+    /// The GCM operation wasn't started, or additional data was passed after the payload
+    InvalidState,
+    /// The eFuse key slot has not been programmed
+    KeySlotEmpty,
+    /// The eFuse contents have not been loaded, so the key slot can't be used
+    KeySlotUnavailable,
+}
+
+#[derive(Copy, Clone)]
+enum KeySize {
+    Aes128,
+    Aes192,
+    Aes256,
+}
+
+impl KeySize {
+    fn from_key(key: &[u8]) -> Result<Self, Error> {
+        match key.len() {
+            16 => Ok(KeySize::Aes128),
+            24 => Ok(KeySize::Aes192),
+            32 => Ok(KeySize::Aes256),
+            _ => Err(Error::InvalidKeyLength),
+        }
+    }
+
+    fn bits(&self) -> u8 {
+        match self {
+            KeySize::Aes128 => 0,
+            KeySize::Aes256 => 1,
+            KeySize::Aes192 => 2,
+        }
+    }
+}
+
+/// Where the engine takes the key from
+#[derive(Copy, Clone)]
+enum Key<'a> {
+    Software(&'a [u8]),
+    Efuse(KeySlot),
+}
+
+#[derive(Copy, Clone)]
+enum BlockMode {
+    Ecb = 0,
+    Ctr = 1,
+    Cbc = 2,
+}
+
+/// Configuration read by the engine in link mode, laid out as in the vendor SDK
+#[repr(C, align(4))]
+struct LinkConfig {
+    /// [4:3] key size, [5] decrypt, [13:12] block mode, [14] continue with the previous IV,
+    /// [31:16] message length in blocks
+    ctrl: u32,
+    source: u32,
+    destination: u32,
+    /// IV and key in memory byte order, the key starting at `key[0]`
+    iv: [u32; 4],
+    key: [u32; 8],
+}
+
+/// The engine reads and writes words, keep the bounce buffer aligned
+#[repr(align(4))]
+struct Block([u8; BLOCK_SIZE]);
+
+/// AES engine driver
+pub struct Aes {
+    aes: Aes0,
+}
+
+impl Aes {
+    /// Enables the AES engine
+    pub fn new(aes: Aes0, _clk_cfg: &mut ClkCfg) -> Self {
+        glb_ahb_slave1_clock_enable(AhbSlave1::Sec, true);
+
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+        // Data in memory order, the key and IV byte order is handled in `set_key_iv`
+        sec_eng.se_aes_0_endian.modify(|_, w| unsafe {
+            w.se_aes_0_dout_endian()
+                .clear_bit()
+                .se_aes_0_din_endian()
+                .clear_bit()
+                .se_aes_0_key_endian()
+                .clear_bit()
+                .se_aes_0_iv_endian()
+                .clear_bit()
+                .se_aes_0_ctr_len()
+                .bits(0) // 4 byte counter
+        });
+        sec_eng.se_aes_0_ctrl.modify(|_, w| {
+            w.se_aes_0_en()
+                .set_bit()
+                .se_aes_0_hw_key_en()
+                .clear_bit()
+                .se_aes_0_link_mode()
+                .clear_bit()
+                .se_aes_0_int_mask()
+                .set_bit()
+        });
+
+        Aes { aes }
+    }
+
+    /// Encrypts `data` in place in ECB mode
+    ///
+    /// `key` must be 16, 24 or 32 bytes and `data` a multiple of 16 bytes long.
+    pub fn encrypt_ecb(&mut self, key: &[u8], data: &mut [u8]) -> Result<(), Error> {
+        self.run(
+            BlockMode::Ecb,
+            false,
+            Key::Software(key),
+            &[0; BLOCK_SIZE],
+            data,
+        )
+    }
+
+    /// Decrypts `data` in place in ECB mode
+    ///
+    /// `key` must be 16, 24 or 32 bytes and `data` a multiple of 16 bytes long.
+    pub fn decrypt_ecb(&mut self, key: &[u8], data: &mut [u8]) -> Result<(), Error> {
+        self.run(
+            BlockMode::Ecb,
+            true,
+            Key::Software(key),
+            &[0; BLOCK_SIZE],
+            data,
+        )
+    }
+
+    /// Encrypts `data` in place in CBC mode
+    ///
+    /// `key` must be 16, 24 or 32 bytes and `data` a multiple of 16 bytes long.
+    pub fn encrypt_cbc(
+        &mut self,
+        key: &[u8],
+        iv: &[u8; BLOCK_SIZE],
+        data: &mut [u8],
+    ) -> Result<(), Error> {
+        self.run(BlockMode::Cbc, false, Key::Software(key), iv, data)
+    }
+
+    /// Decrypts `data` in place in CBC mode
+    ///
+    /// `key` must be 16, 24 or 32 bytes and `data` a multiple of 16 bytes long.
+    pub fn decrypt_cbc(
+        &mut self,
+        key: &[u8],
+        iv: &[u8; BLOCK_SIZE],
+        data: &mut [u8],
+    ) -> Result<(), Error> {
+        self.run(BlockMode::Cbc, true, Key::Software(key), iv, data)
+    }
+
+    /// Encrypts or decrypts `data` in place in CTR mode, with `iv` as the initial counter block
+    ///
+    /// `key` must be 16, 24 or 32 bytes, `data` can have any length.
+    pub fn ctr(&mut self, key: &[u8], iv: &[u8; BLOCK_SIZE], data: &mut [u8]) -> Result<(), Error> {
+        self.run(BlockMode::Ctr, false, Key::Software(key), iv, data)
+    }
+
+    /// Encrypts `src` into `dst` in CBC mode, using link mode
+    ///
+    /// Both buffers must have the same length, a multiple of 16 bytes.
+    pub fn encrypt_cbc_linked(
+        &mut self,
+        key: &[u8],
+        iv: &[u8; BLOCK_SIZE],
+        src: &[u8],
+        dst: &mut [u8],
+    ) -> Result<(), Error> {
+        self.run_linked(BlockMode::Cbc, false, key, iv, src, dst)
+    }
+
+    /// Decrypts `src` into `dst` in CBC mode, using link mode
+    ///
+    /// Both buffers must have the same length, a multiple of 16 bytes.
+    pub fn decrypt_cbc_linked(
+        &mut self,
+        key: &[u8],
+        iv: &[u8; BLOCK_SIZE],
+        src: &[u8],
+        dst: &mut [u8],
+    ) -> Result<(), Error> {
+        self.run_linked(BlockMode::Cbc, true, key, iv, src, dst)
+    }
+
+    /// Encrypts or decrypts `src` into `dst` in CTR mode, using link mode
+    ///
+    /// Both buffers must have the same length, which can be any.
+    pub fn ctr_linked(
+        &mut self,
+        key: &[u8],
+        iv: &[u8; BLOCK_SIZE],
+        src: &[u8],
+        dst: &mut [u8],
+    ) -> Result<(), Error> {
+        self.run_linked(BlockMode::Ctr, false, key, iv, src, dst)
+    }
+
+    /// Uses the AES-128 key stored in the eFuse key `slot` for the returned operations
+    ///
+    /// Returns [`Error::KeySlotEmpty`] if nothing has been burnt into the slot. Read-protected
+    /// slots can't be checked and are assumed to be programmed, since protecting an empty slot
+    /// would make it useless.
+    pub fn with_efuse_key(&mut self, slot: KeySlot) -> Result<EfuseKeyed<'_>, Error> {
+        match efuse::key_slot_programmed(slot) {
+            Ok(true) | Err(efuse::Error::ReadLocked) => Ok(EfuseKeyed { aes: self, slot }),
+            Ok(false) => Err(Error::KeySlotEmpty),
+            Err(_) => Err(Error::KeySlotUnavailable),
+        }
+    }
+
+    /// Clears the key and IV registers of the engine
+    ///
+    /// The operations of this driver already do so when they finish, this is for taking over the
+    /// engine from code which may have left a key behind, like the boot ROM.
+    pub fn clear_key(&mut self) {
+        clear_key_iv();
+    }
+
+    /// Disables the AES engine and releases it
+    pub fn free(self) -> Aes0 {
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+        clear_key_iv();
+        sec_eng
+            .se_aes_0_ctrl
+            .modify(|_, w| w.se_aes_0_en().clear_bit());
+
+        self.aes
+    }
+
+    fn run(
+        &mut self,
+        mode: BlockMode,
+        decrypt: bool,
+        key: Key,
+        iv: &[u8; BLOCK_SIZE],
+        data: &mut [u8],
+    ) -> Result<(), Error> {
+        let key_size = match key {
+            Key::Software(key) => KeySize::from_key(key)?,
+            Key::Efuse(_) => KeySize::Aes128,
+        };
+        let partial = data.len() % BLOCK_SIZE;
+        if partial != 0 && !matches!(mode, BlockMode::Ctr) {
+            return Err(Error::InvalidDataLength);
+        }
+
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+        sec_eng.se_aes_0_ctrl.modify(|_, w| unsafe {
+            w.se_aes_0_mode()
+                .bits(key_size.bits())
+                .se_aes_0_block_mode()
+                .bits(mode as u8)
+                .se_aes_0_dec_en()
+                .bit(decrypt)
+                // Derive the decryption key schedule from the newly loaded key
+                .se_aes_0_dec_key_sel()
+                .clear_bit()
+                .se_aes_0_hw_key_en()
+                .bit(matches!(key, Key::Efuse(_)))
+        });
+        match key {
+            Key::Software(key) => set_key_iv(key, iv),
+            Key::Efuse(slot) => {
+                // Both halves of the key select the same slot for a 128 bit key
+                sec_eng
+                    .se_aes_0_key_sel_0
+                    .write(|w| unsafe { w.bits(slot as u32) });
+                sec_eng
+                    .se_aes_0_key_sel_1
+                    .write(|w| unsafe { w.bits(slot as u32) });
+                set_key_iv(&[], iv);
+            }
+        }
+
+        let result = self.process(data, partial);
+        clear_key_iv();
+        sec_eng
+            .se_aes_0_ctrl
+            .modify(|_, w| w.se_aes_0_hw_key_en().clear_bit());
+        result
+    }
+
+    fn run_linked(
+        &mut self,
+        mode: BlockMode,
+        decrypt: bool,
+        key: &[u8],
+        iv: &[u8; BLOCK_SIZE],
+        src: &[u8],
+        dst: &mut [u8],
+    ) -> Result<(), Error> {
+        let key_size = KeySize::from_key(key)?;
+        let partial = src.len() % BLOCK_SIZE;
+        if src.len() != dst.len() || (partial != 0 && !matches!(mode, BlockMode::Ctr)) {
+            return Err(Error::InvalidDataLength);
+        }
+
+        if src.len() < LINK_MODE_THRESHOLD
+            || src.as_ptr() as usize % 4 != 0
+            || dst.as_ptr() as usize % 4 != 0
+        {
+            dst.copy_from_slice(src);
+            return self.run(mode, decrypt, Key::Software(key), iv, dst);
+        }
+
+        let mut link = LinkConfig {
+            ctrl: ((key_size.bits() as u32) << 3) | ((decrypt as u32) << 5) | ((mode as u32) << 12),
+            source: 0,
+            destination: 0,
+            iv: [0; 4],
+            key: [0; 8],
+        };
+        for (word, bytes) in link.iv.iter_mut().zip(iv.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for (word, bytes) in link.key.iter_mut().zip(key.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+        sec_eng.se_aes_0_ctrl.modify(|_, w| {
+            w.se_aes_0_link_mode()
+                .set_bit()
+                .se_aes_0_hw_key_en()
+                .clear_bit()
+        });
+
+        let whole = src.len() - partial;
+        let mut result = Ok(());
+        let mut continued = false;
+        for (from, to) in src[..whole]
+            .chunks(MAX_BLOCKS_PER_TRIGGER * BLOCK_SIZE)
+            .zip(dst[..whole].chunks_mut(MAX_BLOCKS_PER_TRIGGER * BLOCK_SIZE))
+        {
+            let blocks = from.len() / BLOCK_SIZE;
+            result = trigger_linked(&mut link, from.as_ptr(), to.as_mut_ptr(), blocks, continued);
+            if result.is_err() {
+                break;
+            }
+            continued = true;
+        }
+
+        sec_eng
+            .se_aes_0_ctrl
+            .modify(|_, w| w.se_aes_0_link_mode().clear_bit());
+        for word in link.key.iter_mut() {
+            unsafe { core::ptr::write_volatile(word, 0) };
+        }
+        result?;
+
+        if partial != 0 {
+            // Continue the counter where the linked blocks stopped
+            let mut counter = *iv;
+            inc32(&mut counter, (whole / BLOCK_SIZE) as u32);
+            dst[whole..].copy_from_slice(&src[whole..]);
+            self.run(
+                BlockMode::Ctr,
+                false,
+                Key::Software(key),
+                &counter,
+                &mut dst[whole..],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn process(&mut self, data: &mut [u8], partial: usize) -> Result<(), Error> {
+        let whole = data.len() - partial;
+        // Only the first trigger loads the IV, later ones continue the chain or counter
+        let mut continued = false;
+
+        if data.as_ptr() as usize % 4 == 0 {
+            for chunk in data[..whole].chunks_mut(MAX_BLOCKS_PER_TRIGGER * BLOCK_SIZE) {
+                let blocks = chunk.len() / BLOCK_SIZE;
+                trigger(chunk.as_mut_ptr(), blocks, continued)?;
+                continued = true;
+            }
+        } else {
+            let mut block = Block([0; BLOCK_SIZE]);
+            for chunk in data[..whole].chunks_mut(BLOCK_SIZE) {
+                block.0.copy_from_slice(chunk);
+                trigger(block.0.as_mut_ptr(), 1, continued)?;
+                chunk.copy_from_slice(&block.0);
+                continued = true;
+            }
+            block.0.iter_mut().for_each(|b| *b = 0);
+        }
+
+        if partial != 0 {
+            // Encrypt a full counter block and only use as much of the key stream as needed
+            let mut block = Block([0; BLOCK_SIZE]);
+            block.0[..partial].copy_from_slice(&data[whole..]);
+            trigger(block.0.as_mut_ptr(), 1, continued)?;
+            data[whole..].copy_from_slice(&block.0[..partial]);
+            block.0.iter_mut().for_each(|b| *b = 0);
+        }
+
+        Ok(())
+    }
+}
+
+/// AES-128 operations with a key from an eFuse key slot, see [`Aes::with_efuse_key`]
+pub struct EfuseKeyed<'a> {
+    aes: &'a mut Aes,
+    slot: KeySlot,
+}
+
+impl EfuseKeyed<'_> {
+    /// Encrypts `data` in place in ECB mode
+    ///
+    /// `data` must be a multiple of 16 bytes long.
+    pub fn encrypt_ecb(&mut self, data: &mut [u8]) -> Result<(), Error> {
+        let key = Key::Efuse(self.slot);
+        self.aes
+            .run(BlockMode::Ecb, false, key, &[0; BLOCK_SIZE], data)
+    }
+
+    /// Decrypts `data` in place in ECB mode
+    ///
+    /// `data` must be a multiple of 16 bytes long.
+    pub fn decrypt_ecb(&mut self, data: &mut [u8]) -> Result<(), Error> {
+        let key = Key::Efuse(self.slot);
+        self.aes
+            .run(BlockMode::Ecb, true, key, &[0; BLOCK_SIZE], data)
+    }
+
+    /// Encrypts `data` in place in CBC mode
+    ///
+    /// `data` must be a multiple of 16 bytes long.
+    pub fn encrypt_cbc(&mut self, iv: &[u8; BLOCK_SIZE], data: &mut [u8]) -> Result<(), Error> {
+        let key = Key::Efuse(self.slot);
+        self.aes.run(BlockMode::Cbc, false, key, iv, data)
+    }
+
+    /// Decrypts `data` in place in CBC mode
+    ///
+    /// `data` must be a multiple of 16 bytes long.
+    pub fn decrypt_cbc(&mut self, iv: &[u8; BLOCK_SIZE], data: &mut [u8]) -> Result<(), Error> {
+        let key = Key::Efuse(self.slot);
+        self.aes.run(BlockMode::Cbc, true, key, iv, data)
+    }
+
+    /// Encrypts or decrypts `data` in place in CTR mode, with `iv` as the initial counter block
+    pub fn ctr(&mut self, iv: &[u8; BLOCK_SIZE], data: &mut [u8]) -> Result<(), Error> {
+        let key = Key::Efuse(self.slot);
+        self.aes.run(BlockMode::Ctr, false, key, iv, data)
+    }
+}
+
+/// Loads the key and IV in the order the engine expects, see the module documentation
+fn set_key_iv(key: &[u8], iv: &[u8; BLOCK_SIZE]) {
+    let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+
+    let mut words = iv
+        .chunks(4)
+        .map(|w| u32::from_be_bytes([w[0], w[1], w[2], w[3]]));
+    let mut next = || words.next().unwrap_or(0);
+    sec_eng.se_aes_0_iv_3.write(|w| unsafe { w.bits(next()) });
+    sec_eng.se_aes_0_iv_2.write(|w| unsafe { w.bits(next()) });
+    sec_eng.se_aes_0_iv_1.write(|w| unsafe { w.bits(next()) });
+    sec_eng.se_aes_0_iv_0.write(|w| unsafe { w.bits(next()) });
+
+    // 128 bit keys use key_7..key_4, 192 bit keys key_7..key_2 and 256 bit keys all registers
+    let mut words = key
+        .chunks(4)
+        .map(|w| u32::from_be_bytes([w[0], w[1], w[2], w[3]]));
+    let mut next = || words.next().unwrap_or(0);
+    sec_eng.se_aes_0_key_7.write(|w| unsafe { w.bits(next()) });
+    sec_eng.se_aes_0_key_6.write(|w| unsafe { w.bits(next()) });
+    sec_eng.se_aes_0_key_5.write(|w| unsafe { w.bits(next()) });
+    sec_eng.se_aes_0_key_4.write(|w| unsafe { w.bits(next()) });
+    sec_eng.se_aes_0_key_3.write(|w| unsafe { w.bits(next()) });
+    sec_eng.se_aes_0_key_2.write(|w| unsafe { w.bits(next()) });
+    sec_eng.se_aes_0_key_1.write(|w| unsafe { w.bits(next()) });
+    sec_eng.se_aes_0_key_0.write(|w| unsafe { w.bits(next()) });
+}
+
+/// Don't leave key material in the engine after an operation
+fn clear_key_iv() {
+    let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+
+    sec_eng.se_aes_0_key_0.write(|w| unsafe { w.bits(0) });
+    sec_eng.se_aes_0_key_1.write(|w| unsafe { w.bits(0) });
+    sec_eng.se_aes_0_key_2.write(|w| unsafe { w.bits(0) });
+    sec_eng.se_aes_0_key_3.write(|w| unsafe { w.bits(0) });
+    sec_eng.se_aes_0_key_4.write(|w| unsafe { w.bits(0) });
+    sec_eng.se_aes_0_key_5.write(|w| unsafe { w.bits(0) });
+    sec_eng.se_aes_0_key_6.write(|w| unsafe { w.bits(0) });
+    sec_eng.se_aes_0_key_7.write(|w| unsafe { w.bits(0) });
+    sec_eng.se_aes_0_iv_0.write(|w| unsafe { w.bits(0) });
+    sec_eng.se_aes_0_iv_1.write(|w| unsafe { w.bits(0) });
+    sec_eng.se_aes_0_iv_2.write(|w| unsafe { w.bits(0) });
+    sec_eng.se_aes_0_iv_3.write(|w| unsafe { w.bits(0) });
+}
+
+/// Processes `blocks` blocks of 16 bytes in place at `ptr`
+fn trigger(ptr: *mut u8, blocks: usize, continued: bool) -> Result<(), Error> {
+    let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+
+    sec_eng
+        .se_aes_0_msa
+        .write(|w| unsafe { w.bits(ptr as u32) });
+    sec_eng
+        .se_aes_0_mda
+        .write(|w| unsafe { w.bits(ptr as u32) });
+    sec_eng.se_aes_0_ctrl.modify(|_, w| unsafe {
+        w.se_aes_0_iv_sel()
+            .bit(continued)
+            .se_aes_0_msg_len()
+            .bits(blocks as u16)
+    });
+
+    // The engine accesses the buffer directly, make sure all writes to it have happened before
+    // and no reads are moved ahead of the operation
+    compiler_fence(Ordering::SeqCst);
+    sec_eng
+        .se_aes_0_ctrl
+        .modify(|_, w| w.se_aes_0_trig_1t().set_bit());
+    let result = wait_idle();
+    sec_eng
+        .se_aes_0_ctrl
+        .modify(|_, w| w.se_aes_0_trig_1t().clear_bit());
+    compiler_fence(Ordering::SeqCst);
+
+    result
+}
+
+/// Processes `blocks` blocks of 16 bytes from `src` to `dst` as described by `link`
+fn trigger_linked(
+    link: &mut LinkConfig,
+    src: *const u8,
+    dst: *mut u8,
+    blocks: usize,
+    continued: bool,
+) -> Result<(), Error> {
+    let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+
+    let ctrl = (link.ctrl & 0x3038) | ((continued as u32) << 14) | ((blocks as u32) << 16);
+    unsafe {
+        core::ptr::write_volatile(&mut link.ctrl, ctrl);
+        core::ptr::write_volatile(&mut link.source, src as u32);
+        core::ptr::write_volatile(&mut link.destination, dst as u32);
+    }
+    sec_eng
+        .se_aes_0_link
+        .write(|w| unsafe { w.bits(link as *mut LinkConfig as u32) });
+
+    compiler_fence(Ordering::SeqCst);
+    sec_eng
+        .se_aes_0_ctrl
+        .modify(|_, w| w.se_aes_0_trig_1t().set_bit());
+    let result = wait_idle();
+    sec_eng
+        .se_aes_0_ctrl
+        .modify(|_, w| w.se_aes_0_trig_1t().clear_bit());
+    compiler_fence(Ordering::SeqCst);
+
+    result
+}
+
+/// Waits until the AES engine is no longer busy
+fn wait_idle() -> Result<(), Error> {
+    let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+
+    // The busy flag is only raised one cycle after triggering, reading the control register
+    // once takes longer than that
+    let _ = sec_eng.se_aes_0_ctrl.read();
+
+    let mut timeout_countdown = AES_TIMEOUT;
+    while sec_eng.se_aes_0_ctrl.read().se_aes_0_busy().bit_is_set() {
+        if timeout_countdown == 0 {
+            return Err(Error::Timeout);
+        }
+        timeout_countdown -= 1;
+    }
+
+    Ok(())
+}
+
+/// Size of a GCM authentication tag, in bytes
+pub const TAG_SIZE: usize = 16;
+
+/// Size of a GCM nonce, in bytes
+pub const NONCE_SIZE: usize = 12;
+
+/// GCM authentication tag
+pub type Tag = [u8; TAG_SIZE];
+
+/// Reduction polynomial of GF(2^128) in the bit order used by GCM
+const GHASH_R: u128 = 0xe1 << 120;
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum GcmState {
+    Idle,
+    Aad,
+    Payload,
+}
+
+/// AES-GCM authenticated encryption
+///
+/// The engine has no native GCM support, so the payload is encrypted with the hardware AES in CTR
+/// mode while GHASH is computed in software. GHASH multiplies in GF(2<sup>128</sup>) bit by bit,
+/// without table lookups, so its timing does not depend on the hash key or the data; that makes
+/// it considerably slower than the hardware AES pass and it dominates the time per block. CCM is
+/// not provided, the engine has no CBC-MAC mode which could speed it up.
+///
+/// Only 96 bit nonces are supported. A nonce must never be reused with the same key.
+///
+/// Besides the one-shot [`encrypt_in_place`](Gcm::encrypt_in_place) and
+/// [`decrypt_in_place`](Gcm::decrypt_in_place), messages can be processed in pieces: call
+/// [`start`](Gcm::start), pass the additional authenticated data to [`aad`](Gcm::aad), the payload
+/// to [`encrypt`](Gcm::encrypt) or [`decrypt`](Gcm::decrypt), and finish with
+/// [`finish`](Gcm::finish) or [`verify`](Gcm::verify).
+pub struct Gcm {
+    aes: Aes,
+    state: GcmState,
+    key: Zeroizing<[u8; 32]>,
+    key_len: usize,
+    /// Hash subkey
+    h: Zeroizing<u128>,
+    /// GHASH accumulator
+    ghash: u128,
+    /// GHASH input which doesn't fill a block yet
+    pending: [u8; BLOCK_SIZE],
+    pending_len: usize,
+    /// Encrypted initial counter block, masks the tag
+    tag_mask: Zeroizing<[u8; BLOCK_SIZE]>,
+    /// Next counter block for the payload
+    counter: [u8; BLOCK_SIZE],
+    /// Key stream left over from the last partial block
+    keystream: Zeroizing<[u8; BLOCK_SIZE]>,
+    keystream_used: usize,
+    aad_len: u64,
+    payload_len: u64,
+}
+
+impl Gcm {
+    /// Creates a GCM context using the AES engine
+    pub fn new(aes: Aes) -> Self {
+        Gcm {
+            aes,
+            state: GcmState::Idle,
+            key: Zeroizing::new([0; 32]),
+            key_len: 0,
+            h: Zeroizing::new(0),
+            ghash: 0,
+            pending: [0; BLOCK_SIZE],
+            pending_len: 0,
+            tag_mask: Zeroizing::new([0; BLOCK_SIZE]),
+            counter: [0; BLOCK_SIZE],
+            keystream: Zeroizing::new([0; BLOCK_SIZE]),
+            keystream_used: BLOCK_SIZE,
+            aad_len: 0,
+            payload_len: 0,
+        }
+    }
+
+    /// Encrypts `buf` in place and returns the authentication tag over `aad` and the ciphertext
+    pub fn encrypt_in_place(
+        &mut self,
+        key: &[u8],
+        nonce: &[u8; NONCE_SIZE],
+        aad: &[u8],
+        buf: &mut [u8],
+    ) -> Result<Tag, Error> {
+        self.start(key, nonce)?;
+        self.aad(aad)?;
+        self.encrypt(buf)?;
+        self.finish()
+    }
+
+    /// Checks `tag` and decrypts `buf` in place
+    ///
+    /// If the tag doesn't match, [`Error::TagMismatch`] is returned and `buf` is left encrypted.
+    pub fn decrypt_in_place(
+        &mut self,
+        key: &[u8],
+        nonce: &[u8; NONCE_SIZE],
+        aad: &[u8],
+        buf: &mut [u8],
+        tag: &Tag,
+    ) -> Result<(), Error> {
+        // Authenticate before decrypting, so no unauthenticated plaintext is ever released
+        self.start(key, nonce)?;
+        self.aad(aad)?;
+        self.set_payload_state();
+        self.ghash_update(buf);
+        self.payload_len += buf.len() as u64;
+        let expected = self.compute_tag();
+
+        if !ct_eq(&expected, tag) {
+            self.reset();
+            return Err(Error::TagMismatch);
+        }
+
+        let result = self.ctr(buf);
+        self.reset();
+        result
+    }
+
+    /// Starts a new message, discarding any message in progress
+    ///
+    /// `key` must be 16, 24 or 32 bytes long.
+    pub fn start(&mut self, key: &[u8], nonce: &[u8; NONCE_SIZE]) -> Result<(), Error> {
+        KeySize::from_key(key)?;
+        self.reset();
+        self.key[..key.len()].copy_from_slice(key);
+        self.key_len = key.len();
+
+        let mut block = [0u8; BLOCK_SIZE];
+        self.aes.encrypt_ecb(key, &mut block)?;
+        *self.h = u128::from_be_bytes(block);
+        block.zeroize();
+
+        // J0 = nonce || 1, the payload starts at inc32(J0)
+        self.counter[..NONCE_SIZE].copy_from_slice(nonce);
+        self.counter[BLOCK_SIZE - 1] = 1;
+        *self.tag_mask = self.counter;
+        self.aes.encrypt_ecb(key, &mut self.tag_mask[..])?;
+        inc32(&mut self.counter, 1);
+
+        self.state = GcmState::Aad;
+        Ok(())
+    }
+
+    /// Adds additional authenticated data, can be called several times before the payload
+    pub fn aad(&mut self, aad: &[u8]) -> Result<(), Error> {
+        if self.state != GcmState::Aad {
+            return Err(Error::InvalidState);
+        }
+
+        self.ghash_update(aad);
+        self.aad_len += aad.len() as u64;
+        Ok(())
+    }
+
+    /// Encrypts the next part of the payload in place
+    pub fn encrypt(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        if self.state == GcmState::Idle {
+            return Err(Error::InvalidState);
+        }
+        self.set_payload_state();
+
+        self.ctr(buf)?;
+        self.ghash_update(buf);
+        self.payload_len += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Decrypts the next part of the payload in place
+    ///
+    /// The plaintext is returned before the tag has been checked by [`verify`](Gcm::verify), so it
+    /// must not be used until then. Prefer [`decrypt_in_place`](Gcm::decrypt_in_place) if the
+    /// whole message fits into memory.
+    pub fn decrypt(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        if self.state == GcmState::Idle {
+            return Err(Error::InvalidState);
+        }
+        self.set_payload_state();
+
+        self.ghash_update(buf);
+        self.payload_len += buf.len() as u64;
+        self.ctr(buf)
+    }
+
+    /// Ends an encryption and returns the authentication tag
+    pub fn finish(&mut self) -> Result<Tag, Error> {
+        if self.state == GcmState::Idle {
+            return Err(Error::InvalidState);
+        }
+
+        let tag = self.compute_tag();
+        self.reset();
+        Ok(tag)
+    }
+
+    /// Ends a decryption and checks the authentication tag in constant time
+    pub fn verify(&mut self, tag: &Tag) -> Result<(), Error> {
+        let expected = self.finish()?;
+
+        if ct_eq(&expected, tag) {
+            Ok(())
+        } else {
+            Err(Error::TagMismatch)
+        }
+    }
+
+    /// Releases the AES engine
+    pub fn free(mut self) -> Aes {
+        self.reset();
+        self.aes
+    }
+
+    fn set_payload_state(&mut self) {
+        if self.state == GcmState::Aad {
+            // The payload starts on a new GHASH block
+            self.ghash_flush();
+            self.state = GcmState::Payload;
+        }
+    }
+
+    fn compute_tag(&mut self) -> Tag {
+        self.set_payload_state();
+        self.ghash_flush();
+
+        let lengths = (u128::from(self.aad_len.wrapping_mul(8)) << 64)
+            | u128::from(self.payload_len.wrapping_mul(8));
+        self.ghash_block(lengths);
+
+        let mut tag = self.ghash.to_be_bytes();
+        tag.iter_mut()
+            .zip(self.tag_mask.iter())
+            .for_each(|(t, m)| *t ^= m);
+        tag
+    }
+
+    /// XORs the key stream onto `buf`, continuing where the last call stopped
+    fn ctr(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        let key = &self.key[..self.key_len];
+
+        // Use up the key stream left from a partial block first
+        let n = core::cmp::min(BLOCK_SIZE - self.keystream_used, buf.len());
+        let (head, buf) = buf.split_at_mut(n);
+        head.iter_mut()
+            .zip(self.keystream[self.keystream_used..].iter())
+            .for_each(|(b, k)| *b ^= k);
+        self.keystream_used += n;
+
+        let whole = buf.len() - buf.len() % BLOCK_SIZE;
+        let (blocks, tail) = buf.split_at_mut(whole);
+        if !blocks.is_empty() {
+            self.aes.ctr(key, &self.counter, blocks)?;
+            inc32(&mut self.counter, (whole / BLOCK_SIZE) as u32);
+        }
+
+        if !tail.is_empty() {
+            *self.keystream = [0; BLOCK_SIZE];
+            self.aes.ctr(key, &self.counter, &mut self.keystream[..])?;
+            inc32(&mut self.counter, 1);
+            tail.iter_mut()
+                .zip(self.keystream.iter())
+                .for_each(|(b, k)| *b ^= k);
+            self.keystream_used = tail.len();
+        }
+
+        Ok(())
+    }
+
+    fn ghash_update(&mut self, mut data: &[u8]) {
+        if self.pending_len > 0 {
+            let n = core::cmp::min(BLOCK_SIZE - self.pending_len, data.len());
+            self.pending[self.pending_len..self.pending_len + n].copy_from_slice(&data[..n]);
+            self.pending_len += n;
+            data = &data[n..];
+
+            if self.pending_len < BLOCK_SIZE {
+                return;
+            }
+            self.ghash_flush();
+        }
+
+        for chunk in data.chunks(BLOCK_SIZE) {
+            self.pending[..chunk.len()].copy_from_slice(chunk);
+            self.pending_len = chunk.len();
+            if self.pending_len == BLOCK_SIZE {
+                self.ghash_flush();
+            }
+        }
+    }
+
+    /// Hashes the pending input, padded with zeros to a full block
+    fn ghash_flush(&mut self) {
+        if self.pending_len > 0 {
+            self.pending[self.pending_len..]
+                .iter_mut()
+                .for_each(|b| *b = 0);
+            self.ghash_block(u128::from_be_bytes(self.pending));
+            self.pending_len = 0;
+        }
+    }
+
+    fn ghash_block(&mut self, block: u128) {
+        self.ghash = gf128_mul(self.ghash ^ block, *self.h);
+    }
+
+    /// Erases all key material and returns to the idle state
+    fn reset(&mut self) {
+        self.state = GcmState::Idle;
+        self.key.zeroize();
+        self.key_len = 0;
+        self.h.zeroize();
+        self.ghash.zeroize();
+        self.pending.zeroize();
+        self.pending_len = 0;
+        self.tag_mask.zeroize();
+        self.counter = [0; BLOCK_SIZE];
+        self.keystream.zeroize();
+        self.keystream_used = BLOCK_SIZE;
+        self.aad_len = 0;
+        self.payload_len = 0;
+    }
+}
+
+/// Multiplication in GF(2^128) as defined for GHASH, in constant time
+fn gf128_mul(x: u128, h: u128) -> u128 {
+    let mut z = 0;
+    let mut v = h;
+
+    for i in (0..128).rev() {
+        // All ones if bit i of x is set, without branching on it
+        let mask = 0u128.wrapping_sub((x >> i) & 1);
+        z ^= v & mask;
+        let reduce = 0u128.wrapping_sub(v & 1);
+        v = (v >> 1) ^ (GHASH_R & reduce);
+    }
+
+    z
+}
+
+/// Adds `n` to the last 32 bits of `counter` as a big endian number
+fn inc32(counter: &mut [u8; BLOCK_SIZE], n: u32) {
+    let low = u32::from_be_bytes([counter[12], counter[13], counter[14], counter[15]]);
+    counter[12..].copy_from_slice(&low.wrapping_add(n).to_be_bytes());
+}
+
+#[cfg(feature = "cipher")]
+mod cipher_impls {
+    use core::cell::RefCell;
+
+    use cipher::consts::{U1, U16};
+    use cipher::{Block, BlockCipher, BlockDecrypt, BlockEncrypt};
+
+    use super::Aes;
+    use crate::gpio::ClkCfg;
+    use crate::sec_eng::Aes0;
+    use crate::secure::Zeroizing;
+
+    macro_rules! cipher_impl {
+        ($($Name:ident: ($key_len:expr, $doc:expr),)+) => {
+            $(
+                #[doc = $doc]
+                ///
+                /// Implements the `cipher` 0.3 block cipher traits on top of the hardware engine.
+                /// The key is kept in RAM and loaded into the engine for every block.
+                pub struct $Name {
+                    // The traits take `&self`, the RefCell also keeps the type from being `Sync`
+                    aes: RefCell<Aes>,
+                    key: Zeroizing<[u8; $key_len]>,
+                }
+
+                impl $Name {
+                    /// Enables the AES engine with the given key
+                    pub fn new(aes: Aes0, clk_cfg: &mut ClkCfg, key: &[u8; $key_len]) -> Self {
+                        $Name {
+                            aes: RefCell::new(Aes::new(aes, clk_cfg)),
+                            key: Zeroizing::new(*key),
+                        }
+                    }
+
+                    /// Erases the key and releases the AES engine
+                    pub fn free(self) -> Aes0 {
+                        self.aes.into_inner().free()
+                    }
+                }
+
+                impl BlockCipher for $Name {
+                    type BlockSize = U16;
+                    type ParBlocks = U1;
+                }
+
+                impl BlockEncrypt for $Name {
+                    /// # Panics
+                    ///
+                    /// Panics if the engine times out.
+                    fn encrypt_block(&self, block: &mut Block<Self>) {
+                        self.aes
+                            .borrow_mut()
+                            .encrypt_ecb(&self.key[..], block.as_mut_slice())
+                            .unwrap();
+                    }
+                }
+
+                impl BlockDecrypt for $Name {
+                    /// # Panics
+                    ///
+                    /// Panics if the engine times out.
+                    fn decrypt_block(&self, block: &mut Block<Self>) {
+                        self.aes
+                            .borrow_mut()
+                            .decrypt_ecb(&self.key[..], block.as_mut_slice())
+                            .unwrap();
+                    }
+                }
+            )+
+        };
+    }
+
+    cipher_impl! {
+        Aes128: (16, "Hardware AES-128"),
+        Aes192: (24, "Hardware AES-192"),
+        Aes256: (32, "Hardware AES-256"),
+    }
+}
+
+#[cfg(feature = "cipher")]
+pub use cipher_impls::{Aes128, Aes192, Aes256};
+
+#[cfg(feature = "aead")]
+mod aead_impls {
+    use aead::consts::{U0, U12, U16};
+    use aead::{AeadCore, AeadMutInPlace, Nonce};
+
+    use super::{Aes, Error, Gcm, NONCE_SIZE};
+    use crate::secure::Zeroizing;
+
+    macro_rules! aead_impl {
+        ($($Name:ident: ($key_len:expr, $doc:expr),)+) => {
+            $(
+                #[doc = $doc]
+                ///
+                /// Implements the `aead` 0.4 traits on top of [`Gcm`].
+                pub struct $Name {
+                    gcm: Gcm,
+                    key: Zeroizing<[u8; $key_len]>,
+                }
+
+                impl $Name {
+                    /// Creates an AEAD instance with the given key
+                    pub fn new(aes: Aes, key: &[u8; $key_len]) -> Self {
+                        $Name {
+                            gcm: Gcm::new(aes),
+                            key: Zeroizing::new(*key),
+                        }
+                    }
+
+                    /// Erases the key and releases the AES engine
+                    pub fn free(self) -> Aes {
+                        self.gcm.free()
+                    }
+
+                    fn nonce(nonce: &Nonce<Self>) -> [u8; NONCE_SIZE] {
+                        let mut bytes = [0; NONCE_SIZE];
+                        bytes.copy_from_slice(nonce);
+                        bytes
+                    }
+                }
+
+                impl AeadCore for $Name {
+                    type NonceSize = U12;
+                    type TagSize = U16;
+                    type CiphertextOverhead = U0;
+                }
+
+                impl AeadMutInPlace for $Name {
+                    fn encrypt_in_place_detached(
+                        &mut self,
+                        nonce: &Nonce<Self>,
+                        associated_data: &[u8],
+                        buffer: &mut [u8],
+                    ) -> Result<aead::Tag<Self>, aead::Error> {
+                        let tag = self
+                            .gcm
+                            .encrypt_in_place(&self.key[..], &Self::nonce(nonce), associated_data, buffer)
+                            .map_err(|_: Error| aead::Error)?;
+                        Ok(tag.into())
+                    }
+
+                    fn decrypt_in_place_detached(
+                        &mut self,
+                        nonce: &Nonce<Self>,
+                        associated_data: &[u8],
+                        buffer: &mut [u8],
+                        tag: &aead::Tag<Self>,
+                    ) -> Result<(), aead::Error> {
+                        let mut expected = [0; super::TAG_SIZE];
+                        expected.copy_from_slice(tag);
+                        self.gcm
+                            .decrypt_in_place(
+                                &self.key[..],
+                                &Self::nonce(nonce),
+                                associated_data,
+                                buffer,
+                                &expected,
+                            )
+                            .map_err(|_: Error| aead::Error)
+                    }
+                }
+            )+
+        };
+    }
+
+    aead_impl! {
+        Aes128Gcm: (16, "Hardware AES-128-GCM"),
+        Aes256Gcm: (32, "Hardware AES-256-GCM"),
+    }
+}
+
+#[cfg(feature = "aead")]
+pub use aead_impls::{Aes128Gcm, Aes256Gcm};
@@ -0,0 +1,349 @@
+/*!
+  # AT commands
+  A parser for the Hayes AT command set spoken by WiFi, Bluetooth and cellular modules on a UART,
+  e.g. an ESP8266 or a SIM800. Enabled by the `at-parser` feature.
+
+  [`AtParser::send_command`] sends a command terminated by `"\r\n"` and reads the response line
+  by line up to the final result code:
+
+  | Final line                                | Response                         |
+  |-------------------------------------------|----------------------------------|
+  | `OK`, nothing before it                   | [`AtResponse::Ok`]               |
+  | `OK`, after other lines                   | [`AtResponse::Data`]             |
+  | `+CME ERROR: <n>`, `+CMS ERROR: <n>`      | [`AtResponse::Error`] with `<n>` |
+  | `ERROR`, `FAIL`, or an error without code | [`AtResponse::Error`] with -1    |
+
+  Empty lines and the echo of the command are skipped, the remaining lines are joined with
+  `'\n'` into the data. [`AtParser::wait_for_urc`] reads lines until one starts with a given
+  prefix, the unsolicited result codes a module sends by itself, e.g. `+RECEIVE:` of a SIM800.
+
+  Text is kept in an [`AtString`], which holds up to [`AT_STRING_CAPACITY`] bytes without a heap.
+  A longer response or line is read up to its end anyway, so the parser stays in step with the
+  module, and then reported as [`AtError::Overflow`].
+
+  The parser can't tell an unsolicited result code from the response of a command, one which
+  arrives while a command is running ends up in its data. Prompts which aren't followed by a
+  line ending, like the `>` before the payload of `AT+CIPSEND`, aren't recognized, neither is
+  binary payload such as the data of `+IPD`; those have to be handled with the serial port
+  directly, see [`AtParser::serial`].
+
+  ## Example
+  ```rust
+    use bl602_hal::at::{AtParser, AtResponse};
+
+    let mut modem = AtParser::new(serial, clocks);
+
+    assert_eq!(modem.send_command("ATE0", 100), Ok(AtResponse::Ok));
+    if let Ok(AtResponse::Data(version)) = modem.send_command("AT+GMR", 1000) {
+        // ...
+    }
+    let message = modem.wait_for_urc("+RECEIVE:", 10_000).unwrap();
+  ```
+*/
+
+use core::fmt;
+use core::ops::Deref;
+
+use embedded_hal::serial::nb::{Read, Write};
+use embedded_hal::serial::{Error as _, ErrorKind};
+
+use crate::clock::Clocks;
+
+/// Number of bytes an [`AtString`] can hold
+pub const AT_STRING_CAPACITY: usize = 256;
+
+/// Error of the AT command parser
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum AtError {
+    /// No final result code, or no matching line, arrived in time
+    Timeout,
+    /// The response or line didn't fit into an [`AtString`], it was read up to its end
+    Overflow,
+    /// The response or line isn't valid UTF-8, it was read up to its end
+    InvalidUtf8,
+    /// The serial port reported an error
+    Serial(ErrorKind),
+}
+
+/// Response of the module to a command, see the module documentation
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AtResponse {
+    /// `OK` without any lines before it
+    Ok,
+    /// An error result code with its numeric code, or -1 if it had none
+    Error(i32),
+    /// `OK` after the lines of this text, joined with `'\n'`
+    Data(AtString),
+}
+
+/// Text of at most [`AT_STRING_CAPACITY`] bytes, stored inline
+///
+/// This isn't a `heapless::String`, since heapless needs const generics from 0.7 on, and so a
+/// newer Rust than the MSRV of 1.46.
+#[derive(Copy, Clone)]
+pub struct AtString {
+    bytes: [u8; AT_STRING_CAPACITY],
+    len: usize,
+}
+
+impl AtString {
+    /// Returns an empty string
+    pub const fn new() -> Self {
+        AtString {
+            bytes: [0; AT_STRING_CAPACITY],
+            len: 0,
+        }
+    }
+
+    /// Returns the text
+    pub fn as_str(&self) -> &str {
+        // Only ever filled from `&str`
+        unsafe { core::str::from_utf8_unchecked(&self.bytes[..self.len]) }
+    }
+
+    /// Appends `s`, returns `false` and leaves the string as it was if it doesn't fit
+    pub fn push_str(&mut self, s: &str) -> bool {
+        let end = self.len + s.len();
+        if end > AT_STRING_CAPACITY {
+            return false;
+        }
+
+        self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        true
+    }
+}
+
+impl Default for AtString {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for AtString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq for AtString {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for AtString {}
+
+impl PartialEq<str> for AtString {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for AtString {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Debug for AtString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for AtString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// AT command parser on a serial port, see the module documentation
+pub struct AtParser<UART> {
+    serial: UART,
+    core_frequency: u32,
+    /// The line being received, kept when a timeout interrupts it
+    line: [u8; AT_STRING_CAPACITY],
+    line_len: usize,
+    /// The line being received is longer than `line`
+    line_overflow: bool,
+}
+
+impl<UART> AtParser<UART>
+where
+    UART: Read<u8> + Write<u8>,
+{
+    /// Wraps `serial`, the timeouts are measured with `mcycle` at the system clock of `clocks`
+    pub fn new(serial: UART, clocks: Clocks) -> Self {
+        AtParser {
+            serial,
+            core_frequency: clocks.sysclk().0,
+            line: [0; AT_STRING_CAPACITY],
+            line_len: 0,
+            line_overflow: false,
+        }
+    }
+
+    /// Sends `cmd` followed by `"\r\n"` and waits up to `timeout_ms` milliseconds for the final
+    /// result code
+    ///
+    /// `cmd` is sent as given, including its `AT` prefix. An error result code is a successful
+    /// [`AtResponse::Error`], only a failure of the parser itself is an [`AtError`].
+    ///
+    /// A response which arrives after the timeout isn't dropped, the next call reads it as its
+    /// own response.
+    pub fn send_command(&mut self, cmd: &str, timeout_ms: u32) -> Result<AtResponse, AtError> {
+        let deadline = self.deadline(timeout_ms);
+
+        for &byte in cmd.as_bytes().iter().chain(b"\r\n") {
+            nb::block!(self.serial.write(byte)).map_err(|e| AtError::Serial(e.kind()))?;
+        }
+
+        let mut data = AtString::new();
+        let mut lines = 0;
+        let mut failure = None;
+        loop {
+            self.read_line(deadline)?;
+            let line = &self.line[..self.line_len];
+
+            if self.line_overflow {
+                failure = Some(AtError::Overflow);
+            } else if line.is_empty() || line == cmd.as_bytes() {
+                // Blank line or echo
+            } else if let Some(result) = final_result(line) {
+                self.clear_line();
+                return match failure {
+                    Some(failure) => Err(failure),
+                    None => Ok(match result {
+                        Err(code) => AtResponse::Error(code),
+                        Ok(()) if lines == 0 => AtResponse::Ok,
+                        Ok(()) => AtResponse::Data(data),
+                    }),
+                };
+            } else {
+                match core::str::from_utf8(line) {
+                    Ok(line) => {
+                        let separator = if lines > 0 { "\n" } else { "" };
+                        if data.len + separator.len() + line.len() > AT_STRING_CAPACITY {
+                            failure = failure.or(Some(AtError::Overflow));
+                        } else {
+                            data.push_str(separator);
+                            data.push_str(line);
+                        }
+                    }
+                    Err(_) => failure = failure.or(Some(AtError::InvalidUtf8)),
+                }
+                lines += 1;
+            }
+
+            self.clear_line();
+        }
+    }
+
+    /// Reads lines for up to `timeout_ms` milliseconds until one starts with `prefix`, and
+    /// returns it including the prefix
+    ///
+    /// Lines before it are dropped. One which is cut off by the timeout is kept and continued
+    /// by the next call.
+    pub fn wait_for_urc(&mut self, prefix: &str, timeout_ms: u32) -> Result<AtString, AtError> {
+        let deadline = self.deadline(timeout_ms);
+
+        loop {
+            self.read_line(deadline)?;
+            let line = &self.line[..self.line_len];
+
+            let result = if !line.starts_with(prefix.as_bytes()) {
+                None
+            } else if self.line_overflow {
+                Some(Err(AtError::Overflow))
+            } else {
+                Some(match core::str::from_utf8(line) {
+                    Ok(line) => {
+                        let mut urc = AtString::new();
+                        urc.push_str(line);
+                        Ok(urc)
+                    }
+                    Err(_) => Err(AtError::InvalidUtf8),
+                })
+            };
+
+            self.clear_line();
+            if let Some(result) = result {
+                return result;
+            }
+        }
+    }
+
+    /// Returns the serial port, e.g. to send the payload after a `>` prompt
+    pub fn serial(&mut self) -> &mut UART {
+        &mut self.serial
+    }
+
+    /// Releases the serial port
+    pub fn free(self) -> UART {
+        self.serial
+    }
+
+    fn deadline(&self, timeout_ms: u32) -> u64 {
+        let cycles_per_ms = u64::from(self.core_frequency / 1000);
+        riscv::register::mcycle::read64() + u64::from(timeout_ms) * cycles_per_ms
+    }
+
+    /// Receives bytes into `line` until a `'\n'`, dropping every `'\r'`
+    fn read_line(&mut self, deadline: u64) -> Result<(), AtError> {
+        loop {
+            match self.serial.read() {
+                Ok(b'\n') => return Ok(()),
+                Ok(b'\r') => {}
+                Ok(byte) => {
+                    if self.line_len < AT_STRING_CAPACITY {
+                        self.line[self.line_len] = byte;
+                        self.line_len += 1;
+                    } else {
+                        self.line_overflow = true;
+                    }
+                }
+                Err(nb::Error::WouldBlock) => {
+                    if riscv::register::mcycle::read64() >= deadline {
+                        return Err(AtError::Timeout);
+                    }
+                }
+                Err(nb::Error::Other(e)) => return Err(AtError::Serial(e.kind())),
+            }
+        }
+    }
+
+    fn clear_line(&mut self) {
+        self.line_len = 0;
+        self.line_overflow = false;
+    }
+}
+
+impl<UART> fmt::Debug for AtParser<UART> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AtParser")
+            .field("core_frequency", &self.core_frequency)
+            .field("line", &&self.line[..self.line_len])
+            .field("line_overflow", &self.line_overflow)
+            .finish()
+    }
+}
+
+/// Returns `Ok` for `OK`, the error code for an error result code and `None` for other lines
+fn final_result(line: &[u8]) -> Option<Result<(), i32>> {
+    match line {
+        b"OK" => Some(Ok(())),
+        b"ERROR" | b"FAIL" => Some(Err(-1)),
+        _ if line.starts_with(b"+CME ERROR:") || line.starts_with(b"+CMS ERROR:") => {
+            let code = core::str::from_utf8(&line[b"+CME ERROR:".len()..])
+                .ok()
+                .and_then(|code| code.trim().parse().ok())
+                .unwrap_or(-1);
+            Some(Err(code))
+        }
+        _ => None,
+    }
+}
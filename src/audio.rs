@@ -0,0 +1,375 @@
+/*!
+  # Audio
+  The BL602 has no I2S or other audio block, the PAC has nothing of the kind: I2S is on the
+  BL702 only. [`SpiPcm`] streams 16 bit PCM samples over the SPI master instead, fed by DMA, to
+  an SPI DAC which latches a sample when its chip select goes high, e.g. an AD5541A or a
+  DAC8830. Connect SCLK, MOSI and SS to the DAC's clock, data input and chip select; MISO isn't
+  used. I2S DACs don't work this way, they need a word clock the SPI master has no pin for.
+
+  # Pacing
+  The BL602's DMA controller has no request line from a timer, so the SPI master's own frame
+  timing is the timer: every sample is a 16 bit frame with SS deasserted in between, and the
+  frame takes a fixed number of cycles of the SPI clock, which runs from the same PLL as the
+  core without any interrupt jitter:
+
+  | Phase                  | SPI clock cycles |
+  |------------------------|------------------|
+  | start, SS low to SCLK  | `h`              |
+  | 16 bits                | `32 * h`         |
+  | stop, SCLK to SS high  | `h`              |
+  | interval, SS high      | `i`, 1 to 34     |
+
+  [`SpiPcm::new`] picks the half period `h` of SCLK and the interval `i` to come closest to the
+  sample rate, [`SpiPcm::sample_rate`] returns the rate reached. With a 40 MHz SPI clock,
+  44.1 kHz takes 907 cycles, 44.101 kHz with SCLK at 769 kHz.
+
+  # Streaming
+  Samples are written to a ring of two buffers, the halves of the frame buffer [`SpiPcm::new`]
+  takes, which the DMA channel plays in turn, following the descriptors of a [`PcmBuffer`]: while one is played, [`SpiPcm::write_samples`] fills the other, and
+  blocks until all samples are queued. Playback starts once the first buffer is full, and
+  [`SpiPcm::flush`] pads the last one with silence, plays everything and stops.
+
+  If the channel reaches samples which haven't been written yet, the stream is stopped and
+  `write_samples` or `flush` fails with [`Error::Underrun`]; the next call starts over. Keep
+  the calls coming at least once per buffer, e.g. 512 frames are 11.6 ms at 44.1 kHz.
+
+  ## Example
+  ```rust
+    static mut BUFFER: PcmBuffer = PcmBuffer::new();
+    static mut FRAMES: [u32; 1024] = [0; 1024];
+
+    let spi = hal::spi::Spi::new(
+        dp.SPI,
+        (miso, mosi, ss, sclk),
+        embedded_hal::spi::MODE_0,
+        1_000_000u32.Hz(),
+        clocks,
+    );
+    let (buffer, frames) = unsafe { (&mut BUFFER, &mut FRAMES) };
+    let mut pcm = SpiPcm::new(spi, dp.DMA, 0, buffer, frames, 44_100u32.Hz(), &clocks).unwrap();
+
+    pcm.write_samples(&samples).unwrap();
+    pcm.flush().unwrap();
+  ```
+*/
+
+use core::ptr;
+
+use embedded_time::rate::Hertz;
+
+use crate::clock::Clocks;
+use crate::delay::McycleDelay;
+use crate::dma::{self, Lli};
+use crate::pac;
+use crate::spi::{MisoPin, MosiPin, SclkPin, Spi, SsPin};
+
+/// Half periods of SCLK in a frame: start, 16 bits of two phases each, stop
+const FRAME_HALF_PERIODS: u32 = 34;
+
+/// Most cycles of the SPI clock in half a period of SCLK
+const MAX_HALF_PERIOD: u32 = 256;
+
+/// The frame a silent sample is sent as
+const SILENCE: u32 = 0x8000;
+
+/// Audio error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The sample rate can't be reached from the SPI clock
+    SampleRate,
+    /// The DMA channel ran out of samples, the stream has been stopped
+    Underrun,
+    /// The last frames weren't shifted out in time
+    Timeout,
+}
+
+/// The DMA descriptors linking the two halves of the frame buffer [`SpiPcm`] plays from
+///
+/// The DMA channel keeps reading it between calls, so it has to stay put, e.g. in a `static`.
+pub struct PcmBuffer {
+    lli: [Lli; 2],
+}
+
+impl PcmBuffer {
+    /// Returns unlinked descriptors
+    pub const fn new() -> Self {
+        const EMPTY: Lli = Lli {
+            src: 0,
+            dst: 0,
+            next: 0,
+            control: 0,
+        };
+        PcmBuffer { lli: [EMPTY; 2] }
+    }
+}
+
+/// 16 bit PCM over the SPI master to an SPI DAC, see the module documentation
+pub struct SpiPcm<MISO, MOSI, SS, SCLK> {
+    spi: Spi<pac::SPI, (MISO, MOSI, SS, SCLK)>,
+    dma: pac::DMA,
+    channel: usize,
+    buffer: &'static mut PcmBuffer,
+    /// Both buffers of the ring, one after the other
+    frames: &'static mut [u32],
+    /// Frames of one buffer, half of `frames`
+    len: usize,
+    spi_clk: u32,
+    sysclk: u32,
+    /// Cycles of the SPI clock per frame
+    frame_cycles: u32,
+    /// `spi_prd_0` and `spi_prd_1` before [`SpiPcm::new`], and `spi_config` before the stream
+    /// started
+    saved: [u32; 3],
+    running: bool,
+    /// Frames written since the stream started
+    written: u64,
+    /// Frames the channel had read when last checked, and the cycle count at the time
+    consumed: u64,
+    checked_at: u64,
+}
+
+impl<MISO, MOSI, SS, SCLK> SpiPcm<MISO, MOSI, SS, SCLK>
+where
+    MISO: MisoPin<pac::SPI>,
+    MOSI: MosiPin<pac::SPI>,
+    SS: SsPin<pac::SPI>,
+    SCLK: SclkPin<pac::SPI>,
+{
+    /// Takes the SPI master with SS, DMA channel `channel`, the descriptors and the frame
+    /// buffer, and sets up the frame timing for `sample_rate`
+    ///
+    /// The frame buffer is split into the two buffers of the ring. The DMA controller is taken
+    /// as a whole, the other channels are left alone. The SPI mode set with [`Spi::new`] is
+    /// kept, its frequency replaced.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `channel` isn't below 4, or the length of `frames` is odd, below 64 or above
+    /// 8190.
+    pub fn new(
+        spi: Spi<pac::SPI, (MISO, MOSI, SS, SCLK)>,
+        dma: pac::DMA,
+        channel: usize,
+        buffer: &'static mut PcmBuffer,
+        frames: &'static mut [u32],
+        sample_rate: Hertz,
+        clocks: &Clocks,
+    ) -> Result<Self, Error> {
+        assert!(channel < dma::CHANNELS);
+        let len = frames.len() / 2;
+        // Fewer frames would make a lap too short to tell laps apart by the cycle count
+        assert!(frames.len() % 2 == 0 && len >= 32 && len <= dma::MAX_TRANSFERS);
+
+        let spi_clk = clocks.spi_clk().0;
+        if sample_rate.0 == 0 {
+            return Err(Error::SampleRate);
+        }
+        let total = (spi_clk + sample_rate.0 / 2) / sample_rate.0;
+        // The interval takes what's left over, 1 to 34 cycles
+        let half = total.saturating_sub(1) / FRAME_HALF_PERIODS;
+        if half == 0 || half > MAX_HALF_PERIOD {
+            return Err(Error::SampleRate);
+        }
+        let interval = total - FRAME_HALF_PERIODS * half;
+
+        let regs = unsafe { &*pac::SPI::ptr() };
+        let saved = [
+            regs.spi_prd_0.read().bits(),
+            regs.spi_prd_1.read().bits(),
+            regs.spi_config.read().bits(),
+        ];
+        let half = (half - 1) as u8;
+        regs.spi_prd_0.modify(|_r, w| unsafe {
+            w.cr_spi_prd_s()
+                .bits(half)
+                .cr_spi_prd_p()
+                .bits(half)
+                .cr_spi_prd_d_ph_0()
+                .bits(half)
+                .cr_spi_prd_d_ph_1()
+                .bits(half)
+        });
+        regs.spi_prd_1
+            .modify(|_r, w| unsafe { w.cr_spi_prd_i().bits((interval - 1) as u8) });
+
+        Ok(SpiPcm {
+            spi,
+            dma,
+            channel,
+            buffer,
+            frames,
+            len,
+            spi_clk,
+            sysclk: clocks.sysclk().0,
+            frame_cycles: total,
+            saved,
+            running: false,
+            written: 0,
+            consumed: 0,
+            checked_at: 0,
+        })
+    }
+
+    /// Returns the sample rate reached
+    pub fn sample_rate(&self) -> Hertz {
+        Hertz(self.spi_clk / self.frame_cycles)
+    }
+
+    /// Queues `samples`, blocking until the last one is in the ring
+    ///
+    /// Playback starts once the first buffer is full.
+    pub fn write_samples(&mut self, samples: &[i16]) -> Result<(), Error> {
+        for &sample in samples {
+            self.write_frame(sample as u16 as u32 ^ SILENCE)?;
+        }
+        if self.running {
+            self.consumed()?;
+        }
+        Ok(())
+    }
+
+    /// Pads the last buffer with silence, plays everything written and stops
+    ///
+    /// Returns once the last frame has been shifted out, up to two buffers later.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.written == 0 {
+            return Ok(());
+        }
+        // Starts the stream, if less than a buffer was written
+        while self.written % self.len as u64 != 0 {
+            self.write_frame(SILENCE)?;
+        }
+
+        // The channel may be in the last buffer already, with the link to the next one loaded,
+        // so one more buffer of silence ends the chain
+        let last = (self.written / self.len as u64 % 2) as usize;
+        unsafe { ptr::write_volatile(&mut self.buffer.lli[last].next, 0) };
+        for _ in 0..self.len {
+            self.write_frame(SILENCE)?;
+        }
+        while dma::is_enabled(self.channel) {
+            self.consumed()?;
+        }
+
+        let result = self.spi.flush_and_wait().map_err(|_| Error::Timeout);
+        self.stop();
+        result
+    }
+
+    /// Stops the stream, dropping what's queued, and returns the SPI master with its timing
+    /// restored, the DMA controller, the descriptors and the frame buffer
+    pub fn free(
+        mut self,
+    ) -> (
+        Spi<pac::SPI, (MISO, MOSI, SS, SCLK)>,
+        pac::DMA,
+        &'static mut PcmBuffer,
+        &'static mut [u32],
+    ) {
+        if self.running {
+            self.stop();
+        }
+        let regs = unsafe { &*pac::SPI::ptr() };
+        regs.spi_prd_0.write(|w| unsafe { w.bits(self.saved[0]) });
+        regs.spi_prd_1.write(|w| unsafe { w.bits(self.saved[1]) });
+        (self.spi, self.dma, self.buffer, self.frames)
+    }
+
+    /// Writes a frame to the next slot of the ring, once the channel is done with it
+    fn write_frame(&mut self, frame: u32) -> Result<(), Error> {
+        let lap = self.frames.len() as u64;
+        if self.running {
+            while self.written >= self.consumed()? + lap {}
+        }
+
+        let slot = (self.written % lap) as usize;
+        unsafe { ptr::write_volatile(&mut self.frames[slot], frame) };
+        self.written += 1;
+
+        if !self.running && self.written == self.len as u64 {
+            self.start();
+        }
+        Ok(())
+    }
+
+    /// Links the buffers into a ring and starts the channel on the first one
+    fn start(&mut self) {
+        let regs = unsafe { &*pac::SPI::ptr() };
+        let fifo = &regs.spi_fifo_wdata as *const _ as u32;
+        let control = self.len as u32 | dma::WIDTH_32 | dma::SRC_INCREMENT;
+        let base = dma::bus_address(self.buffer.lli.as_ptr() as usize);
+        for i in 0..2 {
+            self.buffer.lli[i] = Lli {
+                src: dma::bus_address(self.frames[i * self.len..].as_ptr() as usize),
+                dst: fifo,
+                next: 0,
+                control,
+            };
+        }
+        dma::link_ring(&mut self.buffer.lli, base);
+
+        self.saved[2] = regs.spi_config.read().bits();
+        regs.spi_config.modify(|_, w| unsafe {
+            w.cr_spi_frame_size()
+                .bits(1) // 16 bit frames
+                .cr_spi_m_cont_en()
+                .clear_bit() // SS high between frames, latching the sample
+        });
+        self.spi.clear_fifo();
+        // spi_dma_tx_en
+        regs.spi_fifo_config_0
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1) });
+
+        let config = dma::MEMORY_TO_PERIPHERAL | dma::REQ_SPI_TX << dma::DST_PERIPHERAL_POS;
+        unsafe { dma::start(self.channel, &self.buffer.lli[0], config) };
+        self.running = true;
+        self.consumed = 0;
+        self.checked_at = McycleDelay::get_cycle_count();
+    }
+
+    /// Stops the channel and the SPI master and empties the ring
+    fn stop(&mut self) {
+        dma::stop(self.channel);
+        let regs = unsafe { &*pac::SPI::ptr() };
+        regs.spi_fifo_config_0
+            .modify(|r, w| unsafe { w.bits(r.bits() & !1) });
+        self.spi.clear_fifo();
+        regs.spi_config.write(|w| unsafe { w.bits(self.saved[2]) });
+
+        self.running = false;
+        self.written = 0;
+        self.consumed = 0;
+    }
+
+    /// Returns the frames the channel has read since the stream started, stopping the stream
+    /// if it read more than were written
+    ///
+    /// The source address of the channel gives the position in the ring; the cycle count since
+    /// the last check tells how many laps it has gone round.
+    fn consumed(&mut self) -> Result<u64, Error> {
+        let lap = self.frames.len() as u64;
+        let now = McycleDelay::get_cycle_count();
+        // Longer than any ring takes to play, and can't overflow below
+        let elapsed = (now - self.checked_at).min(u32::MAX as u64);
+        let estimate = self.consumed
+            + elapsed * self.spi_clk as u64 / (self.sysclk as u64 * self.frame_cycles as u64);
+
+        let base = dma::bus_address(self.frames.as_ptr() as usize);
+        let position = (dma::src_address(self.channel).wrapping_sub(base) / 4) as u64 % lap;
+        let mut consumed = estimate - estimate % lap + position;
+        if consumed + lap / 2 < estimate {
+            consumed += lap;
+        } else if consumed > estimate + lap / 2 && consumed >= lap {
+            consumed -= lap;
+        }
+        self.consumed = consumed.max(self.consumed);
+        self.checked_at = now;
+
+        if self.consumed > self.written {
+            self.stop();
+            return Err(Error::Underrun);
+        }
+        Ok(self.consumed)
+    }
+}
@@ -0,0 +1,279 @@
+/*!
+  # Shared buses
+  Several drivers can share one I2C or SPI bus through the devices of this module, each of
+  which borrows the bus for one operation at a time. They do what the `RefCellDevice`,
+  `CriticalSectionDevice` and `AtomicDevice` of `embedded-hal-bus` do, for the blocking traits
+  of `embedded-hal` 1.0.0-alpha.6 which the HAL implements; `embedded-hal-bus` needs the
+  traits of `embedded-hal` 1.0.
+
+  The bus is kept in one of the containers which implement [`SharedBus`]:
+
+  - A `RefCell`, like `RefCellDevice`, shared within one context.
+  - A [`Mutex`](crate::sync::Mutex), like `CriticalSectionDevice`, shared with interrupt
+    handlers, every operation in a critical section. It needs the `critical-section` feature.
+  - A [`SpinLock`], like `AtomicDevice`, shared with interrupt handlers without masking them.
+
+  An operation which finds the bus in use fails with [`Error::Busy`], e.g. a handler which
+  interrupted another user of a `SpinLock`. A `RefCell` or `Mutex` can only be found in use if
+  an operation starts another one on the same bus.
+
+  [`I2cDevice`] forwards each operation to the bus with the address given to it. [`SpiDevice`]
+  also owns the chip select of its device and asserts it around each operation, also when the
+  operation fails. The blocking SPI traits have no flush, the HAL's [`Spi`](crate::spi::Spi)
+  only returns from an operation once the last frame has been received, which is after it has
+  been shifted out, so the chip select isn't released early. Other buses need to do the same.
+
+  ## Example
+  ```rust
+    use core::cell::RefCell;
+    use bl602_hal::bus::{I2cDevice, SpiDevice};
+
+    let i2c = RefCell::new(I2c::new(dp.I2C, (scl, sda), 100_000u32.Hz(), clocks));
+    let mut display = Ssd1306::new(I2cDevice::new(&i2c));
+    let mut sensor = Bme280::new(I2cDevice::new(&i2c));
+
+    let spi = Spi::new(dp.SPI, (miso, mosi, sclk), MODE_0, 8_000_000u32.Hz(), clocks);
+    let spi = RefCell::new(spi);
+    let flash = SpiDevice::new(&spi, parts.pin11.into_pull_up_output());
+    let dac = SpiDevice::new(&spi, parts.pin12.into_pull_up_output());
+  ```
+*/
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+
+use embedded_hal::digital::blocking::OutputPin;
+use embedded_hal::i2c::blocking as i2c_blocking;
+use embedded_hal::i2c::{self, AddressMode};
+use embedded_hal::spi;
+use embedded_hal::spi::blocking as spi_blocking;
+
+use crate::sync::SpinLock;
+
+/// Shared bus error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error<E> {
+    /// The operation on the bus failed
+    Bus(E),
+    /// Another user holds the bus
+    Busy,
+}
+
+impl<E: i2c::Error> i2c::Error for Error<E> {
+    fn kind(&self) -> i2c::ErrorKind {
+        match self {
+            Error::Bus(e) => e.kind(),
+            Error::Busy => i2c::ErrorKind::Other,
+        }
+    }
+}
+
+impl<E: spi::Error> spi::Error for Error<E> {
+    fn kind(&self) -> spi::ErrorKind {
+        match self {
+            Error::Bus(e) => e.kind(),
+            Error::Busy => spi::ErrorKind::Other,
+        }
+    }
+}
+
+/// Container giving exclusive access to a bus for one operation at a time
+pub trait SharedBus {
+    /// The bus
+    type Bus;
+
+    /// Runs `f` on the bus, returns `None` if it's in use
+    fn with_bus<R>(&self, f: impl FnOnce(&mut Self::Bus) -> R) -> Option<R>;
+}
+
+impl<BUS> SharedBus for RefCell<BUS> {
+    type Bus = BUS;
+
+    fn with_bus<R>(&self, f: impl FnOnce(&mut BUS) -> R) -> Option<R> {
+        let mut bus = self.try_borrow_mut().ok()?;
+        Some(f(&mut bus))
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl<BUS> SharedBus for crate::sync::Mutex<BUS> {
+    type Bus = BUS;
+
+    fn with_bus<R>(&self, f: impl FnOnce(&mut BUS) -> R) -> Option<R> {
+        Some(self.lock(f))
+    }
+}
+
+impl<BUS> SharedBus for SpinLock<BUS> {
+    type Bus = BUS;
+
+    fn with_bus<R>(&self, f: impl FnOnce(&mut BUS) -> R) -> Option<R> {
+        let mut bus = self.try_lock()?;
+        Some(f(&mut bus))
+    }
+}
+
+/// Runs `f` on the bus of `shared`
+fn on_bus<S: SharedBus, R, E>(
+    shared: &S,
+    f: impl FnOnce(&mut S::Bus) -> Result<R, E>,
+) -> Result<R, Error<E>> {
+    shared.with_bus(f).ok_or(Error::Busy)?.map_err(Error::Bus)
+}
+
+/// Device on a shared I2C bus
+pub struct I2cDevice<'a, S> {
+    bus: &'a S,
+}
+
+impl<'a, S: SharedBus> I2cDevice<'a, S> {
+    /// Returns a device on the bus in `bus`
+    pub fn new(bus: &'a S) -> Self {
+        I2cDevice { bus }
+    }
+}
+
+impl<S, A, E> i2c_blocking::Read<A> for I2cDevice<'_, S>
+where
+    S: SharedBus,
+    S::Bus: i2c_blocking::Read<A, Error = E>,
+    A: AddressMode,
+    E: i2c::Error,
+{
+    type Error = Error<E>;
+
+    fn read(&mut self, address: A, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        on_bus(self.bus, |bus| bus.read(address, buffer))
+    }
+}
+
+impl<S, A, E> i2c_blocking::Write<A> for I2cDevice<'_, S>
+where
+    S: SharedBus,
+    S::Bus: i2c_blocking::Write<A, Error = E>,
+    A: AddressMode,
+    E: i2c::Error,
+{
+    type Error = Error<E>;
+
+    fn write(&mut self, address: A, bytes: &[u8]) -> Result<(), Self::Error> {
+        on_bus(self.bus, |bus| bus.write(address, bytes))
+    }
+}
+
+impl<S, A, E> i2c_blocking::WriteRead<A> for I2cDevice<'_, S>
+where
+    S: SharedBus,
+    S::Bus: i2c_blocking::WriteRead<A, Error = E>,
+    A: AddressMode,
+    E: i2c::Error,
+{
+    type Error = Error<E>;
+
+    fn write_read(
+        &mut self,
+        address: A,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        on_bus(self.bus, |bus| bus.write_read(address, bytes, buffer))
+    }
+}
+
+/// Device on a shared SPI bus, selected by a chip select of its own
+pub struct SpiDevice<'a, S, CS> {
+    bus: &'a S,
+    cs: CS,
+}
+
+impl<'a, S, CS> SpiDevice<'a, S, CS>
+where
+    S: SharedBus,
+    CS: OutputPin<Error = Infallible>,
+{
+    /// Returns a device on the bus in `bus` with the active low chip select `cs`, which is set
+    /// high
+    pub fn new(bus: &'a S, mut cs: CS) -> Self {
+        cs.set_high().ok();
+        SpiDevice { bus, cs }
+    }
+
+    /// Releases the chip select
+    pub fn free(self) -> CS {
+        self.cs
+    }
+
+    /// Runs `f` on the bus with the chip select low
+    fn transaction<R, E>(
+        &mut self,
+        f: impl FnOnce(&mut S::Bus) -> Result<R, E>,
+    ) -> Result<R, Error<E>> {
+        let cs = &mut self.cs;
+        on_bus(self.bus, |bus| {
+            cs.set_low().ok();
+            let result = f(bus);
+            cs.set_high().ok();
+            result
+        })
+    }
+}
+
+impl<S, CS, E> spi_blocking::Transfer<u8> for SpiDevice<'_, S, CS>
+where
+    S: SharedBus,
+    S::Bus: spi_blocking::Transfer<u8, Error = E>,
+    CS: OutputPin<Error = Infallible>,
+    E: spi::Error,
+{
+    type Error = Error<E>;
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        self.transaction(|bus| bus.transfer(read, write))
+    }
+}
+
+impl<S, CS, E> spi_blocking::TransferInplace<u8> for SpiDevice<'_, S, CS>
+where
+    S: SharedBus,
+    S::Bus: spi_blocking::TransferInplace<u8, Error = E>,
+    CS: OutputPin<Error = Infallible>,
+    E: spi::Error,
+{
+    type Error = Error<E>;
+
+    fn transfer_inplace(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.transaction(|bus| bus.transfer_inplace(words))
+    }
+}
+
+impl<S, CS, E> spi_blocking::Write<u8> for SpiDevice<'_, S, CS>
+where
+    S: SharedBus,
+    S::Bus: spi_blocking::Write<u8, Error = E>,
+    CS: OutputPin<Error = Infallible>,
+    E: spi::Error,
+{
+    type Error = Error<E>;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.transaction(|bus| bus.write(words))
+    }
+}
+
+impl<S, CS, E> spi_blocking::WriteIter<u8> for SpiDevice<'_, S, CS>
+where
+    S: SharedBus,
+    S::Bus: spi_blocking::WriteIter<u8, Error = E>,
+    CS: OutputPin<Error = Infallible>,
+    E: spi::Error,
+{
+    type Error = Error<E>;
+
+    fn write_iter<WI>(&mut self, words: WI) -> Result<(), Self::Error>
+    where
+        WI: IntoIterator<Item = u8>,
+    {
+        self.transaction(|bus| bus.write_iter(words))
+    }
+}
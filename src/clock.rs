@@ -24,6 +24,7 @@
 //   - UART using PLL if sysclock is using PLL
 
 use crate::delay::*;
+use crate::efuse;
 use crate::gpio::ClkCfg;
 use crate::pac;
 use core::num::NonZeroU32;
@@ -47,7 +48,7 @@ pub enum SysclkFreq {
 /// Frozen clock frequencies
 ///
 /// The existance of this value indicates that the clock configuration can no longer be changed
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct Clocks {
     sysclk: Hertz,
     uart_clk: Hertz,
@@ -77,6 +78,10 @@ impl Clocks {
         self.pll_enable
     }
 
+    pub(crate) fn xtal_freq(&self) -> Option<Hertz> {
+        self._xtal_freq
+    }
+
     pub const fn uart_clk(&self) -> Hertz {
         self.uart_clk
     }
@@ -96,6 +101,143 @@ impl Default for Clocks {
     }
 }
 
+/// Root clock source of the system clock
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ClockSource {
+    /// Internal 32MHz RC oscillator
+    Rc32m,
+    /// External crystal of the given frequency, without the PLL
+    Xtal(Hertz),
+    /// PLL driven by an external crystal of the given frequency
+    Pll(Hertz),
+}
+
+/// Clock switching error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ClockError {
+    /// The crystal oscillator did not become ready in time
+    OscillatorTimeout,
+    /// The system clock frequency can't be derived from the requested source
+    UnsupportedFrequency,
+    /// The PLL can't run from a crystal of this frequency, only from 24, 26, 32, 38.4 and 40 MHz
+    UnsupportedCrystal,
+    /// The PLL didn't lock, the system clock runs from the RC oscillator
+    PllLock,
+}
+
+impl Clocks {
+    /// Switches the system clock to `src`, running at `freq`
+    ///
+    /// The crystal is powered up and waited for, and the PLL locked, while the CPU still runs
+    /// from the RC oscillator; the clock mux is only switched afterwards, with interrupts
+    /// disabled. [`SysclkFreq`] lists the frequencies the PLL can provide, the RC oscillator and
+    /// the crystal only run at their own frequency.
+    ///
+    /// The bit period of UART0 is rescaled to keep its baudrate. Other drivers compute their
+    /// dividers once when they are constructed, so SPI and I2C instances should be created
+    /// again after switching.
+    ///
+    /// An unsupported frequency or crystal, or a crystal which doesn't start, leaves the current
+    /// configuration in place. If the PLL doesn't lock, the system clock is left on the RC
+    /// oscillator, which `self` then reflects.
+    pub fn switch_clock_source(
+        &mut self,
+        src: ClockSource,
+        freq: impl Into<Hertz>,
+    ) -> Result<(), ClockError> {
+        let freq = freq.into();
+        let supported = match src {
+            ClockSource::Rc32m => freq.0 == RC32M,
+            ClockSource::Xtal(xtal_freq) => freq == xtal_freq,
+            ClockSource::Pll(_) => matches!(freq.0, 48_000_000 | 120_000_000 | 160_000_000),
+        };
+        if !supported {
+            return Err(ClockError::UnsupportedFrequency);
+        }
+        if let ClockSource::Pll(xtal_freq) = src {
+            if rom_xtal_type(xtal_freq.0).is_none() {
+                return Err(ClockError::UnsupportedCrystal);
+            }
+        }
+
+        let old_uart_clk = current_uart_clk();
+
+        // Start the crystal up front, so a timeout leaves the current configuration in place
+        if let ClockSource::Xtal(_) | ClockSource::Pll(_) = src {
+            // `aon_power_on_xtal` gives up after 1.2 ms, allow for a slow crystal
+            let started = (0..4).any(|_| aon_power_on_xtal().is_ok());
+            if !started {
+                return Err(ClockError::OscillatorTimeout);
+            }
+        }
+
+        let result = riscv::interrupt::free(|_| {
+            let result = match src {
+                ClockSource::Rc32m => {
+                    glb_set_system_clk_rc32();
+                    Ok(())
+                }
+                ClockSource::Xtal(xtal_freq) => {
+                    glb_set_system_clk_rc32();
+                    hbn_set_root_clk_sel_xtal();
+                    system_core_clock_set(xtal_freq.0);
+                    Ok(())
+                }
+                ClockSource::Pll(xtal_freq) => glb_set_system_clk_pll(freq.0, xtal_freq.0),
+            };
+
+            let pll_enabled = result.is_ok() && matches!(src, ClockSource::Pll(_));
+            unsafe { &*pac::HBN::ptr() }
+                .hbn_glb
+                .modify(|_, w| w.hbn_uart_clk_sel().bit(pll_enabled));
+
+            rescale_uart_bit_period(old_uart_clk, current_uart_clk());
+            result
+        });
+        // The PLL is switched away from before it's powered up again
+        let (src, freq) = match result {
+            Ok(()) => (src, freq),
+            Err(_) => (ClockSource::Rc32m, Hertz(RC32M)),
+        };
+
+        let glb = unsafe { &*pac::GLB::ptr() };
+        let clk_cfg3 = glb.clk_cfg3.read();
+        let bus_clock = calculate_bus_clock();
+
+        self.sysclk = freq;
+        self.uart_clk = current_uart_clk();
+        self.spi_clk = Hertz(bus_clock.0 / (clk_cfg3.spi_clk_div().bits() as u32 + 1));
+        self.i2c_clk = Hertz(bus_clock.0 / (clk_cfg3.i2c_clk_div().bits() as u32 + 1));
+        self.pll_enable = matches!(src, ClockSource::Pll(_));
+        self._xtal_freq = match src {
+            ClockSource::Xtal(xtal_freq) | ClockSource::Pll(xtal_freq) => Some(xtal_freq),
+            ClockSource::Rc32m => self._xtal_freq,
+        };
+
+        result
+    }
+
+    /// Returns the source the system clock currently runs from
+    pub fn current_source(&self) -> ClockSource {
+        let xtal_freq = self
+            ._xtal_freq
+            .filter(|freq| freq.0 != 0)
+            .unwrap_or(Hertz(40_000_000));
+
+        match unsafe { &*pac::HBN::ptr() }
+            .hbn_glb
+            .read()
+            .hbn_root_clk_sel()
+            .bits()
+        {
+            0 => ClockSource::Rc32m,
+            1 => ClockSource::Xtal(xtal_freq),
+            _ => ClockSource::Pll(xtal_freq),
+        }
+    }
+}
+
 /// Strict clock configurator
 ///
 /// This configurator only accepts strictly accurate value. If all available frequency
@@ -224,7 +366,8 @@ impl Strict {
         if sysclk != SysclkFreq::Pll160Mhz || pll_xtal_freq != 40_000_000 {
             match sysclk {
                 SysclkFreq::Rc32Mhz => glb_set_system_clk_rc32(),
-                _ => glb_set_system_clk_pll(sysclk as u32, pll_xtal_freq),
+                _ => glb_set_system_clk_pll(sysclk as u32, pll_xtal_freq)
+                    .expect("unreachable PLL clock"),
             };
         }
 
@@ -281,6 +424,8 @@ impl Strict {
             .clk_cfg3
             .modify(|_, w| unsafe { w.i2c_clk_en().set_bit().i2c_clk_div().bits(i2c_clk_div) });
 
+        trim_rc32k();
+
         Clocks {
             sysclk: Hertz(sysclk as u32),
             uart_clk: Hertz(uart_clk),
@@ -298,6 +443,27 @@ impl Default for Strict {
     }
 }
 
+/// Sets the 32 kHz RC oscillator to the frequency code trimmed in the factory, returns whether
+/// the eFuses hold one
+///
+/// The oscillator clocks the watchdog and the timers with an `Rc32Khz` source, untrimmed it can
+/// be off by several percent. [`Strict::freeze`] calls this.
+pub fn trim_rc32k() -> bool {
+    let code = match efuse::rc32k_trim() {
+        Some(code) => code,
+        None => return false,
+    };
+
+    // rc32k_ctrl0 of HBN, see bl602_hbn_reg.h of the vendor SDK: rc32k_code_fr_ext in bits 31:22,
+    // rc32k_ext_code_en in bit 19
+    let ctrl0 = (pac::HBN::ptr() as usize + 0x200) as *mut u32;
+    unsafe {
+        let value = ctrl0.read_volatile() & !(0x3ff << 22);
+        ctrl0.write_volatile(value | (code as u32) << 22 | 1 << 19);
+    }
+    true
+}
+
 /// Gets the current bus clock rate
 fn calculate_bus_clock() -> Hertz {
     let root_clk_sel = unsafe { &*pac::GLB::ptr() }
@@ -335,6 +501,73 @@ fn calculate_bus_clock() -> Hertz {
     root / (hclk_div as u32 + 1) / (bclk_div as u32 + 1)
 }
 
+/// Returns the UART clock as currently configured in hardware
+fn current_uart_clk() -> Hertz {
+    let uart_src = if unsafe { &*pac::HBN::ptr() }
+        .hbn_glb
+        .read()
+        .hbn_uart_clk_sel()
+        .bit_is_set()
+    {
+        UART_PLL_FREQ
+    } else {
+        system_core_clock_get()
+    };
+    let uart_clk_div = unsafe { &*pac::GLB::ptr() }
+        .clk_cfg2
+        .read()
+        .uart_clk_div()
+        .bits() as u32
+        + 1;
+
+    Hertz(uart_src / uart_clk_div)
+}
+
+/// Adjusts the bit period of UART0 to a new UART clock, so the baudrate stays the same
+fn rescale_uart_bit_period(old_uart_clk: Hertz, new_uart_clk: Hertz) {
+    if old_uart_clk == new_uart_clk {
+        return;
+    }
+
+    let uart = unsafe { &*pac::UART::ptr() };
+    let rescale = |period: u16| {
+        let cycles = (period as u64 + 1) * new_uart_clk.0 as u64;
+        // Round to nearest, as `Serial::uart0` does
+        let cycles = (cycles + old_uart_clk.0 as u64 / 2) / old_uart_clk.0 as u64;
+        (cycles.max(1).min(65536) - 1) as u16
+    };
+
+    uart.uart_bit_prd.modify(|r, w| unsafe {
+        w.cr_urx_bit_prd()
+            .bits(rescale(r.cr_urx_bit_prd().bits()))
+            .cr_utx_bit_prd()
+            .bits(rescale(r.cr_utx_bit_prd().bits()))
+    });
+}
+
+/// Peripherals on AHB slave 1 with a clock gate bit in `cgen_cfg1`
+#[derive(Copy, Clone)]
+#[repr(u8)]
+pub(crate) enum AhbSlave1 {
+    Sec = 4,
+}
+
+/// Enables or disables the clock of a peripheral on AHB slave 1
+///
+/// This is a reference implementation of `GLB_AHB_Slave1_Clock_Gate`. Note that the C function
+/// takes the inverse argument (whether to *gate* the clock); a set bit means the clock is running.
+pub(crate) fn glb_ahb_slave1_clock_enable(slave: AhbSlave1, enable: bool) {
+    let bit = 1 << slave as u32;
+
+    unsafe { &*pac::GLB::ptr() }.cgen_cfg1.modify(|r, w| unsafe {
+        w.bits(if enable {
+            r.bits() | bit
+        } else {
+            r.bits() & !bit
+        })
+    });
+}
+
 /// Sets the system clock in the (undocumented) system_core_clock register
 fn system_core_clock_set(value: u32) {
     unsafe { &*pac::HBN::ptr() }
@@ -414,36 +647,62 @@ fn pds_power_off_pll() {
 // okay though. So there's probably some steps we need to take to do this safely,
 // but the flash peripheral is not documented yet.
 // The easiest solution is to use the C function built into the ROM to do the change.
+// `pds_power_on_pll` below could become a `ram_function!` once its delays and the
+// panic for unknown frequencies no longer call into flash.
 #[inline]
-fn pds_power_on_pll_rom(freq: u32) {
-    // Lookup table for ROM function addresses is at 0x21010800
-    // index in the table for RomDriver_PDS_Power_On_PLL is 88
-    // each entry is a pointer, size of each entry is 4 bytes (sizeof usize)
-    // we can can use core::ptr::offset to calculate the true offset for us.
+fn pds_power_on_pll_rom(freq: u32) -> Result<(), ClockError> {
+    let romdriver_pds_power_on_pll = rom_pds_power_on_pll();
+    let xtal_src = rom_xtal_type(freq).ok_or(ClockError::UnsupportedCrystal)?;
+
+    // 0 == success, 1 == failure, 2 == timeout
+    match romdriver_pds_power_on_pll(xtal_src) {
+        0 => Ok(()),
+        _ => Err(ClockError::PllLock),
+    }
+}
+
+/// Looks up `RomDriver_PDS_Power_On_PLL` in the ROM function table
+pub(crate) fn rom_pds_power_on_pll() -> extern "C" fn(usize) -> usize {
     // In my ROM, the address of rom_pds_power_on_pll is 0x2101_4ACE,
-    // this line is commented out in case this is not true for others
-    let rom_function_table_base = (0x2101_0800) as *mut usize;
-    let power_on_pll_entry = unsafe { rom_function_table_base.offset(88) };
-    let power_on_pll_addr = unsafe { power_on_pll_entry.read_volatile() };
-    //assert_eq!(power_on_pll_addr, 0x2101_4ACE);
+    // this is not checked in case this is not true for others
+    let power_on_pll_addr = crate::rom::entry(crate::rom::INDEX_PDS_POWER_ON_PLL);
 
-    let romdriver_pds_power_on_pll = unsafe {
+    unsafe {
         core::mem::transmute::<*const (), extern "C" fn(usize) -> usize>(
             power_on_pll_addr as *const (),
         )
-    };
-    let xtal_src = match freq {
-        24_000_000 => 1,
-        32_000_000 => 2,
-        38_400_000 => 3,
-        40_000_000 => 4,
-        26_000_000 => 5,
-        _ => panic!("Unsupported PLL clock source"),
-    };
+    }
+}
 
-    // 0 == success, 1 == failure, 2 == timeout
-    let pll_success = romdriver_pds_power_on_pll(xtal_src);
-    assert_eq!(pll_success, 0);
+/// Returns the ROM driver's `GLB_PLL_XTAL_Type` value for a crystal frequency, `None` if the
+/// PLL can't run from it
+pub(crate) fn rom_xtal_type(freq: u32) -> Option<usize> {
+    match freq {
+        24_000_000 => Some(1),
+        32_000_000 => Some(2),
+        38_400_000 => Some(3),
+        40_000_000 => Some(4),
+        26_000_000 => Some(5),
+        _ => None,
+    }
+}
+
+/// Brings the system clock back to the configuration frozen into `clocks`, e.g. after the
+/// crystal and PLL were powered down for sleeping.
+///
+/// The crystal is powered up and the PLL re-locked while running from the RC oscillator, the
+/// system clock is only switched over afterwards. The peripheral clock dividers are kept by the
+/// hardware and not touched. On an error the system clock is left on the RC oscillator.
+pub(crate) fn restore_system_clk(clocks: &Clocks) -> Result<(), ClockError> {
+    match clocks._xtal_freq {
+        Some(xtal_freq) if clocks.pll_enable => {
+            glb_set_system_clk_pll(clocks.sysclk.0, xtal_freq.0)
+        }
+        _ => {
+            glb_set_system_clk_rc32();
+            Ok(())
+        }
+    }
 }
 
 /// Minimal implementation of power-on pll. Currently only allows external xtal
@@ -603,6 +862,12 @@ fn hbn_set_root_clk_sel_pll() {
     });
 }
 
+fn hbn_set_root_clk_sel_xtal() {
+    unsafe { &*pac::HBN::ptr() }
+        .hbn_glb
+        .modify(|_, w| unsafe { w.hbn_root_clk_sel().bits(0b01u8) });
+}
+
 fn hbn_set_root_clk_sel_rc32() {
     unsafe { &*pac::HBN::ptr() }
         .hbn_glb
@@ -616,7 +881,7 @@ fn pds_enable_pll_all_clks() {
 }
 
 /// Sets the system clock to use the internal 32Mhz RC oscillator
-fn glb_set_system_clk_rc32() {
+pub(crate) fn glb_set_system_clk_rc32() {
     // reg_bclk_en = reg_hclk_en = reg_fclk_en = 1, cannot be zero
     unsafe { &*pac::GLB::ptr() }.clk_cfg0.modify(|_, w| {
         w.reg_bclk_en()
@@ -642,15 +907,26 @@ fn glb_set_system_clk_rc32() {
 }
 
 /// Sets the system clock to use the PLL with external crystal
-fn glb_set_system_clk_pll(target_core_clk: u32, xtal_freq: u32) {
+///
+/// On an error the system clock is left on the RC oscillator.
+fn glb_set_system_clk_pll(target_core_clk: u32, xtal_freq: u32) -> Result<(), ClockError> {
+    let pll_sel = match target_core_clk {
+        48_000_000 => 0,
+        120_000_000 => 1,
+        160_000_000 => 2,
+        // Note that 192Mhz is out of spec
+        192_000_000 => 3,
+        _ => return Err(ClockError::UnsupportedFrequency),
+    };
+
     // Ensure clock is running off internal RC oscillator before changing anything else
     glb_set_system_clk_rc32();
 
     // Power up the external crystal before we start up the PLL
-    aon_power_on_xtal().unwrap();
+    aon_power_on_xtal().map_err(|_| ClockError::OscillatorTimeout)?;
 
     // Power up PLL and enable all PLL clock output
-    pds_power_on_pll_rom(xtal_freq);
+    pds_power_on_pll_rom(xtal_freq)?;
 
     let mut delay = McycleDelay::new(system_core_clock_get());
     delay.delay_us(55).unwrap();
@@ -664,18 +940,9 @@ fn glb_set_system_clk_pll(target_core_clk: u32, xtal_freq: u32) {
 
     // select which pll output clock to use before
     // selecting root clock via HBN_Set_ROOT_CLK_Sel
-    // Note that 192Mhz is out of spec
-    unsafe { &*pac::GLB::ptr() }.clk_cfg0.modify(|_, w| unsafe {
-        w.reg_pll_sel().bits(match target_core_clk {
-            48_000_000 => 0,
-            120_000_000 => 1,
-            160_000_000 => 2,
-            192_000_000 => 3,
-            _ => {
-                panic!()
-            }
-        })
-    });
+    unsafe { &*pac::GLB::ptr() }
+        .clk_cfg0
+        .modify(|_, w| unsafe { w.reg_pll_sel().bits(pll_sel) });
 
     // Keep bclk <= 80MHz
     if target_core_clk > 48_000_000 {
@@ -702,4 +969,6 @@ fn glb_set_system_clk_pll(target_core_clk: u32, xtal_freq: u32) {
     unsafe { &*pac::GLB::ptr() }
         .swrst_cfg2
         .modify(|_, w| w.pka_clk_sel().set_bit());
+
+    Ok(())
 }
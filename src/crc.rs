@@ -0,0 +1,398 @@
+/*!
+  # Cyclic Redundancy Check
+  The BL602 has no CRC engine (the `CKS` block only computes the 16 bit internet checksum, see
+  [`checksum`](crate::checksum)), so CRCs are computed in software with a 256 entry lookup
+  table, which processes one byte per table access.
+
+  Besides the common CRC-32 and CRC-16/CCITT, any CRC of up to 32 bits can be computed by
+  describing it with the usual Rocksoft model parameters, which are listed in the catalogue of
+  parametrised CRC algorithms for most CRCs found in the wild.
+
+  ## Example
+  ```rust
+    use bl602_hal::crc::{Algorithm, Crc};
+
+    let mut crc = Crc::new(Algorithm::Crc32);
+    crc.update(b"12345");
+    crc.update(b"6789");
+    assert_eq!(crc.finish(), 0xcbf4_3926);
+
+    // CRC-16/MODBUS
+    let mut crc = Crc::new(Algorithm::Custom {
+        width: 16,
+        poly: 0x8005,
+        init: 0xffff,
+        refin: true,
+        refout: true,
+        xorout: 0x0000,
+    });
+    crc.update(b"123456789");
+    assert_eq!(crc.finish(), 0x4b37);
+  ```
+*/
+
+/// CRC algorithm
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Algorithm {
+    /// CRC-32 as used by Ethernet, zlib and PNG (CRC-32/ISO-HDLC)
+    ///
+    /// Polynomial 0x04c11db7, reflected, initial value and final XOR 0xffffffff.
+    Crc32,
+    /// CRC-16/CCITT in its most common variant, CRC-16/IBM-3740, which is also known as
+    /// CRC-16/CCITT-FALSE
+    ///
+    /// Polynomial 0x1021, not reflected, initial value 0xffff, no final XOR.
+    Crc16Ccitt,
+    /// CRC described by its Rocksoft model parameters
+    Custom {
+        /// Width of the CRC in bits, from 1 to 32
+        width: u8,
+        /// Generator polynomial in normal (non-reflected) notation, without the leading term
+        poly: u32,
+        /// Initial value of the register, before any reflection
+        init: u32,
+        /// Whether each input byte is processed least significant bit first
+        refin: bool,
+        /// Whether the register is reflected before the final XOR
+        refout: bool,
+        /// Value XORed into the result
+        xorout: u32,
+    },
+}
+
+impl Algorithm {
+    /// Returns the parameters as `(width, poly, init, refin, refout, xorout)`
+    fn parameters(&self) -> (u8, u32, u32, bool, bool, u32) {
+        match *self {
+            Algorithm::Crc32 => (32, 0x04c1_1db7, 0xffff_ffff, true, true, 0xffff_ffff),
+            Algorithm::Crc16Ccitt => (16, 0x1021, 0xffff, false, false, 0x0000),
+            Algorithm::Custom {
+                width,
+                poly,
+                init,
+                refin,
+                refout,
+                xorout,
+            } => (width, poly, init, refin, refout, xorout),
+        }
+    }
+}
+
+/// Software CRC calculator
+///
+/// Each instance holds its own 1 KiB lookup table, which is built by [`Crc::new`].
+pub struct Crc {
+    table: [u32; 256],
+    width: u8,
+    init: u32,
+    refin: bool,
+    refout: bool,
+    xorout: u32,
+    state: u32,
+}
+
+impl Crc {
+    /// Builds the lookup table for `algorithm` and returns a calculator ready to take data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the width of a custom algorithm is 0 or larger than 32 bits.
+    pub fn new(algorithm: Algorithm) -> Self {
+        let (width, poly, init, refin, refout, xorout) = algorithm.parameters();
+        assert!(
+            (1..=32).contains(&width),
+            "CRC width must be between 1 and 32 bits"
+        );
+
+        let mask = mask(width);
+        let poly = poly & mask;
+        let mut table = [0u32; 256];
+
+        if refin {
+            // The register holds the CRC bit reversed, so the table is built from the reversed
+            // polynomial and bytes enter at the least significant end
+            let poly = reflect(poly, width);
+            for (byte, entry) in table.iter_mut().enumerate() {
+                let mut value = byte as u32;
+                for _ in 0..8 {
+                    value = if value & 1 != 0 {
+                        (value >> 1) ^ poly
+                    } else {
+                        value >> 1
+                    };
+                }
+                *entry = value;
+            }
+        } else {
+            // The register holds the CRC left aligned in 32 bits, so narrow CRCs need no special
+            // casing when shifting bytes in
+            let poly = poly << (32 - width as u32);
+            for (byte, entry) in table.iter_mut().enumerate() {
+                let mut value = (byte as u32) << 24;
+                for _ in 0..8 {
+                    value = if value & 0x8000_0000 != 0 {
+                        (value << 1) ^ poly
+                    } else {
+                        value << 1
+                    };
+                }
+                *entry = value;
+            }
+        }
+
+        let mut crc = Crc {
+            table,
+            width,
+            init: init & mask,
+            refin,
+            refout,
+            xorout: xorout & mask,
+            state: 0,
+        };
+        crc.reset();
+
+        crc
+    }
+
+    /// Restarts the calculation, discarding the data processed so far
+    pub fn reset(&mut self) {
+        self.state = if self.refin {
+            reflect(self.init, self.width)
+        } else {
+            self.init << (32 - self.width as u32)
+        };
+    }
+
+    /// Feeds `data` into the calculation
+    pub fn update(&mut self, data: &[u8]) {
+        let mut state = self.state;
+
+        if self.refin {
+            for &byte in data {
+                state = (state >> 8) ^ self.table[((state ^ byte as u32) & 0xff) as usize];
+            }
+        } else {
+            for &byte in data {
+                state = (state << 8) ^ self.table[((state >> 24) ^ byte as u32) as usize];
+            }
+        }
+
+        self.state = state;
+    }
+
+    /// Returns the CRC of the data processed since construction or the last reset
+    ///
+    /// The calculation isn't finished by this, so more data can be fed in afterwards to get the
+    /// CRC of the longer message.
+    pub fn finish(&self) -> u32 {
+        // Bring the register into normal bit order first
+        let value = if self.refin {
+            reflect(self.state, self.width)
+        } else {
+            self.state >> (32 - self.width as u32)
+        };
+
+        let value = if self.refout {
+            reflect(value, self.width)
+        } else {
+            value
+        };
+
+        (value ^ self.xorout) & mask(self.width)
+    }
+
+    /// Computes the CRC of `data` in one go
+    pub fn checksum(algorithm: Algorithm, data: &[u8]) -> u32 {
+        let mut crc = Crc::new(algorithm);
+        crc.update(data);
+        crc.finish()
+    }
+}
+
+/// Returns a mask of the lowest `width` bits
+#[inline(always)]
+fn mask(width: u8) -> u32 {
+    u32::MAX >> (32 - width as u32)
+}
+
+/// Reverses the order of the lowest `width` bits of `value`
+#[inline(always)]
+fn reflect(value: u32, width: u8) -> u32 {
+    value.reverse_bits() >> (32 - width as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Algorithm, Crc};
+
+    /// Input of the check values in the catalogue of parametrised CRC algorithms
+    const CHECK_INPUT: &[u8] = b"123456789";
+
+    /// Algorithms and their check values, one of each combination of `refin` and `refout`
+    const CHECKS: &[(Algorithm, u32)] = &[
+        (Algorithm::Crc32, 0xcbf4_3926),
+        (Algorithm::Crc16Ccitt, 0x29b1),
+        // CRC-16/KERMIT
+        (
+            Algorithm::Custom {
+                width: 16,
+                poly: 0x1021,
+                init: 0x0000,
+                refin: true,
+                refout: true,
+                xorout: 0x0000,
+            },
+            0x2189,
+        ),
+        // CRC-16/XMODEM
+        (
+            Algorithm::Custom {
+                width: 16,
+                poly: 0x1021,
+                init: 0x0000,
+                refin: false,
+                refout: false,
+                xorout: 0x0000,
+            },
+            0x31c3,
+        ),
+        // CRC-16/MODBUS
+        (
+            Algorithm::Custom {
+                width: 16,
+                poly: 0x8005,
+                init: 0xffff,
+                refin: true,
+                refout: true,
+                xorout: 0x0000,
+            },
+            0x4b37,
+        ),
+        // CRC-32/MPEG-2
+        (
+            Algorithm::Custom {
+                width: 32,
+                poly: 0x04c1_1db7,
+                init: 0xffff_ffff,
+                refin: false,
+                refout: false,
+                xorout: 0x0000_0000,
+            },
+            0x0376_e6e7,
+        ),
+        // CRC-32C
+        (
+            Algorithm::Custom {
+                width: 32,
+                poly: 0x1edc_6f41,
+                init: 0xffff_ffff,
+                refin: true,
+                refout: true,
+                xorout: 0xffff_ffff,
+            },
+            0xe306_9283,
+        ),
+        // CRC-8/SMBUS
+        (
+            Algorithm::Custom {
+                width: 8,
+                poly: 0x07,
+                init: 0x00,
+                refin: false,
+                refout: false,
+                xorout: 0x00,
+            },
+            0xf4,
+        ),
+        // CRC-5/USB, narrower than a byte
+        (
+            Algorithm::Custom {
+                width: 5,
+                poly: 0x05,
+                init: 0x1f,
+                refin: true,
+                refout: true,
+                xorout: 0x1f,
+            },
+            0x19,
+        ),
+        // CRC-12/UMTS, reflected output of a non-reflected register
+        (
+            Algorithm::Custom {
+                width: 12,
+                poly: 0x80f,
+                init: 0x000,
+                refin: false,
+                refout: true,
+                xorout: 0x000,
+            },
+            0xdaf,
+        ),
+        // Not in the catalogue: CRC-16/KERMIT without reflecting the output, which is its check
+        // value bit reversed
+        (
+            Algorithm::Custom {
+                width: 16,
+                poly: 0x1021,
+                init: 0x0000,
+                refin: true,
+                refout: false,
+                xorout: 0x0000,
+            },
+            0x9184,
+        ),
+    ];
+
+    #[test]
+    fn check_values() {
+        for &(algorithm, check) in CHECKS {
+            assert_eq!(
+                Crc::checksum(algorithm, CHECK_INPUT),
+                check,
+                "{:?}",
+                algorithm
+            );
+        }
+    }
+
+    /// Feeding the input in two pieces, split anywhere, gives the same CRC as one piece
+    #[test]
+    fn split_updates() {
+        for &(algorithm, check) in CHECKS {
+            for split in 0..=CHECK_INPUT.len() {
+                let mut crc = Crc::new(algorithm);
+                crc.update(&CHECK_INPUT[..split]);
+                crc.update(&CHECK_INPUT[split..]);
+                assert_eq!(crc.finish(), check, "{:?} split at {}", algorithm, split);
+            }
+        }
+    }
+
+    #[test]
+    fn finish_and_reset() {
+        let mut crc = Crc::new(Algorithm::Crc32);
+        crc.update(&CHECK_INPUT[..4]);
+        assert_eq!(crc.finish(), Crc::checksum(Algorithm::Crc32, b"1234"));
+        // Finishing doesn't end the calculation
+        crc.update(&CHECK_INPUT[4..]);
+        assert_eq!(crc.finish(), 0xcbf4_3926);
+
+        crc.reset();
+        assert_eq!(crc.finish(), Crc::checksum(Algorithm::Crc32, &[]));
+        crc.update(CHECK_INPUT);
+        assert_eq!(crc.finish(), 0xcbf4_3926);
+    }
+
+    #[test]
+    #[should_panic]
+    fn width_too_large() {
+        Crc::new(Algorithm::Custom {
+            width: 33,
+            poly: 0x1,
+            init: 0x0,
+            refin: false,
+            refout: false,
+            xorout: 0x0,
+        });
+    }
+}
@@ -0,0 +1,146 @@
+/*!
+  # Debug output
+  A single place for the HAL and the application to print to, e.g. from interrupt handlers, the
+  fault handler of [`trap`](crate::trap) and, with the `panic-handler` feature, the panic handler
+  of the HAL. The output goes to a writer function registered with [`set_writer`], or nowhere
+  until one is registered. [`Serial::into_debug_writer`](crate::serial::Serial::into_debug_writer)
+  registers UART0.
+
+  ## Example
+  ```rust
+    serial.into_debug_writer();
+
+    bl602_hal::debug_println!("clocks: {} Hz", clocks.sysclk().0);
+  ```
+
+  # Re-entrancy
+  Output can be started from every context, and a context can preempt another one in the middle
+  of writing, e.g. an interrupt handler which prints while `main` prints. The writer is never
+  entered twice: a write which starts while another one is in progress is skipped and counted
+  in [`skipped`], so the line being written isn't torn apart and a writer holding a lock can't
+  deadlock against itself.
+
+  Fatal output, a panic or a fault, can't be skipped, and the code it interrupted never runs
+  again. [`write_fatal`] therefore calls the writer even while another write is in progress, and
+  [`is_fatal`] tells the writer that whatever the interrupted write holds is abandoned. The UART
+  writer then stops waiting for its lock and writes to the UART's registers directly: a panic in
+  the middle of a line, e.g. in the UART0 interrupt handler while `main` prints, shows up as the
+  line cut short, followed by the panic message.
+*/
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+/// Writer function, 0 if there is none
+static WRITER: AtomicUsize = AtomicUsize::new(0);
+/// Whether a write is in progress
+static BUSY: AtomicBool = AtomicBool::new(false);
+static FATAL: AtomicBool = AtomicBool::new(false);
+static SKIPPED: AtomicU32 = AtomicU32::new(0);
+
+/// Registers `writer`, which gets the output piece by piece, replacing the one registered before
+///
+/// The writer can be called from any context, including the fault handler, so it can't wait
+/// for interrupts. It has to return without waiting for locks once [`is_fatal`] is `true`.
+pub fn set_writer(writer: fn(&str)) {
+    WRITER.store(writer as usize, Ordering::Release);
+}
+
+/// Removes the writer, the output is dropped from now on
+pub fn clear_writer() {
+    WRITER.store(0, Ordering::Release);
+}
+
+/// Writes `args` with the writer, returns `false` if there is none or the write was skipped
+///
+/// A write is skipped while another one is in progress, see the module documentation.
+pub fn write_fmt(args: fmt::Arguments<'_>) -> bool {
+    let writer = match writer() {
+        Some(writer) => writer,
+        None => return false,
+    };
+
+    if BUSY.swap(true, Ordering::Acquire) {
+        SKIPPED.fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+    fmt::write(&mut Sink(writer), args).ok();
+    BUSY.store(false, Ordering::Release);
+
+    true
+}
+
+/// Writes `args` with the writer even if another write is in progress
+///
+/// For output after which the interrupted code doesn't continue, e.g. the message of a panic
+/// handler which halts or resets.
+pub fn write_fatal(args: fmt::Arguments<'_>) {
+    FATAL.store(true, Ordering::Release);
+    BUSY.store(true, Ordering::Release);
+
+    if let Some(writer) = writer() {
+        fmt::write(&mut Sink(writer), args).ok();
+    }
+}
+
+/// Returns whether [`write_fatal`] has been called, after which the writer must not wait for
+/// anything the interrupted code might hold
+pub fn is_fatal() -> bool {
+    FATAL.load(Ordering::Acquire)
+}
+
+/// Returns whether a write is in progress, a write started now would be skipped
+pub fn is_busy() -> bool {
+    BUSY.load(Ordering::Acquire)
+}
+
+/// Returns how many writes were skipped because another one was in progress
+pub fn skipped() -> u32 {
+    SKIPPED.load(Ordering::Relaxed)
+}
+
+/// Writes a line to the debug writer, see [`debug`](crate::debug)
+///
+/// Takes the same arguments as `println!` and appends `"\r\n"`. Evaluates to `false` if the
+/// line was dropped.
+#[macro_export]
+macro_rules! debug_println {
+    () => {
+        $crate::debug::write_fmt(format_args!("\r\n"))
+    };
+    ($fmt:expr) => {
+        $crate::debug::write_fmt(format_args!(concat!($fmt, "\r\n")))
+    };
+    ($fmt:expr, $($arg:tt)*) => {
+        $crate::debug::write_fmt(format_args!(concat!($fmt, "\r\n"), $($arg)*))
+    };
+}
+
+fn writer() -> Option<fn(&str)> {
+    let writer = WRITER.load(Ordering::Acquire);
+    if writer != 0 {
+        Some(unsafe { core::mem::transmute::<usize, fn(&str)>(writer) })
+    } else {
+        None
+    }
+}
+
+struct Sink(fn(&str));
+
+impl fmt::Write for Sink {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        (self.0)(s);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "panic-handler")]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
+    unsafe { riscv::interrupt::disable() };
+    write_fatal(format_args!("{}\r\n", info));
+
+    loop {
+        unsafe { riscv::asm::wfi() };
+    }
+}
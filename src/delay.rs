@@ -1,19 +1,25 @@
 //! Delays
 
 use core::convert::Infallible;
-use embedded_hal::delay::blocking::{DelayMs, DelayUs};
+use embedded_hal::delay::blocking::DelayUs;
 
 /// Use RISCV machine-mode cycle counter (`mcycle`) as a delay provider.
 ///
 /// This can be used for high resolution delays for device initialization,
 /// bit-banging protocols, etc
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug)]
 pub struct McycleDelay {
     core_frequency: u32,
 }
 
 impl McycleDelay {
     /// Constructs the delay provider based on core clock frequency `freq`
+    ///
+    /// `mcycle` counts cycles of the core clock, not of the bus clock, so `freq` has to be the
+    /// system clock the core actually runs at, e.g. `clocks.sysclk().0`. It isn't checked: a wrong
+    /// value, or one left over from before the system clock was changed, scales every delay by
+    /// the same factor. Delays end within a few cycles after the requested count, the time it
+    /// takes to read `mcycle` and compare it each time around the loop.
     pub fn new(freq: u32) -> Self {
         Self {
             /// System clock frequency, used to convert clock cycles
@@ -22,6 +28,11 @@ impl McycleDelay {
         }
     }
 
+    /// Returns the core clock frequency the delays are based on
+    pub fn frequency(&self) -> u32 {
+        self.core_frequency
+    }
+
     /// Retrieves the cycle count for the current HART
     #[inline]
     pub fn get_cycle_count() -> u64 {
@@ -29,6 +40,10 @@ impl McycleDelay {
     }
 
     /// Returns the number of elapsed cycles since `previous_cycle_count`
+    ///
+    /// The subtraction wraps, so the result is right even if the 64 bit counter overflowed in
+    /// between. Only intervals longer than a whole turn of the counter, about 3600 years at
+    /// 160 MHz, come out wrong.
     #[inline]
     pub fn cycles_since(previous_cycle_count: u64) -> u64 {
         riscv::register::mcycle::read64().wrapping_sub(previous_cycle_count)
@@ -41,27 +56,36 @@ impl McycleDelay {
 
         while McycleDelay::cycles_since(start_cycle_count) <= cycle_count {}
     }
+
+    /// Performs a busy-wait loop until the number of nanoseconds `ns` has elapsed
+    ///
+    /// Delays shorter than one core clock cycle are rounded up to one cycle.
+    ///
+    /// embedded-hal 1.0 will provide this as `DelayNs::delay_ns`, the version currently used by
+    /// the HAL only has `DelayUs`.
+    #[inline]
+    pub fn delay_ns(&mut self, ns: u32) {
+        let cycles = (ns as u64 * (self.core_frequency as u64)) / 1_000_000_000;
+
+        McycleDelay::delay_cycles(cycles.max(1));
+    }
 }
 
-impl DelayUs<u64> for McycleDelay {
+impl DelayUs for McycleDelay {
     type Error = Infallible;
 
     /// Performs a busy-wait loop until the number of microseconds `us` has elapsed
     #[inline]
-    fn delay_us(&mut self, us: u64) -> Result<(), Infallible> {
-        McycleDelay::delay_cycles((us * (self.core_frequency as u64)) / 1_000_000);
+    fn delay_us(&mut self, us: u32) -> Result<(), Infallible> {
+        McycleDelay::delay_cycles((us as u64 * (self.core_frequency as u64)) / 1_000_000);
 
         Ok(())
     }
-}
-
-impl DelayMs<u64> for McycleDelay {
-    type Error = Infallible;
 
     /// Performs a busy-wait loop until the number of milliseconds `ms` has elapsed
     #[inline]
-    fn delay_ms(&mut self, ms: u64) -> Result<(), Infallible> {
-        McycleDelay::delay_cycles((ms * (self.core_frequency as u64)) / 1000);
+    fn delay_ms(&mut self, ms: u32) -> Result<(), Infallible> {
+        McycleDelay::delay_cycles((ms as u64 * (self.core_frequency as u64)) / 1000);
 
         Ok(())
     }
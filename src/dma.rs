@@ -0,0 +1,182 @@
+//! Access to the DMA controller for the drivers which use it
+//!
+//! The HAL has no DMA driver of its own: a driver which moves data with DMA takes `pac::DMA`,
+//! or borrows it, to show that nobody else programs the controller, and uses these helpers to
+//! load a channel with a linked list item and follow it.
+//!
+//! The core sees the TCM at `0x2201_0000`, the DMA controller only through the bus at
+//! `0x4201_0000`, so addresses of buffers and linked list items in RAM are translated with
+//! [`bus_address`].
+
+use crate::regs;
+
+// Offsets of the DMA controller registers
+const ENBLD_CHNS: usize = 0x01c;
+const TOP_CONFIG: usize = 0x030;
+const C0_SRC: usize = 0x100;
+const CHANNEL_STRIDE: usize = 0x100;
+
+// Offsets in a channel's registers
+const SRC: usize = 0x00;
+const DST: usize = 0x04;
+const LLI: usize = 0x08;
+const CONTROL: usize = 0x0c;
+const CONFIG: usize = 0x10;
+
+/// Number of channels
+pub(crate) const CHANNELS: usize = 4;
+/// Most transfers one linked list item can move
+pub(crate) const MAX_TRANSFERS: usize = 0xfff;
+
+/// Request line of the SPI TX FIFO
+pub(crate) const REQ_SPI_TX: u32 = 11;
+
+// Control word
+/// Width of the source and destination transfers
+pub(crate) const WIDTH_8: u32 = 0;
+pub(crate) const WIDTH_32: u32 = 2 << 18 | 2 << 21;
+/// SI, source increment
+pub(crate) const SRC_INCREMENT: u32 = 1 << 26;
+/// DI, destination increment
+pub(crate) const DST_INCREMENT: u32 = 1 << 27;
+
+// Config word
+/// E, channel enable
+pub(crate) const ENABLE: u32 = 1;
+/// FlowCntrl, memory to memory
+pub(crate) const MEMORY_TO_MEMORY: u32 = 0;
+/// FlowCntrl, memory to peripheral
+pub(crate) const MEMORY_TO_PERIPHERAL: u32 = 1 << 11;
+/// Position of DstPeripheral
+pub(crate) const DST_PERIPHERAL_POS: u32 = 6;
+
+/// A linked list item, the layout the DMA controller loads
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub(crate) struct Lli {
+    pub src: u32,
+    pub dst: u32,
+    /// Bus address of the next item, 0 to end the chain
+    pub next: u32,
+    pub control: u32,
+}
+
+/// Links `items`, which the DMA controller sees at `base`, into a ring, each one pointing at
+/// the next and the last one at the first
+pub(crate) fn link_ring(items: &mut [Lli], base: u32) {
+    let len = items.len();
+    for (i, item) in items.iter_mut().enumerate() {
+        item.next = base + ((i + 1) % len * core::mem::size_of::<Lli>()) as u32;
+    }
+}
+
+/// Returns the address at which the DMA controller sees `address`
+pub(crate) fn bus_address(address: usize) -> u32 {
+    if (0x2200_0000..0x2300_0000).contains(&address) {
+        (address + 0x2000_0000) as u32
+    } else {
+        address as u32
+    }
+}
+
+/// Loads `first` into `channel` and enables it with `config`
+///
+/// # Safety
+///
+/// The caller owns the channel, and everything `first` points to stays valid until the channel
+/// is disabled again.
+pub(crate) unsafe fn start(channel: usize, first: &Lli, config: u32) {
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+
+    // E, enable the controller
+    let top = (regs::dma_base() + TOP_CONFIG) as *mut u32;
+    top.write_volatile(top.read_volatile() | 1);
+
+    reg(channel, SRC).write_volatile(first.src);
+    reg(channel, DST).write_volatile(first.dst);
+    reg(channel, LLI).write_volatile(first.next);
+    reg(channel, CONTROL).write_volatile(first.control);
+    reg(channel, CONFIG).write_volatile(config | ENABLE);
+}
+
+/// Returns whether `channel` is still busy with its chain
+pub(crate) fn is_enabled(channel: usize) -> bool {
+    let enabled = unsafe { ((regs::dma_base() + ENBLD_CHNS) as *const u32).read_volatile() };
+    enabled & (1 << channel) != 0
+}
+
+/// Returns the bus address of the item `channel` loads next, 0 with the last one loaded
+pub(crate) fn next_lli(channel: usize) -> u32 {
+    unsafe { reg(channel, LLI).read_volatile() }
+}
+
+/// Returns the bus address `channel` reads from next
+pub(crate) fn src_address(channel: usize) -> u32 {
+    unsafe { reg(channel, SRC).read_volatile() }
+}
+
+/// Disables `channel`, dropping what's left in its FIFO
+pub(crate) fn stop(channel: usize) {
+    unsafe {
+        let config = reg(channel, CONFIG);
+        config.write_volatile(config.read_volatile() & !ENABLE);
+    }
+}
+
+fn reg(channel: usize, offset: usize) -> *mut u32 {
+    (regs::dma_base() + C0_SRC + channel * CHANNEL_STRIDE + offset) as *mut u32
+}
+
+#[cfg(all(test, feature = "mock-registers"))]
+mod tests {
+    use super::*;
+
+    fn register(offset: usize) -> u32 {
+        unsafe { ((regs::dma_base() + offset) as *const u32).read_volatile() }
+    }
+
+    #[test]
+    fn descriptors() {
+        let _registers = regs::lock();
+
+        assert_eq!(bus_address(0x2201_0040), 0x4201_0040);
+        assert_eq!(bus_address(0x4201_0040), 0x4201_0040);
+        assert_eq!(bus_address(0x4000_a488), 0x4000_a488);
+
+        let mut ring = [Lli {
+            src: 0x4201_1000,
+            dst: 0x4000_a488,
+            next: 0,
+            control: 64 | WIDTH_32 | SRC_INCREMENT,
+        }; 3];
+        link_ring(&mut ring, 0x4201_0000);
+        assert_eq!(ring[0].next, 0x4201_0010);
+        assert_eq!(ring[1].next, 0x4201_0020);
+        assert_eq!(ring[2].next, 0x4201_0000);
+        assert_eq!(ring[0].control, 0x0448_0040);
+
+        let mut single = [Lli::default()];
+        link_ring(&mut single, 0x4201_0100);
+        assert_eq!(single[0].next, 0x4201_0100);
+
+        let config = MEMORY_TO_PERIPHERAL | REQ_SPI_TX << DST_PERIPHERAL_POS;
+        unsafe { start(1, &ring[0], config) };
+        assert_eq!(register(TOP_CONFIG), 1, "controller enabled");
+        assert_eq!(register(0x200), 0x4201_1000);
+        assert_eq!(register(0x204), 0x4000_a488);
+        assert_eq!(register(0x208), 0x4201_0010);
+        assert_eq!(register(0x20c), 0x0448_0040);
+        assert_eq!(register(0x210), 0x0000_0ac1);
+        assert_eq!(register(0x110), 0, "channel 0 left alone");
+        assert_eq!(next_lli(1), 0x4201_0010);
+        assert_eq!(src_address(1), 0x4201_1000);
+
+        assert!(!is_enabled(1));
+        unsafe { ((regs::dma_base() + ENBLD_CHNS) as *mut u32).write_volatile(1 << 1) };
+        assert!(is_enabled(1));
+        assert!(!is_enabled(0));
+
+        stop(1);
+        assert_eq!(register(0x210), 0x0000_0ac0);
+    }
+}
@@ -0,0 +1,605 @@
+/*!
+  # eFuse
+  The eFuse array holds the chip configuration, trim values, the MAC address and the key slots
+  for the AES engine. The boot ROM loads the array into shadow registers at reset.
+
+  Each key slot can be read-protected. A protected slot reads back as zeros, but the AES engine
+  can still use the key stored in it, see `Aes::with_efuse_key`.
+
+  # Reading
+  [`read_mac`], [`chip_id`], [`adc_trim`], [`rc32k_trim`], [`tsen_trim`] and [`read_word`] load
+  the array into the shadow registers again before reading, with the read sequence of the eFuse
+  controller: switch its clock, trigger the load and wait for it. The shadow registers can hold
+  stale data otherwise, e.g. after a burn or an interrupted load. [`EfuseData`] keeps a copy of
+  the whole data block and decodes it, also from a dump taken elsewhere:
+
+  | Field       | Words         | Bits                                                      |
+  |-------------|---------------|-----------------------------------------------------------|
+  | MAC slot 0  | `0x14` `0x18` | 48 bit address, count of its zero bits in 21:16 of `0x18` |
+  | MAC slot 1  | `0x74` `0x78` | like slot 0, burnt later to replace the factory address   |
+  | ADC gain    | `0x6c`        | signed coefficient 11:0, parity 12, enable 13             |
+  | TSEN code   | `0x6c`        | reference code 25:14, parity 26, enable 27                |
+  | RC32K trim  | `0x70`        | frequency code 9:0, parity 10, enable 11                  |
+
+  A trim is only used if its enable bit is set and its parity bit matches the number of set bits
+  in its value; a MAC slot if the zero count matches and the address isn't all zeros. The chip ID
+  is the raw content of MAC slot 0, including the zero count.
+
+  # Burning
+  With the `efuse-write` feature, a `Writer` burns bits into the array, e.g. to provision a MAC
+  address and a serial number on the production line. A bit which has been burnt stays set for
+  the life of the chip, there is no way to clear it again. The writer only sets the bits a value
+  is missing, refuses words which already have other bits set and reads every word back after
+  burning it. The configuration word `0x00`, which enables secure boot and disables JTAG, and the
+  lock word `0x7c`, which makes key slots unreadable and words unwritable, are only burnt by
+  `Writer::burn_lock`. The user data of `Writer::burn_user_data` goes to `0x5c` to `0x6b`,
+  the words of the key slot the AES engine doesn't use.
+
+  ## Example
+  ```rust
+    use bl602_hal::efuse::{self, KeySlot};
+
+    let mac = efuse::read_mac().unwrap();
+
+    if efuse::key_slot_locked(KeySlot::Slot2) {
+        // The key is only usable by the AES engine
+    } else if !efuse::key_slot_programmed(KeySlot::Slot2).unwrap() {
+        // Nothing burnt into the slot yet
+    }
+  ```
+*/
+
+use crate::pac;
+
+/// Offset of key slot 0 in the eFuse data block, each slot is 16 bytes
+const KEY_SLOT_0_OFFSET: usize = 0x1c;
+
+/// Number of 32 bit words in the eFuse data block
+pub const WORDS: usize = 32;
+
+// Offsets in the data block, see the module documentation
+const MAC_SLOT_0: (usize, usize) = (0x14, 0x18);
+const MAC_SLOT_1: (usize, usize) = (0x74, 0x78);
+const TRIM_0: usize = 0x6c;
+const TRIM_1: usize = 0x70;
+
+// ef_if_ctrl_0
+const EF_IF_0_AUTOLOAD_DONE: u32 = 1 << 1;
+const EF_IF_0_BUSY: u32 = 1 << 2;
+const EF_IF_0_TRIG: u32 = 1 << 4;
+const EF_IF_AUTO_RD_EN: u32 = 1 << 18;
+const EF_IF_0_INT_CLR: u32 = 1 << 21;
+/// Protection codes which let the control bits be written
+const EF_IF_PROTECT: u32 = 0xbf << 8 | 0xbf << 24;
+
+/// Polling iterations to wait for a load, which takes a few microseconds
+const LOAD_TIMEOUT: u32 = 100_000;
+
+/// eFuse error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The shadow registers haven't been loaded from the eFuse array
+    NotLoaded,
+    /// The slot is read-protected, so its contents can't be inspected
+    ReadLocked,
+    /// The eFuse controller didn't finish loading the array in time
+    Timeout,
+    /// The offset isn't word aligned or beyond the data block
+    InvalidOffset,
+    /// Neither MAC slot holds a valid address
+    NoMac,
+    /// The word already has bits burnt which the value doesn't have, or the field is in use
+    AlreadyProgrammed,
+    /// The words read back after burning don't hold the value, e.g. because they are
+    /// write-locked
+    VerifyFailed,
+    /// The word holds lock or security bits, which only `Writer::burn_lock` burns
+    LockWord,
+    /// The value can't be burnt, e.g. an all-zero MAC address or more user data than fits
+    InvalidData,
+}
+
+/// eFuse key slots usable by the AES engine
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum KeySlot {
+    Slot0 = 0,
+    Slot1 = 1,
+    Slot2 = 2,
+    Slot3 = 3,
+}
+
+/// Gain correction of the ADC, see [`adc_trim`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct AdcTrim {
+    /// Deviation of the gain from 1 in steps of 1/2048, a positive value for a gain below 1
+    pub gain_coefficient: i16,
+}
+
+impl AdcTrim {
+    /// Returns the conversion result `raw` corrected for the gain error
+    pub fn correct(&self, raw: i32) -> i32 {
+        raw * 2048 / (2048 - i32::from(self.gain_coefficient))
+    }
+}
+
+/// Copy of the eFuse data block, see the module documentation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct EfuseData {
+    words: [u32; WORDS],
+}
+
+impl EfuseData {
+    /// Loads the array and returns the data block
+    pub fn read() -> Result<Self, Error> {
+        load()?;
+
+        let base = pac::EF_DATA_0::ptr() as *const u32;
+        let mut words = [0; WORDS];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = unsafe { base.add(i).read_volatile() };
+        }
+        Ok(EfuseData { words })
+    }
+
+    /// Wraps the words of a data block, e.g. a dump of another chip
+    pub const fn from_words(words: [u32; WORDS]) -> Self {
+        EfuseData { words }
+    }
+
+    /// Returns the word at byte offset `offset`
+    pub fn word(&self, offset: usize) -> Result<u32, Error> {
+        if offset % 4 != 0 || offset / 4 >= WORDS {
+            return Err(Error::InvalidOffset);
+        }
+        Ok(self.words[offset / 4])
+    }
+
+    /// Returns the MAC address of slot 1 if it's valid, otherwise the one of slot 0
+    pub fn mac(&self) -> Result<[u8; 6], Error> {
+        self.mac_slot(MAC_SLOT_1)
+            .or_else(|| self.mac_slot(MAC_SLOT_0))
+            .ok_or(Error::NoMac)
+    }
+
+    /// Returns the raw content of MAC slot 0, which is unique to the chip
+    pub fn chip_id(&self) -> [u8; 8] {
+        let low = self.words[MAC_SLOT_0.0 / 4].to_le_bytes();
+        let high = self.words[MAC_SLOT_0.1 / 4].to_le_bytes();
+        [
+            low[0], low[1], low[2], low[3], high[0], high[1], high[2], high[3],
+        ]
+    }
+
+    /// Returns the gain correction of the ADC, `None` if it isn't burnt or its parity is wrong
+    pub fn adc_trim(&self) -> Option<AdcTrim> {
+        let code = trim(self.words[TRIM_0 / 4], 0, 12)?;
+        // Sign extension of the 12 bit coefficient
+        let gain_coefficient = ((code << 4) as i16) >> 4;
+        Some(AdcTrim { gain_coefficient })
+    }
+
+    /// Returns the reference code of the temperature sensor, `None` if it isn't burnt or its
+    /// parity is wrong
+    pub fn tsen_trim(&self) -> Option<u16> {
+        trim(self.words[TRIM_0 / 4], 14, 12)
+    }
+
+    /// Returns the frequency code of the 32 kHz RC oscillator, `None` if it isn't burnt or its
+    /// parity is wrong
+    pub fn rc32k_trim(&self) -> Option<u16> {
+        trim(self.words[TRIM_1 / 4], 0, 10)
+    }
+
+    fn mac_slot(&self, (low, high): (usize, usize)) -> Option<[u8; 6]> {
+        let low = self.words[low / 4];
+        let high = self.words[high / 4];
+        let mac = [
+            low as u8,
+            (low >> 8) as u8,
+            (low >> 16) as u8,
+            (low >> 24) as u8,
+            high as u8,
+            (high >> 8) as u8,
+        ];
+        let zeros = (!low).count_ones() + (!high & 0xffff).count_ones();
+
+        if low == 0 && high & 0xffff == 0 || (high >> 16) & 0x3f != zeros {
+            None
+        } else {
+            Some(mac)
+        }
+    }
+}
+
+/// Returns whether the shadow registers hold the eFuse contents
+///
+/// The boot ROM loads them before the application starts, so this only fails if the load was
+/// interrupted or the array is being reloaded.
+pub fn is_loaded() -> bool {
+    let ef_ctrl = unsafe { &*pac::EF_CTRL::ptr() };
+
+    // ef_if_0_autoload_done
+    ef_ctrl.ef_if_ctrl_0.read().bits() & (1 << 1) != 0
+}
+
+/// Returns whether `slot` is read-protected
+pub fn key_slot_locked(slot: KeySlot) -> bool {
+    let ef_data = unsafe { &*pac::EF_DATA_0::ptr() };
+
+    // rd_lock_key_slot_0 to rd_lock_key_slot_3
+    ef_data.ef_data_0_lock.read().bits() & (1 << (27 + slot as u32)) != 0
+}
+
+/// Returns whether any bit of `slot` has been burnt
+///
+/// The contents of a read-protected slot can't be checked, that case is reported as
+/// [`Error::ReadLocked`].
+pub fn key_slot_programmed(slot: KeySlot) -> Result<bool, Error> {
+    if !is_loaded() {
+        return Err(Error::NotLoaded);
+    }
+    if key_slot_locked(slot) {
+        return Err(Error::ReadLocked);
+    }
+
+    let base = pac::EF_DATA_0::ptr() as *const u32;
+    let offset = (KEY_SLOT_0_OFFSET + slot as usize * 16) / 4;
+    let programmed = (0..4).any(|word| unsafe { base.add(offset + word).read_volatile() } != 0);
+
+    Ok(programmed)
+}
+
+/// Returns the MAC address, from MAC slot 1 if it's been burnt, otherwise the factory address
+pub fn read_mac() -> Result<[u8; 6], Error> {
+    EfuseData::read()?.mac()
+}
+
+/// Returns the chip ID, see [`EfuseData::chip_id`]
+pub fn chip_id() -> Result<[u8; 8], Error> {
+    Ok(EfuseData::read()?.chip_id())
+}
+
+/// Returns the gain correction of the ADC, `None` if it isn't burnt or can't be read
+pub fn adc_trim() -> Option<AdcTrim> {
+    EfuseData::read().ok()?.adc_trim()
+}
+
+/// Returns the reference code of the temperature sensor, `None` if it isn't burnt or can't be
+/// read
+pub fn tsen_trim() -> Option<u16> {
+    EfuseData::read().ok()?.tsen_trim()
+}
+
+/// Returns the frequency code of the 32 kHz RC oscillator, `None` if it isn't burnt or can't be
+/// read
+pub fn rc32k_trim() -> Option<u16> {
+    EfuseData::read().ok()?.rc32k_trim()
+}
+
+/// Returns the word at byte offset `offset` of the data block, for fields this module doesn't
+/// decode
+pub fn read_word(offset: usize) -> Result<u32, Error> {
+    if offset % 4 != 0 || offset / 4 >= WORDS {
+        return Err(Error::InvalidOffset);
+    }
+
+    load()?;
+    let base = pac::EF_DATA_0::ptr() as *const u32;
+    Ok(unsafe { base.add(offset / 4).read_volatile() })
+}
+
+/// Decodes a trim of `len` bits at bit `pos` of `word`, followed by its parity and enable bits
+fn trim(word: u32, pos: u32, len: u32) -> Option<u16> {
+    let code = (word >> pos) & ((1 << len) - 1);
+    let parity = (word >> (pos + len)) & 1;
+    let enabled = (word >> (pos + len + 1)) & 1 != 0;
+
+    if enabled && code.count_ones() % 2 == parity {
+        Some(code as u16)
+    } else {
+        None
+    }
+}
+
+/// Loads the eFuse array into the shadow registers
+///
+/// The controller reads with the bus clock and in automatic mode, as the boot ROM leaves it.
+fn load() -> Result<(), Error> {
+    let ef_ctrl = unsafe { &*pac::EF_CTRL::ptr() };
+    let ctrl_0 = &ef_ctrl.ef_if_ctrl_0 as *const _ as *mut u32;
+    let config = EF_IF_PROTECT | EF_IF_AUTO_RD_EN | EF_IF_0_INT_CLR;
+
+    crate::sync::critical(|| unsafe {
+        if !wait(ctrl_0, EF_IF_0_BUSY, 0, LOAD_TIMEOUT) {
+            return Err(Error::Timeout);
+        }
+        ctrl_0.write_volatile(config);
+        ctrl_0.write_volatile(config | EF_IF_0_TRIG);
+
+        // Busy goes up a few cycles after the trigger
+        for _ in 0..8 {
+            ctrl_0.read_volatile();
+        }
+        let loaded = wait(ctrl_0, EF_IF_0_BUSY, 0, LOAD_TIMEOUT)
+            && wait(
+                ctrl_0,
+                EF_IF_0_AUTOLOAD_DONE,
+                EF_IF_0_AUTOLOAD_DONE,
+                LOAD_TIMEOUT,
+            );
+        ctrl_0.write_volatile(config);
+
+        if loaded {
+            Ok(())
+        } else {
+            Err(Error::Timeout)
+        }
+    })
+}
+
+/// Polls until the bits `mask` of `register` equal `value`, at most `timeout` times
+unsafe fn wait(register: *mut u32, mask: u32, value: u32, timeout: u32) -> bool {
+    (0..timeout).any(|_| register.read_volatile() & mask == value)
+}
+
+#[cfg(feature = "efuse-write")]
+pub use self::write::{Irreversible, Writer, LOCK_WORD, SECURITY_WORD, USER_DATA_LEN};
+
+#[cfg(feature = "efuse-write")]
+mod write {
+    use super::{
+        key_slot_locked, load, wait, EfuseData, Error, KeySlot, EF_IF_0_BUSY, EF_IF_0_INT_CLR,
+        EF_IF_0_TRIG, EF_IF_AUTO_RD_EN, EF_IF_PROTECT, KEY_SLOT_0_OFFSET, LOAD_TIMEOUT, MAC_SLOT_1,
+        WORDS,
+    };
+    use crate::clock::Clocks;
+    use crate::delay::McycleDelay;
+    use crate::pac;
+
+    // ef_if_ctrl_0
+    const EF_IF_0_RW: u32 = 1 << 3;
+    const EF_IF_0_MANUAL_EN: u32 = 1 << 5;
+    const EF_CLK_SAHB_DATA_SEL: u32 = 1 << 7;
+
+    /// Configuration word with the secure boot, encryption and debug settings
+    pub const SECURITY_WORD: usize = 0x00;
+    /// Write and read lock bits of the other words
+    pub const LOCK_WORD: usize = 0x7c;
+    /// Start of the user data, the words of key slot 4
+    const USER_DATA: usize = 0x5c;
+    /// Bytes of user data
+    pub const USER_DATA_LEN: usize = 16;
+
+    /// Polling iterations to wait for a program cycle, which burns the bits one after another
+    const PROGRAM_TIMEOUT: u32 = 10_000_000;
+    /// Settling time of the programming supply, from the vendor SDK
+    const PROGRAM_SUPPLY_US: u32 = 4;
+
+    /// Acknowledgement that bits burnt by a [`Writer`] can never be cleared
+    pub struct Irreversible {
+        _private: (),
+    }
+
+    impl Irreversible {
+        /// Creates the acknowledgement
+        ///
+        /// # Safety
+        ///
+        /// Every bit the writer burns is permanent. A wrong value in a trim, the debug password
+        /// or a key slot can't be fixed later, and burning the lock or security words can make
+        /// the chip refuse to boot unsigned firmware or to be debugged. Only create this in code
+        /// which is meant to provision chips, e.g. a production line test.
+        pub const unsafe fn new() -> Self {
+            Irreversible { _private: () }
+        }
+    }
+
+    /// Burns bits into the eFuse array, see the module documentation
+    pub struct Writer {
+        _irreversible: Irreversible,
+        cycles_per_us: u32,
+    }
+
+    impl Writer {
+        /// Creates a writer, `clocks` is used to time the program cycle
+        pub fn new(irreversible: Irreversible, clocks: &Clocks) -> Self {
+            Writer {
+                _irreversible: irreversible,
+                cycles_per_us: clocks.sysclk().0 / 1_000_000,
+            }
+        }
+
+        /// Burns the word at byte offset `offset` so it reads `value`
+        ///
+        /// Only the bits which aren't burnt yet are programmed, a word which already reads
+        /// `value` is left alone. Fails with [`Error::AlreadyProgrammed`] if the word has a bit
+        /// set which `value` doesn't, with [`Error::LockWord`] for the lock and security words
+        /// and with [`Error::ReadLocked`] in a read-protected key slot, whose contents can't be
+        /// checked.
+        ///
+        /// # Safety
+        ///
+        /// The value has to be valid for the field the word belongs to, e.g. a trim with a
+        /// correct parity bit. Nothing checks that, and a wrong value can't be cleared again.
+        pub unsafe fn burn(&mut self, offset: usize, value: u32) -> Result<(), Error> {
+            if offset % 4 != 0 || offset / 4 >= WORDS {
+                return Err(Error::InvalidOffset);
+            }
+            if offset == SECURITY_WORD || offset == LOCK_WORD {
+                return Err(Error::LockWord);
+            }
+            if let Some(slot) = key_slot(offset) {
+                if key_slot_locked(slot) {
+                    return Err(Error::ReadLocked);
+                }
+            }
+
+            self.burn_words(&[(offset, value)])
+        }
+
+        /// Burns `mac` into MAC slot 1, which then replaces the factory address of slot 0
+        ///
+        /// Slot 1 can only be burnt once, if any of its bits is set the result is
+        /// [`Error::AlreadyProgrammed`]. The address is read back with [`EfuseData::mac`].
+        pub fn burn_mac(&mut self, mac: [u8; 6]) -> Result<(), Error> {
+            if mac == [0; 6] {
+                return Err(Error::InvalidData);
+            }
+
+            let data = EfuseData::read()?;
+            if data.word(MAC_SLOT_1.0)? != 0 || data.word(MAC_SLOT_1.1)? != 0 {
+                return Err(Error::AlreadyProgrammed);
+            }
+
+            let low = u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]);
+            let high = u32::from(mac[4]) | u32::from(mac[5]) << 8;
+            // The zero bits are counted so a partially burnt address is recognized
+            let zeros = (!low).count_ones() + (!high & 0xffff).count_ones();
+            self.burn_words(&[(MAC_SLOT_1.0, low), (MAC_SLOT_1.1, high | zeros << 16)])?;
+
+            match EfuseData::read()?.mac() {
+                Ok(read) if read == mac => Ok(()),
+                Ok(_) | Err(Error::NoMac) => Err(Error::VerifyFailed),
+                Err(error) => Err(error),
+            }
+        }
+
+        /// Burns `data` at the start of the user data, e.g. a serial number
+        ///
+        /// At most [`USER_DATA_LEN`] bytes fit. The words `data` covers have to be unburnt,
+        /// otherwise the result is [`Error::AlreadyProgrammed`], the rest of the user data stays
+        /// free for another call with a longer `data`.
+        pub fn burn_user_data(&mut self, data: &[u8]) -> Result<(), Error> {
+            if data.len() > USER_DATA_LEN {
+                return Err(Error::InvalidData);
+            }
+
+            let current = EfuseData::read()?;
+            let mut words = [(0, 0); USER_DATA_LEN / 4];
+            let count = (data.len() + 3) / 4;
+            for (i, (word, chunk)) in words.iter_mut().zip(data.chunks(4)).enumerate() {
+                let offset = USER_DATA + 4 * i;
+                if current.word(offset)? != 0 {
+                    return Err(Error::AlreadyProgrammed);
+                }
+
+                let mut bytes = [0; 4];
+                bytes[..chunk.len()].copy_from_slice(chunk);
+                *word = (offset, u32::from_le_bytes(bytes));
+            }
+
+            self.burn_words(&words[..count])
+        }
+
+        /// Burns `bits` into the lock word or the security word, `offset` is [`LOCK_WORD`] or
+        /// [`SECURITY_WORD`]
+        ///
+        /// Bits which are already set stay set, so locks can be added one after another.
+        ///
+        /// # Safety
+        ///
+        /// A write lock makes its words unchangeable, a read lock hides a key slot from the
+        /// CPU, and the security word can enable secure boot, which rejects firmware without a
+        /// valid signature, or disable JTAG for good. Check every bit against the reference
+        /// manual before burning it.
+        pub unsafe fn burn_lock(&mut self, offset: usize, bits: u32) -> Result<(), Error> {
+            if offset != LOCK_WORD && offset != SECURITY_WORD {
+                return Err(Error::InvalidOffset);
+            }
+
+            let current = EfuseData::read()?.word(offset)?;
+            self.burn_words(&[(offset, current | bits)])
+        }
+
+        /// Burns the missing bits of `words`, pairs of byte offset and value, in one program
+        /// cycle and reads them back
+        fn burn_words(&mut self, words: &[(usize, u32)]) -> Result<(), Error> {
+            let current = EfuseData::read()?;
+            let mut bits = [(0, 0); WORDS];
+            for (new, &(offset, value)) in bits.iter_mut().zip(words) {
+                let burnt = current.word(offset)?;
+                if burnt & !value != 0 {
+                    return Err(Error::AlreadyProgrammed);
+                }
+                *new = (offset, value & !burnt);
+            }
+
+            let bits = &bits[..words.len()];
+            if bits.iter().all(|&(_, bits)| bits == 0) {
+                return Ok(());
+            }
+            self.program(bits)?;
+
+            let read = EfuseData::read()?;
+            for &(offset, value) in words {
+                if read.word(offset)? != value {
+                    return Err(Error::VerifyFailed);
+                }
+            }
+            Ok(())
+        }
+
+        /// Runs a program cycle, which burns every bit set in the shadow registers, with only
+        /// `words` set, then loads the array again
+        fn program(&self, words: &[(usize, u32)]) -> Result<(), Error> {
+            let ef_ctrl = unsafe { &*pac::EF_CTRL::ptr() };
+            let ctrl_0 = &ef_ctrl.ef_if_ctrl_0 as *const _ as *mut u32;
+            let shadow = pac::EF_DATA_0::ptr() as *mut u32;
+            let auto = EF_IF_PROTECT | EF_IF_AUTO_RD_EN | EF_IF_0_INT_CLR;
+            let manual = auto | EF_IF_0_MANUAL_EN | EF_CLK_SAHB_DATA_SEL;
+
+            crate::sync::critical(|| unsafe {
+                if !wait(ctrl_0, EF_IF_0_BUSY, 0, LOAD_TIMEOUT) {
+                    return Err(Error::Timeout);
+                }
+
+                // The shadow registers are only writable in manual mode with the bus clock. The
+                // program cycle burns every set bit, so the loaded contents are cleared first and
+                // only the new bits are programmed.
+                ctrl_0.write_volatile(manual);
+                for i in 0..WORDS {
+                    shadow.add(i).write_volatile(0);
+                }
+                for &(offset, bits) in words {
+                    shadow.add(offset / 4).write_volatile(bits);
+                }
+
+                // Back to automatic mode with the eFuse clock, whose default timing the program
+                // cycle is run with. RW switches on the programming supply, which has to settle
+                // before the trigger.
+                ctrl_0.write_volatile(auto);
+                ctrl_0.write_volatile(auto | EF_IF_0_RW);
+                McycleDelay::delay_cycles(u64::from(self.cycles_per_us * PROGRAM_SUPPLY_US));
+                ctrl_0.write_volatile(auto | EF_IF_0_RW | EF_IF_0_TRIG);
+
+                // Busy goes up a few cycles after the trigger
+                for _ in 0..8 {
+                    ctrl_0.read_volatile();
+                }
+                let programmed = wait(ctrl_0, EF_IF_0_BUSY, 0, PROGRAM_TIMEOUT);
+                ctrl_0.write_volatile(auto);
+
+                // The shadow registers only hold the new bits until they are loaded again
+                let loaded = load();
+                if programmed {
+                    loaded
+                } else {
+                    Err(Error::Timeout)
+                }
+            })
+        }
+    }
+
+    /// Returns the key slot `offset` is in, for the slots which can be read-protected
+    fn key_slot(offset: usize) -> Option<KeySlot> {
+        match offset.checked_sub(KEY_SLOT_0_OFFSET)? / 16 {
+            0 => Some(KeySlot::Slot0),
+            1 => Some(KeySlot::Slot1),
+            2 => Some(KeySlot::Slot2),
+            3 => Some(KeySlot::Slot3),
+            _ => None,
+        }
+    }
+}
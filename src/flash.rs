@@ -0,0 +1,1400 @@
+/*!
+  # Serial flash
+  The code runs from the SPI NOR flash through the XIP (execute in place) window at
+  [`XIP_BASE`], which the serial flash controller (SF_CTRL) serves with read commands of its own.
+  [`Flash`] takes the controller over for a moment to send erase, program and read commands,
+  and implements [`ReadNorFlash`] and [`NorFlash`] of `embedded-storage` on top of them.
+
+  While the controller is busy with such a command the XIP window can't be read, so neither code
+  nor constants in flash. Every command is therefore sent by a
+  [`ram_function!`](crate::ram_function) with interrupts masked: it switches the controller from XIP to commands, waits until the flash
+  is done, e.g. up to a few hundred milliseconds for erasing a sector, then switches back and
+  invalidates the cache, which may still hold the old content. Interrupts stay masked for each
+  sector erase or page program on its own, not for the whole operation.
+
+  | Operation | Granularity                                   |
+  |-----------|-----------------------------------------------|
+  | read      | any offset and length                         |
+  | write     | any offset and length, split into 256 B pages |
+  | erase     | 4 KiB sectors, [`SECTOR_SIZE`]                |
+
+  As with any NOR flash, writing can only clear bits, so the range has to be erased first.
+
+  # Identification
+  Boards come with flash chips of different vendors, which differ in their erase commands,
+  timings and above all in how quad I/O is enabled. [`identify`] reads the JEDEC ID and the
+  basic parameter table of the SFDP (serial flash discoverable parameters, JESD216) into
+  [`FlashParams`]: the capacity, the page size, the erase commands with their sizes and maximum
+  times and the [`QuadEnable`] procedure. Chips in a table of known chips, see
+  [`FlashParams::from_jedec_id`], take the quad enable procedure and the maximum clock
+  frequencies from it, and chips without SFDP are described by the table alone.
+
+  | Chip    | JEDEC ID   | Quad enable bit            | Written with                   |
+  |---------|------------|----------------------------|--------------------------------|
+  | GD25Q16 | `c8 40 15` | bit 1 of status register 2 | `01` and both registers        |
+  | W25Q16  | `ef 40 15` | bit 1 of status register 2 | `01` and both registers        |
+  | XT25F16 | `0b 40 15` | bit 1 of status register 2 | `31` and register 2 on its own |
+
+  A status register write which doesn't match the chip can clear the wrong bit, or set the
+  protection bits instead of the quad enable bit, and a bootloader set up for quad reads can't
+  start the firmware then. [`Flash::enable_quad`] keeps every other bit as it is and checks that
+  the bit was actually set. [`QuadEnable::status_write`] tells which write a procedure makes
+  without sending anything.
+
+  # Reading
+  Reads go through the XIP window if the offset is mapped into it, which is the case from the
+  offset the firmware was booted from onwards, and are as fast as any other read from flash then.
+  Offsets before it, or all offsets if the firmware's offset can't be told because there are
+  several copies of its start in flash, are read with read commands in command mode. A read
+  which starts before the mapped offset and ends after it is split there.
+
+  There are three ways to read the mapped part, the first two through the cache:
+
+  | Path                | Used by                          | Cache          | Core     |
+  |---------------------|----------------------------------|----------------|----------|
+  | byte loop           | a decoder reading the XIP window | filled         | busy     |
+  | word copy           | [`ReadNorFlash::read`]           | filled         | busy     |
+  | DMA                 | [`Flash::read_dma`]              | left alone     | polls    |
+  | commands            | [`Flash::read_with_commands`]    | left alone     | masked   |
+
+  The word copy reads four bytes per load wherever source and destination are aligned alike,
+  and is the default for `read`. A long read through the cache evicts the code running from
+  flash though, e.g. a 200 KiB asset passes through the 32 KiB cache six times, and the code
+  has to be fetched again afterwards. [`Flash::read_dma`] has a DMA channel copy from the XIP
+  window instead, which doesn't go through the cache of the core. Command mode doesn't either,
+  but moves 256 bytes per command with interrupts masked and should be the slowest by far. No
+  throughput figures are given here because none have been measured yet: the `flash_read`
+  example prints them for all four paths on the board at hand.
+
+  [`Flash::read_iter`] streams a range byte by byte through a small buffer, for decoders which
+  take their input as an iterator.
+
+  # Protection
+  Offsets beyond the capacity are rejected with [`Error::OutOfBounds`]. Erasing or writing the
+  running firmware crashes it, so by default everything from offset 0, with the bootloader and
+  partition table, up to the end of the firmware is protected, and erasing or writing it fails
+  with [`Error::Protected`]. If the firmware isn't found, e.g. because it's encrypted, the whole
+  flash is protected. [`Flash::set_protected`] changes the range, e.g. to update the firmware in
+  place.
+
+  The XIP setup of the bootloader has to send a read command for every access: a flash in
+  continuous read mode, where the command is left out, would have to be taken out of it and put
+  back into it, which isn't supported. [`identify`] and [`Flash::new`] fail with
+  [`Error::ContinuousRead`] then.
+
+  # Verified writes
+  A program operation which is cut short, e.g. by a brown-out, leaves bits unprogrammed without
+  the flash reporting anything. [`Flash::write_with_crc_verify`] reads the data back with read
+  commands, so past the cache, and compares its CRC-32 with the one the data is supposed to have,
+  e.g. from the header of a firmware update. The CRC is computed in software by
+  [`crc`](crate::crc), the BL602 has no CRC engine. On a mismatch the sectors written to are
+  erased, so a half-written image can't be mistaken for a valid one.
+
+  # Settings and updates
+  [`kv::Kv`] keeps small settings in two sectors, with records that survive a power loss while
+  writing them. [`ota`] writes a firmware update into the slot of the firmware partition which
+  isn't running and switches the bootloader over to it. [`layout`] parses the partition table
+  and boot headers, and finds partitions by name.
+
+  # External memory
+  The SF_CTRL of the BL602 serves a single flash, on the embedded pads or on one of the two
+  sets of external pins, and maps it at [`XIP_BASE`]. Unlike the one of the BL702 it has no
+  second bank, so a PSRAM can't be mapped next to the flash and there is no PSRAM driver. A
+  PSRAM on a module with a BL602 can only be reached as an SPI device through [`spi`](crate::spi),
+  with explicit reads and writes. Code ported from an SDK of a chip which has the second bank
+  faults on the first access to the PSRAM window, which doesn't exist here.
+
+  ## Example
+  ```rust
+    use bl602_hal::flash::Flash;
+    use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+    let mut flash = Flash::new(dp.SF_CTRL, &clocks).unwrap();
+
+    const SETTINGS: u32 = 0x1f_f000;
+    flash.erase(SETTINGS, SETTINGS + 4096).unwrap();
+    flash.write(SETTINGS, b"settings").unwrap();
+
+    let mut buffer = [0; 8];
+    flash.read(SETTINGS, &mut buffer).unwrap();
+
+    if flash.params().quad_enable.is_some() {
+        flash.enable_quad().unwrap();
+    }
+  ```
+*/
+
+use core::fmt;
+use core::ops::Range;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+use crate::clock::Clocks;
+use crate::crc::{Algorithm, Crc};
+use crate::dma::{self, Lli};
+use crate::pac;
+
+pub mod kv;
+pub mod layout;
+pub mod ota;
+
+/// Start of the XIP window of the flash
+pub const XIP_BASE: usize = 0x2300_0000;
+/// Size of the erase unit
+pub const SECTOR_SIZE: u32 = 4096;
+/// Size of the program unit, a program command can't cross its boundaries
+pub const PAGE_SIZE: u32 = 256;
+
+/// Flash error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The erase range doesn't start and end on a sector boundary
+    NotAligned,
+    /// The range goes beyond the end of the flash
+    OutOfBounds,
+    /// The range overlaps the protected range
+    Protected,
+    /// The flash or the controller stayed busy for longer than the flash's maximum time
+    Timeout,
+    /// The chip has no SFDP and isn't a known chip, or it can't be used: it's bigger than
+    /// 16 MiB, can't erase 4 KiB sectors, or its quad enable procedure isn't known
+    UnknownChip([u8; 3]),
+    /// The XIP setup reads the flash in continuous read mode
+    ContinuousRead,
+    /// The quad enable bit wasn't set after writing it
+    QuadEnable,
+    /// The CRC of the data read back after writing isn't the expected one, see
+    /// [`Flash::write_with_crc_verify`]
+    CrcMismatch {
+        /// CRC the data was expected to have
+        written_crc: u32,
+        /// CRC of the data read back from the flash
+        read_crc: u32,
+    },
+}
+
+/// Parameters of a flash chip, see [`identify`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FlashParams {
+    /// Manufacturer ID, memory type and capacity code
+    pub jedec_id: [u8; 3],
+    /// Part number, for chips in the table of known chips
+    pub name: Option<&'static str>,
+    /// Size in bytes
+    pub capacity: u32,
+    /// Bytes a page program command can write at once
+    pub page_size: u32,
+    /// Maximum time of a page program
+    pub program_timeout_ms: u32,
+    /// Supported erase commands, smallest first
+    pub erase_types: [Option<EraseType>; 4],
+    /// How quad I/O is enabled, `None` if it isn't known
+    pub quad_enable: Option<QuadEnable>,
+    /// Maximum clock of the read command (`03`)
+    pub max_read_hz: u32,
+    /// Maximum clock of every other command, e.g. fast reads
+    pub max_clock_hz: u32,
+    /// Whether the geometry and timings were read from the SFDP of the chip
+    pub from_sfdp: bool,
+}
+
+impl FlashParams {
+    /// Returns the parameters of a chip in the table of known chips, `None` for other chips
+    ///
+    /// The table is taken from the datasheets. The erase commands and timings are the ones
+    /// every revision of the chip supports, the clock frequencies those of the slowest revision.
+    pub fn from_jedec_id(jedec_id: [u8; 3]) -> Option<Self> {
+        let chip = KNOWN_CHIPS.iter().find(|chip| chip.jedec_id == jedec_id)?;
+        Some(FlashParams {
+            jedec_id,
+            name: Some(chip.name),
+            capacity: 1 << jedec_id[2],
+            page_size: PAGE_SIZE,
+            program_timeout_ms: 5,
+            erase_types: [
+                Some(EraseType::new(4 * 1024, 0x20, 500)),
+                Some(EraseType::new(32 * 1024, 0x52, 1600)),
+                Some(EraseType::new(64 * 1024, 0xd8, 2000)),
+                None,
+            ],
+            quad_enable: Some(chip.quad_enable),
+            max_read_hz: chip.max_read_hz,
+            max_clock_hz: chip.max_clock_hz,
+            from_sfdp: false,
+        })
+    }
+
+    /// Returns the erase command for 4 KiB sectors
+    pub fn sector_erase(&self) -> Option<EraseType> {
+        self.erase_types
+            .iter()
+            .flatten()
+            .find(|erase| erase.size == SECTOR_SIZE)
+            .copied()
+    }
+}
+
+/// An erase command
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct EraseType {
+    /// Bytes erased, the address has to be a multiple of it
+    pub size: u32,
+    pub opcode: u8,
+    /// Maximum time of the erase
+    pub timeout_ms: u32,
+}
+
+impl EraseType {
+    const fn new(size: u32, opcode: u8, timeout_ms: u32) -> Self {
+        EraseType {
+            size,
+            opcode,
+            timeout_ms,
+        }
+    }
+}
+
+/// Where the quad enable (QE) bit is and how it's written, the QER field of JESD216
+///
+/// Status register 1 is read with `05`, status register 2 with `35` unless noted otherwise. A
+/// write enable (`06`) comes before every write, so the write is non-volatile.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum QuadEnable {
+    /// There's no QE bit, quad I/O is always available (QER 000)
+    NotNeeded,
+    /// Bit 6 of status register 1, written with `01` and status register 1 (QER 010)
+    Sr1Bit6,
+    /// Bit 1 of status register 2, written with `01` and both status registers, because a
+    /// write of status register 1 alone may clear status register 2 (QER 001, 100, 101)
+    Sr2Bit1WithSr1,
+    /// Bit 1 of status register 2, written with `31` and status register 2 alone (QER 110)
+    Sr2Bit1,
+    /// Bit 7 of status register 2, read with `3f` and written with `3e` (QER 011)
+    Sr2Bit7,
+}
+
+impl QuadEnable {
+    /// Returns the procedure of a QER field value, `None` for reserved values
+    pub fn from_qer(qer: u8) -> Option<Self> {
+        match qer {
+            0b000 => Some(QuadEnable::NotNeeded),
+            0b001 | 0b100 | 0b101 => Some(QuadEnable::Sr2Bit1WithSr1),
+            0b010 => Some(QuadEnable::Sr1Bit6),
+            0b011 => Some(QuadEnable::Sr2Bit7),
+            0b110 => Some(QuadEnable::Sr2Bit1),
+            _ => None,
+        }
+    }
+
+    /// Returns the commands which read status register 1 and status register 2, for the
+    /// registers the procedure needs
+    pub fn read_opcodes(self) -> (Option<u8>, Option<u8>) {
+        match self {
+            QuadEnable::NotNeeded => (None, None),
+            QuadEnable::Sr1Bit6 => (Some(CMD_READ_STATUS), None),
+            QuadEnable::Sr2Bit1WithSr1 => (Some(CMD_READ_STATUS), Some(CMD_READ_STATUS_2)),
+            QuadEnable::Sr2Bit1 => (None, Some(CMD_READ_STATUS_2)),
+            QuadEnable::Sr2Bit7 => (None, Some(0x3f)),
+        }
+    }
+
+    /// Returns whether the QE bit is set in the status registers read with
+    /// [`read_opcodes`](Self::read_opcodes), registers which weren't read don't matter
+    pub fn is_enabled(self, sr1: u8, sr2: u8) -> bool {
+        match self {
+            QuadEnable::NotNeeded => true,
+            QuadEnable::Sr1Bit6 => sr1 & 0x40 != 0,
+            QuadEnable::Sr2Bit1WithSr1 | QuadEnable::Sr2Bit1 => sr2 & 0x02 != 0,
+            QuadEnable::Sr2Bit7 => sr2 & 0x80 != 0,
+        }
+    }
+
+    /// Returns the write which sets the QE bit and keeps every other writable bit of the status
+    /// registers read with [`read_opcodes`](Self::read_opcodes), `None` if the bit is already set
+    pub fn status_write(self, sr1: u8, sr2: u8) -> Option<StatusWrite> {
+        if self.is_enabled(sr1, sr2) {
+            return None;
+        }
+
+        // Busy and write enable latch are read only
+        let sr1 = sr1 & !0x03;
+        Some(match self {
+            QuadEnable::NotNeeded => return None,
+            QuadEnable::Sr1Bit6 => StatusWrite::new(CMD_WRITE_STATUS, &[sr1 | 0x40]),
+            QuadEnable::Sr2Bit1WithSr1 => StatusWrite::new(CMD_WRITE_STATUS, &[sr1, sr2 | 0x02]),
+            QuadEnable::Sr2Bit1 => StatusWrite::new(0x31, &[sr2 | 0x02]),
+            QuadEnable::Sr2Bit7 => StatusWrite::new(0x3e, &[sr2 | 0x80]),
+        })
+    }
+}
+
+/// A write of one or two status registers, see [`QuadEnable::status_write`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct StatusWrite {
+    pub opcode: u8,
+    bytes: [u8; 2],
+    len: usize,
+}
+
+impl StatusWrite {
+    fn new(opcode: u8, bytes: &[u8]) -> Self {
+        let mut write = StatusWrite {
+            opcode,
+            bytes: [0; 2],
+            len: bytes.len(),
+        };
+        write.bytes[..bytes.len()].copy_from_slice(bytes);
+        write
+    }
+
+    /// Returns the bytes sent after the opcode
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+struct KnownChip {
+    jedec_id: [u8; 3],
+    name: &'static str,
+    quad_enable: QuadEnable,
+    max_read_hz: u32,
+    max_clock_hz: u32,
+}
+
+const KNOWN_CHIPS: &[KnownChip] = &[
+    KnownChip {
+        jedec_id: [0xc8, 0x40, 0x15],
+        name: "GD25Q16",
+        quad_enable: QuadEnable::Sr2Bit1WithSr1,
+        max_read_hz: 80_000_000,
+        max_clock_hz: 120_000_000,
+    },
+    KnownChip {
+        jedec_id: [0xef, 0x40, 0x15],
+        name: "W25Q16",
+        quad_enable: QuadEnable::Sr2Bit1WithSr1,
+        max_read_hz: 50_000_000,
+        max_clock_hz: 104_000_000,
+    },
+    // Status register 2 has a write command of its own
+    KnownChip {
+        jedec_id: [0x0b, 0x40, 0x15],
+        name: "XT25F16",
+        quad_enable: QuadEnable::Sr2Bit1,
+        max_read_hz: 50_000_000,
+        max_clock_hz: 80_000_000,
+    },
+];
+
+/// Clock frequencies assumed for chips which aren't in the table, SFDP doesn't describe them
+const DEFAULT_MAX_READ_HZ: u32 = 33_000_000;
+const DEFAULT_MAX_CLOCK_HZ: u32 = 50_000_000;
+
+// Registers of SF_CTRL and their bits, see bl602_sf_ctrl_reg.h of the vendor SDK. The SAHB
+// command port is driven by raw offsets, the data buffer isn't described by the PAC at all.
+const SF_CTRL_1: usize = 0x04;
+const SF_IF_SAHB_0: usize = 0x08;
+const SF_IF_SAHB_1: usize = 0x0c;
+const SF_IF_SAHB_2: usize = 0x10;
+const SF_IF_IAHB_0: usize = 0x14;
+const SF_CTRL_BUF: usize = 0x700;
+
+// SF_CTRL_1
+const SF_IF_FN_SEL: u32 = 1 << 28;
+const SF_AHB2SIF_EN: u32 = 1 << 30;
+
+// SF_IF_SAHB_0, the enables are at the same place in SF_IF_IAHB_0
+const SF_IF_BUSY: u32 = 1 << 0;
+const SF_IF_0_TRIG: u32 = 1 << 1;
+const SF_IF_0_DAT_BYTE_POS: u32 = 2;
+const SF_IF_0_DMY_BYTE_POS: u32 = 12;
+const SF_IF_0_ADR_BYTE_POS: u32 = 17;
+const SF_IF_0_DAT_RW: u32 = 1 << 23;
+const SF_IF_0_DAT_EN: u32 = 1 << 24;
+const SF_IF_0_DMY_EN: u32 = 1 << 25;
+const SF_IF_0_ADR_EN: u32 = 1 << 26;
+const SF_IF_0_CMD_EN: u32 = 1 << 27;
+
+/// Bytes of the data buffer, a command moves at most this many
+const BUF_SIZE: usize = 256;
+
+// SPI NOR commands shared by every supported chip
+const CMD_WRITE_ENABLE: u8 = 0x06;
+const CMD_READ_STATUS: u8 = 0x05;
+const CMD_READ_STATUS_2: u8 = 0x35;
+const CMD_WRITE_STATUS: u8 = 0x01;
+const CMD_READ_DATA: u8 = 0x03;
+const CMD_PAGE_PROGRAM: u8 = 0x02;
+const CMD_READ_JEDEC_ID: u8 = 0x9f;
+const CMD_READ_SFDP: u8 = 0x5a;
+const STATUS_WIP: u32 = 1 << 0;
+
+// Maximum times from the datasheets of common chips, with some margin
+const COMMAND_TIMEOUT_MS: u32 = 1;
+const STATUS_WRITE_TIMEOUT_MS: u32 = 30;
+
+/// Bytes compared to find the start of the firmware in flash
+const SIGNATURE_SIZE: usize = 32;
+
+/// Dwords of the basic flash parameter table which are used, JESD216B has 16
+const BFPT_DWORDS: usize = 16;
+
+extern "C" {
+    // Symbols of the `link.x` of `riscv-rt`, the initial content of `.data` is the last part of
+    // the firmware in flash
+    static _sidata: u8;
+    static _sdata: u8;
+    static _edata: u8;
+}
+
+/// Reads the JEDEC ID and the SFDP of the flash and returns its parameters, see the module
+/// documentation
+///
+/// `clocks` is used for the timeouts. Fails with [`Error::UnknownChip`] if the chip has no SFDP
+/// and isn't in the table of known chips.
+pub fn identify(sf_ctrl: &mut pac::SF_CTRL, clocks: &Clocks) -> Result<FlashParams, Error> {
+    // Only taken to tell that no `Flash` is using the controller
+    let _ = sf_ctrl;
+    Port::new(clocks)?.identify()
+}
+
+/// Erase, program and read commands for the flash, see the module documentation
+pub struct Flash {
+    sf_ctrl: pac::SF_CTRL,
+    port: Port,
+    params: FlashParams,
+    sector_erase: EraseType,
+    /// Offset mapped at `XIP_BASE`, if it's known
+    xip_offset: Option<u32>,
+    protected: Range<u32>,
+}
+
+impl Flash {
+    /// Takes over the flash controller, identifies the flash and protects the running firmware
+    ///
+    /// `clocks` is used for the timeouts.
+    pub fn new(sf_ctrl: pac::SF_CTRL, clocks: &Clocks) -> Result<Self, Error> {
+        let port = Port::new(clocks)?;
+        let params = port.identify()?;
+        let sector_erase = match params.sector_erase() {
+            Some(erase) if params.capacity <= 1 << 24 => erase,
+            _ => return Err(Error::UnknownChip(params.jedec_id)),
+        };
+
+        let mut flash = Flash {
+            sf_ctrl,
+            port,
+            params,
+            sector_erase,
+            xip_offset: None,
+            protected: 0..0,
+        };
+
+        let (first, last) = flash.find_firmware()?;
+        if first == last {
+            flash.xip_offset = first;
+        }
+        let firmware_len = unsafe {
+            &_sidata as *const u8 as usize + (&_edata as *const u8 as usize)
+                - (&_sdata as *const u8 as usize)
+                - XIP_BASE
+        } as u32;
+        let end = match last {
+            Some(last) => last + firmware_len,
+            // Read commands don't return what XIP reads, e.g. with flash encryption
+            None => flash.params.capacity,
+        };
+        flash.protected = 0..(end + SECTOR_SIZE - 1) / SECTOR_SIZE * SECTOR_SIZE;
+
+        Ok(flash)
+    }
+
+    /// Reads the manufacturer ID, the memory type and the capacity code of the flash
+    pub fn read_jedec_id(&mut self) -> Result<[u8; 3], Error> {
+        self.port.read_jedec_id()
+    }
+
+    /// Reads with read commands even where the XIP window could be used
+    ///
+    /// The cache is bypassed, so this returns what's stored in the flash right now, e.g. to check
+    /// what was programmed.
+    pub fn read_with_commands(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        self.check_bounds(offset, bytes.len())?;
+        self.port.read(CMD_READ_DATA, 0, offset, bytes)
+    }
+
+    /// Reads like [`ReadNorFlash::read`], but has DMA channel `channel` copy the mapped part out
+    /// of the XIP window, past the cache of the core
+    ///
+    /// The core waits for the channel, with interrupts enabled. Taking `dma` only shows that
+    /// nobody else programs the controller, the other channels are left alone.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `channel` isn't below 4.
+    pub fn read_dma(
+        &mut self,
+        offset: u32,
+        bytes: &mut [u8],
+        dma: &mut pac::DMA,
+        channel: usize,
+    ) -> Result<(), Error> {
+        let _ = dma;
+        assert!(channel < dma::CHANNELS);
+        self.check_bounds(offset, bytes.len())?;
+
+        let unmapped = self.unmapped_len(offset, bytes.len());
+        let (commands, mut mapped) = bytes.split_at_mut(unmapped);
+        self.port.read(CMD_READ_DATA, 0, offset, commands)?;
+        let mut src = self.xip_address(offset + unmapped as u32);
+
+        // Words where both sides are aligned alike, bytes otherwise
+        let words = (src ^ mapped.as_ptr() as usize) & 3 == 0;
+        if words {
+            let head = mapped.len().min(src.wrapping_neg() & 3);
+            copy_xip(src, &mut mapped[..head]);
+            src += head;
+            mapped = &mut core::mem::take(&mut mapped)[head..];
+        }
+
+        while !mapped.is_empty() {
+            let (transfers, len, width) = if words && mapped.len() >= 4 {
+                let transfers = (mapped.len() / 4).min(dma::MAX_TRANSFERS);
+                (transfers, transfers * 4, dma::WIDTH_32)
+            } else if words {
+                // The tail after the last word
+                copy_xip(src, mapped);
+                break;
+            } else {
+                let transfers = mapped.len().min(dma::MAX_TRANSFERS);
+                (transfers, transfers, dma::WIDTH_8)
+            };
+
+            let lli = Lli {
+                src: src as u32,
+                dst: dma::bus_address(mapped.as_mut_ptr() as usize),
+                next: 0,
+                control: transfers as u32 | width | dma::SRC_INCREMENT | dma::DST_INCREMENT,
+            };
+            unsafe { dma::start(channel, &lli, dma::MEMORY_TO_MEMORY) };
+
+            // Every time around the loop takes well over a cycle
+            let mut countdown = self.port.cycles_per_ms.saturating_mul(COMMAND_TIMEOUT_MS);
+            while dma::is_enabled(channel) {
+                if countdown == 0 {
+                    dma::stop(channel);
+                    return Err(Error::Timeout);
+                }
+                countdown -= 1;
+            }
+            compiler_fence(Ordering::SeqCst);
+
+            src += len;
+            mapped = &mut core::mem::take(&mut mapped)[len..];
+        }
+
+        Ok(())
+    }
+
+    /// Returns an iterator over the `len` bytes from `offset`, read in pieces of 256 bytes like
+    /// [`ReadNorFlash::read`]
+    pub fn read_iter(&mut self, offset: u32, len: u32) -> Result<ReadIter<'_>, Error> {
+        self.check_bounds(offset, len as usize)?;
+
+        Ok(ReadIter {
+            flash: self,
+            offset,
+            end: offset + len,
+            buf: [0; BUF_SIZE],
+            pos: 0,
+            filled: 0,
+            error: None,
+        })
+    }
+
+    /// Writes `bytes` at `offset`, reads them back and returns their CRC-32
+    ///
+    /// The range has to be erased, as for [`NorFlash::write`]. If the CRC of what was read back
+    /// isn't `expected_crc`, every sector the range touches is erased, including whatever else
+    /// is stored in them, and the result is [`Error::CrcMismatch`]. A mismatch can also come from
+    /// `bytes` themselves, e.g. a corrupted download, which is erased just the same.
+    pub fn write_with_crc_verify(
+        &mut self,
+        offset: u32,
+        bytes: &[u8],
+        expected_crc: u32,
+    ) -> Result<u32, Error> {
+        self.write(offset, bytes)?;
+
+        let mut crc = Crc::new(Algorithm::Crc32);
+        let mut chunk = [0u8; PAGE_SIZE as usize];
+        let end = offset + bytes.len() as u32;
+        for address in (offset..end).step_by(chunk.len()) {
+            let len = chunk.len().min((end - address) as usize);
+            self.read_with_commands(address, &mut chunk[..len])?;
+            crc.update(&chunk[..len]);
+        }
+
+        let read_crc = crc.finish();
+        if read_crc == expected_crc {
+            return Ok(read_crc);
+        }
+
+        let from = offset / SECTOR_SIZE * SECTOR_SIZE;
+        let to = (end + SECTOR_SIZE - 1) / SECTOR_SIZE * SECTOR_SIZE;
+        self.erase(from, to)?;
+        Err(Error::CrcMismatch {
+            written_crc: expected_crc,
+            read_crc,
+        })
+    }
+
+    /// Returns whether the quad enable bit of the flash is set
+    pub fn quad_enabled(&mut self) -> Result<bool, Error> {
+        let quad_enable = self.quad_enable()?;
+        let (sr1, sr2) = self.read_status(quad_enable)?;
+        Ok(quad_enable.is_enabled(sr1, sr2))
+    }
+
+    /// Sets the quad enable bit of the flash, returns whether it had to be written
+    ///
+    /// The bit is non-volatile, so it's only written if it isn't set yet. Fails with
+    /// [`Error::UnknownChip`] if the procedure isn't known.
+    pub fn enable_quad(&mut self) -> Result<bool, Error> {
+        let quad_enable = self.quad_enable()?;
+        let (sr1, sr2) = self.read_status(quad_enable)?;
+        let write = match quad_enable.status_write(sr1, sr2) {
+            Some(write) => write,
+            None => return Ok(false),
+        };
+
+        // The bytes have to be in RAM
+        let mut bytes = [0u8; 2];
+        bytes[..write.len].copy_from_slice(write.bytes());
+        self.port.transfer(
+            command_config(false, 0, write.len, true),
+            write.opcode,
+            0,
+            bytes.as_mut_ptr(),
+            write.len,
+            true,
+            STATUS_WRITE_TIMEOUT_MS,
+        )?;
+
+        if self.quad_enabled()? {
+            Ok(true)
+        } else {
+            Err(Error::QuadEnable)
+        }
+    }
+
+    /// Returns the parameters found by [`identify`]
+    pub fn params(&self) -> &FlashParams {
+        &self.params
+    }
+
+    /// Returns the JEDEC ID read by [`new`](Self::new)
+    pub fn jedec_id(&self) -> [u8; 3] {
+        self.params.jedec_id
+    }
+
+    /// Returns the flash offset which is mapped at [`XIP_BASE`], `None` if it isn't known
+    pub fn xip_offset(&self) -> Option<u32> {
+        self.xip_offset
+    }
+
+    /// Returns the range of offsets which can't be erased or written
+    pub fn protected(&self) -> Range<u32> {
+        self.protected.clone()
+    }
+
+    /// Replaces the protected range, an empty range allows every offset
+    pub fn set_protected(&mut self, range: Range<u32>) {
+        self.protected = range;
+    }
+
+    /// Releases the flash controller
+    pub fn free(self) -> pac::SF_CTRL {
+        self.sf_ctrl
+    }
+
+    fn quad_enable(&self) -> Result<QuadEnable, Error> {
+        self.params
+            .quad_enable
+            .ok_or(Error::UnknownChip(self.params.jedec_id))
+    }
+
+    /// Reads the status registers `quad_enable` needs, 0 for the others
+    fn read_status(&mut self, quad_enable: QuadEnable) -> Result<(u8, u8), Error> {
+        let (sr1, sr2) = quad_enable.read_opcodes();
+        let mut status = [0u8; 2];
+        if let Some(opcode) = sr1 {
+            self.port.read_register(opcode, &mut status[..1])?;
+        }
+        if let Some(opcode) = sr2 {
+            self.port.read_register(opcode, &mut status[1..])?;
+        }
+        Ok((status[0], status[1]))
+    }
+
+    fn check_bounds(&self, offset: u32, len: usize) -> Result<(), Error> {
+        check_bounds(self.params.capacity, offset, len)
+    }
+
+    fn unmapped_len(&self, offset: u32, len: usize) -> usize {
+        unmapped_len(self.xip_offset, offset, len)
+    }
+
+    fn xip_address(&self, offset: u32) -> usize {
+        xip_address(self.xip_offset, offset)
+    }
+
+    fn check_protected(&self, from: u32, to: u32) -> Result<(), Error> {
+        if from < to && from < self.protected.end && self.protected.start < to {
+            Err(Error::Protected)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the first and last sector aligned offset at which the start of the XIP window was
+    /// found
+    fn find_firmware(&mut self) -> Result<(Option<u32>, Option<u32>), Error> {
+        let mut signature = [0u8; SIGNATURE_SIZE];
+        signature.copy_from_slice(unsafe {
+            core::slice::from_raw_parts(XIP_BASE as *const u8, SIGNATURE_SIZE)
+        });
+
+        let mut first = None;
+        let mut last = None;
+        let mut candidate = [0u8; SIGNATURE_SIZE];
+        for offset in (0..self.params.capacity).step_by(SECTOR_SIZE as usize) {
+            self.port.read(CMD_READ_DATA, 0, offset, &mut candidate)?;
+            if candidate == signature {
+                first = first.or(Some(offset));
+                last = Some(offset);
+            }
+        }
+
+        Ok((first, last))
+    }
+}
+
+impl fmt::Debug for Flash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Flash")
+            .field("params", &self.params)
+            .field("xip_offset", &self.xip_offset)
+            .field("protected", &self.protected)
+            .finish()
+    }
+}
+
+impl ReadNorFlash for Flash {
+    type Error = Error;
+
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        self.check_bounds(offset, bytes.len())?;
+
+        let unmapped = self.unmapped_len(offset, bytes.len());
+        let (commands, mapped) = bytes.split_at_mut(unmapped);
+        self.port.read(CMD_READ_DATA, 0, offset, commands)?;
+        if !mapped.is_empty() {
+            copy_xip(self.xip_address(offset + unmapped as u32), mapped);
+        }
+
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.params.capacity as usize
+    }
+}
+
+impl NorFlash for Flash {
+    const WRITE_SIZE: usize = 1;
+
+    const ERASE_SIZE: usize = SECTOR_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        if from % SECTOR_SIZE != 0 || to % SECTOR_SIZE != 0 || from > to {
+            return Err(Error::NotAligned);
+        }
+        self.check_bounds(from, (to - from) as usize)?;
+        self.check_protected(from, to)?;
+
+        let erase = self.sector_erase;
+        for address in (from..to).step_by(SECTOR_SIZE as usize) {
+            self.port.transfer(
+                command_config(true, 0, 0, false),
+                erase.opcode,
+                address,
+                core::ptr::null_mut(),
+                0,
+                true,
+                erase.timeout_ms,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        self.check_bounds(offset, bytes.len())?;
+        self.check_protected(offset, offset + bytes.len() as u32)?;
+
+        // Page sizes are powers of two, so pieces of the buffer's size never cross a page
+        let page_size = self.params.page_size.min(BUF_SIZE as u32);
+        let mut address = offset;
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let len = rest.len().min((page_size - address % page_size) as usize);
+
+            // `bytes` may be in flash, which can't be read while programming
+            let mut page = [0u8; BUF_SIZE];
+            page[..len].copy_from_slice(&rest[..len]);
+            self.port.transfer(
+                command_config(true, 0, len, true),
+                CMD_PAGE_PROGRAM,
+                address,
+                page.as_mut_ptr(),
+                len,
+                true,
+                self.params.program_timeout_ms,
+            )?;
+
+            address += len as u32;
+            rest = &rest[len..];
+        }
+
+        Ok(())
+    }
+}
+
+/// Iterator over a range of the flash, see [`Flash::read_iter`]
+///
+/// The iterator ends early if a read fails, which [`error`](Self::error) tells.
+pub struct ReadIter<'a> {
+    flash: &'a mut Flash,
+    /// Offset of the byte after the buffer
+    offset: u32,
+    end: u32,
+    buf: [u8; BUF_SIZE],
+    pos: usize,
+    filled: usize,
+    error: Option<Error>,
+}
+
+impl ReadIter<'_> {
+    /// Returns the error which ended the iterator early, if any
+    pub fn error(&self) -> Option<Error> {
+        self.error
+    }
+
+    /// Returns the offset of the next byte
+    pub fn offset(&self) -> u32 {
+        self.offset - (self.filled - self.pos) as u32
+    }
+}
+
+impl Iterator for ReadIter<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.pos == self.filled {
+            let len = BUF_SIZE.min((self.end - self.offset) as usize);
+            if len == 0 || self.error.is_some() {
+                return None;
+            }
+            if let Err(error) = self.flash.read(self.offset, &mut self.buf[..len]) {
+                self.error = Some(error);
+                return None;
+            }
+            self.offset += len as u32;
+            self.pos = 0;
+            self.filled = len;
+        }
+
+        self.pos += 1;
+        Some(self.buf[self.pos - 1])
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = (self.end - self.offset) as usize + self.filled - self.pos;
+        (0, Some(left))
+    }
+}
+
+/// Fails with [`Error::OutOfBounds`] unless the `len` bytes from `offset` end within `capacity`
+fn check_bounds(capacity: u32, offset: u32, len: usize) -> Result<(), Error> {
+    match offset.checked_add(len as u32) {
+        Some(end) if end <= capacity => Ok(()),
+        _ => Err(Error::OutOfBounds),
+    }
+}
+
+/// Returns how many of the `len` bytes from `offset` come before the XIP window, which starts at
+/// `xip_offset`, all of them if the window isn't known
+fn unmapped_len(xip_offset: Option<u32>, offset: u32, len: usize) -> usize {
+    match xip_offset {
+        Some(xip_offset) => len.min(xip_offset.saturating_sub(offset) as usize),
+        None => len,
+    }
+}
+
+/// Returns the address of `offset` in the XIP window, which has to be mapped
+fn xip_address(xip_offset: Option<u32>, offset: u32) -> usize {
+    XIP_BASE + (offset - xip_offset.unwrap_or(0)) as usize
+}
+
+/// Copies `bytes.len()` bytes from `src` in the XIP window, a word at a time where `src` and
+/// `bytes` are aligned alike
+fn copy_xip(src: usize, bytes: &mut [u8]) {
+    let len = bytes.len();
+    let dst = bytes.as_mut_ptr();
+    let head = if (src ^ dst as usize) & 3 == 0 {
+        len.min(src.wrapping_neg() & 3)
+    } else {
+        len
+    };
+    let words = (len - head) / 4;
+
+    unsafe {
+        for i in 0..head {
+            *dst.add(i) = *((src + i) as *const u8);
+        }
+        for i in 0..words {
+            let word = ((src + head) as *const u32).add(i).read();
+            (dst.add(head) as *mut u32).add(i).write(word);
+        }
+        for i in head + words * 4..len {
+            *dst.add(i) = *((src + i) as *const u8);
+        }
+    }
+}
+
+/// Sends commands through the command port of the controller
+#[derive(Debug, Copy, Clone)]
+struct Port {
+    cycles_per_ms: u32,
+}
+
+impl Port {
+    fn new(clocks: &Clocks) -> Result<Self, Error> {
+        let base = pac::SF_CTRL::ptr() as usize;
+        if unsafe { reg(base, SF_IF_IAHB_0).read_volatile() } & SF_IF_0_CMD_EN == 0 {
+            return Err(Error::ContinuousRead);
+        }
+
+        Ok(Port {
+            cycles_per_ms: clocks.sysclk().0 / 1000,
+        })
+    }
+
+    fn identify(&self) -> Result<FlashParams, Error> {
+        let jedec_id = self.read_jedec_id()?;
+        let known = FlashParams::from_jedec_id(jedec_id);
+
+        Ok(match (self.read_sfdp(jedec_id)?, known) {
+            (Some(mut params), Some(known)) => {
+                // The table was checked against the datasheets, the QER field of some chips isn't
+                params.name = known.name;
+                params.quad_enable = known.quad_enable;
+                params.max_read_hz = known.max_read_hz;
+                params.max_clock_hz = known.max_clock_hz;
+                params
+            }
+            (Some(params), None) => params,
+            (None, Some(known)) => known,
+            (None, None) => return Err(Error::UnknownChip(jedec_id)),
+        })
+    }
+
+    fn read_jedec_id(&self) -> Result<[u8; 3], Error> {
+        let mut id = [0u8; 3];
+        self.read_register(CMD_READ_JEDEC_ID, &mut id)?;
+        Ok(id)
+    }
+
+    /// Reads the basic flash parameter table, `None` if the chip has no SFDP
+    fn read_sfdp(&self, jedec_id: [u8; 3]) -> Result<Option<FlashParams>, Error> {
+        // The SFDP header and the first parameter header, which is the basic table's
+        let mut header = [0u8; 16];
+        self.read(CMD_READ_SFDP, 1, 0, &mut header)?;
+        if &header[..4] != b"SFDP" || header[8] != 0x00 {
+            return Ok(None);
+        }
+        let dwords = (header[11] as usize).min(BFPT_DWORDS);
+        let pointer = u32::from_le_bytes([header[12], header[13], header[14], 0]);
+        if dwords < 9 {
+            return Ok(None);
+        }
+
+        let mut bytes = [0u8; BFPT_DWORDS * 4];
+        self.read(CMD_READ_SFDP, 1, pointer, &mut bytes[..dwords * 4])?;
+        let mut table = [0u32; BFPT_DWORDS];
+        for (dword, bytes) in table.iter_mut().zip(bytes.chunks(4)) {
+            *dword = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        Ok(Some(parse_bfpt(jedec_id, &table[..dwords])))
+    }
+
+    /// Reads with read commands, in pieces of the data buffer's size
+    fn read(
+        &self,
+        opcode: u8,
+        dummy_bytes: u32,
+        offset: u32,
+        bytes: &mut [u8],
+    ) -> Result<(), Error> {
+        for (i, chunk) in bytes.chunks_mut(BUF_SIZE).enumerate() {
+            let address = offset + (i * BUF_SIZE) as u32;
+            self.transfer(
+                command_config(true, dummy_bytes, chunk.len(), false),
+                opcode,
+                address,
+                chunk.as_mut_ptr(),
+                chunk.len(),
+                false,
+                COMMAND_TIMEOUT_MS,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends `opcode` without address and reads `bytes.len()` bytes
+    fn read_register(&self, opcode: u8, bytes: &mut [u8]) -> Result<(), Error> {
+        self.transfer(
+            command_config(false, 0, bytes.len(), false),
+            opcode,
+            0,
+            bytes.as_mut_ptr(),
+            bytes.len(),
+            false,
+            COMMAND_TIMEOUT_MS,
+        )
+    }
+
+    /// Sends one command with interrupts masked, see `ram_transfer`
+    #[allow(clippy::too_many_arguments)]
+    fn transfer(
+        &self,
+        config: u32,
+        opcode: u8,
+        address: u32,
+        data: *mut u8,
+        len: usize,
+        program: bool,
+        timeout_ms: u32,
+    ) -> Result<(), Error> {
+        let base = pac::SF_CTRL::ptr() as usize;
+        // Every time around a polling loop takes well over a cycle
+        let timeout = self.cycles_per_ms.saturating_mul(timeout_ms);
+        let transfer = Transfer {
+            config,
+            opcode,
+            address,
+            data,
+            len,
+            program,
+        };
+        if riscv::interrupt::free(|_| unsafe { ram_transfer(base, &transfer, timeout) }) {
+            Ok(())
+        } else {
+            Err(Error::Timeout)
+        }
+    }
+}
+
+/// Builds the parameters from the dwords of a basic flash parameter table, at least 9
+fn parse_bfpt(jedec_id: [u8; 3], table: &[u32]) -> FlashParams {
+    let dword = |n: usize| table.get(n - 1).copied();
+
+    let density = table[1];
+    let capacity_bits = if density & 1 << 31 == 0 {
+        u64::from(density) + 1
+    } else {
+        1u64 << (density & 0x7fff_ffff).min(63)
+    };
+    let capacity = (capacity_bits / 8).min(u64::from(u32::MAX)) as u32;
+
+    // Typical times in 2 bit units and 5 bit counts, the maximum is a multiple of them
+    let erase_times = dword(10);
+    let max_factor = |times: u32| 2 * ((times & 0xf) + 1);
+    let erase_timeout_ms = |n: u32| match erase_times {
+        Some(times) => {
+            let field = times >> (4 + 7 * n);
+            let unit_ms = [1, 16, 128, 1000][(field >> 5 & 0x3) as usize];
+            ((field & 0x1f) + 1) * unit_ms * max_factor(times)
+        }
+        None => 2000,
+    };
+
+    let mut erase_types = [None; 4];
+    let mut n = 0;
+    for i in 0..4 {
+        let field = table[7 + i / 2] >> (16 * (i % 2));
+        let size = field & 0xff;
+        if size != 0 && size < 32 {
+            erase_types[n] = Some(EraseType::new(
+                1 << size,
+                (field >> 8) as u8,
+                erase_timeout_ms(i as u32),
+            ));
+            n += 1;
+        }
+    }
+    // Older tables may only list the 4 KiB erase in the first dword
+    if n == 0 && table[0] & 0x3 == 0x1 {
+        erase_types[0] = Some(EraseType::new(SECTOR_SIZE, (table[0] >> 8) as u8, 500));
+    }
+    erase_types[..].sort_unstable_by_key(|erase| erase.map_or(u32::MAX, |erase| erase.size));
+
+    let (page_size, program_timeout_ms) = match dword(11) {
+        Some(times) => {
+            let unit_us = if times & 1 << 13 == 0 { 8 } else { 64 };
+            let typical_us = ((times >> 8 & 0x1f) + 1) * unit_us;
+            let max_ms = (typical_us * max_factor(times) + 999) / 1000;
+            (1 << (times >> 4 & 0xf), max_ms.max(1))
+        }
+        None => (PAGE_SIZE, 5),
+    };
+
+    FlashParams {
+        jedec_id,
+        name: None,
+        capacity,
+        page_size,
+        program_timeout_ms,
+        erase_types,
+        quad_enable: dword(15).and_then(|qer| QuadEnable::from_qer((qer >> 20 & 0x7) as u8)),
+        max_read_hz: DEFAULT_MAX_READ_HZ,
+        max_clock_hz: DEFAULT_MAX_CLOCK_HZ,
+        from_sfdp: true,
+    }
+}
+
+#[inline(always)]
+fn reg(base: usize, offset: usize) -> *mut u32 {
+    (base + offset) as *mut u32
+}
+
+/// Returns the `SF_IF_SAHB_0` configuration of a single SPI command with a 3 byte address if
+/// `address` is set, `dummy_bytes` dummy bytes and `data_len` bytes of data
+#[inline(always)]
+fn command_config(address: bool, dummy_bytes: u32, data_len: usize, write: bool) -> u32 {
+    let mut config = SF_IF_0_CMD_EN;
+    if address {
+        config |= SF_IF_0_ADR_EN | (3 - 1) << SF_IF_0_ADR_BYTE_POS;
+    }
+    if dummy_bytes > 0 {
+        config |= SF_IF_0_DMY_EN | (dummy_bytes - 1) << SF_IF_0_DMY_BYTE_POS;
+    }
+    if data_len > 0 {
+        config |= SF_IF_0_DAT_EN | (data_len as u32 - 1) << SF_IF_0_DAT_BYTE_POS;
+        if write {
+            config |= SF_IF_0_DAT_RW;
+        }
+    }
+    // Single SPI for all phases
+    config
+}
+
+/// A command for `ram_transfer`
+struct Transfer {
+    /// `SF_IF_SAHB_0` from `command_config`
+    config: u32,
+    opcode: u8,
+    address: u32,
+    /// `len` bytes in RAM, at most `BUF_SIZE`, which are sent or received depending on `config`
+    data: *mut u8,
+    len: usize,
+    /// Sends a write enable first and waits until the flash is done afterwards
+    program: bool,
+}
+
+// Everything from here on runs while the flash can't be read: only `#[inline(always)]` helpers,
+// raw pointers and volatile accesses, so there are neither calls into flash nor jump tables,
+// panics or `memcpy`.
+
+/// Hands the controller from XIP over to the command port, returns the `SF_CTRL_1` to restore
+#[inline(always)]
+unsafe fn suspend_xip(base: usize) -> u32 {
+    let ctrl_1 = reg(base, SF_CTRL_1).read_volatile();
+    reg(base, SF_CTRL_1).write_volatile(ctrl_1 & !(SF_IF_FN_SEL | SF_AHB2SIF_EN));
+    ctrl_1
+}
+
+/// Gives the controller back to XIP and drops the cached content of the flash
+#[inline(always)]
+unsafe fn resume_xip(base: usize, ctrl_1: u32) {
+    reg(base, SF_CTRL_1).write_volatile(ctrl_1);
+
+    let l1c = &*pac::L1C::ptr();
+    l1c.l1c_config.modify(|_, w| w.l1c_invalid_en().clear_bit());
+    l1c.l1c_config.modify(|_, w| w.l1c_invalid_en().set_bit());
+    while l1c.l1c_config.read().l1c_invalid_done().bit_is_clear() {}
+}
+
+/// Sends `command` with `address` in the command buffer and waits for the controller
+#[inline(always)]
+unsafe fn command(base: usize, config: u32, command: u8, address: u32, timeout: u32) -> bool {
+    reg(base, SF_IF_SAHB_0).write_volatile(config & !SF_IF_0_TRIG);
+    reg(base, SF_IF_SAHB_1).write_volatile((command as u32) << 24 | (address & 0xff_ffff));
+    reg(base, SF_IF_SAHB_2).write_volatile(0);
+    reg(base, SF_IF_SAHB_0).write_volatile(config | SF_IF_0_TRIG);
+
+    let mut countdown = timeout;
+    while reg(base, SF_IF_SAHB_0).read_volatile() & SF_IF_BUSY != 0 {
+        if countdown == 0 {
+            return false;
+        }
+        countdown -= 1;
+    }
+    reg(base, SF_IF_SAHB_0).write_volatile(config & !SF_IF_0_TRIG);
+
+    true
+}
+
+/// Polls the status register until the flash is done with a program or erase
+#[inline(always)]
+unsafe fn wait_ready(base: usize, timeout: u32) -> bool {
+    let mut countdown = timeout;
+    loop {
+        if !command(
+            base,
+            command_config(false, 0, 1, false),
+            CMD_READ_STATUS,
+            0,
+            timeout,
+        ) {
+            return false;
+        }
+        if reg(base, SF_CTRL_BUF).read_volatile() & STATUS_WIP == 0 {
+            return true;
+        }
+        if countdown == 0 {
+            return false;
+        }
+        countdown -= 1;
+    }
+}
+
+#[inline(always)]
+unsafe fn write_enable(base: usize, timeout: u32) -> bool {
+    command(
+        base,
+        command_config(false, 0, 0, false),
+        CMD_WRITE_ENABLE,
+        0,
+        timeout,
+    )
+}
+
+crate::ram_function! {
+    /// Sends `transfer`, moving its data through the data buffer
+    unsafe fn ram_transfer(base: usize, transfer: &Transfer, timeout: u32) -> bool {
+        let (data, len) = (transfer.data, transfer.len);
+        let sending = transfer.config & SF_IF_0_DAT_RW != 0;
+
+        let ctrl_1 = suspend_xip(base);
+        if sending {
+            // The buffer is written in words, bytes after `len` are ignored
+            let mut i = 0;
+            while i < len {
+                let mut word = 0;
+                let mut j = 0;
+                while j < 4 && i + j < len {
+                    word |= (data.add(i + j).read_volatile() as u32) << (j * 8);
+                    j += 1;
+                }
+                reg(base, SF_CTRL_BUF + i).write_volatile(word);
+                i += 4;
+            }
+        }
+        let done = (!transfer.program || write_enable(base, timeout))
+            && command(base, transfer.config, transfer.opcode, transfer.address, timeout)
+            && (!transfer.program || wait_ready(base, timeout));
+        if !sending {
+            let mut i = 0;
+            while i < len {
+                let word = reg(base, SF_CTRL_BUF + (i & !3)).read_volatile();
+                data.add(i).write_volatile((word >> ((i & 3) * 8)) as u8);
+                i += 1;
+            }
+        }
+        resume_xip(base, ctrl_1);
+
+        done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_bounds, unmapped_len, xip_address, Error, XIP_BASE};
+
+    const CAPACITY: u32 = 0x20_0000;
+    /// The firmware partition at 0x10000, with its boot header in front of the window
+    const WINDOW: Option<u32> = Some(0x11000);
+
+    #[test]
+    fn bounds() {
+        assert_eq!(check_bounds(CAPACITY, 0, CAPACITY as usize), Ok(()));
+        assert_eq!(check_bounds(CAPACITY, CAPACITY - 300, 300), Ok(()));
+        assert_eq!(check_bounds(CAPACITY, CAPACITY, 0), Ok(()));
+        assert_eq!(
+            check_bounds(CAPACITY, CAPACITY - 1, 2),
+            Err(Error::OutOfBounds)
+        );
+        assert_eq!(check_bounds(CAPACITY, CAPACITY, 1), Err(Error::OutOfBounds));
+        assert_eq!(check_bounds(CAPACITY, u32::MAX, 2), Err(Error::OutOfBounds));
+    }
+
+    #[test]
+    fn split_before_window() {
+        assert_eq!(unmapped_len(WINDOW, 0x10000, 0x100), 0x100);
+        // Ends right at the start of the window
+        assert_eq!(unmapped_len(WINDOW, 0x10f00, 0x100), 0x100);
+        assert_eq!(unmapped_len(WINDOW, 0x10fff, 0), 0);
+    }
+
+    #[test]
+    fn split_across_window_start() {
+        let unmapped = unmapped_len(WINDOW, 0x10f00, 0x200);
+        assert_eq!(unmapped, 0x100);
+        assert_eq!(xip_address(WINDOW, 0x10f00 + unmapped as u32), XIP_BASE);
+
+        let unmapped = unmapped_len(WINDOW, 0x10fff, 2);
+        assert_eq!(unmapped, 1);
+        assert_eq!(xip_address(WINDOW, 0x10fff + unmapped as u32), XIP_BASE);
+    }
+
+    #[test]
+    fn split_in_window() {
+        assert_eq!(unmapped_len(WINDOW, 0x11000, 0x100), 0);
+        assert_eq!(xip_address(WINDOW, 0x11000), XIP_BASE);
+        assert_eq!(xip_address(WINDOW, 0x11001), XIP_BASE + 1);
+
+        // Ends at the end of the flash
+        let offset = CAPACITY - 300;
+        assert_eq!(unmapped_len(WINDOW, offset, 300), 0);
+        assert_eq!(
+            xip_address(WINDOW, offset) + 300,
+            XIP_BASE + (CAPACITY - 0x11000) as usize
+        );
+    }
+
+    #[test]
+    fn split_without_window() {
+        assert_eq!(unmapped_len(None, 0x11000, 0x100), 0x100);
+        assert_eq!(unmapped_len(None, CAPACITY - 300, 300), 300);
+        assert_eq!(unmapped_len(Some(0), 0, 0x100), 0);
+        assert_eq!(xip_address(Some(0), 0x100), XIP_BASE + 0x100);
+    }
+}
@@ -0,0 +1,624 @@
+/*!
+  # Key-value store
+  [`Kv`] keeps small settings in two 4 KiB sectors of any [`NorFlash`], e.g. the
+  [`Flash`](super::Flash) driver. Values are appended to the active sector as records, a later
+  record of a key replaces the earlier ones:
+
+  | Bytes     | Sector header                                 |
+  |-----------|-----------------------------------------------|
+  | 0 to 3    | `"BLKV"`                                      |
+  | 4 to 7    | sequence number, one up at every compaction   |
+  | 8 to 11   | CRC-32 of bytes 0 to 7                        |
+
+  | Bytes     | Record, from byte 12 on, 4 byte aligned       |
+  |-----------|-----------------------------------------------|
+  | 0 to 1    | key                                           |
+  | 2 to 3    | length of the value                           |
+  | 4 to 7    | CRC-32 of the key, the length and the value   |
+  | 8 on      | value, padded with `0xff` to 4 bytes          |
+
+  When the active sector is full, the live records, the last one of each key, are copied to the
+  other sector together with the new value, and its header is written last. So the sectors take
+  turns, and each is only erased once per compaction, which spreads the wear over both.
+
+  Everything is written so a power loss at any point leaves either the old or the new value
+  behind: a sector without a valid header is ignored, and of two valid ones the one with the
+  higher sequence number is used. A record which was cut short fails its CRC, and it and
+  everything after it is dropped when the store is opened. The rest of that sector can't be
+  written anymore then, the next `set` compacts into the other sector.
+
+  A copy of the active sector is kept in RAM, so [`Kv::get`] doesn't touch the flash. Records
+  are read back after writing them, and a mismatch is reported as [`Error::VerifyFailed`].
+
+  ## Example
+  ```rust
+    use bl602_hal::flash::{kv::Kv, Flash};
+
+    let flash = Flash::new(dp.SF_CTRL, &clocks).unwrap();
+    let mut settings = Kv::new(flash, [0x1f_e000, 0x1f_f000]).unwrap();
+
+    const WIFI_SSID: u16 = 1;
+    if settings.get(WIFI_SSID).is_none() {
+        settings.set(WIFI_SSID, b"home").unwrap();
+    }
+  ```
+*/
+
+use core::ops::Range;
+
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::crc::{Algorithm, Crc};
+
+/// Bytes of each of the two sectors of a [`Kv`]
+pub const KV_SECTOR_SIZE: usize = 4096;
+/// Longest value a record can hold
+pub const MAX_VALUE_LEN: usize = KV_SECTOR_SIZE - HEADER_SIZE - RECORD_HEADER_SIZE;
+
+/// Bytes of the sector header
+const HEADER_SIZE: usize = 12;
+/// Bytes of a record before its value
+const RECORD_HEADER_SIZE: usize = 8;
+/// Start of a sector in use
+const MAGIC: [u8; 4] = *b"BLKV";
+/// Key of an erased record header, which can't be set
+const ERASED_KEY: u16 = 0xffff;
+
+/// Key-value store error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error<E> {
+    /// The flash failed
+    Flash(E),
+    /// The sectors overlap, go beyond the end of the flash or don't match its erase and write
+    /// sizes
+    InvalidSectors,
+    /// The key is `0xffff`, which marks erased flash
+    InvalidKey,
+    /// The value is longer than [`MAX_VALUE_LEN`]
+    TooLong,
+    /// The live records and the new one don't fit into a sector
+    Full,
+    /// A record or sector read back doesn't hold what was written
+    VerifyFailed,
+}
+
+/// Log-structured key-value store in two flash sectors, see the module documentation
+pub struct Kv<F> {
+    flash: F,
+    sectors: [u32; 2],
+    /// Index of the sector in use
+    active: usize,
+    /// Sequence number of the active sector
+    sequence: u32,
+    /// Offset of the first byte after the last valid record
+    end: usize,
+    /// Whether the flash after `end` has bits programmed, e.g. by a record cut short
+    dirty: bool,
+    /// Copy of the active sector
+    buffer: [u8; KV_SECTOR_SIZE],
+}
+
+impl<F: NorFlash> Kv<F> {
+    /// Opens the store in the sectors at the offsets `sectors`
+    ///
+    /// If neither sector holds a valid header, the first one is erased and the store starts out
+    /// empty. The offsets have to be aligned to the erase size of the flash, which has to divide
+    /// 4 KiB, and the write and read sizes have to divide 4 bytes.
+    pub fn new(flash: F, sectors: [u32; 2]) -> Result<Self, Error<F::Error>> {
+        let size = KV_SECTOR_SIZE as u32;
+        let aligned = KV_SECTOR_SIZE % F::ERASE_SIZE == 0
+            && 4 % F::WRITE_SIZE == 0
+            && 4 % F::READ_SIZE == 0
+            && sectors.iter().all(|&offset| {
+                offset as usize % F::ERASE_SIZE == 0
+                    && (offset as usize).saturating_add(KV_SECTOR_SIZE) <= flash.capacity()
+            });
+        let apart = sectors[0].max(sectors[1]) - sectors[0].min(sectors[1]) >= size;
+        if !aligned || !apart {
+            return Err(Error::InvalidSectors);
+        }
+
+        let mut kv = Kv {
+            flash,
+            sectors,
+            active: 0,
+            sequence: 0,
+            end: HEADER_SIZE,
+            dirty: false,
+            buffer: [0xff; KV_SECTOR_SIZE],
+        };
+
+        let active = match [kv.read_header(0)?, kv.read_header(1)?] {
+            [Some(first), Some(second)] => {
+                // The sequence number wraps around after 2^32 compactions
+                if second.wrapping_sub(first) as i32 > 0 {
+                    1
+                } else {
+                    0
+                }
+            }
+            [Some(_), None] => 0,
+            [None, Some(_)] => 1,
+            [None, None] => {
+                kv.erase(0)?;
+                kv.write_header(0, 0)?;
+                0
+            }
+        };
+        kv.open(active)?;
+
+        Ok(kv)
+    }
+
+    /// Returns the value of `key`, `None` if it has never been set
+    pub fn get(&self, key: u16) -> Option<&[u8]> {
+        let mut value = None;
+        let mut offset = HEADER_SIZE;
+        while offset < self.end {
+            let (record_key, data, next) = record(&self.buffer, offset);
+            if record_key == key {
+                value = Some(data);
+            }
+            offset = next;
+        }
+
+        value.map(|data| &self.buffer[data])
+    }
+
+    /// Sets `key` to `value`
+    ///
+    /// Nothing is written if `key` already has this value. If the active sector has no room for
+    /// the record, the store is compacted into the other sector first, which fails with
+    /// [`Error::Full`] if the values of all keys don't fit into a sector.
+    pub fn set(&mut self, key: u16, value: &[u8]) -> Result<(), Error<F::Error>> {
+        if key == ERASED_KEY {
+            return Err(Error::InvalidKey);
+        }
+        if value.len() > MAX_VALUE_LEN {
+            return Err(Error::TooLong);
+        }
+        if self.get(key) == Some(value) {
+            return Ok(());
+        }
+
+        if !self.dirty && self.end + record_size(value.len()) <= KV_SECTOR_SIZE {
+            self.append(key, value)
+        } else {
+            self.compact(key, value)
+        }
+    }
+
+    /// Returns the bytes used by the active sector, including records which have been replaced
+    pub fn used(&self) -> usize {
+        self.end
+    }
+
+    /// Releases the flash
+    pub fn free(self) -> F {
+        self.flash
+    }
+
+    /// Writes a record at the end of the active sector and reads it back
+    fn append(&mut self, key: u16, value: &[u8]) -> Result<(), Error<F::Error>> {
+        let start = self.end;
+        let end = start + record_size(value.len());
+        let address = self.sectors[self.active] + start as u32;
+        let mut crc = Crc::new(Algorithm::Crc32);
+
+        // Whatever made it into the flash before a failure has to be erased before writing
+        // there again
+        self.dirty = true;
+        write_record(&mut self.flash, address, key, value, &mut crc)?;
+        self.flash
+            .read(address, &mut self.buffer[start..end])
+            .map_err(Error::Flash)?;
+
+        if check_record(&self.buffer, start, &mut crc) != Some(end)
+            || record(&self.buffer, start).0 != key
+            || self.buffer[start + RECORD_HEADER_SIZE..][..value.len()] != *value
+        {
+            return Err(Error::VerifyFailed);
+        }
+
+        self.end = end;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Copies the live records and the new one into the other sector and switches to it
+    fn compact(&mut self, key: u16, value: &[u8]) -> Result<(), Error<F::Error>> {
+        let mut size = HEADER_SIZE + record_size(value.len());
+        let mut offset = HEADER_SIZE;
+        while offset < self.end {
+            let (record_key, data, next) = record(&self.buffer, offset);
+            if record_key != key && self.is_live(record_key, next) {
+                size += record_size(data.len());
+            }
+            offset = next;
+        }
+        if size > KV_SECTOR_SIZE {
+            return Err(Error::Full);
+        }
+
+        let target = 1 - self.active;
+        let base = self.sectors[target];
+        let mut crc = Crc::new(Algorithm::Crc32);
+        self.erase(target)?;
+
+        let mut to = HEADER_SIZE;
+        let mut offset = HEADER_SIZE;
+        while offset < self.end {
+            let (record_key, data, next) = record(&self.buffer, offset);
+            if record_key != key && self.is_live(record_key, next) {
+                let len = data.len();
+                let address = base + to as u32;
+                write_record(
+                    &mut self.flash,
+                    address,
+                    record_key,
+                    &self.buffer[data],
+                    &mut crc,
+                )?;
+                to += record_size(len);
+            }
+            offset = next;
+        }
+        write_record(&mut self.flash, base + to as u32, key, value, &mut crc)?;
+
+        // The header goes last, a sector without one is ignored when opening the store
+        let sequence = self.sequence.wrapping_add(1);
+        self.write_header(target, sequence)?;
+        self.open(target)?;
+
+        if self.end != size || self.get(key) != Some(value) {
+            return Err(Error::VerifyFailed);
+        }
+        Ok(())
+    }
+
+    /// Returns whether no record of `key` follows `offset`
+    fn is_live(&self, key: u16, mut offset: usize) -> bool {
+        while offset < self.end {
+            let (record_key, _, next) = record(&self.buffer, offset);
+            if record_key == key {
+                return false;
+            }
+            offset = next;
+        }
+        true
+    }
+
+    /// Reads sector `index` into the buffer and finds the end of its valid records
+    fn open(&mut self, index: usize) -> Result<(), Error<F::Error>> {
+        self.flash
+            .read(self.sectors[index], &mut self.buffer)
+            .map_err(Error::Flash)?;
+        let sequence = parse_header(&self.buffer).ok_or(Error::VerifyFailed)?;
+
+        let mut crc = Crc::new(Algorithm::Crc32);
+        let mut end = HEADER_SIZE;
+        while let Some(next) = check_record(&self.buffer, end, &mut crc) {
+            end = next;
+        }
+
+        self.active = index;
+        self.sequence = sequence;
+        self.end = end;
+        self.dirty = self.buffer[end..].iter().any(|&byte| byte != 0xff);
+        Ok(())
+    }
+
+    /// Returns the sequence number of sector `index` if its header is valid
+    fn read_header(&mut self, index: usize) -> Result<Option<u32>, Error<F::Error>> {
+        let mut header = [0; HEADER_SIZE];
+        self.flash
+            .read(self.sectors[index], &mut header)
+            .map_err(Error::Flash)?;
+        Ok(parse_header(&header))
+    }
+
+    fn write_header(&mut self, index: usize, sequence: u32) -> Result<(), Error<F::Error>> {
+        let mut header = [0; HEADER_SIZE];
+        header[..4].copy_from_slice(&MAGIC);
+        header[4..8].copy_from_slice(&sequence.to_le_bytes());
+        let crc = Crc::checksum(Algorithm::Crc32, &header[..8]);
+        header[8..].copy_from_slice(&crc.to_le_bytes());
+
+        self.flash
+            .write(self.sectors[index], &header)
+            .map_err(Error::Flash)
+    }
+
+    fn erase(&mut self, index: usize) -> Result<(), Error<F::Error>> {
+        let from = self.sectors[index];
+        self.flash
+            .erase(from, from + KV_SECTOR_SIZE as u32)
+            .map_err(Error::Flash)
+    }
+}
+
+/// Returns the sequence number of a sector starting with `header`, `None` if it isn't valid
+fn parse_header(header: &[u8]) -> Option<u32> {
+    let word =
+        |i: usize| u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+
+    if header[..4] == MAGIC && Crc::checksum(Algorithm::Crc32, &header[..8]) == word(8) {
+        Some(word(4))
+    } else {
+        None
+    }
+}
+
+/// Returns the bytes a record with a value of `len` bytes takes
+fn record_size(len: usize) -> usize {
+    RECORD_HEADER_SIZE + (len + 3) / 4 * 4
+}
+
+/// Returns the key and the value range of the valid record at `offset`, and where the next
+/// record starts
+fn record(buffer: &[u8], offset: usize) -> (u16, Range<usize>, usize) {
+    let key = u16::from_le_bytes([buffer[offset], buffer[offset + 1]]);
+    let len = u16::from_le_bytes([buffer[offset + 2], buffer[offset + 3]]) as usize;
+    let start = offset + RECORD_HEADER_SIZE;
+
+    (key, start..start + len, offset + record_size(len))
+}
+
+/// Checks the record at `offset` and returns where the next one starts, `None` if there's no
+/// record or it was cut short
+fn check_record(buffer: &[u8], offset: usize, crc: &mut Crc) -> Option<usize> {
+    if offset + RECORD_HEADER_SIZE > buffer.len() {
+        return None;
+    }
+
+    let header = &buffer[offset..offset + RECORD_HEADER_SIZE];
+    let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+    let stored = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    if header[..2] == ERASED_KEY.to_le_bytes() || offset + record_size(len) > buffer.len() {
+        return None;
+    }
+
+    let (key, data, next) = record(buffer, offset);
+    if record_crc(crc, key, &buffer[data]) == stored {
+        Some(next)
+    } else {
+        None
+    }
+}
+
+/// Returns the CRC of a record
+fn record_crc(crc: &mut Crc, key: u16, value: &[u8]) -> u32 {
+    crc.reset();
+    crc.update(&key.to_le_bytes());
+    crc.update(&(value.len() as u16).to_le_bytes());
+    crc.update(value);
+    crc.finish()
+}
+
+/// Writes a record at `address`, which is erased
+///
+/// The value is written in place, only its last partial word goes through a padded copy.
+fn write_record<F: NorFlash>(
+    flash: &mut F,
+    address: u32,
+    key: u16,
+    value: &[u8],
+    crc: &mut Crc,
+) -> Result<(), Error<F::Error>> {
+    let mut header = [0; RECORD_HEADER_SIZE];
+    header[..2].copy_from_slice(&key.to_le_bytes());
+    header[2..4].copy_from_slice(&(value.len() as u16).to_le_bytes());
+    header[4..].copy_from_slice(&record_crc(crc, key, value).to_le_bytes());
+    flash.write(address, &header).map_err(Error::Flash)?;
+
+    let whole = value.len() / 4 * 4;
+    let address = address + RECORD_HEADER_SIZE as u32;
+    if whole > 0 {
+        flash
+            .write(address, &value[..whole])
+            .map_err(Error::Flash)?;
+    }
+    if whole < value.len() {
+        let mut tail = [0xff; 4];
+        tail[..value.len() - whole].copy_from_slice(&value[whole..]);
+        flash
+            .write(address + whole as u32, &tail)
+            .map_err(Error::Flash)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Kv, KV_SECTOR_SIZE, MAX_VALUE_LEN};
+    use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+    use std::vec::Vec;
+
+    const AREA: usize = 2 * KV_SECTOR_SIZE;
+    const SECTORS: [u32; 2] = [0, KV_SECTOR_SIZE as u32];
+
+    /// The values set while the power is cut, the third one compacts the store
+    const WRITES: [(u16, &[u8]); 4] = [
+        (1, b"bravo"),
+        (3, &[0x33; 100]),
+        (4, &[0x44; 200]),
+        (1, b"charlie"),
+    ];
+
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    struct PowerLoss;
+
+    /// NOR flash in RAM, which fails every write and erase once `budget` steps have been taken,
+    /// a step being a programmed byte or an erased sector
+    struct RamFlash {
+        memory: Vec<u8>,
+        budget: usize,
+    }
+
+    impl RamFlash {
+        fn new(memory: &[u8], budget: usize) -> Self {
+            RamFlash {
+                memory: memory.to_vec(),
+                budget,
+            }
+        }
+    }
+
+    impl ReadNorFlash for RamFlash {
+        type Error = PowerLoss;
+
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), PowerLoss> {
+            let offset = offset as usize;
+            bytes.copy_from_slice(&self.memory[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            AREA
+        }
+    }
+
+    impl NorFlash for RamFlash {
+        const WRITE_SIZE: usize = 1;
+
+        const ERASE_SIZE: usize = KV_SECTOR_SIZE;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), PowerLoss> {
+            for sector in (from as usize..to as usize).step_by(KV_SECTOR_SIZE) {
+                if self.budget == 0 {
+                    return Err(PowerLoss);
+                }
+                self.budget -= 1;
+                for byte in self.memory[sector..sector + KV_SECTOR_SIZE].iter_mut() {
+                    *byte = 0xff;
+                }
+            }
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), PowerLoss> {
+            for (cell, &byte) in self.memory[offset as usize..].iter_mut().zip(bytes) {
+                if self.budget == 0 {
+                    return Err(PowerLoss);
+                }
+                self.budget -= 1;
+                // Programming only clears bits
+                *cell &= byte;
+            }
+            Ok(())
+        }
+    }
+
+    /// Returns the values of keys 1 to 4 after the first `writes` of `WRITES`
+    fn expected<'a>(initial: [Option<&'a [u8]>; 4], writes: usize) -> [Option<&'a [u8]>; 4] {
+        let mut values = initial;
+        for &(key, value) in WRITES[..writes.min(WRITES.len())].iter() {
+            values[key as usize - 1] = Some(value);
+        }
+        values
+    }
+
+    fn values<F: NorFlash>(kv: &Kv<F>) -> [Option<&[u8]>; 4] {
+        [kv.get(1), kv.get(2), kv.get(3), kv.get(4)]
+    }
+
+    /// Cuts the power at every step of `WRITES` into a store whose active sector is nearly full
+    #[test]
+    fn power_loss() {
+        // Fill the first sector so the first two writes still fit and the third compacts
+        let second = [0x22; 40];
+        let mut last = [0x33; 100];
+        let (initial, filled) = {
+            let mut kv = Kv::new(RamFlash::new(&[0x00; AREA], usize::MAX), SECTORS).unwrap();
+            kv.set(1, b"alpha").unwrap();
+            kv.set(2, &second).unwrap();
+            let mut round = 0;
+            while kv.used() + 16 + 108 + 108 <= KV_SECTOR_SIZE {
+                last[0] = round;
+                kv.set(3, &last).unwrap();
+                round += 1;
+            }
+            let used = kv.used();
+            (kv.free().memory, used)
+        };
+        let before: [Option<&[u8]>; 4] = [
+            Some(&b"alpha"[..]),
+            Some(&second[..]),
+            Some(&last[..]),
+            None,
+        ];
+
+        let mut kv = Kv::new(RamFlash::new(&initial, usize::MAX), SECTORS).unwrap();
+        let mut compacted = false;
+        for &(key, value) in WRITES.iter() {
+            kv.set(key, value).unwrap();
+            compacted |= kv.used() < filled;
+        }
+        assert!(compacted);
+        assert_eq!(values(&kv), expected(before, WRITES.len()));
+        let steps = usize::MAX - kv.free().budget;
+
+        for cut in 0..=steps {
+            // Opening a valid store doesn't write, so only the writes can be cut
+            let mut kv = Kv::new(RamFlash::new(&initial, cut), SECTORS).unwrap();
+            let mut written = 0;
+            for &(key, value) in WRITES.iter() {
+                if kv.set(key, value).is_err() {
+                    break;
+                }
+                written += 1;
+            }
+            let mut flash = kv.free();
+
+            flash.budget = usize::MAX;
+            let mut kv = Kv::new(flash, SECTORS)
+                .unwrap_or_else(|error| panic!("cut after {} steps: {:?}", cut, error));
+            // Either the cut write made it or it didn't, all the others are complete
+            let state = if values(&kv) == expected(before, written) {
+                written
+            } else if values(&kv) == expected(before, written + 1) {
+                written + 1
+            } else {
+                panic!("cut after {} steps: torn value", cut);
+            };
+
+            kv.set(5, b"after").unwrap();
+            let kv = Kv::new(kv.free(), SECTORS).unwrap();
+            assert_eq!(kv.get(5), Some(&b"after"[..]), "cut after {} steps", cut);
+            assert_eq!(
+                values(&kv),
+                expected(before, state),
+                "cut after {} steps",
+                cut
+            );
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        let mut kv = Kv::new(RamFlash::new(&[0x00; AREA], usize::MAX), SECTORS).unwrap();
+        assert_eq!(kv.get(7), None);
+        assert_eq!(kv.set(0xffff, b"x"), Err(Error::InvalidKey));
+        assert_eq!(kv.set(7, &[0; MAX_VALUE_LEN + 1]), Err(Error::TooLong));
+    }
+
+    #[test]
+    fn full() {
+        let mut kv = Kv::new(RamFlash::new(&[0x00; AREA], usize::MAX), SECTORS).unwrap();
+        let big = [0x5a; 1000];
+        for key in 10..14 {
+            kv.set(key, &big).unwrap();
+        }
+        assert_eq!(kv.set(14, &big), Err(Error::Full));
+        assert!((10..14).all(|key| kv.get(key) == Some(&big[..])));
+
+        // A smaller value of a key makes room again
+        kv.set(10, b"small").unwrap();
+        assert_eq!(kv.get(10), Some(&b"small"[..]));
+        kv.set(14, &big).unwrap();
+    }
+}
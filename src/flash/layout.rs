@@ -0,0 +1,755 @@
+/*!
+  # Flash layout
+  The bootloader (boot2) of the vendor SDK finds the partitions in the flash through a partition
+  table, which is stored twice, in the sectors at [`PARTITION_TABLE_OFFSETS`]:
+
+  | Bytes        | Header                                                 |
+  |--------------|--------------------------------------------------------|
+  | 0 to 3       | `"BFPT"`                                               |
+  | 4 to 5       | version                                                |
+  | 6 to 7       | number of entries, at most [`MAX_ENTRIES`]             |
+  | 8 to 11      | age                                                    |
+  | 12 to 15     | CRC-32 of bytes 0 to 11                                |
+
+  | Bytes        | Entry, 36 bytes each from byte 16 on                   |
+  |--------------|--------------------------------------------------------|
+  | 0, 1, 2      | type, device, active index                             |
+  | 3 to 11      | name, NUL padded                                       |
+  | 12 to 19     | addresses of slots 0 and 1                             |
+  | 20 to 27     | maximum lengths of slots 0 and 1                       |
+  | 28 to 31     | length of the contents of the active slot              |
+  | 32 to 35     | age                                                    |
+
+  The entries are followed by the CRC-32 of all entries. Boot2 uses the copy whose CRCs match,
+  the one with the higher age if both do, the first one if their ages are equal. Most entries
+  only use slot 0, the `FW` entry has two slots for firmware updates, see [`ota`](super::ota).
+
+  [`PartitionTable`] and [`BootHeader`] parse a table or header in place, without copying it.
+  [`PartitionTableBuf`] holds a table which can be changed, with its CRCs kept up to date, so it
+  can be written back with [`write_table`]. [`find`] looks a partition up by name in the table
+  boot2 uses, so the firmware doesn't depend on where a board variant puts its partitions.
+
+  Each image starts with a boot header, which the boot ROM reads for boot2 at offset 0 and boot2
+  for the firmware at the start of its slot:
+
+  | Bytes        | Boot header                                            |
+  |--------------|--------------------------------------------------------|
+  | 0 to 3       | `"BFNP"`                                               |
+  | 4 to 7       | revision                                               |
+  | 8 to 99      | flash configuration, `"FCFG"` and its CRC-32           |
+  | 100 to 115   | clock configuration, `"PCFG"` and its CRC-32           |
+  | 116 to 119   | boot configuration: signature 1:0, encryption 3:2,     |
+  |              | ignore the CRC 16, ignore the hash 17                  |
+  | 120 to 123   | length of the image                                    |
+  | 124 to 127   | boot entry point                                       |
+  | 128 to 131   | offset of the image from the header                    |
+  | 132 to 163   | SHA-256 of the image                                   |
+  | 172 to 175   | CRC-32 of bytes 0 to 171                               |
+
+  ## Example
+  ```rust
+    use bl602_hal::flash::layout;
+
+    let media = layout::find(&mut flash, "media").unwrap();
+    let mut buffer = [0; 64];
+    flash.read(media.address, &mut buffer).unwrap();
+  ```
+*/
+
+use core::fmt;
+
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+use super::{Flash, SECTOR_SIZE};
+use crate::crc::{Algorithm, Crc};
+
+/// Offsets of the two copies of the partition table
+pub const PARTITION_TABLE_OFFSETS: [u32; 2] = [0xe000, 0xf000];
+/// Number of entries a partition table can have
+pub const MAX_ENTRIES: usize = 16;
+/// Bytes of a partition table with [`MAX_ENTRIES`] entries
+pub const MAX_TABLE_SIZE: usize = HEADER_SIZE + MAX_ENTRIES * ENTRY_SIZE + 4;
+/// Bytes of a boot header
+pub const BOOT_HEADER_SIZE: usize = 176;
+
+const TABLE_MAGIC: [u8; 4] = *b"BFPT";
+const BOOT_MAGIC: [u8; 4] = *b"BFNP";
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 36;
+const NAME_SIZE: usize = 9;
+
+// Offsets in an entry
+const ENTRY_ACTIVE: usize = 2;
+const ENTRY_NAME: usize = 3;
+const ENTRY_ADDRESS: usize = 12;
+const ENTRY_MAX_LEN: usize = 20;
+const ENTRY_LEN: usize = 28;
+const ENTRY_AGE: usize = 32;
+
+// Offsets in a boot header
+const BOOT_REVISION: usize = 4;
+const BOOT_CONFIG: usize = 116;
+const BOOT_IMAGE_LEN: usize = 120;
+const BOOT_ENTRY: usize = 124;
+const BOOT_IMAGE_OFFSET: usize = 128;
+const BOOT_HASH: usize = 132;
+const BOOT_CRC: usize = 172;
+
+// Bits of the boot configuration
+const BOOT_SIGNED: u32 = 0b11;
+const BOOT_ENCRYPTED: u32 = 0b11 << 2;
+const BOOT_CRC_IGNORE: u32 = 1 << 16;
+const BOOT_HASH_IGNORE: u32 = 1 << 17;
+
+/// Error parsing a partition table or boot header
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ParseError {
+    /// The data ends before the header or the entries it announces
+    TooShort,
+    /// The magic number is wrong, e.g. because the sector is erased
+    Magic,
+    /// The CRC of the header doesn't match
+    HeaderCrc,
+    /// The header announces more than [`MAX_ENTRIES`] entries
+    TooManyEntries,
+    /// The CRC of the entries doesn't match
+    EntriesCrc,
+}
+
+/// A partition table in place, see the module documentation
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct PartitionTable<'a> {
+    /// The table up to the CRC of its entries
+    bytes: &'a [u8],
+}
+
+impl<'a> PartitionTable<'a> {
+    /// Checks the partition table at the start of `bytes`, anything after it is ignored
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(ParseError::TooShort);
+        }
+        if bytes[..4] != TABLE_MAGIC {
+            return Err(ParseError::Magic);
+        }
+        if crc32(&bytes[..12]) != read_u32(bytes, 12) {
+            return Err(ParseError::HeaderCrc);
+        }
+
+        let entries = read_u16(bytes, 6) as usize;
+        if entries > MAX_ENTRIES {
+            return Err(ParseError::TooManyEntries);
+        }
+        let end = HEADER_SIZE + entries * ENTRY_SIZE;
+        if bytes.len() < end + 4 {
+            return Err(ParseError::TooShort);
+        }
+        if crc32(&bytes[HEADER_SIZE..end]) != read_u32(bytes, end) {
+            return Err(ParseError::EntriesCrc);
+        }
+
+        Ok(PartitionTable {
+            bytes: &bytes[..end + 4],
+        })
+    }
+
+    /// Returns the version of the table format
+    pub fn version(&self) -> u16 {
+        read_u16(self.bytes, 4)
+    }
+
+    /// Returns the age, which goes up with every change of the table
+    pub fn age(&self) -> u32 {
+        read_u32(self.bytes, 8)
+    }
+
+    /// Returns the bytes of the table, including the CRC of its entries
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Returns the entries in the order they are stored
+    pub fn entries(&self) -> impl Iterator<Item = Entry<'a>> {
+        self.bytes[HEADER_SIZE..self.bytes.len() - 4]
+            .chunks(ENTRY_SIZE)
+            .map(|bytes| Entry { bytes })
+    }
+
+    /// Returns the entry called `name`
+    pub fn entry(&self, name: &str) -> Option<Entry<'a>> {
+        self.entries().find(|entry| entry.name() == name.as_bytes())
+    }
+}
+
+impl fmt::Debug for PartitionTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PartitionTable")
+            .field("version", &self.version())
+            .field("age", &self.age())
+            .field("entries", &EntriesDebug(*self))
+            .finish()
+    }
+}
+
+struct EntriesDebug<'a>(PartitionTable<'a>);
+
+impl fmt::Debug for EntriesDebug<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.0.entries()).finish()
+    }
+}
+
+/// An entry of a [`PartitionTable`]
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct Entry<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Entry<'a> {
+    /// Returns the type of the partition, e.g. 0 for the firmware
+    pub fn kind(&self) -> u8 {
+        self.bytes[0]
+    }
+
+    /// Returns the device the partition is on, 0 for the flash
+    pub fn device(&self) -> u8 {
+        self.bytes[1]
+    }
+
+    /// Returns the slot which is in use, 0 or 1
+    pub fn active_index(&self) -> usize {
+        (self.bytes[ENTRY_ACTIVE] != 0) as usize
+    }
+
+    /// Returns the name without the NUL padding
+    pub fn name(&self) -> &'a [u8] {
+        let name = &self.bytes[ENTRY_NAME..ENTRY_NAME + NAME_SIZE];
+        let len = name.iter().position(|&b| b == 0).unwrap_or(NAME_SIZE);
+        &name[..len]
+    }
+
+    /// Returns the flash offset of slot `index`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` isn't 0 or 1.
+    pub fn address(&self, index: usize) -> u32 {
+        assert!(index < 2);
+        read_u32(self.bytes, ENTRY_ADDRESS + 4 * index)
+    }
+
+    /// Returns the maximum length of slot `index`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` isn't 0 or 1.
+    pub fn max_len(&self, index: usize) -> u32 {
+        assert!(index < 2);
+        read_u32(self.bytes, ENTRY_MAX_LEN + 4 * index)
+    }
+
+    /// Returns the length of the contents of the active slot, 0 if it isn't recorded
+    pub fn image_len(&self) -> u32 {
+        read_u32(self.bytes, ENTRY_LEN)
+    }
+
+    /// Returns the age, which goes up with every change of the entry
+    pub fn age(&self) -> u32 {
+        read_u32(self.bytes, ENTRY_AGE)
+    }
+
+    /// Returns the active slot
+    pub fn partition(&self) -> Partition {
+        let index = self.active_index();
+        Partition {
+            kind: self.kind(),
+            address: self.address(index),
+            size: self.max_len(index),
+            image_len: self.image_len(),
+        }
+    }
+}
+
+impl fmt::Debug for Entry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Entry")
+            .field("name", &core::str::from_utf8(self.name()).unwrap_or("?"))
+            .field("kind", &self.kind())
+            .field("active_index", &self.active_index())
+            .field("address", &[self.address(0), self.address(1)])
+            .field("max_len", &[self.max_len(0), self.max_len(1)])
+            .field("image_len", &self.image_len())
+            .field("age", &self.age())
+            .finish()
+    }
+}
+
+/// The active slot of a partition, see [`Entry::partition`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Partition {
+    /// Type of the partition
+    pub kind: u8,
+    /// Flash offset
+    pub address: u32,
+    /// Maximum length
+    pub size: u32,
+    /// Length of the contents, 0 if it isn't recorded
+    pub image_len: u32,
+}
+
+/// A partition table which can be changed, see the module documentation
+///
+/// Every change updates the CRCs, so the table is always valid.
+#[derive(Clone)]
+pub struct PartitionTableBuf {
+    bytes: [u8; MAX_TABLE_SIZE],
+}
+
+impl PartitionTableBuf {
+    /// Returns a table without entries
+    pub fn new(version: u16, age: u32) -> Self {
+        let mut buf = PartitionTableBuf {
+            bytes: [0; MAX_TABLE_SIZE],
+        };
+        buf.bytes[..4].copy_from_slice(&TABLE_MAGIC);
+        buf.bytes[4..6].copy_from_slice(&version.to_le_bytes());
+        write_u32(&mut buf.bytes, 8, age);
+        buf.seal();
+        buf
+    }
+
+    /// Copies `table`
+    pub fn from_table(table: &PartitionTable) -> Self {
+        let mut bytes = [0; MAX_TABLE_SIZE];
+        bytes[..table.bytes.len()].copy_from_slice(table.bytes);
+        PartitionTableBuf { bytes }
+    }
+
+    /// Returns the table
+    pub fn table(&self) -> PartitionTable<'_> {
+        PartitionTable {
+            bytes: &self.bytes[..HEADER_SIZE + self.entries() * ENTRY_SIZE + 4],
+        }
+    }
+
+    /// Returns the bytes to write to the flash
+    pub fn as_bytes(&self) -> &[u8] {
+        self.table().bytes
+    }
+
+    /// Sets the age of the table
+    pub fn set_age(&mut self, age: u32) {
+        write_u32(&mut self.bytes, 8, age);
+        self.seal();
+    }
+
+    /// Appends an entry, returns `false` if the table is full or `name` is longer than 8 bytes
+    pub fn push(
+        &mut self,
+        kind: u8,
+        device: u8,
+        name: &str,
+        address: [u32; 2],
+        max_len: [u32; 2],
+    ) -> bool {
+        let entries = self.entries();
+        if entries == MAX_ENTRIES || name.len() >= NAME_SIZE {
+            return false;
+        }
+
+        let entry = HEADER_SIZE + entries * ENTRY_SIZE;
+        let bytes = &mut self.bytes[entry..entry + ENTRY_SIZE];
+        for byte in bytes.iter_mut() {
+            *byte = 0;
+        }
+        bytes[0] = kind;
+        bytes[1] = device;
+        bytes[ENTRY_NAME..ENTRY_NAME + name.len()].copy_from_slice(name.as_bytes());
+        for index in 0..2 {
+            write_u32(bytes, ENTRY_ADDRESS + 4 * index, address[index]);
+            write_u32(bytes, ENTRY_MAX_LEN + 4 * index, max_len[index]);
+        }
+
+        self.bytes[6..8].copy_from_slice(&(entries as u16 + 1).to_le_bytes());
+        self.seal();
+        true
+    }
+
+    /// Selects slot `index` of the entry `name` with contents of `image_len` bytes and ages the
+    /// entry, returns `false` if there's no such entry
+    ///
+    /// The age of the table isn't changed, see [`set_age`](Self::set_age).
+    pub fn select(&mut self, name: &str, index: usize, image_len: u32) -> bool {
+        let entry = match (0..self.entries())
+            .map(|i| HEADER_SIZE + i * ENTRY_SIZE)
+            .find(|&entry| self.table_entry(entry).name() == name.as_bytes())
+        {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        let age = self.table_entry(entry).age().wrapping_add(1);
+        let bytes = &mut self.bytes[entry..entry + ENTRY_SIZE];
+        bytes[ENTRY_ACTIVE] = (index != 0) as u8;
+        write_u32(bytes, ENTRY_LEN, image_len);
+        write_u32(bytes, ENTRY_AGE, age);
+
+        self.seal();
+        true
+    }
+
+    fn entries(&self) -> usize {
+        read_u16(&self.bytes, 6) as usize
+    }
+
+    fn table_entry(&self, entry: usize) -> Entry<'_> {
+        Entry {
+            bytes: &self.bytes[entry..entry + ENTRY_SIZE],
+        }
+    }
+
+    /// Updates both CRCs
+    fn seal(&mut self) {
+        let header_crc = crc32(&self.bytes[..12]);
+        write_u32(&mut self.bytes, 12, header_crc);
+
+        let end = HEADER_SIZE + self.entries() * ENTRY_SIZE;
+        let entries_crc = crc32(&self.bytes[HEADER_SIZE..end]);
+        write_u32(&mut self.bytes, end, entries_crc);
+    }
+}
+
+impl fmt::Debug for PartitionTableBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.table().fmt(f)
+    }
+}
+
+/// A boot header in place, see the module documentation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BootHeader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> BootHeader<'a> {
+    /// Checks the boot header at the start of `bytes`
+    ///
+    /// The CRC isn't checked if the header says to ignore it, as the boot ROM does. The CRCs of
+    /// the flash and clock configurations aren't checked.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        if bytes.len() < BOOT_HEADER_SIZE {
+            return Err(ParseError::TooShort);
+        }
+        let header = BootHeader {
+            bytes: &bytes[..BOOT_HEADER_SIZE],
+        };
+
+        if bytes[..4] != BOOT_MAGIC {
+            return Err(ParseError::Magic);
+        }
+        if !header.crc_ignored() && crc32(&bytes[..BOOT_CRC]) != read_u32(bytes, BOOT_CRC) {
+            return Err(ParseError::HeaderCrc);
+        }
+
+        Ok(header)
+    }
+
+    /// Returns the revision of the header format
+    pub fn revision(&self) -> u32 {
+        read_u32(self.bytes, BOOT_REVISION)
+    }
+
+    /// Returns the length of the image
+    pub fn image_len(&self) -> u32 {
+        read_u32(self.bytes, BOOT_IMAGE_LEN)
+    }
+
+    /// Returns the entry point, 0 for the start of the XIP window
+    pub fn boot_entry(&self) -> u32 {
+        read_u32(self.bytes, BOOT_ENTRY)
+    }
+
+    /// Returns the offset of the image from the start of the header
+    pub fn image_offset(&self) -> u32 {
+        read_u32(self.bytes, BOOT_IMAGE_OFFSET)
+    }
+
+    /// Returns the SHA-256 of the image, see [`hash_ignored`](Self::hash_ignored)
+    pub fn hash(&self) -> &'a [u8] {
+        &self.bytes[BOOT_HASH..BOOT_HASH + 32]
+    }
+
+    /// Returns whether the image is signed
+    pub fn signed(&self) -> bool {
+        self.config() & BOOT_SIGNED != 0
+    }
+
+    /// Returns whether the image is encrypted
+    pub fn encrypted(&self) -> bool {
+        self.config() & BOOT_ENCRYPTED != 0
+    }
+
+    /// Returns whether the CRC of the header is ignored
+    pub fn crc_ignored(&self) -> bool {
+        self.config() & BOOT_CRC_IGNORE != 0
+    }
+
+    /// Returns whether the hash of the image is ignored
+    pub fn hash_ignored(&self) -> bool {
+        self.config() & BOOT_HASH_IGNORE != 0
+    }
+
+    /// Returns the bytes of the header
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    fn config(&self) -> u32 {
+        read_u32(self.bytes, BOOT_CONFIG)
+    }
+}
+
+/// Reads copy `index` of the partition table, `None` if it isn't valid
+///
+/// # Panics
+///
+/// Panics if `index` isn't 0 or 1.
+pub fn read_table(
+    flash: &mut Flash,
+    index: usize,
+) -> Result<Option<PartitionTableBuf>, super::Error> {
+    let mut bytes = [0; MAX_TABLE_SIZE];
+    flash.read(PARTITION_TABLE_OFFSETS[index], &mut bytes)?;
+
+    Ok(PartitionTable::parse(&bytes)
+        .ok()
+        .map(|table| PartitionTableBuf::from_table(&table)))
+}
+
+/// Returns the index and contents of the copy of the partition table boot2 uses, `None` if
+/// neither is valid
+pub fn active_table(flash: &mut Flash) -> Result<Option<(usize, PartitionTableBuf)>, super::Error> {
+    let first = read_table(flash, 0)?;
+    let second = read_table(flash, 1)?;
+
+    Ok(match (first, second) {
+        (Some(first), Some(second)) if second.table().age() > first.table().age() => {
+            Some((1, second))
+        }
+        (Some(first), _) => Some((0, first)),
+        (None, Some(second)) => Some((1, second)),
+        (None, None) => None,
+    })
+}
+
+/// Erases copy `index` of the partition table and writes `table` into it, the header last, so
+/// a table cut short by a power loss isn't valid
+///
+/// The sector has to be outside the protected range of `flash`, which by default it isn't.
+///
+/// # Panics
+///
+/// Panics if `index` isn't 0 or 1.
+pub fn write_table(
+    flash: &mut Flash,
+    index: usize,
+    table: &PartitionTableBuf,
+) -> Result<(), super::Error> {
+    let offset = PARTITION_TABLE_OFFSETS[index];
+    let bytes = table.as_bytes();
+
+    flash.erase(offset, offset + SECTOR_SIZE)?;
+    flash.write(offset + HEADER_SIZE as u32, &bytes[HEADER_SIZE..])?;
+    flash.write(offset, &bytes[..HEADER_SIZE])
+}
+
+/// Returns the active slot of the partition `name` in the table boot2 uses, `None` if there's
+/// no such partition, no valid table or the flash can't be read
+pub fn find(flash: &mut Flash, name: &str) -> Option<Partition> {
+    let (_, table) = active_table(flash).ok()??;
+    let entry = table.table().entry(name)?;
+    Some(entry.partition())
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    Crc::checksum(Algorithm::Crc32, bytes)
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// The fixtures are synthetic, see `tests/fixtures/README.md`
+#[cfg(test)]
+mod tests {
+    use super::{
+        BootHeader, ParseError, Partition, PartitionTable, PartitionTableBuf, BOOT_HEADER_SIZE,
+        MAX_ENTRIES, MAX_TABLE_SIZE,
+    };
+    use crate::crc::{Algorithm, Crc};
+
+    const PARTITION_2M: &[u8] = include_bytes!("../../tests/fixtures/synthetic_partition_2m.bin");
+    const PARTITION_2M_OTA: &[u8] =
+        include_bytes!("../../tests/fixtures/synthetic_partition_2m_ota.bin");
+    const BOOT_HEADER: &[u8] = include_bytes!("../../tests/fixtures/synthetic_boot_header.bin");
+
+    /// Name, type, addresses and maximum lengths of the entries of `PARTITION_2M`
+    const ENTRIES: [(&str, u8, [u32; 2], [u32; 2]); 7] = [
+        ("FW", 0, [0x10000, 0xd8000], [0xc8000, 0x88000]),
+        ("mfg", 2, [0x160000, 0], [0x32000, 0]),
+        ("media", 3, [0x192000, 0], [0x57000, 0]),
+        ("PSM", 4, [0x1e9000, 0], [0x8000, 0]),
+        ("KEY", 5, [0x1f1000, 0], [0x2000, 0]),
+        ("DATA", 6, [0x1f3000, 0], [0x5000, 0]),
+        ("factory", 7, [0x1f8000, 0], [0x7000, 0]),
+    ];
+
+    const IMAGE_LEN: u32 = 0x6d2a0;
+
+    #[test]
+    fn parse() {
+        let table = PartitionTable::parse(PARTITION_2M).unwrap();
+        assert_eq!((table.version(), table.age()), (0, 0));
+        assert_eq!(table.entries().count(), ENTRIES.len());
+        for (entry, &(name, kind, address, max_len)) in table.entries().zip(ENTRIES.iter()) {
+            assert_eq!(entry.name(), name.as_bytes());
+            assert_eq!(entry.kind(), kind);
+            assert_eq!(entry.device(), 0);
+            assert_eq!(entry.active_index(), 0);
+            assert_eq!([entry.address(0), entry.address(1)], address);
+            assert_eq!([entry.max_len(0), entry.max_len(1)], max_len);
+        }
+        assert_eq!(
+            table.entry("media").map(|entry| entry.partition()),
+            Some(Partition {
+                kind: 3,
+                address: 0x192000,
+                size: 0x57000,
+                image_len: 0,
+            })
+        );
+        assert!(table.entry("nvs").is_none());
+
+        let table = PartitionTable::parse(PARTITION_2M_OTA).unwrap();
+        assert_eq!(table.age(), 1);
+        let firmware = table.entry("FW").unwrap();
+        assert_eq!((firmware.active_index(), firmware.age()), (1, 1));
+        assert_eq!(
+            firmware.partition(),
+            Partition {
+                kind: 0,
+                address: 0xd8000,
+                size: 0x88000,
+                image_len: IMAGE_LEN,
+            }
+        );
+    }
+
+    #[test]
+    fn build() {
+        let mut buf = PartitionTableBuf::new(0, 0);
+        for &(name, kind, address, max_len) in ENTRIES.iter() {
+            assert!(buf.push(kind, 0, name, address, max_len));
+        }
+        assert_eq!(buf.as_bytes(), PARTITION_2M);
+
+        assert!(buf.select("FW", 1, IMAGE_LEN));
+        assert!(!buf.select("nvs", 1, 0));
+        buf.set_age(1);
+        assert_eq!(buf.as_bytes(), PARTITION_2M_OTA);
+
+        assert!(!buf.push(1, 0, "toolongname", [0; 2], [0; 2]));
+        for _ in ENTRIES.len()..MAX_ENTRIES {
+            assert!(buf.push(1, 0, "x", [0; 2], [0; 2]));
+        }
+        assert!(!buf.push(1, 0, "x", [0; 2], [0; 2]));
+        assert!(PartitionTable::parse(buf.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn corrupt_table() {
+        assert_eq!(
+            PartitionTable::parse(&[0xff; 272]).err(),
+            Some(ParseError::Magic)
+        );
+        assert_eq!(
+            PartitionTable::parse(&PARTITION_2M[..100]).err(),
+            Some(ParseError::TooShort)
+        );
+        assert_eq!(
+            PartitionTable::parse(&PARTITION_2M[..8]).err(),
+            Some(ParseError::TooShort)
+        );
+
+        let mut corrupt = PARTITION_2M.to_vec();
+        corrupt[20] ^= 1;
+        assert_eq!(
+            PartitionTable::parse(&corrupt).err(),
+            Some(ParseError::EntriesCrc)
+        );
+        corrupt[20] ^= 1;
+        corrupt[8] ^= 1;
+        assert_eq!(
+            PartitionTable::parse(&corrupt).err(),
+            Some(ParseError::HeaderCrc)
+        );
+
+        let mut buf = PartitionTableBuf::new(0, 0);
+        for _ in 0..MAX_ENTRIES {
+            buf.push(1, 0, "x", [0; 2], [0; 2]);
+        }
+        let mut too_many = [0; MAX_TABLE_SIZE];
+        too_many.copy_from_slice(buf.as_bytes());
+        // Announce one entry more and fix the header CRC, so only the count is wrong
+        too_many[6] += 1;
+        let crc = Crc::checksum(Algorithm::Crc32, &too_many[..12]);
+        too_many[12..16].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(
+            PartitionTable::parse(&too_many).err(),
+            Some(ParseError::TooManyEntries)
+        );
+    }
+
+    #[test]
+    fn boot_header() {
+        let header = BootHeader::parse(BOOT_HEADER).unwrap();
+        assert_eq!(header.revision(), 1);
+        assert_eq!(header.image_len(), IMAGE_LEN);
+        assert_eq!(header.boot_entry(), 0);
+        assert_eq!(header.image_offset(), 0x1000);
+        assert!(header.hash_ignored());
+        assert!(!header.crc_ignored());
+        assert!(!header.signed());
+        assert!(!header.encrypted());
+
+        let mut corrupt = [0; BOOT_HEADER_SIZE];
+        corrupt.copy_from_slice(BOOT_HEADER);
+        corrupt[120] ^= 1;
+        assert_eq!(
+            BootHeader::parse(&corrupt).err(),
+            Some(ParseError::HeaderCrc)
+        );
+        assert_eq!(
+            BootHeader::parse(&BOOT_HEADER[..100]).err(),
+            Some(ParseError::TooShort)
+        );
+        assert_eq!(
+            BootHeader::parse(PARTITION_2M).err(),
+            Some(ParseError::Magic)
+        );
+    }
+}
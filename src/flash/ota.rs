@@ -0,0 +1,432 @@
+/*!
+  # Firmware updates
+  The bootloader (boot2) of the vendor SDK starts the firmware from the `FW` entry of the
+  partition table. The entry has two slots, A and B, each with its own address and maximum
+  length, and its active index selects the slot boot2 boots. An update is written to the slot
+  which isn't running while the firmware keeps running from the other one through the XIP
+  window, then the active index is switched.
+
+  The partition table is stored twice, in the sectors at [`PARTITION_TABLE_OFFSETS`], see
+  [`layout`](super::layout) for its format. Boot2 uses the copy whose CRCs match, the one with
+  the higher age if both do, the first one if their ages are equal.
+  [`OtaUpdater::activate`] never touches that copy: it writes the table with the new active
+  index and a higher age into the other sector, the header last, so a power loss leaves either
+  the old or the new table in effect.
+
+  Until [`mark_boot_successful`] copies the new table over the old one, the old table still
+  selects the previous firmware. [`rollback`] erases the new table, and the previous firmware
+  starts on the next reset. Boot2 itself has no trial boot, so the new firmware has to decide:
+  check [`is_confirmed`] at startup, and either mark the boot successful once it works or roll
+  back, e.g. from a watchdog reset.
+
+  The image is written with [`Flash`], whose erase and program commands run from RAM, so the
+  running firmware isn't read while the flash is busy. Only the slot which is running stays
+  protected while the updater writes, the protected range of the [`Flash`] is put back
+  afterwards. [`OtaUpdater::finalize`] reads the image back with read commands, past the cache,
+  and checks its SHA-256 with the hardware [`Sha`] engine.
+
+  ## Example
+  ```rust
+    use bl602_hal::flash::ota::{self, OtaUpdater};
+
+    let slot = ota::current_slot(&mut flash).unwrap().other();
+    let mut update = OtaUpdater::begin(&mut flash, &mut sha, slot).unwrap();
+    while let Some(chunk) = receive() {
+        update.write(chunk).unwrap();
+    }
+    update.finalize(&expected_sha256).unwrap();
+    update.activate().unwrap();
+    bl602_hal::power::software_reset();
+  ```
+*/
+
+use core::ops::Range;
+
+use embedded_storage::nor_flash::NorFlash;
+
+use super::layout::{self, Entry, PartitionTableBuf};
+use super::{Flash, PAGE_SIZE, SECTOR_SIZE};
+use crate::sha::{self, Mode, Sha};
+
+pub use super::layout::PARTITION_TABLE_OFFSETS;
+
+/// Name of the entry boot2 starts the firmware from
+const FIRMWARE: &str = "FW";
+
+/// Firmware update error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The flash failed
+    Flash(super::Error),
+    /// The SHA engine failed
+    Sha(sha::Error),
+    /// Neither copy of the partition table is valid
+    NoPartitionTable,
+    /// The partition table has no `FW` entry, or its slots aren't sector aligned
+    NoFirmwarePartition,
+    /// The slot is the one the firmware is running from
+    RunningSlot,
+    /// The image is longer than the slot
+    ImageTooLarge,
+    /// `write` or `finalize` was called after `finalize`
+    Finalized,
+    /// `activate` was called before `finalize` succeeded
+    NotFinalized,
+    /// The SHA-256 of the image read back isn't the expected one
+    HashMismatch,
+    /// The partition table read back doesn't select the new slot
+    VerifyFailed,
+    /// Both copies of the partition table select the same slot, there's nothing to roll back to
+    NothingToRollBack,
+}
+
+impl From<super::Error> for Error {
+    fn from(error: super::Error) -> Self {
+        Error::Flash(error)
+    }
+}
+
+impl From<sha::Error> for Error {
+    fn from(error: sha::Error) -> Self {
+        Error::Sha(error)
+    }
+}
+
+/// Slot of the firmware partition
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Slot {
+    A = 0,
+    B = 1,
+}
+
+impl Slot {
+    /// Returns the other slot
+    pub fn other(self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+
+    fn from_index(index: usize) -> Slot {
+        if index == 0 {
+            Slot::A
+        } else {
+            Slot::B
+        }
+    }
+}
+
+/// Writes an image into a slot of the firmware partition, see the module documentation
+pub struct OtaUpdater<'a> {
+    flash: &'a mut Flash,
+    sha: &'a mut Sha,
+    slot: Slot,
+    /// Range of the slot which is running, which stays protected
+    running: Range<u32>,
+    start: u32,
+    max_len: u32,
+    /// Bytes programmed so far
+    written: u32,
+    page: [u8; PAGE_SIZE as usize],
+    /// Bytes in `page` which haven't been programmed yet
+    buffered: usize,
+    finalized: bool,
+}
+
+impl<'a> OtaUpdater<'a> {
+    /// Starts an update of `slot`, which can't be the slot the firmware is running from
+    ///
+    /// The slot is erased sector by sector as the image is written, so an update which is
+    /// abandoned leaves the start of an image behind, which isn't booted as long as the table
+    /// doesn't select it.
+    pub fn begin(flash: &'a mut Flash, sha: &'a mut Sha, slot: Slot) -> Result<Self, Error> {
+        let (_, table) = active_table(flash)?;
+        let running = running_slot(flash, &table);
+        if slot == running {
+            return Err(Error::RunningSlot);
+        }
+
+        Ok(OtaUpdater {
+            running: table.slot_range(running),
+            start: table.address(slot),
+            max_len: table.max_len(slot),
+            flash,
+            sha,
+            slot,
+            written: 0,
+            page: [0xff; PAGE_SIZE as usize],
+            buffered: 0,
+            finalized: false,
+        })
+    }
+
+    /// Returns the slot being written
+    pub fn slot(&self) -> Slot {
+        self.slot
+    }
+
+    /// Returns the number of bytes of the image taken so far
+    pub fn image_len(&self) -> u32 {
+        self.written + self.buffered as u32
+    }
+
+    /// Appends `data` to the image
+    ///
+    /// The data is collected into pages, which are programmed when they are full.
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        if self.finalized {
+            return Err(Error::Finalized);
+        }
+        if self.image_len() as usize + data.len() > self.max_len as usize {
+            return Err(Error::ImageTooLarge);
+        }
+
+        while !data.is_empty() {
+            let len = data.len().min(self.page.len() - self.buffered);
+            self.page[self.buffered..self.buffered + len].copy_from_slice(&data[..len]);
+            self.buffered += len;
+            data = &data[len..];
+
+            if self.buffered == self.page.len() {
+                self.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Programs the rest of the image, reads it back and checks that its SHA-256 is
+    /// `expected_sha256`
+    pub fn finalize(&mut self, expected_sha256: &[u8; 32]) -> Result<(), Error> {
+        if self.finalized {
+            return Err(Error::Finalized);
+        }
+        self.flush()?;
+
+        self.sha.start(Mode::Sha256);
+        let end = self.start + self.written;
+        for address in (self.start..end).step_by(self.page.len()) {
+            let len = self.page.len().min((end - address) as usize);
+            self.flash
+                .read_with_commands(address, &mut self.page[..len])?;
+            self.sha.update(&self.page[..len])?;
+        }
+        let mut digest = [0; 32];
+        self.sha.finalize(&mut digest)?;
+
+        if digest != *expected_sha256 {
+            return Err(Error::HashMismatch);
+        }
+        self.finalized = true;
+        Ok(())
+    }
+
+    /// Switches the firmware partition to the new slot, which is booted after the next reset
+    ///
+    /// The previous firmware stays selected by the old copy of the table until
+    /// [`mark_boot_successful`] is called.
+    pub fn activate(self) -> Result<(), Error> {
+        if !self.finalized {
+            return Err(Error::NotFinalized);
+        }
+
+        let (index, mut table) = active_table(self.flash)?;
+        table.select(self.slot, self.written);
+        let running = self.running;
+        unprotected(self.flash, running, |flash| table.write(flash, 1 - index))?;
+
+        match active_table(self.flash)? {
+            (_, table) if table.active() == self.slot => Ok(()),
+            _ => Err(Error::VerifyFailed),
+        }
+    }
+
+    /// Programs the buffered bytes, erasing the sector first when the page starts one
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.buffered == 0 {
+            return Ok(());
+        }
+
+        let address = self.start + self.written;
+        let page = &self.page[..self.buffered];
+        unprotected(self.flash, self.running.clone(), |flash| {
+            if address % SECTOR_SIZE == 0 {
+                flash.erase(address, address + SECTOR_SIZE)?;
+            }
+            flash.write(address, page)
+        })?;
+
+        self.written += self.buffered as u32;
+        self.buffered = 0;
+        Ok(())
+    }
+}
+
+/// Returns the slot the firmware is running from
+///
+/// That's the slot the XIP window is mapped into, or the one the partition table selects if the
+/// offset of the XIP window isn't known.
+pub fn current_slot(flash: &mut Flash) -> Result<Slot, Error> {
+    let (_, table) = active_table(flash)?;
+    Ok(running_slot(flash, &table))
+}
+
+/// Returns whether both copies of the partition table select the running slot, so there's
+/// nothing left to roll back
+pub fn is_confirmed(flash: &mut Flash) -> Result<bool, Error> {
+    let (index, table) = active_table(flash)?;
+    let running = running_slot(flash, &table);
+    let other = Table::read(flash, 1 - index)?;
+
+    Ok(table.active() == running && other.map(|other| other.active()) == Some(running))
+}
+
+/// Makes the running firmware permanent by writing the partition table which selects it into
+/// the other copy as well
+///
+/// Nothing is written if both copies already select it.
+pub fn mark_boot_successful(flash: &mut Flash) -> Result<(), Error> {
+    if is_confirmed(flash)? {
+        return Ok(());
+    }
+
+    let (index, mut table) = active_table(flash)?;
+    let running = running_slot(flash, &table);
+    if table.active() != running {
+        // Boot2 fell back to the other slot, e.g. because the new image didn't boot
+        return Err(Error::VerifyFailed);
+    }
+
+    let len = table.image_len();
+    table.select(running, len);
+    let range = table.slot_range(running);
+    unprotected(flash, range, |flash| table.write(flash, 1 - index))
+}
+
+/// Erases the newer copy of the partition table, so the previous firmware starts after the
+/// next reset
+///
+/// Fails with [`Error::NothingToRollBack`] once [`mark_boot_successful`] has been called, or if
+/// there's no previous firmware.
+pub fn rollback(flash: &mut Flash) -> Result<(), Error> {
+    let (index, table) = active_table(flash)?;
+    match Table::read(flash, 1 - index)? {
+        Some(other) if other.active() != table.active() => {}
+        _ => return Err(Error::NothingToRollBack),
+    }
+
+    let running = running_slot(flash, &table);
+    let offset = PARTITION_TABLE_OFFSETS[index];
+    unprotected(flash, table.slot_range(running), |flash| {
+        flash.erase(offset, offset + SECTOR_SIZE)
+    })?;
+    Ok(())
+}
+
+/// Runs `f` with only `running` protected, then puts the protected range back
+fn unprotected<R>(
+    flash: &mut Flash,
+    running: Range<u32>,
+    f: impl FnOnce(&mut Flash) -> Result<R, super::Error>,
+) -> Result<R, Error> {
+    let protected = flash.protected();
+    flash.set_protected(running);
+    let result = f(flash);
+    flash.set_protected(protected);
+
+    Ok(result?)
+}
+
+/// Returns the index and contents of the copy of the partition table boot2 uses
+fn active_table(flash: &mut Flash) -> Result<(usize, Table), Error> {
+    match layout::active_table(flash)? {
+        Some((index, buf)) => Ok((index, Table::new(buf)?)),
+        None => Err(Error::NoPartitionTable),
+    }
+}
+
+fn running_slot(flash: &Flash, table: &Table) -> Slot {
+    let xip_offset = match flash.xip_offset() {
+        Some(offset) => offset,
+        None => return table.active(),
+    };
+
+    if table.slot_range(Slot::A).contains(&xip_offset) {
+        Slot::A
+    } else if table.slot_range(Slot::B).contains(&xip_offset) {
+        Slot::B
+    } else {
+        table.active()
+    }
+}
+
+/// A copy of the partition table with a valid `FW` entry
+#[derive(Clone)]
+struct Table {
+    buf: PartitionTableBuf,
+}
+
+impl Table {
+    /// Reads copy `index` of the table, `None` if it isn't valid
+    fn read(flash: &mut Flash, index: usize) -> Result<Option<Table>, Error> {
+        match layout::read_table(flash, index)? {
+            Some(buf) => Ok(Some(Table::new(buf)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn new(buf: PartitionTableBuf) -> Result<Table, Error> {
+        let aligned = match buf.table().entry(FIRMWARE) {
+            Some(entry) => (0..2).all(|index| entry.address(index) % SECTOR_SIZE == 0),
+            None => false,
+        };
+        if aligned {
+            Ok(Table { buf })
+        } else {
+            Err(Error::NoFirmwarePartition)
+        }
+    }
+
+    /// Returns the `FW` entry, which `new` made sure exists
+    fn firmware(&self) -> Entry<'_> {
+        self.buf.table().entry(FIRMWARE).unwrap()
+    }
+
+    fn age(&self) -> u32 {
+        self.buf.table().age()
+    }
+
+    fn active(&self) -> Slot {
+        Slot::from_index(self.firmware().active_index())
+    }
+
+    fn address(&self, slot: Slot) -> u32 {
+        self.firmware().address(slot as usize)
+    }
+
+    fn max_len(&self, slot: Slot) -> u32 {
+        self.firmware().max_len(slot as usize)
+    }
+
+    fn image_len(&self) -> u32 {
+        self.firmware().image_len()
+    }
+
+    fn slot_range(&self, slot: Slot) -> Range<u32> {
+        self.address(slot)..self.address(slot) + self.max_len(slot)
+    }
+
+    /// Selects `slot` with an image of `len` bytes, and ages the entry and the table
+    fn select(&mut self, slot: Slot, len: u32) {
+        let age = self.age().wrapping_add(1);
+        self.buf.select(FIRMWARE, slot as usize, len);
+        self.buf.set_age(age);
+    }
+
+    /// Erases copy `index` and writes the table into it, the header last
+    fn write(&self, flash: &mut Flash, index: usize) -> Result<(), super::Error> {
+        layout::write_table(flash, index, &self.buf)
+    }
+}
@@ -1,7 +1,14 @@
 //! General Purpose Input/Output
+use core::fmt;
 use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
 use crate::pac;
+use crate::regs;
+use crate::sync::{critical, SpinLock};
+
+pub mod qdec;
+pub mod wire_or;
 
 /// Extension trait to split GLB peripheral into independent pins, registers and other modules
 pub trait GlbExt {
@@ -28,19 +35,159 @@ pub trait InterruptPin {
     fn trigger_on_event(&mut self, event: Event);
     fn control_asynchronous(&mut self);
     fn control_synchronous(&mut self);
+    /// Unmasks the interrupt of the pin, the `Gpio` interrupt also has to be enabled with
+    /// [`interrupts::enable`](crate::interrupts::enable) for its handler to run
     fn enable_interrupt(&mut self);
     fn disable_interrupt(&mut self);
     fn clear_interrupt_pending_bit(&mut self);
     fn check_interrupt(&self) -> bool;
 }
 
+/// Pins with an interrupt handler of the [`GpioInterruptManager`]
+static PIN_HANDLERS: SpinLock<[Option<fn()>; PIN_COUNT]> = SpinLock::new([None; PIN_COUNT]);
+
+static MANAGER_TAKEN: AtomicBool = AtomicBool::new(false);
+
+const PIN_COUNT: usize = 23;
+
+/// Dispatches the `Gpio` interrupt to a handler per pin
+///
+/// Once the manager has been taken, the trap handler handles the `Gpio` interrupt itself and a
+/// `Gpio` handler of the application isn't called anymore. The interrupt reads which pins are
+/// pending, clears them and calls the handler of each, in order of the pin number. A pin which
+/// becomes pending again while its handler runs gets another call right afterwards.
+///
+/// The pending bit of a level triggered pin is set again as long as the level lasts, its handler
+/// has to remove the cause, e.g. by reading the device which holds the line.
+///
+/// ```rust
+///   let mut manager = GpioInterruptManager::take().unwrap();
+///   let button = parts.pin3.into_pull_down_input().erase();
+///   manager.on_pin_interrupt(button, Event::PositivePulse, on_button);
+///
+///   fn on_button() {
+///       // ..
+///   }
+/// ```
+pub struct GpioInterruptManager {
+    _ownership: (),
+}
+
+impl GpioInterruptManager {
+    /// Returns the manager and enables the `Gpio` interrupt, `None` if it has been taken before
+    pub fn take() -> Option<Self> {
+        if MANAGER_TAKEN.swap(true, Ordering::AcqRel) {
+            return None;
+        }
+
+        crate::interrupts::enable(crate::interrupts::Interrupt::Gpio);
+        Some(GpioInterruptManager { _ownership: () })
+    }
+
+    /// Calls `f` from the `Gpio` interrupt on every `event` of `pin`
+    ///
+    /// The pin keeps its configuration and stays with the manager, a handler registered for it
+    /// before is replaced.
+    pub fn on_pin_interrupt<MODE>(&mut self, pin: AnyPin<Input<MODE>>, event: Event, f: fn()) {
+        let n = pin.pin;
+        let glb = regs::glb();
+        let bit = 1 << n;
+
+        critical(|| {
+            glb.gpio_int_mask1
+                .modify(|r, w| unsafe { w.bits(r.bits() | bit) })
+        });
+        set_pin_event(n, event);
+
+        clear_pin_interrupt(n);
+        PIN_HANDLERS.lock_irq_disabled()[n as usize] = Some(f);
+        critical(|| {
+            glb.gpio_int_mask1
+                .modify(|r, w| unsafe { w.bits(r.bits() & !bit) })
+        });
+    }
+
+    /// Masks the interrupt of pin `pin_index` and removes its handler, returns whether it had one
+    pub fn remove_handler(&mut self, pin_index: u8) -> bool {
+        if !is_valid_pin(pin_index) {
+            return false;
+        }
+
+        let glb = regs::glb();
+        critical(|| {
+            glb.gpio_int_mask1
+                .modify(|r, w| unsafe { w.bits(r.bits() | 1 << pin_index) })
+        });
+        clear_pin_interrupt(pin_index);
+        PIN_HANDLERS.lock_irq_disabled()[pin_index as usize]
+            .take()
+            .is_some()
+    }
+}
+
+/// Sets the event of pin `n`, with the asynchronous control mode
+fn set_pin_event(n: u8, event: Event) {
+    let glb = regs::glb();
+
+    // Ten pins per register, 3 bits each: the event and the asynchronous control mode
+    let shift = 3 * (n as u32 % 10);
+    let mode_set = &glb.gpio_int_mode_set1 as *const _ as *mut u32;
+    critical(|| unsafe {
+        let mode_set = mode_set.add(n as usize / 10);
+        let mode = (1 << 2) | event as u32;
+        let value = mode_set.read_volatile();
+        mode_set.write_volatile((value & !(0x7 << shift)) | (mode << shift));
+    });
+}
+
+/// Clears the pending bit of pin `n`, which stays cleared only once the clear bit is reset
+fn clear_pin_interrupt(n: u8) {
+    let glb = regs::glb();
+    critical(|| {
+        glb.gpio_int_clr1
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << n) });
+        glb.gpio_int_clr1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << n)) });
+    });
+}
+
+/// Handles the `Gpio` interrupt if the [`GpioInterruptManager`] has been taken, called by the
+/// trap handler
+pub(crate) fn on_interrupt() -> bool {
+    if !MANAGER_TAKEN.load(Ordering::Acquire) {
+        return false;
+    }
+
+    let glb = regs::glb();
+    let pending = glb.gpio_int_stat1.read().bits() & !glb.gpio_int_mask1.read().bits();
+    let handlers = *PIN_HANDLERS.lock();
+    for n in 0..PIN_COUNT as u8 {
+        if pending & 1 << n == 0 {
+            continue;
+        }
+
+        // Cleared first, so an event while the handler runs isn't lost
+        clear_pin_interrupt(n);
+        match handlers[n as usize] {
+            Some(handler) => handler(),
+            // Unmasked some other way, it would keep the interrupt pending
+            None => critical(|| {
+                glb.gpio_int_mask1
+                    .modify(|r, w| unsafe { w.bits(r.bits() | 1 << n) })
+            }),
+        }
+    }
+
+    true
+}
+
 pub use uart_sig::*;
 
 /// UART signals
 pub mod uart_sig {
     use core::marker::PhantomData;
 
-    use crate::pac;
+    use crate::regs;
 
     /// UART0 RTS (type state)
     pub struct Uart0Rts;
@@ -120,7 +267,7 @@ pub mod uart_sig {
                 paste::paste! {
                     #[inline]
                     fn into_uart_mode<T>(self, mode: u8) -> $UartMuxi<T> {
-                        let glb = unsafe { &*pac::GLB::ptr() };
+                        let glb = regs::glb();
 
                         glb.uart_sig_sel_0.modify(|_r, w| unsafe { w
                             .[<uart_ $sigi _sel>]().bits(mode)
@@ -196,6 +343,35 @@ pub mod uart_sig {
         UartMux7,
         "UART multiplexer peripherals for signal 7"
     );
+
+    /// All eight UART multiplexers, for drivers which route the signals themselves
+    ///
+    /// Obtained with [`Parts::take_uart_mux_bundle`](super::Parts::take_uart_mux_bundle). The type
+    /// states of the multiplexers aren't updated when a driver routes them at runtime, so take
+    /// them out of the bundle only when no driver owns it.
+    pub struct UartMuxBundle {
+        pub mux0: UartMux0<Uart0Cts>,
+        pub mux1: UartMux1<Uart0Cts>,
+        pub mux2: UartMux2<Uart0Cts>,
+        pub mux3: UartMux3<Uart0Cts>,
+        pub mux4: UartMux4<Uart0Cts>,
+        pub mux5: UartMux5<Uart0Cts>,
+        pub mux6: UartMux6<Uart0Cts>,
+        pub mux7: UartMux7<Uart0Cts>,
+    }
+
+    impl UartMuxBundle {
+        /// Routes `function` to UART signal `sig`, with the same function numbers as
+        /// `uart_sig_sel_0`
+        pub(crate) fn route(&mut self, sig: u8, function: u8) {
+            let glb = regs::glb();
+            let shift = 4 * sig as u32;
+
+            glb.uart_sig_sel_0.modify(|r, w| unsafe {
+                w.bits((r.bits() & !(0xf << shift)) | ((function as u32) << shift))
+            });
+        }
+    }
 }
 
 /// Clock configurator registers
@@ -241,15 +417,669 @@ pub struct Spi;
 /// I2C pin mode (type state)
 pub struct I2c;
 
+/// Analog pin mode, used by the ADC (type state)
+pub struct Analog;
+
+/// JTAG pin mode, connected to the debug module of the core (type state)
+pub struct Jtag;
+
+/// Pin function selected by a type state, for checking it against the hardware
+#[doc(hidden)]
+pub trait PinMode {
+    /// Value of the `func_sel` field in this mode, `None` if the type state doesn't say
+    const FUNC_SEL: Option<u8>;
+}
+
+impl<MODE> PinMode for Input<MODE> {
+    const FUNC_SEL: Option<u8> = Some(11);
+}
+
+impl<MODE> PinMode for Output<MODE> {
+    const FUNC_SEL: Option<u8> = Some(11);
+}
+
+impl<MODE> PinMode for Pwm<MODE> {
+    const FUNC_SEL: Option<u8> = Some(8);
+}
+
+impl PinMode for Uart {
+    const FUNC_SEL: Option<u8> = Some(7);
+}
+
+impl PinMode for Spi {
+    const FUNC_SEL: Option<u8> = Some(4);
+}
+
+impl PinMode for I2c {
+    const FUNC_SEL: Option<u8> = Some(6);
+}
+
+impl PinMode for Analog {
+    const FUNC_SEL: Option<u8> = Some(10);
+}
+
+impl PinMode for Jtag {
+    const FUNC_SEL: Option<u8> = Some(14);
+}
+
+impl PinMode for () {
+    const FUNC_SEL: Option<u8> = None;
+}
+
+/// Name of a type state for the `Debug` output of pins, e.g. `Output(Floating)`
+#[doc(hidden)]
+pub trait ModeDebug {
+    fn fmt_mode(f: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+macro_rules! impl_mode_debug {
+    ($($Mode:ident),+) => {
+        $(
+            impl ModeDebug for $Mode {
+                fn fmt_mode(f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    f.write_str(stringify!($Mode))
+                }
+            }
+        )+
+    };
+    ($($Mode:ident<MODE>),+) => {
+        $(
+            impl<MODE: ModeDebug> ModeDebug for $Mode<MODE> {
+                fn fmt_mode(f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    f.write_str(concat!(stringify!($Mode), "("))?;
+                    MODE::fmt_mode(f)?;
+                    f.write_str(")")
+                }
+            }
+        )+
+    };
+}
+
+impl_mode_debug!(Floating, PullDown, PullUp, Uart, Spi, I2c, Analog, Jtag);
+impl_mode_debug!(Input<MODE>, Output<MODE>, Pwm<MODE>);
+
+/// Formats the type state `MODE` with [`ModeDebug`]
+struct DebugMode<MODE>(PhantomData<MODE>);
+
+impl<MODE: ModeDebug> fmt::Debug for DebugMode<MODE> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        MODE::fmt_mode(f)
+    }
+}
+
+/// Adds the type state and the pad configuration of pin `n`, as read from the GLB, to `debug`
+fn debug_pad<MODE: ModeDebug>(debug: &mut fmt::DebugStruct<'_, '_>, n: u8) -> fmt::Result {
+    let glb = regs::glb();
+    let first = &glb.gpio_cfgctl0 as *const _ as *const u32;
+    let cfgctl = unsafe { first.add(n as usize / 2).read_volatile() } >> (16 * (n as u32 % 2));
+    let oe = glb.gpio_cfgctl34.read().bits() & (1 << n) != 0;
+
+    debug
+        .field("mode", &DebugMode::<MODE>(PhantomData))
+        .field("drive", &((cfgctl >> 2) & 0b11))
+        .field("oe", &oe)
+        .field("smt", &(cfgctl & 0b10 != 0))
+        .finish()
+}
+
 #[doc(hidden)]
 pub trait UartPin<SIG> {}
 
+/// Number of a typed pin, for drivers which have to reconfigure pins they only know by type
+#[doc(hidden)]
+pub trait PinNumber {
+    const PIN: u8;
+}
+
+/// Typed pins in UART alternate mode, whatever UART signal they are connected to
+#[doc(hidden)]
+pub trait UartModePin: PinNumber {}
+
 // There are Pin0 to Pin22, totally 23 pins
 
+/// Returns whether `n` is the number of a GPIO pin
+pub const fn is_valid_pin(n: u8) -> bool {
+    n <= 22
+}
+
+/// Pins whose function has been set by the HAL, only tracked in debug builds
+#[cfg(debug_assertions)]
+static CONFIGURED_PINS: AtomicU32 = AtomicU32::new(0);
+
+/// Checks in debug builds that pin `n` still has the function of its type state `MODE`, before
+/// it's changed.
+///
+/// Pins handed out by [`GlbExt::split`] are in whatever state the boot ROM left them, so only
+/// pins which have been configured through the HAL before are checked.
+#[inline(always)]
+fn debug_check_mode<MODE: PinMode>(n: u8) {
+    #[cfg(debug_assertions)]
+    {
+        if let Some(expected) = MODE::FUNC_SEL {
+            if CONFIGURED_PINS.load(Ordering::Relaxed) & (1 << n) != 0 {
+                let glb = regs::glb();
+                let first = &glb.gpio_cfgctl0 as *const _ as *const u32;
+                let cfgctl = unsafe { first.add(n as usize / 2).read_volatile() };
+                let actual = ((cfgctl >> (16 * (n as u32 % 2) + 8)) & 0xf) as u8;
+
+                assert!(
+                    actual == expected,
+                    "pin {} has function {}, but its type state expects {}",
+                    n,
+                    actual,
+                    expected
+                );
+            }
+        }
+
+        CONFIGURED_PINS.fetch_or(1 << n, Ordering::Relaxed);
+    }
+    #[cfg(not(debug_assertions))]
+    let _ = n;
+}
+
+/// Level a pin is held at while the chip sleeps
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HoldLevel {
+    Low,
+    High,
+}
+
+/// What happens to a pad while the chip sleeps
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub(crate) enum PadSleep {
+    Hold(HoldLevel),
+    Isolate,
+}
+
+/// Sleep configuration of the pads, one bit per pin
+struct SleepPads {
+    held: AtomicU32,
+    high: AtomicU32,
+    isolated: AtomicU32,
+}
+
+impl SleepPads {
+    const fn new() -> Self {
+        SleepPads {
+            held: AtomicU32::new(0),
+            high: AtomicU32::new(0),
+            isolated: AtomicU32::new(0),
+        }
+    }
+
+    fn hold(&self, n: u8, level: HoldLevel) {
+        let bit = 1 << n;
+        match level {
+            HoldLevel::Low => self.high.fetch_and(!bit, Ordering::Relaxed),
+            HoldLevel::High => self.high.fetch_or(bit, Ordering::Relaxed),
+        };
+        self.isolated.fetch_and(!bit, Ordering::Relaxed);
+        self.held.fetch_or(bit, Ordering::Relaxed);
+    }
+
+    fn isolate(&self, n: u8) {
+        let bit = 1 << n;
+        self.held.fetch_and(!bit, Ordering::Relaxed);
+        self.isolated.fetch_or(bit, Ordering::Relaxed);
+    }
+
+    fn get(&self, n: u8) -> Option<PadSleep> {
+        let bit = 1 << n;
+        if self.held.load(Ordering::Relaxed) & bit != 0 {
+            if self.high.load(Ordering::Relaxed) & bit != 0 {
+                Some(PadSleep::Hold(HoldLevel::High))
+            } else {
+                Some(PadSleep::Hold(HoldLevel::Low))
+            }
+        } else if self.isolated.load(Ordering::Relaxed) & bit != 0 {
+            Some(PadSleep::Isolate)
+        } else {
+            None
+        }
+    }
+
+    fn clear(&self) {
+        self.held.store(0, Ordering::Relaxed);
+        self.high.store(0, Ordering::Relaxed);
+        self.isolated.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Pins held or isolated during power-down sleep
+static PDS_PADS: SleepPads = SleepPads::new();
+
+/// Always-on pads held or isolated during hibernate
+static HBN_PADS: SleepPads = SleepPads::new();
+
+/// Returns how the always-on pad `n` is configured for hibernate
+pub(crate) fn hibernate_pad(n: u8) -> Option<PadSleep> {
+    HBN_PADS.get(n)
+}
+
+/// Drives the pins held for power-down sleep to their level and floats the isolated ones.
+///
+/// Returns the held pins, whose configuration the wake up path must leave alone.
+pub(crate) fn apply_sleep_pads() -> u32 {
+    let glb = regs::glb();
+    let held = PDS_PADS.held.load(Ordering::Relaxed);
+    let high = PDS_PADS.high.load(Ordering::Relaxed);
+    let isolated = PDS_PADS.isolated.load(Ordering::Relaxed);
+
+    glb.gpio_cfgctl32
+        .modify(|r, w| unsafe { w.bits((r.bits() & !held) | (high & held)) });
+    glb.gpio_cfgctl34
+        .modify(|r, w| unsafe { w.bits((r.bits() | held) & !isolated) });
+
+    let first = &glb.gpio_cfgctl0 as *const _ as *mut u32;
+    for n in 0..23 {
+        if isolated & (1 << n) != 0 {
+            // ie, pu and pd
+            let bits = 0b11_0001 << (16 * (n % 2));
+            unsafe {
+                let cfgctl = first.add(n as usize / 2);
+                cfgctl.write_volatile(cfgctl.read_volatile() & !bits);
+            }
+        }
+    }
+
+    held
+}
+
+/// Hands all pins held or isolated for sleep back to their GPIO configuration.
+///
+/// After waking up, first configure the held pins the way they should be driven from now on,
+/// e.g. as push-pull output at the level they were held at, and only then call this. Until then
+/// the hold keeps the pin steady: pins held during power-down sleep aren't touched by the wake up
+/// path, and the always-on pads held during hibernate keep the pull of the HBN section, whose
+/// configuration survives the reset at wake up.
+pub fn release_holds() {
+    PDS_PADS.clear();
+    HBN_PADS.clear();
+
+    let hbn = unsafe { &*pac::HBN::ptr() };
+    // reg_en_hw_pu_pd
+    hbn.hbn_irq_mode
+        .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 16)) });
+}
+
+pub use self::any_pin::*;
+
+/// GPIO pins with the pin number erased from the type
+pub mod any_pin {
+    use core::convert::Infallible;
+    use core::fmt;
+    use core::marker::PhantomData;
+    use core::ops::{Deref, DerefMut};
+    use embedded_hal::digital::blocking::{
+        InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin,
+    };
+    use embedded_hal_zero::digital::v2::{
+        InputPin as InputPinZero, OutputPin as OutputPinZero,
+        StatefulOutputPin as StatefulOutputPinZero, ToggleableOutputPin as ToggleableOutputPinZero,
+    };
+
+    use super::{
+        debug_check_mode, debug_pad, is_valid_pin, Floating, Input, ModeDebug, Output, PinMode,
+        PullDown, PullUp,
+    };
+    use crate::regs;
+    use crate::sync::critical;
+
+    /// Pin whose number is only known at runtime
+    ///
+    /// Obtained from a typed pin with `erase`, or from a pin number with [`AnyPin::new`].
+    pub struct AnyPin<MODE> {
+        pub(crate) pin: u8,
+        pub(crate) _mode: PhantomData<MODE>,
+    }
+
+    impl<MODE: ModeDebug> fmt::Debug for AnyPin<MODE> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            debug_pad::<MODE>(f.debug_struct("AnyPin").field("pin", &self.pin), self.pin)
+        }
+    }
+
+    impl AnyPin<Input<Floating>> {
+        /// Configures pin `n` as a Hi-Z floating input, returns `None` if there is no such pin.
+        ///
+        /// This bypasses the ownership of the typed pin with the same number, the caller has to
+        /// make sure it isn't in use elsewhere.
+        pub fn new(n: u8) -> Option<AnyPin<Input<Floating>>> {
+            if !is_valid_pin(n) {
+                return None;
+            }
+
+            let pin: AnyPin<()> = AnyPin {
+                pin: n,
+                _mode: PhantomData,
+            };
+            Some(pin.into_floating_input())
+        }
+    }
+
+    impl<MODE: PinMode> AnyPin<MODE> {
+        /// Returns the pin number
+        pub fn pin(&self) -> u8 {
+            self.pin
+        }
+
+        /// Configures the pin to operate as a Hi-Z floating output pin.
+        pub fn into_floating_output(self) -> AnyPin<Output<Floating>> {
+            self.into_pin_with_mode(11, false, false, false)
+        }
+
+        /// Configures the pin to operate as a pull-up output pin.
+        pub fn into_pull_up_output(self) -> AnyPin<Output<PullUp>> {
+            self.into_pin_with_mode(11, true, false, false)
+        }
+
+        /// Configures the pin to operate as a pull-down output pin.
+        pub fn into_pull_down_output(self) -> AnyPin<Output<PullDown>> {
+            self.into_pin_with_mode(11, false, true, false)
+        }
+
+        /// Configures the pin to operate as a Hi-Z floating input pin.
+        pub fn into_floating_input(self) -> AnyPin<Input<Floating>> {
+            self.into_pin_with_mode(11, false, false, true)
+        }
+
+        /// Configures the pin to operate as a pull-up input pin.
+        pub fn into_pull_up_input(self) -> AnyPin<Input<PullUp>> {
+            self.into_pin_with_mode(11, true, false, true)
+        }
+
+        /// Configures the pin to operate as a pull-down input pin.
+        pub fn into_pull_down_input(self) -> AnyPin<Input<PullDown>> {
+            self.into_pin_with_mode(11, false, true, true)
+        }
+
+        #[inline]
+        fn into_pin_with_mode<T>(self, mode: u8, pu: bool, pd: bool, ie: bool) -> AnyPin<T> {
+            debug_check_mode::<MODE>(self.pin);
+
+            // Two pins share a configuration register, the odd one uses the upper half word
+            let shift = 16 * (self.pin as u32 % 2);
+            let mask = 0xffff << shift;
+            let config = ((mode as u32) << 8) | ((pd as u32) << 5) | ((pu as u32) << 4) | ie as u32;
+
+            let cfgctl = self.cfgctl();
+            unsafe {
+                let value = cfgctl.read_volatile();
+                cfgctl.write_volatile((value & !mask) | (config << shift));
+            }
+
+            // If we're an input clear the Output Enable bit as well, else set it.
+            let glb = regs::glb();
+            let bit = self.bit();
+            critical(|| {
+                glb.gpio_cfgctl34.modify(|r, w| unsafe {
+                    w.bits(if ie { r.bits() & !bit } else { r.bits() | bit })
+                })
+            });
+
+            AnyPin {
+                pin: self.pin,
+                _mode: PhantomData,
+            }
+        }
+
+        /// Returns the `gpio_cfgctl` register holding the configuration of this pin
+        #[inline(always)]
+        fn cfgctl(&self) -> *mut u32 {
+            // An invalid number would access the registers following the pin configuration
+            debug_assert!(is_valid_pin(self.pin));
+
+            let glb = regs::glb();
+            let first = &glb.gpio_cfgctl0 as *const _ as *mut u32;
+            unsafe { first.add(self.pin as usize / 2) }
+        }
+
+        /// Returns the bit of this pin in the input, output and output enable registers
+        #[inline(always)]
+        fn bit(&self) -> u32 {
+            debug_assert!(is_valid_pin(self.pin));
+
+            1 << self.pin
+        }
+    }
+
+    impl<MODE> AnyPin<Input<MODE>> {
+        fn is_high_inner(&self) -> bool {
+            let glb = regs::glb();
+            glb.gpio_cfgctl30.read().bits() & self.bit() != 0
+        }
+    }
+
+    impl<MODE> AnyPin<Output<MODE>> {
+        /// Configures the pin as a floating input until the returned guard is dropped, which
+        /// restores the output configuration.
+        ///
+        /// The output level is kept and driven again afterwards.
+        pub fn reconfigure_as_input(&mut self) -> ReconfiguredPin<'_, Input<Floating>> {
+            ReconfiguredPin::new(self.pin)
+        }
+
+        fn set_inner(&self, high: bool) {
+            let glb = regs::glb();
+            let bit = self.bit();
+            critical(|| {
+                glb.gpio_cfgctl32.modify(|r, w| unsafe {
+                    w.bits(if high {
+                        r.bits() | bit
+                    } else {
+                        r.bits() & !bit
+                    })
+                })
+            });
+        }
+
+        fn is_output_high_inner(&self) -> bool {
+            let glb = regs::glb();
+            glb.gpio_cfgctl32.read().bits() & self.bit() != 0
+        }
+    }
+
+    impl<MODE> InputPin for AnyPin<Input<MODE>> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_high_inner())
+        }
+
+        fn is_low(&self) -> Result<bool, Self::Error> {
+            Ok(!self.is_high_inner())
+        }
+    }
+
+    impl<MODE> InputPinZero for AnyPin<Input<MODE>> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_high_inner())
+        }
+
+        fn is_low(&self) -> Result<bool, Self::Error> {
+            Ok(!self.is_high_inner())
+        }
+    }
+
+    impl<MODE> OutputPin for AnyPin<Output<MODE>> {
+        type Error = Infallible;
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.set_inner(true);
+            Ok(())
+        }
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.set_inner(false);
+            Ok(())
+        }
+    }
+
+    impl<MODE> OutputPinZero for AnyPin<Output<MODE>> {
+        type Error = Infallible;
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.set_inner(true);
+            Ok(())
+        }
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.set_inner(false);
+            Ok(())
+        }
+    }
+
+    impl<MODE> StatefulOutputPin for AnyPin<Output<MODE>> {
+        fn is_set_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_output_high_inner())
+        }
+
+        fn is_set_low(&self) -> Result<bool, Self::Error> {
+            Ok(!self.is_output_high_inner())
+        }
+    }
+
+    impl<MODE> StatefulOutputPinZero for AnyPin<Output<MODE>> {
+        fn is_set_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.is_output_high_inner())
+        }
+
+        fn is_set_low(&self) -> Result<bool, Self::Error> {
+            Ok(!self.is_output_high_inner())
+        }
+    }
+
+    impl<MODE> ToggleableOutputPin for AnyPin<Output<MODE>> {
+        type Error = Infallible;
+
+        fn toggle(&mut self) -> Result<(), Self::Error> {
+            self.set_inner(!self.is_output_high_inner());
+            Ok(())
+        }
+    }
+
+    impl<MODE> ToggleableOutputPinZero for AnyPin<Output<MODE>> {
+        type Error = Infallible;
+
+        fn toggle(&mut self) -> Result<(), Self::Error> {
+            self.set_inner(!self.is_output_high_inner());
+            Ok(())
+        }
+    }
+
+    /// Output pin borrowed and configured as an input, obtained with `reconfigure_as_input`
+    ///
+    /// Dereferences to an [`AnyPin`] in the input mode and implements the input traits itself,
+    /// so it can be passed to drivers as well. When dropped, the pin gets its previous function,
+    /// pulls and output enable back.
+    pub struct ReconfiguredPin<'a, MODE> {
+        pin: AnyPin<MODE>,
+        /// Configuration half word of the pin before it was reconfigured
+        saved_config: u32,
+        saved_output_enable: bool,
+        _borrow: PhantomData<&'a mut ()>,
+    }
+
+    impl<'a> ReconfiguredPin<'a, Input<Floating>> {
+        pub(crate) fn new(pin: u8) -> Self {
+            let pin: AnyPin<()> = AnyPin {
+                pin,
+                _mode: PhantomData,
+            };
+            let shift = 16 * (pin.pin as u32 % 2);
+            let saved_config = (unsafe { pin.cfgctl().read_volatile() } >> shift) & 0xffff;
+            let glb = regs::glb();
+            let saved_output_enable = glb.gpio_cfgctl34.read().bits() & pin.bit() != 0;
+
+            ReconfiguredPin {
+                pin: pin.into_floating_input(),
+                saved_config,
+                saved_output_enable,
+                _borrow: PhantomData,
+            }
+        }
+    }
+
+    impl<'a, MODE> Drop for ReconfiguredPin<'a, MODE> {
+        fn drop(&mut self) {
+            let pin: AnyPin<()> = AnyPin {
+                pin: self.pin.pin,
+                _mode: PhantomData,
+            };
+            let shift = 16 * (pin.pin as u32 % 2);
+            let cfgctl = pin.cfgctl();
+            unsafe {
+                let value = cfgctl.read_volatile();
+                cfgctl.write_volatile((value & !(0xffff << shift)) | (self.saved_config << shift));
+            }
+
+            let glb = regs::glb();
+            let bit = pin.bit();
+            let output_enable = self.saved_output_enable;
+            critical(|| {
+                glb.gpio_cfgctl34.modify(|r, w| unsafe {
+                    w.bits(if output_enable {
+                        r.bits() | bit
+                    } else {
+                        r.bits() & !bit
+                    })
+                })
+            });
+        }
+    }
+
+    impl<'a, MODE> Deref for ReconfiguredPin<'a, MODE> {
+        type Target = AnyPin<MODE>;
+
+        fn deref(&self) -> &AnyPin<MODE> {
+            &self.pin
+        }
+    }
+
+    impl<'a, MODE> DerefMut for ReconfiguredPin<'a, MODE> {
+        fn deref_mut(&mut self) -> &mut AnyPin<MODE> {
+            &mut self.pin
+        }
+    }
+
+    impl<'a, MODE: ModeDebug> fmt::Debug for ReconfiguredPin<'a, MODE> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_tuple("ReconfiguredPin").field(&self.pin).finish()
+        }
+    }
+
+    impl<'a, MODE> InputPin for ReconfiguredPin<'a, Input<MODE>> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.pin.is_high_inner())
+        }
+
+        fn is_low(&self) -> Result<bool, Self::Error> {
+            Ok(!self.pin.is_high_inner())
+        }
+    }
+
+    impl<'a, MODE> InputPinZero for ReconfiguredPin<'a, Input<MODE>> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.pin.is_high_inner())
+        }
+
+        fn is_low(&self) -> Result<bool, Self::Error> {
+            Ok(!self.pin.is_high_inner())
+        }
+    }
+}
+
 pub use self::pin::*;
 
 macro_rules! impl_glb {
-    ($($Pini: ident: ($pini: ident, $gpio_cfgctli: ident, $UartSigi: ident, $sigi: ident, $spi_kind: ident, $i2c_kind: ident, $gpio_i: ident, $gpio_int_mode_seti: ident) ,)+) => {
+    ($($Pini: ident: ($i: expr, $pini: ident, $gpio_cfgctli: ident, $UartSigi: ident, $sigi: ident, $spi_kind: ident, $i2c_kind: ident, $jtag_kind: ident, $gpio_i: ident, $gpio_int_mode_seti: ident) ,)+) => {
         impl GlbExt for pac::GLB {
             fn split(self) -> Parts {
                 Parts {
@@ -281,10 +1111,41 @@ macro_rules! impl_glb {
             pub clk_cfg: ClkCfg,
         }
 
+        /// GPIO parts without the UART multiplexers, see [`Parts::take_uart_mux_bundle`]
+        pub struct PartialParts {
+            $( pub $pini: $Pini<Input<Floating>>, )+
+            pub clk_cfg: ClkCfg,
+        }
+
+        impl Parts {
+            /// Splits off the UART multiplexers as one bundle, which can be handed to
+            /// [`Serial::uart0_with_mux`](crate::serial::Serial::uart0_with_mux) to have the
+            /// signals routed according to the pins used
+            pub fn take_uart_mux_bundle(self) -> (UartMuxBundle, PartialParts) {
+                let bundle = UartMuxBundle {
+                    mux0: self.uart_mux0,
+                    mux1: self.uart_mux1,
+                    mux2: self.uart_mux2,
+                    mux3: self.uart_mux3,
+                    mux4: self.uart_mux4,
+                    mux5: self.uart_mux5,
+                    mux6: self.uart_mux6,
+                    mux7: self.uart_mux7,
+                };
+                let parts = PartialParts {
+                    $( $pini: self.$pini, )+
+                    clk_cfg: self.clk_cfg,
+                };
+
+                (bundle, parts)
+            }
+        }
+
         /// GPIO pins
         pub mod pin {
             use core::marker::PhantomData;
             use core::convert::Infallible;
+            use embedded_hal::delay::blocking::DelayUs;
             use embedded_hal::digital::blocking::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
             use embedded_hal_zero::digital::v2::{
                 InputPin as InputPinZero,
@@ -292,6 +1153,8 @@ macro_rules! impl_glb {
                 StatefulOutputPin as StatefulOutputPinZero,
                 ToggleableOutputPin as ToggleableOutputPinZero
             };
+            use crate::delay::McycleDelay;
+            #[cfg(feature = "raw-access")]
             use crate::pac;
             use super::*;
 
@@ -319,7 +1182,13 @@ macro_rules! impl_glb {
                 pub(crate) _mode: PhantomData<MODE>,
             }
 
-            impl<MODE> $Pini<MODE> {
+            impl<MODE: ModeDebug> fmt::Debug for $Pini<MODE> {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    debug_pad::<MODE>(&mut f.debug_struct(stringify!($Pini)), $i)
+                }
+            }
+
+            impl<MODE: PinMode> $Pini<MODE> {
                 // 11 -> GPIO_FUN_SWGPIO
                 /// Configures the pin to operate as a Hi-Z floating output pin.
                 pub fn into_floating_output(self) -> $Pini<Output<Floating>> {
@@ -369,10 +1238,28 @@ macro_rules! impl_glb {
                     self.into_pin_with_mode(8, false, false, true)
                 }
 
+                // 10 -> GPIO_FUN_ANALOG
+                /// Configures the pin to operate as an analog input, e.g. for the ADC.
+                ///
+                /// Pulls are always disabled in analog mode.
+                pub fn into_analog(self) -> $Pini<Analog> {
+                    let pin: $Pini<Analog> = self.into_pin_with_mode(10, false, false, false);
+
+                    paste::paste! {
+                        // Neither input nor output buffer may be enabled in analog mode
+                        let glb = regs::glb();
+                        critical(|| glb.gpio_cfgctl34.modify(|_, w| w.[<reg_ $gpio_i _oe>]().clear_bit()));
+                    }
+
+                    pin
+                }
+
                 paste::paste! {
                     #[inline]
                     fn into_pin_with_mode<T>(self, mode: u8, pu: bool, pd: bool, ie: bool) -> $Pini<T> {
-                        let glb = unsafe { &*pac::GLB::ptr() };
+                        debug_check_mode::<MODE>($i);
+
+                        let glb = regs::glb();
 
                         glb.$gpio_cfgctli.modify(|_r, w| unsafe { w
                             .[<reg_ $gpio_i _func_sel>]().bits(mode)
@@ -384,32 +1271,61 @@ macro_rules! impl_glb {
                         });
 
                         // If we're an input clear the Output Enable bit as well, else set it.
-                        glb.gpio_cfgctl34.modify(|_, w| w.[<reg_ $gpio_i _oe>]().bit(!ie));
+                        critical(|| glb.gpio_cfgctl34.modify(|_, w| w.[<reg_ $gpio_i _oe>]().bit(!ie)));
 
                         $Pini { _mode: PhantomData }
                     }
                 }
+
+                /// Returns the GLB register block, which holds the configuration of this pin.
+                ///
+                /// # Safety
+                ///
+                /// The GLB registers are shared with all other pins and with the clock
+                /// configuration. Changing the function or direction of this pin through them
+                /// invalidates its type state, and touching anything else can break other drivers.
+                #[cfg(feature = "raw-access")]
+                pub unsafe fn glb_block(&self) -> &'static pac::glb::RegisterBlock {
+                    regs::glb()
+                }
+
+                /// Erases the pin number from the type, so pins can be stored in arrays or passed
+                /// to code which selects them at runtime.
+                pub fn erase(self) -> AnyPin<MODE> {
+                    AnyPin { pin: $i, _mode: PhantomData }
+                }
+
+                /// Floats the pin while the chip is in power-down sleep.
+                ///
+                /// Pulls and both buffers are disabled right before sleeping, the configuration
+                /// is written back after waking up unless [`PdsConfig::restore_gpio`] is
+                /// disabled. Hibernate powers the GPIO matrix off, so this has no effect there.
+                ///
+                /// [`PdsConfig::restore_gpio`]: crate::pds::PdsConfig::restore_gpio
+                pub fn isolate_during_sleep(&mut self) {
+                    PDS_PADS.isolate($i);
+                }
             }
 
             impl<MODE> $Pini<Input<MODE>> {
                 paste::paste! {
                     /// Enable smitter GPIO input filter
                     pub fn enable_smitter(&mut self) {
-                        let glb = unsafe { &*pac::GLB::ptr() };
+                        let glb = regs::glb();
 
                         glb.$gpio_cfgctli.modify(|_, w| w.[<reg_ $gpio_i _smt>]().set_bit());
                     }
 
                     /// Enable smitter GPIO output filter
                     pub fn disable_smitter(&mut self) {
-                        let glb = unsafe { &*pac::GLB::ptr() };
+                        let glb = regs::glb();
 
                         glb.$gpio_cfgctli.modify(|_, w| w.[<reg_ $gpio_i _smt>]().clear_bit());
                     }
                 }
             }
 
-            impl<MODE> $Pini<MODE> {
+            impl<MODE: PinMode> $Pini<MODE> {
                 paste::paste! {
                     /// Configures the pin to UART alternate mode
                     pub fn [<into_uart_ $sigi>](self) -> $Pini<Uart> {
@@ -428,21 +1344,33 @@ macro_rules! impl_glb {
                         // 6 -> GPIO_FUN_I2C_x
                         self.into_pin_with_mode(6, true, false, true)
                     }
+
+                    /// Configures the pin to JTAG alternate mode
+                    pub fn [<into_jtag_ $jtag_kind>](self) -> $Pini<Jtag> {
+                        // 14 -> GPIO_FUN_JTAG
+                        self.into_pin_with_mode(14, true, false, true)
+                    }
                 }
             }
 
             impl UartPin<$UartSigi> for $Pini<Uart> {}
 
+            impl UartModePin for $Pini<Uart> {}
+
+            impl<MODE> PinNumber for $Pini<MODE> {
+                const PIN: u8 = $i;
+            }
+
             impl<MODE> InternalInputPinImpl for $Pini<Input<MODE>> {
                 paste::paste! {
                     fn is_high_inner(&self) -> bool {
-                        let glb = unsafe { &*pac::GLB::ptr() };
+                        let glb = regs::glb();
                         glb.gpio_cfgctl30.read().[<reg_ $gpio_i _i>]().bit_is_set()
                     }
                 }
                 paste::paste! {
                     fn is_low_inner(&self) -> bool {
-                        let glb = unsafe { &*pac::GLB::ptr() };
+                        let glb = regs::glb();
                         glb.gpio_cfgctl30.read().[<reg_ $gpio_i _i>]().bit_is_clear()
                     }
                 }
@@ -451,14 +1379,14 @@ macro_rules! impl_glb {
             impl<MODE> InternalOutputPinImp for $Pini<Output<MODE>> {
                 paste::paste! {
                     fn set_high_inner(&self) {
-                        let glb = unsafe { &*pac::GLB::ptr() };
-                        glb.gpio_cfgctl32.modify(|_, w| w.[<reg_ $gpio_i _o>]().set_bit())
+                        let glb = regs::glb();
+                        critical(|| glb.gpio_cfgctl32.modify(|_, w| w.[<reg_ $gpio_i _o>]().set_bit()))
                     }
                 }
                 paste::paste! {
                     fn set_low_inner(&self)  {
-                        let glb = unsafe { &*pac::GLB::ptr() };
-                        glb.gpio_cfgctl32.modify(|_, w| w.[<reg_ $gpio_i _o>]().clear_bit())
+                        let glb = regs::glb();
+                        critical(|| glb.gpio_cfgctl32.modify(|_, w| w.[<reg_ $gpio_i _o>]().clear_bit()))
                     }
                 }
             }
@@ -466,12 +1394,12 @@ macro_rules! impl_glb {
             impl<MODE> InternalStatefulOutputImp for $Pini<Output<MODE>> {
                 paste::paste! {
                     fn is_output_high_inner(&self) -> bool {
-                        let glb = unsafe { &*pac::GLB::ptr() };
+                        let glb = regs::glb();
                         glb.gpio_cfgctl32.read().[<reg_ $gpio_i _o>]().bit_is_set()
                     }
 
                     fn is_output_low_inner(& self) -> bool {
-                        let glb = unsafe { &*pac::GLB::ptr() };
+                        let glb = regs::glb();
                         glb.gpio_cfgctl32.read().[<reg_ $gpio_i _o>]().bit_is_clear()
                     }
                 }
@@ -506,7 +1434,7 @@ macro_rules! impl_glb {
 
                 paste::paste! {
                     fn trigger_on_event(&mut self, event: Event) {
-                        let glb = unsafe { &*pac::GLB::ptr() };
+                        let glb = regs::glb();
 
                         glb.$gpio_int_mode_seti.modify(|_, w| { w
                                                                 .[<reg_ $gpio_i _interrupt_trigger_mode>]().bits(event as u8)
@@ -514,7 +1442,7 @@ macro_rules! impl_glb {
                     }
 
                     fn control_asynchronous(&mut self) {
-                        let glb = unsafe { &*pac::GLB::ptr() };
+                        let glb = regs::glb();
 
                         glb.$gpio_int_mode_seti.modify(|_, w| { w
                                                                 .[<reg_ $gpio_i _interrupt_control_mode>]().asynchronous()
@@ -522,7 +1450,7 @@ macro_rules! impl_glb {
                     }
 
                     fn control_synchronous(&mut self) {
-                        let glb = unsafe { &*pac::GLB::ptr() };
+                        let glb = regs::glb();
 
                         glb.$gpio_int_mode_seti.modify(|_, w| { w
                                                                 .[<reg_ $gpio_i _interrupt_control_mode>]().synchronous()
@@ -530,7 +1458,7 @@ macro_rules! impl_glb {
                     }
 
                     fn enable_interrupt(&mut self) {
-                        let glb = unsafe { &*pac::GLB::ptr() };
+                        let glb = regs::glb();
 
                         glb.gpio_int_mask1.modify(|_, w| { w
                                                            .[<reg_ $gpio_i _mask>]().unmasked()
@@ -538,7 +1466,7 @@ macro_rules! impl_glb {
                     }
 
                     fn disable_interrupt(&mut self) {
-                        let glb = unsafe { &*pac::GLB::ptr() };
+                        let glb = regs::glb();
 
                         glb.gpio_int_mask1.modify(|_, w| { w
                                                            .[<reg_ $gpio_i _mask>]().masked()
@@ -546,7 +1474,7 @@ macro_rules! impl_glb {
                     }
 
                     fn clear_interrupt_pending_bit(&mut self) {
-                        let glb = unsafe { &*pac::GLB::ptr() };
+                        let glb = regs::glb();
 
                         glb.gpio_int_clr1.modify(|_, w| { w
                                                           .[<reg_ $gpio_i _interrupt_clear>]().clear_bit()
@@ -554,7 +1482,7 @@ macro_rules! impl_glb {
                     }
 
                     fn check_interrupt(&self) -> bool {
-                        let glb = unsafe { &*pac::GLB::ptr() };
+                        let glb = regs::glb();
 
                         glb.gpio_int_stat1.read().[<reg_ $gpio_i _interrupt_status>]().is_set()
                     }
@@ -637,35 +1565,226 @@ macro_rules! impl_glb {
                 }
             }
 
+            impl<MODE> $Pini<Output<MODE>> {
+                /// Configures the pin as a floating input until the returned guard is dropped,
+                /// which restores the output configuration.
+                ///
+                /// Reads the pin as an input without giving up the output pin, e.g. for a bus
+                /// line which is released to read what the other side drives. The output level
+                /// is kept and driven again afterwards.
+                pub fn reconfigure_as_input(&mut self) -> ReconfiguredPin<'_, Input<Floating>> {
+                    ReconfiguredPin::new($i)
+                }
+
+                /// Keeps the pin at `level` while the chip is in power-down sleep.
+                ///
+                /// The pin is driven to `level` right before sleeping and left alone by the
+                /// wake up path, so it stays there until [`release_holds`] is called. Only the
+                /// always-on pads can be held during hibernate, see `hold_during_hibernate`.
+                pub fn hold_during_sleep(&mut self, level: HoldLevel) {
+                    PDS_PADS.hold($i, level);
+                }
+
+                /// Drives the pin high for `duration_us` microseconds, then low.
+                ///
+                /// A duration of 0 skips the delay and produces the shortest possible pulse.
+                #[inline(always)]
+                pub fn pulse_high(&mut self, duration_us: u32, delay: &mut McycleDelay) -> Result<(), Infallible> {
+                    self.set_high_inner();
+                    if duration_us > 0 {
+                        DelayUs::delay_us(delay, duration_us)?;
+                    }
+                    self.set_low_inner();
+                    Ok(())
+                }
+
+                /// Drives the pin low for `duration_us` microseconds, then high.
+                ///
+                /// A duration of 0 skips the delay and produces the shortest possible pulse.
+                #[inline(always)]
+                pub fn pulse_low(&mut self, duration_us: u32, delay: &mut McycleDelay) -> Result<(), Infallible> {
+                    self.set_low_inner();
+                    if duration_us > 0 {
+                        DelayUs::delay_us(delay, duration_us)?;
+                    }
+                    self.set_high_inner();
+                    Ok(())
+                }
+            }
+
             )+
         }
     };
 }
 
 // There are Pin0 to Pin22, totally 23 pins
+// The functions repeat with the pin number: the UART signal is the pin number modulo 8, so
+// Pin22 is UartSig6 and can't be UartSig7; SPI (MISO, MOSI, SS, SCLK) and JTAG (TMS, TDI, TCK,
+// TDO) go by the pin number modulo 4 and I2C by its parity. Each cfgctl register holds two pins
+// and each int mode set register ten, Pin20 to Pin22 are in gpio_int_mode_set3.
 // todo: generate macros
 impl_glb! {
-    Pin0: (pin0, gpio_cfgctl0, UartSig0, sig0, miso, scl, gpio_0, gpio_int_mode_set1),
-    Pin1: (pin1, gpio_cfgctl0, UartSig1, sig1, mosi, sda, gpio_1, gpio_int_mode_set1),
-    Pin2: (pin2, gpio_cfgctl1, UartSig2, sig2, ss, scl, gpio_2, gpio_int_mode_set1),
-    Pin3: (pin3, gpio_cfgctl1, UartSig3, sig3, sclk, sda, gpio_3, gpio_int_mode_set1),
-    Pin4: (pin4, gpio_cfgctl2, UartSig4, sig4, miso, scl, gpio_4, gpio_int_mode_set1),
-    Pin5: (pin5, gpio_cfgctl2, UartSig5, sig5, mosi, sda, gpio_5, gpio_int_mode_set1),
-    Pin6: (pin6, gpio_cfgctl3, UartSig6, sig6, ss, scl, gpio_6, gpio_int_mode_set1),
-    Pin7: (pin7, gpio_cfgctl3, UartSig7, sig7, sclk, sda, gpio_7, gpio_int_mode_set1),
-    Pin8: (pin8, gpio_cfgctl4, UartSig0, sig0, miso, scl, gpio_8, gpio_int_mode_set1),
-    Pin9: (pin9, gpio_cfgctl4, UartSig1, sig1, mosi, sda, gpio_9, gpio_int_mode_set1),
-    Pin10: (pin10, gpio_cfgctl5, UartSig2, sig2, ss, scl, gpio_10, gpio_int_mode_set2),
-    Pin11: (pin11, gpio_cfgctl5, UartSig3, sig3, sclk, sda, gpio_11, gpio_int_mode_set2),
-    Pin12: (pin12, gpio_cfgctl6, UartSig4, sig4, miso, scl, gpio_12, gpio_int_mode_set2),
-    Pin13: (pin13, gpio_cfgctl6, UartSig5, sig5, mosi, sda, gpio_13, gpio_int_mode_set2),
-    Pin14: (pin14, gpio_cfgctl7, UartSig6, sig6, ss, scl, gpio_14, gpio_int_mode_set2),
-    Pin15: (pin15, gpio_cfgctl7, UartSig7, sig7, sclk, sda, gpio_15, gpio_int_mode_set2),
-    Pin16: (pin16, gpio_cfgctl8, UartSig0, sig0, miso, scl, gpio_16, gpio_int_mode_set2),
-    Pin17: (pin17, gpio_cfgctl8, UartSig1, sig1, mosi, sda, gpio_17, gpio_int_mode_set2),
-    Pin18: (pin18, gpio_cfgctl9, UartSig2, sig2, ss, scl, gpio_18, gpio_int_mode_set2),
-    Pin19: (pin19, gpio_cfgctl9, UartSig3, sig3, sclk, sda, gpio_19, gpio_int_mode_set2),
-    Pin20: (pin20, gpio_cfgctl10, UartSig4, sig4, miso, scl, gpio_20, gpio_int_mode_set3),
-    Pin21: (pin21, gpio_cfgctl10, UartSig5, sig5, mosi, sda, gpio_21, gpio_int_mode_set3),
-    Pin22: (pin22, gpio_cfgctl11, UartSig6, sig6, ss, scl, gpio_22, gpio_int_mode_set3),
+    Pin0: (0, pin0, gpio_cfgctl0, UartSig0, sig0, miso, scl, tms, gpio_0, gpio_int_mode_set1),
+    Pin1: (1, pin1, gpio_cfgctl0, UartSig1, sig1, mosi, sda, tdi, gpio_1, gpio_int_mode_set1),
+    Pin2: (2, pin2, gpio_cfgctl1, UartSig2, sig2, ss, scl, tck, gpio_2, gpio_int_mode_set1),
+    Pin3: (3, pin3, gpio_cfgctl1, UartSig3, sig3, sclk, sda, tdo, gpio_3, gpio_int_mode_set1),
+    Pin4: (4, pin4, gpio_cfgctl2, UartSig4, sig4, miso, scl, tms, gpio_4, gpio_int_mode_set1),
+    Pin5: (5, pin5, gpio_cfgctl2, UartSig5, sig5, mosi, sda, tdi, gpio_5, gpio_int_mode_set1),
+    Pin6: (6, pin6, gpio_cfgctl3, UartSig6, sig6, ss, scl, tck, gpio_6, gpio_int_mode_set1),
+    Pin7: (7, pin7, gpio_cfgctl3, UartSig7, sig7, sclk, sda, tdo, gpio_7, gpio_int_mode_set1),
+    Pin8: (8, pin8, gpio_cfgctl4, UartSig0, sig0, miso, scl, tms, gpio_8, gpio_int_mode_set1),
+    Pin9: (9, pin9, gpio_cfgctl4, UartSig1, sig1, mosi, sda, tdi, gpio_9, gpio_int_mode_set1),
+    Pin10: (10, pin10, gpio_cfgctl5, UartSig2, sig2, ss, scl, tck, gpio_10, gpio_int_mode_set2),
+    Pin11: (11, pin11, gpio_cfgctl5, UartSig3, sig3, sclk, sda, tdo, gpio_11, gpio_int_mode_set2),
+    Pin12: (12, pin12, gpio_cfgctl6, UartSig4, sig4, miso, scl, tms, gpio_12, gpio_int_mode_set2),
+    Pin13: (13, pin13, gpio_cfgctl6, UartSig5, sig5, mosi, sda, tdi, gpio_13, gpio_int_mode_set2),
+    Pin14: (14, pin14, gpio_cfgctl7, UartSig6, sig6, ss, scl, tck, gpio_14, gpio_int_mode_set2),
+    Pin15: (15, pin15, gpio_cfgctl7, UartSig7, sig7, sclk, sda, tdo, gpio_15, gpio_int_mode_set2),
+    Pin16: (16, pin16, gpio_cfgctl8, UartSig0, sig0, miso, scl, tms, gpio_16, gpio_int_mode_set2),
+    Pin17: (17, pin17, gpio_cfgctl8, UartSig1, sig1, mosi, sda, tdi, gpio_17, gpio_int_mode_set2),
+    Pin18: (18, pin18, gpio_cfgctl9, UartSig2, sig2, ss, scl, tck, gpio_18, gpio_int_mode_set2),
+    Pin19: (19, pin19, gpio_cfgctl9, UartSig3, sig3, sclk, sda, tdo, gpio_19, gpio_int_mode_set2),
+    Pin20: (20, pin20, gpio_cfgctl10, UartSig4, sig4, miso, scl, tms, gpio_20, gpio_int_mode_set3),
+    Pin21: (21, pin21, gpio_cfgctl10, UartSig5, sig5, mosi, sda, tdi, gpio_21, gpio_int_mode_set3),
+    Pin22: (22, pin22, gpio_cfgctl11, UartSig6, sig6, ss, scl, tck, gpio_22, gpio_int_mode_set3),
+}
+
+/// The pins JTAG is on after boot: TDO on GPIO11, TMS on GPIO12, TCK on GPIO14 and TDI on GPIO17
+///
+/// Every pin can carry one JTAG signal, the pin number modulo 4 selects which: 0 is TMS, 1 TDI, 2
+/// TCK and 3 TDO. Bundling these four gives the debugger back the pins it boots with, e.g. after
+/// they were used as GPIOs to save power. JTAG stays off if it has been disabled in the eFuses.
+///
+/// ```rust
+///   let jtag = JtagPins::new(parts.pin11, parts.pin12, parts.pin14, parts.pin17);
+/// ```
+pub struct JtagPins {
+    pub tdo: Pin11<Jtag>,
+    pub tms: Pin12<Jtag>,
+    pub tck: Pin14<Jtag>,
+    pub tdi: Pin17<Jtag>,
+}
+
+impl JtagPins {
+    /// Configures the four pins for JTAG
+    pub fn new<M11: PinMode, M12: PinMode, M14: PinMode, M17: PinMode>(
+        tdo: Pin11<M11>,
+        tms: Pin12<M12>,
+        tck: Pin14<M14>,
+        tdi: Pin17<M17>,
+    ) -> Self {
+        JtagPins {
+            tdo: tdo.into_jtag_tdo(),
+            tms: tms.into_jtag_tms(),
+            tck: tck.into_jtag_tck(),
+            tdi: tdi.into_jtag_tdi(),
+        }
+    }
+}
+
+/// GPIO7 and GPIO8 are the only pads wired to the always-on section, whose pulls keep working
+/// while the chip hibernates
+macro_rules! impl_aon_pads {
+    ($($Pini:ident: $i:expr,)+) => {
+        $(
+            impl<MODE> pin::$Pini<Output<MODE>> {
+                /// Keeps the pin at `level` while the chip hibernates, through a pull of the
+                /// HBN section.
+                ///
+                /// Both always-on pads share the pull direction with each other and with the
+                /// wake pins, [`Hbn::enter`](crate::hbn::Hbn::enter) rejects combinations which
+                /// disagree. The pull is weak, so this only holds high impedance loads. After
+                /// waking up the pull stays on until [`release_holds`] is called.
+                pub fn hold_during_hibernate(&mut self, level: HoldLevel) {
+                    HBN_PADS.hold($i, level);
+                }
+            }
+
+            impl<MODE> pin::$Pini<MODE> {
+                /// Floats the pin while the chip hibernates, without pull or input buffer.
+                ///
+                /// This can't be combined with wake pins or with a hold of the other always-on
+                /// pad, which need the pulls of the HBN section.
+                pub fn isolate_during_hibernate(&mut self) {
+                    HBN_PADS.isolate($i);
+                }
+            }
+        )+
+    };
+}
+
+impl_aon_pads! {
+    Pin7: 7,
+    Pin8: 8,
+}
+
+/// Two pins carrying the same peripheral signal, found by [`Parts::check_pin_usage_conflicts`]
+///
+/// A pad has a single function select, so two peripherals can't claim the same pad at once.
+/// What goes wrong silently is one signal routed to two pads: an input is then read from either
+/// of them, and an output drives both.
+#[cfg(debug_assertions)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ConflictError {
+    /// Pin which carries a signal already carried by `other_pin`
+    pub pin: u8,
+    /// Lower numbered pin carrying the signal
+    pub other_pin: u8,
+    /// Function select of both pins: 4 is SPI, 6 I2C, 7 UART and 8 PWM
+    pub function: u8,
+    /// Signal of the function: the UART function of `uart_sig_sel_0` (0 to 3 are RTS, CTS, TX
+    /// and RX of UART0, 4 to 7 those of UART1), the SPI signal (0 to 3 are MISO, MOSI, SS and
+    /// SCLK), the I2C signal (0 is SCL, 1 SDA) or the PWM channel
+    pub signal: u8,
+}
+
+#[cfg(debug_assertions)]
+impl Parts {
+    /// Checks that no UART, SPI, I2C or PWM signal is routed to more than one pin, only in debug
+    /// builds
+    ///
+    /// Reads the function select of every pin and, for UART pins, the function routed to their
+    /// UART signal, so it also sees pins configured by drivers at runtime or left by the boot
+    /// ROM. Returns the first conflict, in the order of the pin numbers.
+    ///
+    /// It doesn't take `self`, since the pins have usually been moved out of the parts by the
+    /// time there is anything to check.
+    pub fn check_pin_usage_conflicts() -> Result<(), ConflictError> {
+        let glb = regs::glb();
+        let first = &glb.gpio_cfgctl0 as *const _ as *const u32;
+        let uart_sig_sel = glb.uart_sig_sel_0.read().bits();
+
+        // One bit per signal, 8 UART, 4 SPI, 2 I2C and 5 PWM, with the pin claiming it first
+        let mut claimed = 0u32;
+        let mut owners = [0u8; 19];
+        for n in 0..PIN_COUNT as u8 {
+            let cfgctl = unsafe { first.add(n as usize / 2).read_volatile() };
+            let function = ((cfgctl >> (16 * (n as u32 % 2) + 8)) & 0xf) as u8;
+            let (offset, signal) = match function {
+                7 => (0, ((uart_sig_sel >> (4 * (n as u32 % 8))) & 0xf) as u8),
+                4 => (8, n % 4),
+                6 => (12, n % 2),
+                8 => (14, n % 5),
+                _ => continue,
+            };
+            // Functions 8 to 15 of the UART multiplexer aren't UART signals
+            if function == 7 && signal > 7 {
+                continue;
+            }
+
+            let index = offset + signal as usize;
+            if claimed & 1 << index != 0 {
+                return Err(ConflictError {
+                    pin: n,
+                    other_pin: owners[index],
+                    function,
+                    signal,
+                });
+            }
+            claimed |= 1 << index;
+            owners[index] = n;
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,284 @@
+/*!
+  # Quadrature decoder
+  The BL602 has no quadrature decoder, so [`Encoder`] decodes the A and B signals of a rotary
+  encoder from the GPIO interrupts of both pins. Every change of either signal is a quarter
+  step, 4 per cycle of the signals, which [`transition`] turns into +1, -1 or 0 with a table
+  indexed by the previous and the current levels. A positive step is A leading B.
+
+  Contact bounce makes a signal go back and forth, which counts +1 and -1 alternately and
+  ends at the right count. A transition which changes both signals at once can't have come
+  from a rotation and is rejected rather than counted, the encoder only takes the new levels
+  as its state, e.g. after bounce which came too fast for the interrupt.
+
+  The GPIO interrupt has no trigger on both edges, so each pin triggers on the level opposite
+  to the one it's at: the handler reads the levels, sets the trigger of each pending pin to
+  the level it doesn't have and clears it. A pin which changed again meanwhile is pending again
+  at once, where an edge trigger would have missed the change.
+
+  [`Encoder::on_interrupt`] has to be called from the `Gpio` interrupt handler, it reads the
+  status and input registers directly, looks up the step and adds it to an atomic counter.
+  [`Encoder::count`] and [`Encoder::take_delta`] return the position in detents once
+  [`Encoder::steps_per_detent`] is set, e.g. 4 for the usual mechanical encoders with a full
+  cycle per detent, and in quarter steps otherwise.
+
+  ## Velocity
+  Each counted step also records the value of `mcycle` and the cycles since the step before,
+  which [`Encoder::velocity`] turns into detents per second. A reversal starts over, so bounce
+  reads as 0 rather than as a very fast turn. Only the low 32 bits of `mcycle` are kept, which
+  wrap after 26 s at 160 MHz: steps more than a second apart read as 0, and `velocity` has to
+  be called at least every 26 s to notice that the encoder stopped.
+
+  ## Example
+  ```rust
+    use bl602_hal::gpio::qdec::Encoder;
+    use bl602_hal::sync::SpinLock;
+
+    static ENCODER: SpinLock<Option<Encoder<PullUp>>> = SpinLock::new(None);
+
+    let a = parts.pin3.into_pull_up_input().erase();
+    let b = parts.pin4.into_pull_up_input().erase();
+    *ENCODER.lock_irq_disabled() = Some(Encoder::new(a, b).steps_per_detent(4));
+    interrupts::enable(Interrupt::Gpio);
+
+    let turned = ENCODER.lock_irq_disabled().as_ref().map_or(0, Encoder::take_delta);
+
+    bl602_hal::interrupt!(Gpio, on_gpio);
+
+    fn on_gpio() {
+        if let Some(encoder) = ENCODER.lock().as_ref() {
+            encoder.on_interrupt();
+        }
+        interrupts::clear_interrupt(Interrupt::Gpio);
+    }
+  ```
+*/
+
+use core::sync::atomic::{AtomicI32, AtomicU32, AtomicU8, Ordering};
+
+use riscv::register::mcycle;
+
+use super::{clear_pin_interrupt, critical, set_pin_event, AnyPin, Event, Input};
+use crate::clock::Clocks;
+use crate::regs;
+
+/// Steps by the previous state in bits 3:2 and the current state in bits 1:0, with A in the
+/// upper bit of each
+const TRANSITIONS: [i8; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+/// Returns the quarter steps from the `previous` to the `current` state, the level of A in bit
+/// 1 and B in bit 0
+///
+/// A change of one signal is +1 or -1, no change and a change of both are 0.
+pub const fn transition(previous: u8, current: u8) -> i32 {
+    TRANSITIONS[((previous & 0b11) << 2 | (current & 0b11)) as usize] as i32
+}
+
+/// Rotary encoder on two GPIO interrupts, see the module documentation
+pub struct Encoder<MODE> {
+    a: AnyPin<Input<MODE>>,
+    b: AnyPin<Input<MODE>>,
+    steps_per_detent: i32,
+    state: AtomicU8,
+    steps: AtomicI32,
+    taken: AtomicI32,
+    /// `mcycle` at the last step
+    last_step: AtomicU32,
+    /// Cycles between the last two steps, negative for backward steps, 0 if unknown
+    interval: AtomicI32,
+}
+
+impl<MODE> Encoder<MODE> {
+    /// Takes the pins of A and B and unmasks their interrupts, counting from 0
+    ///
+    /// The pins keep their pulls, most encoders need pull-ups. The `Gpio` interrupt has to be
+    /// enabled as well, with [`on_interrupt`](Self::on_interrupt) called from its handler.
+    pub fn new(pin_a: AnyPin<Input<MODE>>, pin_b: AnyPin<Input<MODE>>) -> Self {
+        let glb = regs::glb();
+        let levels = glb.gpio_cfgctl30.read().bits();
+
+        let encoder = Encoder {
+            a: pin_a,
+            b: pin_b,
+            steps_per_detent: 1,
+            state: AtomicU8::new(0),
+            steps: AtomicI32::new(0),
+            taken: AtomicI32::new(0),
+            last_step: AtomicU32::new(0),
+            interval: AtomicI32::new(0),
+        };
+        encoder
+            .state
+            .store(encoder.state_of(levels), Ordering::Relaxed);
+
+        for &n in &[encoder.a.pin, encoder.b.pin] {
+            wait_for_change(n, levels);
+            clear_pin_interrupt(n);
+        }
+        critical(|| {
+            glb.gpio_int_mask1
+                .modify(|r, w| unsafe { w.bits(r.bits() & !encoder.mask()) })
+        });
+
+        encoder
+    }
+
+    /// Counts `steps` quarter steps as one, e.g. 4 for encoders with a full cycle per detent
+    ///
+    /// The count changes half way between two detents, so bounce at a detent doesn't count.
+    pub fn steps_per_detent(mut self, steps: u8) -> Self {
+        self.steps_per_detent = steps.max(1) as i32;
+
+        self
+    }
+
+    /// Counts the change of A or B which caused the `Gpio` interrupt, returns whether either
+    /// pin was pending
+    pub fn on_interrupt(&self) -> bool {
+        let glb = regs::glb();
+        let pending = glb.gpio_int_stat1.read().bits() & self.mask();
+        if pending == 0 {
+            return false;
+        }
+
+        let levels = glb.gpio_cfgctl30.read().bits();
+        for &n in &[self.a.pin, self.b.pin] {
+            if pending & 1 << n != 0 {
+                wait_for_change(n, levels);
+                clear_pin_interrupt(n);
+            }
+        }
+
+        let current = self.state_of(levels);
+        let previous = self.state.swap(current, Ordering::Relaxed);
+        let step = transition(previous, current);
+        if step != 0 {
+            self.steps.fetch_add(step, Ordering::Relaxed);
+            self.record_step(step);
+        }
+        true
+    }
+
+    /// Returns the position in detents, or in quarter steps without
+    /// [`steps_per_detent`](Self::steps_per_detent)
+    pub fn count(&self) -> i32 {
+        let steps = self.steps.load(Ordering::Relaxed);
+        steps
+            .wrapping_add(self.steps_per_detent / 2)
+            .div_euclid(self.steps_per_detent)
+    }
+
+    /// Returns the change of [`count`](Self::count) since the last call
+    pub fn take_delta(&self) -> i32 {
+        let count = self.count();
+        count.wrapping_sub(self.taken.swap(count, Ordering::Relaxed))
+    }
+
+    /// Returns the velocity in detents per second, negative when turning backward
+    ///
+    /// It's estimated from the time between the last two steps, or the time since the last
+    /// step once that's longer, so it drops while the encoder slows down.
+    pub fn velocity(&self, clocks: &Clocks) -> f32 {
+        let interval = self.interval.load(Ordering::Relaxed);
+        if interval == 0 {
+            return 0.0;
+        }
+
+        let sysclk = clocks.sysclk().0;
+        let since = (mcycle::read() as u32).wrapping_sub(self.last_step.load(Ordering::Relaxed));
+        if since >= sysclk {
+            // Stopped, before `since` wraps and looks recent again
+            self.interval
+                .compare_exchange(interval, 0, Ordering::Relaxed, Ordering::Relaxed)
+                .ok();
+            return 0.0;
+        }
+
+        let cycles = since.max(interval.wrapping_abs() as u32);
+        let detents = sysclk as f32 / cycles as f32 / self.steps_per_detent as f32;
+        if interval < 0 {
+            -detents
+        } else {
+            detents
+        }
+    }
+
+    /// Masks the interrupts of the pins and releases them
+    pub fn free(self) -> (AnyPin<Input<MODE>>, AnyPin<Input<MODE>>) {
+        let glb = regs::glb();
+        let mask = self.mask();
+        critical(|| {
+            glb.gpio_int_mask1
+                .modify(|r, w| unsafe { w.bits(r.bits() | mask) })
+        });
+        clear_pin_interrupt(self.a.pin);
+        clear_pin_interrupt(self.b.pin);
+        (self.a, self.b)
+    }
+
+    fn record_step(&self, step: i32) {
+        let now = mcycle::read() as u32;
+        let since = now.wrapping_sub(self.last_step.swap(now, Ordering::Relaxed));
+        let interval = self.interval.load(Ordering::Relaxed);
+
+        // A reversal is bounce or the knob turned back, the speed so far says nothing either way
+        let interval = if interval != 0 && (interval > 0) != (step > 0) {
+            0
+        } else {
+            since.min(i32::MAX as u32) as i32 * step
+        };
+        self.interval.store(interval, Ordering::Relaxed);
+    }
+
+    fn state_of(&self, levels: u32) -> u8 {
+        ((levels >> self.a.pin & 1) << 1 | levels >> self.b.pin & 1) as u8
+    }
+
+    fn mask(&self) -> u32 {
+        1 << self.a.pin | 1 << self.b.pin
+    }
+}
+
+/// Triggers pin `n` on the level it doesn't have in `levels`
+fn wait_for_change(n: u8, levels: u32) {
+    let event = if levels & 1 << n != 0 {
+        Event::NegativeLevel
+    } else {
+        Event::HighLevel
+    };
+    set_pin_event(n, event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::transition;
+
+    /// Sums the steps of a sequence of states, the level of A in bit 1 and B in bit 0
+    fn replay(states: &[u8]) -> i32 {
+        states
+            .windows(2)
+            .map(|pair| transition(pair[0], pair[1]))
+            .sum()
+    }
+
+    /// Sequences of the kind a mechanical encoder produces at its contacts
+    #[test]
+    fn bounce() {
+        // One detent forward, A leading B, with A bouncing at both of its edges
+        assert_eq!(
+            replay(&[0b11, 0b01, 0b11, 0b01, 0b11, 0b01, 0b00, 0b10, 0b00, 0b10, 0b11]),
+            4
+        );
+        // One detent backward, with B bouncing at both of its edges
+        assert_eq!(
+            replay(&[0b11, 0b10, 0b11, 0b10, 0b00, 0b01, 0b00, 0b01, 0b11]),
+            -4
+        );
+        // Both contacts bouncing together, faster than the interrupt, so only changes of both
+        // are seen
+        assert_eq!(replay(&[0b11, 0b00, 0b11, 0b00, 0b11]), 0);
+        // A resting at a detent with B chattering
+        assert_eq!(replay(&[0b11, 0b10, 0b11, 0b10, 0b11, 0b10, 0b11]), 0);
+        // Half a detent forward and back again
+        assert_eq!(replay(&[0b11, 0b01, 0b00, 0b01, 0b11]), 0);
+    }
+}
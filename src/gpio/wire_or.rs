@@ -0,0 +1,256 @@
+/*!
+  # Wire-ORed interrupt lines
+  Several I2C devices with open-drain interrupt outputs can share one active-low line with a
+  pull-up, e.g. the sensors of a sensor hub on a single GPIO. The line is low as long as any of
+  them holds it, so the host has to ask each device whether it's one of them, and acknowledge
+  its interrupt, before the line is released.
+
+  [`WireOrInterrupt`] keeps the devices registered as sources, up to [`MAX_SOURCES`], each with
+  the register holding its interrupt flags and how they're acknowledged. When the line fires,
+  [`WireOrInterrupt::identify_sources`] reads the status register of every source and returns
+  the addresses of those with a pending interrupt. The pending bit of the pin is cleared only
+  after all of them have been acknowledged; if the line is still low then, because another
+  source asserted it meanwhile, the sources are read again, so no interrupt is lost to an edge
+  which never came.
+
+  ## Example
+  ```rust
+    use bl602_hal::gpio::wire_or::{Acknowledge, Source, WireOrInterrupt};
+
+    let mut pin = parts.pin3.into_pull_up_input();
+    pin.trigger_on_event(Event::NegativePulse);
+    pin.enable_interrupt();
+
+    let mut line = WireOrInterrupt::new(pin);
+    // Accelerometer at 0x18, INT1_SRC cleared by reading it
+    line.register(Source::new(0x18, 0x31, 0x40, Acknowledge::OnRead)).unwrap();
+    // Light sensor at 0x29, interrupt flag cleared by writing it back
+    line.register(Source::new(0x29, 0x13, 0x01, Acknowledge::WriteBack)).unwrap();
+
+    // in the Gpio interrupt handler
+    if line.is_pending() {
+        for &address in line.identify_sources(&mut i2c).unwrap().iter() {
+            // read the data of the device at `address`
+        }
+    }
+  ```
+*/
+
+use core::convert::Infallible;
+use core::fmt;
+use core::ops::Deref;
+
+use embedded_hal::digital::blocking::InputPin;
+use embedded_hal::i2c::blocking::{Read, Write};
+use embedded_hal::i2c::{ErrorKind, SevenBitAddress};
+
+use super::InterruptPin;
+
+/// Most devices which can share a line
+pub const MAX_SOURCES: usize = 8;
+
+/// Times the sources are read while the line stays low
+const MAX_PASSES: usize = 4;
+
+/// Wire-ORed interrupt error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading or acknowledging a source failed
+    I2c(ErrorKind),
+    /// There are [`MAX_SOURCES`] sources already
+    Full,
+    /// The line stayed low with all sources acknowledged, a device which isn't registered or
+    /// doesn't let go holds it
+    LineStuck,
+}
+
+/// How a source's interrupt flags are acknowledged
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Acknowledge {
+    /// Reading the status register clears them
+    OnRead,
+    /// Writing the flags which are set back to the status register clears them
+    WriteBack,
+}
+
+/// A device which can pull the shared line
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Source {
+    /// 7 bit I2C address
+    pub address: SevenBitAddress,
+    /// Register holding the interrupt flags
+    pub status_register: u8,
+    /// Flags of the status register which pull the line
+    pub mask: u8,
+    /// How the flags are acknowledged
+    pub acknowledge: Acknowledge,
+}
+
+impl Source {
+    /// Returns a source at `address`
+    pub const fn new(
+        address: SevenBitAddress,
+        status_register: u8,
+        mask: u8,
+        acknowledge: Acknowledge,
+    ) -> Self {
+        Source {
+            address,
+            status_register,
+            mask,
+            acknowledge,
+        }
+    }
+}
+
+/// Addresses of the sources with a pending interrupt, in the order they were found
+///
+/// Not a `heapless::Vec`: heapless has used const generics since 0.7, which the MSRV of 1.46
+/// doesn't have.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct PendingSources {
+    addresses: [SevenBitAddress; MAX_SOURCES],
+    len: usize,
+}
+
+impl PendingSources {
+    const fn new() -> Self {
+        PendingSources {
+            addresses: [0; MAX_SOURCES],
+            len: 0,
+        }
+    }
+
+    /// Adds `address` unless it's there already, from an earlier pass
+    fn insert(&mut self, address: SevenBitAddress) {
+        if !self.contains(&address) {
+            self.addresses[self.len] = address;
+            self.len += 1;
+        }
+    }
+}
+
+impl Deref for PendingSources {
+    type Target = [SevenBitAddress];
+
+    fn deref(&self) -> &[SevenBitAddress] {
+        &self.addresses[..self.len]
+    }
+}
+
+impl fmt::Debug for PendingSources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Sources of an interrupt line shared by several devices, see the module documentation
+pub struct WireOrInterrupt<PIN> {
+    pin: PIN,
+    sources: [Option<Source>; MAX_SOURCES],
+}
+
+impl<PIN> WireOrInterrupt<PIN>
+where
+    PIN: InterruptPin + InputPin<Error = Infallible>,
+{
+    /// Takes the pin of the line, set up for its interrupt, without any sources
+    ///
+    /// The pin needs a pull-up, internal or external, and to trigger on the falling edge or
+    /// the low level.
+    pub fn new(pin: PIN) -> Self {
+        WireOrInterrupt {
+            pin,
+            sources: [None; MAX_SOURCES],
+        }
+    }
+
+    /// Adds `source`, replacing the one at the same address
+    pub fn register(&mut self, source: Source) -> Result<(), Error> {
+        let slot = match self.position(source.address) {
+            Some(slot) => slot,
+            None => self
+                .sources
+                .iter()
+                .position(Option::is_none)
+                .ok_or(Error::Full)?,
+        };
+        self.sources[slot] = Some(source);
+        Ok(())
+    }
+
+    /// Removes the source at `address`, returns it if there was one
+    pub fn unregister(&mut self, address: SevenBitAddress) -> Option<Source> {
+        let slot = self.position(address)?;
+        self.sources[slot].take()
+    }
+
+    /// Returns the registered sources
+    pub fn sources(&self) -> impl Iterator<Item = &Source> {
+        self.sources.iter().flatten()
+    }
+
+    /// Returns whether the pin's interrupt is pending
+    pub fn is_pending(&self) -> bool {
+        self.pin.check_interrupt()
+    }
+
+    /// Reads and acknowledges every source, and returns the addresses of those with a pending
+    /// interrupt once the line has been released
+    ///
+    /// The pending bit of the pin is cleared after all sources have been acknowledged. If the
+    /// line is still low, the sources are read again, up to 4 times, before this fails with
+    /// [`Error::LineStuck`]. After an I2C error, the pending bit is left set, so the handler
+    /// runs again.
+    pub fn identify_sources<I2C, E>(&mut self, i2c: &mut I2C) -> Result<PendingSources, Error>
+    where
+        I2C: Read<SevenBitAddress, Error = E> + Write<SevenBitAddress, Error = E>,
+        E: embedded_hal::i2c::Error,
+    {
+        let mut pending = PendingSources::new();
+
+        for _ in 0..MAX_PASSES {
+            for source in self.sources.iter().flatten() {
+                let mut status = [0];
+                i2c.write(source.address, &[source.status_register])
+                    .and_then(|()| i2c.read(source.address, &mut status))
+                    .map_err(|e| Error::I2c(e.kind()))?;
+
+                let flags = status[0] & source.mask;
+                if flags == 0 {
+                    continue;
+                }
+                if source.acknowledge == Acknowledge::WriteBack {
+                    i2c.write(source.address, &[source.status_register, flags])
+                        .map_err(|e| Error::I2c(e.kind()))?;
+                }
+                pending.insert(source.address);
+            }
+
+            // An edge while the line was held low by another source never came, so the level
+            // tells whether one is still waiting
+            self.pin.clear_interrupt_pending_bit();
+            if self.pin.is_high().unwrap_or(true) {
+                return Ok(pending);
+            }
+        }
+        Err(Error::LineStuck)
+    }
+
+    /// Returns the pin, e.g. to change its trigger
+    pub fn pin(&mut self) -> &mut PIN {
+        &mut self.pin
+    }
+
+    /// Releases the pin
+    pub fn free(self) -> PIN {
+        self.pin
+    }
+
+    fn position(&self, address: SevenBitAddress) -> Option<usize> {
+        self.sources
+            .iter()
+            .position(|source| matches!(source, Some(source) if source.address == address))
+    }
+}
@@ -0,0 +1,473 @@
+/*!
+  # HART
+  The digital side of the HART protocol, which process instruments speak over their 4-20 mA
+  loop, for a field communicator or another master. The FSK physical layer is left to an
+  external modem, e.g. an AD5700, on a UART set up for 1200 baud, 8 data bits, odd parity and
+  one stop bit, with the modem's RTS on a GPIO. Enabled by the `hart` feature.
+
+  [`Hart::send_command`] sends a request and waits for the response of the addressed device.
+  Both are frames of this form:
+
+  | Field        | Bytes  | Content                                                       |
+  |--------------|--------|---------------------------------------------------------------|
+  | Preamble     | 5-20   | `0xff`                                                        |
+  | Delimiter    | 1      | `0x02` request, `0x06` response, `0x01` burst, `0x80` if long |
+  | Address      | 1 or 5 | see [`HartAddr`], with the master bit                         |
+  | Command      | 1      |                                                               |
+  | Byte count   | 1      | length of the data                                            |
+  | Data         | 0-255  | in a response, the response code and device status first      |
+  | Check byte   | 1      | XOR of everything from the delimiter on                       |
+
+  A response pairs with the request by its delimiter, address and command; other frames, like
+  the burst frames of a device in burst mode or the requests of another master, are skipped.
+  The response code is passed on in the [`HartResponse`], as the meaning of most codes depends
+  on the command, except for a communication error, where the device didn't understand the
+  request: that is an [`HartError::Communication`]. Like one which times out or arrives broken,
+  such a request is sent again, up to [`Config::retries`] times.
+
+  Devices are reached by their polling address at first, which is 0 for a device on its own
+  loop. Command 0 returns the identity of the device, from which
+  [`HartAddr::from_identity`] takes its unique long address, needed for most other commands
+  since HART 6.
+
+  ## Example
+  ```rust
+    use bl602_hal::hart::{self, Hart, HartAddr};
+
+    let serial = Serial::uart0(
+        dp.UART,
+        Config::default().baudrate(1200.Bd()).parity_odd(),
+        ((pin16, mux0), (pin7, mux7)),
+        clocks,
+    );
+    let rts = parts.pin11.into_pull_up_output();
+    let mut hart = Hart::new(serial, rts, hart::Config::default(), clocks);
+
+    let identity = hart.send_command(HartAddr::Short(0), 0, &[]).unwrap();
+    let device = HartAddr::from_identity(identity.data()).unwrap();
+    // Command 1, primary variable: units code and IEEE 754 value
+    let pv = hart.send_command(device, 1, &[]).unwrap();
+  ```
+*/
+
+use core::convert::Infallible;
+use core::fmt;
+
+use embedded_hal::digital::blocking::OutputPin;
+use embedded_hal::serial::nb::{Read, Write};
+use embedded_hal::serial::{Error as _, ErrorKind};
+
+use crate::clock::Clocks;
+
+/// Most data bytes in a [`HartResponse`], after the response code and device status
+pub const MAX_RESPONSE_DATA: usize = 253;
+
+// Delimiter
+const DELIMITER_LONG_ADDRESS: u8 = 0x80;
+const DELIMITER_EXPANSION_POS: u8 = 5;
+const DELIMITER_FRAME_TYPE: u8 = 0b111;
+const FRAME_BURST: u8 = 0b001;
+const FRAME_REQUEST: u8 = 0b010;
+const FRAME_RESPONSE: u8 = 0b110;
+
+// First byte of the address
+const ADDRESS_PRIMARY_MASTER: u8 = 0x80;
+const ADDRESS_BURST: u8 = 0x40;
+
+/// Set in the response code for a communication error, with the error flags in the other bits
+const COMMUNICATION_ERROR: u8 = 0x80;
+
+/// Preamble bytes a receiver needs to see before the delimiter
+const MIN_PREAMBLES: u8 = 2;
+
+/// Longest gap between the bytes of a frame, a little over two characters at 1200 baud
+const CHARACTER_GAP_MS: u32 = 20;
+
+/// HART error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum HartError {
+    /// No response arrived in time
+    Timeout,
+    /// The response had a wrong check byte
+    Checksum,
+    /// The response was too short to hold the response code and device status
+    InvalidResponse,
+    /// The device reported a communication error with these flags, e.g. `0x08` for a wrong
+    /// check byte of the request
+    Communication(u8),
+    /// The polling address is above 63, or there are more than 255 bytes of data
+    InvalidRequest,
+    /// The serial port reported an error
+    Serial(ErrorKind),
+}
+
+/// Address of a field device
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HartAddr {
+    /// Polling address, 0 to 63, or 0 to 15 before HART 6
+    Short(u8),
+    /// Unique address: the expanded device type, or the manufacturer ID and the device type
+    /// before HART 7, of which the top two bits are dropped, and the 24 bit device ID
+    Long { device_type: u16, device_id: u32 },
+}
+
+impl HartAddr {
+    /// Returns the long address from the data of the response to command 0, or `None` if the
+    /// data isn't an identity
+    pub fn from_identity(data: &[u8]) -> Option<Self> {
+        if data.len() < 12 || data[0] != 254 {
+            return None;
+        }
+        Some(HartAddr::Long {
+            device_type: u16::from_be_bytes([data[1], data[2]]),
+            device_id: u32::from_be_bytes([0, data[9], data[10], data[11]]),
+        })
+    }
+
+    /// Returns the address bytes of a frame with the master bit, and their number
+    fn encode(&self, primary_master: bool) -> ([u8; 5], usize) {
+        let master = if primary_master {
+            ADDRESS_PRIMARY_MASTER
+        } else {
+            0
+        };
+        match *self {
+            HartAddr::Short(polling) => ([master | polling, 0, 0, 0, 0], 1),
+            HartAddr::Long {
+                device_type,
+                device_id,
+            } => {
+                let [high, low] = device_type.to_be_bytes();
+                let [_, id0, id1, id2] = device_id.to_be_bytes();
+                ([master | high & 0x3f, low, id0, id1, id2], 5)
+            }
+        }
+    }
+}
+
+/// Response of a field device to a command
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct HartResponse {
+    /// The command answered
+    pub command: u8,
+    /// Response code, 0 for success, the others depend on the command
+    pub response_code: u8,
+    /// Field device status, e.g. `0x10` if more status is available with command 48
+    pub device_status: u8,
+    data: [u8; MAX_RESPONSE_DATA],
+    len: usize,
+}
+
+impl HartResponse {
+    /// Returns the data after the response code and device status
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+impl fmt::Debug for HartResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HartResponse")
+            .field("command", &self.command)
+            .field("response_code", &self.response_code)
+            .field("device_status", &self.device_status)
+            .field("data", &self.data())
+            .finish()
+    }
+}
+
+/// Settings of a [`Hart`] master
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Config {
+    /// Preamble bytes sent before each request, 5 to 20
+    pub preambles: u8,
+    /// Whether to address devices as the primary master, a control system, rather than the
+    /// secondary one, a field communicator
+    pub primary_master: bool,
+    /// How often a request is sent again after a timeout or a broken response
+    pub retries: u8,
+    /// Milliseconds to wait for the start of a response
+    pub timeout_ms: u32,
+}
+
+impl Config {
+    /// Sets the number of preamble bytes, devices may ask for more than 5 in their identity
+    pub fn preambles(mut self, preambles: u8) -> Self {
+        self.preambles = preambles.max(5).min(20);
+
+        self
+    }
+
+    /// Addresses devices as the primary master
+    pub fn primary_master(mut self) -> Self {
+        self.primary_master = true;
+
+        self
+    }
+
+    /// Sets how often a request is sent again
+    pub fn retries(mut self, retries: u8) -> Self {
+        self.retries = retries;
+
+        self
+    }
+
+    /// Sets the milliseconds to wait for the start of a response
+    pub fn timeout_ms(mut self, timeout_ms: u32) -> Self {
+        self.timeout_ms = timeout_ms;
+
+        self
+    }
+}
+
+impl Default for Config {
+    /// 5 preamble bytes, secondary master, 2 retries and a timeout of 380 ms, the time a
+    /// secondary master leaves a device to answer
+    fn default() -> Self {
+        Config {
+            preambles: 5,
+            primary_master: false,
+            retries: 2,
+            timeout_ms: 380,
+        }
+    }
+}
+
+/// A frame as received, without the preamble
+struct Frame {
+    delimiter: u8,
+    address: [u8; 5],
+    command: u8,
+    data: [u8; 255],
+    len: usize,
+}
+
+/// HART master on a serial port and the RTS pin of a modem, see the module documentation
+pub struct Hart<UART, RTS> {
+    serial: UART,
+    rts: RTS,
+    config: Config,
+    core_frequency: u32,
+}
+
+impl<UART, RTS> Hart<UART, RTS>
+where
+    UART: Read<u8> + Write<u8>,
+    RTS: OutputPin<Error = Infallible>,
+{
+    /// Wraps `serial` and the modem's `rts`, which is driven low while sending; the timeouts
+    /// are measured with `mcycle` at the system clock of `clocks`
+    pub fn new(serial: UART, mut rts: RTS, config: Config, clocks: Clocks) -> Self {
+        rts.set_high().ok();
+
+        Hart {
+            serial,
+            rts,
+            config,
+            core_frequency: clocks.sysclk().0,
+        }
+    }
+
+    /// Sends command `cmd` with `data` to the device at `addr` and waits for its response
+    ///
+    /// Frames which don't answer the request are skipped. After a timeout, a broken response
+    /// or a communication error, the request is sent again up to [`Config::retries`] times;
+    /// if all fail, the error of the last response received is returned, or
+    /// [`HartError::Timeout`] if there was none.
+    ///
+    /// A [`HartError::Serial`] is returned right away: the port may need to recover first,
+    /// e.g. with [`Serial::recover_from_error`](crate::serial::Serial::recover_from_error)
+    /// through [`serial`](Self::serial).
+    pub fn send_command(
+        &mut self,
+        addr: HartAddr,
+        cmd: u8,
+        data: &[u8],
+    ) -> Result<HartResponse, HartError> {
+        if matches!(addr, HartAddr::Short(polling) if polling > 63) || data.len() > 255 {
+            return Err(HartError::InvalidRequest);
+        }
+        let (address, address_len) = addr.encode(self.config.primary_master);
+        let address = &address[..address_len];
+
+        let mut error = HartError::Timeout;
+        for _ in 0..=self.config.retries {
+            self.send_request(address, cmd, data)?;
+            match self.receive_response(address, cmd) {
+                Ok(response) => return Ok(response),
+                Err(HartError::Timeout) => {}
+                Err(e @ HartError::Serial(_)) => return Err(e),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    /// Returns the settings
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Returns the serial port, e.g. to recover from an error
+    pub fn serial(&mut self) -> &mut UART {
+        &mut self.serial
+    }
+
+    /// Releases the serial port and the RTS pin
+    pub fn free(self) -> (UART, RTS) {
+        (self.serial, self.rts)
+    }
+
+    /// Drops whatever was received, then sends a request with the modem switched to transmit
+    fn send_request(&mut self, address: &[u8], cmd: u8, data: &[u8]) -> Result<(), HartError> {
+        while self.serial.read().is_ok() {}
+
+        let delimiter = FRAME_REQUEST
+            | if address.len() == 5 {
+                DELIMITER_LONG_ADDRESS
+            } else {
+                0
+            };
+        let header = [delimiter, cmd, data.len() as u8];
+        let check = address
+            .iter()
+            .chain(&header)
+            .chain(data)
+            .fold(0, |check, byte| check ^ byte);
+
+        self.rts.set_low().ok();
+        let preamble = core::iter::repeat(&0xff).take(self.config.preambles as usize);
+        let result = preamble
+            .chain(&header[..1])
+            .chain(address)
+            .chain(&header[1..])
+            .chain(data)
+            .chain(&[check])
+            .try_for_each(|&byte| nb::block!(self.serial.write(byte)))
+            .and_then(|()| nb::block!(self.serial.flush()));
+        self.rts.set_high().ok();
+
+        result.map_err(|e| HartError::Serial(e.kind()))
+    }
+
+    /// Reads frames until one answers the request
+    fn receive_response(&mut self, address: &[u8], cmd: u8) -> Result<HartResponse, HartError> {
+        loop {
+            let deadline = self.deadline(self.config.timeout_ms);
+            let frame = self.read_frame(deadline)?;
+
+            // A device in burst mode sets the burst bit in its responses as well
+            let answers = frame.delimiter & DELIMITER_FRAME_TYPE == FRAME_RESPONSE
+                && frame.command == cmd
+                && frame.address[0] & !ADDRESS_BURST == address[0]
+                && frame.address[1..address.len()] == address[1..];
+            if !answers {
+                continue;
+            }
+
+            if frame.len < 2 {
+                return Err(HartError::InvalidResponse);
+            }
+            if frame.data[0] & COMMUNICATION_ERROR != 0 {
+                return Err(HartError::Communication(frame.data[0]));
+            }
+            let mut response = HartResponse {
+                command: cmd,
+                response_code: frame.data[0],
+                device_status: frame.data[1],
+                data: [0; MAX_RESPONSE_DATA],
+                len: frame.len - 2,
+            };
+            response.data[..frame.len - 2].copy_from_slice(&frame.data[2..frame.len]);
+            return Ok(response);
+        }
+    }
+
+    /// Waits up to `deadline` for a frame to start and reads it
+    ///
+    /// A gap of more than [`CHARACTER_GAP_MS`] within the frame ends it with a timeout.
+    fn read_frame(&mut self, deadline: u64) -> Result<Frame, HartError> {
+        let mut preambles = 0;
+        let delimiter = loop {
+            match self.read_byte(deadline)? {
+                0xff => preambles += 1,
+                byte if preambles >= MIN_PREAMBLES && is_delimiter(byte) => break byte,
+                _ => preambles = 0,
+            }
+        };
+
+        let mut frame = Frame {
+            delimiter,
+            address: [0; 5],
+            command: 0,
+            data: [0; 255],
+            len: 0,
+        };
+        let mut check = delimiter;
+        let address_len = if delimiter & DELIMITER_LONG_ADDRESS != 0 {
+            5
+        } else {
+            1
+        };
+        for i in 0..address_len {
+            frame.address[i] = self.read_frame_byte(&mut check)?;
+        }
+        for _ in 0..delimiter >> DELIMITER_EXPANSION_POS & 0b11 {
+            self.read_frame_byte(&mut check)?;
+        }
+        frame.command = self.read_frame_byte(&mut check)?;
+        frame.len = self.read_frame_byte(&mut check)? as usize;
+        for i in 0..frame.len {
+            frame.data[i] = self.read_frame_byte(&mut check)?;
+        }
+
+        if self.read_frame_byte(&mut check)? != 0 {
+            return Err(HartError::Checksum);
+        }
+        Ok(frame)
+    }
+
+    /// Reads the next byte of a frame and adds it to the check byte
+    fn read_frame_byte(&mut self, check: &mut u8) -> Result<u8, HartError> {
+        let byte = self.read_byte(self.deadline(CHARACTER_GAP_MS))?;
+        *check ^= byte;
+        Ok(byte)
+    }
+
+    fn read_byte(&mut self, deadline: u64) -> Result<u8, HartError> {
+        loop {
+            match self.serial.read() {
+                Ok(byte) => return Ok(byte),
+                Err(nb::Error::WouldBlock) => {
+                    if riscv::register::mcycle::read64() >= deadline {
+                        return Err(HartError::Timeout);
+                    }
+                }
+                Err(nb::Error::Other(e)) => return Err(HartError::Serial(e.kind())),
+            }
+        }
+    }
+
+    fn deadline(&self, timeout_ms: u32) -> u64 {
+        let cycles_per_ms = u64::from(self.core_frequency / 1000);
+        riscv::register::mcycle::read64() + u64::from(timeout_ms) * cycles_per_ms
+    }
+}
+
+impl<UART, RTS> fmt::Debug for Hart<UART, RTS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hart")
+            .field("config", &self.config)
+            .field("core_frequency", &self.core_frequency)
+            .finish()
+    }
+}
+
+/// Returns whether `byte` is the delimiter of a request, response or burst frame
+fn is_delimiter(byte: u8) -> bool {
+    matches!(
+        byte & DELIMITER_FRAME_TYPE,
+        FRAME_BURST | FRAME_REQUEST | FRAME_RESPONSE
+    ) && byte & 0b0001_1000 == 0
+}
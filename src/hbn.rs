@@ -0,0 +1,1117 @@
+/*!
+  # Hibernate
+  Hibernate (HBN) is the deepest low power mode of the BL602. The CPU, the core SRAM and all
+  peripherals outside of the always-on section are powered off, so leaving it is a reset: the
+  chip boots again through the boot ROM and `main` starts from the top. Use [`wakeup_cause`]
+  early in `main` to tell a wake up from a power-on.
+
+  The deeper levels power off more of the HBN section itself:
+
+  | Level                | RTC | 4 KiB HBN RAM | Wake sources        |
+  |----------------------|-----|---------------|---------------------|
+  | [`HbnLevel::Level0`] | on  | retained      | RTC, GPIO7/8, ACOMP |
+  | [`HbnLevel::Level1`] | on  | lost          | RTC, GPIO7/8, ACOMP |
+  | [`HbnLevel::Level2`] | off | retained      | GPIO7/8, ACOMP      |
+  | [`HbnLevel::Level3`] | off | lost          | GPIO7/8, ACOMP      |
+
+  Only GPIO7 and GPIO8 are wired to the always-on section and can wake the chip. The other pins
+  lose their configuration while hibernating, only the two always-on pads can be held at a level
+  with `hold_during_hibernate` or left floating with `isolate_during_hibernate`. The analog
+  comparators have no driver in the HAL yet, they have to be configured by the application
+  before enabling [`WakeSources::acomp`].
+
+  ## Always-on pads
+  The GLB pull and input settings of GPIO7 and GPIO8 are lost while hibernating as well, the
+  always-on section has its own. A wake button without a pull floats and wakes the chip at
+  random. [`aon_pad_config`] sets pull, input buffer and wake trigger of a pad, which
+  [`Hbn::enter`] applies together with the other wake sources. Both pads share one pull
+  enable, one input enable and one trigger, whose direction also selects the pulls: up for the
+  falling edge and low level, down otherwise. Combinations the pads can't have at the same time
+  are rejected with [`Error::PadConflict`] when entering.
+  ```rust
+    use bl602_hal::hbn::{self, AonPad, Pull, WakeTrigger};
+
+    // Button to ground on GPIO7
+    hbn::aon_pad_config(AonPad::Gpio7, Pull::Up, true, Some(WakeTrigger::FallingEdge)).unwrap();
+  ```
+
+  ## Example
+  ```rust
+    use bl602_hal::hbn::{self, Hbn, HbnLevel, WakeCause, WakePin, WakeSources, WakeTrigger};
+    use core::time::Duration;
+
+    match hbn::wakeup_cause() {
+        WakeCause::PowerOn => { /* first boot */ }
+        WakeCause::Rtc => { /* take a measurement */ }
+        WakeCause::Gpio(WakePin::Gpio7) => { /* button pressed */ }
+        _ => {}
+    }
+
+    let mut hbn = Hbn::new(dp.HBN);
+    let error = hbn.enter(
+        HbnLevel::Level1,
+        WakeSources {
+            rtc_after: Some(Duration::from_secs(10 * 60)),
+            gpio: &[WakePin::Gpio7],
+            gpio_trigger: WakeTrigger::FallingEdge,
+            acomp: false,
+        },
+    );
+    // Only reached if the wake sources were rejected
+  ```
+
+  # Brown-out detector
+  [`Bod`] watches the supply voltage and either raises the `HbnOut1` interrupt or resets the chip
+  when it drops below the threshold. A reset by the detector itself is a power-on reset and
+  can't be told apart from one afterwards. In interrupt mode, the handler can save what it needs
+  and then call [`Bod::reset`], which leaves a marker for [`bod_reset_occurred`]:
+  ```rust
+    use bl602_hal::hbn::{self, Bod, BodMode, BodThreshold};
+
+    if hbn::bod_reset_occurred() { /* settings may be stale */ }
+    let bod = Bod::configure(BodThreshold::V2_4, BodMode::Interrupt);
+    enable_interrupt(Interrupt::HbnOut1);
+
+    #[no_mangle]
+    fn HbnOut1(_: &mut TrapFrame) {
+        if hbn::bod_interrupt_pending() {
+            // Finish or abandon the flash write in progress, no new ones
+            Bod::reset();
+        }
+    }
+  ```
+
+  # Current consumption
+  The HAL puts the SoC into hibernate but can't do anything about the rest of the board. The
+  SPI flash in particular draws several µA in standby, send it into deep power-down before
+  entering hibernate to reach the lowest figures.
+*/
+
+use core::convert::Infallible;
+use core::time::Duration;
+
+use crate::clock::glb_set_system_clk_rc32;
+use crate::gpio::{hibernate_pad, HoldLevel, PadSleep};
+use crate::pac;
+use crate::power::{self, WakeFrom, WakeStats};
+use crate::rtc::read_counter;
+use crate::serial::{Error as SerialError, RxWake};
+use crate::sync::SpinLock;
+use crate::watchdog;
+
+/// RTC counter frequency
+const RTC_FREQ: u64 = 32_768;
+
+/// Written to `HBN_RSV0` before entering hibernate, the same marker the vendor SDK uses
+pub(crate) const HBN_ENTER_FLAG: u32 = 0x4e42_4845;
+
+// Bits of `HBN_IRQ_STAT` and `HBN_IRQ_CLR`
+const IRQ_GPIO7: u32 = 1 << 0;
+const IRQ_GPIO8: u32 = 1 << 1;
+pub(crate) const IRQ_RTC: u32 = 1 << 16;
+const IRQ_ACOMP0: u32 = 1 << 20;
+const IRQ_ACOMP1: u32 = 1 << 22;
+const IRQ_BOD: u32 = 1 << 18;
+
+/// Written to `HBN_RSV0` by [`Bod::reset`], tells a reset after a brown-out from other resets
+pub(crate) const BOD_RESET_FLAG: u32 = 0x424f_4452;
+
+/// Written to `HBN_RSV0` by [`power::software_reset`] ("SWRS")
+pub(crate) const SOFTWARE_RESET_FLAG: u32 = 0x5357_5253;
+
+/// Written to `HBN_RSV0` by [`power::reset_to_bootloader`] ("BOOT")
+pub(crate) const BOOTLOADER_FLAG: u32 = 0x424f_4f54;
+
+/// Settings of GPIO7 and GPIO8 made with [`aon_pad_config`]
+static AON_PAD_CONFIG: SpinLock<[Option<AonPadConfig>; 2]> = SpinLock::new([None; 2]);
+
+/// HBN error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// No wake source is enabled, the chip would only come back through a power cycle
+    NoWakeSource,
+    /// An RTC wake was requested at a level which powers the RTC off
+    RtcPoweredOff,
+    /// The RTC wake time doesn't fit into the 40 bit compare value
+    RtcOutOfRange,
+    /// The always-on pads are held, isolated, configured or used as wake pins in ways which need
+    /// different pulls, input enables or triggers, which both pads share
+    PadConflict,
+    /// A wake pin has its input buffer disabled with [`aon_pad_config`], so it can't trigger
+    WakeInputDisabled,
+}
+
+/// How much of the HBN section stays powered, see the module documentation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HbnLevel {
+    Level0,
+    Level1,
+    Level2,
+    Level3,
+}
+
+impl HbnLevel {
+    /// Returns `(pwrdn_hbn_core, pwrdn_hbn_rtc)`
+    fn power_down_bits(self) -> (bool, bool) {
+        match self {
+            HbnLevel::Level0 => (false, false),
+            HbnLevel::Level1 => (true, false),
+            HbnLevel::Level2 => (false, true),
+            HbnLevel::Level3 => (true, true),
+        }
+    }
+}
+
+/// Pins which can wake the chip from hibernate
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WakePin {
+    Gpio7,
+    Gpio8,
+}
+
+/// The always-on pads, which are also the wake pins
+pub type AonPad = WakePin;
+
+/// Pull resistor of an always-on pad while hibernating
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Pull {
+    Up,
+    Down,
+    Floating,
+}
+
+#[derive(Copy, Clone)]
+struct AonPadConfig {
+    pull: Pull,
+    input_enable: bool,
+    wake_trigger: Option<WakeTrigger>,
+}
+
+/// Sets how `pad` is configured while hibernating, replacing an earlier configuration
+///
+/// With a `wake_trigger` the pad becomes a wake source, in addition to [`WakeSources::gpio`],
+/// which needs its input buffer enabled and a pull in the direction the trigger selects, if
+/// any. Whether both pads can be configured like this together is checked by
+/// [`Hbn::enter`], see the module documentation.
+pub fn aon_pad_config(
+    pad: AonPad,
+    pull: Pull,
+    input_enable: bool,
+    wake_trigger: Option<WakeTrigger>,
+) -> Result<(), Error> {
+    if let Some(trigger) = wake_trigger {
+        if !input_enable {
+            return Err(Error::WakeInputDisabled);
+        }
+        if pull != Pull::Floating && (pull == Pull::Up) != pulls_up(trigger) {
+            return Err(Error::PadConflict);
+        }
+    }
+
+    AON_PAD_CONFIG.lock_irq_disabled()[pad as usize] = Some(AonPadConfig {
+        pull,
+        input_enable,
+        wake_trigger,
+    });
+
+    Ok(())
+}
+
+/// Level or edge of the wake pins which wakes the chip
+///
+/// The wake pins get a pull resistor towards their idle level while hibernating, a pull-up for
+/// the falling edge and low level triggers and a pull-down otherwise.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WakeTrigger {
+    FallingEdge,
+    RisingEdge,
+    LowLevel,
+    HighLevel,
+}
+
+/// Sources which end hibernation
+pub struct WakeSources<'a> {
+    /// Wake up after this much time, measured by the RTC
+    pub rtc_after: Option<Duration>,
+    /// Wake up when any of these pins triggers
+    pub gpio: &'a [WakePin],
+    /// Trigger shared by all wake pins
+    pub gpio_trigger: WakeTrigger,
+    /// Wake up on an edge of either analog comparator
+    pub acomp: bool,
+}
+
+/// Why the chip is running
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WakeCause {
+    /// The chip was not woken from hibernate, e.g. after a power-on or a reset
+    PowerOn,
+    /// The RTC reached the wake time
+    Rtc,
+    /// A wake pin triggered
+    Gpio(WakePin),
+    /// Analog comparator 0 or 1 changed its output
+    Acomp(u8),
+    /// The chip was woken from hibernate, but no known wake source is flagged
+    Unknown,
+}
+
+/// Returns why the chip is running and clears the wake flags.
+///
+/// The flags survive the reset at wake up, so this has to be called before anything else
+/// touches the HBN block. Later calls return [`WakeCause::PowerOn`]. If several sources fired,
+/// the RTC takes precedence over the pins and the pins over the comparators.
+pub fn wakeup_cause() -> WakeCause {
+    let hbn = unsafe { &*pac::HBN::ptr() };
+
+    if hbn.hbn_rsv0.read().bits() != HBN_ENTER_FLAG {
+        return WakeCause::PowerOn;
+    }
+
+    let status = hbn.hbn_irq_stat.read().bits();
+    clear_flags(hbn);
+
+    let cause = decode_wake_cause(status);
+
+    // The compare value which woke the chip is kept by the HBN block through the reset
+    let time_to_resume = if cause == WakeCause::Rtc {
+        let compare = ((hbn.hbn_time_h.read().bits() as u64 & 0xff) << 32)
+            | hbn.hbn_time_l.read().bits() as u64;
+        let ticks = read_counter(hbn).wrapping_sub(compare) & ((1 << 40) - 1);
+        Some(Duration::from_nanos(ticks * 1_000_000_000 / RTC_FREQ))
+    } else {
+        None
+    };
+    power::record_wake(WakeStats {
+        time_to_resume,
+        time_to_pll_lock: None,
+        cause: WakeFrom::Hbn(cause),
+        // Nothing enabled is flagged, e.g. a glitch on a floating wake pin which was gone
+        // again before it could be latched
+        spurious_wakes: power::count_wake(cause == WakeCause::Unknown),
+    });
+
+    cause
+}
+
+/// Returns the wake source flagged in the `HBN_IRQ_STAT` value `status`, with the precedence
+/// documented for [`wakeup_cause`]
+pub(crate) fn decode_wake_cause(status: u32) -> WakeCause {
+    if status & IRQ_RTC != 0 {
+        WakeCause::Rtc
+    } else if status & IRQ_GPIO7 != 0 {
+        WakeCause::Gpio(WakePin::Gpio7)
+    } else if status & IRQ_GPIO8 != 0 {
+        WakeCause::Gpio(WakePin::Gpio8)
+    } else if status & IRQ_ACOMP0 != 0 {
+        WakeCause::Acomp(0)
+    } else if status & IRQ_ACOMP1 != 0 {
+        WakeCause::Acomp(1)
+    } else {
+        WakeCause::Unknown
+    }
+}
+
+/// Clears the wake interrupt flags and the marker in `HBN_RSV0`
+pub(crate) fn clear_flags(hbn: &pac::hbn::RegisterBlock) {
+    hbn.hbn_irq_clr.write(|w| unsafe { w.bits(0xffff_ffff) });
+    hbn.hbn_irq_clr.write(|w| unsafe { w.bits(0) });
+    hbn.hbn_rsv0.write(|w| unsafe { w.bits(0) });
+}
+
+/// Hibernate controller
+pub struct Hbn {
+    hbn: pac::HBN,
+}
+
+impl Hbn {
+    /// Takes ownership of the HBN block
+    ///
+    /// The RTC lives in the same block, so this can't be used together with
+    /// [`Rtc`](crate::rtc::Rtc).
+    pub fn new(hbn: pac::HBN) -> Self {
+        Hbn { hbn }
+    }
+
+    /// Enters hibernate at `level` and only returns if `wake` can't be used at this level.
+    ///
+    /// The system clock is switched to the internal RC oscillator and interrupts are disabled,
+    /// so make sure pending UART output has been flushed before calling this. On wake up the
+    /// chip resets and runs from the start of the program again.
+    pub fn enter(&mut self, level: HbnLevel, wake: WakeSources) -> Result<Infallible, Error> {
+        let rtc_compare = match wake.rtc_after {
+            Some(after) => Some(rtc_compare_after(&self.hbn, after)?),
+            None => None,
+        };
+
+        enter(&self.hbn, level, &wake, rtc_compare)
+    }
+
+    /// Releases the HBN block
+    pub fn free(self) -> pac::HBN {
+        self.hbn
+    }
+}
+
+/// Enters hibernate, waking when the RTC counter reaches `rtc_compare` or from the other sources
+/// in `wake`, whose `rtc_after` is ignored
+pub(crate) fn enter(
+    hbn: &pac::hbn::RegisterBlock,
+    level: HbnLevel,
+    wake: &WakeSources,
+    rtc_compare: Option<u64>,
+) -> Result<Infallible, Error> {
+    let (pwrdn_core, pwrdn_rtc) = level.power_down_bits();
+    let pads = aon_pads(wake)?;
+
+    if rtc_compare.is_none() && pads.wake_pins().is_empty() && !wake.acomp {
+        return Err(Error::NoWakeSource);
+    }
+    if rtc_compare.is_some() && pwrdn_rtc {
+        return Err(Error::RtcPoweredOff);
+    }
+
+    // Start from a clean state, so only sources which fire while hibernating are reported
+    clear_flags(hbn);
+
+    match rtc_compare {
+        Some(compare) => set_rtc_compare(hbn, compare),
+        // rtc_ctl[3:1] enables the three compare channels
+        None => hbn
+            .hbn_ctl
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(0b111 << 1)) }),
+    }
+
+    // Without wake pins the trigger only selects the direction of the pulls
+    configure_pin_wake(hbn, pads.wake_pins(), pads.trigger);
+    hbn.hbn_irq_mode.modify(|r, w| unsafe {
+        let mut value = r.bits();
+        if !pads.input {
+            // reg_aon_pad_ie_smt
+            value &= !(1 << 8);
+        }
+        if !pads.pulls {
+            // reg_en_hw_pu_pd
+            value &= !(1 << 16);
+        }
+        w.bits(value)
+    });
+
+    let acomp = if wake.acomp { 0b11 } else { 0b00 };
+    hbn.hbn_irq_mode.modify(|r, w| unsafe {
+        // irq_acomp0_en and irq_acomp1_en, both edges
+        w.bits((r.bits() & !(0b1111 << 20)) | (acomp << 20) | (acomp << 22))
+    });
+
+    unsafe { riscv::interrupt::disable() };
+    glb_set_system_clk_rc32();
+
+    hbn.hbn_rsv0.write(|w| unsafe { w.bits(HBN_ENTER_FLAG) });
+
+    hbn.hbn_ctl.modify(|r, w| unsafe {
+        let mut value = r.bits();
+        // pwrdn_hbn_core
+        value = (value & !(1 << 9)) | ((pwrdn_core as u32) << 9);
+        // pwrdn_hbn_rtc
+        value = (value & !(1 << 11)) | ((pwrdn_rtc as u32) << 11);
+        // pwr_on_option: go through the power-on reset twice on wake up, as the SDK does
+        value &= !(1 << 25);
+        w.bits(value)
+    });
+    // hbn_mode
+    hbn.hbn_ctl
+        .modify(|r, w| unsafe { w.bits(r.bits() | (1 << 7)) });
+
+    // Power goes away within a few cycles
+    loop {
+        unsafe { riscv::asm::wfi() };
+    }
+}
+
+/// Unmasks the wake interrupt of `pins`, masks the other wake pin
+pub(crate) fn configure_pin_wake(
+    hbn: &pac::hbn::RegisterBlock,
+    pins: &[WakePin],
+    trigger: WakeTrigger,
+) {
+    let trigger = match trigger {
+        WakeTrigger::FallingEdge => 0,
+        WakeTrigger::RisingEdge => 1,
+        WakeTrigger::LowLevel => 2,
+        WakeTrigger::HighLevel => 3,
+    };
+    // A set bit masks the pin
+    let mut pin_mask = 0b11;
+    for pin in pins {
+        pin_mask &= match pin {
+            WakePin::Gpio7 => !0b01,
+            WakePin::Gpio8 => !0b10,
+        };
+    }
+
+    hbn.hbn_irq_mode.modify(|r, w| unsafe {
+        let mut value = r.bits();
+        // hbn_pin_wakeup_mode
+        value = (value & !0b111) | trigger;
+        // hbn_pin_wakeup_mask
+        value = (value & !(0b11 << 3)) | (pin_mask << 3);
+        // reg_aon_pad_ie_smt, input buffers of the wake pins
+        value |= 1 << 8;
+        // reg_en_hw_pu_pd, pulls follow the trigger
+        value |= 1 << 16;
+        w.bits(value)
+    });
+}
+
+/// How the always-on pads are configured while hibernating
+struct AonPads {
+    /// Selects the wake mode and the direction of the pulls
+    trigger: WakeTrigger,
+    /// Unmasked pads, GPIO7 and GPIO8
+    wake: [bool; 2],
+    pulls: bool,
+    input: bool,
+}
+
+impl AonPads {
+    fn wake_pins(&self) -> &'static [WakePin] {
+        wake_pin_slice(self.wake[0], self.wake[1])
+    }
+}
+
+/// What one always-on pad needs while hibernating, `None` where it doesn't care
+#[derive(Copy, Clone)]
+struct PadNeeds {
+    pull: Option<Pull>,
+    input: Option<bool>,
+    wake: Option<WakeTrigger>,
+}
+
+/// Combines the wake pins with the pads held or isolated in [`gpio`](crate::gpio) and the
+/// settings of [`aon_pad_config`]
+fn aon_pads(wake: &WakeSources) -> Result<AonPads, Error> {
+    let config = *AON_PAD_CONFIG.lock_irq_disabled();
+    let mut needs = [PadNeeds {
+        pull: None,
+        input: None,
+        wake: None,
+    }; 2];
+
+    let pads = [(7, WakePin::Gpio7), (8, WakePin::Gpio8)];
+    for (i, (pad, &(n, pin))) in needs.iter_mut().zip(pads.iter()).enumerate() {
+        let wakes = wake.gpio.contains(&pin);
+
+        match (hibernate_pad(n), config[i]) {
+            (None, None) => {}
+            (None, Some(config)) => {
+                pad.pull = Some(config.pull);
+                pad.input = Some(config.input_enable);
+                pad.wake = config.wake_trigger;
+            }
+            (Some(_), _) if wakes => return Err(Error::PadConflict),
+            (Some(_), Some(_)) => return Err(Error::PadConflict),
+            (Some(PadSleep::Hold(HoldLevel::High)), None) => pad.pull = Some(Pull::Up),
+            (Some(PadSleep::Hold(HoldLevel::Low)), None) => pad.pull = Some(Pull::Down),
+            (Some(PadSleep::Isolate), None) => {
+                pad.pull = Some(Pull::Floating);
+                pad.input = Some(false);
+            }
+        }
+
+        if wakes {
+            match pad.wake {
+                Some(trigger) if trigger != wake.gpio_trigger => return Err(Error::PadConflict),
+                _ => pad.wake = Some(wake.gpio_trigger),
+            }
+        }
+        if let Some(trigger) = pad.wake {
+            if pad.input == Some(false) {
+                return Err(Error::WakeInputDisabled);
+            }
+            pad.input = Some(true);
+            if pad.pull.is_none() {
+                let pull = if pulls_up(trigger) {
+                    Pull::Up
+                } else {
+                    Pull::Down
+                };
+                pad.pull = Some(pull);
+            }
+        }
+    }
+
+    // Both pads share the settings, so whatever one of them needs has to suit the other
+    fn merge<T: PartialEq>(a: Option<T>, b: Option<T>) -> Result<Option<T>, Error> {
+        match (a, b) {
+            (Some(a), Some(b)) if a != b => Err(Error::PadConflict),
+            (Some(a), _) => Ok(Some(a)),
+            (None, b) => Ok(b),
+        }
+    }
+    let pull = merge(needs[0].pull, needs[1].pull)?;
+    let input = merge(needs[0].input, needs[1].input)?;
+    let wake_trigger = merge(needs[0].wake, needs[1].wake)?;
+
+    let trigger = match (wake_trigger, pull) {
+        (Some(trigger), Some(pull))
+            if pull != Pull::Floating && (pull == Pull::Up) != pulls_up(trigger) =>
+        {
+            return Err(Error::PadConflict)
+        }
+        (Some(trigger), _) => trigger,
+        (None, Some(Pull::Up)) => WakeTrigger::FallingEdge,
+        (None, Some(Pull::Down)) => WakeTrigger::RisingEdge,
+        (None, _) => wake.gpio_trigger,
+    };
+
+    Ok(AonPads {
+        trigger,
+        wake: [needs[0].wake.is_some(), needs[1].wake.is_some()],
+        pulls: pull != Some(Pull::Floating),
+        input: input.unwrap_or(true),
+    })
+}
+
+/// Returns the wake pins for which `gpio7` and `gpio8` are set
+fn wake_pin_slice(gpio7: bool, gpio8: bool) -> &'static [WakePin] {
+    match (gpio7, gpio8) {
+        (true, true) => &[WakePin::Gpio7, WakePin::Gpio8],
+        (true, false) => &[WakePin::Gpio7],
+        (false, true) => &[WakePin::Gpio8],
+        (false, false) => &[],
+    }
+}
+
+/// Returns whether the wake pins get a pull-up with `trigger`
+fn pulls_up(trigger: WakeTrigger) -> bool {
+    match trigger {
+        WakeTrigger::FallingEdge | WakeTrigger::LowLevel => true,
+        WakeTrigger::RisingEdge | WakeTrigger::HighLevel => false,
+    }
+}
+
+/// Adds `pin` to a wake pin configuration with a falling edge trigger, as used for waking up on
+/// activity on an idle high line
+///
+/// Returns `false` if other pins are configured with a different trigger.
+pub(crate) fn add_falling_edge_wake(
+    pins: &mut &[WakePin],
+    trigger: &mut WakeTrigger,
+    pin: WakePin,
+) -> bool {
+    if !pins.is_empty() && *trigger != WakeTrigger::FallingEdge {
+        return false;
+    }
+
+    *pins = wake_pin_slice(
+        pin == WakePin::Gpio7 || pins.contains(&WakePin::Gpio7),
+        pin == WakePin::Gpio8 || pins.contains(&WakePin::Gpio8),
+    );
+    *trigger = WakeTrigger::FallingEdge;
+
+    true
+}
+
+unsafe impl RxWake for WakeSources<'_> {
+    fn add_wake_pin(&mut self, pin: WakePin) -> Result<(), SerialError> {
+        if add_falling_edge_wake(&mut self.gpio, &mut self.gpio_trigger, pin) {
+            Ok(())
+        } else {
+            Err(SerialError::WakeConflict)
+        }
+    }
+}
+
+/// Returns whether `pin` is at the level it idles at with `trigger`, i.e. doesn't trigger
+pub(crate) fn pin_at_idle(pin: WakePin, trigger: WakeTrigger) -> bool {
+    let glb = unsafe { &*pac::GLB::ptr() };
+    let n = match pin {
+        WakePin::Gpio7 => 7,
+        WakePin::Gpio8 => 8,
+    };
+
+    // reg_gpio_i
+    let high = glb.gpio_cfgctl30.read().bits() & (1 << n) != 0;
+    high == pulls_up(trigger)
+}
+
+/// Returns which wake pin is flagged in `HBN_IRQ_STAT`, if any
+pub(crate) fn flagged_wake_pin(hbn: &pac::hbn::RegisterBlock) -> Option<WakePin> {
+    let status = hbn.hbn_irq_stat.read().bits();
+
+    if status & IRQ_GPIO7 != 0 {
+        Some(WakePin::Gpio7)
+    } else if status & IRQ_GPIO8 != 0 {
+        Some(WakePin::Gpio8)
+    } else {
+        None
+    }
+}
+
+/// Returns the RTC compare value `after` from now
+fn rtc_compare_after(hbn: &pac::hbn::RegisterBlock, after: Duration) -> Result<u64, Error> {
+    let ticks = after.as_secs() as u128 * RTC_FREQ as u128
+        + after.subsec_nanos() as u128 * RTC_FREQ as u128 / 1_000_000_000;
+    if ticks >= 1 << 40 {
+        return Err(Error::RtcOutOfRange);
+    }
+
+    // rtc_ctl[0] runs the counter, which counts on from wherever it is
+    hbn.hbn_ctl.modify(|r, w| unsafe { w.bits(r.bits() | 1) });
+
+    // Wrapping around the 40 bit counter is fine, the comparison is for equality
+    Ok((read_counter(hbn) + ticks as u64) & ((1 << 40) - 1))
+}
+
+/// Programs RTC compare channel 0 to fire when the counter reaches `compare`
+pub(crate) fn set_rtc_compare(hbn: &pac::hbn::RegisterBlock, compare: u64) {
+    hbn.hbn_time_l.write(|w| unsafe { w.bits(compare as u32) });
+    hbn.hbn_time_h
+        .write(|w| unsafe { w.bits((compare >> 32) as u32) });
+
+    hbn.hbn_ctl.modify(|r, w| unsafe {
+        let value = r.bits();
+        // rtc_ctl[3:1]: compare channel 0 only, on all 40 bits; rtc_dly_option off
+        w.bits((value & !(0b111 << 1) & !(1 << 24)) | (1 << 1))
+    });
+}
+
+/// Supply voltage below which the brown-out detector triggers
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BodThreshold {
+    /// 2.0 V
+    V2_0,
+    /// 2.4 V
+    V2_4,
+}
+
+/// What the brown-out detector does when it triggers
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BodMode {
+    /// Raise the `HbnOut1` interrupt
+    Interrupt,
+    /// Reset the chip through the power-on reset
+    Reset,
+}
+
+/// Brown-out detector
+///
+/// The detector lives in the always-on section, next to the HBN registers used by [`Hbn`] and
+/// the RTC, but has its own configuration register.
+pub struct Bod {
+    _ownership: (),
+}
+
+impl Bod {
+    /// Powers up the detector and arms it at `threshold`
+    ///
+    /// In [`BodMode::Interrupt`], `HbnOut1` still has to be enabled with
+    /// [`enable_interrupt`](crate::interrupts::enable_interrupt). The interrupt flag is level
+    /// sensitive, it's raised again after clearing as long as the voltage stays low.
+    pub fn configure(threshold: BodThreshold, mode: BodMode) -> Self {
+        let hbn = unsafe { &*pac::HBN::ptr() };
+
+        let vth = match threshold {
+            BodThreshold::V2_0 => 0,
+            BodThreshold::V2_4 => 1,
+        };
+        let sel = match mode {
+            BodMode::Interrupt => 0,
+            BodMode::Reset => 1,
+        };
+
+        hbn.hbn_bor_cfg.modify(|r, w| unsafe {
+            // bor_sel, bor_vth, pu_bor
+            w.bits((r.bits() & !0b111) | sel | (vth << 1) | (1 << 2))
+        });
+
+        hbn.hbn_irq_clr.write(|w| unsafe { w.bits(IRQ_BOD) });
+        hbn.hbn_irq_clr.write(|w| unsafe { w.bits(0) });
+        hbn.hbn_irq_mode.modify(|r, w| unsafe {
+            // irq_bor_en
+            let value = r.bits() & !IRQ_BOD;
+            w.bits(match mode {
+                BodMode::Interrupt => value | IRQ_BOD,
+                BodMode::Reset => value,
+            })
+        });
+
+        Bod { _ownership: () }
+    }
+
+    /// Returns whether the supply voltage is below the threshold right now
+    pub fn is_active(&self) -> bool {
+        let hbn = unsafe { &*pac::HBN::ptr() };
+
+        // r_bor_out
+        hbn.hbn_bor_cfg.read().bits() & (1 << 3) != 0
+    }
+
+    /// Powers down the detector
+    pub fn disable(self) {
+        let hbn = unsafe { &*pac::HBN::ptr() };
+
+        hbn.hbn_irq_mode
+            .modify(|r, w| unsafe { w.bits(r.bits() & !IRQ_BOD) });
+        hbn.hbn_bor_cfg
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 2)) });
+    }
+
+    /// Resets the chip, marking the reset for [`bod_reset_occurred`]
+    ///
+    /// Meant to be called from the `HbnOut1` handler. The system clock is switched to the
+    /// internal RC oscillator first, as the boot ROM expects.
+    pub fn reset() -> ! {
+        let hbn = unsafe { &*pac::HBN::ptr() };
+
+        unsafe { riscv::interrupt::disable() };
+        // A flag left over from an earlier reset would override the marker
+        watchdog::clear_reset_flag();
+        hbn.hbn_rsv0.write(|w| unsafe { w.bits(BOD_RESET_FLAG) });
+
+        glb_set_system_clk_rc32();
+        chip_reset()
+    }
+}
+
+/// Returns whether the brown-out detector triggered, and clears the flag
+///
+/// To be called from the `HbnOut1` handler, which is shared with the analog comparators.
+pub fn bod_interrupt_pending() -> bool {
+    let hbn = unsafe { &*pac::HBN::ptr() };
+
+    if hbn.hbn_irq_stat.read().bits() & IRQ_BOD == 0 {
+        return false;
+    }
+
+    hbn.hbn_irq_clr.write(|w| unsafe { w.bits(IRQ_BOD) });
+    hbn.hbn_irq_clr.write(|w| unsafe { w.bits(0) });
+
+    true
+}
+
+/// Returns whether the chip was reset with [`Bod::reset`] and clears the marker
+///
+/// Like [`wakeup_cause`], this has to be called before anything else uses `HBN_RSV0`, i.e.
+/// before entering hibernate.
+pub fn bod_reset_occurred() -> bool {
+    let hbn = unsafe { &*pac::HBN::ptr() };
+
+    if hbn.hbn_rsv0.read().bits() != BOD_RESET_FLAG {
+        return false;
+    }
+
+    hbn.hbn_rsv0.write(|w| unsafe { w.bits(0) });
+
+    true
+}
+
+/// Resets the CPU, the peripherals and the power-on state machine
+pub(crate) fn chip_reset() -> ! {
+    let glb = unsafe { &*pac::GLB::ptr() };
+
+    // reg_ctrl_pwron_rst, reg_ctrl_cpu_reset, reg_ctrl_sys_reset
+    glb.swrst_cfg2
+        .modify(|r, w| unsafe { w.bits(r.bits() & !0b111) });
+    glb.swrst_cfg2
+        .modify(|r, w| unsafe { w.bits(r.bits() | 0b111) });
+
+    loop {
+        unsafe { riscv::asm::wfi() };
+    }
+}
+
+/**
+  State which survives hibernate and resets
+
+  Two kinds of storage keep their contents through anything but a power cycle:
+
+  - The HBN scratch registers `HBN_RSV1` and `HBN_RSV3`, one word each, accessed with
+    [`register`](retention::register) and [`set_register`](retention::set_register). `HBN_RSV0`
+    and `HBN_RSV2` are used by the HAL itself.
+  - The 4 KiB HBN RAM at `0x4001_0000`, which is powered down at [`HbnLevel::Level1`] and
+    [`HbnLevel::Level3`] though. Its last 256 bytes are used by [`read`](retention::read) and
+    [`write`](retention::write); the rest is available to statics declared with
+    [`retained!`](crate::retained).
+
+  Values in HBN RAM are stored behind a header with a magic number, their size and a CRC-32, so
+  an uninitialized area after power-on, or data written by a different firmware version with a
+  differently sized type, is reported as [`Error::Invalid`](retention::Error::Invalid) rather
+  than returned.
+
+  `retained!` statics are placed in the `.hbn_ram` section, which the linker only knows about
+  with an `HBN_RAM` region in `memory.x` and `-Thbn_ram.x` in the linker arguments, as set up
+  in this repository:
+  ```text
+    HBN_RAM (wxa) : ORIGIN = 0x40010000, LENGTH = 4K - 256
+  ```
+  Without `-Thbn_ram.x` the link fails with `_shbn_ram` and `_ehbn_ram` undefined, rather than
+  the statics ending up in ordinary RAM and losing their values in hibernate.
+
+  ## Example
+  ```rust
+    use bl602_hal::hbn::retention;
+
+    let boots = retention::read::<u32>().unwrap_or(0) + 1;
+    retention::write(&boots).unwrap();
+
+    bl602_hal::retained! {
+        static SCHEDULE: retention::Retained<[u32; 8]>;
+    }
+    let schedule = SCHEDULE.read().unwrap_or([0; 8]);
+  ```
+*/
+pub mod retention {
+    use core::cell::UnsafeCell;
+    use core::mem::{size_of, MaybeUninit};
+
+    use crate::crc::{Algorithm, Crc};
+    use crate::pac;
+
+    /// Start of the area used by [`read`] and [`write`], at the end of HBN RAM
+    const AREA_ADDRESS: usize = 0x4001_0f00;
+    /// Size of the area used by [`read`] and [`write`], including the header
+    const AREA_SIZE: usize = 256;
+    /// Largest value [`write`] can store
+    pub const CAPACITY: usize = AREA_SIZE - size_of::<Header>();
+
+    /// Marks a valid header ("RETN")
+    const MAGIC: u32 = 0x5245_544e;
+
+    /// Retention error
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    #[non_exhaustive]
+    pub enum Error {
+        /// No value of this type has been stored, or it was corrupted
+        Invalid,
+        /// The type is larger than the available space
+        TooLarge,
+    }
+
+    /// HBN scratch registers available to applications
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    pub enum Register {
+        Rsv1,
+        Rsv3,
+    }
+
+    /// Returns the value of the scratch register `reg`
+    ///
+    /// The registers are not initialized by a power-on, so their contents are only meaningful
+    /// if they were written with something recognisable.
+    pub fn register(reg: Register) -> u32 {
+        let hbn = unsafe { &*pac::HBN::ptr() };
+
+        match reg {
+            Register::Rsv1 => hbn.hbn_rsv1.read().bits(),
+            Register::Rsv3 => hbn.hbn_rsv3.read().bits(),
+        }
+    }
+
+    /// Sets the scratch register `reg` to `value`
+    pub fn set_register(reg: Register, value: u32) {
+        let hbn = unsafe { &*pac::HBN::ptr() };
+
+        match reg {
+            Register::Rsv1 => hbn.hbn_rsv1.write(|w| unsafe { w.bits(value) }),
+            Register::Rsv3 => hbn.hbn_rsv3.write(|w| unsafe { w.bits(value) }),
+        }
+    }
+
+    /// Types which are valid for any bit pattern and contain no pointers, so they can be
+    /// restored from raw memory
+    ///
+    /// # Safety
+    ///
+    /// Implementors must be `#[repr(C)]` or `#[repr(transparent)]` with only `Pod` fields and no
+    /// padding.
+    pub unsafe trait Pod: Copy {}
+
+    macro_rules! pod_integers {
+        ($($ty:ty),+) => {
+            $(
+                unsafe impl Pod for $ty {}
+            )+
+        };
+    }
+
+    pod_integers!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+    macro_rules! pod_arrays {
+        ($($len:expr),+) => {
+            $(
+                unsafe impl<T: Pod> Pod for [T; $len] {}
+            )+
+        };
+    }
+
+    pod_arrays!(1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 24, 32, 48, 64);
+
+    /// Header in front of every retained value
+    #[repr(C)]
+    #[derive(Copy, Clone)]
+    struct Header {
+        magic: u32,
+        len: u32,
+        crc: u32,
+        _reserved: u32,
+    }
+
+    /// Reads the value of type `T` stored with [`write`]
+    pub fn read<T: Pod>() -> Result<T, Error> {
+        if size_of::<T>() > CAPACITY {
+            return Err(Error::TooLarge);
+        }
+
+        riscv::interrupt::free(|_| unsafe { read_raw(AREA_ADDRESS as *const u8) })
+    }
+
+    /// Stores `value` in the retention area, replacing whatever was there
+    pub fn write<T: Pod>(value: &T) -> Result<(), Error> {
+        if size_of::<T>() > CAPACITY {
+            return Err(Error::TooLarge);
+        }
+
+        riscv::interrupt::free(|_| unsafe { write_raw(AREA_ADDRESS as *mut u8, value) });
+
+        Ok(())
+    }
+
+    /// Invalidates the value in the retention area
+    pub fn clear() {
+        riscv::interrupt::free(|_| unsafe { invalidate_raw(AREA_ADDRESS as *mut u8) });
+    }
+
+    /// A value of type `T` in HBN RAM, declared with [`retained!`](crate::retained)
+    #[repr(C)]
+    pub struct Retained<T> {
+        header: UnsafeCell<MaybeUninit<Header>>,
+        value: UnsafeCell<MaybeUninit<T>>,
+    }
+
+    // All accesses happen in critical sections
+    unsafe impl<T: Send> Sync for Retained<T> {}
+
+    impl<T> Retained<T> {
+        #[doc(hidden)]
+        pub const fn new() -> Self {
+            Retained {
+                header: UnsafeCell::new(MaybeUninit::uninit()),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            }
+        }
+    }
+
+    impl<T: Pod> Retained<T> {
+        /// Returns the stored value, if there is a valid one
+        pub fn read(&self) -> Result<T, Error> {
+            let base = self.base();
+            riscv::interrupt::free(|_| unsafe { read_raw(base) })
+        }
+
+        /// Stores `value`
+        pub fn write(&self, value: &T) {
+            let base = self.base();
+            riscv::interrupt::free(|_| unsafe { write_raw(base, value) })
+        }
+
+        /// Invalidates the stored value
+        pub fn clear(&self) {
+            let base = self.base();
+            riscv::interrupt::free(|_| unsafe { invalidate_raw(base) })
+        }
+
+        /// Returns the address of the header, after checking that it's in the `.hbn_ram` section
+        ///
+        /// The bounds of the section are only defined by `hbn_ram.x`, so without it the link
+        /// fails here instead of leaving the section to the linker, which puts it into ordinary
+        /// RAM.
+        fn base(&self) -> *mut u8 {
+            extern "C" {
+                static _shbn_ram: u8;
+                static _ehbn_ram: u8;
+            }
+
+            let base = self.header.get() as *mut u8;
+            let (start, end) = unsafe { (&_shbn_ram as *const u8, &_ehbn_ram as *const u8) };
+            assert!(
+                start <= base as *const u8 && (base as *const u8) < end,
+                "Retained statics must be declared with retained!"
+            );
+
+            base
+        }
+    }
+
+    /// Reads a header and a value of type `T` from `base`, checking the header
+    ///
+    /// # Safety
+    ///
+    /// `base` must be valid for reads of the header followed by a `T`.
+    unsafe fn read_raw<T: Pod>(base: *const u8) -> Result<T, Error> {
+        let header = (base as *const Header).read_volatile();
+        let data = base.add(size_of::<Header>());
+
+        if header.magic != MAGIC || header.len as usize != size_of::<T>() {
+            return Err(Error::Invalid);
+        }
+
+        let value = (data as *const T).read_unaligned();
+        if header.crc != checksum(&value) {
+            return Err(Error::Invalid);
+        }
+
+        Ok(value)
+    }
+
+    /// Writes a header and `value` to `base`
+    ///
+    /// # Safety
+    ///
+    /// `base` must be valid for writes of the header followed by a `T`.
+    unsafe fn write_raw<T: Pod>(base: *mut u8, value: &T) {
+        // Invalidate first, so a reset in the middle doesn't leave a half written value behind
+        // which passes as valid
+        invalidate_raw(base);
+
+        (base.add(size_of::<Header>()) as *mut T).write_unaligned(*value);
+        (base as *mut Header).write_volatile(Header {
+            magic: MAGIC,
+            len: size_of::<T>() as u32,
+            crc: checksum(value),
+            _reserved: 0,
+        });
+    }
+
+    /// Destroys the header at `base`
+    ///
+    /// # Safety
+    ///
+    /// `base` must be valid for writes of a header.
+    unsafe fn invalidate_raw(base: *mut u8) {
+        (base as *mut u32).write_volatile(0);
+    }
+
+    /// Returns the CRC-32 of the bytes of `value`
+    fn checksum<T: Pod>(value: &T) -> u32 {
+        let bytes =
+            unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        Crc::checksum(Algorithm::Crc32, bytes)
+    }
+}
+
+/// Declares statics which keep their value through hibernate and resets
+///
+/// The statics are of type [`Retained<T>`](crate::hbn::retention::Retained) and live in HBN RAM,
+/// see [`hbn::retention`](crate::hbn::retention) for the linker setup this needs.
+#[macro_export]
+macro_rules! retained {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty;)+) => {
+        $(
+            $(#[$attr])*
+            #[link_section = ".hbn_ram"]
+            $vis static $name: $ty = <$ty>::new();
+        )+
+    };
+}
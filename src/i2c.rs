@@ -15,6 +15,15 @@
         clocks,
     );
     ```
+
+  ## Single byte writes
+  Writing one byte, e.g. a command or a register address, doesn't need the word buffer
+  [`write`](WriteAlpha::write) packs its data into. [`I2c::write_direct`] loads the byte into
+  the FIFO right away and then starts the transfer. The I2C block has no way to bypass its
+  FIFO, so the transfer on the bus takes the same time, only the work before it is saved. The
+  `i2c_write_direct` example measures both paths; at 160 MHz and 100 kHz the bus time of about
+  200 µs dominates either way, the difference matters when many short writes are issued at
+  higher bus speeds.
 */
 
 use bl602_pac::I2C;
@@ -42,6 +51,17 @@ pub enum Error {
     Timeout,
 }
 
+impl i2cAlpha::Error for Error {
+    fn kind(&self) -> i2cAlpha::ErrorKind {
+        match self {
+            Error::RxOverflow | Error::TxOverflow => i2cAlpha::ErrorKind::Overrun,
+            Error::RxUnderflow | Error::TxUnderflow | Error::Timeout => {
+                i2cAlpha::ErrorKind::Other
+            }
+        }
+    }
+}
+
 /// SDA pins - DO NOT IMPLEMENT THIS TRAIT
 pub unsafe trait SdaPin<I2C> {}
 
@@ -173,6 +193,72 @@ where
             .i2c_fifo_config_0
             .write(|w| w.rx_fifo_clr().set_bit().tx_fifo_clr().set_bit());
     }
+
+    /// Writes the single byte `data` to the device at `address`, with less overhead than
+    /// [`write`](WriteAlpha::write)
+    ///
+    /// See the module documentation for what is saved.
+    pub fn write_direct(&mut self, address: u8, data: u8) -> Result<(), Error> {
+        self.check_tx_fifo()?;
+
+        // The byte is the only FIFO word needed, so one free entry is enough
+        let mut timeout_countdown = self.timeout;
+        while self.i2c.i2c_fifo_config_1.read().tx_fifo_cnt().bits() == 0 {
+            if timeout_countdown == 0 {
+                return Err(Error::Timeout);
+            }
+            timeout_countdown -= 1;
+        }
+        self.i2c
+            .i2c_fifo_wdata
+            .write(|w| unsafe { w.i2c_fifo_wdata().bits(data as u32) });
+
+        self.i2c.i2c_config.modify(|_r, w| unsafe {
+            w.cr_i2c_pkt_len()
+                .bits(0)
+                .cr_i2c_slv_addr()
+                .bits(address)
+                .cr_i2c_sub_addr_en()
+                .clear_bit()
+                .cr_i2c_sub_addr_bc()
+                .bits(0)
+                .cr_i2c_scl_sync_en()
+                .set_bit()
+                .cr_i2c_pkt_dir()
+                .clear_bit() // = write
+                .cr_i2c_m_en()
+                .set_bit()
+        });
+
+        while self.i2c.i2c_bus_busy.read().sts_i2c_bus_busy().bit_is_set() {
+            // wait for transfer to finish
+        }
+
+        self.i2c
+            .i2c_config
+            .modify(|_r, w| w.cr_i2c_m_en().clear_bit());
+
+        Ok(())
+    }
+
+    /// Reports and clears an overflow or underflow of the TX FIFO left from an earlier transfer
+    fn check_tx_fifo(&mut self) -> Result<(), Error> {
+        let fifo_config = self.i2c.i2c_fifo_config_0.read();
+
+        if fifo_config.tx_fifo_overflow().bit_is_set() {
+            self.i2c
+                .i2c_fifo_config_0
+                .write(|w| w.tx_fifo_clr().set_bit());
+            return Err(Error::TxOverflow);
+        } else if fifo_config.tx_fifo_underflow().bit_is_set() {
+            self.i2c
+                .i2c_fifo_config_0
+                .write(|w| w.tx_fifo_clr().set_bit());
+            return Err(Error::TxUnderflow);
+        }
+
+        Ok(())
+    }
 }
 
 impl<PINS> ReadAlpha<i2cAlpha::SevenBitAddress> for I2c<pac::I2C, PINS>
@@ -256,19 +342,7 @@ where
         address: i2cAlpha::SevenBitAddress,
         buffer: &[u8],
     ) -> Result<(), Self::Error> {
-        let fifo_config = self.i2c.i2c_fifo_config_0.read();
-
-        if fifo_config.tx_fifo_overflow().bit_is_set() {
-            self.i2c
-                .i2c_fifo_config_0
-                .write(|w| w.tx_fifo_clr().set_bit());
-            return Err(Error::TxOverflow);
-        } else if fifo_config.tx_fifo_underflow().bit_is_set() {
-            self.i2c
-                .i2c_fifo_config_0
-                .write(|w| w.tx_fifo_clr().set_bit());
-            return Err(Error::TxUnderflow);
-        }
+        self.check_tx_fifo()?;
 
         let mut word_buffer = [0u32; 255];
         let count = buffer.len() / 4 + if buffer.len() % 4 > 0 { 1 } else { 0 };
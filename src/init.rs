@@ -0,0 +1,87 @@
+/*!
+  # Initialization sequences
+  Helpers to replay the register write and delay tables found in the C drivers of many sensors
+  and displays, so they can be ported without rewriting them as code. Enabled by the
+  `init-helpers` feature.
+
+  ## Example
+  ```rust
+    use bl602_hal::init::{init_sequence, uart_init_sequence};
+
+    // (register, value, delay in ms after the write)
+    const IMU_STARTUP: &[(u8, u8, u32)] = &[
+        (0x6b, 0x80, 100), // reset
+        (0x6b, 0x01, 10),  // clock source
+        (0x1b, 0x18, 0),   // gyro full scale
+    ];
+    init_sequence(&mut i2c, &mut delay, 0x68, IMU_STARTUP).unwrap();
+
+    // (command, delay in ms after sending it)
+    let commands: &[(&[u8], u32)] = &[(b"AT+RST\r\n", 500), (b"ATE0\r\n", 10)];
+    uart_init_sequence(&mut serial, &mut delay, commands).unwrap();
+  ```
+*/
+
+use embedded_hal::delay::blocking::DelayUs;
+use embedded_hal::i2c::blocking::Write as I2cWrite;
+use embedded_hal::i2c::SevenBitAddress;
+use embedded_hal::serial::nb::Write as SerialWrite;
+
+/// Error of an initialization sequence
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error<B, D> {
+    /// Writing to the bus failed, the sequence was aborted at that step
+    Bus(B),
+    /// The delay provider failed
+    Delay(D),
+}
+
+/// Writes each `(register, value, delay_ms)` of `sequence` to the I2C device at `address`
+///
+/// Every write sends the register address followed by the value. A delay of 0 continues with the
+/// next write right away.
+pub fn init_sequence<I, D>(
+    i2c: &mut I,
+    delay: &mut D,
+    address: SevenBitAddress,
+    sequence: &[(u8, u8, u32)],
+) -> Result<(), Error<I::Error, D::Error>>
+where
+    I: I2cWrite<SevenBitAddress>,
+    D: DelayUs,
+{
+    for &(register, value, delay_ms) in sequence {
+        i2c.write(address, &[register, value]).map_err(Error::Bus)?;
+        if delay_ms > 0 {
+            delay.delay_ms(delay_ms).map_err(Error::Delay)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends each `(command, delay_ms)` of `commands` over `serial`
+///
+/// The delay starts once the command has been transmitted completely, not just queued.
+pub fn uart_init_sequence<S, D>(
+    serial: &mut S,
+    delay: &mut D,
+    commands: &[(&[u8], u32)],
+) -> Result<(), Error<S::Error, D::Error>>
+where
+    S: SerialWrite<u8>,
+    D: DelayUs,
+{
+    for &(command, delay_ms) in commands {
+        for &byte in command {
+            nb::block!(serial.write(byte)).map_err(Error::Bus)?;
+        }
+        nb::block!(serial.flush()).map_err(Error::Bus)?;
+
+        if delay_ms > 0 {
+            delay.delay_ms(delay_ms).map_err(Error::Delay)?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,6 +1,7 @@
 /*!
   # Interrupt Management
-  Interrupts can be enabled, disabled and cleared.
+  Interrupts can be enabled, disabled, pended and cleared in the core local interrupt
+  controller (CLIC), and given a priority there.
 
   ## Example
   ```rust
@@ -16,22 +17,166 @@
   ```
 
   ## The following functions can be implemented as interrupt handlers
+  Every variant of [`Interrupt`] has a handler of the same name, e.g.
   ```rust
     fn Gpio();
     fn TimerCh0();
     fn TimerCh1();
     fn Watchdog();
+    fn HbnOut0();
+    fn HbnOut1();
+    fn Uart0();
+    fn Dma();
+  ```
+
+  Handlers can also be defined with the [`interrupt!`](crate::interrupt) macro, which checks the
+  name against [`Interrupt`], so a typo fails to compile instead of leaving the handler unused:
+  ```rust
+    bl602_hal::interrupt!(Uart0, on_uart0);
+
+    fn on_uart0() {
+        // ..
+    }
+  ```
+
+  The trap handler reads the interrupt number from `mcause` and calls the handler of that
+  number, all others are linked to `DefaultHandler` by `hal_defaults.x`. The CLIC has already
+  picked the interrupt when the trap is taken, so unlike with a PLIC there is nothing to claim
+  or complete: an interrupt which becomes pending while a handler runs is taken right after the
+  handler returns, in priority order. Numbers without a source are counted by
+  [`spurious_count`] and passed on to `DefaultHandler`. The machine timer interrupt is handled
+  by [`mtimer`](crate::mtimer) itself, the software interrupt by the handler set with
+  [`set_software_handler`], exceptions by [`trap`](crate::trap). Once the
+  [`GpioInterruptManager`](crate::gpio::GpioInterruptManager) has been taken, it handles the
+  `Gpio` interrupt in place of a `Gpio` handler.
+
+  Enabling an interrupt in a driver, e.g. with
+  [`enable_match0_interrupt`](crate::timer::ConfiguredTimerChannel0::enable_match0_interrupt),
+  only lets the peripheral raise its interrupt line. The line also has to be enabled here with
+  [`enable`] before the handler runs.
+
+  # Pending interrupts
+  The interrupts of the peripherals are level triggered: the CLIC keeps an interrupt pending
+  while the peripheral's flag is set, so [`unpend`] only has an effect after the flag has been
+  cleared in the peripheral. [`pend`] triggers an interrupt from software, which is taken once it
+  is enabled and interrupts are enabled in `mstatus`.
+
+  # Software interrupt
+  The machine software interrupt has no peripheral behind it, it is pending while the `msip`
+  register of the core is set. [`pend_software`] sets it, and the handler given to
+  [`set_software_handler`] runs when nothing more urgent is pending: at its default priority 0,
+  after the handler which pended it has returned and the other pending interrupts have been
+  handled. That moves slow work out of a handler with a tight deadline, like the software tasks
+  of RTIC; [`set_software_priority`] places it between other interrupts.
+  [`Deferred`](crate::sync::Deferred) queues the data for it:
+
+  ```rust
+    use bl602_hal::{interrupts, sync::Deferred};
+
+    static EVENTS: Deferred<u32> = Deferred::new();
+
+    interrupts::set_software_handler(on_software);
+
+    bl602_hal::interrupt!(Gpio, on_gpio);
+
+    fn on_gpio() {
+        // Record what happened, pushing pends the software interrupt
+        EVENTS.push(read_event()).ok();
+    }
+
+    fn on_software() {
+        while let Some(event) = EVENTS.pop() {
+            // Slow processing
+        }
+    }
+  ```
+
+  # Priorities
+  Each interrupt has a 4 bit [`Priority`], 0 by default. The upper [`level_bits`] bits of it are
+  the interrupt level, the rest orders interrupts of the same level. Of the interrupts which are
+  pending and enabled, the one with the highest priority value is taken first, and of those with
+  the same value the one with the higher interrupt number, see [`runs_before`]. A pending
+  interrupt only preempts a running handler if its level is higher, see [`preempts`]. The
+  configuration after reset has no level bits, so all interrupts have the same level and handlers
+  aren't preempted; the priority only decides the order in which pending interrupts are taken.
+
+  # Nested interrupts
+  Handlers run with interrupts disabled in `mstatus`, so they are never preempted unless they ask
+  for it. A slow handler can let more urgent interrupts in with [`allow_preemption_above`]: while
+  the returned guard is alive, enabled interrupts with a priority above the given one preempt the
+  handler. The CLIC only preempts for a higher interrupt level, so the level bits have to be set
+  up first with [`set_level_bits`], e.g. to 4 so the level is the whole priority.
+
+  ```rust
+    use bl602_hal::interrupts::{self, Interrupt, Priority};
+
+    interrupts::set_level_bits(4);
+    interrupts::set_priority(Interrupt::Uart0, Priority::new(2).unwrap());
+    interrupts::set_priority(Interrupt::TimerCh0, Priority::new(10).unwrap());
+
+    bl602_hal::interrupt!(Uart0, on_uart0);
+
+    fn on_uart0() {
+        // Quick work which has to see a consistent state
+        let _preemptible = interrupts::allow_preemption_above(Priority::new(2).unwrap());
+        // Slow work, TimerCh0 can run in between
+    }
+  ```
+
+  Every preemption puts another trap frame of 128 bytes and the stack of the preempting handler on
+  top of the stack of the handler it interrupts, so the stack has to fit the deepest chain of
+  priorities which can preempt each other. A critical section in a preemptible handler, with
+  [`free`] or `critical_section::with`, disables interrupts again until it ends, the same as
+  outside of handlers. Data shared between the preemptible handler and those which can preempt it
+  has to be locked like data shared with `main`: a [`SpinLock`](crate::sync::SpinLock) has to be
+  taken with `lock_irq_disabled`, as a preempting handler spinning on a plain `lock` never lets
+  the handler holding it continue.
+
+  [`nesting_depth`] returns how many handlers are running, one per preemption, and [`in_isr`]
+  whether the caller runs in a handler at all, e.g. for drivers which can't wait for an interrupt
+  from within a handler.
+
+  # Statistics
+  With the `irq-stats` feature the trap handler reads `mcycle` before and after it calls the
+  handler of a peripheral interrupt, and keeps the number of runs and the shortest, longest and
+  mean duration per interrupt, see [`stats`]. [`pend`] notes the time as well, so for interrupts
+  triggered from software the latency until the handler is called is known too. This costs a
+  few dozen instructions per interrupt, run with interrupts disabled, and 3 KiB of RAM for the
+  table. Without the feature none of it is compiled in.
+
+  ```rust
+    for (interrupt, stats) in interrupts::stats() {
+        writeln!(
+            serial,
+            "{:?}: {} runs, {}..{} cycles, mean {}",
+            interrupt, stats.count, stats.min_cycles, stats.max_cycles, stats.mean_cycles()
+        ).ok();
+    }
+    interrupts::reset_stats();
+  ```
+
+  # Critical sections
+  [`free`] runs a closure with all interrupts disabled in `mstatus`. [`masked`] only disables the
+  given interrupts in the CLIC, so unrelated interrupts with tight deadlines keep being handled
+  while e.g. a buffer shared with the UART handler is updated.
+
+  ```rust
+    use bl602_hal::interrupts::{self, Interrupt};
+
+    interrupts::masked(&[Interrupt::Uart0], || {
+        // The Uart0 handler doesn't run here, TimerCh0 still does
+    });
   ```
 */
 
-use riscv::register::mcause;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 
-extern "C" {
-    fn Gpio(trap_frame: &mut TrapFrame);
-    fn TimerCh0(trap_frame: &mut TrapFrame);
-    fn TimerCh1(trap_frame: &mut TrapFrame);
-    fn Watchdog(trap_frame: &mut TrapFrame);
-}
+use core::marker::PhantomData;
+
+use crate::regs;
+
+use riscv::interrupt::CriticalSection;
+use riscv::register::{mcause, mepc, mstatus};
 
 // see components\bl602\bl602_std\bl602_std\RISCV\Core\Include\clic.h
 // see components\hal_drv\bl602_hal\bl_irq.c
@@ -39,11 +184,158 @@ const IRQ_NUM_BASE: u32 = 16;
 const CLIC_HART0_ADDR: u32 = 0x02800000;
 const CLIC_INTIE: u32 = 0x400;
 const CLIC_INTIP: u32 = 0x000;
+const CLIC_INTCFG: u32 = 0x800;
+const CLIC_CFG: u32 = 0xc00;
+
+/// Number of priority bits implemented in `clicintcfg`, the upper bits of the byte
+const CLIC_INTCTLBITS: u8 = 4;
+/// Number of interrupts in the CLIC, the core local ones and those of the peripherals
+const CLIC_NUM_INTERRUPTS: u32 = IRQ_NUM_BASE + 64;
+
+/// Interrupts taken with a number no source uses
+static SPURIOUS: AtomicU32 = AtomicU32::new(0);
+
+/// Handler of the software interrupt, 0 if there is none
+static SOFTWARE_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of interrupt handlers the trap handler is in, see [`nesting_depth`]
+static NESTING_DEPTH: AtomicU32 = AtomicU32::new(0);
+
+macro_rules! impl_interrupts {
+    ($($(#[$doc:meta])* $name:ident = $irq:literal,)+) => {
+        extern "C" {
+            $( fn $name(trap_frame: &mut TrapFrame); )+
+        }
+
+        /// Available interrupts
+        #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+        pub enum Interrupt {
+            #[doc(hidden)]
+            Unknown,
+            $( $(#[$doc])* $name, )+
+        }
+
+        impl Interrupt {
+            fn to_irq(&self) -> u32 {
+                match &self {
+                    Interrupt::Unknown => panic!("Unknown interrupt has no irq number"),
+                    $( Interrupt::$name => IRQ_NUM_BASE + $irq, )+
+                }
+            }
+
+            fn from(irq: u32) -> Interrupt {
+                match irq.wrapping_sub(IRQ_NUM_BASE) {
+                    $( $irq => Interrupt::$name, )+
+                    _ => Interrupt::Unknown,
+                }
+            }
+        }
 
-const GPIO_IRQ: u32 = IRQ_NUM_BASE + 44;
-const TIMER_CH0_IRQ: u32 = IRQ_NUM_BASE + 36;
-const TIMER_CH1_IRQ: u32 = IRQ_NUM_BASE + 37;
-const WATCHDOG_IRQ: u32 = IRQ_NUM_BASE + 38;
+        /// Calls the handler of `interrupt`, `false` for `Interrupt::Unknown`
+        unsafe fn dispatch(interrupt: Interrupt, trap_frame: &mut TrapFrame) -> bool {
+            match interrupt {
+                Interrupt::Unknown => return false,
+                $( Interrupt::$name => $name(trap_frame), )+
+            }
+            true
+        }
+    };
+}
+
+// see components\bl602\bl602_std\bl602_std\Include\bl602.h, the numbers not listed are reserved
+impl_interrupts! {
+    /// Bus matrix error
+    BmxErr = 0,
+    /// Bus matrix timeout
+    BmxTimeout = 1,
+    /// L1 cache bus error
+    L1cBmxErr = 2,
+    /// L1 cache bus timeout
+    L1cBmxTimeout = 3,
+    /// Security engine bus error
+    SecBmxErr = 4,
+    /// RF interrupt 0
+    RfTop0 = 5,
+    /// RF interrupt 1
+    RfTop1 = 6,
+    /// SDIO Interrupt
+    Sdio = 7,
+    /// DMA bus error
+    DmaBmxErr = 8,
+    /// Security engine GMAC Interrupt
+    SecGmac = 9,
+    /// Security engine CDET Interrupt
+    SecCdet = 10,
+    /// Security engine PKA Interrupt
+    SecPka = 11,
+    /// Security engine TRNG Interrupt
+    SecTrng = 12,
+    /// Security engine AES Interrupt
+    SecAes = 13,
+    /// Security engine SHA Interrupt
+    SecSha = 14,
+    /// DMA Interrupt of all channels
+    Dma = 15,
+    /// IR transmitter Interrupt
+    IrTx = 19,
+    /// IR receiver Interrupt
+    IrRx = 20,
+    /// Flash controller Interrupt
+    SfCtrl = 23,
+    /// ADC DMA Interrupt
+    GpadcDma = 25,
+    /// eFuse Interrupt
+    Efuse = 26,
+    /// SPI Interrupt
+    Spi = 27,
+    /// UART0 Interrupt
+    Uart0 = 29,
+    /// UART1 Interrupt
+    Uart1 = 30,
+    /// I2C Interrupt
+    I2c = 32,
+    /// PWM Interrupt
+    Pwm = 34,
+    /// Timer Channel 0 Interrupt
+    TimerCh0 = 36,
+    /// Timer Channel 1 Interrupt
+    TimerCh1 = 37,
+    /// Watchdog Timer Interrupt
+    /// Used when WDT is configured in Interrupt mode using ConfiguredWatchdog0::set_mode()
+    Watchdog = 38,
+    /// GPIO Interrupt
+    Gpio = 44,
+    /// Wake up from power-down sleep
+    PdsWakeup = 50,
+    /// HBN Interrupt for the RTC and the wake pins
+    /// Used by RTC alarms, see `Rtc::set_alarm`
+    HbnOut0 = 51,
+    /// HBN Interrupt for the brown-out detector and the analog comparators
+    /// Used when the BOD is configured with `BodMode::Interrupt`
+    HbnOut1 = 52,
+    /// Brown-out reset Interrupt
+    Bor = 53,
+    /// Wi-Fi Interrupt
+    Wifi = 54,
+    /// Bluetooth PHY Interrupt
+    BzPhy = 55,
+    /// Bluetooth LE Interrupt
+    Ble = 56,
+    /// Wi-Fi MAC TX/RX timer Interrupt
+    MacTxRxTimer = 57,
+    /// Wi-Fi MAC TX/RX miscellaneous Interrupt
+    MacTxRxMisc = 58,
+    /// Wi-Fi MAC RX trigger Interrupt
+    MacRxTrigger = 59,
+    /// Wi-Fi MAC TX trigger Interrupt
+    MacTxTrigger = 60,
+    /// Wi-Fi MAC general Interrupt
+    MacGeneral = 61,
+    /// Wi-Fi MAC port trigger Interrupt
+    MacPortTrigger = 62,
+    /// Wi-Fi IPC Interrupt
+    WifiIpc = 63,
+}
 
 #[doc(hidden)]
 #[no_mangle]
@@ -126,87 +418,526 @@ pub unsafe extern "C" fn start_trap_rust_hal(trap_frame: *mut TrapFrame) {
 
     let cause = mcause::read();
     if cause.is_exception() {
-        _start_trap_rust(trap_frame);
+        crate::trap::on_exception(&*trap_frame);
     } else {
+        NESTING_DEPTH.fetch_add(1, Ordering::Relaxed);
+
         let code = cause.code();
-        if code < IRQ_NUM_BASE as usize {
+        if code & 0xff == MTIMER_IRQ as usize {
+            crate::mtimer::on_interrupt();
+        } else if code & 0xff == MSIP_IRQ as usize {
+            on_software_interrupt();
+        } else if code < IRQ_NUM_BASE as usize {
             _start_trap_rust(trap_frame);
         } else {
             let interrupt_number = (code & 0xff) as u32;
             let interrupt = Interrupt::from(interrupt_number);
+            #[cfg(feature = "irq-stats")]
+            let entered = riscv::register::mcycle::read() as u32;
 
-            match interrupt {
-                Interrupt::Unknown => _start_trap_rust(trap_frame),
-                Interrupt::Gpio => Gpio(trap_frame.as_mut().unwrap()),
-                Interrupt::TimerCh0 => TimerCh0(trap_frame.as_mut().unwrap()),
-                Interrupt::TimerCh1 => TimerCh1(trap_frame.as_mut().unwrap()),
-                Interrupt::Watchdog => Watchdog(trap_frame.as_mut().unwrap()),
-            };
+            let handled = interrupt == Interrupt::Gpio && crate::gpio::on_interrupt();
+            if !handled && !dispatch(interrupt, trap_frame.as_mut().unwrap()) {
+                SPURIOUS.fetch_add(1, Ordering::Relaxed);
+                _start_trap_rust(trap_frame);
+            }
+            #[cfg(feature = "irq-stats")]
+            stats::record(interrupt, entered);
         }
+
+        NESTING_DEPTH.fetch_sub(1, Ordering::Relaxed);
     }
 }
 
-/// Available interrupts
-pub enum Interrupt {
-    #[doc(hidden)]
-    Unknown,
-    /// GPIO Interrupt
-    Gpio,
-    /// Timer Channel 0 Interrupt
-    TimerCh0,
-    /// Timer Channel 1 Interrupt
-    TimerCh1,
-    /// Watchdog Timer Interrupt
-    /// Used when WDT is configured in Interrupt mode using ConfiguredWatchdog0::set_mode()
-    Watchdog,
+/// Returns how many interrupts were taken with a reserved interrupt number since reset
+pub fn spurious_count() -> u32 {
+    SPURIOUS.load(Ordering::Relaxed)
 }
 
-impl Interrupt {
-    fn to_irq(&self) -> u32 {
-        match &self {
-            Interrupt::Unknown => panic!("Unknown interrupt has no irq number"),
-            Interrupt::Gpio => GPIO_IRQ,
-            Interrupt::TimerCh0 => TIMER_CH0_IRQ,
-            Interrupt::TimerCh1 => TIMER_CH1_IRQ,
-            Interrupt::Watchdog => WATCHDOG_IRQ,
-        }
-    }
+/// Returns how many interrupt handlers are running: 0 outside of handlers, 1 in a handler, 2 in
+/// a handler which preempted another one, and so on
+///
+/// Counted by the trap handler around every interrupt it dispatches, including the machine
+/// timer and software interrupts. Exceptions don't count, the fault handler sees the depth of
+/// the code which faulted.
+pub fn nesting_depth() -> u32 {
+    NESTING_DEPTH.load(Ordering::Relaxed)
+}
 
-    fn from(irq: u32) -> Interrupt {
-        match irq {
-            GPIO_IRQ => Interrupt::Gpio,
-            TIMER_CH0_IRQ => Interrupt::TimerCh0,
-            TIMER_CH1_IRQ => Interrupt::TimerCh1,
-            WATCHDOG_IRQ => Interrupt::Watchdog,
-            _ => Interrupt::Unknown,
+/// Returns whether this runs in an interrupt handler, e.g. to avoid waiting for an interrupt
+/// which can't be taken before the handler returns
+pub fn in_isr() -> bool {
+    nesting_depth() > 0
+}
+
+/// Defines the handler of interrupt `$name`, a variant of [`Interrupt`](crate::interrupts::Interrupt)
+///
+/// `$handler` is a `fn()`. It's the same as defining `#[no_mangle] fn $name(trap_frame: &mut
+/// TrapFrame)`, except that a name which isn't an interrupt fails to compile.
+#[macro_export]
+macro_rules! interrupt {
+    ($name:ident, $handler:path) => {
+        #[allow(non_snake_case)]
+        #[no_mangle]
+        fn $name(_trap_frame: &mut $crate::interrupts::TrapFrame) {
+            let _ = $crate::interrupts::Interrupt::$name;
+            let handler: fn() = $handler;
+            handler();
         }
+    };
+}
+
+/// Machine timer interrupt, which comes from the CLINT compatible `mtime` and `mtimecmp`
+pub(crate) const MTIMER_IRQ: u32 = 7;
+
+/// Machine software interrupt, which is pending while `msip` is set
+const MSIP_IRQ: u32 = 3;
+
+/// Triggers the software interrupt
+///
+/// Pending it again before the handler ran only runs the handler once.
+pub fn pend_software() {
+    unsafe { (regs::msip() as *mut u32).write_volatile(1) };
+}
+
+/// Clears the software interrupt, if the handler hasn't run yet it won't
+pub fn clear_software() {
+    unsafe { (regs::msip() as *mut u32).write_volatile(0) };
+}
+
+/// Returns whether the software interrupt is pending
+pub fn is_software_pending() -> bool {
+    unsafe { (regs::msip() as *const u32).read_volatile() & 1 != 0 }
+}
+
+/// Sets the handler of the software interrupt and enables the interrupt
+pub fn set_software_handler(handler: fn()) {
+    SOFTWARE_HANDLER.store(handler as usize, Ordering::Release);
+    set_irq_enabled(MSIP_IRQ, true);
+}
+
+/// Disables the software interrupt and removes its handler
+pub fn remove_software_handler() {
+    set_irq_enabled(MSIP_IRQ, false);
+    SOFTWARE_HANDLER.store(0, Ordering::Release);
+}
+
+/// Sets the priority of the software interrupt
+pub fn set_software_priority(priority: Priority) {
+    let ptr = (CLIC_HART0_ADDR + CLIC_INTCFG + MSIP_IRQ) as *mut u8;
+    unsafe { ptr.write_volatile(priority.to_intcfg()) };
+}
+
+/// Handles the software interrupt, called by the trap handler
+fn on_software_interrupt() {
+    // Cleared before the handler runs, so work pended while it runs gets another call
+    clear_software();
+
+    let handler = SOFTWARE_HANDLER.load(Ordering::Acquire);
+    if handler != 0 {
+        let handler: fn() = unsafe { core::mem::transmute(handler) };
+        handler();
+    } else {
+        set_irq_enabled(MSIP_IRQ, false);
     }
 }
 
-/// Enable the given interrupt
-pub fn enable_interrupt(interrupt: Interrupt) {
-    let irq = interrupt.to_irq();
+/// Returns whether the CLIC interrupt enable bit of `irq` is set
+pub(crate) fn irq_enabled(irq: u32) -> bool {
+    let ptr = (CLIC_HART0_ADDR + CLIC_INTIE + irq) as *const u8;
+    unsafe { ptr.read_volatile() != 0 }
+}
+
+/// Sets or clears the CLIC interrupt enable bit of `irq`
+pub(crate) fn set_irq_enabled(irq: u32, enabled: bool) {
     let ptr = (CLIC_HART0_ADDR + CLIC_INTIE + irq) as *mut u8;
     unsafe {
-        ptr.write_volatile(1);
+        ptr.write_volatile(enabled as u8);
     }
 }
 
-/// Disable the given interrupt
-pub fn disable_interrupt(interrupt: Interrupt) {
-    let irq = interrupt.to_irq();
-    let ptr = (CLIC_HART0_ADDR + CLIC_INTIE + irq) as *mut u8;
+fn clic_byte(offset: u32, interrupt: Interrupt) -> *mut u8 {
+    (CLIC_HART0_ADDR + offset + interrupt.to_irq()) as *mut u8
+}
+
+/// Enables `interrupt` in the CLIC
+pub fn enable(interrupt: Interrupt) {
+    unsafe { clic_byte(CLIC_INTIE, interrupt).write_volatile(1) };
+}
+
+/// Disables `interrupt` in the CLIC
+pub fn disable(interrupt: Interrupt) {
+    unsafe { clic_byte(CLIC_INTIE, interrupt).write_volatile(0) };
+}
+
+/// Returns whether `interrupt` is enabled in the CLIC
+pub fn is_enabled(interrupt: Interrupt) -> bool {
+    unsafe { clic_byte(CLIC_INTIE, interrupt).read_volatile() != 0 }
+}
+
+/// Makes `interrupt` pending, which triggers it from software
+pub fn pend(interrupt: Interrupt) {
+    #[cfg(feature = "irq-stats")]
+    stats::pended(interrupt.to_irq() - IRQ_NUM_BASE);
+    unsafe { clic_byte(CLIC_INTIP, interrupt).write_volatile(1) };
+}
+
+/// Clears the pending bit of `interrupt`
+///
+/// The interrupt becomes pending again right away while its flag in the peripheral is set.
+pub fn unpend(interrupt: Interrupt) {
+    unsafe { clic_byte(CLIC_INTIP, interrupt).write_volatile(0) };
+}
+
+/// Returns whether `interrupt` is pending
+pub fn is_pending(interrupt: Interrupt) -> bool {
+    unsafe { clic_byte(CLIC_INTIP, interrupt).read_volatile() != 0 }
+}
+
+/// Priority of an interrupt, 0 to 15, see the module documentation
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Priority(u8);
+
+impl Priority {
+    /// The lowest priority, which all interrupts have after reset
+    pub const MIN: Priority = Priority(0);
+    /// The highest priority
+    pub const MAX: Priority = Priority((1 << CLIC_INTCTLBITS) - 1);
+
+    /// Returns the priority `value`, `None` if it is above 15
+    pub fn new(value: u8) -> Option<Self> {
+        if value <= Self::MAX.0 {
+            Some(Priority(value))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the priority as a number
+    pub fn value(self) -> u8 {
+        self.0
+    }
+
+    /// Returns the interrupt level of this priority if the upper `level_bits` bits are the level
+    ///
+    /// Like the CLIC, the unused bits of the 8 bit level are filled with ones, so with no level
+    /// bits all priorities are level 255.
+    pub fn level(self, level_bits: u8) -> u8 {
+        let level_bits = level_bits.min(CLIC_INTCTLBITS);
+        let level = self.to_intcfg() as u32 >> (8 - level_bits);
+
+        ((level << (8 - level_bits)) | (0xff >> level_bits)) as u8
+    }
+
+    /// Value of `clicintcfg`, the unimplemented lower bits read as ones
+    fn to_intcfg(self) -> u8 {
+        (self.0 << (8 - CLIC_INTCTLBITS)) | (0xff >> CLIC_INTCTLBITS)
+    }
+}
+
+/// Sets the priority of `interrupt`
+pub fn set_priority(interrupt: Interrupt, priority: Priority) {
+    unsafe { clic_byte(CLIC_INTCFG, interrupt).write_volatile(priority.to_intcfg()) };
+}
+
+/// Returns the priority of `interrupt`
+pub fn get_priority(interrupt: Interrupt) -> Priority {
+    let intcfg = unsafe { clic_byte(CLIC_INTCFG, interrupt).read_volatile() };
+    Priority(intcfg >> (8 - CLIC_INTCTLBITS))
+}
+
+/// Returns how many of the upper priority bits are the interrupt level, `nlbits` of `cliccfg`
+pub fn level_bits() -> u8 {
+    let cliccfg = unsafe { ((CLIC_HART0_ADDR + CLIC_CFG) as *const u8).read_volatile() };
+    ((cliccfg >> 1) & 0xf).min(CLIC_INTCTLBITS)
+}
+
+/// Sets how many of the upper priority bits are the interrupt level, at most 4
+///
+/// Interrupts only preempt handlers of a lower level, see [`allow_preemption_above`].
+pub fn set_level_bits(level_bits: u8) {
+    let cliccfg = (CLIC_HART0_ADDR + CLIC_CFG) as *mut u8;
     unsafe {
-        ptr.write_volatile(0);
+        let value = cliccfg.read_volatile() & !(0xf << 1);
+        cliccfg.write_volatile(value | (level_bits.min(CLIC_INTCTLBITS) << 1));
+    }
+}
+
+/// Returns whether the CLIC takes interrupt `a` before `b` when both are pending and enabled
+///
+/// The higher priority comes first, for the same priority the higher interrupt number.
+pub fn runs_before(a: (Interrupt, Priority), b: (Interrupt, Priority)) -> bool {
+    // The level is made of the upper bits of the priority, so comparing the priorities compares
+    // the levels first
+    (a.1, a.0.to_irq()) > (b.1, b.0.to_irq())
+}
+
+/// Returns whether a pending interrupt with `priority` preempts a handler running at interrupt
+/// level `running_level`, with `level_bits` level bits
+///
+/// Outside of handlers the level is 0. Preemption also needs interrupts to be enabled in
+/// `mstatus`, which the trap handler doesn't do.
+pub fn preempts(priority: Priority, running_level: u8, level_bits: u8) -> bool {
+    priority.level(level_bits) > running_level
+}
+
+/// Runs `f` with all interrupts disabled
+pub fn free<F, R>(f: F) -> R
+where
+    F: FnOnce(&CriticalSection) -> R,
+{
+    riscv::interrupt::free(f)
+}
+
+/// Runs `f` with only `interrupts` disabled in the CLIC, all others keep being handled
+///
+/// The interrupts which were enabled are enabled again afterwards. This isn't a critical section
+/// for data shared with handlers of other interrupts.
+pub fn masked<F, R>(interrupts: &[Interrupt], f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let mut enabled = 0u64;
+    for &interrupt in interrupts {
+        if is_enabled(interrupt) {
+            enabled |= 1 << (interrupt.to_irq() - IRQ_NUM_BASE);
+            disable(interrupt);
+        }
+    }
+
+    let result = f();
+
+    for &interrupt in interrupts {
+        if enabled & (1 << (interrupt.to_irq() - IRQ_NUM_BASE)) != 0 {
+            enable(interrupt);
+        }
     }
+
+    result
+}
+
+extern "C" {
+    fn _hal_write_mcause(value: usize);
+    fn _hal_write_mepc(value: usize);
+}
+
+/// Lets interrupts with a priority above `priority` preempt the running handler until the
+/// returned guard is dropped
+///
+/// The enabled interrupts with `priority` or lower are disabled in the CLIC, then interrupts are
+/// enabled in `mstatus`. The CLIC additionally only preempts for an interrupt level above the
+/// level of the running handler, see [`set_level_bits`] and [`preempts`], so whichever of the two
+/// limits is higher applies.
+///
+/// A preempting interrupt overwrites `mepc` and `mcause`, which the running handler needs to
+/// return to the code it interrupted. They are saved here and written back when the guard is
+/// dropped, after interrupts have been disabled again and the masked interrupts enabled again.
+/// Interrupts enabled or disabled in between by another handler keep that state.
+pub fn allow_preemption_above(priority: Priority) -> PreemptionGuard {
+    let interrupts_enabled = mstatus::read().mie();
+    unsafe { riscv::interrupt::disable() };
+
+    let mepc = mepc::read();
+    let mcause = mcause::read().bits();
+
+    let mut masked = 0u128;
+    for irq in 0..CLIC_NUM_INTERRUPTS {
+        let intcfg =
+            unsafe { ((CLIC_HART0_ADDR + CLIC_INTCFG + irq) as *const u8).read_volatile() };
+        if irq_enabled(irq) && Priority(intcfg >> (8 - CLIC_INTCTLBITS)) <= priority {
+            set_irq_enabled(irq, false);
+            masked |= 1 << irq;
+        }
+    }
+
+    unsafe { riscv::interrupt::enable() };
+
+    PreemptionGuard {
+        mepc,
+        mcause,
+        masked,
+        interrupts_enabled,
+        _not_send: PhantomData,
+    }
+}
+
+/// Ends preemption of the running handler when dropped, see [`allow_preemption_above`]
+#[must_use = "the handler can only be preempted while the guard is alive"]
+pub struct PreemptionGuard {
+    mepc: usize,
+    mcause: usize,
+    /// Interrupts disabled by [`allow_preemption_above`], bit `n` for interrupt number `n`
+    masked: u128,
+    interrupts_enabled: bool,
+    /// Has to be dropped in the handler which created it
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for PreemptionGuard {
+    fn drop(&mut self) {
+        unsafe { riscv::interrupt::disable() };
+
+        for irq in 0..CLIC_NUM_INTERRUPTS {
+            if self.masked & (1 << irq) != 0 {
+                set_irq_enabled(irq, true);
+            }
+        }
+
+        // In CLIC mode `mcause` also holds the previous level and the `mpie` and `mpp` bits of
+        // `mstatus`, so this returns the handler to the state it was entered with
+        unsafe {
+            _hal_write_mepc(self.mepc);
+            _hal_write_mcause(self.mcause);
+        }
+
+        if self.interrupts_enabled {
+            unsafe { riscv::interrupt::enable() };
+        }
+    }
+}
+
+/// Enable the given interrupt, same as [`enable`]
+pub fn enable_interrupt(interrupt: Interrupt) {
+    enable(interrupt);
+}
+
+/// Disable the given interrupt, same as [`disable`]
+pub fn disable_interrupt(interrupt: Interrupt) {
+    disable(interrupt);
 }
 
 /// Clear the given interrupt.
 /// Usually the interrupt needs to be cleared also on the peripheral level.
 pub fn clear_interrupt(interrupt: Interrupt) {
-    let irq = interrupt.to_irq();
-    let ptr = (CLIC_HART0_ADDR + CLIC_INTIP + irq) as *mut u8;
-    unsafe {
-        ptr.write_volatile(0);
+    unpend(interrupt);
+}
+
+#[cfg(feature = "irq-stats")]
+pub use self::stats::{reset_stats, stats, IrqStats};
+
+#[cfg(feature = "irq-stats")]
+mod stats {
+    use riscv::register::mcycle;
+
+    use super::{Interrupt, IRQ_NUM_BASE};
+    use crate::sync::SpinLock;
+
+    /// Number of peripheral interrupt numbers
+    const NUM_IRQS: usize = 64;
+
+    static STATS: SpinLock<[IrqStats; NUM_IRQS]> = SpinLock::new([IrqStats::new(); NUM_IRQS]);
+
+    /// Statistics of the handler of one interrupt, in cycles of the core clock
+    ///
+    /// The duration is measured from the dispatch to the handler until it returns, it includes
+    /// the handlers of interrupts which preempted it. The latency is measured from [`pend`] to
+    /// the dispatch, so it's only known for interrupts pended from software.
+    ///
+    /// [`pend`]: super::pend
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    pub struct IrqStats {
+        /// Number of times the handler ran
+        pub count: u32,
+        /// Shortest run of the handler
+        pub min_cycles: u32,
+        /// Longest run of the handler
+        pub max_cycles: u32,
+        total_cycles: u64,
+        /// Number of runs whose latency is known
+        pub latency_count: u32,
+        /// Longest latency
+        pub max_latency_cycles: u32,
+        total_latency_cycles: u64,
+        /// `mcycle` at the last [`pend`](super::pend) which hasn't been taken yet
+        pended_at: Option<u32>,
+    }
+
+    impl IrqStats {
+        const fn new() -> Self {
+            IrqStats {
+                count: 0,
+                min_cycles: u32::MAX,
+                max_cycles: 0,
+                total_cycles: 0,
+                latency_count: 0,
+                max_latency_cycles: 0,
+                total_latency_cycles: 0,
+                pended_at: None,
+            }
+        }
+
+        /// Returns the mean run of the handler, 0 if it didn't run
+        pub fn mean_cycles(&self) -> u32 {
+            if self.count == 0 {
+                0
+            } else {
+                (self.total_cycles / self.count as u64) as u32
+            }
+        }
+
+        /// Returns the mean latency, `None` if no latency is known
+        pub fn mean_latency_cycles(&self) -> Option<u32> {
+            if self.latency_count == 0 {
+                None
+            } else {
+                Some((self.total_latency_cycles / self.latency_count as u64) as u32)
+            }
+        }
+    }
+
+    /// Returns the statistics of every interrupt whose handler ran since reset or
+    /// [`reset_stats`]
+    ///
+    /// Each entry is copied out of the table with interrupts disabled for a moment, so entries
+    /// can be printed while the statistics keep being updated.
+    pub fn stats() -> impl Iterator<Item = (Interrupt, IrqStats)> {
+        (0..NUM_IRQS).filter_map(|index| {
+            let stats = STATS.lock_irq_disabled()[index];
+            let interrupt = Interrupt::from(IRQ_NUM_BASE + index as u32);
+            if stats.count != 0 && interrupt != Interrupt::Unknown {
+                Some((interrupt, stats))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Clears the statistics of all interrupts
+    pub fn reset_stats() {
+        let mut stats = STATS.lock_irq_disabled();
+        for entry in stats.iter_mut() {
+            // A pending interrupt keeps its timestamp, so its latency is still measured
+            *entry = IrqStats {
+                pended_at: entry.pended_at,
+                ..IrqStats::new()
+            };
+        }
+    }
+
+    /// Notes the time interrupt `index`, counted from the first peripheral interrupt, was pended
+    pub(super) fn pended(index: u32) {
+        let now = mcycle::read() as u32;
+        STATS.lock_irq_disabled()[index as usize].pended_at = Some(now);
+    }
+
+    /// Adds a run of the handler of `interrupt` which was dispatched at `entered`
+    pub(super) fn record(interrupt: Interrupt, entered: u32) {
+        let cycles = (mcycle::read() as u32).wrapping_sub(entered);
+        if interrupt == Interrupt::Unknown {
+            return;
+        }
+        let mut stats = STATS.lock_irq_disabled();
+        let entry = &mut stats[(interrupt.to_irq() - IRQ_NUM_BASE) as usize];
+
+        entry.count = entry.count.wrapping_add(1);
+        entry.min_cycles = entry.min_cycles.min(cycles);
+        entry.max_cycles = entry.max_cycles.max(cycles);
+        entry.total_cycles += cycles as u64;
+
+        if let Some(pended_at) = entry.pended_at.take() {
+            let latency = entered.wrapping_sub(pended_at);
+            entry.latency_count += 1;
+            entry.max_latency_cycles = entry.max_latency_cycles.max(latency);
+            entry.total_latency_cycles += latency as u64;
+        }
     }
 }
@@ -0,0 +1,1071 @@
+/*!
+  # Infrared remote control
+  The IR peripheral has a transmitter and a receiver, which [`IrExt::split`] hands out to a
+  [`Transmitter`] and a [`Receiver`] of their own. Both run from the IR clock of about 2 MHz.
+
+  # Sending
+  The IR transmitter sends a train of pulses, alternately marks, during which the carrier is
+  on, and spaces, during which the output is off, always starting with a mark. Its output goes
+  through the LED driver of GLB, which sinks the current of an IR LED on GPIO22; the pin is
+  taken in [`Analog`] mode so its GPIO function doesn't drive the pad as well.
+
+  [`Transmitter::send_pulses`] takes the train as durations in microseconds, e.g. from an
+  encoder below or captured from a remote. The hardware stores every pulse as a multiple of 1
+  to 16 of a common unit, so the shortest pulse becomes the unit and every other pulse has to be
+  within 1/8 of a multiple of it, which holds for the usual protocols:
+
+  | Protocol | Unit   | Pulses per frame | Encoders                                  |
+  |----------|--------|------------------|-------------------------------------------|
+  | NEC      | 562 µs | 67, repeat 3     | [`nec`], [`nec_extended`], [`nec_repeat`] |
+  | RC5      | 889 µs | up to 28         | [`rc5`]                                   |
+
+  # Long trains
+  The transmitter has no FIFO but a buffer of 64 pulses, [`CHUNK_PULSES`]. A longer train, up to
+  [`MAX_PULSES`], is kept in the [`Transmitter`] and loaded in chunks of 64 pulses: when the
+  end interrupt flag says a chunk is out, [`Transmitter::poll`] loads the next one and starts
+  the transmitter again. A chunk of 64 pulses ends with a space, which the reload stretches by
+  the time it takes to call `poll`, a few microseconds when polling in a loop and the interrupt
+  latency from a handler, well within the tolerance of IR receivers.
+
+  [`Transmitter::start`] only loads the first chunk and returns, so a frame can be sent from an
+  interrupt handler: enable the end interrupt with [`Transmitter::enable_end_interrupt`] and
+  the `IrTx` line in [`interrupts`](crate::interrupts), and call `poll` in the handler until it
+  returns `Ok`, which tells that the whole train is out.
+
+  # Receiving
+  The receiver measures every mark and space of the signal of a receiver module on GPIO11, 12 or
+  13 and puts it into a FIFO of 64 entries. A space of at least the end of frame gap of
+  [`RxConfig`] ends the frame and sets the end flag. [`Receiver::poll`] moves the pulses from
+  the FIFO into a buffer and returns the frame once the end flag is set, and
+  [`Receiver::read_frame`] polls until then or a timeout. A frame which overflowed the FIFO, or
+  frames which ran together, both because `poll` wasn't called in time, are dropped with
+  [`Error::Overflow`] and [`Error::FramesMerged`] rather than returned cut or merged.
+
+  The end interrupt, [`Receiver::enable_end_interrupt`], only comes after a frame, so it's
+  enough on its own for frames of up to 64 pulses, e.g. RC5. An NEC frame has 67, and `poll`
+  has to be called while it comes in as well, e.g. from a timer every 10 ms.
+
+  [`decode_nec`] turns a frame into an [`NecCommand`], with tolerance for the pulses a
+  receiver module lengthens or shortens.
+
+  ## Example
+  ```rust
+    use bl602_hal::ir::{self, IrExt, Receiver, Transmitter};
+
+    let ir = dp.IR.split();
+    let pin = parts.pin22.into_analog();
+    let mut tx = Transmitter::new(ir.tx, pin, ir::Config::default(), &clocks).unwrap();
+
+    tx.send_pulses(&ir::nec(0x04, 0x08)).unwrap();
+    delay.delay_ms(40).unwrap();
+    tx.send_pulses(&ir::nec_repeat()).unwrap();
+
+    let pin = parts.pin11.into_pull_up_input();
+    let mut rx = Receiver::new(ir.rx, pin, ir::RxConfig::default(), &clocks).unwrap();
+
+    let mut pulses = [0; 100];
+    let len = rx.read_frame(&mut pulses, 1000).unwrap();
+    if let Ok(ir::NecCommand::Press { command, .. }) = ir::decode_nec(&pulses[..len]) {
+        // ..
+    }
+  ```
+*/
+
+use core::convert::Infallible;
+use core::ops::Deref;
+
+use embedded_time::rate::Hertz;
+
+use crate::clock::{Clocks, RC32M};
+use crate::delay::McycleDelay;
+use crate::gpio::{Analog, Input, Pin11, Pin12, Pin13, Pin22, PinNumber};
+use crate::pac;
+
+/// Duration of a mark or a space in microseconds
+pub type PulseUs = u32;
+
+/// Pulses the transmitter sends at a time
+pub const CHUNK_PULSES: usize = 64;
+/// Most pulses a [`Transmitter`] takes for one train
+pub const MAX_PULSES: usize = 256;
+
+// Registers of IR, see bl602_ir_reg.h of the vendor SDK
+const IRTX_CONFIG: usize = 0x00;
+const IRTX_INT_STS: usize = 0x04;
+const IRTX_PULSE_WIDTH: usize = 0x10;
+const IRTX_SWM_PW_0: usize = 0x40;
+const IRRX_CONFIG: usize = 0x80;
+const IRRX_INT_STS: usize = 0x84;
+const IRRX_PW_CONFIG: usize = 0x88;
+const IRRX_SWM_FIFO_CONFIG_0: usize = 0xc0;
+const IRRX_SWM_FIFO_RDATA: usize = 0xc4;
+
+// IRTX_CONFIG
+const IRTX_EN: u32 = 1 << 0;
+const IRTX_MOD_EN: u32 = 1 << 2;
+const IRTX_SWM_EN: u32 = 1 << 3;
+const IRTX_DATA_NUM_POS: u32 = 4;
+const IRTX_DATA_NUM_MASK: u32 = 0x3f << IRTX_DATA_NUM_POS;
+
+// IRTX_INT_STS
+const IRTX_END_INT: u32 = 1 << 0;
+const IRTX_END_MASK: u32 = 1 << 8;
+const IRTX_END_CLR: u32 = 1 << 16;
+const IRTX_END_EN: u32 = 1 << 24;
+
+// IRRX_CONFIG
+const IRRX_EN: u32 = 1 << 0;
+const IRRX_IN_INV: u32 = 1 << 1;
+/// CR_IRRX_MODE, pulse width (SWM) mode
+const IRRX_MODE_SWM: u32 = 2 << 2;
+const IRRX_DEG_EN: u32 = 1 << 4;
+const IRRX_DEG_CNT_POS: u32 = 8;
+const IRRX_DEG_CNT_MAX: u32 = 0xf;
+
+// IRRX_INT_STS
+const IRRX_END_INT: u32 = 1 << 0;
+const IRRX_END_MASK: u32 = 1 << 8;
+const IRRX_END_CLR: u32 = 1 << 16;
+const IRRX_END_EN: u32 = 1 << 24;
+
+// IRRX_PW_CONFIG
+const IRRX_END_TH_POS: u32 = 16;
+const IRRX_TH_MAX: u32 = 0xffff;
+
+// IRRX_SWM_FIFO_CONFIG_0
+const RX_FIFO_CLR: u32 = 1 << 0;
+const RX_FIFO_OVERFLOW: u32 = 1 << 2;
+const RX_FIFO_CNT_POS: u32 = 4;
+const RX_FIFO_CNT_MASK: u32 = 0x7f << RX_FIFO_CNT_POS;
+
+// IRTX_PULSE_WIDTH
+const IRTX_PW_UNIT_MAX: u32 = 0xfff;
+const IRTX_MOD_PH0_W_POS: u32 = 16;
+const IRTX_MOD_PH1_W_POS: u32 = 24;
+
+// LED driver of GLB, see bl602_glb_reg.h of the vendor SDK
+const GLB_LED_DRIVER: usize = 0x224;
+/// LED_DIN_SEL, driven by the IR transmitter instead of LED_DIN_REG
+const LED_DIN_SEL: u32 = 1 << 1;
+/// LED_DIN_POLARITY_SEL
+const LED_DIN_POLARITY_SEL: u32 = 1 << 2;
+/// IR_RX_GPIO_SEL, GPIO11 to GPIO13 as 1 to 3
+const IR_RX_GPIO_SEL_POS: u32 = 8;
+const IR_RX_GPIO_SEL_MASK: u32 = 0b11 << IR_RX_GPIO_SEL_POS;
+/// PU_LEDDRV
+const PU_LEDDRV: u32 = 1 << 31;
+
+/// Frequency the IR clock is divided down to from XCLK
+const IR_CLK_TARGET: u32 = 2_000_000;
+/// Most pulse units per pulse
+const MAX_UNITS: u32 = 16;
+
+/// IR error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The carrier frequency or duty cycle can't be generated from the 2 MHz IR clock
+    Carrier,
+    /// The pulse at this index isn't within 1/8 of 1 to 16 times the shortest pulse, or the
+    /// shortest pulse is 0 or longer than 2 ms
+    PulseWidth(usize),
+    /// The train is empty or longer than [`MAX_PULSES`]
+    Length,
+    /// The previous train isn't out yet
+    Busy,
+    /// The glitch filter or the end of frame gap is longer than the receiver can count, 7 µs and
+    /// 32 ms
+    Threshold,
+    /// The receive FIFO overflowed, the frame is dropped
+    Overflow,
+    /// The frame has more pulses than the buffer holds
+    FrameTooLong,
+    /// A space as long as the end of frame gap came in before the end of the frame was handled,
+    /// so two frames ran together
+    FramesMerged,
+    /// No frame came in within the timeout
+    Timeout,
+}
+
+/// IR transmitter
+pub struct IrTx {
+    pub(crate) _ownership: (),
+}
+
+/// IR receiver
+pub struct IrRx {
+    pub(crate) _ownership: (),
+}
+
+/// Parts obtained from [IR.split](IrExt::split)
+pub struct Parts {
+    pub tx: IrTx,
+    pub rx: IrRx,
+}
+
+/// Extension trait to split the IR peripheral into transmitter and receiver
+pub trait IrExt {
+    /// Splits the register block into transmitter and receiver
+    fn split(self) -> Parts;
+}
+
+impl IrExt for pac::IR {
+    fn split(self) -> Parts {
+        Parts {
+            tx: IrTx { _ownership: () },
+            rx: IrRx { _ownership: () },
+        }
+    }
+}
+
+/// IR transmitter configuration
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Config {
+    /// Carrier frequency, `None` to send the pulses unmodulated, e.g. over a wire
+    pub carrier: Option<Hertz>,
+    /// Part of each carrier period the LED is on, in percent
+    pub duty_percent: u8,
+}
+
+impl Config {
+    /// Sets the carrier frequency
+    pub fn carrier(mut self, frequency: impl Into<Hertz>) -> Self {
+        self.carrier = Some(frequency.into());
+
+        self
+    }
+
+    /// Sets the carrier duty cycle
+    pub fn duty_percent(mut self, duty_percent: u8) -> Self {
+        self.duty_percent = duty_percent;
+
+        self
+    }
+
+    /// Sends the pulses without carrier
+    pub fn unmodulated(mut self) -> Self {
+        self.carrier = None;
+
+        self
+    }
+}
+
+impl Default for Config {
+    /// 38 kHz at a third on, what most receivers of consumer remotes expect
+    fn default() -> Config {
+        Config {
+            carrier: Some(Hertz(38_000)),
+            duty_percent: 33,
+        }
+    }
+}
+
+/// IR TX pins - DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait TxPin {}
+
+unsafe impl TxPin for Pin22<Analog> {}
+
+/// IR RX pins - DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait RxPin: PinNumber {}
+
+unsafe impl<MODE> RxPin for Pin11<Input<MODE>> {}
+unsafe impl<MODE> RxPin for Pin12<Input<MODE>> {}
+unsafe impl<MODE> RxPin for Pin13<Input<MODE>> {}
+
+/// Pulse trains, see the module documentation
+pub struct Transmitter<PIN> {
+    tx: IrTx,
+    pin: PIN,
+    ir_clk: u32,
+    /// Pulses of the train as units minus 1, 8 per word, as the SWM_PW registers take them
+    widths: [u32; MAX_PULSES / 8],
+    /// Pulse unit of the train in IR clock cycles
+    unit: u32,
+    len: usize,
+    /// Pulses loaded into the transmitter so far
+    loaded: usize,
+}
+
+impl<PIN: TxPin> Transmitter<PIN> {
+    /// Sets up the IR clock, the carrier and the LED driver
+    ///
+    /// The IR clock is divided from XCLK, the crystal or the 32 MHz RC oscillator, to about 2 MHz.
+    /// The receiver uses the same clock.
+    pub fn new(tx: IrTx, pin: PIN, config: Config, clocks: &Clocks) -> Result<Self, Error> {
+        let ir_clk = enable_clock(clocks);
+
+        let (mod_en, ph0, ph1) = match config.carrier {
+            Some(carrier) => {
+                let (ph0, ph1) = carrier_phases(ir_clk, carrier.0, config.duty_percent)?;
+                (IRTX_MOD_EN, ph0, ph1)
+            }
+            None => (0, 1, 1),
+        };
+
+        unsafe {
+            let led_driver = (pac::GLB::ptr() as usize + GLB_LED_DRIVER) as *mut u32;
+            let value = led_driver.read_volatile() & !LED_DIN_POLARITY_SEL;
+            led_driver.write_volatile(value | LED_DIN_SEL | PU_LEDDRV);
+
+            reg(IRTX_CONFIG).write_volatile(mod_en | IRTX_SWM_EN);
+            reg(IRTX_PULSE_WIDTH)
+                .write_volatile((ph0 - 1) << IRTX_MOD_PH0_W_POS | (ph1 - 1) << IRTX_MOD_PH1_W_POS);
+            reg(IRTX_INT_STS).write_volatile(IRTX_END_MASK | IRTX_END_CLR | IRTX_END_EN);
+        }
+
+        Ok(Transmitter {
+            tx,
+            pin,
+            ir_clk,
+            widths: [0; MAX_PULSES / 8],
+            unit: 1,
+            len: 0,
+            loaded: 0,
+        })
+    }
+}
+
+impl<PIN> Transmitter<PIN> {
+    /// Sends `pulses`, starting with a mark, and returns when they're out
+    pub fn send_pulses(&mut self, pulses: &[PulseUs]) -> Result<(), Error> {
+        self.start(pulses)?;
+        nb::block!(self.poll()).ok();
+
+        Ok(())
+    }
+
+    /// Starts sending `pulses` and returns after loading the first chunk, see the module
+    /// documentation
+    ///
+    /// Fails with [`Error::Busy`] until [`poll`](Self::poll) returned `Ok` for the previous train.
+    pub fn start(&mut self, pulses: &[PulseUs]) -> Result<(), Error> {
+        if self.len != 0 {
+            return Err(Error::Busy);
+        }
+        self.unit = encode(pulses, self.ir_clk, &mut self.widths)?;
+        self.len = pulses.len();
+        self.loaded = 0;
+        self.load_chunk();
+
+        Ok(())
+    }
+
+    /// Loads the next chunk once the transmitter is done with the last one
+    ///
+    /// Returns `Ok` once the whole train is out, or if there is none.
+    pub fn poll(&mut self) -> nb::Result<(), Infallible> {
+        if self.len == 0 {
+            return Ok(());
+        }
+        unsafe {
+            if reg(IRTX_INT_STS).read_volatile() & IRTX_END_INT == 0 {
+                return Err(nb::Error::WouldBlock);
+            }
+            let int_sts = reg(IRTX_INT_STS);
+            int_sts.write_volatile(int_sts.read_volatile() | IRTX_END_CLR);
+        }
+
+        if self.loaded < self.len {
+            self.load_chunk();
+            Err(nb::Error::WouldBlock)
+        } else {
+            unsafe {
+                let config = reg(IRTX_CONFIG);
+                config.write_volatile(config.read_volatile() & !IRTX_EN);
+            }
+            self.len = 0;
+            Ok(())
+        }
+    }
+
+    /// Raises the `IrTx` interrupt whenever a chunk is out, for [`poll`](Self::poll) to be
+    /// called from the handler
+    pub fn enable_end_interrupt(&mut self) {
+        unsafe {
+            let int_sts = reg(IRTX_INT_STS);
+            int_sts.write_volatile(int_sts.read_volatile() & !IRTX_END_MASK);
+        }
+    }
+
+    /// Stops raising the `IrTx` interrupt
+    pub fn disable_end_interrupt(&mut self) {
+        unsafe {
+            let int_sts = reg(IRTX_INT_STS);
+            int_sts.write_volatile(int_sts.read_volatile() | IRTX_END_MASK);
+        }
+    }
+
+    /// Returns whether a train is still being sent
+    pub fn is_busy(&self) -> bool {
+        self.len != 0
+    }
+
+    /// Stops the transmitter and the LED driver, and returns the peripheral and the pin
+    pub fn free(self) -> (IrTx, PIN) {
+        unsafe {
+            reg(IRTX_CONFIG).write_volatile(0);
+            let led_driver = (pac::GLB::ptr() as usize + GLB_LED_DRIVER) as *mut u32;
+            led_driver.write_volatile(led_driver.read_volatile() & !(LED_DIN_SEL | PU_LEDDRV));
+        }
+
+        (self.tx, self.pin)
+    }
+
+    /// Loads up to `CHUNK_PULSES` pulses from `loaded` on and starts the transmitter
+    fn load_chunk(&mut self) {
+        let n = (self.len - self.loaded).min(CHUNK_PULSES);
+        let first = self.loaded / 8;
+        unsafe {
+            let config = reg(IRTX_CONFIG);
+            let value = config.read_volatile() & !(IRTX_EN | IRTX_DATA_NUM_MASK);
+            config.write_volatile(value);
+
+            for (i, &word) in self.widths[first..first + (n + 7) / 8].iter().enumerate() {
+                reg(IRTX_SWM_PW_0 + i * 4).write_volatile(word);
+            }
+            let pulse_width = reg(IRTX_PULSE_WIDTH);
+            pulse_width.write_volatile(pulse_width.read_volatile() & !IRTX_PW_UNIT_MAX | self.unit);
+
+            // The transmitter starts on the rising edge of IRTX_EN
+            let value = value | (n as u32 - 1) << IRTX_DATA_NUM_POS;
+            config.write_volatile(value);
+            config.write_volatile(value | IRTX_EN);
+        }
+        self.loaded += n;
+    }
+}
+
+/// IR receiver configuration
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RxConfig {
+    /// Pulses shorter than this are filtered out as glitches, 0 to turn the filter off
+    pub threshold_us: u32,
+    /// A space at least this long ends a frame
+    pub end_of_frame_gap_us: u32,
+    /// Whether the input is low during a mark, as with receiver modules
+    pub inverted: bool,
+}
+
+impl RxConfig {
+    /// Sets the glitch filter
+    pub fn threshold_us(mut self, threshold_us: u32) -> Self {
+        self.threshold_us = threshold_us;
+
+        self
+    }
+
+    /// Sets the space which ends a frame
+    pub fn end_of_frame_gap_us(mut self, end_of_frame_gap_us: u32) -> Self {
+        self.end_of_frame_gap_us = end_of_frame_gap_us;
+
+        self
+    }
+
+    /// Sets whether the input is low during a mark
+    pub fn inverted(mut self, inverted: bool) -> Self {
+        self.inverted = inverted;
+
+        self
+    }
+}
+
+impl Default for RxConfig {
+    /// A receiver module, with a gap longer than any space within NEC or RC5 frames and shorter
+    /// than the one between them
+    fn default() -> RxConfig {
+        RxConfig {
+            threshold_us: 5,
+            end_of_frame_gap_us: 10_000,
+            inverted: true,
+        }
+    }
+}
+
+/// Pulse trains captured from an IR receiver module, see the module documentation
+pub struct Receiver<PIN> {
+    rx: IrRx,
+    pin: PIN,
+    ir_clk: u32,
+    cycles_per_ms: u32,
+    gap_us: u32,
+    /// Pulses of the current frame stored so far
+    len: usize,
+    too_long: bool,
+    merged: bool,
+    /// Drops the rest of a frame whose start was lost
+    discard: bool,
+}
+
+impl<PIN: RxPin> Receiver<PIN> {
+    /// Sets up the IR clock and starts receiving on `pin`
+    pub fn new(rx: IrRx, pin: PIN, config: RxConfig, clocks: &Clocks) -> Result<Self, Error> {
+        let ir_clk = enable_clock(clocks);
+        let cycles = |us: u32| (us as u64 * ir_clk as u64 / 1_000_000) as u32;
+        let glitch = cycles(config.threshold_us);
+        let gap = cycles(config.end_of_frame_gap_us);
+        if glitch > IRRX_DEG_CNT_MAX || gap == 0 || gap > IRRX_TH_MAX {
+            return Err(Error::Threshold);
+        }
+
+        let mut rx_config = IRRX_MODE_SWM;
+        if glitch > 0 {
+            rx_config |= IRRX_DEG_EN | glitch << IRRX_DEG_CNT_POS;
+        }
+        if config.inverted {
+            rx_config |= IRRX_IN_INV;
+        }
+        unsafe {
+            let led_driver = (pac::GLB::ptr() as usize + GLB_LED_DRIVER) as *mut u32;
+            let value = led_driver.read_volatile() & !IR_RX_GPIO_SEL_MASK;
+            led_driver.write_volatile(value | (PIN::PIN as u32 - 10) << IR_RX_GPIO_SEL_POS);
+
+            reg(IRRX_CONFIG).write_volatile(rx_config);
+            reg(IRRX_PW_CONFIG).write_volatile(gap << IRRX_END_TH_POS);
+            reg(IRRX_INT_STS).write_volatile(IRRX_END_MASK | IRRX_END_CLR | IRRX_END_EN);
+            reg(IRRX_SWM_FIFO_CONFIG_0).write_volatile(RX_FIFO_CLR);
+            reg(IRRX_CONFIG).write_volatile(rx_config | IRRX_EN);
+        }
+
+        Ok(Receiver {
+            rx,
+            pin,
+            ir_clk,
+            cycles_per_ms: clocks.sysclk().0 / 1000,
+            gap_us: config.end_of_frame_gap_us,
+            len: 0,
+            too_long: false,
+            merged: false,
+            discard: false,
+        })
+    }
+}
+
+impl<PIN> Receiver<PIN> {
+    /// Waits up to `timeout_ms` for a frame and stores its pulses in `pulses`, starting with a
+    /// mark, returns their number
+    pub fn read_frame(&mut self, pulses: &mut [PulseUs], timeout_ms: u32) -> Result<usize, Error> {
+        let timeout = self.cycles_per_ms as u64 * timeout_ms as u64;
+        let start = McycleDelay::get_cycle_count();
+        loop {
+            match self.poll(pulses) {
+                Ok(len) => return Ok(len),
+                Err(nb::Error::Other(error)) => return Err(error),
+                Err(nb::Error::WouldBlock) => {}
+            }
+            if McycleDelay::cycles_since(start) >= timeout {
+                // What comes of a frame cut off here isn't a frame of its own
+                self.discard |= self.len > 0;
+                self.reset();
+                return Err(Error::Timeout);
+            }
+        }
+    }
+
+    /// Moves the pulses from the FIFO to `pulses`, and returns their number once the frame has
+    /// ended
+    ///
+    /// Pass the same buffer until this returns something else than `WouldBlock`. Errors drop the
+    /// frame, the next call starts a new one.
+    pub fn poll(&mut self, pulses: &mut [PulseUs]) -> nb::Result<usize, Error> {
+        let fifo = reg(IRRX_SWM_FIFO_CONFIG_0);
+        let int_sts = reg(IRRX_INT_STS);
+        let (ended, available) = unsafe {
+            if fifo.read_volatile() & RX_FIFO_OVERFLOW != 0 {
+                fifo.write_volatile(RX_FIFO_CLR);
+                self.discard = true;
+                self.reset();
+                return Err(nb::Error::Other(Error::Overflow));
+            }
+            // Everything of a frame is in the FIFO once its end is flagged, what comes in later
+            // belongs to the next one
+            let ended = int_sts.read_volatile() & IRRX_END_INT != 0;
+            let available = (fifo.read_volatile() & RX_FIFO_CNT_MASK) >> RX_FIFO_CNT_POS;
+            (ended, available)
+        };
+
+        for _ in 0..available {
+            let cycles = unsafe { reg(IRRX_SWM_FIFO_RDATA).read_volatile() } & 0xffff;
+            let pulse = (cycles as u64 * 1_000_000 / self.ir_clk as u64) as PulseUs;
+            if self.discard || (self.len == 0 && pulse >= self.gap_us) {
+                // The rest of a dropped frame, or the idle line before a frame
+                continue;
+            }
+            if pulse >= self.gap_us {
+                self.merged = true;
+            }
+            match pulses.get_mut(self.len) {
+                Some(slot) => {
+                    *slot = pulse;
+                    self.len += 1;
+                }
+                None => self.too_long = true,
+            }
+        }
+
+        if !ended {
+            return Err(nb::Error::WouldBlock);
+        }
+        unsafe { int_sts.write_volatile(int_sts.read_volatile() | IRRX_END_CLR) };
+
+        let result = if self.discard || self.len == 0 {
+            self.discard = false;
+            Err(nb::Error::WouldBlock)
+        } else if self.too_long {
+            Err(nb::Error::Other(Error::FrameTooLong))
+        } else if self.merged {
+            Err(nb::Error::Other(Error::FramesMerged))
+        } else {
+            Ok(self.len)
+        };
+        self.reset();
+
+        result
+    }
+
+    /// Raises the `IrRx` interrupt at the end of every frame, for [`poll`](Self::poll) to be
+    /// called from the handler
+    pub fn enable_end_interrupt(&mut self) {
+        unsafe {
+            let int_sts = reg(IRRX_INT_STS);
+            int_sts.write_volatile(int_sts.read_volatile() & !IRRX_END_MASK);
+        }
+    }
+
+    /// Stops raising the `IrRx` interrupt
+    pub fn disable_end_interrupt(&mut self) {
+        unsafe {
+            let int_sts = reg(IRRX_INT_STS);
+            int_sts.write_volatile(int_sts.read_volatile() | IRRX_END_MASK);
+        }
+    }
+
+    /// Stops receiving and returns the receiver and the pin
+    pub fn free(self) -> (IrRx, PIN) {
+        unsafe {
+            reg(IRRX_CONFIG).write_volatile(0);
+            let led_driver = (pac::GLB::ptr() as usize + GLB_LED_DRIVER) as *mut u32;
+            led_driver.write_volatile(led_driver.read_volatile() & !IR_RX_GPIO_SEL_MASK);
+        }
+
+        (self.rx, self.pin)
+    }
+
+    fn reset(&mut self) {
+        self.len = 0;
+        self.too_long = false;
+        self.merged = false;
+    }
+}
+
+/// Pulses of the longest train an encoder builds, an NEC frame
+const TRAIN_PULSES: usize = NEC_PULSES;
+
+/// A pulse train built by an encoder, starting with a mark
+///
+/// The pulses are kept in an array large enough for the train of any of the encoders, of
+/// which [`as_slice`](Self::as_slice) hands out the pulses pushed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PulseTrain {
+    pulses: [PulseUs; TRAIN_PULSES],
+    len: usize,
+}
+
+impl PulseTrain {
+    fn new() -> Self {
+        PulseTrain {
+            pulses: [0; TRAIN_PULSES],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, pulse: PulseUs) {
+        self.pulses[self.len] = pulse;
+        self.len += 1;
+    }
+
+    /// Returns the pulses, alternately marks and spaces
+    pub fn as_slice(&self) -> &[PulseUs] {
+        &self.pulses[..self.len]
+    }
+}
+
+impl Deref for PulseTrain {
+    type Target = [PulseUs];
+
+    fn deref(&self) -> &[PulseUs] {
+        self.as_slice()
+    }
+}
+
+/// Pulses of an NEC frame: leader, 32 bits and the closing mark
+pub const NEC_PULSES: usize = 67;
+/// Time from the start of an NEC frame to the start of the first repeat frame, and between
+/// repeat frames, while a key is held
+pub const NEC_REPEAT_PERIOD_US: u32 = 108_000;
+
+const NEC_LEADER_MARK: PulseUs = 9000;
+const NEC_LEADER_SPACE: PulseUs = 4500;
+const NEC_REPEAT_SPACE: PulseUs = 2250;
+const NEC_BIT_MARK: PulseUs = 562;
+const NEC_ZERO_SPACE: PulseUs = 562;
+const NEC_ONE_SPACE: PulseUs = 1687;
+
+/// Encodes an NEC frame: the address and the command, each followed by its inverse
+pub fn nec(address: u8, command: u8) -> PulseTrain {
+    nec_extended(u16::from_le_bytes([address, !address]), command)
+}
+
+/// Encodes an extended NEC frame, with a 16 bit address in place of the address and its inverse
+pub fn nec_extended(address: u16, command: u8) -> PulseTrain {
+    let bits = address as u32 | (command as u32) << 16 | (!command as u32) << 24;
+
+    let mut train = PulseTrain::new();
+    train.push(NEC_LEADER_MARK);
+    train.push(NEC_LEADER_SPACE);
+    // Least significant bit first
+    for i in 0..32 {
+        train.push(NEC_BIT_MARK);
+        train.push(if bits >> i & 1 != 0 {
+            NEC_ONE_SPACE
+        } else {
+            NEC_ZERO_SPACE
+        });
+    }
+    train.push(NEC_BIT_MARK);
+
+    train
+}
+
+/// Encodes the NEC repeat frame, sent every [`NEC_REPEAT_PERIOD_US`] while a key is held
+pub fn nec_repeat() -> PulseTrain {
+    let mut train = PulseTrain::new();
+    train.push(NEC_LEADER_MARK);
+    train.push(NEC_REPEAT_SPACE);
+    train.push(NEC_BIT_MARK);
+
+    train
+}
+
+/// NEC frame decoded by [`decode_nec`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum NecCommand {
+    /// A key press, with the address as [`nec_extended`] takes it: the address of a standard
+    /// frame in the low byte and its inverse in the high byte
+    Press { address: u16, command: u8 },
+    /// The key is still held
+    Repeat,
+}
+
+/// Error decoding a frame
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum DecodeError {
+    /// The frame has neither the number of pulses of a frame nor of a repeat frame
+    Length,
+    /// The leader isn't that of the protocol
+    Leader,
+    /// The pulse at this index is neither the duration of a zero nor of a one
+    Pulse(usize),
+    /// The command doesn't match its inverse
+    Checksum,
+}
+
+/// Decodes an NEC frame or repeat frame
+///
+/// A pulse has to be within a quarter of its nominal duration plus 150 µs, receiver modules make
+/// marks up to 150 µs longer and spaces shorter than sent.
+pub fn decode_nec(pulses: &[PulseUs]) -> Result<NecCommand, DecodeError> {
+    let near = |index: usize, nominal: PulseUs| {
+        let pulse = pulses[index];
+        pulse.max(nominal) - pulse.min(nominal) <= nominal / 4 + 150
+    };
+
+    if pulses.len() == 3 && near(0, NEC_LEADER_MARK) && near(1, NEC_REPEAT_SPACE) {
+        return if near(2, NEC_BIT_MARK) {
+            Ok(NecCommand::Repeat)
+        } else {
+            Err(DecodeError::Pulse(2))
+        };
+    }
+    if pulses.len() != NEC_PULSES {
+        return Err(DecodeError::Length);
+    }
+    if !near(0, NEC_LEADER_MARK) || !near(1, NEC_LEADER_SPACE) {
+        return Err(DecodeError::Leader);
+    }
+
+    let mut bits = 0u32;
+    for i in 0..32 {
+        let (mark, space) = (2 + 2 * i, 3 + 2 * i);
+        if !near(mark, NEC_BIT_MARK) {
+            return Err(DecodeError::Pulse(mark));
+        }
+        if near(space, NEC_ONE_SPACE) {
+            bits |= 1 << i;
+        } else if !near(space, NEC_ZERO_SPACE) {
+            return Err(DecodeError::Pulse(space));
+        }
+    }
+    if !near(NEC_PULSES - 1, NEC_BIT_MARK) {
+        return Err(DecodeError::Pulse(NEC_PULSES - 1));
+    }
+
+    let [address_low, address_high, command, inverse] = bits.to_le_bytes();
+    if command != !inverse {
+        return Err(DecodeError::Checksum);
+    }
+    Ok(NecCommand::Press {
+        address: u16::from_le_bytes([address_low, address_high]),
+        command,
+    })
+}
+
+/// Most pulses of an RC5 frame
+pub const RC5_MAX_PULSES: usize = 28;
+/// Half of an RC5 bit, the Manchester code changes level in the middle of every bit
+pub const RC5_HALF_BIT_US: PulseUs = 889;
+
+/// Encodes an RC5 frame of a 5 bit address and a 7 bit command
+///
+/// Commands from 64 on are sent as RC5X, with the second start bit inverted. `toggle` has to
+/// change with every key press and stay the same while a key is held, so the receiver can tell
+/// a new press from a repeated frame.
+pub fn rc5(address: u8, command: u8, toggle: bool) -> PulseTrain {
+    let start = 0b10 | ((command >> 6 & 1) ^ 1) as u16;
+    let bits = start << 12
+        | (toggle as u16) << 11
+        | ((address & 0x1f) as u16) << 6
+        | (command & 0x3f) as u16;
+
+    let mut train = PulseTrain::new();
+    let mut mark = true;
+    let mut len = 0;
+    // Most significant bit first, a one is a space and then a mark, a zero the other way round
+    for i in (0..14).rev() {
+        let one = bits >> i & 1 != 0;
+        for &half_mark in &[!one, one] {
+            if half_mark != mark {
+                // The space of the first start bit can't be sent, the line is idle before it
+                if len == 0 {
+                    continue;
+                }
+                train.push(len);
+                mark = half_mark;
+                len = 0;
+            }
+            len += RC5_HALF_BIT_US;
+        }
+    }
+    if mark {
+        train.push(len);
+    }
+
+    train
+}
+
+/// Enables the IR clock, divided from XCLK to about 2 MHz, and returns its frequency
+fn enable_clock(clocks: &Clocks) -> u32 {
+    let hbn = unsafe { &*pac::HBN::ptr() };
+    let xclk = if hbn.hbn_glb.read().hbn_root_clk_sel().bits() & 1 != 0 {
+        clocks.xtal_freq().map_or(0, |freq| freq.0)
+    } else {
+        0
+    };
+    let xclk = if xclk == 0 { RC32M } else { xclk };
+    let div = ((xclk + IR_CLK_TARGET / 2) / IR_CLK_TARGET).max(1).min(64);
+
+    unsafe { &*pac::GLB::ptr() }
+        .clk_cfg2
+        .modify(|_, w| unsafe { w.ir_clk_div().bits((div - 1) as u8).ir_clk_en().set_bit() });
+
+    xclk / div
+}
+
+/// Returns the carrier's on and off phases in IR clock cycles, each 1 to 256
+fn carrier_phases(ir_clk: u32, carrier: u32, duty_percent: u8) -> Result<(u32, u32), Error> {
+    if carrier == 0 || !(1..100).contains(&duty_percent) {
+        return Err(Error::Carrier);
+    }
+    let period = (ir_clk + carrier / 2) / carrier;
+    let on = (period * duty_percent as u32 + 50) / 100;
+    let off = period.saturating_sub(on);
+    if (1..=256).contains(&on) && (1..=256).contains(&off) {
+        Ok((on, off))
+    } else {
+        Err(Error::Carrier)
+    }
+}
+
+/// Encodes `pulses` into `widths` and returns the pulse unit in IR clock cycles
+fn encode(
+    pulses: &[PulseUs],
+    ir_clk: u32,
+    widths: &mut [u32; MAX_PULSES / 8],
+) -> Result<u32, Error> {
+    if pulses.is_empty() || pulses.len() > MAX_PULSES {
+        return Err(Error::Length);
+    }
+    let (shortest_index, &shortest) = pulses
+        .iter()
+        .enumerate()
+        .min_by_key(|&(_, pulse)| pulse)
+        .unwrap();
+    let unit = (shortest as u64 * ir_clk as u64 / 1_000_000) as u32;
+    if shortest == 0 || unit == 0 || unit > IRTX_PW_UNIT_MAX {
+        return Err(Error::PulseWidth(shortest_index));
+    }
+
+    for word in widths.iter_mut() {
+        *word = 0;
+    }
+    for (i, &pulse) in pulses.iter().enumerate() {
+        let units = pulse.saturating_add(shortest / 2) / shortest;
+        if units > MAX_UNITS {
+            return Err(Error::PulseWidth(i));
+        }
+        let error = (units * shortest).max(pulse) - (units * shortest).min(pulse);
+        if error > pulse / 8 {
+            return Err(Error::PulseWidth(i));
+        }
+        widths[i / 8] |= (units - 1) << (i % 8 * 4);
+    }
+
+    Ok(unit)
+}
+
+fn reg(offset: usize) -> *mut u32 {
+    (pac::IR::ptr() as usize + offset) as *mut u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn nec() {
+        // Address 0x04, 0xfb, command 0x08, 0xf7, least significant bit first
+        let frame = super::nec(0x04, 0x08);
+        assert_eq!(frame.len(), NEC_PULSES);
+        assert_eq!(frame[..2], [9000, 4500]);
+        let bits = 0xf708_fb04u32;
+        for i in 0..32 {
+            let space = if bits >> i & 1 != 0 { 1687 } else { 562 };
+            assert_eq!(frame[3 + 2 * i], space, "bit {}", i);
+        }
+        assert!((0..33).all(|i| frame[2 + 2 * i] == 562));
+        assert_eq!(
+            frame.iter().sum::<u32>(),
+            13_500 + 33 * 562 + 16 * 1687 + 16 * 562
+        );
+
+        assert_eq!(nec_extended(0xfb04, 0x08), frame);
+        assert_ne!(nec_extended(0x1234, 0x08), frame);
+        assert_eq!(*nec_repeat(), [9000, 2250, 562]);
+    }
+
+    #[test]
+    fn rc5() {
+        // Start bits 1 1, toggle 0 and 11 zeros: the space of the first half bit is left out, the
+        // marks of the second start bit and the toggle bit merge, and the last space is left out
+        let frame = super::rc5(0, 0, false);
+        assert_eq!(frame.len(), 25);
+        assert_eq!(frame[..4], [889, 889, 1778, 889]);
+        assert!(frame[4..].iter().all(|&pulse| pulse == 889));
+
+        // 1 1 1 00101 001100: every change between the bits merges two half bits
+        assert_eq!(
+            *super::rc5(5, 12, true),
+            [
+                889, 889, 889, 889, 1778, 889, 889, 1778, 1778, 1778, 1778, 889, 889, 1778, 889,
+                889, 1778, 889, 889,
+            ]
+        );
+
+        // RC5X sends the seventh command bit inverted in the second start bit
+        assert_eq!(super::rc5(0, 64, false)[..2], [1778, 889]);
+    }
+
+    /// Returns `frame` with marks `by` µs longer and spaces `by` µs shorter, as a receiver
+    /// module passes them on
+    fn distort(frame: &[PulseUs], by: PulseUs) -> Vec<PulseUs> {
+        frame
+            .iter()
+            .enumerate()
+            .map(|(i, &sent)| if i % 2 == 0 { sent + by } else { sent - by })
+            .collect()
+    }
+
+    #[test]
+    fn decode() {
+        let press = NecCommand::Press {
+            address: 0xfb04,
+            command: 0x08,
+        };
+        let frame = distort(&super::nec(0x04, 0x08), 80);
+        assert_eq!(decode_nec(&frame), Ok(press));
+        // The edge of the tolerance
+        assert_eq!(
+            decode_nec(&distort(&super::nec(0x04, 0x08), 150)),
+            Ok(press)
+        );
+        assert_eq!(
+            decode_nec(&distort(&nec_extended(0x1234, 0x56), 80)),
+            Ok(NecCommand::Press {
+                address: 0x1234,
+                command: 0x56,
+            })
+        );
+        assert_eq!(
+            decode_nec(&distort(&nec_repeat(), 80)),
+            Ok(NecCommand::Repeat)
+        );
+
+        let mut bad_leader = frame.clone();
+        bad_leader[1] = 2250;
+        assert_eq!(decode_nec(&bad_leader), Err(DecodeError::Leader));
+        let mut bad_bit = frame.clone();
+        // The space of bit 5, between a zero and a one
+        bad_bit[13] = 1100;
+        assert_eq!(decode_nec(&bad_bit), Err(DecodeError::Pulse(13)));
+        let mut bad_mark = frame.clone();
+        bad_mark[20] = 1200;
+        assert_eq!(decode_nec(&bad_mark), Err(DecodeError::Pulse(20)));
+        let mut bad_inverse = frame.clone();
+        // Bit 24 is the lowest bit of the inverse of the command, 1 for 0x08
+        bad_inverse[51] = 482;
+        assert_eq!(decode_nec(&bad_inverse), Err(DecodeError::Checksum));
+        assert_eq!(decode_nec(&frame[..66]), Err(DecodeError::Length));
+        assert_eq!(decode_nec(&[]), Err(DecodeError::Length));
+    }
+
+    #[test]
+    fn encode_pulses() {
+        let mut widths = [0; MAX_PULSES / 8];
+        assert_eq!(
+            encode(&[500, 1000, 1500], IR_CLK_TARGET, &mut widths),
+            Ok(1000)
+        );
+        assert_eq!(widths[0], 0x210);
+
+        assert_eq!(encode(&[], IR_CLK_TARGET, &mut widths), Err(Error::Length));
+        let long = [500; MAX_PULSES + 1];
+        assert_eq!(
+            encode(&long, IR_CLK_TARGET, &mut widths),
+            Err(Error::Length)
+        );
+        // More than 16 units, and too far from a whole number of units
+        assert_eq!(
+            encode(&[500, 9000], IR_CLK_TARGET, &mut widths),
+            Err(Error::PulseWidth(1))
+        );
+        assert_eq!(
+            encode(&[500, 700], IR_CLK_TARGET, &mut widths),
+            Err(Error::PulseWidth(1))
+        );
+        assert_eq!(
+            encode(&[0, 500], IR_CLK_TARGET, &mut widths),
+            Err(Error::PulseWidth(0))
+        );
+    }
+}
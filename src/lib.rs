@@ -26,24 +26,70 @@
 //!   "-C", "link-arg=-Tmemory.x",
 //!   "-C", "link-arg=-Tlink.x",
 //!   "-C", "link-arg=-Thal_defaults.x",
+//!   # Only with statics declared with `retained!`, needs an `HBN_RAM` region in memory.x
+//!   "-C", "link-arg=-Thbn_ram.x",
 //! ]
 //! ```
 //!
+//! [`retained!`](crate::retained) statics are only kept through hibernate with `hbn_ram.x`, see
+//! [`hbn::retention`] for the `HBN_RAM` region it needs. Without it they don't link.
+//!
 
 #![no_std]
 
+#[cfg(any(test, feature = "mock-registers"))]
+extern crate std;
+
 pub use bl602_pac as pac;
 
+pub mod adc;
+pub mod aes;
+#[cfg(feature = "at-parser")]
+pub mod at;
+pub mod audio;
+pub mod bus;
 pub mod checksum;
 pub mod clock;
+pub mod crc;
+pub mod debug;
 pub mod delay;
+pub(crate) mod dma;
+pub mod efuse;
+pub mod flash;
 pub mod gpio;
+#[cfg(feature = "hart")]
+pub mod hart;
+pub mod hbn;
 pub mod i2c;
+#[cfg(feature = "init-helpers")]
+pub mod init;
 pub mod interrupts;
+pub mod ir;
+pub mod memory;
+pub mod mtimer;
+pub mod p256;
+pub mod pds;
+pub mod pka;
+pub mod power;
+#[cfg(not(feature = "mock-registers"))]
+pub(crate) mod regs;
+#[cfg(feature = "mock-registers")]
+pub mod regs;
+pub mod rng;
+pub mod rom;
 pub mod rtc;
+pub mod sec_eng;
+pub mod secure;
 pub mod serial;
+pub mod sha;
 pub mod spi;
+pub mod sync;
 pub mod timer;
+pub mod timing;
+pub mod touch;
+pub mod trap;
+#[cfg(feature = "uart-logger")]
+pub mod uart_logger;
 pub mod watchdog;
 pub mod pwm;
 
@@ -0,0 +1,121 @@
+/*!
+  # Memory layout
+  Code runs from the XIP flash at `0x2300_0000` through the 32 KiB L1 cache, while the 64 KiB of
+  tightly coupled memory (TCM) next to the core are read and written in a single cycle. The
+  `memory.x` of this repository splits the TCM like this:
+
+  | Region | Address       | Size   | Contents                                 |
+  |--------|---------------|--------|------------------------------------------|
+  | ITCM   | `0x2201_0000` | 16 KiB | `.data`, `.bss`, [`ram_function!`] code  |
+  | DTCM   | `0x2201_4000` | 48 KiB | heap, stack                              |
+
+  ```text
+    REGION_ALIAS("REGION_DATA", ITCM);
+    REGION_ALIAS("REGION_BSS", ITCM);
+    REGION_ALIAS("REGION_HEAP", DTCM);
+    REGION_ALIAS("REGION_STACK", DTCM);
+  ```
+
+  # RAM functions
+  Functions declared with [`ram_function!`] are placed in a `.data.tcm_code` section. `riscv-rt`
+  links all `.data.*` sections into `.data`, stores its initial content in flash and copies it
+  to `REGION_DATA` before `main`, so the code is in ITCM from the start without a linker script
+  or startup code of its own. Calling such a function saves the cache misses of the flash, and
+  it keeps running while the flash can't be read, e.g. while it's erased or programmed or while
+  its clock is switched.
+
+  The remaining code isn't moved along: anything a RAM function calls runs from flash, unless
+  it's inlined or a RAM function as well. A RAM function which must not touch the flash at all
+  can only use `#[inline(always)]` functions and other RAM functions, which includes the
+  register accessors of the PAC, but not a `panic!` with a message or a
+  [`debug_println!`](crate::debug_println).
+
+  ## Example
+  ```rust
+    bl602_hal::ram_function! {
+        /// Filters one sample, called 10000 times a second
+        fn filter(state: &mut [i32; 4], sample: i32) -> i32 {
+            // ...
+        }
+    }
+
+    let free = bl602_hal::memory::tcm_free();
+  ```
+*/
+
+/// Start of the instruction TCM
+pub const ITCM_START: usize = 0x2201_0000;
+/// Size of the instruction TCM in bytes
+pub const ITCM_SIZE: usize = 16 * 1024;
+/// Start of the data TCM
+pub const DTCM_START: usize = 0x2201_4000;
+/// Size of the data TCM in bytes
+pub const DTCM_SIZE: usize = 48 * 1024;
+
+extern "C" {
+    // End of `.bss`, defined by the `link.x` of `riscv-rt`
+    static _ebss: u8;
+}
+
+/// Returns how many bytes of the ITCM are left after `.data`, `.bss` and the RAM functions
+///
+/// Returns 0 if `.bss` doesn't end in the ITCM, i.e. with a `memory.x` which places
+/// `REGION_BSS` elsewhere. The DTCM is shared by the heap and the stack and isn't counted.
+pub fn tcm_free() -> usize {
+    let bss_end = unsafe { &_ebss as *const u8 as usize };
+    let itcm_end = ITCM_START + ITCM_SIZE;
+
+    if bss_end >= ITCM_START && bss_end <= itcm_end {
+        itcm_end - bss_end
+    } else {
+        0
+    }
+}
+
+/// Returns whether `address` is in the ITCM or DTCM, e.g. the address of a function declared
+/// with [`ram_function!`]
+pub fn is_in_tcm(address: usize) -> bool {
+    address >= ITCM_START && address < DTCM_START + DTCM_SIZE
+}
+
+/// Machine code of `wfi; ret`, in ITCM like the [`ram_function!`]s
+#[link_section = ".data.tcm_code"]
+static WFI_SHIM: [u32; 2] = [0x1050_0073, 0x0000_8067];
+
+/// Waits for an interrupt like `riscv::asm::wfi`, without fetching from flash
+///
+/// `riscv` is used without its `inline-asm` feature, so its `wfi` and CSR reads are calls into
+/// its prebuilt library in flash. RAM functions which run while the flash is unclocked call this instead.
+#[inline(always)]
+pub(crate) unsafe fn ram_wfi() {
+    let shim: extern "C" fn() = core::mem::transmute(&WFI_SHIM as *const [u32; 2] as *const ());
+    shim()
+}
+
+/// Machine code of `csrr a0, mcycle; ret`
+#[link_section = ".data.tcm_code"]
+static MCYCLE_SHIM: [u32; 2] = [0xb000_2573, 0x0000_8067];
+
+/// Returns the low half of `mcycle` like `mcycle::read`, without fetching from flash
+#[inline(always)]
+pub(crate) unsafe fn ram_mcycle() -> u32 {
+    let shim: extern "C" fn() -> u32 =
+        core::mem::transmute(&MCYCLE_SHIM as *const [u32; 2] as *const ());
+    shim()
+}
+
+/// Declares functions which run from ITCM instead of flash
+///
+/// Takes any number of function items, including `unsafe` and `extern "C"` ones, and places each
+/// in the `.data.tcm_code` section, see [`memory`](crate::memory#ram-functions). They are never
+/// inlined, so the code stays in RAM no matter where they're called from.
+#[macro_export]
+macro_rules! ram_function {
+    ($($item:item)+) => {
+        $(
+            #[inline(never)]
+            #[link_section = ".data.tcm_code"]
+            $item
+        )+
+    };
+}
@@ -0,0 +1,190 @@
+/*!
+  # Machine timer
+  The CLINT compatible machine timer of the core: the 64 bit counter `mtime`, which counts at
+  [`MTIME_FREQ`] once started, and the compare register `mtimecmp`. Its interrupt is pending
+  while `mtime` is at or past `mtimecmp`.
+
+  [`MTimer::start_periodic`] turns it into a system tick. The interrupt is handled by the HAL's
+  trap handler, which moves the compare value on by one period and then calls the given
+  handler. The next compare value is computed from the previous one, not from the time the
+  interrupt is handled, so a late handler doesn't shift the ticks after it.
+
+  ## Example
+  ```rust
+    use bl602_hal::mtimer::MTimer;
+
+    let mut mtimer = MTimer::take(&clocks).unwrap();
+    mtimer.start_periodic(1000.Hz(), on_tick).unwrap();
+
+    fn on_tick() {
+        // runs every millisecond
+    }
+  ```
+
+  [`power::sleep_until`](crate::power::sleep_until) borrows the compare register while it
+  waits. A tick which falls into the wait is taken right after it, and the next one is on time
+  again.
+*/
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embedded_time::rate::Hertz;
+
+use crate::clock::Clocks;
+use crate::interrupts::{set_irq_enabled, MTIMER_IRQ};
+use crate::power;
+use crate::sync::SpinLock;
+
+pub use crate::power::MTIME_FREQ;
+
+// CLINT compatible machine timer registers
+const MTIMECMP_ADDR: usize = 0x0200_4000;
+const MTIME_ADDR: usize = 0x0200_bff8;
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Handler and period of the running periodic tick
+static PERIODIC: SpinLock<Option<Periodic>> = SpinLock::new(None);
+
+#[derive(Copy, Clone)]
+struct Periodic {
+    handler: fn(),
+    period: u64,
+}
+
+/// Machine timer error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The frequency is 0 or above [`MTIME_FREQ`]
+    InvalidFrequency,
+}
+
+/// The machine timer, see the module documentation
+pub struct MTimer {
+    _ownership: (),
+}
+
+impl MTimer {
+    /// Starts `mtime` and returns the machine timer, `None` if it has been taken before
+    ///
+    /// `mtime` is started with [`power::start_mtime`], which has to be called again after
+    /// changing the system clock.
+    pub fn take(clocks: &Clocks) -> Option<Self> {
+        if TAKEN.swap(true, Ordering::AcqRel) {
+            return None;
+        }
+
+        power::start_mtime(clocks);
+        Some(MTimer { _ownership: () })
+    }
+
+    /// Returns the current value of `mtime`
+    pub fn now(&self) -> u64 {
+        read_mtime()
+    }
+
+    /// Returns the compare value
+    pub fn compare(&self) -> u64 {
+        read_mtimecmp()
+    }
+
+    /// Sets the compare value, the interrupt is pending from when `mtime` reaches it
+    pub fn set_compare(&mut self, value: u64) {
+        write_mtimecmp(value);
+    }
+
+    /// Calls `handler` from the machine timer interrupt `freq` times per second
+    ///
+    /// The first tick is one period from now. A running tick is replaced. `freq` is rounded to a
+    /// whole number of `mtime` ticks per period, e.g. 3 Hz ticks every 333333 µs.
+    pub fn start_periodic(&mut self, freq: impl Into<Hertz>, handler: fn()) -> Result<(), Error> {
+        let freq = freq.into().0;
+        if freq == 0 || freq > MTIME_FREQ {
+            return Err(Error::InvalidFrequency);
+        }
+        let period = (MTIME_FREQ / freq) as u64;
+
+        set_irq_enabled(MTIMER_IRQ, false);
+        *PERIODIC.lock_irq_disabled() = Some(Periodic { handler, period });
+        write_mtimecmp(read_mtime() + period);
+        set_irq_enabled(MTIMER_IRQ, true);
+
+        Ok(())
+    }
+
+    /// Stops the periodic tick
+    pub fn stop(&mut self) {
+        set_irq_enabled(MTIMER_IRQ, false);
+        *PERIODIC.lock_irq_disabled() = None;
+        write_mtimecmp(u64::MAX);
+    }
+
+    /// Returns whether a periodic tick is running
+    pub fn is_periodic(&self) -> bool {
+        PERIODIC.lock_irq_disabled().is_some()
+    }
+}
+
+/// Handles the machine timer interrupt, called by the trap handler
+pub(crate) fn on_interrupt() {
+    let periodic = *PERIODIC.lock_irq_disabled();
+
+    match periodic {
+        Some(Periodic { handler, period }) => {
+            // Ticks missed while interrupts were disabled for longer than a period are dropped,
+            // the following ones stay on the original schedule
+            let now = read_mtime();
+            let mut next = read_mtimecmp() + period;
+            if next <= now {
+                next += (now - next) / period * period + period;
+            }
+            write_mtimecmp(next);
+
+            handler();
+        }
+        // Nobody asked for the interrupt, keep it from being taken again and again
+        None => {
+            set_irq_enabled(MTIMER_IRQ, false);
+            write_mtimecmp(u64::MAX);
+        }
+    }
+}
+
+/// Reads the 64 bit `mtime`, which is accessed as two halves
+pub(crate) fn read_mtime() -> u64 {
+    let low = MTIME_ADDR as *const u32;
+    let high = (MTIME_ADDR + 4) as *const u32;
+
+    // Read until the high half didn't change, so a carry between the reads isn't missed
+    loop {
+        let h = unsafe { high.read_volatile() };
+        let l = unsafe { low.read_volatile() };
+        if unsafe { high.read_volatile() } == h {
+            return ((h as u64) << 32) | l as u64;
+        }
+    }
+}
+
+pub(crate) fn read_mtimecmp() -> u64 {
+    let low = MTIMECMP_ADDR as *const u32;
+    let high = (MTIMECMP_ADDR + 4) as *const u32;
+
+    unsafe { ((high.read_volatile() as u64) << 32) | low.read_volatile() as u64 }
+}
+
+pub(crate) fn write_mtimecmp(value: u64) {
+    let low = MTIMECMP_ADDR as *mut u32;
+    let high = (MTIMECMP_ADDR + 4) as *mut u32;
+
+    // RV32 writes the compare register one half at a time, and the timer compares after each
+    // write. Writing the new low half next to the old high half can give a value below `mtime`,
+    // e.g. going from 0x1_ffff_fff0 to 0x2_0000_0010 passes through 0x1_0000_0010, which raises
+    // an interrupt nobody asked for. Setting the low half to its maximum first keeps every
+    // intermediate value at or above both the old and the new one, so they can't trigger early.
+    unsafe {
+        low.write_volatile(u32::MAX);
+        high.write_volatile((value >> 32) as u32);
+        low.write_volatile(value as u32);
+    }
+}
@@ -0,0 +1,498 @@
+/*!
+  # NIST P-256
+  ECDSA signature verification over the P-256 curve (secp256r1), with the field arithmetic done
+  by the [`Pka`]. Points are kept in Jacobian coordinates, so only a single inversion is needed
+  per scalar multiplication.
+
+  Scalar multiplication is *not* constant time. That is fine for verification, where all inputs
+  are public, but [`mul`] must not be used with secret scalars, e.g. to derive a public key or
+  for ECDH.
+
+  Numbers are taken as 32 byte big endian arrays, as in SEC 1 and most signature formats.
+
+  ## Example
+  ```rust
+    use bl602_hal::p256::{self, AffinePoint};
+    use bl602_hal::pka::{Pka, WordOrder};
+
+    // Any word order works, the functions here switch to the one they need
+    let mut pka = Pka::new(sec_eng.pka, &mut parts.clk_cfg, WordOrder::LeastSignificantFirst);
+    let public_key = AffinePoint::from_coordinates(&x, &y);
+
+    // `hash` is the SHA-256 digest of the signed message
+    p256::verify(&mut pka, &public_key, &hash, &r, &s).unwrap();
+  ```
+*/
+
+use crate::pka::{self, OperandSize, Pka, WordOrder};
+
+/// Field element or scalar, least significant word first
+type Word8 = [u32; 8];
+
+/// The field prime p
+const P: Word8 = [
+    0xffff_ffff,
+    0xffff_ffff,
+    0xffff_ffff,
+    0x0000_0000,
+    0x0000_0000,
+    0x0000_0000,
+    0x0000_0001,
+    0xffff_ffff,
+];
+
+/// The group order n
+const N: Word8 = [
+    0xfc63_2551,
+    0xf3b9_cac2,
+    0xa717_9e84,
+    0xbce6_faad,
+    0xffff_ffff,
+    0xffff_ffff,
+    0x0000_0000,
+    0xffff_ffff,
+];
+
+/// The curve coefficient b, a is -3
+const B: Word8 = [
+    0x27d2_604b,
+    0x3bce_3c3e,
+    0xcc53_b0f6,
+    0x651d_06b0,
+    0x7698_86bc,
+    0xb3eb_bd55,
+    0xaa3a_93e7,
+    0x5ac6_35d8,
+];
+
+/// x coordinate of the generator
+const GX: Word8 = [
+    0xd898_c296,
+    0xf4a1_3945,
+    0x2deb_33a0,
+    0x7703_7d81,
+    0x63a4_40f2,
+    0xf8bc_e6e5,
+    0xe12c_4247,
+    0x6b17_d1f2,
+];
+
+/// y coordinate of the generator
+const GY: Word8 = [
+    0x37bf_51f5,
+    0xcbb6_4068,
+    0x6b31_5ece,
+    0x2bce_3357,
+    0x7c0f_9e16,
+    0x8ee7_eb4a,
+    0xfe1a_7f9b,
+    0x4fe3_42e2,
+];
+
+const ZERO: Word8 = [0; 8];
+const ONE: Word8 = [1, 0, 0, 0, 0, 0, 0, 0];
+
+/// P-256 error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The PKA reported an error
+    Pka(pka::Error),
+    /// The point is not on the curve
+    InvalidPoint,
+    /// `r` or `s` is out of range, or the signature doesn't match
+    InvalidSignature,
+}
+
+impl From<pka::Error> for Error {
+    fn from(error: pka::Error) -> Self {
+        Error::Pka(error)
+    }
+}
+
+/// A point on the curve in affine coordinates
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct AffinePoint {
+    x: Word8,
+    y: Word8,
+}
+
+impl AffinePoint {
+    /// The generator of the curve
+    pub fn generator() -> Self {
+        AffinePoint { x: GX, y: GY }
+    }
+
+    /// Creates a point from its big endian coordinates
+    ///
+    /// The coordinates are not validated here, [`verify`] checks that the point lies on the
+    /// curve before using it.
+    pub fn from_coordinates(x: &[u8; 32], y: &[u8; 32]) -> Self {
+        AffinePoint {
+            x: from_be_bytes(x),
+            y: from_be_bytes(y),
+        }
+    }
+
+    /// Returns the big endian x coordinate
+    pub fn x(&self) -> [u8; 32] {
+        to_be_bytes(&self.x)
+    }
+
+    /// Returns the big endian y coordinate
+    pub fn y(&self) -> [u8; 32] {
+        to_be_bytes(&self.y)
+    }
+}
+
+/// A point in Jacobian coordinates, `z == 0` is the point at infinity
+#[derive(Copy, Clone)]
+struct JacobianPoint {
+    x: Word8,
+    y: Word8,
+    z: Word8,
+}
+
+impl JacobianPoint {
+    const INFINITY: JacobianPoint = JacobianPoint {
+        x: ONE,
+        y: ONE,
+        z: ZERO,
+    };
+
+    fn from_affine(point: &AffinePoint) -> Self {
+        JacobianPoint {
+            x: point.x,
+            y: point.y,
+            z: ONE,
+        }
+    }
+
+    fn is_infinity(&self) -> bool {
+        self.z == ZERO
+    }
+}
+
+/// Verifies an ECDSA signature `(r, s)` over the message digest `hash`
+///
+/// `hash` must be a 256 bit digest, e.g. SHA-256. Returns [`Error::InvalidSignature`] if the
+/// signature doesn't match.
+pub fn verify(
+    pka: &mut Pka,
+    public_key: &AffinePoint,
+    hash: &[u8; 32],
+    r: &[u8; 32],
+    s: &[u8; 32],
+) -> Result<(), Error> {
+    let mut field = Field::new(pka);
+
+    let r = from_be_bytes(r);
+    let s = from_be_bytes(s);
+    if r == ZERO || s == ZERO || !less_than(&r, &N) || !less_than(&s, &N) {
+        return Err(Error::InvalidSignature);
+    }
+    if !field.is_on_curve(public_key)? {
+        return Err(Error::InvalidPoint);
+    }
+
+    // u1 = z / s, u2 = r / s (mod n)
+    let z = field.scalar_reduce(&from_be_bytes(hash))?;
+    let w = field.scalar_inv(&s)?;
+    let u1 = field.scalar_mul(&z, &w)?;
+    let u2 = field.scalar_mul(&r, &w)?;
+
+    let point = field.double_mul(&u1, &AffinePoint::generator(), &u2, public_key)?;
+    if point.is_infinity() {
+        return Err(Error::InvalidSignature);
+    }
+
+    let x = field.to_affine(&point)?.x;
+    // x is below p, which is less than 2n, so one conditional subtraction reduces it
+    let x = if less_than(&x, &N) { x } else { sub(&x, &N) };
+
+    if x == r {
+        Ok(())
+    } else {
+        Err(Error::InvalidSignature)
+    }
+}
+
+/// Computes `k * point`
+///
+/// Not constant time, see the module documentation. Returns `None` for the point at infinity.
+pub fn mul(pka: &mut Pka, k: &[u8; 32], point: &AffinePoint) -> Result<Option<AffinePoint>, Error> {
+    let mut field = Field::new(pka);
+    if !field.is_on_curve(point)? {
+        return Err(Error::InvalidPoint);
+    }
+
+    let result = field.double_mul(&from_be_bytes(k), point, &ZERO, point)?;
+
+    if result.is_infinity() {
+        Ok(None)
+    } else {
+        field.to_affine(&result).map(Some)
+    }
+}
+
+/// Field and scalar arithmetic on the PKA
+struct Field<'a> {
+    pka: &'a mut Pka,
+    order: WordOrder,
+}
+
+impl<'a> Field<'a> {
+    /// Switches the PKA to the word order used here, the previous one is restored on drop
+    fn new(pka: &'a mut Pka) -> Self {
+        let order = pka.word_order();
+        pka.set_word_order(WordOrder::LeastSignificantFirst);
+
+        Field { pka, order }
+    }
+
+    fn mul(&mut self, a: &Word8, b: &Word8) -> Result<Word8, Error> {
+        let mut out = ZERO;
+        self.pka.mod_mul(OperandSize::Bits256, a, b, &P, &mut out)?;
+        Ok(out)
+    }
+
+    fn add(&mut self, a: &Word8, b: &Word8) -> Result<Word8, Error> {
+        let mut out = ZERO;
+        self.pka.mod_add(OperandSize::Bits256, a, b, &P, &mut out)?;
+        Ok(out)
+    }
+
+    fn sub(&mut self, a: &Word8, b: &Word8) -> Result<Word8, Error> {
+        let mut out = ZERO;
+        self.pka.mod_sub(OperandSize::Bits256, a, b, &P, &mut out)?;
+        Ok(out)
+    }
+
+    fn square(&mut self, a: &Word8) -> Result<Word8, Error> {
+        self.mul(a, a)
+    }
+
+    /// Multiplies by a small constant
+    fn mul_small(&mut self, a: &Word8, k: u32) -> Result<Word8, Error> {
+        self.mul(a, &[k, 0, 0, 0, 0, 0, 0, 0])
+    }
+
+    fn inv(&mut self, a: &Word8) -> Result<Word8, Error> {
+        let mut out = ZERO;
+        self.pka.mod_inv(OperandSize::Bits256, a, &P, &mut out)?;
+        Ok(out)
+    }
+
+    fn scalar_mul(&mut self, a: &Word8, b: &Word8) -> Result<Word8, Error> {
+        let mut out = ZERO;
+        self.pka.mod_mul(OperandSize::Bits256, a, b, &N, &mut out)?;
+        Ok(out)
+    }
+
+    fn scalar_inv(&mut self, a: &Word8) -> Result<Word8, Error> {
+        let mut out = ZERO;
+        self.pka.mod_inv(OperandSize::Bits256, a, &N, &mut out)?;
+        Ok(out)
+    }
+
+    fn scalar_reduce(&mut self, a: &Word8) -> Result<Word8, Error> {
+        let mut out = ZERO;
+        self.pka.mod_reduce(OperandSize::Bits256, a, &N, &mut out)?;
+        Ok(out)
+    }
+
+    /// Checks `y^2 = x^3 - 3x + b` with both coordinates in range
+    fn is_on_curve(&mut self, point: &AffinePoint) -> Result<bool, Error> {
+        if !less_than(&point.x, &P) || !less_than(&point.y, &P) {
+            return Ok(false);
+        }
+
+        let y2 = self.square(&point.y)?;
+        let x2 = self.square(&point.x)?;
+        let x3 = self.mul(&x2, &point.x)?;
+        let three_x = self.mul_small(&point.x, 3)?;
+        let rhs = self.sub(&x3, &three_x)?;
+        let rhs = self.add(&rhs, &B)?;
+
+        Ok(y2 == rhs)
+    }
+
+    fn to_affine(&mut self, point: &JacobianPoint) -> Result<AffinePoint, Error> {
+        let z_inv = self.inv(&point.z)?;
+        let z_inv2 = self.square(&z_inv)?;
+        let z_inv3 = self.mul(&z_inv2, &z_inv)?;
+
+        Ok(AffinePoint {
+            x: self.mul(&point.x, &z_inv2)?,
+            y: self.mul(&point.y, &z_inv3)?,
+        })
+    }
+
+    /// Point doubling for a = -3 ("dbl-2001-b")
+    fn double(&mut self, p: &JacobianPoint) -> Result<JacobianPoint, Error> {
+        if p.is_infinity() {
+            return Ok(*p);
+        }
+
+        let delta = self.square(&p.z)?;
+        let gamma = self.square(&p.y)?;
+        let beta = self.mul(&p.x, &gamma)?;
+
+        // alpha = 3 * (x - delta) * (x + delta)
+        let t0 = self.sub(&p.x, &delta)?;
+        let t1 = self.add(&p.x, &delta)?;
+        let t0 = self.mul(&t0, &t1)?;
+        let alpha = self.mul_small(&t0, 3)?;
+
+        // x3 = alpha^2 - 8 * beta
+        let t0 = self.square(&alpha)?;
+        let beta8 = self.mul_small(&beta, 8)?;
+        let x3 = self.sub(&t0, &beta8)?;
+
+        // z3 = (y + z)^2 - gamma - delta
+        let t0 = self.add(&p.y, &p.z)?;
+        let t0 = self.square(&t0)?;
+        let t0 = self.sub(&t0, &gamma)?;
+        let z3 = self.sub(&t0, &delta)?;
+
+        // y3 = alpha * (4 * beta - x3) - 8 * gamma^2
+        let t0 = self.mul_small(&beta, 4)?;
+        let t0 = self.sub(&t0, &x3)?;
+        let t0 = self.mul(&alpha, &t0)?;
+        let t1 = self.square(&gamma)?;
+        let t1 = self.mul_small(&t1, 8)?;
+        let y3 = self.sub(&t0, &t1)?;
+
+        Ok(JacobianPoint {
+            x: x3,
+            y: y3,
+            z: z3,
+        })
+    }
+
+    /// General point addition ("add-1998-cmo-2")
+    fn add_points(&mut self, p: &JacobianPoint, q: &JacobianPoint) -> Result<JacobianPoint, Error> {
+        if p.is_infinity() {
+            return Ok(*q);
+        }
+        if q.is_infinity() {
+            return Ok(*p);
+        }
+
+        let z1z1 = self.square(&p.z)?;
+        let z2z2 = self.square(&q.z)?;
+        let u1 = self.mul(&p.x, &z2z2)?;
+        let u2 = self.mul(&q.x, &z1z1)?;
+        let t0 = self.mul(&p.y, &z2z2)?;
+        let s1 = self.mul(&t0, &q.z)?;
+        let t0 = self.mul(&q.y, &z1z1)?;
+        let s2 = self.mul(&t0, &p.z)?;
+
+        let h = self.sub(&u2, &u1)?;
+        let r = self.sub(&s2, &s1)?;
+        if h == ZERO {
+            return if r == ZERO {
+                self.double(p)
+            } else {
+                Ok(JacobianPoint::INFINITY)
+            };
+        }
+
+        let h2 = self.square(&h)?;
+        let h3 = self.mul(&h2, &h)?;
+        let u1h2 = self.mul(&u1, &h2)?;
+
+        // x3 = r^2 - h^3 - 2 * u1 * h^2
+        let t0 = self.square(&r)?;
+        let t0 = self.sub(&t0, &h3)?;
+        let t1 = self.add(&u1h2, &u1h2)?;
+        let x3 = self.sub(&t0, &t1)?;
+
+        // y3 = r * (u1 * h^2 - x3) - s1 * h^3
+        let t0 = self.sub(&u1h2, &x3)?;
+        let t0 = self.mul(&r, &t0)?;
+        let t1 = self.mul(&s1, &h3)?;
+        let y3 = self.sub(&t0, &t1)?;
+
+        // z3 = z1 * z2 * h
+        let t0 = self.mul(&p.z, &q.z)?;
+        let z3 = self.mul(&t0, &h)?;
+
+        Ok(JacobianPoint {
+            x: x3,
+            y: y3,
+            z: z3,
+        })
+    }
+
+    /// Computes `k1 * p1 + k2 * p2` with Shamir's trick
+    fn double_mul(
+        &mut self,
+        k1: &Word8,
+        p1: &AffinePoint,
+        k2: &Word8,
+        p2: &AffinePoint,
+    ) -> Result<JacobianPoint, Error> {
+        let p1 = JacobianPoint::from_affine(p1);
+        let p2 = JacobianPoint::from_affine(p2);
+        let sum = self.add_points(&p1, &p2)?;
+
+        let mut result = JacobianPoint::INFINITY;
+        for bit in (0..256).rev() {
+            result = self.double(&result)?;
+
+            let b1 = (k1[bit / 32] >> (bit % 32)) & 1 == 1;
+            let b2 = (k2[bit / 32] >> (bit % 32)) & 1 == 1;
+            result = match (b1, b2) {
+                (true, true) => self.add_points(&result, &sum)?,
+                (true, false) => self.add_points(&result, &p1)?,
+                (false, true) => self.add_points(&result, &p2)?,
+                (false, false) => result,
+            };
+        }
+
+        Ok(result)
+    }
+}
+
+impl Drop for Field<'_> {
+    fn drop(&mut self) {
+        self.pka.set_word_order(self.order);
+    }
+}
+
+fn from_be_bytes(bytes: &[u8; 32]) -> Word8 {
+    let mut words = ZERO;
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks(4).rev()) {
+        *word = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    words
+}
+
+fn to_be_bytes(words: &Word8) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    for (chunk, word) in bytes.chunks_mut(4).rev().zip(words.iter()) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    bytes
+}
+
+fn less_than(a: &Word8, b: &Word8) -> bool {
+    for (x, y) in a.iter().zip(b.iter()).rev() {
+        if x != y {
+            return x < y;
+        }
+    }
+    false
+}
+
+fn sub(a: &Word8, b: &Word8) -> Word8 {
+    let mut out = ZERO;
+    let mut borrow = 0u64;
+    for i in 0..8 {
+        let diff = (a[i] as u64).wrapping_sub(b[i] as u64).wrapping_sub(borrow);
+        out[i] = diff as u32;
+        borrow = (diff >> 63) & 1;
+    }
+    out
+}
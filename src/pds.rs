@@ -0,0 +1,416 @@
+/*!
+  # Power-down sleep
+  Power-down sleep (PDS) gates the core clock and, at the deeper levels supported here, powers
+  down the crystal, the PLL and the wireless section. Unlike [hibernate](crate::hbn), the CPU and
+  all RAM keep their power, so [`enter`] returns once the sleep is over and the program simply
+  continues.
+
+  | Level                | Powered down                     | Wake up latency            |
+  |----------------------|----------------------------------|----------------------------|
+  | [`PdsLevel::Level0`] | nothing, only the clock is gated | a few cycles               |
+  | [`PdsLevel::Level1`] | crystal, PLL                     | crystal start-up, PLL lock |
+  | [`PdsLevel::Level2`] | crystal, PLL, wireless section   | crystal start-up, PLL lock |
+
+  The chip wakes up when the PDS timer expires, or when one of the HBN wake pins (GPIO7 and
+  GPIO8) configured with [`PdsConfig::wake_pins`] triggers.
+
+  # Clock restore
+  Code is executed from flash, whose clock derives from the PLL. While the crystal is off the
+  flash can't be read, so the sleep itself and the first steps after waking up run from RAM
+  as a [`ram_function!`](crate::ram_function): the crystal is powered up, the PLL is re-locked
+  by the ROM driver and only then is flash accessed again. Afterwards the system clock is
+  brought back to what was frozen into the [`Clocks`] passed in the configuration. Interrupts
+  stay disabled until all of this is done.
+
+  The configuration of all pins and the clock gates of the peripherals are saved before sleeping
+  and written back afterwards.
+
+  # Pins
+  The GPIO matrix keeps its power, so pins hold their state through the sleep by themselves.
+  Pins marked with `hold_during_sleep` are additionally driven to their hold level right before
+  sleeping and left out of the restore, so they don't move until
+  [`release_holds`](crate::gpio::release_holds) is called. Pins marked with
+  `isolate_during_sleep` float while sleeping and get their configuration back when waking up.
+
+  ## Example
+  ```rust
+    use bl602_hal::pds::{self, PdsConfig, PdsLevel};
+    use core::time::Duration;
+
+    loop {
+        let sample = adc.read(Channel::Ch1).unwrap();
+        // ...
+        pds::enter(PdsLevel::Level1, Some(Duration::from_millis(100)), PdsConfig::new(clocks))
+            .unwrap();
+    }
+  ```
+*/
+
+use core::time::Duration;
+
+use riscv::register::mcycle;
+
+use crate::clock::{
+    glb_set_system_clk_rc32, restore_system_clk, rom_pds_power_on_pll, rom_xtal_type, Clocks, RC32M,
+};
+use crate::gpio::apply_sleep_pads;
+use crate::hbn::{
+    add_falling_edge_wake, configure_pin_wake, flagged_wake_pin, pin_at_idle, WakePin, WakeTrigger,
+};
+use crate::memory;
+use crate::pac;
+use crate::power::{self, WakeFrom, WakeStats, WdtPolicy};
+use crate::serial::{Error as SerialError, RxWake};
+use crate::watchdog::ConfiguredWatchdog0;
+
+/// Frequency of the PDS timer
+const PDS_TIMER_FREQ: u64 = 32_768;
+
+/// Number of `gpio_cfgctl` registers holding the configuration of the pins, two pins each
+const GPIO_CFG_REGISTERS: usize = 12;
+
+/// Number of polling iterations to wait for the crystal after waking up
+const XTAL_TIMEOUT: u32 = 100_000;
+
+// Bits of `PDS_INT`
+const PDS_INT_CLR: u32 = 1 << 15;
+const WAKEUP_SRC_TIMER: u32 = 1 << 16;
+const WAKEUP_SRC_HBN_IRQ: u32 = 1 << 17;
+const WAKEUP_EVENT_SHIFT: u32 = 24;
+
+/// PDS error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// Neither a duration nor a wake pin is given, the chip would sleep forever
+    NoWakeSource,
+    /// The duration doesn't fit into the 32 bit PDS timer, which overflows after about 36 hours
+    DurationOutOfRange,
+    /// The watchdog would expire while sleeping with the configured [`WdtPolicy`]
+    WatchdogExpires,
+}
+
+/// What is powered down while sleeping, see the module documentation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PdsLevel {
+    Level0,
+    Level1,
+    Level2,
+}
+
+/// Sleep configuration
+#[derive(Clone, Copy)]
+pub struct PdsConfig<'a> {
+    clocks: Clocks,
+    wake_pins: &'a [WakePin],
+    wake_trigger: WakeTrigger,
+    restore_gpio: bool,
+    watchdog: Option<(&'a ConfiguredWatchdog0, WdtPolicy)>,
+}
+
+impl<'a> PdsConfig<'a> {
+    /// Creates a configuration which restores `clocks` after waking up
+    pub fn new(clocks: Clocks) -> Self {
+        PdsConfig {
+            clocks,
+            wake_pins: &[],
+            wake_trigger: WakeTrigger::FallingEdge,
+            restore_gpio: true,
+            watchdog: None,
+        }
+    }
+
+    /// Also wakes up when any of `pins` triggers
+    pub fn wake_pins(mut self, pins: &'a [WakePin], trigger: WakeTrigger) -> Self {
+        self.wake_pins = pins;
+        self.wake_trigger = trigger;
+
+        self
+    }
+
+    /// Sets whether the pin configuration is written back after waking up, which is the default
+    ///
+    /// Disable it if pins are reconfigured from interrupt handlers, which may run before
+    /// [`enter`] is called and would be undone.
+    pub fn restore_gpio(mut self, restore: bool) -> Self {
+        self.restore_gpio = restore;
+
+        self
+    }
+
+    /// Handles `watchdog` with `policy` around the sleep, see
+    /// [`power`](crate::power#watchdog-during-sleep)
+    ///
+    /// Without this the watchdog is left alone, as with [`WdtPolicy::KeepRunning`] but without
+    /// the feed and the check of the duration.
+    pub fn watchdog(mut self, watchdog: &'a ConfiguredWatchdog0, policy: WdtPolicy) -> Self {
+        self.watchdog = Some((watchdog, policy));
+
+        self
+    }
+}
+
+unsafe impl RxWake for PdsConfig<'_> {
+    fn add_wake_pin(&mut self, pin: WakePin) -> Result<(), SerialError> {
+        if add_falling_edge_wake(&mut self.wake_pins, &mut self.wake_trigger, pin) {
+            Ok(())
+        } else {
+            Err(SerialError::WakeConflict)
+        }
+    }
+}
+
+/// What ended the sleep
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WakeCause {
+    /// The PDS timer expired
+    Timer,
+    /// A wake pin triggered
+    Pin(WakePin),
+    /// Another wake event, given as the raw `ro_pds_wakeup_event` bits
+    Other(u8),
+}
+
+/// Enters power-down sleep at `level` until `duration` has passed or a wake pin triggers
+///
+/// Without a duration the chip only wakes up through the wake pins. Returns what ended the
+/// sleep, with the clocks, pins and peripheral clock gates as they were before.
+pub fn enter(
+    level: PdsLevel,
+    duration: Option<Duration>,
+    cfg: PdsConfig,
+) -> Result<WakeCause, Error> {
+    let pds = unsafe { &*pac::PDS::ptr() };
+    let glb = unsafe { &*pac::GLB::ptr() };
+    let hbn = unsafe { &*pac::HBN::ptr() };
+
+    let ticks = match duration {
+        Some(duration) => {
+            let ticks = duration.as_secs() as u128 * PDS_TIMER_FREQ as u128
+                + duration.subsec_nanos() as u128 * PDS_TIMER_FREQ as u128 / 1_000_000_000;
+            if ticks > u32::MAX as u128 {
+                return Err(Error::DurationOutOfRange);
+            }
+            Some((ticks as u32).max(1))
+        }
+        None if cfg.wake_pins.is_empty() => return Err(Error::NoWakeSource),
+        None => None,
+    };
+    if let Some((watchdog, policy)) = cfg.watchdog {
+        if !power::watchdog_covers(watchdog, policy, duration) {
+            return Err(Error::WatchdogExpires);
+        }
+    }
+
+    let interrupts_enabled = riscv::register::mstatus::read().mie();
+    unsafe { riscv::interrupt::disable() };
+
+    // Save what the wake up path writes back
+    let gpio_cfg_base = &glb.gpio_cfgctl0 as *const _ as *mut u32;
+    let mut gpio_cfg = [0u32; GPIO_CFG_REGISTERS];
+    for (i, value) in gpio_cfg.iter_mut().enumerate() {
+        *value = unsafe { gpio_cfg_base.add(i).read_volatile() };
+    }
+    let gpio_output = glb.gpio_cfgctl32.read().bits();
+    let gpio_output_enable = glb.gpio_cfgctl34.read().bits();
+    let clock_gates = glb.cgen_cfg1.read().bits();
+    let pds_ctl2 = pds.pds_ctl2.read().bits();
+
+    let held = apply_sleep_pads();
+
+    let power_down_pll = level != PdsLevel::Level0 && cfg.clocks.pll_enable();
+    if level != PdsLevel::Level0 {
+        // Nothing may run from the PLL once it stops
+        glb_set_system_clk_rc32();
+    }
+
+    if level == PdsLevel::Level2 {
+        pds.pds_ctl2.modify(|r, w| unsafe {
+            // cr_pds_force_bz_pwr_off, cr_pds_force_bz_iso_en, cr_pds_force_bz_gate_clk
+            w.bits(r.bits() | (1 << 2) | (1 << 6) | (1 << 18))
+        });
+    }
+
+    if !cfg.wake_pins.is_empty() {
+        hbn.hbn_irq_clr.write(|w| unsafe { w.bits(0xffff_ffff) });
+        hbn.hbn_irq_clr.write(|w| unsafe { w.bits(0) });
+        configure_pin_wake(hbn, cfg.wake_pins, cfg.wake_trigger);
+    }
+
+    let mut wakeup_sources = 0;
+    if ticks.is_some() {
+        wakeup_sources |= WAKEUP_SRC_TIMER;
+    }
+    if !cfg.wake_pins.is_empty() {
+        wakeup_sources |= WAKEUP_SRC_HBN_IRQ;
+    }
+    pds.pds_int.modify(|r, w| unsafe {
+        w.bits((r.bits() & !(0xff << 16)) | wakeup_sources | PDS_INT_CLR)
+    });
+    pds.pds_int
+        .modify(|r, w| unsafe { w.bits(r.bits() & !PDS_INT_CLR) });
+
+    pds.pds_time1
+        .write(|w| unsafe { w.bits(ticks.unwrap_or(0)) });
+
+    // cr_pds_gate_clk and cr_pds_mem_stby; the sleep starts on `wfi`, cr_pds_start_ps is set
+    // from RAM
+    let mut ctl = (1 << 8) | (1 << 9);
+    if ticks.is_none() {
+        // cr_sleep_forever
+        ctl |= 1 << 1;
+    }
+    pds.pds_ctl.write(|w| unsafe { w.bits(ctl) });
+
+    let xtal_type = match cfg.clocks.xtal_freq() {
+        // The PLL only runs from a crystal it accepts
+        Some(freq) if power_down_pll => rom_xtal_type(freq.0).unwrap_or(0),
+        _ => 0,
+    };
+    let watchdog_resume = cfg
+        .watchdog
+        .map(|(watchdog, policy)| power::watchdog_before_sleep(watchdog, policy, duration));
+    let (woke, locked) =
+        unsafe { sleep_from_ram(power_down_pll, rom_pds_power_on_pll(), xtal_type) };
+    if let (Some((watchdog, _)), Some(resume)) = (cfg.watchdog, watchdog_resume) {
+        // Before the clocks are restored, which may hang waiting for the crystal
+        power::watchdog_after_sleep(watchdog, resume);
+    }
+
+    let event = (pds.pds_int.read().bits() >> WAKEUP_EVENT_SHIFT) as u8;
+    pds.pds_int
+        .modify(|r, w| unsafe { w.bits(r.bits() | PDS_INT_CLR) });
+    pds.pds_int
+        .modify(|r, w| unsafe { w.bits(r.bits() & !PDS_INT_CLR & !(0xff << 16)) });
+
+    if level != PdsLevel::Level0 {
+        // The crystal and the PLL ran with these settings before, if they fail now the core
+        // carries on from the RC oscillator rather than losing the wake up cause
+        restore_system_clk(&cfg.clocks).ok();
+    }
+    let clock_restored = mcycle::read() as u32;
+    pds.pds_ctl2.write(|w| unsafe { w.bits(pds_ctl2) });
+    glb.cgen_cfg1.write(|w| unsafe { w.bits(clock_gates) });
+    if cfg.restore_gpio {
+        // Held pins keep what they were set to before sleeping
+        for (i, value) in gpio_cfg.iter().enumerate() {
+            let mut keep = 0;
+            if held & (1 << (2 * i)) != 0 {
+                keep |= 0xffff;
+            }
+            if held & (1 << (2 * i + 1)) != 0 {
+                keep |= 0xffff << 16;
+            }
+
+            unsafe {
+                let cfgctl = gpio_cfg_base.add(i);
+                cfgctl.write_volatile((*value & !keep) | (cfgctl.read_volatile() & keep));
+            }
+        }
+        glb.gpio_cfgctl32
+            .modify(|r, w| unsafe { w.bits((gpio_output & !held) | (r.bits() & held)) });
+        glb.gpio_cfgctl34
+            .modify(|r, w| unsafe { w.bits((gpio_output_enable & !held) | (r.bits() & held)) });
+    }
+
+    let cause = if event & (WAKEUP_SRC_TIMER >> 16) as u8 != 0 {
+        WakeCause::Timer
+    } else if let Some(pin) = flagged_wake_pin(hbn) {
+        WakeCause::Pin(pin)
+    } else {
+        WakeCause::Other(event)
+    };
+    let spurious = match cause {
+        WakeCause::Timer => false,
+        WakeCause::Pin(pin) => pin_at_idle(pin, cfg.wake_trigger),
+        WakeCause::Other(_) => true,
+    };
+    if !cfg.wake_pins.is_empty() {
+        hbn.hbn_irq_clr.write(|w| unsafe { w.bits(0xffff_ffff) });
+        hbn.hbn_irq_clr.write(|w| unsafe { w.bits(0) });
+        // Mask both pins again
+        configure_pin_wake(hbn, &[], cfg.wake_trigger);
+    }
+
+    // Up to here everything ran from the clock the sleep was entered with
+    let sleep_clk = if level == PdsLevel::Level0 {
+        cfg.clocks.sysclk().0
+    } else {
+        RC32M
+    };
+    let done = mcycle::read() as u32;
+    power::record_wake(WakeStats {
+        time_to_resume: Some(
+            power::cycles_to_duration(clock_restored.wrapping_sub(woke), sleep_clk)
+                + power::cycles_to_duration(
+                    done.wrapping_sub(clock_restored),
+                    cfg.clocks.sysclk().0,
+                ),
+        ),
+        time_to_pll_lock: if power_down_pll {
+            Some(power::cycles_to_duration(
+                locked.wrapping_sub(woke),
+                sleep_clk,
+            ))
+        } else {
+            None
+        },
+        cause: WakeFrom::Pds(cause),
+        spurious_wakes: power::count_wake(spurious),
+    });
+
+    if interrupts_enabled {
+        unsafe { riscv::interrupt::enable() };
+    }
+
+    Ok(cause)
+}
+
+crate::ram_function! {
+    /// Starts the sleep and, if the crystal and PLL are powered down, brings them back up before
+    /// returning to code in flash
+    ///
+    /// Returns the low half of `mcycle` right after waking up and once the PLL is locked.
+    ///
+    /// # Safety
+    ///
+    /// Must be called with interrupts disabled and the system clock running from the RC oscillator
+    /// if `power_down_pll` is set. Everything called from here has to be inlined, this function is
+    /// placed in RAM so it keeps running while the flash is unreadable.
+    unsafe fn sleep_from_ram(
+        power_down_pll: bool,
+        rom_power_on_pll: extern "C" fn(usize) -> usize,
+        xtal_type: usize,
+    ) -> (u32, u32) {
+        let pds = &*pac::PDS::ptr();
+        let aon = &*pac::AON::ptr();
+
+        if power_down_pll {
+            pds.pu_rst_clkpll
+                .modify(|_, w| w.pu_clkpll_sfreg().clear_bit().pu_clkpll().clear_bit());
+            aon.rf_top_aon
+                .modify(|_, w| w.pu_xtal_aon().clear_bit().pu_xtal_buf_aon().clear_bit());
+        }
+
+        // cr_pds_start_ps
+        pds.pds_ctl.modify(|r, w| w.bits(r.bits() | 1));
+        memory::ram_wfi();
+        let woke = memory::ram_mcycle();
+        pds.pds_ctl.modify(|r, w| w.bits(r.bits() & !1));
+
+        if power_down_pll {
+            aon.rf_top_aon
+                .modify(|_, w| w.pu_xtal_aon().set_bit().pu_xtal_buf_aon().set_bit());
+
+            let mut timeout_countdown = XTAL_TIMEOUT;
+            while aon.tsen.read().xtal_rdy().bit_is_clear() && timeout_countdown > 0 {
+                timeout_countdown -= 1;
+            }
+
+            // The ROM driver runs from ROM and waits for the lock itself
+            rom_power_on_pll(xtal_type);
+        }
+
+        (woke, memory::ram_mcycle())
+    }
+}
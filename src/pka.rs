@@ -0,0 +1,424 @@
+/*!
+  # Public Key Accelerator
+  The PKA in the SEC_ENG block performs big number arithmetic on a register file inside the
+  engine. This module exposes the modular operations needed for public key cryptography, e.g.
+  [`Pka::mod_exp`] for RSA signature verification. The [`p256`](crate::p256) module builds
+  ECDSA verification on top of it.
+
+  ## Operand format
+  Numbers are slices of `u32` words. With [`WordOrder::LeastSignificantFirst`] the first word
+  holds the lowest 32 bits, with [`WordOrder::MostSignificantFirst`] the first word holds the
+  highest 32 bits. In both cases each word is a native `u32` value, so numbers given as big
+  endian bytes (as in most standards) need `u32::from_be_bytes` on each 4 byte group together
+  with `MostSignificantFirst`.
+
+  Every operation works on registers of the given [`OperandSize`]. Operands may be shorter than
+  that, they are zero extended, but not longer. Results are written to the start of `out`, which
+  must be at least as long as the modulus.
+
+  The engine is driven with the same command words as the vendor SDK (`Sec_Eng_PKA_*`): load
+  operands, run one operation, read the result back.
+
+  ## Example
+  ```rust
+    use bl602_hal::pka::{OperandSize, Pka, WordOrder};
+    use bl602_hal::sec_eng::SecEngExt;
+
+    let sec_eng = dp.SEC_ENG.split();
+    let mut pka = Pka::new(sec_eng.pka, &mut parts.clk_cfg, WordOrder::LeastSignificantFirst);
+
+    // 4^13 mod 497 = 445
+    let mut out = [0u32; 1];
+    pka.mod_exp(OperandSize::Bits256, &[4], &[13], &[497], &mut out).unwrap();
+    assert_eq!(out[0], 445);
+  ```
+*/
+
+use crate::clock::{glb_ahb_slave1_clock_enable, AhbSlave1};
+use crate::gpio::ClkCfg;
+use crate::pac;
+use crate::sec_eng::Pka0;
+
+/// Number of polling iterations to wait for an operation before giving up
+///
+/// A 4096 bit modular exponentiation with a full size exponent is the longest operation and
+/// needs far more time than the other engines.
+const PKA_TIMEOUT: u32 = 100_000_000;
+
+/// PKA error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The operation did not finish in time
+    Timeout,
+    /// An operand has more words than the operand size allows, or the modulus is empty
+    OperandTooLarge,
+    /// The output slice is shorter than the modulus
+    BufferTooSmall,
+}
+
+/// Order of the words in operand slices
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WordOrder {
+    /// The first word is the least significant one
+    LeastSignificantFirst,
+    /// The first word is the most significant one
+    MostSignificantFirst,
+}
+
+/// Size of the engine registers used for an operation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OperandSize {
+    /// 256 bits, e.g. for P-256
+    Bits256,
+    /// 512 bits
+    Bits512,
+    /// 1024 bits
+    Bits1024,
+    /// 2048 bits, e.g. for RSA-2048
+    Bits2048,
+    /// 3072 bits
+    Bits3072,
+    /// 4096 bits
+    Bits4096,
+}
+
+impl OperandSize {
+    /// Number of 32 bit words in an operand of this size
+    pub fn words(&self) -> usize {
+        match self {
+            OperandSize::Bits256 => 8,
+            OperandSize::Bits512 => 16,
+            OperandSize::Bits1024 => 32,
+            OperandSize::Bits2048 => 64,
+            OperandSize::Bits3072 => 96,
+            OperandSize::Bits4096 => 128,
+        }
+    }
+
+    /// Register type of the engine, `SEC_ENG_PKA_REG_SIZE_*` in the SDK (named by size in bytes)
+    fn reg_type(&self) -> u32 {
+        match self {
+            OperandSize::Bits256 => 3,
+            OperandSize::Bits512 => 4,
+            OperandSize::Bits1024 => 6,
+            OperandSize::Bits2048 => 8,
+            OperandSize::Bits3072 => 9,
+            OperandSize::Bits4096 => 10,
+        }
+    }
+}
+
+/// Operation codes, `SEC_ENG_PKA_OP_*` in the SDK
+#[derive(Copy, Clone)]
+#[repr(u32)]
+enum Op {
+    MInv = 0x22,
+    MExp = 0x23,
+    MMul = 0x25,
+    MRem = 0x26,
+    MSub = 0x27,
+    MAdd = 0x28,
+    /// Copy a register out to the read buffer
+    CflirBuffer = 0x38,
+    /// Load a register from the write data
+    CtlirPld = 0x39,
+}
+
+/// Register indices used by the driver, all of the same register type
+const REG_MODULUS: u32 = 0;
+const REG_A: u32 = 1;
+const REG_B: u32 = 2;
+const REG_RESULT: u32 = 3;
+
+/// Public key accelerator driver
+pub struct Pka {
+    pka: Pka0,
+    order: WordOrder,
+}
+
+impl Pka {
+    /// Enables and resets the PKA
+    pub fn new(pka: Pka0, _clk_cfg: &mut ClkCfg, order: WordOrder) -> Self {
+        glb_ahb_slave1_clock_enable(AhbSlave1::Sec, true);
+
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+        sec_eng.se_pka_0_ctrl_0.write(|w| unsafe { w.bits(0) });
+        sec_eng.se_pka_0_ctrl_0.write(|w| {
+            w.se_pka_0_en()
+                .set_bit()
+                .se_pka_0_int_mask()
+                .set_bit()
+                .se_pka_0_status_clr_1t()
+                .set_bit()
+        });
+        // The driver converts word orders itself, the engine always sees the lowest word first
+        sec_eng.se_pka_0_ctrl_0.modify(|_, w| {
+            w.se_pka_0_status_clr_1t()
+                .clear_bit()
+                .se_pka_0_endian()
+                .clear_bit()
+        });
+
+        Pka { pka, order }
+    }
+
+    /// Returns the word order of the operand slices
+    pub fn word_order(&self) -> WordOrder {
+        self.order
+    }
+
+    /// Changes the word order of the operand slices
+    pub fn set_word_order(&mut self, order: WordOrder) {
+        self.order = order;
+    }
+
+    /// Computes `a * b mod m`
+    pub fn mod_mul(
+        &mut self,
+        size: OperandSize,
+        a: &[u32],
+        b: &[u32],
+        m: &[u32],
+        out: &mut [u32],
+    ) -> Result<(), Error> {
+        self.binary_op(Op::MMul, size, a, b, m, out)
+    }
+
+    /// Computes `a + b mod m`
+    pub fn mod_add(
+        &mut self,
+        size: OperandSize,
+        a: &[u32],
+        b: &[u32],
+        m: &[u32],
+        out: &mut [u32],
+    ) -> Result<(), Error> {
+        self.binary_op(Op::MAdd, size, a, b, m, out)
+    }
+
+    /// Computes `a - b mod m`
+    pub fn mod_sub(
+        &mut self,
+        size: OperandSize,
+        a: &[u32],
+        b: &[u32],
+        m: &[u32],
+        out: &mut [u32],
+    ) -> Result<(), Error> {
+        self.binary_op(Op::MSub, size, a, b, m, out)
+    }
+
+    /// Computes `base ^ exponent mod m`
+    ///
+    /// The run time depends on the exponent, so this is only meant for public exponents, e.g.
+    /// RSA signature verification.
+    pub fn mod_exp(
+        &mut self,
+        size: OperandSize,
+        base: &[u32],
+        exponent: &[u32],
+        m: &[u32],
+        out: &mut [u32],
+    ) -> Result<(), Error> {
+        self.binary_op(Op::MExp, size, base, exponent, m, out)
+    }
+
+    /// Computes the inverse of `a` modulo `m`
+    ///
+    /// `a` and `m` must be coprime, otherwise the result is unspecified.
+    pub fn mod_inv(
+        &mut self,
+        size: OperandSize,
+        a: &[u32],
+        m: &[u32],
+        out: &mut [u32],
+    ) -> Result<(), Error> {
+        self.check(size, &[a, m], out)?;
+
+        self.load(size, REG_MODULUS, m);
+        self.load(size, REG_A, a);
+        self.reduce(size, REG_A);
+        self.command(Op::MInv, size, REG_RESULT, REG_A, None, REG_MODULUS)?;
+        self.store(size, REG_RESULT, &mut out[..m.len()]);
+
+        Ok(())
+    }
+
+    /// Computes `a mod m`
+    pub fn mod_reduce(
+        &mut self,
+        size: OperandSize,
+        a: &[u32],
+        m: &[u32],
+        out: &mut [u32],
+    ) -> Result<(), Error> {
+        self.check(size, &[a, m], out)?;
+
+        self.load(size, REG_MODULUS, m);
+        self.load(size, REG_A, a);
+        self.command(Op::MRem, size, REG_RESULT, REG_A, None, REG_MODULUS)?;
+        self.store(size, REG_RESULT, &mut out[..m.len()]);
+
+        Ok(())
+    }
+
+    /// Disables the PKA and releases it
+    pub fn free(self) -> Pka0 {
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+        sec_eng
+            .se_pka_0_ctrl_0
+            .modify(|_, w| w.se_pka_0_en().clear_bit());
+
+        self.pka
+    }
+
+    fn binary_op(
+        &mut self,
+        op: Op,
+        size: OperandSize,
+        a: &[u32],
+        b: &[u32],
+        m: &[u32],
+        out: &mut [u32],
+    ) -> Result<(), Error> {
+        self.check(size, &[a, b, m], out)?;
+
+        self.load(size, REG_MODULUS, m);
+        self.load(size, REG_A, a);
+        self.load(size, REG_B, b);
+        // The modular operations expect reduced operands, except for the exponent
+        self.reduce(size, REG_A);
+        if !matches!(op, Op::MExp) {
+            self.reduce(size, REG_B);
+        }
+        self.command(op, size, REG_RESULT, REG_A, Some(REG_B), REG_MODULUS)?;
+        self.store(size, REG_RESULT, &mut out[..m.len()]);
+
+        Ok(())
+    }
+
+    fn check(&self, size: OperandSize, operands: &[&[u32]], out: &[u32]) -> Result<(), Error> {
+        if operands.iter().any(|o| o.len() > size.words()) {
+            return Err(Error::OperandTooLarge);
+        }
+        // The modulus always comes last
+        let modulus_len = operands[operands.len() - 1].len();
+        if modulus_len == 0 {
+            return Err(Error::OperandTooLarge);
+        }
+        if out.len() < modulus_len {
+            return Err(Error::BufferTooSmall);
+        }
+
+        Ok(())
+    }
+
+    /// Reduces register `reg` modulo the modulus register in place
+    fn reduce(&mut self, size: OperandSize, reg: u32) {
+        // Reduction only fails by timing out, which the following operation reports as well
+        self.command(Op::MRem, size, reg, reg, None, REG_MODULUS)
+            .ok();
+    }
+
+    /// Loads `value` zero extended into register `reg`
+    fn load(&mut self, size: OperandSize, reg: u32, value: &[u32]) {
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+
+        let cfg = (size.words() as u32 & 0xfff)
+            | (reg << 12)
+            | (size.reg_type() << 20)
+            | ((Op::CtlirPld as u32) << 24);
+        sec_eng.se_pka_0_rw.write(|w| unsafe { w.bits(cfg) });
+
+        for i in 0..size.words() {
+            let word = match self.order {
+                WordOrder::LeastSignificantFirst => value.get(i),
+                WordOrder::MostSignificantFirst => value
+                    .len()
+                    .checked_sub(i + 1)
+                    .and_then(|index| value.get(index)),
+            };
+            let word = word.copied().unwrap_or(0);
+            sec_eng.se_pka_0_rw_burst.write(|w| unsafe { w.bits(word) });
+        }
+    }
+
+    /// Reads the lowest `out.len()` words of register `reg`
+    fn store(&mut self, size: OperandSize, reg: u32, out: &mut [u32]) {
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+
+        let cfg = (size.words() as u32 & 0xfff)
+            | (reg << 12)
+            | (size.reg_type() << 20)
+            | ((Op::CflirBuffer as u32) << 24);
+        sec_eng.se_pka_0_rw.write(|w| unsafe { w.bits(cfg) });
+
+        let len = out.len();
+        for i in 0..size.words() {
+            // The whole register has to be read out, even if only the low words are needed
+            let word = sec_eng.se_pka_0_rw_burst.read().bits();
+            if i < len {
+                match self.order {
+                    WordOrder::LeastSignificantFirst => out[i] = word,
+                    WordOrder::MostSignificantFirst => out[len - 1 - i] = word,
+                }
+            }
+        }
+    }
+
+    /// Runs `d = op(s0, s1) mod s2` and waits for it to finish
+    fn command(
+        &mut self,
+        op: Op,
+        size: OperandSize,
+        d: u32,
+        s0: u32,
+        s1: Option<u32>,
+        s2: u32,
+    ) -> Result<(), Error> {
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+        let reg_type = size.reg_type();
+
+        // First word: destination and first source, marked as the last operation of the batch
+        let first = s0 | (reg_type << 8) | (d << 12) | (reg_type << 20) | ((op as u32) << 24);
+        // Second word: second source (if any) and modulus
+        let second = match s1 {
+            Some(s1) => s2 | (reg_type << 8) | (s1 << 12) | (reg_type << 20),
+            None => s2 | (reg_type << 8),
+        };
+
+        sec_eng
+            .se_pka_0_rw
+            .write(|w| unsafe { w.bits(first | 1 << 31) });
+        sec_eng.se_pka_0_rw.write(|w| unsafe { w.bits(second) });
+
+        wait_done()
+    }
+}
+
+/// Waits until the PKA has finished the current operation and clears its done flag
+fn wait_done() -> Result<(), Error> {
+    let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+
+    // The busy flag is only raised one cycle after the command was queued, reading the control
+    // register once takes longer than that
+    let _ = sec_eng.se_pka_0_ctrl_0.read();
+
+    let mut timeout_countdown = PKA_TIMEOUT;
+    while sec_eng.se_pka_0_ctrl_0.read().se_pka_0_busy().bit_is_set() {
+        if timeout_countdown == 0 {
+            return Err(Error::Timeout);
+        }
+        timeout_countdown -= 1;
+    }
+
+    sec_eng
+        .se_pka_0_ctrl_0
+        .modify(|_, w| w.se_pka_0_done_clr_1t().set_bit());
+    sec_eng
+        .se_pka_0_ctrl_0
+        .modify(|_, w| w.se_pka_0_done_clr_1t().clear_bit());
+
+    Ok(())
+}
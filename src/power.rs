@@ -0,0 +1,1142 @@
+/*!
+  # Power management
+  Helpers for sleeping the core between interrupts. The core clock is gated while waiting, the
+  peripherals and their clocks keep running, so this works with anything that raises an
+  interrupt and needs no setup. For the deeper sleep modes, which also stop peripherals, see
+  [`pds`](crate::pds) and [`hbn`](crate::hbn).
+
+  [`sleep_until`] waits for an interrupt or a deadline measured by the machine timer `mtime`,
+  which has to be started with [`start_mtime`] first. [`idle`] just waits for an interrupt.
+
+  ## Example
+  ```rust
+    use bl602_hal::power::{self, Instant};
+    use core::time::Duration;
+
+    power::start_mtime(&clocks);
+
+    let mut next = Instant::now();
+    loop {
+        next = next + Duration::from_millis(10);
+        while power::sleep_until(next) == power::Wake::Interrupt {
+            // handle whatever the interrupt handler left to do
+        }
+        // runs every 10 ms
+    }
+  ```
+
+  ## Superloops
+  A superloop which only has work after interrupts can end every pass with [`idle`]. Checking
+  for work and going to sleep would race with an interrupt arriving in between, which would only
+  be seen after the next interrupt. `idle` avoids that by disabling interrupts before calling
+  the hook registered with [`on_idle`], so checks made in the hook are still valid when the
+  core sleeps: an interrupt arriving after them is left pending, and a pending interrupt ends
+  the wait right away.
+
+  # Core voltage
+  The digital core is supplied by the LDO11 regulator, whose output can be lowered at lower
+  system clock frequencies to save current. Running faster than the voltage allows makes the
+  core fail in ways only a reset recovers from, so [`Regulators`] refuses voltages below what
+  the current system clock needs, and [`set_performance`] changes both in the safe order:
+  the voltage is raised before speeding up and lowered after slowing down.
+
+  | [`Level`]         | System clock | [`CoreVoltage`]        |
+  |-------------------|--------------|------------------------|
+  | [`Level::Low`]    | RC, 32 MHz   | [`CoreVoltage::V1_10`] |
+  | [`Level::Medium`] | PLL, 48 MHz  | [`CoreVoltage::V1_10`] |
+  | [`Level::High`]   | PLL, 120 MHz | [`CoreVoltage::V1_10`] |
+  | [`Level::Full`]   | PLL, 160 MHz | [`CoreVoltage::V1_10`] |
+
+  1.10 V is the setting the chip comes up with, at which it runs at 160 MHz. Neither the
+  datasheet nor the reference manual give the frequency the core reaches below it, so the HAL
+  offers no lower setting; the levels only save what the slower clock saves, and lower voltages
+  can be added once a figure is published. `mtime` and the dividers of SPI and
+  I2C have to be set up again after changing the system clock.
+  ```rust
+    use bl602_hal::power::{self, Level};
+
+    power::set_performance(Level::Low, &mut clocks).unwrap();
+    // ... a long wait for the next event
+    power::set_performance(Level::Full, &mut clocks).unwrap();
+    power::start_mtime(&clocks);
+  ```
+
+  # Power domains
+  The analog blocks and the TRNG have their own enable bits. Drivers switch them on with a
+  [`DomainGuard`] when they are created and off again when they are released and no other guard
+  of the same [`Domain`] is left. [`report`] lists which domains are on, which helps finding
+  a block left running when the sleep current is higher than expected:
+  ```rust
+    writeln!(serial, "{}\r", bl602_hal::power::report()).ok();
+    // adc: off, dac: off, acomp: on (1 user), trng: off
+  ```
+
+  # Wake up latency
+  The resume paths of [`pds::enter`](crate::pds::enter) and
+  [`hbn::wakeup_cause`](crate::hbn::wakeup_cause) take timestamps, which [`last_wake_stats`]
+  returns for the last wake up. Printing them after every wake shows regressions in the clock
+  set up code early.
+
+  # Watchdog during sleep
+  The watchdog keeps counting through power-down sleep when it runs from the 32 kHz RC
+  oscillator; from the other clock sources it stops while they are off. A [`WdtPolicy`] given
+  with [`PdsConfig::watchdog`](crate::pds::PdsConfig::watchdog) decides what happens to it, so
+  neither does it reset the chip in the middle of a long sleep nor is it left off after waking
+  up:
+
+  | [`WdtPolicy`]                         | Before sleeping                 | After waking up           |
+  |---------------------------------------|---------------------------------|---------------------------|
+  | [`WdtPolicy::PauseDuringSleep`]       | stop the counter                | feed, start the counter   |
+  | [`WdtPolicy::ExtendToCoverSleep`]     | feed, raise the match           | feed, restore the match   |
+  | [`WdtPolicy::KeepRunning`]            | feed                            | nothing                   |
+
+  Hibernate powers the watchdog off together with the core. The wake up restarts the program,
+  which has to set the watchdog up again.
+
+  # Reset reason
+  [`reset_reason`] tells why the program started, combining the sticky watchdog flag with the
+  markers the HAL writes to `HBN_RSV0` before the resets it triggers itself: hibernate,
+  [`Bod::reset`](crate::hbn::Bod::reset), [`software_reset`] and [`reset_to_bootloader`].
+  Nothing is cleared by reading it, so call [`clear_reset_reason`] once it has been handled,
+  and before [`hbn::wakeup_cause`], which clears the hibernate marker. Power-down sleep doesn't
+  reset the chip at the levels supported by [`pds`], so it never shows up as a reset reason.
+
+  ```rust
+    use bl602_hal::power::{self, ResetReason};
+
+    match power::reset_reason() {
+        ResetReason::Bootloader => { /* run the update loader */ }
+        ResetReason::Watchdog => { /* log the hang */ }
+        _ => {}
+    }
+    power::clear_reset_reason();
+  ```
+
+  ## Boot pin
+  The boot ROM samples the boot pin, GPIO8, once at every reset and enters its download mode if
+  it's high. Nothing in GLB latches the sampled level or takes the pin out of the boot decision,
+  and once the program runs GPIO8 is an ordinary pin, `pin8` of
+  [`GlbExt::split`](crate::gpio::GlbExt::split). So the firmware can neither tell afterwards how
+  the pin was strapped nor keep the next reset from entering download mode; external circuitry
+  which may drive the pin has to hold it low, e.g. with a pull-down, while the chip comes out of
+  reset.
+
+  # Quiescing drivers
+  Power-down sleep stops the peripherals wherever they are, so bytes still in the UART TX FIFO
+  or a DMA transfer in flight are cut off. Drivers with such state register a [`SleepAware`]
+  hook, the UART driver does so when it's created. [`prepare_sleep`] runs the hooks until all
+  peripherals are at rest or a deadline has passed, in which case the caller gets
+  [`Error::Busy`] and can try again later. The returned [`Quiesced`] runs the resume hooks when
+  it's dropped after the wake up.
+  ```rust
+    use bl602_hal::power::{self, Instant};
+
+    power::register::<power::DmaHalt>().unwrap();
+
+    let deadline = Instant::now() + Duration::from_millis(2);
+    match power::prepare_sleep(deadline) {
+        Ok(_quiesced) => pds::enter(PdsLevel::Level1, Some(period), config).unwrap(),
+        Err(power::Error::Busy(peripheral)) => { /* try again after the next interrupt */ }
+        Err(_) => unreachable!(),
+    }
+  ```
+*/
+
+use core::fmt;
+use core::ops::Add;
+use core::sync::atomic::{AtomicU32, AtomicU8, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use embedded_hal::delay::blocking::DelayUs;
+use embedded_time::rate::Hertz;
+use riscv::register::mstatus;
+
+use crate::clock::{glb_set_system_clk_rc32, ClockError, ClockSource, Clocks, RC32M};
+use crate::delay::McycleDelay;
+use crate::gpio::ClkCfg;
+use crate::interrupts::{irq_enabled, set_irq_enabled, MTIMER_IRQ};
+use crate::mtimer::{read_mtime, read_mtimecmp, write_mtimecmp};
+use crate::pac;
+use crate::sync::SpinLock;
+use crate::watchdog::{self, ConfiguredWatchdog0};
+use crate::{hbn, pds};
+
+/// `mtime` frequency set by [`start_mtime`]
+pub const MTIME_FREQ: u32 = 1_000_000;
+
+/// Time for LDO11 to settle after raising its output
+const LDO_SETTLE_US: u32 = 50;
+
+/// Number of [`SleepAware`] hooks which can be registered
+pub const SLEEP_HOOKS: usize = 8;
+
+// DMA controller registers
+const DMA_ENBLD_CHNS_ADDR: usize = 0x4000_c01c;
+const DMA_C0_CONFIG_ADDR: usize = 0x4000_c110;
+const DMA_CHANNEL_STRIDE: usize = 0x100;
+const DMA_CHANNELS: usize = 4;
+
+static IDLE_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// Stats of the last wake up, recorded by the resume paths
+static LAST_WAKE: SpinLock<Option<WakeStats>> = SpinLock::new(None);
+
+/// Number of wake ups which had no cause left when checked
+static SPURIOUS_WAKES: AtomicU32 = AtomicU32::new(0);
+
+/// Hooks registered with [`register`], in the order they are run
+static SLEEP_HOOK_LIST: SpinLock<[Option<SleepHook>; SLEEP_HOOKS]> =
+    SpinLock::new([None; SLEEP_HOOKS]);
+
+/// DMA channels halted by [`DmaHalt`], one bit per channel
+static HALTED_DMA: AtomicU8 = AtomicU8::new(0);
+
+/// Number of [`DomainGuard`]s of each [`Domain`]
+static DOMAIN_USERS: [AtomicU8; DOMAINS] = [
+    AtomicU8::new(0),
+    AtomicU8::new(0),
+    AtomicU8::new(0),
+    AtomicU8::new(0),
+];
+
+/// Power management error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The voltage is too low for the current system clock
+    VoltageTooLow,
+    /// The level needs the PLL, but the clocks don't say which crystal drives it
+    NoCrystal,
+    /// Switching the system clock failed, the voltage is left at the higher of both levels
+    Clock(ClockError),
+    /// The peripheral didn't come to rest before the deadline
+    Busy(Peripheral),
+    /// All [`SLEEP_HOOKS`] places of the registry are taken
+    TooManyHooks,
+}
+
+/// Point in time measured by `mtime`, in microseconds once [`start_mtime`] has been called
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Instant {
+    ticks: u64,
+}
+
+impl Instant {
+    /// Returns the current time
+    pub fn now() -> Self {
+        Instant {
+            ticks: read_mtime(),
+        }
+    }
+
+    /// Returns the instant at which `mtime` is `ticks`
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Instant { ticks }
+    }
+
+    /// Returns the value of `mtime` at this instant
+    pub const fn ticks(&self) -> u64 {
+        self.ticks
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        let ticks = rhs.as_secs() * MTIME_FREQ as u64
+            + rhs.subsec_nanos() as u64 * MTIME_FREQ as u64 / 1_000_000_000;
+
+        Instant {
+            ticks: self.ticks + ticks,
+        }
+    }
+}
+
+/// Why [`sleep_until`] returned
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Wake {
+    /// The deadline has passed
+    Deadline,
+    /// An interrupt was handled before the deadline
+    Interrupt,
+}
+
+/// Starts `mtime` counting at [`MTIME_FREQ`], derived from the core clock
+///
+/// Call this again after changing the system clock.
+pub fn start_mtime(clocks: &Clocks) {
+    let glb = unsafe { &*pac::GLB::ptr() };
+    let div = clocks.sysclk().0 / MTIME_FREQ - 1;
+
+    // cpu_rtc_en has to be off while changing cpu_rtc_div
+    glb.cpu_clk_cfg
+        .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 18)) });
+    glb.cpu_clk_cfg
+        .modify(|r, w| unsafe { w.bits((r.bits() & !0x1_ffff) | div) });
+    glb.cpu_clk_cfg
+        .modify(|r, w| unsafe { w.bits(r.bits() | (1 << 18)) });
+}
+
+/// Sleeps until `deadline` or until an interrupt has been handled, whichever comes first
+///
+/// Returns right away if the deadline has already passed. The machine timer compare register
+/// is borrowed for the wait and restored afterwards, together with whether the machine timer
+/// interrupt was enabled. No machine timer handler is needed, the timer only ends the wait.
+pub fn sleep_until(deadline: Instant) -> Wake {
+    let interrupts_enabled = mstatus::read().mie();
+    // With interrupts disabled, an interrupt which becomes pending from here on can't be taken
+    // between the deadline check and `wfi`, where it wouldn't end the wait. It stays pending
+    // instead, and `wfi` returns right away for pending interrupts which are enabled in the
+    // CLIC, whether interrupts are enabled globally or not.
+    unsafe { riscv::interrupt::disable() };
+
+    let saved_compare = read_mtimecmp();
+    let saved_enable = irq_enabled(MTIMER_IRQ);
+
+    // The compare register is written before the deadline is checked, so a deadline passing
+    // right after the check leaves the timer interrupt pending and `wfi` doesn't wait
+    write_mtimecmp(deadline.ticks);
+    set_irq_enabled(MTIMER_IRQ, true);
+
+    let wake = if Instant::now() >= deadline {
+        Wake::Deadline
+    } else {
+        unsafe { riscv::asm::wfi() };
+
+        if Instant::now() >= deadline {
+            Wake::Deadline
+        } else {
+            Wake::Interrupt
+        }
+    };
+
+    // Take the timer back out of the picture before interrupts are enabled again, so its
+    // pending interrupt isn't taken by a handler which doesn't expect it
+    set_irq_enabled(MTIMER_IRQ, saved_enable);
+    write_mtimecmp(saved_compare);
+
+    if interrupts_enabled {
+        // Pending interrupts are taken here, in particular the one which ended the wait
+        unsafe { riscv::interrupt::enable() };
+    }
+
+    wake
+}
+
+/// Runs the [`on_idle`] hook and sleeps until an interrupt is pending
+///
+/// The hook and the wait run with interrupts disabled, the interrupt which ends the wait is
+/// handled right before `idle` returns. If interrupts were disabled when calling `idle`, the
+/// wait still ends on a pending interrupt but it isn't handled.
+pub fn idle() {
+    let interrupts_enabled = mstatus::read().mie();
+    unsafe { riscv::interrupt::disable() };
+
+    let hook = IDLE_HOOK.load(Ordering::Relaxed);
+    if hook != 0 {
+        let hook: fn() = unsafe { core::mem::transmute(hook) };
+        hook();
+    }
+
+    unsafe { riscv::asm::wfi() };
+
+    if interrupts_enabled {
+        unsafe { riscv::interrupt::enable() };
+    }
+}
+
+/// Registers `hook` to be called by [`idle`] before the core sleeps, replacing the previous one
+///
+/// The hook runs with interrupts disabled, so it must not wait for them.
+pub fn on_idle(hook: fn()) {
+    IDLE_HOOK.store(hook as usize, Ordering::Relaxed);
+}
+
+/// Output voltage of the LDO11 regulator which supplies the digital core
+///
+/// Only the settings from the one the chip comes up with are listed, see the module
+/// documentation.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum CoreVoltage {
+    V1_10,
+    V1_15,
+    V1_20,
+}
+
+impl CoreVoltage {
+    /// Returns the highest system clock frequency the core runs at with this voltage
+    pub fn max_sysclk(self) -> Hertz {
+        match self {
+            CoreVoltage::V1_10 | CoreVoltage::V1_15 | CoreVoltage::V1_20 => Hertz(160_000_000),
+        }
+    }
+
+    /// Returns the `sw_ldo11soc_vout_sel_aon` value, 0.60 V plus 50 mV per step
+    fn bits(self) -> u32 {
+        match self {
+            CoreVoltage::V1_10 => 10,
+            CoreVoltage::V1_15 => 11,
+            CoreVoltage::V1_20 => 12,
+        }
+    }
+}
+
+/// Core voltage regulator
+pub struct Regulators {
+    _private: (),
+}
+
+impl Regulators {
+    /// Gives access to the regulators, which are configured together with the clocks
+    pub fn new(_clk_cfg: &mut ClkCfg) -> Self {
+        Regulators { _private: () }
+    }
+
+    /// Returns the current core voltage, `None` if it's set to a level not listed in
+    /// [`CoreVoltage`]
+    pub fn core_voltage(&self) -> Option<CoreVoltage> {
+        core_voltage()
+    }
+
+    /// Sets the core voltage, refusing voltages too low for the system clock in `clocks`
+    pub fn set_core_voltage(&mut self, voltage: CoreVoltage, clocks: &Clocks) -> Result<(), Error> {
+        if clocks.sysclk().0 > voltage.max_sysclk().0 {
+            return Err(Error::VoltageTooLow);
+        }
+
+        set_core_voltage(voltage, clocks);
+        Ok(())
+    }
+}
+
+/// Combination of system clock and core voltage, see the module documentation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Level {
+    Low,
+    Medium,
+    High,
+    Full,
+}
+
+impl Level {
+    /// Returns the system clock frequency of this level
+    pub fn sysclk(self) -> Hertz {
+        match self {
+            Level::Low => Hertz(RC32M),
+            Level::Medium => Hertz(48_000_000),
+            Level::High => Hertz(120_000_000),
+            Level::Full => Hertz(160_000_000),
+        }
+    }
+
+    /// Returns the core voltage of this level
+    pub fn core_voltage(self) -> CoreVoltage {
+        match self {
+            Level::Low | Level::Medium | Level::High | Level::Full => CoreVoltage::V1_10,
+        }
+    }
+}
+
+/// Switches the system clock and the core voltage to `level`, in the order which keeps the core
+/// supplied sufficiently throughout
+///
+/// The PLL levels use the crystal the PLL was last set up with in `clocks`. When the voltage has
+/// already been raised and switching the clock fails, the voltage stays raised.
+pub fn set_performance(level: Level, clocks: &mut Clocks) -> Result<(), Error> {
+    let source = match level {
+        Level::Low => ClockSource::Rc32m,
+        _ => ClockSource::Pll(clocks.xtal_freq().ok_or(Error::NoCrystal)?),
+    };
+    let voltage = level.core_voltage();
+    // An unknown setting is treated as too low, so it's replaced before speeding up
+    let raise = core_voltage().map_or(true, |current| voltage > current);
+
+    if raise {
+        set_core_voltage(voltage, clocks);
+    }
+    clocks
+        .switch_clock_source(source, level.sysclk())
+        .map_err(Error::Clock)?;
+    if !raise {
+        set_core_voltage(voltage, clocks);
+    }
+
+    Ok(())
+}
+
+fn core_voltage() -> Option<CoreVoltage> {
+    let hbn = unsafe { &*pac::HBN::ptr() };
+
+    match (hbn.hbn_glb.read().bits() >> 16) & 0xf {
+        10 => Some(CoreVoltage::V1_10),
+        11 => Some(CoreVoltage::V1_15),
+        12 => Some(CoreVoltage::V1_20),
+        _ => None,
+    }
+}
+
+/// Sets the LDO11 output and waits for it to settle, timed with the system clock in `clocks`
+fn set_core_voltage(voltage: CoreVoltage, clocks: &Clocks) {
+    let hbn = unsafe { &*pac::HBN::ptr() };
+
+    // sw_ldo11soc_vout_sel_aon
+    hbn.hbn_glb
+        .modify(|r, w| unsafe { w.bits((r.bits() & !(0xf << 16)) | (voltage.bits() << 16)) });
+
+    McycleDelay::new(clocks.sysclk().0)
+        .delay_us(LDO_SETTLE_US)
+        .ok();
+}
+
+/// Number of variants of [`Domain`]
+const DOMAINS: usize = 4;
+
+/// Blocks with a separate enable, see the module documentation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Domain {
+    /// Analog part of the general purpose ADC
+    Adc,
+    /// Both channels of the general purpose DAC
+    Dac,
+    /// Both analog comparators
+    Acomp,
+    /// True random number generator of the security engine
+    Trng,
+}
+
+impl Domain {
+    /// All domains, in the order they are reported
+    pub const ALL: [Domain; DOMAINS] = [Domain::Adc, Domain::Dac, Domain::Acomp, Domain::Trng];
+
+    /// Switches the domain on, if it isn't already, and keeps it on while the guard exists
+    pub fn power_up(self) -> DomainGuard {
+        riscv::interrupt::free(|_| {
+            let users = &DOMAIN_USERS[self as usize];
+            let count = users.load(Ordering::Relaxed);
+            if count == 0 {
+                self.set_powered(true);
+            }
+            users.store(
+                count.checked_add(1).expect("too many guards"),
+                Ordering::Relaxed,
+            );
+        });
+
+        DomainGuard { domain: self }
+    }
+
+    /// Returns whether the enable bits of the domain are set
+    pub fn is_powered(self) -> bool {
+        let aon = unsafe { &*pac::AON::ptr() };
+        let glb = unsafe { &*pac::GLB::ptr() };
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+
+        match self {
+            Domain::Adc => aon.gpadc_reg_cmd.read().gpadc_global_en().bit_is_set(),
+            // gpdac_a_en, gpdac_b_en
+            Domain::Dac => {
+                glb.gpdac_actrl.read().bits() & 1 != 0 || glb.gpdac_bctrl.read().bits() & 1 != 0
+            }
+            // acomp0_en, acomp1_en
+            Domain::Acomp => {
+                aon.acomp0_ctrl.read().bits() & 1 != 0 || aon.acomp1_ctrl.read().bits() & 1 != 0
+            }
+            Domain::Trng => sec_eng.se_trng_0_ctrl_0.read().se_trng_0_en().bit_is_set(),
+        }
+    }
+
+    fn set_powered(self, on: bool) {
+        let aon = unsafe { &*pac::AON::ptr() };
+        let glb = unsafe { &*pac::GLB::ptr() };
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+        let set = |bits: u32| (bits & !1) | on as u32;
+
+        match self {
+            Domain::Adc => aon.gpadc_reg_cmd.modify(|_, w| w.gpadc_global_en().bit(on)),
+            Domain::Dac => {
+                glb.gpdac_actrl
+                    .modify(|r, w| unsafe { w.bits(set(r.bits())) });
+                glb.gpdac_bctrl
+                    .modify(|r, w| unsafe { w.bits(set(r.bits())) });
+            }
+            Domain::Acomp => {
+                aon.acomp0_ctrl
+                    .modify(|r, w| unsafe { w.bits(set(r.bits())) });
+                aon.acomp1_ctrl
+                    .modify(|r, w| unsafe { w.bits(set(r.bits())) });
+            }
+            Domain::Trng => sec_eng
+                .se_trng_0_ctrl_0
+                .modify(|_, w| w.se_trng_0_en().bit(on)),
+        }
+    }
+}
+
+/// Keeps a [`Domain`] switched on, the last guard of a domain switches it off when dropped
+pub struct DomainGuard {
+    domain: Domain,
+}
+
+impl DomainGuard {
+    /// Returns the domain kept on by this guard
+    pub fn domain(&self) -> Domain {
+        self.domain
+    }
+}
+
+impl Drop for DomainGuard {
+    fn drop(&mut self) {
+        riscv::interrupt::free(|_| {
+            let users = &DOMAIN_USERS[self.domain as usize];
+            let count = users.load(Ordering::Relaxed) - 1;
+            users.store(count, Ordering::Relaxed);
+            if count == 0 {
+                self.domain.set_powered(false);
+            }
+        });
+    }
+}
+
+/// Snapshot of the power domains, returned by [`report`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Report {
+    powered: [bool; DOMAINS],
+    users: [u8; DOMAINS],
+}
+
+impl Report {
+    /// Returns whether the enable bits of `domain` were set
+    pub fn is_powered(&self, domain: Domain) -> bool {
+        self.powered[domain as usize]
+    }
+
+    /// Returns how many guards of `domain` existed
+    ///
+    /// A domain which is powered without guards has been switched on outside of the HAL drivers.
+    pub fn users(&self, domain: Domain) -> u8 {
+        self.users[domain as usize]
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, domain) in Domain::ALL.iter().enumerate() {
+            let name = match domain {
+                Domain::Adc => "adc",
+                Domain::Dac => "dac",
+                Domain::Acomp => "acomp",
+                Domain::Trng => "trng",
+            };
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+
+            match (self.is_powered(*domain), self.users(*domain)) {
+                (false, _) => write!(f, "{}: off", name)?,
+                (true, 1) => write!(f, "{}: on (1 user)", name)?,
+                (true, users) => write!(f, "{}: on ({} users)", name, users)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns which domains are switched on right now
+pub fn report() -> Report {
+    riscv::interrupt::free(|_| {
+        let mut report = Report {
+            powered: [false; DOMAINS],
+            users: [0; DOMAINS],
+        };
+        for domain in Domain::ALL.iter() {
+            report.powered[*domain as usize] = domain.is_powered();
+            report.users[*domain as usize] = DOMAIN_USERS[*domain as usize].load(Ordering::Relaxed);
+        }
+
+        report
+    })
+}
+
+/// Sleep mode and cause of a wake up
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WakeFrom {
+    Pds(pds::WakeCause),
+    Hbn(hbn::WakeCause),
+}
+
+/// Timing of a wake up, returned by [`last_wake_stats`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct WakeStats {
+    /// Time it took until the program continued.
+    ///
+    /// After power-down sleep this runs from the first instruction after the sleep until
+    /// `pds::enter` returns, with the clocks and pins restored. After hibernate it runs from the
+    /// RTC wake time until `hbn::wakeup_cause` was called, which includes the boot ROM; it isn't
+    /// known for other wake sources, whose time of wake up isn't recorded.
+    pub time_to_resume: Option<Duration>,
+    /// Part of `time_to_resume` spent starting the crystal and locking the PLL before running
+    /// from flash again, when power-down sleep had stopped them
+    pub time_to_pll_lock: Option<Duration>,
+    /// What the chip woke up from
+    pub cause: WakeFrom,
+    /// Number of wake ups since the program started which had no cause left when checked: a
+    /// wake pin back at its idle level, or no wake source flagged at all
+    ///
+    /// Usually a floating wake pin picking up noise, see
+    /// [`hbn::aon_pad_config`](crate::hbn::aon_pad_config). Pulses shorter than the wake up
+    /// latency, like a UART start bit, are counted as well. Hibernate restarts the program, so
+    /// after it this is 1 if that wake up was spurious and 0 otherwise.
+    pub spurious_wakes: u32,
+}
+
+/// Returns the timing of the last wake up from power-down sleep or hibernate, `None` before the
+/// first one
+///
+/// A wake up from hibernate is only recorded by [`hbn::wakeup_cause`].
+pub fn last_wake_stats() -> Option<WakeStats> {
+    *LAST_WAKE.lock_irq_disabled()
+}
+
+pub(crate) fn record_wake(stats: WakeStats) {
+    *LAST_WAKE.lock_irq_disabled() = Some(stats);
+}
+
+/// Counts a wake up for [`WakeStats::spurious_wakes`] and returns the new total
+pub(crate) fn count_wake(spurious: bool) -> u32 {
+    if spurious {
+        SPURIOUS_WAKES.fetch_add(1, Ordering::Relaxed) + 1
+    } else {
+        SPURIOUS_WAKES.load(Ordering::Relaxed)
+    }
+}
+
+/// Converts `cycles` of a clock running at `freq` to a duration
+pub(crate) fn cycles_to_duration(cycles: u32, freq: u32) -> Duration {
+    Duration::from_nanos(cycles as u64 * 1_000_000_000 / freq as u64)
+}
+
+/// Peripheral brought to rest by a [`SleepAware`] hook
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Peripheral {
+    Uart0,
+    Dma,
+    /// Peripheral handled by the application, with a number of its choice
+    User(u8),
+}
+
+/// Driver state which has to be brought to rest before power-down sleep
+///
+/// Both functions are called without a driver instance, they work on the registers directly.
+pub trait SleepAware {
+    /// Peripheral reported by [`Error::Busy`], a registry entry is kept for each
+    const PERIPHERAL: Peripheral;
+
+    /// Starts or continues bringing the peripheral to rest, e.g. by flushing, parking or aborting
+    /// what it's doing, and returns whether it's at rest now
+    ///
+    /// [`prepare_sleep`] calls this repeatedly until it returns `true`, so it must not block.
+    fn quiesce() -> bool;
+
+    /// Restores what `quiesce` parked, called after the wake up and when preparing failed
+    fn resume() {}
+}
+
+#[derive(Copy, Clone)]
+struct SleepHook {
+    peripheral: Peripheral,
+    quiesce: fn() -> bool,
+    resume: fn(),
+}
+
+/// Registers the hook of `T`, replacing an earlier one of the same peripheral
+pub fn register<T: SleepAware>() -> Result<(), Error> {
+    let hook = SleepHook {
+        peripheral: T::PERIPHERAL,
+        quiesce: T::quiesce,
+        resume: T::resume,
+    };
+    let mut hooks = SLEEP_HOOK_LIST.lock_irq_disabled();
+
+    let index = hooks
+        .iter()
+        .position(|h| matches!(h, Some(h) if h.peripheral == hook.peripheral))
+        .or_else(|| hooks.iter().position(Option::is_none))
+        .ok_or(Error::TooManyHooks)?;
+    hooks[index] = Some(hook);
+
+    Ok(())
+}
+
+/// Removes the hook of `T`'s peripheral, if one is registered
+pub fn unregister<T: SleepAware>() {
+    let mut hooks = SLEEP_HOOK_LIST.lock_irq_disabled();
+
+    for hook in hooks.iter_mut() {
+        if matches!(hook, Some(h) if h.peripheral == T::PERIPHERAL) {
+            *hook = None;
+        }
+    }
+}
+
+/// Runs the [`SleepAware`] hooks until all peripherals are at rest, giving up at `deadline`
+///
+/// The deadline is measured by `mtime`, see [`start_mtime`]. Each hook is asked at least once,
+/// even if the deadline has already passed. When a peripheral is still busy at the deadline, all
+/// resume hooks are run and the first busy peripheral is returned in [`Error::Busy`]. On success
+/// the resume hooks run when the returned [`Quiesced`] is dropped, so keep it until after the
+/// wake up.
+///
+/// The hooks run with interrupts enabled, so interrupt handlers which start new transfers
+/// should be kept from doing so before calling this.
+pub fn prepare_sleep(deadline: Instant) -> Result<Quiesced, Error> {
+    // Copied, so the hooks don't run with the registry locked
+    let hooks = *SLEEP_HOOK_LIST.lock_irq_disabled();
+    let mut at_rest = [false; SLEEP_HOOKS];
+
+    loop {
+        let mut busy = None;
+        for (hook, at_rest) in hooks.iter().zip(at_rest.iter_mut()) {
+            if let Some(hook) = hook {
+                if !*at_rest {
+                    *at_rest = (hook.quiesce)();
+                }
+                if !*at_rest && busy.is_none() {
+                    busy = Some(hook.peripheral);
+                }
+            }
+        }
+
+        match busy {
+            None => return Ok(Quiesced { hooks }),
+            Some(peripheral) if Instant::now() >= deadline => {
+                drop(Quiesced { hooks });
+                return Err(Error::Busy(peripheral));
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+/// Peripherals brought to rest by [`prepare_sleep`], resumed in reverse order when dropped
+pub struct Quiesced {
+    hooks: [Option<SleepHook>; SLEEP_HOOKS],
+}
+
+impl Drop for Quiesced {
+    fn drop(&mut self) {
+        for hook in self.hooks.iter().rev().flatten() {
+            (hook.resume)();
+        }
+    }
+}
+
+/// Halts the enabled DMA channels for applications which program the DMA controller themselves
+///
+/// A halted channel ignores further requests from its peripheral and is at rest once the data in
+/// its FIFO has been written out. Resuming lets it continue where it stopped, so a transfer loses
+/// nothing when power-down sleep keeps the DMA controller's state. Channels which are started
+/// while halted are not covered.
+pub struct DmaHalt;
+
+impl DmaHalt {
+    fn config(channel: usize) -> *mut u32 {
+        (DMA_C0_CONFIG_ADDR + channel * DMA_CHANNEL_STRIDE) as *mut u32
+    }
+}
+
+impl SleepAware for DmaHalt {
+    const PERIPHERAL: Peripheral = Peripheral::Dma;
+
+    fn quiesce() -> bool {
+        let enabled = unsafe { (DMA_ENBLD_CHNS_ADDR as *const u32).read_volatile() };
+        let mut halted = HALTED_DMA.load(Ordering::Relaxed);
+        let mut at_rest = true;
+
+        for channel in 0..DMA_CHANNELS {
+            let config = Self::config(channel);
+            if enabled & (1 << channel) != 0 && halted & (1 << channel) == 0 {
+                // H, halt
+                unsafe { config.write_volatile(config.read_volatile() | (1 << 18)) };
+                halted |= 1 << channel;
+            }
+            // A, active: data left in the FIFO
+            if halted & (1 << channel) != 0 && unsafe { config.read_volatile() } & (1 << 17) != 0 {
+                at_rest = false;
+            }
+        }
+        HALTED_DMA.store(halted, Ordering::Relaxed);
+
+        at_rest
+    }
+
+    fn resume() {
+        let halted = HALTED_DMA.swap(0, Ordering::Relaxed);
+
+        for channel in 0..DMA_CHANNELS {
+            if halted & (1 << channel) != 0 {
+                let config = Self::config(channel);
+                unsafe { config.write_volatile(config.read_volatile() & !(1 << 18)) };
+            }
+        }
+    }
+}
+
+/// Why the program started, see the module documentation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ResetReason {
+    /// Power-on, the reset pin, a reset by the brown-out detector in
+    /// [`BodMode::Reset`](crate::hbn::BodMode::Reset), or any other reset the HAL doesn't mark
+    PowerOn,
+    /// The watchdog expired
+    Watchdog,
+    /// [`software_reset`] was called
+    Software,
+    /// [`reset_to_bootloader`] was called
+    Bootloader,
+    /// Woken from hibernate
+    Hibernate(hbn::WakeCause),
+    /// [`Bod::reset`](crate::hbn::Bod::reset) was called after the brown-out detector triggered
+    BrownOut,
+}
+
+/// Raw state which [`ResetReason`] is decoded from
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ResetFlags {
+    /// Value of `HBN_RSV0`, where the HAL leaves a marker before the resets it triggers
+    pub hbn_rsv0: u32,
+    /// Value of `HBN_IRQ_STAT`, the flagged wake sources
+    pub hbn_irq_stat: u32,
+    /// The watchdog's WTS flag
+    pub watchdog: bool,
+}
+
+impl ResetFlags {
+    /// Reads the flags from the registers
+    pub fn read() -> Self {
+        let hbn = unsafe { &*pac::HBN::ptr() };
+
+        ResetFlags {
+            hbn_rsv0: hbn.hbn_rsv0.read().bits(),
+            hbn_irq_stat: hbn.hbn_irq_stat.read().bits(),
+            watchdog: watchdog::reset_flag(),
+        }
+    }
+
+    /// Decodes the reset reason
+    ///
+    /// The watchdog flag takes precedence over the markers. A marker stays in `HBN_RSV0` until
+    /// it's cleared, so it may be left over from an earlier reset, while the HAL clears the
+    /// watchdog flag right before writing a marker, and hibernate powers the watchdog off, which
+    /// clears it as well. Both together mean the watchdog expired after the marked reset.
+    pub fn decode(&self) -> ResetReason {
+        if self.watchdog {
+            return ResetReason::Watchdog;
+        }
+
+        match self.hbn_rsv0 {
+            hbn::SOFTWARE_RESET_FLAG => ResetReason::Software,
+            hbn::BOOTLOADER_FLAG => ResetReason::Bootloader,
+            hbn::BOD_RESET_FLAG => ResetReason::BrownOut,
+            hbn::HBN_ENTER_FLAG => {
+                ResetReason::Hibernate(hbn::decode_wake_cause(self.hbn_irq_stat))
+            }
+            _ => ResetReason::PowerOn,
+        }
+    }
+}
+
+/// Returns why the program started, without clearing anything
+pub fn reset_reason() -> ResetReason {
+    ResetFlags::read().decode()
+}
+
+/// Clears the watchdog flag, the wake flags and the marker of the last reset, so the next reset
+/// reason isn't mixed up with this one
+pub fn clear_reset_reason() {
+    let hbn = unsafe { &*pac::HBN::ptr() };
+
+    watchdog::clear_reset_flag();
+    hbn::clear_flags(hbn);
+}
+
+/// Resets the chip, which starts the program again with [`ResetReason::Software`]
+pub fn software_reset() -> ! {
+    reset_with_marker(hbn::SOFTWARE_RESET_FLAG)
+}
+
+/// Resets the chip, which starts the program again with [`ResetReason::Bootloader`]
+///
+/// The boot ROM has no known flag which makes it enter its own download mode, that is only
+/// selected by the boot pin, so the marker is meant for a bootloader or update loader in the
+/// application: it checks for [`ResetReason::Bootloader`] at startup and stays in update mode.
+pub fn reset_to_bootloader() -> ! {
+    reset_with_marker(hbn::BOOTLOADER_FLAG)
+}
+
+fn reset_with_marker(marker: u32) -> ! {
+    let hbn = unsafe { &*pac::HBN::ptr() };
+
+    unsafe { riscv::interrupt::disable() };
+    // A flag left over from an earlier reset would override the marker
+    watchdog::clear_reset_flag();
+    hbn.hbn_rsv0.write(|w| unsafe { w.bits(marker) });
+
+    // The boot ROM expects to start from the RC oscillator
+    glb_set_system_clk_rc32();
+    hbn::chip_reset()
+}
+
+/// What happens to the watchdog during power-down sleep, see the module documentation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WdtPolicy {
+    /// Stops the counter while sleeping, and feeds and restarts it right after waking up
+    PauseDuringSleep,
+    /// Raises the timeout to the sleep duration plus `margin` and feeds the watchdog, then puts
+    /// the timeout back and feeds it again after waking up
+    ///
+    /// The margin has to cover the wake up latency. Needs a sleep duration within the 16 bit
+    /// match register.
+    ExtendToCoverSleep { margin: Duration },
+    /// Feeds the watchdog right before sleeping and leaves it alone otherwise
+    ///
+    /// A sleep duration longer than the timeout is refused. Without a duration the watchdog
+    /// bounds the sleep, resetting the chip if no wake pin triggers in time.
+    KeepRunning,
+}
+
+/// How to put the watchdog back after the sleep, returned by [`watchdog_before_sleep`]
+pub(crate) enum WdtResume {
+    Start,
+    Restore(u16),
+    Nothing,
+}
+
+/// Returns whether `policy` keeps `watchdog` from expiring during a sleep of `duration`, which
+/// is checked before anything is changed for the sleep
+pub(crate) fn watchdog_covers(
+    watchdog: &ConfiguredWatchdog0,
+    policy: WdtPolicy,
+    duration: Option<Duration>,
+) -> bool {
+    if !watchdog.is_running() {
+        return true;
+    }
+
+    match (policy, duration) {
+        (WdtPolicy::PauseDuringSleep, _) => true,
+        (WdtPolicy::ExtendToCoverSleep { margin }, Some(duration)) => {
+            watchdog.ticks_for(duration + margin) <= u16::MAX as u64
+        }
+        (WdtPolicy::ExtendToCoverSleep { .. }, None) => false,
+        (WdtPolicy::KeepRunning, Some(duration)) => {
+            watchdog.ticks_for(duration) < watchdog.get_match_ticks() as u64
+        }
+        (WdtPolicy::KeepRunning, None) => true,
+    }
+}
+
+/// Applies `policy` right before sleeping, after [`watchdog_covers`] has accepted it
+pub(crate) fn watchdog_before_sleep(
+    watchdog: &ConfiguredWatchdog0,
+    policy: WdtPolicy,
+    duration: Option<Duration>,
+) -> WdtResume {
+    if !watchdog.is_running() {
+        return WdtResume::Nothing;
+    }
+
+    match policy {
+        WdtPolicy::PauseDuringSleep => {
+            watchdog.set_running(false);
+            WdtResume::Start
+        }
+        WdtPolicy::ExtendToCoverSleep { margin } => {
+            let saved = watchdog.get_match_ticks();
+            let needed = watchdog.ticks_for(duration.unwrap_or_default() + margin) as u16;
+            // Feed first, so the counter can't be past the new match when it's written
+            watchdog.restart_counter();
+            watchdog.set_match_ticks(needed.max(saved));
+            WdtResume::Restore(saved)
+        }
+        WdtPolicy::KeepRunning => {
+            watchdog.restart_counter();
+            WdtResume::Nothing
+        }
+    }
+}
+
+/// Puts the watchdog back after waking up, as the first thing after the sleep
+pub(crate) fn watchdog_after_sleep(watchdog: &ConfiguredWatchdog0, resume: WdtResume) {
+    match resume {
+        WdtResume::Start => {
+            watchdog.restart_counter();
+            watchdog.set_running(true);
+        }
+        WdtResume::Restore(ticks) => {
+            watchdog.restart_counter();
+            watchdog.set_match_ticks(ticks);
+        }
+        WdtResume::Nothing => {}
+    }
+}
+
+#[cfg(all(test, feature = "mock-registers"))]
+mod tests {
+    use super::*;
+    use crate::regs;
+    use crate::timer::TimerExt;
+    use crate::watchdog::WdtClockSource;
+
+    #[test]
+    fn watchdog_policies() {
+        let _registers = regs::lock();
+        let timers = unsafe { pac::Peripherals::steal() }.TIMER.split();
+        // 125 ticks per second, expiring after one
+        let watchdog = timers
+            .watchdog
+            .set_clock_source(WdtClockSource::Rc32Khz, Hertz(125));
+        watchdog.set_match_ticks(125);
+        watchdog.set_running(true);
+
+        let long = Some(Duration::from_secs(3));
+        let short = Some(Duration::from_millis(200));
+        let pause = WdtPolicy::PauseDuringSleep;
+        let keep = WdtPolicy::KeepRunning;
+        let extend = WdtPolicy::ExtendToCoverSleep {
+            margin: Duration::from_millis(100),
+        };
+        assert!(watchdog_covers(&watchdog, pause, long));
+        assert!(watchdog_covers(&watchdog, pause, None));
+        assert!(!watchdog_covers(&watchdog, keep, long));
+        assert!(watchdog_covers(&watchdog, keep, short));
+        assert!(watchdog_covers(&watchdog, extend, long));
+        assert!(!watchdog_covers(&watchdog, extend, None));
+        // The match register ends at 65535 ticks, 524 s
+        let too_long = Some(Duration::from_secs(525));
+        assert!(!watchdog_covers(&watchdog, extend, too_long));
+
+        let resume = watchdog_before_sleep(&watchdog, pause, long);
+        assert!(!watchdog.is_running());
+        watchdog_after_sleep(&watchdog, resume);
+        assert!(watchdog.is_running());
+        assert_eq!(watchdog.get_match_ticks(), 125);
+
+        // 3.1 s are 387.5 ticks, rounded up
+        let resume = watchdog_before_sleep(&watchdog, extend, long);
+        assert_eq!(watchdog.get_match_ticks(), 388);
+        assert!(watchdog.is_running());
+        watchdog_after_sleep(&watchdog, resume);
+        assert_eq!(watchdog.get_match_ticks(), 125);
+
+        // A stopped watchdog can't expire, and is left stopped
+        watchdog.set_running(false);
+        assert!(watchdog_covers(&watchdog, keep, long));
+        let resume = watchdog_before_sleep(&watchdog, pause, long);
+        watchdog_after_sleep(&watchdog, resume);
+        assert!(!watchdog.is_running());
+    }
+}
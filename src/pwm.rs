@@ -23,6 +23,33 @@
 //!
 //! // Control PWM and its settings via the `pwm` object
 //! ```
+//!
+//! # Synchronized start
+//!
+//! [`Channels::global_sync_start`] restarts all channels at once, so channels with the same
+//! period keep a fixed phase relationship, e.g. for the three phases of a motor:
+//!
+//! ```no_run
+//! // set clock, period and duty of channels 0 to 2 first
+//! channels.global_sync_start();
+//! ```
+//!
+//! # Capture
+//!
+//! [`PwmCapture`] measures an external PWM signal with the interrupt of an input pin, taking
+//! `mcycle` timestamps of its edges. Call [`PwmCapture::on_interrupt`] from the `Gpio` handler.
+//!
+//! ```no_run
+//! # use crate::pwm::PwmCapture;
+//! let mut capture = PwmCapture::new(parts.pin3.into_floating_input(), &clocks);
+//!
+//! // in the Gpio handler
+//! capture.on_interrupt();
+//!
+//! if let (Some(duty), Some(freq)) = (capture.get_duty_cycle(), capture.get_frequency_hz()) {
+//!     // duty is between 0.0 and 1.0
+//! }
+//! ```
 
 use core::convert::{Infallible, TryInto};
 use embedded_hal::pwm::blocking::Pwm as PwmTrait;
@@ -32,7 +59,19 @@ use embedded_time::{
     rate::Hertz,
 };
 
-use crate::{clock::Clocks, pac};
+use crate::{
+    clock::Clocks,
+    delay::McycleDelay,
+    gpio::{Event, InterruptPin},
+    pac,
+};
+
+/// `pwm_stop_en` in the configuration register of a channel
+const STOP_EN: u32 = 1 << 6;
+
+/// How often [`Channels::global_sync_start`] checks whether a channel has stopped, so a channel
+/// without a clock doesn't hang it
+const SYNC_STOP_POLLS: u32 = 100_000;
 
 macro_rules! per_channel {
     ( $($channel:literal),* ) => { paste::paste!{
@@ -49,6 +88,43 @@ macro_rules! per_channel {
             }
         )+
 
+        impl Channels {
+            /// Restarts all channels so their counters run in step
+            ///
+            /// The PWM has no start bit shared by the channels, each one is started by clearing
+            /// `stop_en` in its own configuration register. All channels are stopped first, then
+            /// the five registers are written back to back with interrupts disabled, from values
+            /// prepared before. The counters therefore start a few bus cycles apart, the same
+            /// few cycles every time, and keep that offset as long as the channels have the same
+            /// clock, divider and period. Set those up before calling this.
+            pub fn global_sync_start(&mut self) {
+                let pwm = unsafe { &*pac::PWM::ptr() };
+
+                $(
+                    pwm.[<pwm $channel _config>].modify(|_, w| w.pwm_stop_en().set_bit());
+                )+
+                // The stop takes effect with the next cycle of the channel's clock
+                $(
+                    for _ in 0..SYNC_STOP_POLLS {
+                        if pwm.[<pwm $channel _config>].read().pwm_sts_top().bit_is_set() {
+                            break;
+                        }
+                    }
+                )+
+
+                let mut start = [0u32; 5];
+                $(
+                    start[$channel] = pwm.[<pwm $channel _config>].read().bits() & !STOP_EN;
+                )+
+
+                riscv::interrupt::free(|_| {
+                    $(
+                        pwm.[<pwm $channel _config>].write(|w| unsafe { w.bits(start[$channel]) });
+                    )+
+                });
+            }
+        }
+
         impl From<(pac::PWM, Clocks)> for Channels {
             fn from(other: (pac::PWM, Clocks)) -> Self {
                 Self {
@@ -61,6 +137,7 @@ macro_rules! per_channel {
         }
 
         $(impl PwmTrait for [<Channel $channel>] {
+            // Nothing here can fail, and embedded-hal has no error kinds for PWM anyway
             type Error = Infallible;
             type Channel = ();
             type Time = Milliseconds<u64>;
@@ -198,7 +275,141 @@ macro_rules! per_channel {
                 Ok(())
             }
         })+
+
+        $(impl [<Channel $channel>] {
+            /// Returns the number of bits of duty cycle resolution at the configured period
+            ///
+            /// The duty cycle is compared against the period counter, which counts the clock
+            /// after the divider, so there are as many duty steps as the period is long:
+            /// floor(log2(period)). The divider `set_period` picks for long periods costs
+            /// resolution, e.g. a period of 1000 divided clocks gives 9 bits. The period register
+            /// has 16 bits, so the result is at most 15.
+            pub fn duty_resolution_bits(&self) -> u8 {
+                let period = self
+                    .pwm
+                    .[<pwm $channel _period>]
+                    .read()
+                    .pwm_period()
+                    .bits();
+                let bits = match period {
+                    0 => 0,
+                    n => (15 - n.leading_zeros()) as u8,
+                };
+
+                #[cfg(feature = "defmt")]
+                if bits < 8 {
+                    defmt::warn!(
+                        "PWM channel {} has only {} bits of duty resolution",
+                        $channel,
+                        bits
+                    );
+                }
+
+                bits
+            }
+        })+
     }}
 }
 
 per_channel!(0, 1, 2, 3, 4);
+
+/// Edge a [`PwmCapture`] waits for next
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Edge {
+    Rising,
+    Falling,
+}
+
+/// Measures the duty cycle and frequency of a PWM signal on an input pin
+///
+/// The timestamps are taken in the interrupt handler, so interrupt latency and jitter limit the
+/// precision. The shortest high or low time which can be measured is the time the handler takes
+/// to switch the pin to the other edge, signals with shorter pulses miss edges and report a
+/// wrong measurement.
+pub struct PwmCapture<PIN: InterruptPin> {
+    pin: PIN,
+    core_frequency: u32,
+    edge: Edge,
+    t_rise: Option<u64>,
+    t_fall: Option<u64>,
+    /// High time and period of the last complete period, in core clock cycles
+    last: Option<(u64, u64)>,
+}
+
+impl<PIN: InterruptPin> PwmCapture<PIN> {
+    /// Starts capturing on `pin`, whose interrupt is enabled for the rising edge
+    ///
+    /// The `Gpio` interrupt has to be enabled as well for [`on_interrupt`](Self::on_interrupt)
+    /// to be called.
+    pub fn new(mut pin: PIN, clocks: &Clocks) -> Self {
+        pin.trigger_on_event(Event::PositivePulse);
+        pin.control_asynchronous();
+        pin.clear_interrupt_pending_bit();
+        pin.enable_interrupt();
+
+        PwmCapture {
+            pin,
+            core_frequency: clocks.sysclk().0,
+            edge: Edge::Rising,
+            t_rise: None,
+            t_fall: None,
+            last: None,
+        }
+    }
+
+    /// Records the edge which raised the pin's interrupt and arms the other one, call this from
+    /// the `Gpio` handler
+    ///
+    /// Does nothing if the pin's interrupt isn't pending, so handlers shared with other pins can
+    /// call it unconditionally.
+    pub fn on_interrupt(&mut self) {
+        if !self.pin.check_interrupt() {
+            return;
+        }
+        let now = McycleDelay::get_cycle_count();
+        self.pin.clear_interrupt_pending_bit();
+
+        match self.edge {
+            Edge::Rising => {
+                if let (Some(t_rise), Some(t_fall)) = (self.t_rise, self.t_fall) {
+                    self.last = Some((t_fall - t_rise, now - t_rise));
+                }
+                self.t_rise = Some(now);
+                self.t_fall = None;
+                self.edge = Edge::Falling;
+                self.pin.trigger_on_event(Event::NegativePulse);
+            }
+            Edge::Falling => {
+                self.t_fall = Some(now);
+                self.edge = Edge::Rising;
+                self.pin.trigger_on_event(Event::PositivePulse);
+            }
+        }
+    }
+
+    /// Returns the ratio of high time to period of the last complete period, `None` until a
+    /// full period has been captured
+    pub fn get_duty_cycle(&self) -> Option<f32> {
+        self.last.map(|(high, period)| high as f32 / period as f32)
+    }
+
+    /// Returns the frequency of the last complete period, `None` until a full period has been
+    /// captured
+    pub fn get_frequency_hz(&self) -> Option<u32> {
+        self.last
+            .map(|(_, period)| (self.core_frequency as u64 / period) as u32)
+    }
+
+    /// Forgets the captured edges, e.g. after the signal changed
+    pub fn reset(&mut self) {
+        self.t_rise = None;
+        self.t_fall = None;
+        self.last = None;
+    }
+
+    /// Stops capturing and returns the pin, with its interrupt disabled
+    pub fn free(mut self) -> PIN {
+        self.pin.disable_interrupt();
+        self.pin
+    }
+}
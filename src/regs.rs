@@ -0,0 +1,196 @@
+/*!
+  # Register blocks
+  Drivers reach their registers through the functions of this module rather than through
+  `pac::X::ptr()`. On the chip they return the blocks at their addresses, which is all they
+  compile to.
+
+  With the `mock-registers` feature they return register files in RAM instead, so the drivers
+  can run on the PC, e.g. in `cargo test`. A test takes [`lock`], which clears the files and
+  keeps other tests out of them, sets up what the driver reads, runs the driver and checks what
+  it wrote. Nothing behind the registers reacts, a driver waiting for a status bit waits
+  forever unless the test sets it first. `mstatus.MIE`, which the implementation of
+  `critical_section` saves and restores, is a flag in RAM as well. The drivers of the GPIOs, the
+  UART, the timers, the watchdog, the DMA helpers and the software interrupt use this module so
+  far, the others still use the PAC directly.
+
+  ## Example
+  ```rust
+    // cargo test --features mock-registers --target x86_64-unknown-linux-gnu
+    let _registers = bl602_hal::regs::lock();
+    let dp = unsafe { pac::Peripherals::steal() };
+    let mut parts = dp.GLB.split();
+
+    let _pin = parts.pin2.into_pull_up_input();
+    assert_eq!(bl602_hal::regs::glb().gpio_cfgctl1.read().bits() & 0x1f, 0x11);
+  ```
+*/
+
+use crate::pac;
+
+macro_rules! register_blocks {
+    ($($(#[$doc:meta])* $name:ident: $PERIPH:ident, $block:ident;)+) => {
+        $(
+            $(#[$doc])*
+            #[cfg(not(feature = "mock-registers"))]
+            #[inline(always)]
+            pub(crate) fn $name() -> &'static pac::$block::RegisterBlock {
+                unsafe { &*pac::$PERIPH::ptr() }
+            }
+
+            $(#[$doc])*
+            #[cfg(feature = "mock-registers")]
+            pub fn $name() -> &'static pac::$block::RegisterBlock {
+                mock::$name()
+            }
+        )+
+
+        #[cfg(feature = "mock-registers")]
+        mod mock {
+            use super::pac;
+            use core::cell::UnsafeCell;
+            use core::mem::size_of;
+
+            /// Register file in RAM, an array of as many words as the block it stands in for
+            pub(super) struct RegisterFile<T>(UnsafeCell<T>);
+
+            // The registers are `VolatileCell`s, which are shared between threads on the chip
+            // as well
+            unsafe impl<T> Sync for RegisterFile<T> {}
+
+            impl<T> RegisterFile<T> {
+                const fn new(words: T) -> Self {
+                    RegisterFile(UnsafeCell::new(words))
+                }
+
+                pub(super) fn address(&self) -> usize {
+                    self.0.get() as usize
+                }
+
+                pub(super) fn clear(&self) {
+                    let words = self.0.get() as *mut u32;
+                    for i in 0..size_of::<T>() / 4 {
+                        unsafe { words.add(i).write_volatile(0) };
+                    }
+                }
+            }
+
+            const fn words(bytes: usize) -> usize {
+                (bytes + 3) / 4
+            }
+
+            paste::paste! {
+                $(
+                    pub(super) static [<$name:upper>]: RegisterFile<
+                        [u32; words(size_of::<pac::$block::RegisterBlock>())],
+                    > = RegisterFile::new([0; words(size_of::<pac::$block::RegisterBlock>())]);
+
+                    pub(super) fn $name() -> &'static pac::$block::RegisterBlock {
+                        unsafe { &*([<$name:upper>].address() as *const _) }
+                    }
+                )+
+
+                /// The DMA helpers address their registers by offset
+                pub(super) static DMA: RegisterFile<[u32; words(0x500)]> =
+                    RegisterFile::new([0; words(0x500)]);
+
+                /// `msip` of the CLINT, a word of its own
+                pub(super) static MSIP: RegisterFile<[u32; 1]> = RegisterFile::new([0]);
+
+                pub(super) fn clear() {
+                    $( [<$name:upper>].clear(); )+
+                    DMA.clear();
+                    MSIP.clear();
+                }
+            }
+        }
+    };
+}
+
+register_blocks! {
+    /// Returns the GLB registers, the GPIOs and clocks
+    glb: GLB, glb;
+    /// Returns the UART0 registers
+    uart: UART, uart;
+    /// Returns the timer registers
+    timer: TIMER, timer;
+}
+
+/// Returns the address of the DMA controller registers
+#[cfg(not(feature = "mock-registers"))]
+#[inline(always)]
+pub(crate) fn dma_base() -> usize {
+    0x4000_c000
+}
+
+/// Returns the address of the DMA controller registers
+#[cfg(feature = "mock-registers")]
+pub(crate) fn dma_base() -> usize {
+    mock::DMA.address()
+}
+
+/// Returns the address of `msip` of hart 0, which pends the software interrupt
+#[cfg(not(feature = "mock-registers"))]
+#[inline(always)]
+pub(crate) fn msip() -> usize {
+    0x0200_0000
+}
+
+/// Returns the address of `msip` of hart 0, which pends the software interrupt
+#[cfg(feature = "mock-registers")]
+pub(crate) fn msip() -> usize {
+    mock::MSIP.address()
+}
+
+/// Returns whether interrupts are enabled in `mstatus`
+#[cfg(all(feature = "critical-section-impl", not(feature = "mock-registers")))]
+#[inline(always)]
+pub(crate) fn interrupts_enabled() -> bool {
+    riscv::register::mstatus::read().mie()
+}
+
+/// Enables or disables interrupts in `mstatus`
+#[cfg(all(feature = "critical-section-impl", not(feature = "mock-registers")))]
+#[inline(always)]
+pub(crate) unsafe fn set_interrupts_enabled(enabled: bool) {
+    if enabled {
+        riscv::interrupt::enable();
+    } else {
+        riscv::interrupt::disable();
+    }
+}
+
+/// Stands in for `mstatus.MIE`, the host has no interrupts to mask
+#[cfg(all(feature = "critical-section-impl", feature = "mock-registers"))]
+static MIE: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+/// Returns whether interrupts are enabled in `mstatus`
+#[cfg(all(feature = "critical-section-impl", feature = "mock-registers"))]
+pub(crate) fn interrupts_enabled() -> bool {
+    MIE.load(core::sync::atomic::Ordering::SeqCst)
+}
+
+/// Enables or disables interrupts in `mstatus`
+#[cfg(all(feature = "critical-section-impl", feature = "mock-registers"))]
+pub(crate) unsafe fn set_interrupts_enabled(enabled: bool) {
+    MIE.store(enabled, core::sync::atomic::Ordering::SeqCst)
+}
+
+/// Clears all register files and returns a guard which keeps other tests out of them
+///
+/// Tests run in parallel threads by default, but there's only one set of register files, so
+/// every test touching them holds this guard. The pins configured by a test are remembered by
+/// the debug check of the GPIO type states, so another test using the same pin finds its
+/// function cleared and panics; tests use pins of their own.
+#[cfg(feature = "mock-registers")]
+pub fn lock() -> std::sync::MutexGuard<'static, ()> {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    // A failed test poisons the lock, which the others don't care about
+    let guard = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    mock::clear();
+    #[cfg(feature = "critical-section-impl")]
+    unsafe {
+        set_interrupts_enabled(false)
+    };
+    guard
+}
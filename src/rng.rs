@@ -0,0 +1,558 @@
+/*!
+  # True Random Number Generator
+  The SEC_ENG block contains a true random number generator (TRNG) which produces a block of
+  256 bits of entropy each time it is triggered. The driver implements
+  [`rand_core::RngCore`] and [`rand_core::CryptoRng`], so it can be used directly as an entropy
+  source or to seed a software CSPRNG.
+
+  ## Example
+  ```rust
+    use bl602_hal::sec_eng::SecEngExt;
+    use rand_core::RngCore;
+
+    let sec_eng = dp.SEC_ENG.split();
+    let mut trng = hal::rng::Trng::new(sec_eng.trng, &mut parts.clk_cfg).unwrap();
+
+    let mut nonce = [0u8; 12];
+    trng.try_fill_bytes(&mut nonce).unwrap();
+  ```
+
+  # Throughput
+  Every trigger yields one 256 bit block which is buffered by the driver, so requesting fewer
+  than 32 bytes at a time does not waste entropy. The time per block is the time the engine
+  stays busy after a trigger, which depends on the SEC_ENG clock; it is bounded by
+  [`TRNG_TIMEOUT`] polling iterations. Use
+  [`McycleDelay::cycles_since`](crate::delay::McycleDelay::cycles_since) around
+  [`Trng::try_fill_bytes`] to measure it on your board.
+
+  # Health checks
+  The engine runs a health test on its noise source and flags failures, which are reported as
+  [`Error::HealthCheck`]. In addition, the driver rejects blocks where two consecutive 32 bit
+  words are identical ([`Error::StuckOutput`]): for a working source this has a chance of about
+  2<sup>-32</sup> per word, while a stuck source triggers it on the first block.
+
+  # Global generator
+  Code which can't be handed the driver, e.g. a protocol crate, can use the free functions
+  [`random_u32`], [`random_range`] and [`jitter_delay`] once the driver has been installed with
+  [`init`]. Each call takes the driver with interrupts disabled, so it is safe to use from
+  interrupt handlers as well, but may keep interrupts masked until the TRNG delivers a block.
+
+  ```rust
+    let trng = hal::rng::Trng::new(sec_eng.trng, &mut parts.clk_cfg).unwrap();
+    hal::rng::init(trng);
+
+    // Wait between 50 and 200 µs before answering, so the response time doesn't reveal anything
+    let mut delay = McycleDelay::new(clocks.sysclk().0);
+    hal::rng::jitter_delay(&mut delay, 50..200).unwrap();
+  ```
+*/
+
+use core::convert::Infallible;
+use core::num::NonZeroU32;
+use core::ops::Range;
+
+use embedded_hal::delay::blocking::DelayUs;
+
+use rand_core::{CryptoRng, RngCore};
+
+use crate::clock::{glb_ahb_slave1_clock_enable, AhbSlave1};
+use crate::delay::McycleDelay;
+use crate::gpio::ClkCfg;
+use crate::pac;
+use crate::power::{Domain, DomainGuard};
+use crate::sec_eng::Trng0;
+use crate::sync::SpinLock;
+
+/// Number of polling iterations to wait for the TRNG to become idle before giving up
+pub const TRNG_TIMEOUT: u32 = 100_000;
+
+/// TRNG error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The TRNG did not finish generating a block in time
+    Timeout,
+    /// The hardware health test of the noise source failed
+    HealthCheck,
+    /// The TRNG produced the same word twice in a row
+    StuckOutput,
+    /// The TRNG is not enabled
+    Disabled,
+    /// No driver has been installed with [`init`]
+    NotInitialized,
+}
+
+impl Error {
+    fn code(&self) -> u32 {
+        match self {
+            Error::Timeout => 1,
+            Error::HealthCheck => 2,
+            Error::StuckOutput => 3,
+            Error::Disabled => 4,
+            Error::NotInitialized => 5,
+        }
+    }
+}
+
+impl From<Error> for rand_core::Error {
+    fn from(error: Error) -> Self {
+        // Custom error codes must be above `CUSTOM_START`, so this never is zero
+        let code = NonZeroU32::new(rand_core::Error::CUSTOM_START + error.code()).unwrap();
+        rand_core::Error::from(code)
+    }
+}
+
+/// True random number generator
+pub struct Trng {
+    trng: Trng0,
+    _power: DomainGuard,
+    buffer: [u32; 8],
+    available: usize,
+    last_word: Option<u32>,
+}
+
+impl Trng {
+    /// Enables the TRNG and returns a ready to use driver.
+    ///
+    /// The first block generated after enabling the engine is discarded.
+    pub fn new(trng: Trng0, _clk_cfg: &mut ClkCfg) -> Result<Self, Error> {
+        glb_ahb_slave1_clock_enable(AhbSlave1::Sec, true);
+
+        let power = Domain::Trng.power_up();
+
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+        sec_eng
+            .se_trng_0_ctrl_0
+            .modify(|_, w| w.se_trng_0_int_clr_1t().set_bit());
+        wait_idle()?;
+        sec_eng
+            .se_trng_0_ctrl_0
+            .modify(|_, w| w.se_trng_0_int_clr_1t().clear_bit());
+
+        let mut rng = Trng {
+            trng,
+            _power: power,
+            buffer: [0; 8],
+            available: 0,
+            last_word: None,
+        };
+
+        // Discard the first block, it may have been generated before the noise source settled
+        let mut block = [0; 8];
+        read_block(&mut block)?;
+        rng.last_word = Some(block[7]);
+
+        Ok(rng)
+    }
+
+    /// Fills `dest` with random bytes
+    pub fn fill(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        for chunk in dest.chunks_mut(4) {
+            let word = self.next_word()?;
+            chunk.copy_from_slice(&word.to_le_bytes()[..chunk.len()]);
+        }
+
+        Ok(())
+    }
+
+    /// Disables the TRNG and releases the engine
+    pub fn free(self) -> Trng0 {
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+        sec_eng
+            .se_trng_0_ctrl_0
+            .modify(|_, w| w.se_trng_0_dout_clr_1t().set_bit());
+        sec_eng
+            .se_trng_0_ctrl_0
+            .modify(|_, w| w.se_trng_0_dout_clr_1t().clear_bit());
+
+        // Dropping the guard disables the TRNG
+        self.trng
+    }
+
+    fn next_word(&mut self) -> Result<u32, Error> {
+        if self.available == 0 {
+            read_block(&mut self.buffer)?;
+
+            // Continue the stuck output check across block boundaries
+            if self.last_word == Some(self.buffer[0]) {
+                return Err(Error::StuckOutput);
+            }
+            self.last_word = Some(self.buffer[7]);
+            self.available = self.buffer.len();
+        }
+
+        self.available -= 1;
+        let word = self.buffer[self.available];
+        // Don't keep handed out entropy in RAM
+        self.buffer[self.available] = 0;
+
+        Ok(word)
+    }
+}
+
+impl RngCore for Trng {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    /// Fills `dest` with random bytes
+    ///
+    /// # Panics
+    ///
+    /// Panics if the TRNG reports an error, use `try_fill_bytes` to handle it instead.
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.fill(dest).unwrap()
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill(dest).map_err(Into::into)
+    }
+}
+
+impl CryptoRng for Trng {}
+
+/// Fills `dest` with random bytes from the TRNG without going through a [`Trng`] instance.
+///
+/// This is meant for code which does not own the driver, e.g. a `getrandom` backend. The TRNG
+/// must have been enabled with [`Trng::new`] before, otherwise [`Error::Disabled`] is returned.
+pub fn random_bytes(dest: &mut [u8]) -> Result<(), Error> {
+    let mut block = [0u32; 8];
+
+    for chunk in dest.chunks_mut(32) {
+        read_block(&mut block)?;
+        for (bytes, word) in chunk.chunks_mut(4).zip(block.iter()) {
+            bytes.copy_from_slice(&word.to_le_bytes()[..bytes.len()]);
+        }
+    }
+    block.iter_mut().for_each(|w| *w = 0);
+
+    Ok(())
+}
+
+/// Driver used by the free functions, installed by [`init`]
+static GLOBAL_TRNG: SpinLock<Option<Trng>> = SpinLock::new(None);
+
+/// Installs `trng` as the generator used by [`random_u32`], [`random_range`] and
+/// [`jitter_delay`]
+///
+/// A previously installed driver is returned.
+pub fn init(trng: Trng) -> Option<Trng> {
+    GLOBAL_TRNG.lock_irq_disabled().replace(trng)
+}
+
+/// Removes the driver installed with [`init`], so it can be freed or used directly again
+pub fn release() -> Option<Trng> {
+    GLOBAL_TRNG.lock_irq_disabled().take()
+}
+
+/// Returns a random word from the driver installed with [`init`]
+pub fn random_u32() -> Result<u32, Error> {
+    match GLOBAL_TRNG.lock_irq_disabled().as_mut() {
+        Some(trng) => trng.next_word(),
+        None => Err(Error::NotInitialized),
+    }
+}
+
+/// Returns a uniformly distributed number in `range` from the driver installed with [`init`]
+///
+/// # Panics
+///
+/// Panics if `range` is empty.
+pub fn random_range(range: Range<u32>) -> Result<u32, Error> {
+    match GLOBAL_TRNG.lock_irq_disabled().as_mut() {
+        Some(trng) => sample_range(range, || trng.next_word()),
+        None => Err(Error::NotInitialized),
+    }
+}
+
+/// Waits for a uniformly distributed number of microseconds in `range_us`, which is returned
+///
+/// The random number comes from the driver installed with [`init`], which is not held while
+/// waiting. Note that the drawing itself takes a varying amount of time as well, since the TRNG
+/// only needs to be triggered every eighth call.
+///
+/// # Panics
+///
+/// Panics if `range_us` is empty.
+pub fn jitter_delay(delay: &mut McycleDelay, range_us: Range<u32>) -> Result<u32, Error> {
+    let us = random_range(range_us)?;
+    delay.delay_us(us).unwrap();
+
+    Ok(us)
+}
+
+/// Returns a uniformly distributed number in `range` drawn from `rng`
+///
+/// Taking the remainder of a random word would favour small results whenever the size of the
+/// range doesn't divide 2<sup>32</sup>. Instead, the words at the bottom which would cause that
+/// bias are rejected and another word is drawn, which happens with a probability below 1/2.
+///
+/// # Panics
+///
+/// Panics if `range` is empty.
+pub fn gen_range<R: RngCore + ?Sized>(rng: &mut R, range: Range<u32>) -> u32 {
+    match sample_range(range, || Ok::<_, Infallible>(rng.next_u32())) {
+        Ok(value) => value,
+        Err(never) => match never {},
+    }
+}
+
+/// Rejection sampling shared by [`gen_range`] and [`random_range`]
+fn sample_range<E>(
+    range: Range<u32>,
+    mut next_word: impl FnMut() -> Result<u32, E>,
+) -> Result<u32, E> {
+    assert!(range.start < range.end, "range must not be empty");
+
+    let span = range.end - range.start;
+    // 2^32 mod span: the words at or above this come in a multiple of `span`
+    let threshold = span.wrapping_neg() % span;
+
+    loop {
+        let word = next_word()?;
+        if word >= threshold {
+            return Ok(range.start + word % span);
+        }
+    }
+}
+
+/// Waits until the TRNG is no longer busy
+fn wait_idle() -> Result<(), Error> {
+    let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+
+    // The busy flag is only raised one cycle after triggering, reading the control register
+    // once takes longer than that
+    let _ = sec_eng.se_trng_0_ctrl_0.read();
+
+    let mut timeout_countdown = TRNG_TIMEOUT;
+    while sec_eng
+        .se_trng_0_ctrl_0
+        .read()
+        .se_trng_0_busy()
+        .bit_is_set()
+    {
+        if timeout_countdown == 0 {
+            return Err(Error::Timeout);
+        }
+        timeout_countdown -= 1;
+    }
+
+    Ok(())
+}
+
+/// Triggers the TRNG and reads one 256 bit block of output
+fn read_block(block: &mut [u32; 8]) -> Result<(), Error> {
+    let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+
+    if sec_eng
+        .se_trng_0_ctrl_0
+        .read()
+        .se_trng_0_en()
+        .bit_is_clear()
+    {
+        return Err(Error::Disabled);
+    }
+
+    sec_eng
+        .se_trng_0_ctrl_0
+        .modify(|_, w| w.se_trng_0_trig_1t().set_bit());
+    wait_idle()?;
+
+    let health_error = sec_eng
+        .se_trng_0_ctrl_0
+        .read()
+        .se_trng_0_ht_error()
+        .bit_is_set();
+
+    block[0] = sec_eng.se_trng_0_dout_0.read().bits();
+    block[1] = sec_eng.se_trng_0_dout_1.read().bits();
+    block[2] = sec_eng.se_trng_0_dout_2.read().bits();
+    block[3] = sec_eng.se_trng_0_dout_3.read().bits();
+    block[4] = sec_eng.se_trng_0_dout_4.read().bits();
+    block[5] = sec_eng.se_trng_0_dout_5.read().bits();
+    block[6] = sec_eng.se_trng_0_dout_6.read().bits();
+    block[7] = sec_eng.se_trng_0_dout_7.read().bits();
+
+    // Clear the interrupt flag and the output registers so the block doesn't linger in hardware
+    sec_eng.se_trng_0_ctrl_0.modify(|_, w| {
+        w.se_trng_0_trig_1t()
+            .clear_bit()
+            .se_trng_0_int_clr_1t()
+            .set_bit()
+            .se_trng_0_dout_clr_1t()
+            .set_bit()
+    });
+    sec_eng.se_trng_0_ctrl_0.modify(|_, w| {
+        w.se_trng_0_int_clr_1t()
+            .clear_bit()
+            .se_trng_0_dout_clr_1t()
+            .clear_bit()
+    });
+
+    if health_error {
+        return Err(Error::HealthCheck);
+    }
+
+    if block.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err(Error::StuckOutput);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::gen_range;
+    use rand_core::{impls, RngCore};
+
+    /// Generator returning a fixed sequence of words, to check which words get rejected
+    struct Sequence {
+        words: &'static [u32],
+        drawn: usize,
+    }
+
+    /// Generator counting up from 0, every word comes once
+    struct Counter(u32);
+
+    /// Xorshift generator, deterministic and uniform enough for the statistics below
+    struct Xorshift(u32);
+
+    impl RngCore for Sequence {
+        fn next_u32(&mut self) -> u32 {
+            let word = self.words[self.drawn];
+            self.drawn += 1;
+            word
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            impls::next_u64_via_u32(self)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            impls::fill_bytes_via_next(self, dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl RngCore for Counter {
+        fn next_u32(&mut self) -> u32 {
+            self.0 += 1;
+            self.0 - 1
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            impls::next_u64_via_u32(self)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            impls::fill_bytes_via_next(self, dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl RngCore for Xorshift {
+        fn next_u32(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            impls::next_u64_via_u32(self)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            impls::fill_bytes_via_next(self, dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    /// Draws from `words` and returns the result and the number of words drawn
+    fn draw(range: core::ops::Range<u32>, words: &'static [u32]) -> (u32, usize) {
+        let mut rng = Sequence { words, drawn: 0 };
+        let value = gen_range(&mut rng, range);
+        (value, rng.drawn)
+    }
+
+    /// Words below the threshold `span.wrapping_neg() % span` are drawn again, the threshold
+    /// itself is the first word kept
+    #[test]
+    fn rejection() {
+        // 2^32 mod 3 = 1, so only 0 is rejected
+        assert_eq!(draw(10..13, &[0, 4]), (11, 2));
+        assert_eq!(draw(10..13, &[1]), (11, 1));
+        // 2^32 mod 10 = 6
+        assert_eq!(draw(0..10, &[5, 4, 6]), (6, 3));
+        assert_eq!(draw(0..10, &[0xffff_ffff]), (5, 1));
+        // 2^32 mod (2^31 + 1) = 2^31 - 1, almost half of all words are rejected
+        assert_eq!(
+            draw(0..0x8000_0001, &[0x7fff_fffe, 0, 0x7fff_ffff]),
+            (0x7fff_ffff, 3)
+        );
+        assert_eq!(draw(0..0x8000_0001, &[0xffff_ffff]), (0x7fff_fffe, 1));
+        // The largest range, 2^32 mod (2^32 - 1) = 1
+        assert_eq!(draw(0..u32::MAX, &[0, 1]), (1, 2));
+        // Powers of two never need to reject anything
+        assert_eq!(draw(0..16, &[0, 0xffff_ffff]), (0, 1));
+        assert_eq!(draw(7..8, &[0xdead_beef]), (7, 1));
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_range() {
+        gen_range(&mut Counter(0), 5..5);
+    }
+
+    /// Every word above the threshold maps to exactly one value, so consecutive words fill the
+    /// buckets evenly, with no bias towards the small values
+    #[test]
+    fn buckets() {
+        let mut rng = Counter(0);
+        let mut histogram = [0u32; 10];
+        for _ in 0..1000 {
+            histogram[gen_range(&mut rng, 0..10) as usize] += 1;
+        }
+
+        assert_eq!(histogram, [100; 10]);
+        // Words 0 to 5 were rejected
+        assert_eq!(rng.0, 1006);
+    }
+
+    #[test]
+    fn chi_squared() {
+        const BUCKETS: usize = 10;
+        const SAMPLES: u32 = 10_000;
+        // For 9 degrees of freedom at p = 0.001
+        const LIMIT: f64 = 27.877;
+
+        let mut rng = Xorshift(0x1234_5678);
+        let mut histogram = [0u32; BUCKETS];
+        for _ in 0..SAMPLES {
+            histogram[gen_range(&mut rng, 100..100 + BUCKETS as u32) as usize - 100] += 1;
+        }
+
+        let expected = f64::from(SAMPLES) / BUCKETS as f64;
+        let chi_squared: f64 = histogram
+            .iter()
+            .map(|&count| (f64::from(count) - expected).powi(2) / expected)
+            .sum();
+        assert!(chi_squared < LIMIT, "{:?}", histogram);
+    }
+}
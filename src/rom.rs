@@ -0,0 +1,104 @@
+/*!
+  # ROM functions
+  The boot ROM of the BL602 exports the drivers it boots with through a table of function
+  pointers at [`ROM_API_TABLE`], the `RomDriver_*` functions of the vendor SDK. Entry 0 is the
+  revision of the table, the functions start at entry 4. Calling a function from the ROM saves
+  the flash its code would take, e.g. the 1 KiB lookup table of [`crc`](crate::crc).
+
+  [`RomApi`] wraps entries with the index and C signature given by the SDK's
+  `bl602_romdriver.h`; `examples/rom_api.rs` compares each one with the HAL's own implementation
+  on hardware. A wrong index calls some other function with the wrong arguments, so entries are
+  only added together with such a check. The ROM has no AES or SHA functions, those are in
+  [`aes`](crate::aes) and [`sha`](crate::sha).
+
+  ## Example
+  ```rust
+    use bl602_hal::rom::RomApi;
+
+    let rom = RomApi::new();
+    assert_eq!(rom.crc32(b"123456789"), 0xcbf4_3926);
+    rom.delay_us(10);
+  ```
+*/
+
+/// Address of the ROM function table
+pub const ROM_API_TABLE: usize = 0x2101_0800;
+
+// Entries of the table, see `ROM_API_INDEX_*` in bl602_romdriver.h
+const INDEX_REVISION: usize = 0;
+const INDEX_BL602_DELAY_US: usize = 21;
+const INDEX_BL602_DELAY_MS: usize = 22;
+const INDEX_BL602_MEMCMP: usize = 28;
+const INDEX_BFLB_SOFT_CRC32: usize = 29;
+pub(crate) const INDEX_PDS_POWER_ON_PLL: usize = 88;
+
+/// Safe wrappers of the functions in the ROM, see the module documentation
+#[derive(Debug, Copy, Clone)]
+pub struct RomApi {
+    _private: (),
+}
+
+impl RomApi {
+    /// Returns the wrappers, they need no setup
+    pub fn new() -> Self {
+        RomApi { _private: () }
+    }
+
+    /// Returns the revision of the ROM function table
+    pub fn revision(&self) -> u32 {
+        entry(INDEX_REVISION) as u32
+    }
+
+    /// Returns the CRC-32 of `data`, the same as
+    /// [`Algorithm::Crc32`](crate::crc::Algorithm::Crc32)
+    ///
+    /// `BFLB_Soft_CRC32` computes it bit by bit, which is slower than the table of
+    /// [`crc`](crate::crc) but needs no flash for the table.
+    pub fn crc32(&self, data: &[u8]) -> u32 {
+        let crc32: extern "C" fn(*const u8, u32) -> u32 =
+            unsafe { core::mem::transmute(entry(INDEX_BFLB_SOFT_CRC32)) };
+        crc32(data.as_ptr(), data.len() as u32)
+    }
+
+    /// Busy waits for `us` microseconds with `BL602_Delay_US`
+    ///
+    /// The ROM counts cycles of the core clock it reads from `HBN_RSV2`, where
+    /// [`freeze`](crate::clock::Strict::freeze) stores it.
+    pub fn delay_us(&self, us: u32) {
+        let delay_us: extern "C" fn(u32) =
+            unsafe { core::mem::transmute(entry(INDEX_BL602_DELAY_US)) };
+        delay_us(us);
+    }
+
+    /// Busy waits for `ms` milliseconds with `BL602_Delay_MS`
+    pub fn delay_ms(&self, ms: u32) {
+        let delay_ms: extern "C" fn(u32) =
+            unsafe { core::mem::transmute(entry(INDEX_BL602_DELAY_MS)) };
+        delay_ms(ms);
+    }
+
+    /// Returns whether `a` and `b` have the same content, compared by `BL602_MemCmp`
+    ///
+    /// Slices of different lengths are never equal.
+    pub fn mem_eq(&self, a: &[u8], b: &[u8]) -> bool {
+        if a.len() != b.len() {
+            return false;
+        }
+
+        let memcmp: extern "C" fn(*const u8, *const u8, u32) -> i32 =
+            unsafe { core::mem::transmute(entry(INDEX_BL602_MEMCMP)) };
+        memcmp(a.as_ptr(), b.as_ptr(), a.len() as u32) == 0
+    }
+}
+
+impl Default for RomApi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads entry `index` of the ROM function table
+pub(crate) fn entry(index: usize) -> usize {
+    let table = ROM_API_TABLE as *const usize;
+    unsafe { table.add(index).read_volatile() }
+}
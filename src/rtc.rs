@@ -6,13 +6,204 @@
   ```rust
     let rtc = Rtc::new(dp.HBN);
   ```
+
+  # Alarms
+  One alarm can be set at a time, either at a calendar time, which needs the time of day set
+  with [`Rtc::set_time`], or after a duration. [`Rtc::every`] repeats the alarm, the next one
+  is always scheduled one period after the previous one, so the alarms don't drift however late
+  the interrupt is handled.
+
+  The alarm raises the `HbnOut0` interrupt, whose handler has to call [`Rtc::on_interrupt`].
+  [`Rtc::enter_hbn`] hibernates until the alarm instead. Alarms further away than the counter
+  can express are reached in several steps, each of which also raises the interrupt or wakes the
+  chip; `on_interrupt` only reports the last one.
+
+  The counter runs from a 32 kHz clock whose frequency is only nominally 32768 Hz, especially
+  when it's the internal RC oscillator. [`Rtc::measure_frequency`] measures it against the
+  system clock, alarms and times are converted with the measured frequency from then on.
+
+  ## Alarm example
+  ```rust
+    let mut rtc = Rtc::new(dp.HBN);
+    rtc.measure_frequency(&clocks);
+    rtc.set_time(DateTime::new(2022, 3, 14, 15, 9, 26).unwrap()).unwrap();
+    rtc.every(Duration::from_secs(60)).unwrap();
+    enable_interrupt(Interrupt::HbnOut0);
+
+    #[no_mangle]
+    fn HbnOut0(_: &mut TrapFrame) {
+        clear_interrupt(Interrupt::HbnOut0);
+        riscv::interrupt::free(|cs| {
+            if let Some(rtc) = RTC.borrow(cs).borrow_mut().as_mut() {
+                if rtc.on_interrupt() {
+                    // once a minute
+                }
+            }
+        });
+    }
+  ```
 */
 
+use core::cell::Cell;
+use core::convert::Infallible;
+use core::time::Duration;
+
 use bl602_pac::HBN;
 use embedded_time::Clock;
 
+use crate::clock::Clocks;
+use crate::hbn::{self, HbnLevel, WakeSources};
+use crate::pac;
+
+/// Nominal counter frequency
+const NOMINAL_FREQ: u32 = 32_768;
+/// Mask of the 40 bit counter
+const COUNTER_MASK: u64 = (1 << 40) - 1;
+/// Longest step towards an alarm, half the counter range so steps are never ambiguous
+const MAX_STEP: u64 = 1 << 39;
+/// How far ahead the compare value has to be to be reached after it has been written
+const MIN_LEAD: u64 = 2;
+/// Counter ticks over which the frequency is measured, 125 ms
+const MEASURE_TICKS: u64 = 4096;
+
+/// RTC error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The alarm time has already passed, see [`PastAlarm::Reject`]
+    InPast,
+    /// The time of day hasn't been set with [`Rtc::set_time`]
+    TimeNotSet,
+    /// The date or time doesn't exist, or is before 1970
+    InvalidDateTime,
+    /// The period of a repeating alarm is shorter than a counter tick
+    PeriodTooShort,
+}
+
+/// What happens to alarms set at a time which has already passed
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PastAlarm {
+    /// The alarm fires right away
+    FireImmediately,
+    /// Setting the alarm fails with [`Error::InPast`]
+    Reject,
+}
+
+/// Calendar date and time of day, in UTC or whatever time zone the application keeps
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to 31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Returns the date and time, if it exists and isn't before 1970
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Option<Self> {
+        let time = DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        };
+
+        time.to_unix().map(|_| time)
+    }
+
+    /// Returns the date and time `secs` seconds after 1970-01-01 00:00:00
+    pub fn from_unix(secs: u64) -> Self {
+        let days = (secs / 86_400) as i64;
+        let secs = secs % 86_400;
+
+        // Days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + (month <= 2) as i64;
+
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (secs / 3_600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
+
+    /// Returns the seconds since 1970-01-01 00:00:00, or `None` if the date or time doesn't
+    /// exist or is before 1970
+    pub fn to_unix(&self) -> Option<u64> {
+        let days_in_month = match self.month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if is_leap_year(self.year) => 29,
+            2 => 28,
+            _ => return None,
+        };
+        if self.year < 1970
+            || self.day == 0
+            || self.day > days_in_month
+            || self.hour > 23
+            || self.minute > 59
+            || self.second > 59
+        {
+            return None;
+        }
+
+        // Civil date to days, the inverse of the above
+        let month = self.month as i64;
+        let year = self.year as i64 - (month <= 2) as i64;
+        let era = year / 400;
+        let yoe = year - era * 400;
+        let doy =
+            (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+
+        Some(
+            days as u64 * 86_400
+                + self.hour as u64 * 3_600
+                + self.minute as u64 * 60
+                + self.second as u64,
+        )
+    }
+}
+
+fn is_leap_year(year: u16) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+#[derive(Copy, Clone)]
+struct Alarm {
+    /// Counter value of the alarm, extended beyond 40 bits
+    target: u64,
+    /// Ticks between repeated alarms
+    period: Option<u64>,
+}
+
 pub struct Rtc {
     hbn: HBN,
+    /// Counter frequency used for conversions
+    freq: u32,
+    /// Last counter value read, extended beyond 40 bits
+    last_ticks: Cell<u64>,
+    /// Counter value and Unix time at the moment the time of day was set
+    time_reference: Option<(u64, u64)>,
+    alarm: Option<Alarm>,
+    past_alarm: PastAlarm,
 }
 
 impl Rtc {
@@ -25,18 +216,31 @@ impl Rtc {
         hbn.hbn_ctl
             .modify(|r, w| unsafe { w.rtc_ctl().bits(r.rtc_ctl().bits() | 1) });
 
-        Rtc { hbn }
+        Self::resume(hbn)
+    }
+
+    /// Takes over the RTC without restarting the counter, e.g. after waking from hibernate
+    ///
+    /// The frequency, the time of day and the alarm aren't kept in the RTC, so they have to be
+    /// set again.
+    pub fn resume(hbn: HBN) -> Rtc {
+        hbn.hbn_ctl
+            .modify(|r, w| unsafe { w.rtc_ctl().bits(r.rtc_ctl().bits() | 1) });
+        let ticks = read_counter(&hbn);
+
+        Rtc {
+            hbn,
+            freq: NOMINAL_FREQ,
+            last_ticks: Cell::new(ticks),
+            time_reference: None,
+            alarm: None,
+            past_alarm: PastAlarm::FireImmediately,
+        }
     }
 
     /// Get elapsed milliseconds since the RTC was created
     pub fn get_millis(&self) -> u64 {
-        self.hbn
-            .rtc_time_h
-            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << 31) });
-
-        let h = self.hbn.rtc_time_h.read().bits();
-        let l = self.hbn.rtc_time_l.read().bits();
-        let ts = (h as u64) << 32 | l as u64; // in counter units
+        let ts = read_counter(&self.hbn); // in counter units
 
         // from IOT SDK:
         // #define BL_RTC_COUNTER_TO_MS(CNT)  (((CNT) >> 5) - ((CNT) >> 11) - ((CNT) >> 12))  // ((CNT)*(1024-16-8)/32768)
@@ -45,6 +249,243 @@ impl Rtc {
     }
 }
 
+impl Rtc {
+    /// Returns the counter frequency used for conversions
+    pub fn frequency(&self) -> u32 {
+        self.freq
+    }
+
+    /// Sets the counter frequency used for conversions, e.g. to a value measured before
+    pub fn set_frequency(&mut self, freq: u32) {
+        self.freq = freq;
+    }
+
+    /// Measures the counter frequency against the system clock over 125 ms and uses it for
+    /// conversions from now on, returns the frequency
+    ///
+    /// Interrupts handled during the measurement don't affect the result, it only depends on
+    /// the system clock being accurate, which it is when running from the crystal.
+    pub fn measure_frequency(&mut self, clocks: &Clocks) -> u32 {
+        // Start right after a tick, so both ends of the window are on a tick boundary
+        let first = self.ticks();
+        let start = loop {
+            let ticks = self.ticks();
+            if ticks != first {
+                break ticks;
+            }
+        };
+        let start_cycles = riscv::register::mcycle::read64();
+
+        while self.ticks() < start + MEASURE_TICKS {}
+        let cycles = riscv::register::mcycle::read64() - start_cycles;
+
+        let sysclk = clocks.sysclk().0 as u64;
+        self.freq = ((MEASURE_TICKS * sysclk + cycles / 2) / cycles) as u32;
+
+        self.freq
+    }
+
+    /// Sets the time of day
+    pub fn set_time(&mut self, now: DateTime) -> Result<(), Error> {
+        let unix = now.to_unix().ok_or(Error::InvalidDateTime)?;
+        self.time_reference = Some((self.ticks(), unix));
+
+        Ok(())
+    }
+
+    /// Returns the time of day
+    pub fn time(&self) -> Result<DateTime, Error> {
+        let (ticks, unix) = self.time_reference.ok_or(Error::TimeNotSet)?;
+        let elapsed = (self.ticks() - ticks) / self.freq as u64;
+
+        Ok(DateTime::from_unix(unix + elapsed))
+    }
+
+    /// Sets what happens to alarms set at a time which has already passed
+    pub fn set_past_alarm(&mut self, policy: PastAlarm) {
+        self.past_alarm = policy;
+    }
+
+    /// Sets the alarm to `at`, replacing the previous one
+    pub fn set_alarm(&mut self, at: DateTime) -> Result<(), Error> {
+        let (ticks, unix) = self.time_reference.ok_or(Error::TimeNotSet)?;
+        let at = at.to_unix().ok_or(Error::InvalidDateTime)?;
+
+        let target = if at >= unix {
+            ticks + (at - unix) * self.freq as u64
+        } else {
+            // Before the time was set, which has certainly passed
+            0
+        };
+        self.arm(target, None)
+    }
+
+    /// Sets the alarm to `after` from now, replacing the previous one
+    pub fn set_alarm_in(&mut self, after: Duration) -> Result<(), Error> {
+        let target = self.ticks() + self.duration_to_ticks(after);
+        self.arm(target, None)
+    }
+
+    /// Sets the alarm to go off every `period`, starting `period` from now, replacing the
+    /// previous one
+    ///
+    /// If an alarm is handled so late that the next one has passed too, the alarms in between
+    /// are skipped, keeping the phase.
+    pub fn every(&mut self, period: Duration) -> Result<(), Error> {
+        let period = self.duration_to_ticks(period);
+        if period == 0 {
+            return Err(Error::PeriodTooShort);
+        }
+
+        let target = self.ticks() + period;
+        self.arm(target, Some(period))
+    }
+
+    /// Removes the alarm
+    pub fn cancel_alarm(&mut self) {
+        self.alarm = None;
+        disable_compare(&self.hbn);
+    }
+
+    /// Returns the time until the alarm, or `None` if there is none
+    pub fn time_until_alarm(&self) -> Option<Duration> {
+        let alarm = self.alarm?;
+        let ticks = alarm.target.saturating_sub(self.ticks());
+        let freq = self.freq as u64;
+
+        Some(Duration::new(
+            ticks / freq,
+            ((ticks % freq) * 1_000_000_000 / freq) as u32,
+        ))
+    }
+
+    /// Handles the RTC interrupt, returns whether the alarm went off
+    ///
+    /// Has to be called from the `HbnOut0` handler. Repeating alarms and steps towards far away
+    /// alarms are armed again here.
+    pub fn on_interrupt(&mut self) -> bool {
+        if self.hbn.hbn_irq_stat.read().bits() & hbn::IRQ_RTC == 0 {
+            return false;
+        }
+        self.hbn
+            .hbn_irq_clr
+            .write(|w| unsafe { w.bits(hbn::IRQ_RTC) });
+        self.hbn.hbn_irq_clr.write(|w| unsafe { w.bits(0) });
+
+        let alarm = match self.alarm {
+            Some(alarm) => alarm,
+            None => return false,
+        };
+
+        let now = self.ticks();
+        if now < alarm.target {
+            // Only an intermediate step
+            self.program();
+            return false;
+        }
+
+        match alarm.period {
+            Some(period) => {
+                let mut target = alarm.target + period;
+                if target <= now {
+                    target += (now - target) / period * period + period;
+                }
+                self.alarm = Some(Alarm {
+                    target,
+                    period: Some(period),
+                });
+                self.program();
+            }
+            None => self.cancel_alarm(),
+        }
+
+        true
+    }
+
+    /// Hibernates until the alarm or one of the other sources in `wake`, like
+    /// [`Hbn::enter`](crate::hbn::Hbn::enter)
+    ///
+    /// Without an alarm, `wake.rtc_after` is used, converted with the measured frequency. The
+    /// chip resets on wake up, so a repeating alarm has to be set again after
+    /// [`resume`](Rtc::resume).
+    pub fn enter_hbn(
+        &mut self,
+        level: HbnLevel,
+        wake: WakeSources,
+    ) -> Result<Infallible, hbn::Error> {
+        let compare = match (self.alarm, wake.rtc_after) {
+            (Some(_), _) => Some(self.program()),
+            (None, Some(after)) => {
+                let ticks = self.duration_to_ticks(after);
+                if ticks > COUNTER_MASK {
+                    return Err(hbn::Error::RtcOutOfRange);
+                }
+                Some(self.ticks() + ticks.max(MIN_LEAD))
+            }
+            (None, None) => None,
+        };
+
+        hbn::enter(&self.hbn, level, &wake, compare.map(|c| c & COUNTER_MASK))
+    }
+
+    /// Schedules the alarm at counter value `target`
+    fn arm(&mut self, target: u64, period: Option<u64>) -> Result<(), Error> {
+        if target <= self.ticks() && self.past_alarm == PastAlarm::Reject {
+            return Err(Error::InPast);
+        }
+
+        self.alarm = Some(Alarm { target, period });
+        self.program();
+
+        Ok(())
+    }
+
+    /// Programs the compare channel with the alarm or the next step towards it, returns the
+    /// extended compare value
+    fn program(&mut self) -> u64 {
+        let target = match self.alarm {
+            Some(alarm) => alarm.target,
+            None => return 0,
+        };
+        let now = self.ticks();
+
+        let compare = if target < now + MIN_LEAD {
+            now + MIN_LEAD
+        } else if target - now > MAX_STEP {
+            now + MAX_STEP
+        } else {
+            target
+        };
+        hbn::set_rtc_compare(&self.hbn, compare & COUNTER_MASK);
+
+        compare
+    }
+
+    /// Returns the counter extended beyond 40 bits, it has to be read at least once per
+    /// wrap around, about once a year, which alarms take care of
+    fn ticks(&self) -> u64 {
+        let last = self.last_ticks.get();
+        let mut ticks = (last & !COUNTER_MASK) | read_counter(&self.hbn);
+        if ticks < last {
+            ticks += COUNTER_MASK + 1;
+        }
+        self.last_ticks.set(ticks);
+
+        ticks
+    }
+
+    fn duration_to_ticks(&self, duration: Duration) -> u64 {
+        let freq = self.freq as u64;
+        duration.as_secs() * freq + duration.subsec_nanos() as u64 * freq / 1_000_000_000
+    }
+}
+
+/// Disables the compare channels, so the RTC neither interrupts nor wakes the chip
+fn disable_compare(hbn: &pac::hbn::RegisterBlock) {
+    hbn.hbn_ctl
+        .modify(|r, w| unsafe { w.bits(r.bits() & !(0b111 << 1)) });
+}
+
 impl Clock for Rtc {
     type T = u64;
 
@@ -55,3 +496,13 @@ impl Clock for Rtc {
         Ok(embedded_time::Instant::new(self.get_millis()))
     }
 }
+
+/// Latches and reads the 40 bit RTC counter, which counts at 32768 Hz
+pub(crate) fn read_counter(hbn: &pac::hbn::RegisterBlock) -> u64 {
+    hbn.rtc_time_h
+        .modify(|r, w| unsafe { w.bits(r.bits() | 1 << 31) });
+
+    let h = hbn.rtc_time_h.read().bits() & 0xff;
+    let l = hbn.rtc_time_l.read().bits();
+    (h as u64) << 32 | l as u64
+}
@@ -0,0 +1,65 @@
+/*!
+  # Security Engine
+  The SEC_ENG block bundles the hardware accelerators for hashing (SHA), block ciphers (AES),
+  public key arithmetic (PKA) and the true random number generator (TRNG). All engines share a
+  single register block, so it is split into independent parts which are then handed to the
+  individual drivers.
+
+  ## Example
+  ```rust
+    use bl602_hal::sec_eng::SecEngExt;
+
+    let dp = pac::Peripherals::take().unwrap();
+    let mut parts = dp.GLB.split();
+    let sec_eng = dp.SEC_ENG.split();
+
+    let mut trng = hal::rng::Trng::new(sec_eng.trng, &mut parts.clk_cfg).unwrap();
+  ```
+*/
+
+use crate::pac;
+
+/// SHA engine (`se_sha_0`)
+pub struct Sha0 {
+    pub(crate) _ownership: (),
+}
+
+/// AES engine (`se_aes_0`)
+pub struct Aes0 {
+    pub(crate) _ownership: (),
+}
+
+/// True random number generator (`se_trng_0`)
+pub struct Trng0 {
+    pub(crate) _ownership: (),
+}
+
+/// Public key accelerator (`se_pka_0`)
+pub struct Pka0 {
+    pub(crate) _ownership: (),
+}
+
+/// Engines obtained from [SEC_ENG.split](SecEngExt::split)
+pub struct Parts {
+    pub sha: Sha0,
+    pub aes: Aes0,
+    pub trng: Trng0,
+    pub pka: Pka0,
+}
+
+/// Extension trait to split the SEC_ENG peripheral into independent engines
+pub trait SecEngExt {
+    /// Splits the register block into independent engines
+    fn split(self) -> Parts;
+}
+
+impl SecEngExt for pac::SEC_ENG {
+    fn split(self) -> Parts {
+        Parts {
+            sha: Sha0 { _ownership: () },
+            aes: Aes0 { _ownership: () },
+            trng: Trng0 { _ownership: () },
+            pka: Pka0 { _ownership: () },
+        }
+    }
+}
@@ -0,0 +1,115 @@
+/*!
+  # Handling of secrets
+  Helpers used by the crypto drivers, which are also useful when handling key material in
+  application code: a constant-time comparison for authentication tags and MACs, and a wrapper
+  which erases its contents when dropped, so keys don't linger in RAM after their context is
+  gone.
+
+  ## Example
+  ```rust
+    use bl602_hal::secure::{ct_eq, Zeroizing};
+
+    let key = Zeroizing::new([0x2bu8; 16]);
+    aes.encrypt_ecb(&key[..], &mut block).unwrap();
+    // The key is overwritten with zeros here
+
+    if !ct_eq(&received_mac, &expected_mac) {
+        // reject the message
+    }
+  ```
+*/
+
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{compiler_fence, Ordering};
+
+/// Compares two byte slices without an early exit, so the time taken doesn't reveal where they
+/// differ
+///
+/// Slices of different length compare unequal right away, only their contents are protected.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let diff = a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y));
+    // Keep the compiler from turning the fold back into an early exit
+    unsafe { core::ptr::read_volatile(&diff) == 0 }
+}
+
+/// Overwrites `buf` with zeros in a way the compiler can't optimize away
+pub fn zeroize_bytes(buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+        unsafe { core::ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Values which can be securely erased
+pub trait Zeroize {
+    /// Overwrites the value with zeros in a way the compiler can't optimize away
+    fn zeroize(&mut self);
+}
+
+macro_rules! zeroize_arrays {
+    ($($len:expr),+) => {
+        $(
+            impl Zeroize for [u8; $len] {
+                fn zeroize(&mut self) {
+                    zeroize_bytes(self);
+                }
+            }
+        )+
+    };
+}
+
+zeroize_arrays!(12, 16, 24, 32, 64);
+
+macro_rules! zeroize_integers {
+    ($($ty:ty),+) => {
+        $(
+            impl Zeroize for $ty {
+                fn zeroize(&mut self) {
+                    unsafe { core::ptr::write_volatile(self, 0) };
+                    compiler_fence(Ordering::SeqCst);
+                }
+            }
+        )+
+    };
+}
+
+zeroize_integers!(u32, u64, u128);
+
+/// Wrapper which erases the value when it is dropped
+///
+/// Only the wrapped value itself is erased. Copies of it, e.g. made by moving the wrapper, may
+/// remain in memory, so construct it in place and pass it by reference.
+pub struct Zeroizing<T: Zeroize> {
+    value: T,
+}
+
+impl<T: Zeroize> Zeroizing<T> {
+    /// Wraps `value`
+    pub fn new(value: T) -> Self {
+        Zeroizing { value }
+    }
+}
+
+impl<T: Zeroize> Deref for Zeroizing<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Zeroize> DerefMut for Zeroizing<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: Zeroize> Drop for Zeroizing<T> {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
@@ -1,14 +1,66 @@
 //! Serial communication
+//!
+//! # Receive errors
+//! The BL602 UART flags parity errors and RX FIFO overflows, both of which are reported by
+//! `read`. It has no flag for a missing stop bit, so [`Error::Framing`] is never returned by
+//! this driver. After an error, `read` keeps returning it until
+//! [`recover_from_error`](Serial::recover_from_error) has been called, so it can't be missed.
+//!
+//! The RX FIFO holds 32 bytes. When a byte arrives while it's full, the byte is dropped and the
+//! overflow flag is set. `read` still returns the bytes which were in the FIFO at that point and
+//! then [`Error::Overrun`], so the error marks where the gap in the data is. Applications which
+//! can't wait for their next `read` to notice, e.g. to stop a sender, can have the overflow
+//! raise the `Uart0` interrupt with [`enable_overrun_interrupt`](Serial::enable_overrun_interrupt),
+//! or poll [`check_overrun`](Serial::check_overrun).
+//!
+//! # Waking up on received data
+//! With the RX signal on GPIO7 or GPIO8,
+//! [`enable_wake_on_activity`](Serial::enable_wake_on_activity) makes a start bit end power-down
+//! sleep or hibernate. The byte which wakes the chip is usually lost while the clocks start up,
+//! so the other side has to send again:
+//!
+//! ```rust
+//! let mut cfg = PdsConfig::new(clocks);
+//! serial.enable_wake_on_activity(&mut cfg).unwrap();
+//!
+//! if pds::enter(PdsLevel::Level1, None, cfg) == Ok(WakeCause::Pin(WakePin::Gpio7)) {
+//!     serial.resync_rx();
+//!     // wait for the host to repeat what it sent
+//! }
+//! ```
+//!
+//! # Half-duplex buses
+//! An RS-485 transceiver has to drive the bus until the stop bit of the last byte is out, and
+//! not a bit longer, or it collides with the next station answering. The TX FIFO running empty
+//! is too early for that, the last byte is still being shifted out. The transmitter counts the
+//! bytes it has sent instead: after [`set_tx_transfer_len`](Serial::set_tx_transfer_len) bytes
+//! it sets the TX complete flag, which can raise the `Uart0` interrupt with
+//! [`enable_tx_complete_interrupt`](Serial::enable_tx_complete_interrupt).
+//! [`HalfDuplexSerial`] drives the driver enable pin of the transceiver that way:
+//!
+//! ```rust
+//! let mut bus = HalfDuplexSerial::new(serial, parts.pin14.into_pull_down_output());
+//! bus.send(b"\x01\x03\x00\x00\x00\x01\x84\x0a").unwrap();
+//! let answer = nb::block!(bus.read()).unwrap();
+//! ```
 use crate::clock::Clocks;
+use crate::debug;
+use crate::gpio::{PinNumber, UartModePin, UartMuxBundle};
+use crate::hbn::WakePin;
+use crate::interrupts::{self, Interrupt};
 use crate::pac;
+use crate::power::{self, Peripheral, SleepAware};
+use crate::regs;
+use crate::sync::SpinLock;
 use core::fmt;
+use embedded_hal::digital::blocking::OutputPin;
 use embedded_hal::serial::nb::Write as WriteOne;
 use embedded_hal::serial::nb::Read as ReadOne;
 use embedded_time::rate::{Baud, Extensions};
 use nb::block;
 
 /// Serial error
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[non_exhaustive]
 pub enum Error {
     /// Framing error
@@ -16,9 +68,34 @@ pub enum Error {
     /// Noise error
     Noise,
     /// RX buffer overrun
-    Overrun,
+    Overrun {
+        /// Bytes known to be lost: the one the full RX FIFO dropped, and those received after it
+        /// which are discarded when the overflow is cleared. More may have been dropped, the
+        /// hardware doesn't count them.
+        bytes_lost: u32,
+    },
     /// Parity check error
     Parity,
+    /// The baudrate can't be derived from the UART clock
+    Baudrate,
+    /// The RX pin can't wake the chip, only GPIO7 and GPIO8 can
+    NoWakePin,
+    /// The sleep configuration already has wake pins with a trigger other than the falling edge
+    WakeConflict,
+}
+
+impl embedded_hal::serial::Error for Error {
+    fn kind(&self) -> embedded_hal::serial::ErrorKind {
+        match self {
+            Error::Framing => embedded_hal::serial::ErrorKind::FrameFormat,
+            Error::Noise => embedded_hal::serial::ErrorKind::Noise,
+            Error::Overrun { .. } => embedded_hal::serial::ErrorKind::Overrun,
+            Error::Parity => embedded_hal::serial::ErrorKind::Parity,
+            Error::Baudrate | Error::NoWakePin | Error::WakeConflict => {
+                embedded_hal::serial::ErrorKind::Other
+            }
+        }
+    }
 }
 
 /// Serial configuration
@@ -142,10 +219,19 @@ pub enum Event {
     TxTransferEnd,
 }
 
+/// Serial registered by [`Serial::into_debug_writer`]
+static DEBUG_SERIAL: SpinLock<Option<Serial<pac::UART, ()>>> = SpinLock::new(None);
+
 /// Serial abstraction
 pub struct Serial<UART, PINS> {
     uart: UART,
     pins: PINS,
+    rx_error: Option<Error>,
+    /// Bytes still to be read which arrived before the RX FIFO overflowed
+    rx_before_overrun: Option<u8>,
+    error_count: u32,
+    /// UART clock in Hz, to turn the bit period back into a baudrate
+    uart_clk: u32,
 }
 
 impl<PINS> Serial<pac::UART, PINS>
@@ -156,26 +242,9 @@ where
     pub fn uart0(uart: pac::UART, config: Config, pins: PINS, clocks: Clocks) -> Self {
         // Initialize clocks and baudrate
         let uart_clk = clocks.uart_clk();
-        let baud = config.baudrate.0;
-        let divisor = {
-            // Can't possibly have a baudrate greater than uart_clock
-            if baud > uart_clk.0 {
-                panic!("impossible baudrate");
-            }
-            // If we did this calculation using integer math, it always rounds down
-            // Reduce error by doing calculation using floating point, then
-            // add half before converting back to integer to round nearest instead
-            let ans_f = uart_clk.0 as f32 / baud as f32;
-            let ans = (ans_f + 0.5) as u32;
-
-            if !(1..=65535).contains(&ans) {
-                panic!("impossible baudrate");
-            }
+        let divisor = baud_divisor(uart_clk.0, config.baudrate.0).expect("impossible baudrate");
 
-            ans as u16
-        };
-
-        uart.uart_bit_prd.write(|w| unsafe {
+        regs::uart().uart_bit_prd.write(|w| unsafe {
             w.cr_urx_bit_prd()
                 .bits(divisor - 1)
                 .cr_utx_bit_prd()
@@ -188,7 +257,8 @@ where
             Order::MsbFirst => true,
         };
 
-        uart.data_config
+        regs::uart()
+            .data_config
             .write(|w| w.cr_uart_bit_inv().bit(order_cfg));
 
         // UART TX config
@@ -210,7 +280,7 @@ where
             Parity::ParityOdd => (true, true),   // odd => 1
         };
 
-        uart.utx_config.write(|w| unsafe {
+        regs::uart().utx_config.write(|w| unsafe {
             w.cr_utx_prt_en()
                 .bit(parity_enable)
                 .cr_utx_prt_sel()
@@ -228,7 +298,7 @@ where
         });
 
         // UART RX config
-        uart.urx_config.write(|w| unsafe {
+        regs::uart().urx_config.write(|w| unsafe {
             w.cr_urx_prt_en()
                 .bit(parity_enable)
                 .cr_urx_prt_sel()
@@ -243,13 +313,445 @@ where
                 .bit(PINS::HAS_RX)
         });
 
-        Serial { uart, pins }
+        power::register::<Self>().expect("too many sleep hooks");
+
+        Serial {
+            uart,
+            pins,
+            rx_error: None,
+            rx_before_overrun: None,
+            error_count: 0,
+            uart_clk: uart_clk.0,
+        }
+    }
+
+    /// Adds the RX pin as a falling edge wake source to a power-down sleep or hibernate
+    /// configuration, so the chip wakes up when the other side starts sending
+    ///
+    /// Only GPIO7 and GPIO8 can wake the chip. The falling edge of the first start bit wakes it,
+    /// but the UART only runs again once the clocks are back: after power-down sleep at
+    /// [`Level0`](crate::pds::PdsLevel::Level0) the waking byte may still be received, at the
+    /// deeper levels, which wait for the crystal and the PLL, and after hibernate, which boots
+    /// again, it is lost. Protocols should therefore have the other side resend after a pause,
+    /// e.g. by sending a wake up byte which is ignored and repeating a command which isn't
+    /// answered. Call [`resync_rx`](Serial::resync_rx) after waking up from power-down sleep to
+    /// drop what is left of the waking byte.
+    pub fn enable_wake_on_activity<C: RxWake>(&self, cfg: &mut C) -> Result<(), Error> {
+        let pin = match PINS::RX_PIN {
+            Some(7) => WakePin::Gpio7,
+            Some(8) => WakePin::Gpio8,
+            _ => return Err(Error::NoWakePin),
+        };
+
+        cfg.add_wake_pin(pin)
     }
 
     pub fn free(self) -> (pac::UART, PINS) {
         // todo!
+        power::unregister::<Self>();
         (self.uart, self.pins)
     }
+
+    /// Drops the pins from the type, so drivers can store the serial without being generic over
+    /// them. The pins stay configured.
+    pub(crate) fn erase_pins(self) -> Serial<pac::UART, ()> {
+        Serial {
+            uart: self.uart,
+            pins: (),
+            rx_error: self.rx_error,
+            rx_before_overrun: self.rx_before_overrun,
+            error_count: self.error_count,
+            uart_clk: self.uart_clk,
+        }
+    }
+
+    /// Registers the serial as the [debug writer](crate::debug), which writes blocking
+    ///
+    /// The writer keeps the serial. A fatal write which interrupted another write, e.g. a panic
+    /// in an interrupt handler while `main` prints, writes to the UART's registers without
+    /// taking the serial from the interrupted write, and waits until everything is sent.
+    pub fn into_debug_writer(self) {
+        *DEBUG_SERIAL.lock_irq_disabled() = Some(self.erase_pins());
+        debug::set_writer(write_debug);
+    }
+
+    /// Returns the underlying UART peripheral
+    ///
+    /// # Safety
+    ///
+    /// Reading some registers has side effects, e.g. reading the RX FIFO consumes data which the
+    /// driver then never sees.
+    #[cfg(feature = "raw-access")]
+    pub unsafe fn inner(&self) -> &pac::UART {
+        &self.uart
+    }
+
+    /// Returns the underlying UART peripheral for modification
+    ///
+    /// # Safety
+    ///
+    /// The driver assumes it has exclusive control of the configuration it set up in
+    /// [`uart0`](Serial::uart0). Changing it, e.g. the baudrate or the frame format, is not
+    /// tracked and may break the embedded-hal implementations.
+    #[cfg(feature = "raw-access")]
+    pub unsafe fn inner_mut(&mut self) -> &mut pac::UART {
+        &mut self.uart
+    }
+}
+
+impl<PINS> Serial<pac::UART, MuxedPins<PINS>>
+where
+    PINS: MuxPins,
+{
+    /// Sets up UART0 like [`uart0`](Serial::uart0), routing TX, RX and, if given, RTS and CTS
+    /// through the multiplexers which are connected to the pins.
+    ///
+    /// The pins only have to be in UART mode, e.g. `(pin16.into_uart_sig0(),
+    /// pin7.into_uart_sig7())`. Which multiplexer each signal needs follows from the pin number.
+    ///
+    /// # Panics
+    ///
+    /// Panics if two of the pins share a UART signal, e.g. pins 1 and 9, or if the baudrate is
+    /// impossible.
+    pub fn uart0_with_mux(
+        uart: pac::UART,
+        config: Config,
+        pins: PINS,
+        mut mux: UartMuxBundle,
+        clocks: Clocks,
+    ) -> Self {
+        // Functions of UART0 TX, RX, RTS and CTS, in the order of `MuxPins::PINS`
+        const FUNCTIONS: [u8; 4] = [2, 3, 0, 1];
+
+        let mut used = 0u8;
+        for (pin, &function) in PINS::PINS.iter().zip(FUNCTIONS.iter()) {
+            if let Some(pin) = pin {
+                let sig = pin % 8;
+                if used & (1 << sig) != 0 {
+                    panic!("UART pins share a signal");
+                }
+                used |= 1 << sig;
+
+                mux.route(sig, function);
+            }
+        }
+
+        Self::uart0(uart, config, MuxedPins { pins, mux }, clocks)
+    }
+}
+
+impl<PINS> Serial<pac::UART, PINS> {
+    /// Returns the registers of the UART, which are in RAM with the `mock-registers` feature
+    fn regs(&self) -> &'static pac::uart::RegisterBlock {
+        regs::uart()
+    }
+
+    /// Clears a receive error reported by `read`, so reception can continue.
+    ///
+    /// After a parity error the oldest byte in the RX FIFO is discarded. The hardware only has a
+    /// flag for the error, not the position of the byte, so this is the byte which caused it only
+    /// if no byte was waiting in front of it when `read` reported the error, e.g. when bytes are
+    /// read as they arrive. Otherwise a good byte is discarded and the bad one is returned by a
+    /// later `read`, so protocols which can't tell should drop the whole frame. After an
+    /// overflow the FIFO is cleared, which is the only way to clear the overflow flag, so the
+    /// bytes received after the dropped ones are discarded as well.
+    pub fn recover_from_error(&mut self) -> Result<(), Error> {
+        match self.rx_error.take() {
+            Some(Error::Parity) => {
+                if self.regs().uart_fifo_config_1.read().rx_fifo_cnt().bits() != 0 {
+                    self.regs().uart_fifo_rdata.read();
+                    if let Some(before) = self.rx_before_overrun.as_mut() {
+                        *before = before.saturating_sub(1);
+                    }
+                }
+                self.regs()
+                    .uart_int_clear
+                    .write(|w| w.cr_urx_pce_clr().set_bit());
+            }
+            Some(Error::Overrun { .. }) => {
+                self.regs()
+                    .uart_fifo_config_0
+                    .modify(|_, w| w.rx_fifo_clr().set_bit());
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Changes the baudrate, e.g. when a protocol switches speed after its initialization.
+    ///
+    /// Blocks until everything written before has been sent, then reprograms the bit period
+    /// with transmitter and receiver disabled. A byte arriving meanwhile is lost or garbled.
+    /// Returns the baudrate actually set, which differs from `baud` by the rounding of the
+    /// divider. The divider has 16 bits, which limits the lowest baudrate to the UART clock
+    /// divided by 65536, e.g. about 2441 Bd at 160 MHz; slower signals like the 5 Bd address of
+    /// ISO 9141 have to be bit-banged.
+    pub fn set_baudrate(&mut self, baud: u32, clocks: &Clocks) -> Result<u32, Error> {
+        let uart_clk = clocks.uart_clk().0;
+        let divisor = baud_divisor(uart_clk, baud).ok_or(Error::Baudrate)?;
+
+        // The FIFO being empty isn't enough, the last byte may still be on the line
+        let uart = self.regs();
+        while uart.uart_fifo_config_1.read().tx_fifo_cnt().bits() != 32
+            || uart.uart_status.read().sts_utx_bus_busy().bit_is_set()
+        {}
+
+        let tx_enabled = self.regs().utx_config.read().cr_utx_en().bit_is_set();
+        let rx_enabled = self.regs().urx_config.read().cr_urx_en().bit_is_set();
+        self.regs()
+            .utx_config
+            .modify(|_, w| w.cr_utx_en().clear_bit());
+        self.regs()
+            .urx_config
+            .modify(|_, w| w.cr_urx_en().clear_bit());
+
+        self.regs().uart_bit_prd.write(|w| unsafe {
+            w.cr_urx_bit_prd()
+                .bits(divisor - 1)
+                .cr_utx_bit_prd()
+                .bits(divisor - 1)
+        });
+
+        self.regs()
+            .utx_config
+            .modify(|_, w| w.cr_utx_en().bit(tx_enabled));
+        self.regs()
+            .urx_config
+            .modify(|_, w| w.cr_urx_en().bit(rx_enabled));
+
+        self.uart_clk = uart_clk;
+        Ok(uart_clk / divisor as u32)
+    }
+
+    /// Restarts the receiver with an empty RX FIFO and no pending receive error, dropping the
+    /// remains of a byte which arrived while the clocks were stopped
+    pub fn resync_rx(&mut self) {
+        let rx_enabled = self.regs().urx_config.read().cr_urx_en().bit_is_set();
+        self.regs()
+            .urx_config
+            .modify(|_, w| w.cr_urx_en().clear_bit());
+
+        self.regs()
+            .uart_fifo_config_0
+            .modify(|_, w| w.rx_fifo_clr().set_bit());
+        self.regs()
+            .uart_int_clear
+            .write(|w| w.cr_urx_pce_clr().set_bit());
+        self.rx_error = None;
+        self.rx_before_overrun = None;
+
+        self.regs()
+            .urx_config
+            .modify(|_, w| w.cr_urx_en().bit(rx_enabled));
+    }
+
+    /// Returns the number of receive errors since construction
+    pub fn error_count(&self) -> u32 {
+        self.error_count
+    }
+
+    /// Returns whether the RX FIFO has overflowed since the last check or recovery, and clears
+    /// the overflow.
+    ///
+    /// Clearing the overflow flag empties the RX FIFO, so the bytes which haven't been read yet
+    /// are discarded, and an overrun `read` was going to report is dropped.
+    pub fn check_overrun(&mut self) -> bool {
+        let latched = matches!(self.rx_error, Some(Error::Overrun { .. }));
+        let overrun = latched || self.rx_before_overrun.is_some() || self.rx_fifo_overflow();
+
+        if overrun {
+            self.regs()
+                .uart_fifo_config_0
+                .modify(|_, w| w.rx_fifo_clr().set_bit());
+            if latched {
+                self.rx_error = None;
+            } else {
+                self.error_count = self.error_count.wrapping_add(1);
+            }
+            self.rx_before_overrun = None;
+        }
+
+        overrun
+    }
+
+    /// Makes an overflow of the RX FIFO raise the `Uart0` interrupt, and enables it in the CLIC
+    ///
+    /// The interrupt stays pending as long as the overflow flag is set, so the handler has to
+    /// clear it with [`check_overrun`](Serial::check_overrun) or mask it again with
+    /// [`disable_overrun_interrupt`](Serial::disable_overrun_interrupt), e.g. to keep reading
+    /// the bytes received before the overflow.
+    pub fn enable_overrun_interrupt(&mut self) {
+        self.regs()
+            .uart_int_mask
+            .modify(|_, w| w.cr_urx_fer_mask().clear_bit());
+        interrupts::enable(Interrupt::Uart0);
+    }
+
+    /// Stops an overflow of the RX FIFO from raising the `Uart0` interrupt. The interrupt stays
+    /// enabled in the CLIC, since other events of the UART may use it.
+    pub fn disable_overrun_interrupt(&mut self) {
+        self.regs()
+            .uart_int_mask
+            .modify(|_, w| w.cr_urx_fer_mask().set_bit());
+    }
+
+    /// Sets the number of bytes after which the TX complete flag is set, and restarts the count
+    ///
+    /// The transmitter is restarted to reset its count, so this has to be called while nothing
+    /// is being sent, e.g. after [`flush`](embedded_hal::serial::nb::Write::flush) returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is 0.
+    pub fn set_tx_transfer_len(&mut self, len: u16) {
+        assert!(len != 0);
+
+        let tx_enabled = self.regs().utx_config.read().cr_utx_en().bit_is_set();
+        self.regs()
+            .utx_config
+            .modify(|_, w| w.cr_utx_en().clear_bit());
+        self.regs()
+            .utx_config
+            .modify(|_, w| unsafe { w.cr_utx_len().bits(len - 1) });
+        self.regs()
+            .utx_config
+            .modify(|_, w| w.cr_utx_en().bit(tx_enabled));
+    }
+
+    /// Returns whether the stop bit of the last byte of the transfer has been sent, see
+    /// [`set_tx_transfer_len`](Serial::set_tx_transfer_len)
+    pub fn is_tx_complete(&self) -> bool {
+        self.regs().uart_int_sts.read().utx_end_int().bit_is_set()
+    }
+
+    /// Clears the TX complete flag, which stays set until then
+    pub fn clear_tx_complete_flag(&mut self) {
+        self.regs()
+            .uart_int_clear
+            .write(|w| w.cr_utx_end_clr().set_bit());
+    }
+
+    /// Makes the TX complete flag raise the `Uart0` interrupt, and enables it in the CLIC
+    ///
+    /// The interrupt stays pending until the handler clears the flag with
+    /// [`clear_tx_complete_flag`](Serial::clear_tx_complete_flag).
+    pub fn enable_tx_complete_interrupt(&mut self) {
+        self.regs()
+            .uart_int_mask
+            .modify(|_, w| w.cr_utx_end_mask().clear_bit());
+        interrupts::enable(Interrupt::Uart0);
+    }
+
+    /// Stops the TX complete flag from raising the `Uart0` interrupt. The interrupt stays
+    /// enabled in the CLIC, since other events of the UART may use it.
+    pub fn disable_tx_complete_interrupt(&mut self) {
+        self.regs()
+            .uart_int_mask
+            .modify(|_, w| w.cr_utx_end_mask().set_bit());
+    }
+
+    fn rx_fifo_overflow(&self) -> bool {
+        self.regs()
+            .uart_fifo_config_0
+            .read()
+            .rx_fifo_overflow()
+            .bit_is_set()
+    }
+
+    /// Checks the hardware for a new receive error and latches it until it is recovered from
+    ///
+    /// An overflow is only reported once the bytes received before it have been read.
+    fn check_rx_error(&mut self) -> Option<Error> {
+        if self.rx_error.is_none() {
+            if self.rx_before_overrun.is_none() && self.rx_fifo_overflow() {
+                self.rx_before_overrun =
+                    Some(self.regs().uart_fifo_config_1.read().rx_fifo_cnt().bits());
+            }
+
+            let error = if self.rx_before_overrun == Some(0) {
+                self.rx_before_overrun = None;
+                let received = self.regs().uart_fifo_config_1.read().rx_fifo_cnt().bits();
+                Some(Error::Overrun {
+                    bytes_lost: 1 + u32::from(received),
+                })
+            } else if self.regs().uart_int_sts.read().urx_pce_int().bit_is_set() {
+                Some(Error::Parity)
+            } else {
+                None
+            };
+
+            if error.is_some() {
+                self.error_count = self.error_count.wrapping_add(1);
+                self.rx_error = error;
+            }
+        }
+
+        self.rx_error
+    }
+}
+
+/// Shows the frame format the transmitter is configured for, read back from the registers
+impl<PINS> fmt::Debug for Serial<pac::UART, PINS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let uart = self.regs();
+        let bit_prd = uart.uart_bit_prd.read().cr_utx_bit_prd().bits() as u32 + 1;
+        let utx_config = uart.utx_config.read();
+
+        let parity = match (
+            utx_config.cr_utx_prt_en().bit_is_set(),
+            utx_config.cr_utx_prt_sel().bit_is_set(),
+        ) {
+            (false, _) => Parity::ParityNone,
+            (true, false) => Parity::ParityEven,
+            (true, true) => Parity::ParityOdd,
+        };
+        let stopbits = match utx_config.cr_utx_bit_cnt_p().bits() {
+            0 => StopBits::STOP0P5,
+            1 => StopBits::STOP1,
+            2 => StopBits::STOP1P5,
+            _ => StopBits::STOP2,
+        };
+        let wordlength = match utx_config.cr_utx_bit_cnt_d().bits() {
+            4 => WordLength::Five,
+            5 => WordLength::Six,
+            6 => WordLength::Seven,
+            _ => WordLength::Eight,
+        };
+        let order = if uart.data_config.read().cr_uart_bit_inv().bit_is_set() {
+            Order::MsbFirst
+        } else {
+            Order::LsbFirst
+        };
+
+        f.debug_struct("Serial")
+            .field("baudrate", &(self.uart_clk / bit_prd))
+            .field("parity", &parity)
+            .field("stopbits", &stopbits)
+            .field("wordlength", &wordlength)
+            .field("order", &order)
+            .finish()
+    }
+}
+
+/// Returns the bit period for `baud` in UART clock cycles, rounded to nearest, or `None` if it
+/// doesn't fit into the 16 bit divider
+fn baud_divisor(uart_clk: u32, baud: u32) -> Option<u16> {
+    // Can't possibly have a baudrate greater than uart_clock
+    if baud == 0 || baud > uart_clk {
+        return None;
+    }
+    // If we did this calculation using integer math, it always rounds down
+    // Reduce error by doing calculation using floating point, then
+    // add half before converting back to integer to round nearest instead
+    let ans_f = uart_clk as f32 / baud as f32;
+    let ans = (ans_f + 0.5) as u32;
+
+    if !(1..=65535).contains(&ans) {
+        return None;
+    }
+
+    Some(ans as u16)
 }
 
 impl<PINS> embedded_hal::serial::nb::Write<u8> for Serial<pac::UART, PINS> {
@@ -257,10 +759,10 @@ impl<PINS> embedded_hal::serial::nb::Write<u8> for Serial<pac::UART, PINS> {
 
     fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
         // If there's no room to write a byte or more to the FIFO, return WouldBlock
-        if self.uart.uart_fifo_config_1.read().tx_fifo_cnt().bits() == 0 {
+        if self.regs().uart_fifo_config_1.read().tx_fifo_cnt().bits() == 0 {
             Err(nb::Error::WouldBlock)
         } else {
-            self.uart
+            self.regs()
                 .uart_fifo_wdata
                 .write(|w| unsafe { w.bits(word as u32) });
             Ok(())
@@ -269,8 +771,9 @@ impl<PINS> embedded_hal::serial::nb::Write<u8> for Serial<pac::UART, PINS> {
 
     fn flush(&mut self) -> nb::Result<(), Self::Error> {
         // If we're still transmitting or have data in our 32 byte FIFO, return WouldBlock
-        if self.uart.uart_fifo_config_1.read().tx_fifo_cnt().bits() != 32
-            || self.uart.uart_status.read().sts_utx_bus_busy().bit_is_set()
+        let uart = self.regs();
+        if uart.uart_fifo_config_1.read().tx_fifo_cnt().bits() != 32
+            || uart.uart_status.read().sts_utx_bus_busy().bit_is_set()
         {
             Err(nb::Error::WouldBlock)
         } else {
@@ -283,16 +786,33 @@ impl<PINS> embedded_hal::serial::nb::Read<u8> for Serial<pac::UART, PINS> {
     type Error = Error;
 
     fn read(&mut self) -> nb::Result<u8, Self::Error> {
-        if self.uart.uart_fifo_config_1.read().rx_fifo_cnt().bits() == 0 {
+        if let Some(error) = self.check_rx_error() {
+            Err(nb::Error::Other(error))
+        } else if self.regs().uart_fifo_config_1.read().rx_fifo_cnt().bits() == 0 {
             Err(nb::Error::WouldBlock)
-        }
-        else {
-            let ans = self.uart.uart_fifo_rdata.read().bits();
+        } else {
+            let ans = self.regs().uart_fifo_rdata.read().bits();
+            if let Some(before) = self.rx_before_overrun.as_mut() {
+                *before -= 1;
+            }
             Ok((ans & 0xff) as u8)
         }
     }
 }
 
+/// Waits for the TX FIFO and the transmitter to run empty, so power-down sleep doesn't cut off
+/// bytes which are still being sent
+impl<PINS> SleepAware for Serial<pac::UART, PINS> {
+    const PERIPHERAL: Peripheral = Peripheral::Uart0;
+
+    fn quiesce() -> bool {
+        let uart = regs::uart();
+
+        uart.uart_fifo_config_1.read().tx_fifo_cnt().bits() == 32
+            && uart.uart_status.read().sts_utx_bus_busy().bit_is_clear()
+    }
+}
+
 impl<PINS> embedded_hal_zero::serial::Write<u8> for Serial<pac::UART, PINS> {
     type Error = Error;
 
@@ -313,6 +833,97 @@ impl<PINS> embedded_hal_zero::serial::Read<u8> for Serial<pac::UART, PINS> {
     }
 }
 
+/// Serial on a half-duplex bus like RS-485, see the module documentation
+///
+/// The driver enable pin is high while sending, low otherwise.
+pub struct HalfDuplexSerial<PINS, DE> {
+    serial: Serial<pac::UART, PINS>,
+    de: DE,
+}
+
+impl<PINS, DE: OutputPin> HalfDuplexSerial<PINS, DE> {
+    /// Takes the serial and the driver enable pin, which is driven low
+    pub fn new(serial: Serial<pac::UART, PINS>, mut de: DE) -> Self {
+        de.set_low().ok();
+
+        HalfDuplexSerial { serial, de }
+    }
+
+    /// Returns the serial and the driver enable pin
+    pub fn free(self) -> (Serial<pac::UART, PINS>, DE) {
+        (self.serial, self.de)
+    }
+
+    /// Drives the bus and sends `bytes`, then releases the bus once the stop bit of the last
+    /// byte is out
+    ///
+    /// Waits for anything written through the serial before to be sent first. The bus is
+    /// released from the TX complete flag, not the FIFO running empty, so it's not driven
+    /// during the next byte of another station.
+    pub fn send(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        block!(WriteOne::flush(&mut self.serial))?;
+        self.de.set_high().ok();
+
+        let mut result = Ok(());
+        for chunk in bytes.chunks(u16::MAX as usize) {
+            self.serial.set_tx_transfer_len(chunk.len() as u16);
+            self.serial.clear_tx_complete_flag();
+
+            result = chunk
+                .iter()
+                .try_for_each(|&byte| block!(WriteOne::write(&mut self.serial, byte)));
+            if result.is_err() {
+                break;
+            }
+            while !self.serial.is_tx_complete() {}
+        }
+
+        self.de.set_low().ok();
+        self.serial.clear_tx_complete_flag();
+        result
+    }
+
+    /// Returns the serial, e.g. to change its baudrate
+    pub fn serial(&mut self) -> &mut Serial<pac::UART, PINS> {
+        &mut self.serial
+    }
+}
+
+impl<PINS, DE> embedded_hal::serial::nb::Read<u8> for HalfDuplexSerial<PINS, DE> {
+    type Error = Error;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        ReadOne::read(&mut self.serial)
+    }
+}
+
+/// Writer of [`Serial::into_debug_writer`]
+fn write_debug(s: &str) {
+    match DEBUG_SERIAL.try_lock() {
+        Some(mut serial) => {
+            if let Some(serial) = serial.as_mut() {
+                fmt::Write::write_str(serial, s).ok();
+                if debug::is_fatal() {
+                    block!(WriteOne::flush(serial)).ok();
+                }
+            }
+        }
+        // The debug writer isn't re-entered except by a fatal write, after which the write
+        // holding the lock never continues
+        None => {
+            let uart = regs::uart();
+            for byte in s.bytes() {
+                while uart.uart_fifo_config_1.read().tx_fifo_cnt().bits() == 0 {}
+                uart.uart_fifo_wdata
+                    .write(|w| unsafe { w.bits(byte as u32) });
+            }
+            while uart.uart_fifo_config_1.read().tx_fifo_cnt().bits() != 32
+                || uart.uart_status.read().sts_utx_bus_busy().bit_is_set()
+            {}
+        }
+    }
+}
+
 impl<UART, PINS> fmt::Write for Serial<UART, PINS>
 where
     Serial<UART, PINS>: embedded_hal::serial::nb::Write<u8>,
@@ -328,7 +939,10 @@ where
 /// Serial transmit pins - DO NOT IMPLEMENT THIS TRAIT
 pub unsafe trait TxPin<UART> {}
 /// Serial receive pins - DO NOT IMPLEMENT THIS TRAIT
-pub unsafe trait RxPin<UART> {}
+pub unsafe trait RxPin<UART> {
+    /// Number of the pin
+    const PIN: u8;
+}
 /// Serial rts pins - DO NOT IMPLEMENT THIS TRAIT
 pub unsafe trait RtsPin<UART> {}
 /// Serial cts pins - DO NOT IMPLEMENT THIS TRAIT
@@ -339,7 +953,12 @@ macro_rules! impl_uart_pin {
         use crate::gpio::*;
         $(
         unsafe impl<PIN: UartPin<$UartSigi>> TxPin<pac::UART> for (PIN, $UartMuxi<Uart0Tx>) {}
-        unsafe impl<PIN: UartPin<$UartSigi>> RxPin<pac::UART> for (PIN, $UartMuxi<Uart0Rx>) {}
+        unsafe impl<PIN> RxPin<pac::UART> for (PIN, $UartMuxi<Uart0Rx>)
+        where
+            PIN: UartPin<$UartSigi> + PinNumber,
+        {
+            const PIN: u8 = PIN::PIN;
+        }
         unsafe impl<PIN: UartPin<$UartSigi>> RtsPin<pac::UART> for (PIN, $UartMuxi<Uart0Rts>) {}
         unsafe impl<PIN: UartPin<$UartSigi>> CtsPin<pac::UART> for (PIN, $UartMuxi<Uart0Cts>) {}
         // unsafe impl<PIN: UartPin, SIG: UartSig<Uart1Tx>> TxPin<pac::UART> for (PIN, SIG) {}
@@ -367,6 +986,8 @@ pub unsafe trait Pins<UART> {
     const HAS_RX: bool;
     const HAS_RTS: bool;
     const HAS_CTS: bool;
+    /// Number of the RX pin
+    const RX_PIN: Option<u8>;
 }
 
 unsafe impl<UART, TX, RX> Pins<UART> for (TX, RX)
@@ -378,6 +999,7 @@ where
     const HAS_RX: bool = true;
     const HAS_RTS: bool = false;
     const HAS_CTS: bool = false;
+    const RX_PIN: Option<u8> = Some(RX::PIN);
 }
 
 unsafe impl<UART, TX, RX, RTS, CTS> Pins<UART> for (TX, RX, RTS, CTS)
@@ -391,4 +1013,63 @@ where
     const HAS_RX: bool = true;
     const HAS_RTS: bool = true;
     const HAS_CTS: bool = true;
+    const RX_PIN: Option<u8> = Some(RX::PIN);
+}
+
+/// Pins whose signals were routed by [`Serial::uart0_with_mux`], together with the multiplexers
+pub struct MuxedPins<PINS> {
+    pins: PINS,
+    mux: UartMuxBundle,
+}
+
+impl<PINS> MuxedPins<PINS> {
+    /// Returns the pins and the multiplexer bundle
+    pub fn free(self) -> (PINS, UartMuxBundle) {
+        (self.pins, self.mux)
+    }
+}
+
+/// Serial pins which can be routed through a [`UartMuxBundle`] - DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait MuxPins {
+    /// Numbers of the TX, RX, RTS and CTS pins
+    const PINS: [Option<u8>; 4];
+    const HAS_RTS: bool;
+    const HAS_CTS: bool;
+}
+
+unsafe impl<TX, RX> MuxPins for (TX, RX)
+where
+    TX: UartModePin,
+    RX: UartModePin,
+{
+    const PINS: [Option<u8>; 4] = [Some(TX::PIN), Some(RX::PIN), None, None];
+    const HAS_RTS: bool = false;
+    const HAS_CTS: bool = false;
+}
+
+unsafe impl<TX, RX, RTS, CTS> MuxPins for (TX, RX, RTS, CTS)
+where
+    TX: UartModePin,
+    RX: UartModePin,
+    RTS: UartModePin,
+    CTS: UartModePin,
+{
+    const PINS: [Option<u8>; 4] = [Some(TX::PIN), Some(RX::PIN), Some(RTS::PIN), Some(CTS::PIN)];
+    const HAS_RTS: bool = true;
+    const HAS_CTS: bool = true;
+}
+
+unsafe impl<UART, PINS: MuxPins> Pins<UART> for MuxedPins<PINS> {
+    const HAS_TX: bool = true;
+    const HAS_RX: bool = true;
+    const HAS_RTS: bool = PINS::HAS_RTS;
+    const HAS_CTS: bool = PINS::HAS_CTS;
+    const RX_PIN: Option<u8> = PINS::PINS[1];
+}
+
+/// Sleep configurations the RX pin can be added to as a wake source, see
+/// [`Serial::enable_wake_on_activity`] - DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait RxWake {
+    /// Adds `pin` with a falling edge trigger
+    fn add_wake_pin(&mut self, pin: WakePin) -> Result<(), Error>;
 }
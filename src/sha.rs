@@ -0,0 +1,563 @@
+/*!
+  # Hardware SHA engine
+  The SEC_ENG block contains a SHA engine which computes SHA-1, SHA-224 and SHA-256 over 64 byte
+  blocks read directly from memory. The driver buffers partial blocks, so data can be fed in
+  chunks of any size, e.g. while streaming a firmware image from flash.
+
+  ## Example
+  ```rust
+    use bl602_hal::sec_eng::SecEngExt;
+    use bl602_hal::sha::{Mode, Sha};
+
+    let sec_eng = dp.SEC_ENG.split();
+    let mut sha = Sha::new(sec_eng.sha, &mut parts.clk_cfg);
+
+    // Incremental
+    sha.start(Mode::Sha256);
+    sha.update(b"hello ").unwrap();
+    sha.update(b"world").unwrap();
+    let mut hash = [0u8; 32];
+    sha.finalize(&mut hash).unwrap();
+
+    // One-shot
+    let digest = sha.digest(Mode::Sha256, b"hello world").unwrap();
+    assert_eq!(digest.as_bytes(), &hash[..]);
+  ```
+
+  # Link mode
+  [`Sha::digest_linked`] hands the engine a link configuration in memory instead of programming
+  its registers for every trigger, and lets it fetch the whole message itself. This is meant for
+  large messages like firmware images: the data can be hashed straight from the memory mapped
+  flash (XIP, starting at `0x2300_0000`), since the engine reads through the same bus and cache
+  as the CPU. After writing to flash through the flash controller, the cache has to be
+  invalidated before hashing the new contents, or the engine may read stale lines.
+
+  The `digest` feature implements the [`digest`](https://crates.io/crates/digest) 0.9 traits
+  `Update`, `FixedOutput`, `Reset` and `BlockInput` for `Sha1`, `Sha224` and `Sha256`, so the
+  engine can be used by code bounded by those traits. `Digest` is not implemented: its blanket
+  implementation also needs `Default` and `Clone`, which a wrapper owning the one engine can't
+  provide. Code generic over `D: Digest`, e.g. `hmac`, `signature` or `ecdsa`, can't take the
+  wrappers and needs a software implementation.
+*/
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use crate::clock::{glb_ahb_slave1_clock_enable, AhbSlave1};
+use crate::gpio::ClkCfg;
+use crate::pac;
+use crate::sec_eng::Sha0;
+use crate::secure::{zeroize_bytes, Zeroize, Zeroizing};
+
+/// Size of a block processed by the engine, in bytes
+pub const BLOCK_SIZE: usize = 64;
+
+/// Number of polling iterations to wait for the engine before giving up
+const SHA_TIMEOUT: u32 = 1_000_000;
+
+/// The message length register counts blocks in 16 bits
+const MAX_BLOCKS_PER_TRIGGER: usize = 0xffff;
+
+/// Shorter messages are hashed through the registers, where setting up a link configuration
+/// costs more than it saves
+const LINK_MODE_THRESHOLD: usize = 16 * BLOCK_SIZE;
+
+/// SHA error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The engine did not finish in time
+    Timeout,
+    /// `update` or `finalize` was called without `start`
+    NotStarted,
+    /// The output buffer is smaller than the digest of the current mode
+    BufferTooSmall,
+}
+
+/// Hash algorithm
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Mode {
+    /// SHA-256, 32 byte digest
+    Sha256,
+    /// SHA-224, 28 byte digest
+    Sha224,
+    /// SHA-1, 20 byte digest
+    Sha1,
+}
+
+impl Mode {
+    /// Length of the digest in bytes
+    pub fn output_len(&self) -> usize {
+        match self {
+            Mode::Sha256 => 32,
+            Mode::Sha224 => 28,
+            Mode::Sha1 => 20,
+        }
+    }
+
+    fn bits(&self) -> u8 {
+        match self {
+            Mode::Sha256 => 0,
+            Mode::Sha224 => 1,
+            Mode::Sha1 => 2,
+        }
+    }
+}
+
+/// Result of a one-shot [`Sha::digest`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Digest {
+    bytes: [u8; 32],
+    len: usize,
+}
+
+impl Digest {
+    /// The digest, its length depends on the mode it was computed with
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl AsRef<[u8]> for Digest {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+/// The engine reads words from memory, keep the block buffer aligned
+#[repr(align(4))]
+struct Block([u8; BLOCK_SIZE]);
+
+impl Zeroize for Block {
+    fn zeroize(&mut self) {
+        zeroize_bytes(&mut self.0);
+    }
+}
+
+/// Configuration read by the engine in link mode, laid out as in the vendor SDK
+#[repr(C, align(4))]
+struct LinkConfig {
+    /// [4:2] mode, [6] continue the previous hash, [31:16] message length in blocks
+    ctrl: u32,
+    source: u32,
+    /// Written by the engine, the digest in memory byte order
+    result: [u32; 8],
+}
+
+/// SHA engine driver
+pub struct Sha {
+    sha: Sha0,
+    mode: Option<Mode>,
+    /// May hold secrets like HMAC keys, so it is erased when the driver is dropped
+    block: Zeroizing<Block>,
+    buffered: usize,
+    /// Total message length in bytes
+    length: u64,
+    /// Whether the engine already holds an intermediate hash of this message
+    continued: bool,
+}
+
+impl Sha {
+    /// Enables the SHA engine
+    pub fn new(sha: Sha0, _clk_cfg: &mut ClkCfg) -> Self {
+        glb_ahb_slave1_clock_enable(AhbSlave1::Sec, true);
+
+        Sha {
+            sha,
+            mode: None,
+            block: Zeroizing::new(Block([0; BLOCK_SIZE])),
+            buffered: 0,
+            length: 0,
+            continued: false,
+        }
+    }
+
+    /// Starts a new message, discarding any message in progress
+    pub fn start(&mut self, mode: Mode) {
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+        sec_eng.se_sha_0_ctrl.modify(|_, w| unsafe {
+            w.se_sha_0_en()
+                .set_bit()
+                .se_sha_0_mode()
+                .bits(mode.bits())
+                .se_sha_0_link_mode()
+                .clear_bit()
+                .se_sha_0_int_mask()
+                .set_bit()
+        });
+
+        self.mode = Some(mode);
+        self.buffered = 0;
+        self.length = 0;
+        self.continued = false;
+    }
+
+    /// Feeds `data` into the message started with [`start`](Sha::start)
+    pub fn update(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        if self.mode.is_none() {
+            return Err(Error::NotStarted);
+        }
+        self.length += data.len() as u64;
+
+        // Top up a partially filled block first
+        if self.buffered > 0 {
+            let n = core::cmp::min(BLOCK_SIZE - self.buffered, data.len());
+            self.block.0[self.buffered..self.buffered + n].copy_from_slice(&data[..n]);
+            self.buffered += n;
+            data = &data[n..];
+
+            if self.buffered < BLOCK_SIZE {
+                return Ok(());
+            }
+            self.process_buffer()?;
+        }
+
+        // Whole blocks can be read by the engine in place if they are word aligned
+        let whole = data.len() - data.len() % BLOCK_SIZE;
+        if whole > 0 && data.as_ptr() as usize % 4 == 0 {
+            for chunk in data[..whole].chunks(MAX_BLOCKS_PER_TRIGGER * BLOCK_SIZE) {
+                self.process(chunk.as_ptr(), chunk.len() / BLOCK_SIZE)?;
+            }
+            data = &data[whole..];
+        }
+
+        for chunk in data.chunks(BLOCK_SIZE) {
+            self.block.0[..chunk.len()].copy_from_slice(chunk);
+            self.buffered = chunk.len();
+            if self.buffered == BLOCK_SIZE {
+                self.process_buffer()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pads the message, writes the digest to the start of `out` and ends the message
+    ///
+    /// `out` must be at least [`Mode::output_len`] bytes long, 32 bytes are always enough.
+    pub fn finalize(&mut self, out: &mut [u8]) -> Result<(), Error> {
+        let mode = self.mode.ok_or(Error::NotStarted)?;
+        if out.len() < mode.output_len() {
+            return Err(Error::BufferTooSmall);
+        }
+
+        // Padding: a single 1 bit, zeros, then the message length in bits as big endian u64
+        let bit_length = self.length.wrapping_mul(8);
+        self.block.0[self.buffered] = 0x80;
+        self.buffered += 1;
+        if self.buffered > BLOCK_SIZE - 8 {
+            self.block.0[self.buffered..]
+                .iter_mut()
+                .for_each(|b| *b = 0);
+            self.process_buffer()?;
+        }
+        self.block.0[self.buffered..BLOCK_SIZE - 8]
+            .iter_mut()
+            .for_each(|b| *b = 0);
+        self.block.0[BLOCK_SIZE - 8..].copy_from_slice(&bit_length.to_be_bytes());
+        self.process_buffer()?;
+
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+        let words = [
+            sec_eng.se_sha_0_hash_l_0.read().bits(),
+            sec_eng.se_sha_0_hash_l_1.read().bits(),
+            sec_eng.se_sha_0_hash_l_2.read().bits(),
+            sec_eng.se_sha_0_hash_l_3.read().bits(),
+            sec_eng.se_sha_0_hash_l_4.read().bits(),
+            sec_eng.se_sha_0_hash_l_5.read().bits(),
+            sec_eng.se_sha_0_hash_l_6.read().bits(),
+            sec_eng.se_sha_0_hash_l_7.read().bits(),
+        ];
+        // The hash registers hold the digest in memory byte order
+        for (bytes, word) in out[..mode.output_len()].chunks_mut(4).zip(words.iter()) {
+            bytes.copy_from_slice(&word.to_le_bytes()[..bytes.len()]);
+        }
+
+        self.block.zeroize();
+        self.mode = None;
+        self.buffered = 0;
+        self.length = 0;
+        self.continued = false;
+
+        Ok(())
+    }
+
+    /// Computes the digest of `data` in one go
+    pub fn digest(&mut self, mode: Mode, data: &[u8]) -> Result<Digest, Error> {
+        let mut digest = Digest {
+            bytes: [0; 32],
+            len: mode.output_len(),
+        };
+
+        self.start(mode);
+        self.update(data)?;
+        self.finalize(&mut digest.bytes)?;
+
+        Ok(digest)
+    }
+
+    /// Computes the digest of `data` in link mode, see the module documentation
+    ///
+    /// Discards any message in progress. Messages below 1 KiB or not aligned to 4 bytes are hashed
+    /// with [`digest`](Sha::digest) instead, which gives the same result.
+    pub fn digest_linked(&mut self, mode: Mode, data: &[u8]) -> Result<Digest, Error> {
+        if data.len() < LINK_MODE_THRESHOLD || data.as_ptr() as usize % 4 != 0 {
+            return self.digest(mode, data);
+        }
+
+        // The register based message in progress is gone, the engine state is overwritten
+        self.mode = None;
+        self.buffered = 0;
+        self.length = 0;
+        self.continued = false;
+
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+        sec_eng.se_sha_0_ctrl.modify(|_, w| {
+            w.se_sha_0_en()
+                .set_bit()
+                .se_sha_0_link_mode()
+                .set_bit()
+                .se_sha_0_int_mask()
+                .set_bit()
+        });
+
+        let mut link = LinkConfig {
+            ctrl: (mode.bits() as u32) << 2,
+            source: 0,
+            result: [0; 8],
+        };
+        let result = process_linked(&mut link, data);
+
+        sec_eng
+            .se_sha_0_ctrl
+            .modify(|_, w| w.se_sha_0_link_mode().clear_bit());
+        result?;
+
+        let mut digest = Digest {
+            bytes: [0; 32],
+            len: mode.output_len(),
+        };
+        for (bytes, word) in digest.bytes.chunks_mut(4).zip(link.result.iter()) {
+            let word = unsafe { core::ptr::read_volatile(word) };
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+
+        Ok(digest)
+    }
+
+    /// Disables the SHA engine and releases it
+    pub fn free(self) -> Sha0 {
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+        sec_eng
+            .se_sha_0_ctrl
+            .modify(|_, w| w.se_sha_0_en().clear_bit());
+
+        self.sha
+    }
+
+    fn process_buffer(&mut self) -> Result<(), Error> {
+        let ptr = self.block.0.as_ptr();
+        let result = self.process(ptr, 1);
+        self.buffered = 0;
+        result
+    }
+
+    /// Hashes `blocks` blocks of 64 bytes starting at `ptr`
+    fn process(&mut self, ptr: *const u8, blocks: usize) -> Result<(), Error> {
+        let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+
+        sec_eng
+            .se_sha_0_msa
+            .write(|w| unsafe { w.bits(ptr as u32) });
+        sec_eng.se_sha_0_ctrl.modify(|_, w| unsafe {
+            w.se_sha_0_hash_sel()
+                .bit(self.continued)
+                .se_sha_0_msg_len()
+                .bits(blocks as u16)
+        });
+
+        // The engine reads the message from memory, make sure all writes to it have happened
+        compiler_fence(Ordering::SeqCst);
+        sec_eng
+            .se_sha_0_ctrl
+            .modify(|_, w| w.se_sha_0_trig_1t().set_bit());
+        let result = wait_idle();
+        sec_eng
+            .se_sha_0_ctrl
+            .modify(|_, w| w.se_sha_0_trig_1t().clear_bit());
+
+        self.continued = true;
+        result
+    }
+}
+
+/// Hashes and pads the whole message `data` with the engine in link mode
+fn process_linked(link: &mut LinkConfig, data: &[u8]) -> Result<(), Error> {
+    let whole = data.len() - data.len() % BLOCK_SIZE;
+    let mut continued = false;
+
+    for chunk in data[..whole].chunks(MAX_BLOCKS_PER_TRIGGER * BLOCK_SIZE) {
+        trigger_linked(link, chunk.as_ptr(), chunk.len() / BLOCK_SIZE, continued)?;
+        continued = true;
+    }
+
+    // The remaining bytes and the padding take one or two blocks
+    #[repr(align(4))]
+    struct Tail([u8; 2 * BLOCK_SIZE]);
+
+    let rest = &data[whole..];
+    let blocks = if rest.len() + 9 > BLOCK_SIZE { 2 } else { 1 };
+    let end = blocks * BLOCK_SIZE;
+    let mut tail = Tail([0; 2 * BLOCK_SIZE]);
+    tail.0[..rest.len()].copy_from_slice(rest);
+    tail.0[rest.len()] = 0x80;
+    let bit_length = (data.len() as u64).wrapping_mul(8);
+    tail.0[end - 8..end].copy_from_slice(&bit_length.to_be_bytes());
+
+    trigger_linked(link, tail.0.as_ptr(), blocks, continued)
+}
+
+/// Hashes `blocks` blocks of 64 bytes starting at `ptr` as described by `link`
+fn trigger_linked(
+    link: &mut LinkConfig,
+    ptr: *const u8,
+    blocks: usize,
+    continued: bool,
+) -> Result<(), Error> {
+    let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+
+    let ctrl = (link.ctrl & 0x1c) | ((continued as u32) << 6) | ((blocks as u32) << 16);
+    unsafe {
+        core::ptr::write_volatile(&mut link.ctrl, ctrl);
+        core::ptr::write_volatile(&mut link.source, ptr as u32);
+    }
+    sec_eng
+        .se_sha_0_link
+        .write(|w| unsafe { w.bits(link as *mut LinkConfig as u32) });
+
+    // The engine reads the configuration and the message from memory, and writes the result back
+    compiler_fence(Ordering::SeqCst);
+    sec_eng
+        .se_sha_0_ctrl
+        .modify(|_, w| w.se_sha_0_trig_1t().set_bit());
+    let result = wait_idle();
+    sec_eng
+        .se_sha_0_ctrl
+        .modify(|_, w| w.se_sha_0_trig_1t().clear_bit());
+    compiler_fence(Ordering::SeqCst);
+
+    result
+}
+
+/// Waits until the SHA engine is no longer busy
+fn wait_idle() -> Result<(), Error> {
+    let sec_eng = unsafe { &*pac::SEC_ENG::ptr() };
+
+    // The busy flag is only raised one cycle after triggering, reading the control register
+    // once takes longer than that
+    let _ = sec_eng.se_sha_0_ctrl.read();
+
+    let mut timeout_countdown = SHA_TIMEOUT;
+    while sec_eng.se_sha_0_ctrl.read().se_sha_0_busy().bit_is_set() {
+        if timeout_countdown == 0 {
+            return Err(Error::Timeout);
+        }
+        timeout_countdown -= 1;
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "digest")]
+mod digest_impls {
+    use digest::consts::{U20, U28, U32, U64};
+    use digest::generic_array::GenericArray;
+    use digest::{BlockInput, FixedOutput, Reset, Update};
+
+    use super::{Mode, Sha};
+    use crate::gpio::ClkCfg;
+    use crate::sec_eng::Sha0;
+
+    const TIMEOUT: &str = "SHA engine timed out, is the SEC_ENG clock enabled?";
+
+    macro_rules! digest_impl {
+        ($($Name:ident: ($mode:expr, $OutputSize:ty, $doc:expr),)+) => {
+            $(
+                #[doc = $doc]
+                ///
+                /// Implements `Update`, `FixedOutput`, `Reset` and `BlockInput` of `digest` 0.9 on
+                /// top of the hardware engine, but not `Digest`, see the module documentation.
+                ///
+                /// # Precondition
+                ///
+                /// The SEC_ENG clock must stay enabled while the wrapper is in use, which nothing
+                /// in the HAL changes. The engine can only time out without it, and since the
+                /// traits can't return the error, the methods panic in that case.
+                pub struct $Name {
+                    sha: Sha,
+                }
+
+                impl $Name {
+                    /// Enables the SHA engine and starts a new message
+                    pub fn new(sha: Sha0, clk_cfg: &mut ClkCfg) -> Self {
+                        let mut sha = Sha::new(sha, clk_cfg);
+                        sha.start($mode);
+                        $Name { sha }
+                    }
+
+                    /// Releases the SHA engine
+                    pub fn free(self) -> Sha0 {
+                        self.sha.free()
+                    }
+                }
+
+                impl BlockInput for $Name {
+                    type BlockSize = U64;
+                }
+
+                impl Update for $Name {
+                    /// # Panics
+                    ///
+                    /// Panics if the engine times out, see the precondition of the type. The
+                    /// message is always started, so that's the only error.
+                    fn update(&mut self, data: impl AsRef<[u8]>) {
+                        self.sha.update(data.as_ref()).expect(TIMEOUT);
+                    }
+                }
+
+                impl FixedOutput for $Name {
+                    type OutputSize = $OutputSize;
+
+                    /// # Panics
+                    ///
+                    /// Panics if the engine times out, see the precondition of the type.
+                    fn finalize_into(mut self, out: &mut GenericArray<u8, Self::OutputSize>) {
+                        self.sha.finalize(out).expect(TIMEOUT);
+                    }
+
+                    /// # Panics
+                    ///
+                    /// Panics if the engine times out, see the precondition of the type.
+                    fn finalize_into_reset(&mut self, out: &mut GenericArray<u8, Self::OutputSize>) {
+                        self.sha.finalize(out).expect(TIMEOUT);
+                        self.sha.start($mode);
+                    }
+                }
+
+                impl Reset for $Name {
+                    fn reset(&mut self) {
+                        self.sha.start($mode);
+                    }
+                }
+            )+
+        };
+    }
+
+    digest_impl! {
+        Sha256: (Mode::Sha256, U32, "Hardware SHA-256"),
+        Sha224: (Mode::Sha224, U28, "Hardware SHA-224"),
+        Sha1: (Mode::Sha1, U20, "Hardware SHA-1"),
+    }
+}
+
+#[cfg(feature = "digest")]
+pub use digest_impls::{Sha1, Sha224, Sha256};
@@ -19,18 +19,95 @@
         clocks,
     );
   ```
+
+  ## Remapping example
+  MISO and MOSI can be moved to other pins at runtime, e.g. to switch between two devices which
+  are wired to different pins:
+  ```rust
+    let (spi, old_miso, old_mosi) = spi
+        .remap(parts.pin12.into_spi_miso(), parts.pin13.into_spi_mosi())
+        .map_err(|(error, ..)| error)
+        .unwrap();
+  ```
+
+  ## Ending a transfer
+  The blocking `write` returns once the RX FIFO has received the frames sent, but the
+  non-blocking `FullDuplex::write` returns as soon as the TX FIFO took the frame. Call
+  [`Spi::flush_and_wait`] before deasserting a chip select driven as a GPIO, so the last frame
+  isn't cut off.
+  ```rust
+    for byte in command.iter() {
+        nb::block!(spi.write(*byte)).unwrap();
+    }
+    spi.flush_and_wait().unwrap();
+    cs.set_high().unwrap();
+  ```
+
+  ## Bit-banged SPI
+  [`GpioBitBangSpi`] drives any four GPIO pins in software, e.g. while the SPI peripheral is
+  busy with a long transfer. It implements the same blocking traits, at a clock rate limited by
+  how fast the core toggles pins.
+  ```rust
+    let mut soft_spi = GpioBitBangSpi::new(
+        parts.pin11.into_floating_output().erase(),
+        parts.pin12.into_floating_input().erase(),
+        parts.pin14.into_floating_output().erase(),
+        parts.pin17.into_floating_output().erase(),
+        embedded_hal::spi::MODE_0,
+        80, // 1 MHz at 160 MHz system clock
+    );
+    soft_spi.transfer_inplace(&mut buffer).unwrap();
+  ```
+
+  ## PWM on MOSI
+  [`SpiPwm`] turns MOSI into a PWM output: a pattern of 32 bits per word of the buffer it's given,
+  the first of which are ones for the duty cycle, is sent over and over by a DMA channel, in 32 bit frames without gaps
+  between them. The period is the pattern length divided by the SPI frequency, e.g. 1024 bits at
+  20 MHz give 10 bit resolution at 19.5 kHz, far above what the PWM peripheral's divider allows
+  at that resolution.
+  ```rust
+    let mut pattern = [0; 32];
+    let mut pwm = SpiPwm::new(spi, dp.DMA, 0, &mut pattern);
+    pwm.set_duty(256);
+    pwm.run(20_000); // about a second
+  ```
+
+  ## SPI NAND flash
+  [`nand::SpiNand`] drives an SPI NAND flash on any of the blocking buses above, with on-chip
+  ECC and a bad block table.
 */
 
 use bl602_pac::SPI;
-pub use embedded_hal::spi::blocking::Transfer;
+pub use embedded_hal::spi::blocking::{Transfer, TransferInplace};
 use embedded_hal::spi::nb::FullDuplex;
 pub use embedded_hal::spi::Mode;
 use embedded_hal_zero::spi::FullDuplex as FullDuplexZero;
 use embedded_time::rate::Hertz;
 
+use core::marker::PhantomData;
+use core::ptr;
+
+use embedded_hal::digital::blocking::{InputPin, OutputPin};
+use embedded_hal::spi::{Phase, Polarity};
+
 use crate::pac;
 
 use crate::clock::Clocks;
+use crate::delay::McycleDelay;
+use crate::dma::{self, Lli};
+use crate::gpio::{AnyPin, Floating, Input, Output, PinNumber};
+use crate::sync;
+
+pub mod nand;
+
+/// Number of polling iterations to wait for an ongoing transfer before remapping pins
+pub const SPI_IDLE_TIMEOUT: u32 = 100_000;
+
+/// Depth of the TX and RX FIFOs, `tx_fifo_cnt` counts the free entries
+const FIFO_DEPTH: u8 = 4;
+
+/// Descriptors [`SpiPwm::run`] cycles through
+const PWM_RING: usize = 4;
 
 /// SPI error
 #[derive(Debug)]
@@ -44,6 +121,23 @@ pub enum Error {
     TxOverflow,
     /// Tx underflow occurred
     TxUnderflow,
+    /// The bus didn't become idle within [`SPI_IDLE_TIMEOUT`] polls
+    Timeout,
+    /// [`SpiPwm::run`] lost count and sent more patterns than asked for
+    DmaOverrun,
+}
+
+impl embedded_hal::spi::Error for Error {
+    fn kind(&self) -> embedded_hal::spi::ErrorKind {
+        match self {
+            Error::RxOverflow => embedded_hal::spi::ErrorKind::Overrun,
+            Error::RxUnderflow
+            | Error::TxOverflow
+            | Error::TxUnderflow
+            | Error::Timeout
+            | Error::DmaOverrun => embedded_hal::spi::ErrorKind::Other,
+        }
+    }
 }
 
 /// The bit format to send the data in
@@ -211,6 +305,144 @@ where
             .spi_fifo_config_0
             .write(|w| w.rx_fifo_clr().set_bit().tx_fifo_clr().set_bit());
     }
+
+    /// Waits until the TX FIFO is empty and the last frame has been shifted out
+    ///
+    /// An empty FIFO alone only means the last frame has moved to the shift register, so the bus
+    /// busy flag is checked as well. Fails with [`Error::Timeout`] after [`SPI_IDLE_TIMEOUT`]
+    /// polls, e.g. when the clock is gated.
+    pub fn flush_and_wait(&mut self) -> Result<(), Error> {
+        let mut timeout_countdown = SPI_IDLE_TIMEOUT;
+
+        while self.spi.spi_fifo_config_1.read().tx_fifo_cnt().bits() != FIFO_DEPTH
+            // sts_spi_bus_busy
+            || self.spi.spi_bus_busy.read().bits() & 1 != 0
+        {
+            if timeout_countdown == 0 {
+                return Err(Error::Timeout);
+            }
+            timeout_countdown -= 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl<MISO, MOSI, SS, SCLK> Spi<pac::SPI, (MISO, MOSI, SS, SCLK)>
+where
+    MISO: MisoPin<pac::SPI> + PinNumber,
+    MOSI: MosiPin<pac::SPI> + PinNumber,
+    SS: SsPin<pac::SPI>,
+    SCLK: SclkPin<pac::SPI>,
+{
+    /**
+      Moves MISO and MOSI to other pins, e.g. to talk to a device which is wired to them.
+
+      The new pins need to be configured with `into_spi_miso` and `into_spi_mosi` already. The
+      FIFOs are cleared after the current frame has been shifted out, so no stale data is sent on
+      the new pins, then the old pins are switched to floating inputs and returned with their
+      number erased. The BL602 has no separate pin assignment for the SPI block, each pin's
+      function selection is all there is to it.
+
+      Fails with [`Error::Timeout`] if the current frame isn't done after [`SPI_IDLE_TIMEOUT`]
+      polls, e.g. when the clock is gated. Nothing is changed then, the driver and the new pins
+      are handed back with the error.
+    */
+    pub fn remap<NEWMISO, NEWMOSI>(
+        mut self,
+        miso: NEWMISO,
+        mosi: NEWMOSI,
+    ) -> Result<
+        (
+            Spi<pac::SPI, (NEWMISO, NEWMOSI, SS, SCLK)>,
+            AnyPin<Input<Floating>>,
+            AnyPin<Input<Floating>>,
+        ),
+        (Error, Self, NEWMISO, NEWMOSI),
+    >
+    where
+        NEWMISO: MisoPin<pac::SPI>,
+        NEWMOSI: MosiPin<pac::SPI>,
+    {
+        if let Err(error) = self.flush_for_remap() {
+            return Err((error, self, miso, mosi));
+        }
+
+        let (_, _, ss, sclk) = self.pins;
+        let spi = Spi {
+            spi: self.spi,
+            pins: (miso, mosi, ss, sclk),
+        };
+
+        Ok((spi, release_pin::<MISO>(), release_pin::<MOSI>()))
+    }
+}
+
+impl<MISO, MOSI, SCLK> Spi<pac::SPI, (MISO, MOSI, SCLK)>
+where
+    MISO: MisoPin<pac::SPI> + PinNumber,
+    MOSI: MosiPin<pac::SPI> + PinNumber,
+    SCLK: SclkPin<pac::SPI>,
+{
+    /// Moves MISO and MOSI to other pins, see the 4 pin version of `remap` for details.
+    pub fn remap<NEWMISO, NEWMOSI>(
+        mut self,
+        miso: NEWMISO,
+        mosi: NEWMOSI,
+    ) -> Result<
+        (
+            Spi<pac::SPI, (NEWMISO, NEWMOSI, SCLK)>,
+            AnyPin<Input<Floating>>,
+            AnyPin<Input<Floating>>,
+        ),
+        (Error, Self, NEWMISO, NEWMOSI),
+    >
+    where
+        NEWMISO: MisoPin<pac::SPI>,
+        NEWMOSI: MosiPin<pac::SPI>,
+    {
+        if let Err(error) = self.flush_for_remap() {
+            return Err((error, self, miso, mosi));
+        }
+
+        let (_, _, sclk) = self.pins;
+        let spi = Spi {
+            spi: self.spi,
+            pins: (miso, mosi, sclk),
+        };
+
+        Ok((spi, release_pin::<MISO>(), release_pin::<MOSI>()))
+    }
+}
+
+impl<PINS> Spi<pac::SPI, PINS>
+where
+    PINS: Pins<pac::SPI>,
+{
+    /// Waits for the frame being transferred, then drops whatever is left in the FIFOs
+    fn flush_for_remap(&mut self) -> Result<(), Error> {
+        let mut timeout_countdown = SPI_IDLE_TIMEOUT;
+        // sts_spi_bus_busy
+        while self.spi.spi_bus_busy.read().bits() & 1 != 0 {
+            if timeout_countdown == 0 {
+                return Err(Error::Timeout);
+            }
+            timeout_countdown -= 1;
+        }
+
+        self.clear_fifo();
+
+        Ok(())
+    }
+}
+
+/// Takes pin `P` out of SPI mode
+fn release_pin<P: PinNumber>() -> AnyPin<Input<Floating>> {
+    let pin: AnyPin<()> = AnyPin {
+        pin: P::PIN,
+        _mode: PhantomData,
+    };
+    pin.into_floating_input()
 }
 
 impl<PINS> FullDuplex<u8> for Spi<pac::SPI, PINS>
@@ -275,13 +507,13 @@ impl<PINS> embedded_hal_zero::blocking::spi::transfer::Default<u8> for Spi<pac::
 }
 
 // This is basically the default impl of spi::blocking::Transfer from e-h 0.2
-impl<PINS> embedded_hal::spi::blocking::Transfer<u8> for Spi<pac::SPI, PINS>
+impl<PINS> embedded_hal::spi::blocking::TransferInplace<u8> for Spi<pac::SPI, PINS>
 where
     PINS: Pins<pac::SPI>,
 {
     type Error = Error;
 
-    fn transfer(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+    fn transfer_inplace(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
         for word in words.iter_mut() {
             nb::block!(self.send(*word))?;
             *word = nb::block!(FullDuplexZero::read(self))?;
@@ -291,6 +523,29 @@ where
     }
 }
 
+impl<PINS> embedded_hal::spi::blocking::Transfer<u8> for Spi<pac::SPI, PINS>
+where
+    PINS: Pins<pac::SPI>,
+{
+    type Error = Error;
+
+    /// Transfers `max(read.len(), write.len())` words; zeros are sent once `write` is exhausted
+    /// and received words beyond the length of `read` are discarded
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        let len = core::cmp::max(read.len(), write.len());
+
+        for i in 0..len {
+            nb::block!(self.send(write.get(i).copied().unwrap_or(0)))?;
+            let word = nb::block!(FullDuplexZero::read(self))?;
+            if let Some(r) = read.get_mut(i) {
+                *r = word;
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl<PINS> embedded_hal_zero::blocking::spi::write::Default<u8> for Spi<pac::SPI, PINS> where
     PINS: Pins<pac::SPI>
 {
@@ -343,3 +598,363 @@ where
 //     PINS: Pins<pac::SPI>
 // {
 // }
+
+/// SPI master bit-banged on GPIO pins, see the module documentation
+///
+/// The chip select is asserted for the duration of each call to one of the blocking traits. They
+/// never fail, their error type is [`Error`] as `Infallible` doesn't implement
+/// `embedded_hal::spi::Error`.
+pub struct GpioBitBangSpi {
+    mosi: AnyPin<Output<Floating>>,
+    miso: AnyPin<Input<Floating>>,
+    sclk: AnyPin<Output<Floating>>,
+    cs: AnyPin<Output<Floating>>,
+    mode: Mode,
+    half_period_cycles: u32,
+}
+
+impl GpioBitBangSpi {
+    /// Creates the driver and puts the clock and chip select into their idle state
+    ///
+    /// Each half of a clock period lasts at least `half_period_cycles` core clock cycles, the
+    /// pin accesses add a few more.
+    pub fn new(
+        mosi: AnyPin<Output<Floating>>,
+        miso: AnyPin<Input<Floating>>,
+        sclk: AnyPin<Output<Floating>>,
+        cs: AnyPin<Output<Floating>>,
+        mode: Mode,
+        half_period_cycles: u32,
+    ) -> Self {
+        let mut spi = GpioBitBangSpi {
+            mosi,
+            miso,
+            sclk,
+            cs,
+            mode,
+            half_period_cycles,
+        };
+        spi.cs.set_high().ok();
+        spi.set_sclk(false);
+
+        spi
+    }
+
+    /// Releases the pins as `(mosi, miso, sclk, cs)`
+    pub fn free(
+        self,
+    ) -> (
+        AnyPin<Output<Floating>>,
+        AnyPin<Input<Floating>>,
+        AnyPin<Output<Floating>>,
+        AnyPin<Output<Floating>>,
+    ) {
+        (self.mosi, self.miso, self.sclk, self.cs)
+    }
+
+    /// Drives the clock to its active level if `active` is set, to its idle level otherwise
+    fn set_sclk(&mut self, active: bool) {
+        let high = active != (self.mode.polarity == Polarity::IdleHigh);
+        if high {
+            self.sclk.set_high().ok();
+        } else {
+            self.sclk.set_low().ok();
+        }
+    }
+
+    fn set_mosi(&mut self, high: bool) {
+        if high {
+            self.mosi.set_high().ok();
+        } else {
+            self.mosi.set_low().ok();
+        }
+    }
+
+    fn wait_half_period(&self) {
+        McycleDelay::delay_cycles(self.half_period_cycles as u64);
+    }
+
+    /// Shifts out `word` MSB first and returns the word shifted in at the same time
+    fn transfer_word(&mut self, word: u8) -> u8 {
+        let mut received = 0;
+
+        for bit in (0..8).rev() {
+            let out = word & (1 << bit) != 0;
+
+            let sample = match self.mode.phase {
+                Phase::CaptureOnFirstTransition => {
+                    self.set_mosi(out);
+                    self.wait_half_period();
+                    self.set_sclk(true);
+                    let sample = self.miso.is_high().unwrap_or(false);
+                    self.wait_half_period();
+                    self.set_sclk(false);
+                    sample
+                }
+                Phase::CaptureOnSecondTransition => {
+                    self.set_sclk(true);
+                    self.set_mosi(out);
+                    self.wait_half_period();
+                    self.set_sclk(false);
+                    let sample = self.miso.is_high().unwrap_or(false);
+                    self.wait_half_period();
+                    sample
+                }
+            };
+
+            received |= (sample as u8) << bit;
+        }
+
+        received
+    }
+
+    /// Runs `f` with the chip select asserted
+    fn selected<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        self.cs.set_low().ok();
+        self.wait_half_period();
+        let result = f(self);
+        self.wait_half_period();
+        self.cs.set_high().ok();
+
+        result
+    }
+}
+
+impl embedded_hal::spi::blocking::TransferInplace<u8> for GpioBitBangSpi {
+    type Error = Error;
+
+    fn transfer_inplace(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.selected(|spi| {
+            for word in words.iter_mut() {
+                *word = spi.transfer_word(*word);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+impl embedded_hal::spi::blocking::Transfer<u8> for GpioBitBangSpi {
+    type Error = Error;
+
+    /// Transfers `max(read.len(), write.len())` words; zeros are sent once `write` is exhausted
+    /// and received words beyond the length of `read` are discarded
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        let len = core::cmp::max(read.len(), write.len());
+
+        self.selected(|spi| {
+            for i in 0..len {
+                let word = spi.transfer_word(write.get(i).copied().unwrap_or(0));
+                if let Some(r) = read.get_mut(i) {
+                    *r = word;
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
+
+impl embedded_hal::spi::blocking::Write<u8> for GpioBitBangSpi {
+    type Error = Error;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.selected(|spi| {
+            for word in words {
+                spi.transfer_word(*word);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+impl embedded_hal::spi::blocking::WriteIter<u8> for GpioBitBangSpi {
+    type Error = Error;
+
+    fn write_iter<WI>(&mut self, words: WI) -> Result<(), Self::Error>
+    where
+        WI: IntoIterator<Item = u8>,
+    {
+        self.selected(|spi| {
+            for word in words.into_iter() {
+                spi.transfer_word(word);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// PWM on the MOSI pin of an SPI master, see the module documentation
+///
+/// The pattern is kept in a buffer of 32 bit frames, at most 4095, which the DMA channel reads.
+pub struct SpiPwm<'a, PINS> {
+    spi: Spi<pac::SPI, PINS>,
+    dma: pac::DMA,
+    channel: usize,
+    pattern: &'a mut [u32],
+    ring: [Lli; PWM_RING],
+    duty: u16,
+}
+
+impl<'a, PINS> SpiPwm<'a, PINS>
+where
+    PINS: Pins<pac::SPI>,
+{
+    /// Takes the SPI master, DMA channel `channel` and the pattern buffer, with the duty cycle
+    /// at 0
+    ///
+    /// The DMA controller is taken as a whole, the other channels are left alone.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `channel` isn't below 4, or `pattern` is empty or longer than 4095 words.
+    pub fn new(
+        spi: Spi<pac::SPI, PINS>,
+        dma: pac::DMA,
+        channel: usize,
+        pattern: &'a mut [u32],
+    ) -> Self {
+        assert!(channel < dma::CHANNELS);
+        assert!(!pattern.is_empty() && pattern.len() <= dma::MAX_TRANSFERS);
+        for word in pattern.iter_mut() {
+            *word = 0;
+        }
+
+        SpiPwm {
+            spi,
+            dma,
+            channel,
+            pattern,
+            ring: [Lli::default(); PWM_RING],
+            duty: 0,
+        }
+    }
+
+    /// Sets the duty cycle in 1024ths, 1024 and above being always high
+    ///
+    /// The duty cycle is rounded to the nearest of the [`bits`](Self::bits) steps of the
+    /// pattern.
+    pub fn set_duty(&mut self, duty_1024ths: u16) {
+        let duty = duty_1024ths.min(1024);
+        let ones = (duty as u32 * self.bits() + 512) / 1024;
+
+        for (i, word) in self.pattern.iter_mut().enumerate() {
+            let first = i as u32 * 32;
+            // Frames are sent MSB first, so the pulse starts with the top bits of word 0
+            *word = match ones.saturating_sub(first) {
+                0 => 0,
+                n if n >= 32 => u32::MAX,
+                n => !(u32::MAX >> n),
+            };
+        }
+        self.duty = duty;
+    }
+
+    /// Returns the duty cycle set with [`set_duty`](Self::set_duty)
+    pub fn duty(&self) -> u16 {
+        self.duty
+    }
+
+    /// Returns the bits of the pattern, the number of duty cycle steps
+    pub fn bits(&self) -> u32 {
+        32 * self.pattern.len() as u32
+    }
+
+    /// Sends the pattern `frames` times and returns once the last bit has been shifted out
+    ///
+    /// The DMA channel moves the data, the core only keeps count of the patterns the channel
+    /// has started. That is done with interrupts masked, so the count can't slip while a
+    /// handler runs, and code running from flash doesn't stretch the output on a cache miss.
+    pub fn run(&mut self, frames: u32) -> Result<(), Error> {
+        if frames == 0 {
+            return Ok(());
+        }
+
+        let fifo = unsafe { &(*pac::SPI::ptr()).spi_fifo_wdata } as *const _ as u32;
+        let control = self.pattern.len() as u32 | dma::WIDTH_32 | dma::SRC_INCREMENT;
+        let base = dma::bus_address(self.ring.as_ptr() as usize);
+        let pattern = dma::bus_address(self.pattern.as_ptr() as usize);
+        for lli in self.ring.iter_mut() {
+            *lli = Lli {
+                src: pattern,
+                dst: fifo,
+                next: 0,
+                control,
+            };
+        }
+        dma::link_ring(&mut self.ring, base);
+        // Without enough patterns for a lap, the chain ends right away
+        if frames as usize <= PWM_RING {
+            self.ring[frames as usize - 1].next = 0;
+        }
+
+        let config = self.spi.spi.spi_config.read().bits();
+        self.spi.spi.spi_config.modify(|_, w| unsafe {
+            w.cr_spi_frame_size()
+                .bits(3) // 32 bit frames
+                .cr_spi_m_cont_en()
+                .set_bit() // no gaps between frames
+        });
+        self.spi.clear_fifo();
+        // spi_dma_tx_en
+        self.spi
+            .spi
+            .spi_fifo_config_0
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1) });
+
+        let dma_config = dma::MEMORY_TO_PERIPHERAL | dma::REQ_SPI_TX << dma::DST_PERIPHERAL_POS;
+        let result = sync::critical(|| {
+            unsafe { dma::start(self.channel, &self.ring[0], dma_config) };
+            self.count(frames)
+        });
+
+        self.spi
+            .spi
+            .spi_fifo_config_0
+            .modify(|r, w| unsafe { w.bits(r.bits() & !1) });
+        self.spi.clear_fifo();
+        self.spi.spi.spi_config.write(|w| unsafe { w.bits(config) });
+
+        result
+    }
+
+    /// Returns the SPI master, the DMA controller and the pattern buffer
+    pub fn free(self) -> (Spi<pac::SPI, PINS>, pac::DMA, &'a mut [u32]) {
+        (self.spi, self.dma, self.pattern)
+    }
+
+    /// Follows the channel through the ring and ends the chain after `frames` patterns, then
+    /// waits for the last one to be shifted out
+    fn count(&mut self, frames: u32) -> Result<(), Error> {
+        let base = dma::bus_address(self.ring.as_ptr() as usize);
+        let last = (frames - 1) as usize % PWM_RING;
+        // Patterns the channel has loaded, the first one from its registers
+        let mut loaded = 1u32;
+        let mut closed = frames as usize <= PWM_RING;
+
+        while dma::is_enabled(self.channel) {
+            // The LLI register points at the descriptor of the next pattern
+            let next = dma::next_lli(self.channel);
+            if next != 0 {
+                let index = (next - base) as usize / core::mem::size_of::<Lli>();
+                while (loaded as usize % PWM_RING) != index {
+                    loaded += 1;
+                }
+            }
+
+            // The last descriptor is free once its previous lap has been loaded, and has to be
+            // changed before the channel loads it again
+            if !closed && loaded + PWM_RING as u32 >= frames {
+                unsafe { ptr::write_volatile(&mut self.ring[last].next, 0) };
+                closed = true;
+            }
+        }
+
+        if loaded > frames {
+            return Err(Error::DmaOverrun);
+        }
+        self.spi.flush_and_wait()
+    }
+}
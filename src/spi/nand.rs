@@ -0,0 +1,509 @@
+/*!
+  # SPI NAND flash
+  [`SpiNand`] drives an SPI NAND flash, e.g. a GigaDevice GD5F1GQ4 or a Winbond W25N01GV, over
+  any SPI bus which implements the blocking `Write` and `TransferInplace` traits, with a GPIO as
+  chip select. The array is addressed by block and page, see [`Geometry`]; a page is read into
+  the chip's cache and from there over the bus, and programmed the other way round:
+
+  | Operation               | Commands                                             |
+  |-------------------------|------------------------------------------------------|
+  | [`SpiNand::read_page`]    | `13` page read, poll status, `03` read from cache     |
+  | [`SpiNand::program_page`] | `06` write enable, `02` program load, `10` execute  |
+  | [`SpiNand::erase_block`]  | `06` write enable, `d8` block erase                 |
+
+  The on-chip ECC engine, which the driver enables, corrects bit errors as pages are read and
+  reports what it did in the status register. A page with more errors than it can correct fails
+  with [`NandError::UncorrectableEcc`]; corrected errors aren't reported, the data is fine.
+  A failed program or erase, reported by the status register as well, fails with
+  [`NandError::ProgFailed`] or [`NandError::EraseFailed`].
+
+  # Bad blocks
+  NAND flash comes with bad blocks, marked by the factory with a byte other than `0xff` at the
+  start of the spare area of their first page, and more go bad over the life of the chip. The
+  last block of the chip is reserved for a bad block table, a bitmap of all blocks in its first
+  page:
+
+  | Bytes     | Bad block table                                 |
+  |-----------|-------------------------------------------------|
+  | 0 to 3    | `"NBBT"`                                        |
+  | 4 to 5    | number of blocks                                |
+  | 6 to 7    | `0xffff`                                        |
+  | 8 on      | a bit per block, set for a bad one, LSB first   |
+  | then 4    | CRC-32 of everything before                     |
+
+  Without a valid table, [`SpiNand::new`] scans the factory markers of all blocks and writes
+  one; that has to happen before the first erase, which clears the markers. A block whose
+  program or erase fails is marked as bad and the table written again. Bad blocks and the
+  reserved block are refused with [`NandError::BadBlock`] and [`NandError::OutOfBounds`], it's up
+  to the user, e.g. a file system, to skip them.
+
+  ## Example
+  ```rust
+    use bl602_hal::spi::nand::{Geometry, SpiNand};
+
+    let cs = parts.pin2.into_pull_up_output();
+    let mut nand = SpiNand::new(spi, cs, Geometry::GD5F1GQ4, &clocks).unwrap();
+
+    let block = (0..nand.blocks()).find(|&block| !nand.is_bad(block)).unwrap();
+    nand.erase_block(block).unwrap();
+    nand.program_page(block, 0, b"hello").unwrap();
+
+    let mut buffer = [0; 5];
+    nand.read_page(block, 0, &mut buffer).unwrap();
+  ```
+*/
+
+use core::convert::Infallible;
+
+use embedded_hal::digital::blocking::OutputPin;
+use embedded_hal::spi::blocking::{TransferInplace, Write};
+
+use crate::clock::Clocks;
+use crate::crc::{Algorithm, Crc};
+use crate::delay::McycleDelay;
+
+/// Most blocks a chip can have for [`SpiNand`]
+pub const MAX_BLOCKS: usize = 4096;
+
+const CMD_RESET: u8 = 0xff;
+const CMD_READ_ID: u8 = 0x9f;
+const CMD_GET_FEATURE: u8 = 0x0f;
+const CMD_SET_FEATURE: u8 = 0x1f;
+const CMD_PAGE_READ: u8 = 0x13;
+const CMD_READ_CACHE: u8 = 0x03;
+const CMD_WRITE_ENABLE: u8 = 0x06;
+const CMD_PROGRAM_LOAD: u8 = 0x02;
+const CMD_PROGRAM_EXECUTE: u8 = 0x10;
+const CMD_BLOCK_ERASE: u8 = 0xd8;
+
+// Feature registers
+const FEATURE_PROTECTION: u8 = 0xa0;
+const FEATURE_CONFIG: u8 = 0xb0;
+const FEATURE_STATUS: u8 = 0xc0;
+
+// Configuration register
+const CONFIG_ECC_EN: u8 = 1 << 4;
+
+// Status register
+const STATUS_OIP: u8 = 1 << 0;
+const STATUS_E_FAIL: u8 = 1 << 2;
+const STATUS_P_FAIL: u8 = 1 << 3;
+const STATUS_ECC_POS: u8 = 4;
+const ECC_UNCORRECTABLE: u8 = 0b10;
+
+/// Longest a page read, program or block erase takes, with margin
+const OPERATION_TIMEOUT_MS: u32 = 20;
+
+/// Start of the bad block table
+const BBT_MAGIC: [u8; 4] = *b"NBBT";
+/// Bytes of the bad block table before the bitmap
+const BBT_HEADER_SIZE: usize = 8;
+
+/// Sizes of an SPI NAND flash
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Geometry {
+    /// Blocks of the chip, the erase unit
+    pub blocks: u16,
+    /// Pages of a block, the program unit
+    pub pages_per_block: u8,
+    /// Bytes of the data area of a page
+    pub page_size: u16,
+    /// Bytes of the spare area after the data area
+    pub spare_size: u16,
+}
+
+impl Geometry {
+    /// GigaDevice GD5F1GQ4, 128 MiB
+    pub const GD5F1GQ4: Geometry = Geometry {
+        blocks: 1024,
+        pages_per_block: 64,
+        page_size: 2048,
+        spare_size: 128,
+    };
+
+    /// Winbond W25N01GV, 128 MiB
+    pub const W25N01GV: Geometry = Geometry {
+        blocks: 1024,
+        pages_per_block: 64,
+        page_size: 2048,
+        spare_size: 64,
+    };
+}
+
+/// SPI NAND error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum NandError<E> {
+    /// The SPI bus failed
+    Spi(E),
+    /// The page has more bit errors than the ECC engine can correct
+    UncorrectableEcc,
+    /// The block is marked as bad
+    BadBlock,
+    /// Programming the page failed, the block is now marked as bad
+    ProgFailed,
+    /// Erasing the block failed, the block is now marked as bad
+    EraseFailed,
+    /// The chip didn't finish an operation in time
+    Timeout,
+    /// The block, page or length is beyond the chip, or the block is the one reserved for the
+    /// bad block table
+    OutOfBounds,
+    /// The geometry has more blocks than [`MAX_BLOCKS`]
+    TooManyBlocks,
+}
+
+/// SPI NAND flash on a bus `SPI` with chip select `CS`, see the module documentation
+pub struct SpiNand<SPI, CS> {
+    spi: SPI,
+    cs: CS,
+    geometry: Geometry,
+    cycles_per_ms: u32,
+    /// Bit per block, set for a bad one
+    bad: [u8; MAX_BLOCKS / 8],
+}
+
+impl<SPI, CS, E> SpiNand<SPI, CS>
+where
+    SPI: Write<u8, Error = E> + TransferInplace<u8, Error = E>,
+    CS: OutputPin<Error = Infallible>,
+{
+    /// Resets the chip, unlocks all blocks, enables the ECC engine and loads the bad block
+    /// table, or builds it from the factory markers if there is none
+    ///
+    /// `clocks` is used for the timeouts.
+    pub fn new(
+        spi: SPI,
+        mut cs: CS,
+        geometry: Geometry,
+        clocks: &Clocks,
+    ) -> Result<Self, NandError<E>> {
+        if geometry.blocks as usize > MAX_BLOCKS || geometry.blocks < 2 {
+            return Err(NandError::TooManyBlocks);
+        }
+        cs.set_high().ok();
+
+        let mut nand = SpiNand {
+            spi,
+            cs,
+            geometry,
+            cycles_per_ms: clocks.sysclk().0 / 1000,
+            bad: [0; MAX_BLOCKS / 8],
+        };
+
+        nand.command(&[CMD_RESET], &mut [])?;
+        nand.wait_ready()?;
+        nand.set_feature(FEATURE_PROTECTION, 0)?;
+        let config = nand.get_feature(FEATURE_CONFIG)?;
+        nand.set_feature(FEATURE_CONFIG, config | CONFIG_ECC_EN)?;
+
+        if !nand.load_table()? {
+            for block in 0..nand.table_block() {
+                let mut marker = [0];
+                nand.read(block, 0, geometry.page_size, &mut marker)?;
+                if marker[0] != 0xff {
+                    nand.set_bad(block);
+                }
+            }
+            nand.write_table()?;
+        }
+
+        Ok(nand)
+    }
+
+    /// Reads the manufacturer and device ID
+    pub fn read_id(&mut self) -> Result<[u8; 2], NandError<E>> {
+        let mut id = [0; 2];
+        self.command(&[CMD_READ_ID, 0], &mut id)?;
+        Ok(id)
+    }
+
+    /// Reads `buf.len()` bytes from the start of page `page` of block `block`, the data area
+    /// followed by the spare area
+    pub fn read_page(&mut self, block: u16, page: u8, buf: &mut [u8]) -> Result<(), NandError<E>> {
+        self.check(block, page, buf.len())?;
+        self.read(block, page, 0, buf)
+    }
+
+    /// Programs `data` to the start of page `page` of block `block`
+    ///
+    /// The block has to be erased, and its pages programmed in order.
+    pub fn program_page(&mut self, block: u16, page: u8, data: &[u8]) -> Result<(), NandError<E>> {
+        self.check(block, page, data.len())?;
+        match self.program(block, page, &[data]) {
+            Err(NandError::ProgFailed) => {
+                self.set_bad(block);
+                self.write_table()?;
+                Err(NandError::ProgFailed)
+            }
+            result => result,
+        }
+    }
+
+    /// Erases block `block`
+    pub fn erase_block(&mut self, block: u16) -> Result<(), NandError<E>> {
+        self.check(block, 0, 0)?;
+        match self.erase(block) {
+            Err(NandError::EraseFailed) => {
+                self.set_bad(block);
+                self.write_table()?;
+                Err(NandError::EraseFailed)
+            }
+            result => result,
+        }
+    }
+
+    /// Marks block `block` as bad, e.g. after its data failed a check of its own, and writes
+    /// the bad block table
+    pub fn mark_bad(&mut self, block: u16) -> Result<(), NandError<E>> {
+        if block >= self.table_block() {
+            return Err(NandError::OutOfBounds);
+        }
+        self.set_bad(block);
+        self.write_table()
+    }
+
+    /// Returns whether block `block` is marked as bad
+    pub fn is_bad(&self, block: u16) -> bool {
+        let block = block as usize;
+        self.bad[block / 8] & 1 << (block % 8) != 0
+    }
+
+    /// Returns the number of blocks which can be used, all but the one holding the bad block
+    /// table
+    pub fn blocks(&self) -> u16 {
+        self.table_block()
+    }
+
+    /// Returns the sizes of the chip
+    pub fn geometry(&self) -> Geometry {
+        self.geometry
+    }
+
+    /// Returns the bus and the chip select
+    pub fn free(self) -> (SPI, CS) {
+        (self.spi, self.cs)
+    }
+
+    fn table_block(&self) -> u16 {
+        self.geometry.blocks - 1
+    }
+
+    fn check(&self, block: u16, page: u8, len: usize) -> Result<(), NandError<E>> {
+        let page_len = (self.geometry.page_size + self.geometry.spare_size) as usize;
+        if block >= self.table_block() || page >= self.geometry.pages_per_block || len > page_len {
+            return Err(NandError::OutOfBounds);
+        }
+        if self.is_bad(block) {
+            return Err(NandError::BadBlock);
+        }
+        Ok(())
+    }
+
+    fn set_bad(&mut self, block: u16) {
+        let block = block as usize;
+        self.bad[block / 8] |= 1 << (block % 8);
+    }
+
+    fn bitmap_len(&self) -> usize {
+        (self.geometry.blocks as usize + 7) / 8
+    }
+
+    /// Loads the bad block table, returns whether there was a valid one
+    fn load_table(&mut self) -> Result<bool, NandError<E>> {
+        let block = self.table_block();
+        let len = self.bitmap_len();
+        let mut header = [0; BBT_HEADER_SIZE];
+        let mut crc = [0; 4];
+        let mut bitmap = [0; MAX_BLOCKS / 8];
+        match self.read_parts(
+            block,
+            0,
+            &mut [&mut header[..], &mut bitmap[..len], &mut crc[..]],
+        ) {
+            Ok(()) => {}
+            Err(NandError::UncorrectableEcc) => return Ok(false),
+            Err(error) => return Err(error),
+        }
+
+        let mut expected = Crc::new(Algorithm::Crc32);
+        expected.update(&header);
+        expected.update(&bitmap[..len]);
+        let valid = header[..4] == BBT_MAGIC
+            && u16::from_le_bytes([header[4], header[5]]) == self.geometry.blocks
+            && u32::from_le_bytes(crc) == expected.finish();
+        if valid {
+            self.bad = bitmap;
+        }
+        Ok(valid)
+    }
+
+    /// Erases the reserved block and writes the bad block table to its first page
+    fn write_table(&mut self) -> Result<(), NandError<E>> {
+        let block = self.table_block();
+        let len = self.bitmap_len();
+        let blocks = self.geometry.blocks.to_le_bytes();
+        let header = [
+            BBT_MAGIC[0],
+            BBT_MAGIC[1],
+            BBT_MAGIC[2],
+            BBT_MAGIC[3],
+            blocks[0],
+            blocks[1],
+            0xff,
+            0xff,
+        ];
+        let bitmap = self.bad;
+        let mut crc = Crc::new(Algorithm::Crc32);
+        crc.update(&header);
+        crc.update(&bitmap[..len]);
+        let crc = crc.finish().to_le_bytes();
+
+        self.erase(block)?;
+        self.program(block, 0, &[&header[..], &bitmap[..len], &crc[..]])
+    }
+
+    fn read(
+        &mut self,
+        block: u16,
+        page: u8,
+        column: u16,
+        buf: &mut [u8],
+    ) -> Result<(), NandError<E>> {
+        let row = self.row(block, page);
+        self.command(
+            &[
+                CMD_PAGE_READ,
+                (row >> 16) as u8,
+                (row >> 8) as u8,
+                row as u8,
+            ],
+            &mut [],
+        )?;
+        let status = self.wait_ready()?;
+        if status >> STATUS_ECC_POS & 0b11 == ECC_UNCORRECTABLE {
+            return Err(NandError::UncorrectableEcc);
+        }
+
+        let [high, low] = column.to_be_bytes();
+        self.command(&[CMD_READ_CACHE, high, low, 0], buf)
+    }
+
+    /// Reads consecutive parts of a page from its start
+    fn read_parts(
+        &mut self,
+        block: u16,
+        page: u8,
+        parts: &mut [&mut [u8]],
+    ) -> Result<(), NandError<E>> {
+        let row = self.row(block, page);
+        self.command(
+            &[
+                CMD_PAGE_READ,
+                (row >> 16) as u8,
+                (row >> 8) as u8,
+                row as u8,
+            ],
+            &mut [],
+        )?;
+        let status = self.wait_ready()?;
+        if status >> STATUS_ECC_POS & 0b11 == ECC_UNCORRECTABLE {
+            return Err(NandError::UncorrectableEcc);
+        }
+
+        self.cs.set_low().ok();
+        let mut result = self.spi.write(&[CMD_READ_CACHE, 0, 0, 0]);
+        for part in parts.iter_mut() {
+            result = result.and_then(|()| self.spi.transfer_inplace(part));
+        }
+        self.cs.set_high().ok();
+        result.map_err(NandError::Spi)
+    }
+
+    /// Loads the parts of `data` one after the other into the cache and programs them to the
+    /// start of the page
+    fn program(&mut self, block: u16, page: u8, data: &[&[u8]]) -> Result<(), NandError<E>> {
+        self.command(&[CMD_WRITE_ENABLE], &mut [])?;
+        self.cs.set_low().ok();
+        let mut result = self.spi.write(&[CMD_PROGRAM_LOAD, 0, 0]);
+        for part in data {
+            result = result.and_then(|()| self.spi.write(part));
+        }
+        self.cs.set_high().ok();
+        result.map_err(NandError::Spi)?;
+
+        let row = self.row(block, page);
+        self.command(
+            &[
+                CMD_PROGRAM_EXECUTE,
+                (row >> 16) as u8,
+                (row >> 8) as u8,
+                row as u8,
+            ],
+            &mut [],
+        )?;
+        if self.wait_ready()? & STATUS_P_FAIL != 0 {
+            return Err(NandError::ProgFailed);
+        }
+        Ok(())
+    }
+
+    fn erase(&mut self, block: u16) -> Result<(), NandError<E>> {
+        let row = self.row(block, 0);
+        self.command(&[CMD_WRITE_ENABLE], &mut [])?;
+        self.command(
+            &[
+                CMD_BLOCK_ERASE,
+                (row >> 16) as u8,
+                (row >> 8) as u8,
+                row as u8,
+            ],
+            &mut [],
+        )?;
+        if self.wait_ready()? & STATUS_E_FAIL != 0 {
+            return Err(NandError::EraseFailed);
+        }
+        Ok(())
+    }
+
+    /// Returns the row address of a page
+    fn row(&self, block: u16, page: u8) -> u32 {
+        block as u32 * self.geometry.pages_per_block as u32 + page as u32
+    }
+
+    fn get_feature(&mut self, address: u8) -> Result<u8, NandError<E>> {
+        let mut value = [0];
+        self.command(&[CMD_GET_FEATURE, address], &mut value)?;
+        Ok(value[0])
+    }
+
+    fn set_feature(&mut self, address: u8, value: u8) -> Result<(), NandError<E>> {
+        self.command(&[CMD_SET_FEATURE, address, value], &mut [])
+    }
+
+    /// Polls the status register until the chip is done, returns the last status
+    fn wait_ready(&mut self) -> Result<u8, NandError<E>> {
+        let timeout = self.cycles_per_ms as u64 * OPERATION_TIMEOUT_MS as u64;
+        let start = McycleDelay::get_cycle_count();
+        loop {
+            let status = self.get_feature(FEATURE_STATUS)?;
+            if status & STATUS_OIP == 0 {
+                return Ok(status);
+            }
+            if McycleDelay::cycles_since(start) >= timeout {
+                return Err(NandError::Timeout);
+            }
+        }
+    }
+
+    /// Sends `command` and reads `response` with the chip selected
+    fn command(&mut self, command: &[u8], response: &mut [u8]) -> Result<(), NandError<E>> {
+        self.cs.set_low().ok();
+        let mut result = self.spi.write(command);
+        if !response.is_empty() {
+            result = result.and_then(|()| self.spi.transfer_inplace(response));
+        }
+        self.cs.set_high().ok();
+        result.map_err(NandError::Spi)
+    }
+}
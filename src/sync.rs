@@ -0,0 +1,507 @@
+/*!
+  # Synchronization primitives
+  The BL602 has a single hart, so the usual way to share data with an interrupt handler is a
+  critical section. A [`SpinLock`] built on the atomics of the `A` extension is lighter than that
+  when the contenders can't preempt each other, e.g. interrupt handlers of the same priority.
+
+  Note that a context spinning on a lock can't make progress if it preempted the holder, since the
+  holder only gets to run again once the spinning context returns. `lock` is therefore only safe
+  from deadlocks if no other user of the lock can interrupt the holder. `lock_irq_disabled` also
+  masks interrupts while the guard is alive and can be used from any context.
+
+  ## Example
+  ```rust
+    use bl602_hal::sync::SpinLock;
+
+    static COUNTER: SpinLock<u32> = SpinLock::new(0);
+
+    #[no_mangle]
+    fn TimerCh0() {
+        *COUNTER.lock() += 1;
+    }
+
+    fn main() {
+        let count = *COUNTER.lock_irq_disabled();
+    }
+  ```
+
+  ## Deferred work
+  [`Deferred`] is a fixed size queue which an interrupt handler fills and the handler of the
+  software interrupt empties, see [`interrupts`](crate::interrupts#software-interrupt). Neither
+  side takes a lock or disables interrupts, so pushing from a handler with a tight deadline
+  costs a few instructions and the software handler can be preempted while it pops.
+
+  ## Mutex
+  [`Mutex`] and [`SharedPeripheral`] take a critical section of the [`critical_section`] crate
+  for every access, so they work the same on bare metal and under an RTOS which provides the
+  critical sections. They need the `critical-section` feature and an implementation of the
+  crate linked in.
+
+  ## Critical section implementation
+  With the `critical-section-impl` feature the HAL provides the implementation of the
+  [`critical_section`] crate: interrupts are disabled in `mstatus` and the previous state of
+  `mstatus.MIE` is restored when the critical section ends. Nested critical sections and those
+  entered with interrupts already disabled, e.g. in interrupt handlers, therefore leave
+  interrupts disabled. Leave the feature off if an RTOS or another crate provides the
+  implementation, only one can be linked in.
+
+  The HAL's own read-modify-write sequences on shared registers, e.g. setting GPIO outputs, also
+  run in these critical sections once the `critical-section` feature is enabled, so they are
+  atomic with respect to whatever the implementation guards against.
+
+  ```rust
+    use bl602_hal::sync::SharedPeripheral;
+
+    static UART: SharedPeripheral<Serial<pac::UART, Pins>> = SharedPeripheral::new();
+
+    fn main() {
+        UART.init(serial);
+        UART.with(|serial| serial.write_str("from main\r\n").ok());
+    }
+
+    #[no_mangle]
+    fn TimerCh0() {
+        UART.with(|serial| serial.write_str("from TimerCh0\r\n").ok());
+    }
+  ```
+*/
+
+#[cfg(feature = "critical-section")]
+use core::cell::RefCell;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use crate::interrupts;
+#[cfg(feature = "critical-section-impl")]
+use crate::regs;
+
+/// A spin-lock protecting a value of type `T`
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+// The lock guarantees exclusive access to the value, so it can be shared as long as the value can
+// be sent to whoever takes the lock
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+unsafe impl<T: Send> Send for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    /// Creates a new, unlocked spin-lock holding `value`
+    pub const fn new(value: T) -> Self {
+        SpinLock {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Takes the lock if it is free, returns `None` otherwise
+    #[inline]
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        if self.acquire() {
+            Some(SpinLockGuard {
+                lock: self,
+                restore_interrupts: false,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Spins until the lock is free, then takes it
+    ///
+    /// Interrupts stay enabled while the lock is held.
+    #[inline]
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        while !self.acquire() {}
+
+        SpinLockGuard {
+            lock: self,
+            restore_interrupts: false,
+        }
+    }
+
+    /// Disables interrupts, then spins until the lock is free and takes it
+    ///
+    /// Interrupts are restored to their previous state when the guard is dropped.
+    #[inline]
+    pub fn lock_irq_disabled(&self) -> SpinLockGuard<'_, T> {
+        // The host has no interrupts to mask
+        #[cfg(feature = "mock-registers")]
+        let interrupts_enabled = false;
+        #[cfg(not(feature = "mock-registers"))]
+        let interrupts_enabled = riscv::register::mstatus::read().mie();
+        #[cfg(not(feature = "mock-registers"))]
+        unsafe {
+            riscv::interrupt::disable()
+        };
+
+        while !self.acquire() {}
+
+        SpinLockGuard {
+            lock: self,
+            restore_interrupts: interrupts_enabled,
+        }
+    }
+
+    /// Returns a mutable reference to the value without locking
+    ///
+    /// This is safe since the mutable borrow guarantees nobody else holds the lock.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.value.get() }
+    }
+
+    /// Consumes the lock and returns the value
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    #[inline]
+    fn acquire(&self) -> bool {
+        self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+}
+
+/// Exclusive access to the value of a [`SpinLock`], which is unlocked when the guard is dropped
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+    restore_interrupts: bool,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+
+        if self.restore_interrupts {
+            unsafe { riscv::interrupt::enable() };
+        }
+    }
+}
+
+/// Number of items a [`Deferred`] queue holds
+pub const DEFERRED_CAPACITY: usize = 32;
+
+/// A queue of up to [`DEFERRED_CAPACITY`] items for the software interrupt
+///
+/// There is one producer and one consumer: items are pushed by one context, usually an
+/// interrupt handler, and popped by another, usually the software interrupt handler. Either
+/// may preempt the other at any point. Several contexts may push if they can't preempt each
+/// other, e.g. handlers of the same interrupt level, since each push then runs to its end
+/// before the next one starts; the same goes for popping.
+pub struct Deferred<T> {
+    items: UnsafeCell<MaybeUninit<[T; DEFERRED_CAPACITY]>>,
+    /// Number of items popped so far, only written by the consumer
+    head: AtomicUsize,
+    /// Number of items pushed so far, only written by the producer
+    tail: AtomicUsize,
+    /// Number of items which didn't fit
+    dropped: AtomicU32,
+}
+
+// Items are moved from the producer to the consumer, and each slot is owned by exactly one of
+// them at a time
+unsafe impl<T: Send> Sync for Deferred<T> {}
+unsafe impl<T: Send> Send for Deferred<T> {}
+
+impl<T> Deferred<T> {
+    /// Creates an empty queue
+    pub const fn new() -> Self {
+        Deferred {
+            items: UnsafeCell::new(MaybeUninit::uninit()),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicU32::new(0),
+        }
+    }
+
+    /// Appends `item` and pends the software interrupt, returns the item if the queue is full
+    pub fn push(&self, item: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        // Acquire pairs with the release in `pop`, the consumer is done with the slot
+        let head = self.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == DEFERRED_CAPACITY {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return Err(item);
+        }
+
+        unsafe { self.slot(tail).write(item) };
+        // The slot is written before the consumer can see it
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        interrupts::pend_software();
+
+        Ok(())
+    }
+
+    /// Removes the oldest item, `None` if the queue is empty
+    pub fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        // Acquire pairs with the release in `push`, the item in the slot is complete
+        let tail = self.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+
+        let item = unsafe { self.slot(head).read() };
+        // The item is moved out before the producer can reuse the slot
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+
+        Some(item)
+    }
+
+    /// Returns the number of items in the queue
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        self.tail.load(Ordering::Acquire).wrapping_sub(head)
+    }
+
+    /// Returns whether the queue is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns how many items [`push`](Self::push) turned away since the queue was created
+    pub fn dropped(&self) -> u32 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Returns the slot of the item with the running number `index`
+    ///
+    /// The counters wrap at a multiple of the capacity, a power of two, so the slots keep their
+    /// order across the wrap.
+    fn slot(&self, index: usize) -> *mut T {
+        let items = self.items.get() as *mut T;
+        unsafe { items.add(index % DEFERRED_CAPACITY) }
+    }
+}
+
+impl<T> Default for Deferred<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Deferred<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+/// Runs `f` in a critical section, the one of the [`critical_section`] crate if it is enabled
+#[inline]
+pub(crate) fn critical<R>(f: impl FnOnce() -> R) -> R {
+    // As in `SpinLock::lock_irq_disabled`, and the tests hold the lock of the register files
+    #[cfg(feature = "mock-registers")]
+    {
+        f()
+    }
+    #[cfg(all(feature = "critical-section", not(feature = "mock-registers")))]
+    {
+        critical_section::with(|_| f())
+    }
+    #[cfg(not(any(feature = "critical-section", feature = "mock-registers")))]
+    {
+        riscv::interrupt::free(|_| f())
+    }
+}
+
+#[cfg(feature = "critical-section-impl")]
+struct SingleHartCriticalSection;
+
+#[cfg(feature = "critical-section-impl")]
+critical_section::set_impl!(SingleHartCriticalSection);
+
+// The BL602 has a single hart, so masking interrupts excludes every other context
+#[cfg(feature = "critical-section-impl")]
+unsafe impl critical_section::Impl for SingleHartCriticalSection {
+    unsafe fn acquire() -> critical_section::RawRestoreState {
+        let interrupts_enabled = regs::interrupts_enabled();
+        regs::set_interrupts_enabled(false);
+        interrupts_enabled
+    }
+
+    unsafe fn release(interrupts_enabled: critical_section::RawRestoreState) {
+        if interrupts_enabled {
+            regs::set_interrupts_enabled(true);
+        }
+    }
+}
+
+/// A value guarded by critical sections of the [`critical_section`] crate
+#[cfg(feature = "critical-section")]
+pub struct Mutex<T> {
+    inner: critical_section::Mutex<RefCell<T>>,
+}
+
+#[cfg(feature = "critical-section")]
+impl<T> Mutex<T> {
+    /// Creates a new mutex holding `value`
+    pub const fn new(value: T) -> Self {
+        Mutex {
+            inner: critical_section::Mutex::new(RefCell::new(value)),
+        }
+    }
+
+    /// Runs `f` with exclusive access to the value inside a critical section
+    ///
+    /// # Panics
+    ///
+    /// If the same mutex is locked again from within `f`.
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        critical_section::with(|cs| f(&mut self.inner.borrow(cs).borrow_mut()))
+    }
+
+    /// Consumes the mutex and returns the value
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner().into_inner()
+    }
+}
+
+/// A peripheral stored in a global, so it can be used from `main` and from interrupt handlers
+///
+/// The global starts out empty and gets the peripheral once it has been set up.
+#[cfg(feature = "critical-section")]
+pub struct SharedPeripheral<T> {
+    inner: Mutex<Option<T>>,
+}
+
+#[cfg(feature = "critical-section")]
+impl<T> SharedPeripheral<T> {
+    /// Creates an empty global
+    pub const fn new() -> Self {
+        SharedPeripheral {
+            inner: Mutex::new(None),
+        }
+    }
+
+    /// Stores `peripheral`, returns the one stored before if any
+    pub fn init(&self, peripheral: T) -> Option<T> {
+        self.inner.lock(|inner| inner.replace(peripheral))
+    }
+
+    /// Takes the peripheral out again, e.g. to free it
+    pub fn take(&self) -> Option<T> {
+        self.inner.lock(|inner| inner.take())
+    }
+
+    /// Returns whether a peripheral is stored
+    pub fn is_initialized(&self) -> bool {
+        self.inner.lock(|inner| inner.is_some())
+    }
+
+    /// Runs `f` with the peripheral inside a critical section, `None` if none is stored yet
+    ///
+    /// # Panics
+    ///
+    /// If called again for the same global from within `f`.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        self.inner.lock(|inner| inner.as_mut().map(f))
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl<T> Default for SharedPeripheral<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, feature = "mock-registers"))]
+mod tests {
+    use super::{Deferred, DEFERRED_CAPACITY};
+    use crate::{interrupts, regs};
+
+    #[test]
+    fn deferred_queue() {
+        let _registers = regs::lock();
+        let queue = Deferred::new();
+        assert!(queue.is_empty());
+        assert_eq!(queue.pop(), None);
+
+        // Round after round, so the counters pass the end of the slots several times
+        let mut next = 0;
+        for round in 1..4 {
+            for number in next..next + DEFERRED_CAPACITY {
+                assert_eq!(queue.push(number), Ok(()));
+            }
+            assert!(interrupts::is_software_pending(), "pushed, not pended");
+            assert_eq!(queue.len(), DEFERRED_CAPACITY);
+
+            // Full, the item comes back and is counted
+            assert_eq!(queue.push(usize::MAX), Err(usize::MAX));
+            assert_eq!(queue.dropped(), round);
+
+            // In order, and a slot popped can be pushed into again
+            assert_eq!(queue.pop(), Some(next));
+            assert_eq!(queue.push(next + DEFERRED_CAPACITY), Ok(()));
+            for number in next + 1..=next + DEFERRED_CAPACITY {
+                assert_eq!(queue.pop(), Some(number));
+            }
+            assert!(queue.is_empty());
+            assert_eq!(queue.pop(), None);
+
+            interrupts::clear_software();
+            assert!(!interrupts::is_software_pending());
+            next += DEFERRED_CAPACITY + 1;
+        }
+    }
+
+    #[test]
+    fn deferred_drops_items_left() {
+        let _registers = regs::lock();
+        let item = std::rc::Rc::new(());
+        let queue = Deferred::new();
+        queue.push(item.clone()).unwrap();
+        queue.push(item.clone()).unwrap();
+        drop(queue.pop());
+        assert_eq!(std::rc::Rc::strong_count(&item), 2);
+
+        drop(queue);
+        assert_eq!(std::rc::Rc::strong_count(&item), 1);
+    }
+
+    #[cfg(feature = "critical-section-impl")]
+    #[test]
+    fn critical_section_nesting() {
+        let _registers = regs::lock();
+
+        // Nested, starting with interrupts enabled
+        unsafe { regs::set_interrupts_enabled(true) };
+        let (outer, inner, after_inner) = critical_section::with(|_| {
+            let outer = regs::interrupts_enabled();
+            let inner = critical_section::with(|_| regs::interrupts_enabled());
+            (outer, inner, regs::interrupts_enabled())
+        });
+        assert!(!outer);
+        assert!(!inner);
+        assert!(!after_inner, "still disabled after the inner one");
+        assert!(regs::interrupts_enabled(), "enabled after the outer one");
+
+        // Entered with interrupts disabled, e.g. in an interrupt handler
+        unsafe { regs::set_interrupts_enabled(false) };
+        assert!(!critical_section::with(|_| regs::interrupts_enabled()));
+        assert!(!regs::interrupts_enabled(), "still disabled afterwards");
+    }
+}
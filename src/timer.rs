@@ -24,6 +24,12 @@
 
     ch0.enable(); // start timer
   ```
+  # One-shot callbacks
+  [`one_shot_after`](ConfiguredTimerChannel0::one_shot_after) calls a function once after a
+  delay, e.g. to start a sequence 100 ms after a button press. The channel is stopped again
+  after firing, so it can be used for the next one-shot or anything else. See
+  [`OneShotToken`] for the interrupt handler this needs.
+
   # Units
   This library uses embedded_time::{duration::*, rate::*} for time units. You can use any supported units as long as they can be cast into Nanoseconds::<u64> for durations, or Hertz for cycles. Time can be cast into other units supported by embedded_time by explicitly typing a variable and calling .into() Note that this will round to the nearest integer in the cast units, potentially losing precision.
 
@@ -35,9 +41,12 @@
   ```
 */
 
-use crate::{clock::Clocks, pac};
+use crate::sync::{critical, SpinLock};
+use crate::{clock::Clocks, regs};
 use bl602_pac::TIMER;
 use core::cell::RefCell;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicU32, Ordering};
 use embedded_time::{duration::*, rate::*};
 use paste::paste;
 
@@ -134,52 +143,61 @@ macro_rules! impl_timer_channel {
         paste! {
             impl $conf_name {
                 /// Enable interrupt for match register 0.
+                ///
+                /// The channel's interrupt also has to be enabled with
+                /// [`interrupts::enable`](crate::interrupts::enable) for its handler to run.
                 pub fn enable_match0_interrupt(&self) {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<tier $channel>].modify(|_r, w| w.tier_0().set_bit());
                 }
 
                 /// Enable interrupt for match register 1.
+                ///
+                /// The channel's interrupt also has to be enabled with
+                /// [`interrupts::enable`](crate::interrupts::enable) for its handler to run.
                 pub fn enable_match1_interrupt(&self) {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<tier $channel>].modify(|_r, w| w.tier_1().set_bit());
                 }
 
                 /// Enable interrupt for match register 2.
+                ///
+                /// The channel's interrupt also has to be enabled with
+                /// [`interrupts::enable`](crate::interrupts::enable) for its handler to run.
                 pub fn enable_match2_interrupt(&self) {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<tier $channel>].modify(|_r, w| w.tier_2().set_bit());
                 }
 
                 /// Disable interrupt for match register 0.
                 pub fn disable_match0_interrupt(&self) {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<tier $channel>].modify(|_r, w| w.tier_0().clear_bit());
                 }
 
                 /// Disable interrupt for match register 1.
                 pub fn disable_match1_interrupt(&self) {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<tier $channel>].modify(|_r, w| w.tier_1().clear_bit());
                 }
 
                 /// Disable interrupt for match register 2.
                 pub fn disable_match2_interrupt(&self) {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<tier $channel>].modify(|_r, w| w.tier_2().clear_bit());
                 }
 
                 /// Enable this counter
                 pub fn enable(&self) {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.tcer.modify(|_r, w| w.[<timer $channel _en>]().set_bit());
                     self.is_running.replace(true);
                 }
 
                 /// Disable this counter
                 pub fn disable(&self) {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
-                    timer.tcer.modify(|_r, w| w.[<timer $channel _en>]().set_bit());
+                    let timer = regs::timer();
+                    timer.tcer.modify(|_r, w| w.[<timer $channel _en>]().clear_bit());
                     self.is_running.replace(false);
                 }
 
@@ -191,27 +209,27 @@ macro_rules! impl_timer_channel {
                 /// Clear interrupt for match register 0.
                 /// TICR register is write-only, no need to preserve register contents
                 pub fn clear_match0_interrupt(&self) {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<ticr $channel>].write(|w| w.tclr_0().set_bit());
                 }
 
                 /// Clear interrupt for match register 1.
                 /// TICR register is write-only, no need to preserve register contents
                 pub fn clear_match1_interrupt(&self) {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<ticr $channel>].write(|w| w.tclr_1().set_bit());
                 }
 
                 /// Clear interrupt for match register 2.
                 /// TICR register is write-only, no need to preserve register contents
                 pub fn clear_match2_interrupt(&self) {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<ticr $channel>].write(|w| w.tclr_2().set_bit());
                 }
 
                 /// Sets when the to preload.
                 pub fn set_preload(&self, preload: Preload) {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer
                         .[<tplcr $channel>]
                         .modify(|_r, w| unsafe { w.tplcr().bits(preload.to_prlcr()) });
@@ -221,7 +239,7 @@ macro_rules! impl_timer_channel {
                 pub fn set_match0(&self, time: impl Into<Nanoseconds::<u64>>) {
                     let time: Nanoseconds::<u64> = time.into();
                     let time = (self.clock.0 as u64 * time.integer() / 1_000_000_000_u64) as u32;
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<tmr $channel _0>].modify(|_r, w| unsafe { w.tmr().bits(time) });
                 }
 
@@ -229,7 +247,7 @@ macro_rules! impl_timer_channel {
                 pub fn set_match1(&self, time: impl Into<Nanoseconds::<u64>>) {
                     let time: Nanoseconds::<u64> = time.into();
                     let time = (self.clock.0 as u64 * time.integer() / 1_000_000_000_u64) as u32;
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<tmr $channel _1>].modify(|_r, w| unsafe { w.tmr().bits(time) });
                 }
 
@@ -237,50 +255,50 @@ macro_rules! impl_timer_channel {
                 pub fn set_match2(&self, time: impl Into<Nanoseconds::<u64>>) {
                     let time: Nanoseconds::<u64> = time.into();
                     let time = (self.clock.0 as u64 * time.integer() / 1_000_000_000_u64) as u32;
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<tmr $channel _2>].modify(|_r, w| unsafe { w.tmr().bits(time) });
                 }
 
                 /// Current counter value in raw ticks.
                 pub fn current_ticks(&self) -> u32 {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<tcr $channel>].read().bits()
                 }
 
                 /// Current counter value in nanoseconds.
                 pub fn current_time(&self) -> Nanoseconds::<u64> {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     let ticks = timer.[<tcr $channel>].read().bits() as u64;
                     Nanoseconds::<u64>::new( (ticks as u64 * 1_000_000_000_u64 / self.clock.0 as u64) )
                 }
 
                 /// Will only become true if `enable_match0_interrupt` is active
                 pub fn is_match0(&self) -> bool {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<tmsr $channel>].read().tmsr_0().bit()
                 }
 
                 /// Will only become true if `enable_match2_interrupt` is active
                 pub fn is_match1(&self) -> bool {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<tmsr $channel>].read().tmsr_1().bit()
                 }
 
                 /// Will only become true if `enable_match2_interrupt` is active
                 pub fn is_match2(&self) -> bool {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<tmsr $channel>].read().tmsr_2().bit()
                 }
 
                 /// Set pre-load mode.
                 pub fn pre_load_mode(&self) {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.tcmr.modify(|_r, w| w.[<timer $channel _mode>]().clear_bit());
                 }
 
                 /// Set free running mode.
                 pub fn free_running_mode(&self) {
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.tcmr.modify(|_r, w| w.[<timer $channel _mode>]().set_bit());
                 }
 
@@ -288,12 +306,120 @@ macro_rules! impl_timer_channel {
                 pub fn set_preload_value(&self, time: impl Into<Nanoseconds::<u64>>) {
                     let time: Nanoseconds::<u64> = time.into();
                     let time = (self.clock.0 as u64 * time.0 / 1_000_000_000_u64) as u32;
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer.[<tplvr $channel>].modify(|_r, w| unsafe { w.bits(time) });
                 }
             }
         }
 
+        paste! {
+            impl $conf_name {
+                /// Starts the counter in free running mode and extends it to 64 bits, see
+                /// [`FreeRunningTimer`]
+                ///
+                /// Match register 2 and its interrupt are taken over to count the wrap arounds.
+                pub fn start_free_running(self) -> FreeRunningTimer<Self> {
+                    let timer = regs::timer();
+
+                    self.free_running_mode();
+                    // The counter matches one tick before it wraps around to 0
+                    timer.[<tmr $channel _2>].modify(|_r, w| unsafe { w.tmr().bits(u32::MAX) });
+                    self.clear_match2_interrupt();
+                    <Self as FreeRunningChannel>::overflows().store(0, Ordering::Relaxed);
+                    self.enable_match2_interrupt();
+                    self.enable();
+
+                    FreeRunningTimer { timer: self }
+                }
+            }
+
+            impl $conf_name {
+                /// Calls `f` once after `delay`, from the channel's interrupt handler, see
+                /// [`OneShotToken`]
+                ///
+                /// Match register 0, its interrupt and the preload settings are taken over. The
+                /// counter is restarted from 0 and disabled again when it fires. Fails with
+                /// [`OneShotError::TimerBusy`] while an earlier one-shot of this channel is
+                /// pending.
+                pub fn one_shot_after(
+                    &self,
+                    delay: impl Into<Nanoseconds::<u64>>,
+                    f: fn(),
+                ) -> Result<OneShotToken<Self>, OneShotError> {
+                    let delay: Nanoseconds::<u64> = delay.into();
+                    let ticks = self.clock.0 as u64 * delay.integer() / 1_000_000_000_u64;
+                    if ticks > u32::MAX as u64 {
+                        return Err(OneShotError::DelayTooLong);
+                    }
+
+                    let mut slot = <Self as OneShotChannel>::slot().lock_irq_disabled();
+                    if slot.callback.is_some() {
+                        return Err(OneShotError::TimerBusy);
+                    }
+                    slot.callback = Some(f);
+                    slot.id = slot.id.wrapping_add(1);
+
+                    let timer = regs::timer();
+                    self.disable();
+                    self.pre_load_mode();
+                    timer.[<tplvr $channel>].write(|w| unsafe { w.tplvr().bits(0) });
+                    self.set_preload(Preload::PreloadMatchComparator0);
+                    // A match at 0 would only happen after a wrap around
+                    timer.[<tmr $channel _0>].write(|w| unsafe { w.tmr().bits((ticks as u32).max(1)) });
+                    self.clear_match0_interrupt();
+                    self.enable_match0_interrupt();
+                    self.enable();
+
+                    Ok(OneShotToken { id: slot.id, _timer: PhantomData })
+                }
+            }
+
+            impl OneShotChannel for $conf_name {
+                fn slot() -> &'static SpinLock<OneShotSlot> {
+                    static SLOT: SpinLock<OneShotSlot> = SpinLock::new(OneShotSlot::new());
+                    &SLOT
+                }
+
+                fn fired() -> bool {
+                    let timer = regs::timer();
+                    timer.[<tmsr $channel>].read().tmsr_0().bit()
+                }
+
+                fn stop() {
+                    let timer = regs::timer();
+                    timer.tcer.modify(|_r, w| w.[<timer $channel _en>]().clear_bit());
+                    timer.[<tier $channel>].modify(|_r, w| w.tier_0().clear_bit());
+                    timer.[<ticr $channel>].write(|w| w.tclr_0().set_bit());
+                }
+            }
+
+            impl FreeRunningChannel for $conf_name {
+                fn overflows() -> &'static AtomicU32 {
+                    static OVERFLOWS: AtomicU32 = AtomicU32::new(0);
+                    &OVERFLOWS
+                }
+
+                fn clock(&self) -> Hertz {
+                    self.clock
+                }
+
+                fn ticks() -> u32 {
+                    let timer = regs::timer();
+                    timer.[<tcr $channel>].read().bits()
+                }
+
+                fn overflow_pending() -> bool {
+                    let timer = regs::timer();
+                    timer.[<tmsr $channel>].read().tmsr_2().bit()
+                }
+
+                fn clear_overflow() {
+                    let timer = regs::timer();
+                    timer.[<ticr $channel>].write(|w| w.tclr_2().set_bit());
+                }
+            }
+        }
+
         impl embedded_hal::timer::nb::CountDown for $conf_name {
             type Error = CountDownError;
 
@@ -349,7 +475,7 @@ macro_rules! impl_timer_channel {
                     desired_timing: impl Into<Hertz>,
                 ) -> $conf_name {
                     let target_clock: Hertz = desired_timing.into();
-                    let timer = unsafe { &*pac::TIMER::ptr() };
+                    let timer = regs::timer();
                     timer
                         .tccr
                         .modify(|_r, w| unsafe { w.[<cs_ $channel_cs>]().bits(source.tccr_value()) });
@@ -383,6 +509,209 @@ macro_rules! impl_timer_channel {
     }
 }
 
+/// Configured timer channels which can be extended to 64 bits
+#[doc(hidden)]
+pub trait FreeRunningChannel {
+    /// How often the counter wrapped around
+    fn overflows() -> &'static AtomicU32;
+    fn clock(&self) -> Hertz;
+    /// Counter value
+    fn ticks() -> u32;
+    /// Whether match 2 is flagged
+    fn overflow_pending() -> bool;
+    /// Clears the match 2 flag
+    fn clear_overflow();
+}
+
+/// Configured timer channels which can run a one-shot callback
+#[doc(hidden)]
+pub trait OneShotChannel {
+    /// State of the one-shot of this channel
+    fn slot() -> &'static SpinLock<OneShotSlot>;
+    /// Whether match 0 is flagged
+    fn fired() -> bool;
+    /// Disables the counter and the match 0 interrupt and clears its flag
+    fn stop();
+}
+
+/// One-shot of a channel
+#[doc(hidden)]
+pub struct OneShotSlot {
+    /// Callback of the pending one-shot
+    callback: Option<fn()>,
+    /// Counts the one-shots started, so tokens of earlier ones don't cancel the current one
+    id: u32,
+}
+
+impl OneShotSlot {
+    const fn new() -> Self {
+        OneShotSlot {
+            callback: None,
+            id: 0,
+        }
+    }
+}
+
+/// Error of [`one_shot_after`](ConfiguredTimerChannel0::one_shot_after)
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum OneShotError {
+    /// A one-shot is already pending on the channel
+    TimerBusy,
+    /// The delay doesn't fit into the 32 bit counter at the channel's clock
+    DelayTooLong,
+}
+
+/// A pending one-shot callback, returned by
+/// [`one_shot_after`](ConfiguredTimerChannel0::one_shot_after)
+///
+/// The callback is run by [`on_interrupt`](OneShotToken::on_interrupt), which has to be called
+/// from the channel's interrupt handler:
+/// ```rust
+///   let token = timer.one_shot_after(100_u32.milliseconds(), start_sequence).unwrap();
+///   enable_interrupt(Interrupt::TimerCh0);
+///
+///   #[no_mangle]
+///   fn TimerCh0(_: &mut TrapFrame) {
+///       clear_interrupt(Interrupt::TimerCh0);
+///       OneShotToken::<ConfiguredTimerChannel0>::on_interrupt();
+///   }
+/// ```
+/// Dropping the token leaves the one-shot pending, [`cancel`](OneShotToken::cancel) stops it.
+pub struct OneShotToken<TIMER: OneShotChannel> {
+    id: u32,
+    _timer: PhantomData<TIMER>,
+}
+
+impl<TIMER: OneShotChannel> OneShotToken<TIMER> {
+    /// Stops the one-shot before it fires, returns `false` if it had already fired
+    ///
+    /// The channel can take a new one-shot either way.
+    pub fn cancel(self) -> bool {
+        let mut slot = TIMER::slot().lock_irq_disabled();
+        if slot.id != self.id || slot.callback.is_none() {
+            return false;
+        }
+
+        TIMER::stop();
+        slot.callback = None;
+        true
+    }
+
+    /// Returns whether the callback hasn't run yet
+    pub fn is_pending(&self) -> bool {
+        let slot = TIMER::slot().lock_irq_disabled();
+        slot.id == self.id && slot.callback.is_some()
+    }
+
+    /// Runs the callback if the one-shot fired, returns whether it did
+    ///
+    /// Has to be called from the channel's interrupt handler, the other match interrupts of the
+    /// channel are left alone. The callback runs after the channel has been stopped, so it can
+    /// start the next one-shot.
+    pub fn on_interrupt() -> bool {
+        if !TIMER::fired() {
+            return false;
+        }
+
+        let callback = {
+            let mut slot = TIMER::slot().lock_irq_disabled();
+            let callback = slot.callback.take();
+            if callback.is_some() {
+                TIMER::stop();
+            }
+            callback
+        };
+
+        match callback {
+            Some(f) => {
+                f();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Timer channel counting in 64 bits
+///
+/// The hardware counter is 32 bits wide, so it wraps around after about 27 seconds at 160 MHz.
+/// The wrap arounds are counted in [`on_interrupt`](FreeRunningTimer::on_interrupt), which has
+/// to be called from the channel's interrupt handler:
+/// ```rust
+///   let timer = timers
+///       .channel0
+///       .set_clock_source(ClockSource::Fclk(&clocks), 160_000_000_u32.Hz())
+///       .start_free_running();
+///   enable_interrupt(Interrupt::TimerCh0);
+///
+///   #[no_mangle]
+///   fn TimerCh0(_: &mut TrapFrame) {
+///       clear_interrupt(Interrupt::TimerCh0);
+///       FreeRunningTimer::<ConfiguredTimerChannel0>::on_interrupt();
+///   }
+/// ```
+/// [`now`](FreeRunningTimer::now) also works while the interrupt is pending, e.g. called with
+/// interrupts disabled right after a wrap around.
+pub struct FreeRunningTimer<TIMER> {
+    timer: TIMER,
+}
+
+impl<TIMER: FreeRunningChannel> FreeRunningTimer<TIMER> {
+    /// Returns the number of ticks since the timer was started
+    pub fn now(&self) -> u64 {
+        critical(|| {
+            let mut overflows = TIMER::overflows().load(Ordering::Relaxed);
+            let ticks = TIMER::ticks();
+
+            // The match fires at u32::MAX, a small count means the counter wrapped after it and
+            // the interrupt hasn't been handled yet. A large count was read before the wrap.
+            if TIMER::overflow_pending() && ticks < 1 << 31 {
+                overflows = overflows.wrapping_add(1);
+            }
+
+            ((overflows as u64) << 32) | ticks as u64
+        })
+    }
+
+    /// Returns the time since the timer was started
+    pub fn now_time(&self) -> Nanoseconds<u64> {
+        let ticks = self.now() as u128;
+        Nanoseconds::<u64>::new((ticks * 1_000_000_000 / self.timer.clock().0 as u128) as u64)
+    }
+
+    /// Returns the tick frequency
+    pub fn clock(&self) -> Hertz {
+        self.timer.clock()
+    }
+
+    /// Counts a wrap around of the counter, returns whether there was one
+    ///
+    /// Has to be called from the channel's interrupt handler, the other match interrupts of the
+    /// channel are left alone.
+    pub fn on_interrupt() -> bool {
+        if !TIMER::overflow_pending() {
+            return false;
+        }
+
+        critical(|| {
+            TIMER::clear_overflow();
+            let overflows = TIMER::overflows();
+            overflows.store(
+                overflows.load(Ordering::Relaxed).wrapping_add(1),
+                Ordering::Relaxed,
+            );
+        });
+
+        true
+    }
+
+    /// Returns the channel, which keeps running in free running mode
+    pub fn free(self) -> TIMER {
+        self.timer
+    }
+}
+
 impl_timer_channel!(TimerChannel0, ConfiguredTimerChannel0, 2, 1);
 
 impl_timer_channel!(TimerChannel1, ConfiguredTimerChannel1, 3, 2);
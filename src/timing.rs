@@ -0,0 +1,66 @@
+/*!
+  # Timing of secret-dependent work
+  A check which takes longer the more of a token is right, or a decryption which fails early on
+  a bad padding, tells an attacker with a stopwatch or a current probe more than the result does.
+  [`constant_time_eq`] compares without an early exit. [`run_padded`] goes further for work whose
+  duration can't be made independent of the data, e.g. a software fallback or a driver call
+  which returns early on an error: it waits out a fixed budget from the start of the work, so
+  the caller sees the same duration every time.
+
+  The padding hides how long the work took, not what the core did meanwhile: the power drawn
+  while the work runs still depends on the data, and an interrupt which fires during the work
+  shifts the time left to wait, not the total. A budget shorter than the slowest case leaks
+  again, [`run_padded`] reports it.
+
+  ## Example
+  ```rust
+    use bl602_hal::timing::{constant_time_eq, run_padded};
+
+    let mut delay = McycleDelay::new(clocks.sysclk().0);
+    let (valid, in_time) = run_padded(500, &mut delay, || {
+        let expected = compute_token(&request);
+        constant_time_eq(&expected, &request.token)
+    });
+  ```
+*/
+
+use crate::delay::McycleDelay;
+
+/// Compares two byte slices in a time which doesn't depend on where they differ, see
+/// [`ct_eq`](crate::secure::ct_eq)
+pub use crate::secure::ct_eq as constant_time_eq;
+
+/// Waits `n` microseconds
+///
+/// The wait ends at a deadline computed before waiting, with a loop which only compares it to
+/// `mcycle`, so it ends within the same few cycles of the deadline whatever `n` is.
+pub fn constant_time_delay_us(n: u64, delay: &mut McycleDelay) {
+    wait_until(McycleDelay::get_cycle_count(), us_to_cycles(n, delay));
+}
+
+/// Runs `f`, then waits until `budget_us` microseconds have passed since `f` started
+///
+/// Returns the result of `f`, and whether it finished within the budget. If it didn't, the call
+/// returns as soon as `f` does, and its duration depends on `f` again.
+pub fn run_padded<R>(budget_us: u64, delay: &mut McycleDelay, f: impl FnOnce() -> R) -> (R, bool) {
+    let budget = us_to_cycles(budget_us, delay);
+    let start = McycleDelay::get_cycle_count();
+
+    let result = f();
+    let in_time = McycleDelay::cycles_since(start) <= budget;
+    wait_until(start, budget);
+
+    (result, in_time)
+}
+
+fn us_to_cycles(us: u64, delay: &McycleDelay) -> u64 {
+    us.saturating_mul(delay.frequency() as u64) / 1_000_000
+}
+
+/// Spins until `cycles` have passed since `start`
+#[inline(never)]
+fn wait_until(start: u64, cycles: u64) {
+    // A volatile read of the budget keeps the compiler from specializing the loop on it
+    let cycles = unsafe { core::ptr::read_volatile(&cycles) };
+    while McycleDelay::cycles_since(start) < cycles {}
+}
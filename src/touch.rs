@@ -0,0 +1,453 @@
+/*!
+  # Capacitive touch pads
+  The BL602 has no touch sensing peripheral, so [`TouchPad`] measures the capacitance of a pad
+  on a GPIO by its charge time: the pin drives the pad low for [`Config::drive_time_us`], then
+  floats it, and the cycle counter times how long the pull-up takes to charge it past the
+  input threshold. A finger adds capacitance, which makes the charge time longer. Each reading
+  is the average of [`Config::samples`] charge times.
+
+  The pin is an input with the internal pull-up, or a floating input with a resistor of about
+  1 MΩ from the pad to 3.3 V, which charges it more slowly and gives a finer reading. Either
+  way the charge time of a bare pad is in the order of a microsecond, shorter than a
+  conversion of the ADC, which is why the cycle counter times it instead. Interrupts are
+  disabled while the pad charges.
+
+  # Detection
+  [`Detector`] turns readings into the touched state and doesn't touch the hardware, so it can
+  be fed recorded readings as well. It compares each reading with a baseline, the reading of
+  the untouched pad:
+
+  - A reading [`Config::touch_percent`] above the baseline is a touch, which lasts until the
+    reading drops below [`Config::release_percent`] above it. The gap between the two is the
+    hysteresis, which keeps noise at the threshold from toggling the state.
+  - While the pad isn't touched, the baseline follows the readings by 1/2^[`Config::drift_shift`]
+    of the difference per reading, slow enough to ignore an approaching finger but following
+    changes of temperature and humidity. Readings below the baseline are followed at once.
+  - A touch which lasts longer than [`Config::max_touch_readings`] readings is taken as drift,
+    e.g. water on the pad, and the baseline is reset to the reading.
+
+  The baseline starts at the first reading, or at the mean of a calibration with the pad
+  untouched, [`TouchPad::calibrate`], which also fails if the pad is too noisy for the
+  thresholds.
+
+  ## Example
+  ```rust
+    use bl602_hal::touch::{self, TouchPad};
+
+    let pin = parts.pin3.into_pull_up_input().erase();
+    let mut pad = TouchPad::new(pin, touch::Config::default(), &clocks);
+    pad.calibrate(32).unwrap();
+
+    loop {
+        if pad.update().unwrap() {
+            // touched
+        }
+        delay.delay_ms(20).unwrap();
+    }
+  ```
+*/
+
+use riscv::register::mcycle;
+
+use crate::clock::Clocks;
+use crate::delay::McycleDelay;
+use crate::gpio::{AnyPin, Input};
+use crate::regs;
+use crate::sync::critical;
+
+/// Touch pad error
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The pad didn't charge within [`Config::timeout_us`], the pin has no pull-up or the pad
+    /// is shorted to ground
+    Timeout,
+    /// The readings of the calibration spread further than the release threshold
+    Noisy,
+    /// A calibration needs at least one reading
+    NoReadings,
+}
+
+/// Touch pad configuration
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Config {
+    /// Charge times averaged for a reading
+    pub samples: u16,
+    /// Time the pad is driven low to discharge it
+    pub drive_time_us: u32,
+    /// Longest charge time before the measurement fails
+    pub timeout_us: u32,
+    /// Rise above the baseline which is a touch, in percent of the baseline
+    pub touch_percent: u8,
+    /// Rise above the baseline below which a touch ends, in percent of the baseline
+    pub release_percent: u8,
+    /// The baseline follows an untouched pad by 1/2^`drift_shift` per reading
+    pub drift_shift: u8,
+    /// Readings after which a touch is taken as drift, 0 to keep a touch forever
+    pub max_touch_readings: u32,
+}
+
+impl Config {
+    /// Sets the number of charge times averaged for a reading
+    pub fn samples(mut self, samples: u16) -> Self {
+        self.samples = samples.max(1);
+
+        self
+    }
+
+    /// Sets the time the pad is driven low
+    pub fn drive_time_us(mut self, drive_time_us: u32) -> Self {
+        self.drive_time_us = drive_time_us;
+
+        self
+    }
+
+    /// Sets the longest charge time
+    pub fn timeout_us(mut self, timeout_us: u32) -> Self {
+        self.timeout_us = timeout_us;
+
+        self
+    }
+
+    /// Sets the touch and release thresholds, in percent of the baseline
+    pub fn thresholds(mut self, touch_percent: u8, release_percent: u8) -> Self {
+        self.touch_percent = touch_percent;
+        self.release_percent = release_percent.min(touch_percent);
+
+        self
+    }
+
+    /// Sets how fast the baseline follows an untouched pad
+    pub fn drift_shift(mut self, drift_shift: u8) -> Self {
+        self.drift_shift = drift_shift.min(16);
+
+        self
+    }
+
+    /// Sets the readings after which a touch is taken as drift
+    pub fn max_touch_readings(mut self, max_touch_readings: u32) -> Self {
+        self.max_touch_readings = max_touch_readings;
+
+        self
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            samples: 16,
+            drive_time_us: 5,
+            timeout_us: 100,
+            touch_percent: 10,
+            release_percent: 5,
+            drift_shift: 6,
+            max_touch_readings: 0,
+        }
+    }
+}
+
+/// Result of a calibration with the pad untouched
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Calibration {
+    /// Mean of the readings
+    pub baseline: u32,
+    /// Difference between the highest and the lowest reading
+    pub noise: u32,
+}
+
+impl Calibration {
+    /// Returns the calibration of `readings`, `None` if there are none
+    pub fn from_readings(readings: impl IntoIterator<Item = u32>) -> Option<Self> {
+        let (mut sum, mut count, mut min, mut max) = (0u64, 0u64, u32::MAX, 0);
+        for reading in readings {
+            sum += reading as u64;
+            count += 1;
+            min = min.min(reading);
+            max = max.max(reading);
+        }
+
+        if count == 0 {
+            return None;
+        }
+        Some(Calibration {
+            baseline: (sum / count) as u32,
+            noise: max - min,
+        })
+    }
+}
+
+/// Fraction bits of the baseline, so slow drift isn't lost to rounding
+const BASELINE_FRACTION: u32 = 8;
+
+/// Touch detection from readings, see the module documentation
+#[derive(Debug, Clone)]
+pub struct Detector {
+    config: Config,
+    /// Baseline with `BASELINE_FRACTION` fraction bits, `None` before the first reading
+    baseline: Option<u64>,
+    touched: bool,
+    touch_readings: u32,
+}
+
+impl Detector {
+    /// Returns a detector without a baseline, which starts at the first reading
+    pub fn new(config: Config) -> Self {
+        Detector {
+            config,
+            baseline: None,
+            touched: false,
+            touch_readings: 0,
+        }
+    }
+
+    /// Sets the baseline from `calibration` and ends a touch
+    ///
+    /// Fails with [`Error::Noisy`] if the noise exceeds the release threshold, which could keep
+    /// a touch from ending, and keeps the previous baseline then.
+    pub fn calibrate(&mut self, calibration: &Calibration) -> Result<(), Error> {
+        if calibration.noise > self.threshold(calibration.baseline, self.config.release_percent) {
+            return Err(Error::Noisy);
+        }
+
+        self.baseline = Some((calibration.baseline as u64) << BASELINE_FRACTION);
+        self.touched = false;
+        self.touch_readings = 0;
+        Ok(())
+    }
+
+    /// Takes a reading, returns whether the pad is touched
+    pub fn update(&mut self, reading: u32) -> bool {
+        let reading_fixed = (reading as u64) << BASELINE_FRACTION;
+        let baseline_fixed = *self.baseline.get_or_insert(reading_fixed);
+        let baseline = (baseline_fixed >> BASELINE_FRACTION) as u32;
+        let rise = reading.saturating_sub(baseline);
+
+        self.touched = if self.touched {
+            rise > self.threshold(baseline, self.config.release_percent)
+        } else {
+            rise >= self.threshold(baseline, self.config.touch_percent).max(1)
+        };
+
+        if self.touched {
+            self.touch_readings = self.touch_readings.saturating_add(1);
+            if self.config.max_touch_readings != 0
+                && self.touch_readings > self.config.max_touch_readings
+            {
+                self.baseline = Some(reading_fixed);
+                self.touched = false;
+            }
+        } else {
+            self.touch_readings = 0;
+            let baseline_fixed = if reading_fixed < baseline_fixed {
+                reading_fixed
+            } else {
+                baseline_fixed + ((reading_fixed - baseline_fixed) >> self.config.drift_shift)
+            };
+            self.baseline = Some(baseline_fixed);
+        }
+
+        self.touched
+    }
+
+    /// Returns whether the last reading was a touch
+    pub fn is_touched(&self) -> bool {
+        self.touched
+    }
+
+    /// Returns the baseline, `None` before the first reading or calibration
+    pub fn baseline(&self) -> Option<u32> {
+        self.baseline
+            .map(|baseline| (baseline >> BASELINE_FRACTION) as u32)
+    }
+
+    /// Returns the configuration
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn threshold(&self, baseline: u32, percent: u8) -> u32 {
+        (baseline as u64 * percent as u64 / 100) as u32
+    }
+}
+
+/// Capacitive touch pad on a GPIO, see the module documentation
+pub struct TouchPad<MODE> {
+    pin: AnyPin<Input<MODE>>,
+    detector: Detector,
+    drive_cycles: u64,
+    timeout_cycles: u32,
+}
+
+impl<MODE> TouchPad<MODE> {
+    /// Takes the pin of the pad, an input with a pull-up, without a baseline
+    pub fn new(pin: AnyPin<Input<MODE>>, config: Config, clocks: &Clocks) -> Self {
+        let cycles_per_us = clocks.sysclk().0 / 1_000_000;
+        TouchPad {
+            pin,
+            detector: Detector::new(config),
+            drive_cycles: config.drive_time_us as u64 * cycles_per_us as u64,
+            timeout_cycles: config.timeout_us.saturating_mul(cycles_per_us),
+        }
+    }
+
+    /// Returns a reading, the average charge time in cycles of the system clock
+    pub fn measure(&mut self) -> Result<u32, Error> {
+        let samples = self.detector.config.samples.max(1) as u32;
+        let mut total = 0u32;
+        for _ in 0..samples {
+            total = total.saturating_add(self.charge_time()?);
+        }
+        Ok(total / samples)
+    }
+
+    /// Takes `readings` readings with the pad untouched and sets the baseline to their mean
+    ///
+    /// Fails with [`Error::Noisy`] if they spread further than the release threshold.
+    pub fn calibrate(&mut self, readings: u16) -> Result<Calibration, Error> {
+        let mut remaining = readings;
+        let mut error = None;
+        let calibration = Calibration::from_readings(core::iter::from_fn(|| {
+            if remaining == 0 {
+                return None;
+            }
+            remaining -= 1;
+            self.measure().map_err(|e| error = Some(e)).ok()
+        }));
+
+        if let Some(error) = error {
+            return Err(error);
+        }
+        let calibration = calibration.ok_or(Error::NoReadings)?;
+        self.detector.calibrate(&calibration)?;
+        Ok(calibration)
+    }
+
+    /// Takes a reading and returns whether the pad is touched
+    pub fn update(&mut self) -> Result<bool, Error> {
+        let reading = self.measure()?;
+        Ok(self.detector.update(reading))
+    }
+
+    /// Returns whether the pad was touched at the last [`update`](Self::update)
+    pub fn is_touched(&self) -> bool {
+        self.detector.is_touched()
+    }
+
+    /// Returns the detector, e.g. for its baseline
+    pub fn detector(&self) -> &Detector {
+        &self.detector
+    }
+
+    /// Releases the pin, left as an input
+    pub fn free(self) -> AnyPin<Input<MODE>> {
+        self.pin
+    }
+
+    /// Discharges the pad and returns the cycles it takes to charge
+    fn charge_time(&mut self) -> Result<u32, Error> {
+        let glb = regs::glb();
+        let bit = 1 << self.pin.pin();
+
+        // The output drives low while enabled, the input stays enabled as well
+        critical(|| {
+            glb.gpio_cfgctl32
+                .modify(|r, w| unsafe { w.bits(r.bits() & !bit) });
+            glb.gpio_cfgctl34
+                .modify(|r, w| unsafe { w.bits(r.bits() | bit) });
+        });
+        McycleDelay::delay_cycles(self.drive_cycles);
+
+        let timeout = self.timeout_cycles;
+        critical(|| {
+            glb.gpio_cfgctl34
+                .modify(|r, w| unsafe { w.bits(r.bits() & !bit) });
+            let start = mcycle::read() as u32;
+            loop {
+                let cycles = (mcycle::read() as u32).wrapping_sub(start);
+                if glb.gpio_cfgctl30.read().bits() & bit != 0 {
+                    return Ok(cycles);
+                }
+                if cycles > timeout {
+                    return Err(Error::Timeout);
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Calibration, Config, Detector, Error};
+    use std::vec::Vec;
+
+    /// Feeds `readings` to `detector`, returns how often the state changed
+    fn changes(detector: &mut Detector, readings: &[u32]) -> usize {
+        let mut touched = detector.is_touched();
+        let mut changes = 0;
+        for &reading in readings {
+            if detector.update(reading) != touched {
+                touched = !touched;
+                changes += 1;
+            }
+        }
+        changes
+    }
+
+    #[test]
+    fn hysteresis() {
+        // A touch from a baseline of 100, its rise crossing the release threshold on the way down
+        let readings = [100, 102, 100, 101, 112, 115, 114, 106, 104, 100];
+        let states = [
+            false, false, false, false, true, true, true, true, false, false,
+        ];
+        let mut detector = Detector::new(Config::default());
+        for (i, (&reading, &touched)) in readings.iter().zip(states.iter()).enumerate() {
+            assert_eq!(detector.update(reading), touched, "reading {}", i);
+        }
+
+        // A finger hovering at the touch threshold
+        let mut detector = Detector::new(Config::default());
+        let hover = [100, 109, 111, 107, 111, 106, 110, 108, 103];
+        assert_eq!(changes(&mut detector, &hover), 2);
+        assert!(!detector.is_touched());
+    }
+
+    #[test]
+    fn drift() {
+        // 0.1 per reading, the baseline lags 6.4 behind, below the touch threshold
+        let mut detector = Detector::new(Config::default());
+        let drift: Vec<u32> = (0..300).map(|i| 100 + i / 10).collect();
+        assert_eq!(changes(&mut detector, &drift), 0);
+        assert!((120..=129).contains(&detector.baseline().unwrap()));
+
+        // A reading below the baseline resets it
+        detector.update(90);
+        assert_eq!(detector.baseline(), Some(90));
+        assert!(!detector.is_touched());
+
+        // A touch which lasts too long is taken as drift
+        let mut detector = Detector::new(Config::default().max_touch_readings(50));
+        detector.update(100);
+        assert_eq!(changes(&mut detector, &[130; 60]), 2);
+        assert_eq!(detector.baseline(), Some(130));
+    }
+
+    #[test]
+    fn calibration() {
+        let quiet = Calibration::from_readings([100, 102, 98, 101].iter().copied()).unwrap();
+        assert_eq!(
+            quiet,
+            Calibration {
+                baseline: 100,
+                noise: 4,
+            }
+        );
+        let noisy = Calibration::from_readings([100, 110, 95].iter().copied()).unwrap();
+        assert!(Calibration::from_readings(core::iter::empty()).is_none());
+
+        let mut detector = Detector::new(Config::default());
+        assert_eq!(detector.calibrate(&quiet), Ok(()));
+        assert_eq!(detector.calibrate(&noisy), Err(Error::Noisy));
+        assert_eq!(detector.baseline(), Some(100));
+    }
+}
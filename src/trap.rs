@@ -0,0 +1,223 @@
+/*!
+  # Exceptions
+  Exceptions are taken by the HAL's trap handler, which decodes `mcause` into a [`Cause`],
+  collects `mepc`, `mtval` and the stack pointer into a [`FaultInfo`] and passes it to the fault
+  handler. The default one prints the fault with the writer set by [`set_fault_writer`], or
+  else with the [debug writer](crate::debug), and parks the core with interrupts disabled, so a
+  running watchdog resets the chip. A handler for a fault can't return, since the instruction
+  which caused it would only fault again; replace the default with [`set_fault_handler`], e.g. to
+  reset right away:
+
+  ```rust
+    use bl602_hal::{power, trap::{self, FaultInfo}};
+
+    fn on_fault(_info: &FaultInfo) -> ! {
+        power::software_reset()
+    }
+
+    trap::set_fault_handler(on_fault);
+  ```
+
+  # After the reset
+  Before calling the fault handler, the cause and `mepc` are written to `HBN_RSV1` and
+  `HBN_RSV3`, which keep their value through watchdog and software resets and through
+  hibernate, but not through a power cycle. [`last_fault`] reads them back after the reset, so
+  a fault which happened in the field can be reported once the device is up again:
+
+  ```rust
+    if let Some(fault) = trap::last_fault() {
+        log::error!("reset after {:?} at {:#010x}", fault.cause, fault.mepc);
+        trap::clear_last_fault();
+    }
+  ```
+*/
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use riscv::register::{mcause, mepc, mtval};
+
+use crate::interrupts::TrapFrame;
+use crate::pac;
+
+/// Marker in the upper bytes of `HBN_RSV3` after a fault ("FLT"), the cause code is in the
+/// lowest byte
+const FAULT_FLAG: u32 = 0x464c_5400;
+
+static FAULT_HANDLER: AtomicUsize = AtomicUsize::new(0);
+static FAULT_WRITER: AtomicUsize = AtomicUsize::new(0);
+
+/// Exception cause, decoded from `mcause`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Cause {
+    /// Jump or branch to an address which isn't aligned to an instruction
+    InstructionMisaligned,
+    /// Instruction fetch from an address without memory
+    InstructionFault,
+    /// Instruction which doesn't exist or isn't allowed
+    IllegalInstruction,
+    /// `ebreak`
+    Breakpoint,
+    /// Load from an address which isn't aligned to its size
+    LoadMisaligned,
+    /// Load from an address without memory
+    LoadFault,
+    /// Store to an address which isn't aligned to its size
+    StoreMisaligned,
+    /// Store to an address without memory or to read-only memory
+    StoreFault,
+    /// `ecall` in user mode
+    UserEnvCall,
+    /// `ecall` in machine mode
+    MachineEnvCall,
+    /// Any other exception code
+    Unknown(u8),
+}
+
+impl Cause {
+    /// Returns the cause of exception code `code`, the lowest bits of `mcause`
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0 => Cause::InstructionMisaligned,
+            1 => Cause::InstructionFault,
+            2 => Cause::IllegalInstruction,
+            3 => Cause::Breakpoint,
+            4 => Cause::LoadMisaligned,
+            5 => Cause::LoadFault,
+            6 => Cause::StoreMisaligned,
+            7 => Cause::StoreFault,
+            8 => Cause::UserEnvCall,
+            11 => Cause::MachineEnvCall,
+            code => Cause::Unknown(code),
+        }
+    }
+
+    /// Returns the exception code
+    pub fn code(self) -> u8 {
+        match self {
+            Cause::InstructionMisaligned => 0,
+            Cause::InstructionFault => 1,
+            Cause::IllegalInstruction => 2,
+            Cause::Breakpoint => 3,
+            Cause::LoadMisaligned => 4,
+            Cause::LoadFault => 5,
+            Cause::StoreMisaligned => 6,
+            Cause::StoreFault => 7,
+            Cause::UserEnvCall => 8,
+            Cause::MachineEnvCall => 11,
+            Cause::Unknown(code) => code,
+        }
+    }
+}
+
+/// State of the core when an exception was taken
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FaultInfo {
+    /// What went wrong
+    pub cause: Cause,
+    /// Address of the instruction which caused the exception
+    pub mepc: usize,
+    /// The faulting address for access faults and misaligned accesses, the instruction for
+    /// illegal instructions, 0 otherwise
+    pub mtval: usize,
+    /// Stack pointer at the time of the exception
+    pub sp: usize,
+    /// Return address register at the time of the exception, usually in the caller of the
+    /// faulting function
+    pub ra: usize,
+}
+
+impl fmt::Display for FaultInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} at {:#010x}, mtval {:#010x}, sp {:#010x}, ra {:#010x}",
+            self.cause, self.mepc, self.mtval, self.sp, self.ra
+        )
+    }
+}
+
+/// Fault recorded in the retention registers, see [`last_fault`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Breadcrumb {
+    /// What went wrong
+    pub cause: Cause,
+    /// Address of the instruction which caused the exception
+    pub mepc: u32,
+}
+
+/// Replaces the handler called with every exception
+pub fn set_fault_handler(handler: fn(&FaultInfo) -> !) {
+    FAULT_HANDLER.store(handler as usize, Ordering::Relaxed);
+}
+
+/// Sets where the default fault handler prints the fault, e.g. a function writing to a serial
+/// port, instead of the [debug writer](crate::debug)
+///
+/// The writer runs in the trap handler: it can't wait for interrupts and shouldn't take locks
+/// which the faulting code might hold.
+pub fn set_fault_writer(writer: fn(fmt::Arguments<'_>)) {
+    FAULT_WRITER.store(writer as usize, Ordering::Relaxed);
+}
+
+/// Returns the fault recorded before the last reset, `None` if there was none since
+/// [`clear_last_fault`] or the last power cycle
+pub fn last_fault() -> Option<Breadcrumb> {
+    let hbn = unsafe { &*pac::HBN::ptr() };
+    let flag = hbn.hbn_rsv3.read().bits();
+
+    if flag & !0xff == FAULT_FLAG {
+        Some(Breadcrumb {
+            cause: Cause::from_code(flag as u8),
+            mepc: hbn.hbn_rsv1.read().bits(),
+        })
+    } else {
+        None
+    }
+}
+
+/// Removes the fault record, so [`last_fault`] returns `None` until the next fault
+pub fn clear_last_fault() {
+    let hbn = unsafe { &*pac::HBN::ptr() };
+    hbn.hbn_rsv3.write(|w| unsafe { w.bits(0) });
+    hbn.hbn_rsv1.write(|w| unsafe { w.bits(0) });
+}
+
+/// Handles an exception, called by the trap handler
+pub(crate) fn on_exception(trap_frame: &TrapFrame) -> ! {
+    let info = FaultInfo {
+        cause: Cause::from_code((mcause::read().bits() & 0xff) as u8),
+        mepc: mepc::read(),
+        mtval: mtval::read(),
+        sp: trap_frame.sp,
+        ra: trap_frame.ra,
+    };
+
+    let hbn = unsafe { &*pac::HBN::ptr() };
+    hbn.hbn_rsv1.write(|w| unsafe { w.bits(info.mepc as u32) });
+    hbn.hbn_rsv3
+        .write(|w| unsafe { w.bits(FAULT_FLAG | info.cause.code() as u32) });
+
+    let handler = FAULT_HANDLER.load(Ordering::Relaxed);
+    if handler != 0 {
+        let handler: fn(&FaultInfo) -> ! = unsafe { core::mem::transmute(handler) };
+        handler(&info);
+    }
+
+    default_fault_handler(&info)
+}
+
+fn default_fault_handler(info: &FaultInfo) -> ! {
+    let writer = FAULT_WRITER.load(Ordering::Relaxed);
+    if writer != 0 {
+        let writer: fn(fmt::Arguments<'_>) = unsafe { core::mem::transmute(writer) };
+        writer(format_args!("fault: {}\r\n", info));
+    } else {
+        crate::debug::write_fatal(format_args!("fault: {}\r\n", info));
+    }
+
+    loop {
+        unsafe { riscv::asm::wfi() };
+    }
+}
@@ -41,7 +41,7 @@
  ```
 */
 
-use crate::{clock::Clocks, pac, timer::TimerWatchdog};
+use crate::{clock::Clocks, regs, timer::TimerWatchdog};
 use embedded_time::{duration::*, rate::*};
 
 /// Clock sources for a Watchdog channel.
@@ -107,7 +107,7 @@ pub struct ConfiguredWatchdog0 {
 
 /// This sends the access codes so that we can write values to the WDT registers.
 fn send_access_codes() {
-    let timer = unsafe { &*pac::TIMER::ptr() };
+    let timer = regs::timer();
     timer
         .wfar
         .write(|w| unsafe { w.wfar().bits(WatchdogKeys::Wfar.get_key()) });
@@ -116,10 +116,23 @@ fn send_access_codes() {
         .write(|w| unsafe { w.wsar().bits(WatchdogKeys::Wsar.get_key()) });
 }
 
+/// Returns whether the last reset was caused by the watchdog, the WSR register's WTS bit
+pub(crate) fn reset_flag() -> bool {
+    let timer = regs::timer();
+    timer.wsr.read().wts().bit_is_set()
+}
+
+/// Clears the WTS bit, which stays set after a watchdog reset until cleared
+pub(crate) fn clear_reset_flag() {
+    let timer = regs::timer();
+    send_access_codes();
+    timer.wsr.write(|w| w.wts().set_bit());
+}
+
 impl ConfiguredWatchdog0 {
     /// Enable the watchdog counter
     pub fn enable(&self) {
-        let timer = unsafe { &*pac::TIMER::ptr() };
+        let timer = regs::timer();
         send_access_codes();
         timer.wcr.write(|w| w.wcr().set_bit());
         send_access_codes();
@@ -128,7 +141,7 @@ impl ConfiguredWatchdog0 {
 
     /// Read the WMER register's WE bit to see if the WDT is enabled or disabled.
     pub fn is_enabled(&self) -> WatchdogMode {
-        let timer = unsafe { &*pac::TIMER::ptr() };
+        let timer = regs::timer();
         match timer.wmer.read().we().bit() {
             true => WatchdogMode::Reset,
             false => WatchdogMode::Interrupt,
@@ -140,7 +153,7 @@ impl ConfiguredWatchdog0 {
     pub fn set_timeout(&self, time: impl Into<Nanoseconds<u64>>) {
         let time: Nanoseconds<u64> = time.into();
         let ticks = (self.clock.0 as u64 * time.integer() / 1_000_000_000_u64) as u16;
-        let timer = unsafe { &*pac::TIMER::ptr() };
+        let timer = regs::timer();
         send_access_codes();
         timer.wmr.write(|w| unsafe { w.wmr().bits(ticks) });
     }
@@ -148,7 +161,7 @@ impl ConfiguredWatchdog0 {
     //noinspection RsSelfConvention
     /// Determine whether the watchdog will reset the board, or trigger an interrupt
     pub fn set_mode(&self, mode: WatchdogMode) {
-        let timer = unsafe { &*pac::TIMER::ptr() };
+        let timer = regs::timer();
         match mode {
             WatchdogMode::Interrupt => {
                 send_access_codes();
@@ -163,27 +176,24 @@ impl ConfiguredWatchdog0 {
 
     /// Check the value of the watchdog reset register (WTS) to see if a reset has occurred
     pub fn has_watchdog_reset_occurred(&self) -> bool {
-        let timer = unsafe { &*pac::TIMER::ptr() };
-        timer.wsr.read().wts().bits() as bool
+        reset_flag()
     }
 
     /// Clear the watchdog reset register (WTS)
     pub fn clear_wts(&self) {
-        let timer = unsafe { &*pac::TIMER::ptr() };
-        send_access_codes();
-        timer.wsr.write(|w| w.wts().set_bit());
+        clear_reset_flag();
     }
 
     /// clears the watchdog interrupt once it has been set by the WDT activating in Interrupt mode
     pub fn clear_interrupt(&self) {
-        let timer = unsafe { &*pac::TIMER::ptr() };
+        let timer = regs::timer();
         send_access_codes();
         timer.wicr.write(|w| w.wiclr().set_bit());
     }
 
     /// Gets the value in ticks the match register is currently set to
     pub fn get_match_ticks(&self) -> u16 {
-        let timer = unsafe { &*pac::TIMER::ptr() };
+        let timer = regs::timer();
         timer.wmr.read().wmr().bits() as u16
     }
 
@@ -196,7 +206,7 @@ impl ConfiguredWatchdog0 {
 
     /// Get the current value in ticks of the watchdog timer
     pub fn get_current_ticks(&self) -> u16 {
-        let timer = unsafe { &*pac::TIMER::ptr() };
+        let timer = regs::timer();
         timer.wvr.read().wvr().bits() as u16
     }
 
@@ -209,13 +219,13 @@ impl ConfiguredWatchdog0 {
 
     /// Read the TCCR register containing the CS_WDT bits that select the clock source
     pub fn get_cs_wdt(&self) -> u8 {
-        let timer = unsafe { &*pac::TIMER::ptr() };
+        let timer = regs::timer();
         timer.tccr.read().cs_wdt().bits() as u8
     }
 
     /// Read the WMER register's WRIE bit to see if the WDT is in Reset or Interrupt mode.
     pub fn get_wrie(&self) -> WatchdogMode {
-        let timer = unsafe { &*pac::TIMER::ptr() };
+        let timer = regs::timer();
         match timer.wmer.read().wrie().bit() {
             true => WatchdogMode::Reset,
             false => WatchdogMode::Interrupt,
@@ -224,9 +234,42 @@ impl ConfiguredWatchdog0 {
 
     /// Read the TCDR register's WCDR bits to see the clock division value.
     pub fn get_wcdr(&self) -> u8 {
-        let timer = unsafe { &*pac::TIMER::ptr() };
+        let timer = regs::timer();
         timer.tcdr.read().wcdr().bits()
     }
+
+    /// Returns whether the counter runs, i.e. the WMER register's WE bit
+    pub(crate) fn is_running(&self) -> bool {
+        let timer = regs::timer();
+        timer.wmer.read().we().bit_is_set()
+    }
+
+    /// Starts or stops the counter, keeping the mode
+    pub(crate) fn set_running(&self, running: bool) {
+        let timer = regs::timer();
+        send_access_codes();
+        timer.wmer.modify(|_r, w| w.we().bit(running));
+    }
+
+    /// Resets the counter to 0
+    pub(crate) fn restart_counter(&self) {
+        let timer = regs::timer();
+        send_access_codes();
+        timer.wcr.write(|w| w.wcr().set_bit());
+    }
+
+    /// Sets the match register in ticks
+    pub(crate) fn set_match_ticks(&self, ticks: u16) {
+        let timer = regs::timer();
+        send_access_codes();
+        timer.wmr.write(|w| unsafe { w.wmr().bits(ticks) });
+    }
+
+    /// Converts `time` to ticks of the watchdog clock, rounded up
+    pub(crate) fn ticks_for(&self, time: core::time::Duration) -> u64 {
+        let nanos = time.as_nanos() * self.clock.0 as u128;
+        ((nanos + 999_999_999) / 1_000_000_000) as u64
+    }
 }
 
 impl embedded_hal::watchdog::blocking::Watchdog for ConfiguredWatchdog0 {
@@ -235,9 +278,7 @@ impl embedded_hal::watchdog::blocking::Watchdog for ConfiguredWatchdog0 {
     /// This feeds the watchdog by resetting its counter value to 0.
     /// WCR register is write-only, no need to preserve register contents
     fn feed(&mut self) -> Result<(), Self::Error> {
-        let timer = unsafe { &*pac::TIMER::ptr() };
-        send_access_codes();
-        timer.wcr.write(|w| w.wcr().set_bit());
+        self.restart_counter();
         Ok(())
     }
 }
@@ -247,7 +288,7 @@ impl embedded_hal::watchdog::blocking::Disable for ConfiguredWatchdog0 {
     type Target = ConfiguredWatchdog0;
 
     fn disable(self) -> Result<Self::Target, Self::Error> {
-        let timer = unsafe { &*pac::TIMER::ptr() };
+        let timer = regs::timer();
         send_access_codes();
         timer.wmer.write(|w| w.we().clear_bit());
         Ok(self)
@@ -295,7 +336,7 @@ impl TimerWatchdog {
         target_clock: impl Into<Hertz>,
     ) -> ConfiguredWatchdog0 {
         let target_clock = target_clock.into();
-        let timer = unsafe { &*pac::TIMER::ptr() };
+        let timer = regs::timer();
         timer
             .tccr
             .modify(|_r, w| unsafe { w.cs_wdt().bits(source.tccr_value()) });
@@ -0,0 +1,324 @@
+/*
+
+   Runs the drivers on the PC against the register files of the `mock-registers` feature:
+   cargo test --features mock-registers --target x86_64-unknown-linux-gnu --test host
+
+   Each test holds `regs::lock()`, which clears the register files, and uses pins of its own,
+   since the debug check of the GPIO type states remembers the pins configured before. Nothing
+   reacts to the registers, so the tests set the status bits a driver waits for themselves.
+*/
+
+#![cfg(feature = "mock-registers")]
+
+use bl602_hal as hal;
+use embedded_hal::digital::blocking::{InputPin, OutputPin};
+use embedded_time::duration::Extensions as _;
+use hal::{
+    clock::Clocks,
+    hbn::{WakeCause, WakePin},
+    interrupts::{self, Interrupt, Priority},
+    pac,
+    power::{ResetFlags, ResetReason},
+    prelude::*,
+    regs,
+    serial::{self, Serial},
+    timer::{ClockSource, ConfiguredTimerChannel0, FreeRunningTimer, TimerExt},
+};
+
+/// Sets a register the hardware sets, which the PAC has no writer for
+fn poke<T>(register: &T, value: u32) {
+    unsafe { (register as *const T as *mut u32).write_volatile(value) };
+}
+
+/// Returns the half of `gpio_cfgctl` of pin `n`: IE, SMT, DRV, PU, PD and FUNC_SEL
+fn cfgctl(n: u8) -> u32 {
+    let first = &regs::glb().gpio_cfgctl0 as *const _ as *const u32;
+    let word = unsafe { first.add(n as usize / 2).read_volatile() };
+    (word >> (16 * (n as u32 % 2))) & 0xffff
+}
+
+fn func_sel(n: u8) -> u32 {
+    (cfgctl(n) >> 8) & 0xf
+}
+
+#[test]
+fn gpio_configuration() {
+    let _registers = regs::lock();
+    let dp = unsafe { pac::Peripherals::steal() };
+    let mut parts = dp.GLB.split();
+    let glb = regs::glb();
+
+    let input = parts.pin2.into_pull_up_input();
+    assert_eq!(func_sel(2), 11);
+    assert_eq!(cfgctl(2) & 0x31, 0x11, "IE and PU, no PD");
+    assert_eq!(glb.gpio_cfgctl34.read().bits() & (1 << 2), 0, "no OE");
+    assert_eq!(input.is_high(), Ok(false));
+    poke(&glb.gpio_cfgctl30, 1 << 2);
+    assert_eq!(input.is_high(), Ok(true));
+
+    let mut output = parts.pin5.into_floating_output();
+    assert_eq!(func_sel(5), 11);
+    assert_eq!(cfgctl(5) & 0x31, 0, "no IE and no pulls");
+    assert_ne!(glb.gpio_cfgctl34.read().bits() & (1 << 5), 0, "OE");
+    output.set_high().unwrap();
+    assert_eq!(glb.gpio_cfgctl32.read().bits(), 1 << 5);
+    output.set_low().unwrap();
+    assert_eq!(glb.gpio_cfgctl32.read().bits(), 0);
+
+    let _analog = parts.pin4.into_analog();
+    assert_eq!(func_sel(4), 10);
+    assert_eq!(cfgctl(4) & 0x31, 0);
+    assert_eq!(glb.gpio_cfgctl34.read().bits() & (1 << 4), 0);
+
+    let _pwm = parts.pin3.into_pull_down_pwm();
+    assert_eq!(func_sel(3), 8);
+    assert_eq!(cfgctl(3) & 0x31, 0x21, "IE and PD, like every alternate function");
+    // The neighbour in the same register is left alone
+    assert_eq!(func_sel(2), 11);
+
+    let _sig = parts.pin6.into_uart_sig6();
+    let _mux = parts.uart_mux6.into_uart0_rx();
+    assert_eq!(func_sel(6), 7);
+    assert_eq!((glb.uart_sig_sel_0.read().bits() >> 24) & 0xf, 3);
+}
+
+#[test]
+fn uart_divisor() {
+    let _registers = regs::lock();
+    let dp = unsafe { pac::Peripherals::steal() };
+    let mut parts = dp.GLB.split();
+    let uart = regs::uart();
+
+    // The UART runs on the 32 MHz RC oscillator
+    let clocks = Clocks::new();
+    let pins = (
+        (
+            parts.pin16.into_uart_sig0(),
+            parts.uart_mux0.into_uart0_tx(),
+        ),
+        (parts.pin7.into_uart_sig7(), parts.uart_mux7.into_uart0_rx()),
+    );
+    let mut serial = Serial::uart0(
+        dp.UART,
+        serial::Config::default().baudrate(115_200.Bd()),
+        pins,
+        clocks,
+    );
+
+    // 32 MHz / 115200 Bd = 277.8, rounded to the nearest bit period
+    let bit_prd = uart.uart_bit_prd.read();
+    assert_eq!(bit_prd.cr_utx_bit_prd().bits(), 277);
+    assert_eq!(bit_prd.cr_urx_bit_prd().bits(), 277);
+    let sig_sel = regs::glb().uart_sig_sel_0.read().bits();
+    assert_eq!(sig_sel & 0xf, 2, "UART0 TX on signal 0");
+    assert_eq!(sig_sel >> 28, 3, "UART0 RX on signal 7");
+
+    // set_baudrate waits for the transmitter to run empty
+    poke(&uart.uart_fifo_config_1, 32);
+    assert_eq!(uart.uart_fifo_config_1.read().tx_fifo_cnt().bits(), 32);
+
+    assert_eq!(serial.set_baudrate(2_000_000, &clocks), Ok(2_000_000));
+    assert_eq!(uart.uart_bit_prd.read().cr_utx_bit_prd().bits(), 15);
+
+    // 10.67 rounds up, the baudrate set is 32 MHz / 11
+    assert_eq!(serial.set_baudrate(3_000_000, &clocks), Ok(2_909_090));
+    assert_eq!(uart.uart_bit_prd.read().cr_urx_bit_prd().bits(), 10);
+
+    // 65536 is the longest bit period, the shortest is one cycle
+    assert_eq!(serial.set_baudrate(489, &clocks), Ok(32_000_000 / 65_440));
+    assert_eq!(
+        serial.set_baudrate(488, &clocks),
+        Err(serial::Error::Baudrate)
+    );
+    assert_eq!(
+        serial.set_baudrate(0, &clocks),
+        Err(serial::Error::Baudrate)
+    );
+    assert_eq!(
+        serial.set_baudrate(32_000_001, &clocks),
+        Err(serial::Error::Baudrate)
+    );
+    assert_eq!(serial.set_baudrate(32_000_000, &clocks), Ok(32_000_000));
+    assert_eq!(uart.uart_bit_prd.read().cr_utx_bit_prd().bits(), 0);
+}
+
+#[test]
+fn timer_ticks() {
+    let _registers = regs::lock();
+    let dp = unsafe { pac::Peripherals::steal() };
+    let timers = dp.TIMER.split();
+    let timer = regs::timer();
+
+    let ch0 = timers
+        .channel0
+        .set_clock_source(ClockSource::Pll32Mhz, 1_000_000u32.Hz());
+    assert_eq!(timer.tccr.read().cs_1().bits(), 3);
+    assert_eq!(timer.tcdr.read().tcdr2().bits(), 31, "32 MHz / 32");
+
+    ch0.set_match0(1_500u32.microseconds());
+    ch0.set_match1(2u32.seconds());
+    ch0.set_match2(999u32.nanoseconds());
+    assert_eq!(timer.tmr2_0.read().tmr().bits(), 1_500);
+    assert_eq!(timer.tmr2_1.read().tmr().bits(), 2_000_000);
+    assert_eq!(timer.tmr2_2.read().tmr().bits(), 0, "rounded down");
+
+    poke(&timer.tcr2, 2_500);
+    assert_eq!(ch0.current_ticks(), 2_500);
+    assert_eq!(ch0.current_time().0, 2_500_000);
+
+    let ch1 = timers
+        .channel1
+        .set_clock_source(ClockSource::Rc32Khz, 32_000u32.Hz());
+    assert_eq!(timer.tccr.read().cs_2().bits(), 1);
+    assert_eq!(timer.tcdr.read().tcdr3().bits(), 0);
+    ch1.set_match1(250u32.milliseconds());
+    assert_eq!(timer.tmr3_1.read().tmr().bits(), 8_000);
+
+    // Extending the counter to 64 bits
+    let free_running = ch0.start_free_running();
+    assert_eq!(timer.tmr2_2.read().tmr().bits(), u32::MAX);
+    poke(&timer.tcr2, 5);
+    assert_eq!(free_running.now(), 5);
+
+    // Wrapped, but the interrupt hasn't been handled yet
+    poke(&timer.tmsr2, 1 << 2);
+    assert_eq!(free_running.now(), 1 << 32 | 5);
+    assert!(FreeRunningTimer::<ConfiguredTimerChannel0>::on_interrupt());
+    poke(&timer.tmsr2, 0);
+    assert_eq!(free_running.now(), 1 << 32 | 5);
+    assert_eq!(free_running.now_time().0, ((1 << 32) + 5) * 1_000);
+
+    // Read right before the wrap, with the match already pending
+    poke(&timer.tcr2, u32::MAX);
+    poke(&timer.tmsr2, 1 << 2);
+    assert_eq!(free_running.now(), 1 << 32 | u32::MAX as u64);
+}
+
+#[test]
+fn reset_reason_decoding() {
+    const HBN_ENTER: u32 = 0x4e42_4845;
+    const BOD_RESET: u32 = 0x424f_4452;
+    const SOFTWARE: u32 = 0x5357_5253;
+    const BOOTLOADER: u32 = 0x424f_4f54;
+
+    // Bits of `HBN_IRQ_STAT`
+    const GPIO7: u32 = 1 << 0;
+    const GPIO8: u32 = 1 << 1;
+    const RTC: u32 = 1 << 16;
+    const ACOMP0: u32 = 1 << 20;
+    const ACOMP1: u32 = 1 << 22;
+
+    // `(HBN_RSV0, HBN_IRQ_STAT, WTS, expected reason)`, a marker and the watchdog flag together
+    // mean the watchdog expired after the marked reset
+    let truth_table = [
+        (0, 0, false, ResetReason::PowerOn),
+        (0, RTC, false, ResetReason::PowerOn),
+        (0x1234_5678, 0, false, ResetReason::PowerOn),
+        (0, 0, true, ResetReason::Watchdog),
+        (0x1234_5678, 0, true, ResetReason::Watchdog),
+        (SOFTWARE, 0, false, ResetReason::Software),
+        (SOFTWARE, 0, true, ResetReason::Watchdog),
+        (BOOTLOADER, 0, false, ResetReason::Bootloader),
+        (BOOTLOADER, 0, true, ResetReason::Watchdog),
+        (BOD_RESET, 0, false, ResetReason::BrownOut),
+        (BOD_RESET, 0, true, ResetReason::Watchdog),
+        (HBN_ENTER, RTC, true, ResetReason::Watchdog),
+        (
+            HBN_ENTER,
+            RTC | GPIO7,
+            false,
+            ResetReason::Hibernate(WakeCause::Rtc),
+        ),
+        (
+            HBN_ENTER,
+            GPIO8,
+            false,
+            ResetReason::Hibernate(WakeCause::Gpio(WakePin::Gpio8)),
+        ),
+        (
+            HBN_ENTER,
+            GPIO7 | ACOMP0,
+            false,
+            ResetReason::Hibernate(WakeCause::Gpio(WakePin::Gpio7)),
+        ),
+        (
+            HBN_ENTER,
+            ACOMP1,
+            false,
+            ResetReason::Hibernate(WakeCause::Acomp(1)),
+        ),
+        (
+            HBN_ENTER,
+            ACOMP0 | ACOMP1,
+            false,
+            ResetReason::Hibernate(WakeCause::Acomp(0)),
+        ),
+        (
+            HBN_ENTER,
+            0,
+            false,
+            ResetReason::Hibernate(WakeCause::Unknown),
+        ),
+    ];
+
+    for &(hbn_rsv0, hbn_irq_stat, watchdog, expected) in truth_table.iter() {
+        let flags = ResetFlags {
+            hbn_rsv0,
+            hbn_irq_stat,
+            watchdog,
+        };
+        assert_eq!(flags.decode(), expected, "{:x?}", flags);
+    }
+}
+
+#[test]
+fn interrupt_priorities() {
+    let priority = |value| Priority::new(value).unwrap();
+    assert_eq!(Priority::new(16), None);
+    assert_eq!(Priority::MAX, priority(15));
+
+    // `(priority, level bits, level)`, the unused bits are filled with ones
+    let levels = [
+        (0, 0, 0xff),
+        (0, 4, 0x0f),
+        (15, 4, 0xff),
+        (0b1010, 2, 0b1011_1111),
+        (0b0110, 1, 0b0111_1111),
+    ];
+    for &(value, level_bits, level) in levels.iter() {
+        assert_eq!(priority(value).level(level_bits), level, "{}", value);
+    }
+
+    // `(priority, running level, level bits, preempts)`
+    let preemption = [
+        // No level bits after reset, every interrupt is level 255
+        (15, 0, 0, true),
+        (15, 255, 0, false),
+        (0, 255, 0, false),
+        // Level bits 1: priorities 8 to 15 are level 255, 0 to 7 are level 127
+        (8, 127, 1, true),
+        (7, 127, 1, false),
+        (8, 255, 1, false),
+        (3, 0x2f, 4, true),
+    ];
+    for &(value, running, level_bits, expected) in preemption.iter() {
+        assert_eq!(
+            interrupts::preempts(priority(value), running, level_bits),
+            expected,
+            "{} at {:#x}",
+            value,
+            running
+        );
+    }
+
+    // `(TimerCh0, TimerCh1, TimerCh0 runs first)`, for the same priority the higher interrupt
+    // number wins
+    let arbitration = [(1, 0, true), (0, 1, false), (5, 5, false), (15, 14, true)];
+    for &(a, b, expected) in arbitration.iter() {
+        let a_first = interrupts::runs_before(
+            (Interrupt::TimerCh0, priority(a)),
+            (Interrupt::TimerCh1, priority(b)),
+        );
+        assert_eq!(a_first, expected, "{} and {}", a, b);
+    }
+}